@@ -0,0 +1,293 @@
+use crate::{event::Events, store::Store};
+use glsp::{bail, lib, rfn, GResult, Runtime, Sym};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+
+/// The store key the rebound actions are saved with.
+const STORE_KEY: &str = "action-bindings";
+
+lib! {
+/// Named actions bound to keys, so the game checks `'jump` instead of every key that jumps &
+/// players can rebind them.
+///
+/// Keys are named like in the `key-down` event, e.g. `'space` or `'left-shift`. An action is down
+/// while any of its keys is held. When an action is pressed or released the `action-pressed` &
+/// `action-released` events are emitted with the name of the action. Changed bindings can be
+/// saved to the key-value store & loaded again the next time the game starts. For rebinding in a
+/// settings menu `rebind-action` binds an action to the next key that's pressed, emitting the
+/// `action-rebound` event with the action & the key.
+///
+/// Gamepad buttons can't be bound, because the backend doesn't read gamepad input.
+///
+/// ```gamelisp
+/// (bind-action 'jump '(space w up))
+/// (bind-action 'fire '(left-control x))
+/// (load-action-bindings)
+///
+/// (defn engine:update ()
+///   (when (action-pressed? 'jump)
+///     (jump player)))
+/// ```
+pub struct Actions {
+    /// The keys of every action.
+    bindings: HashMap<Sym, Vec<String>>,
+    /// The bindings the actions were first bound with, restored by `reset-action-bindings`.
+    defaults: HashMap<Sym, Vec<String>>,
+    /// The keys that are held.
+    held: HashSet<String>,
+    /// The actions that were pressed during this update.
+    pressed: HashSet<Sym>,
+    /// The actions that were released during this update.
+    released: HashSet<Sym>,
+    /// The action that's bound to the next key that's pressed.
+    rebinding: Option<Sym>,
+}
+}
+
+impl Actions {
+    /// Create without any actions.
+    pub fn new() -> Self {
+        Self {
+            bindings: HashMap::new(),
+            defaults: HashMap::new(),
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            released: HashSet::new(),
+            rebinding: None,
+        }
+    }
+
+    /// Set the keys of an action, the first keys it's bound with are its defaults.
+    pub fn bind(&mut self, action: Sym, keys: Vec<String>) {
+        self.defaults.entry(action).or_insert_with(|| keys.clone());
+        self.bindings.insert(action, keys);
+    }
+
+    /// Whether any key of an action is held.
+    pub fn is_down(&self, action: Sym) -> bool {
+        self.bindings
+            .get(&action)
+            .map_or(false, |keys| keys.iter().any(|key| self.held.contains(key)))
+    }
+
+    /// Whether a key is held.
+    pub fn is_key_held(&self, key: &str) -> bool {
+        self.held.contains(key)
+    }
+
+    /// The actions bound to a key.
+    fn actions_of(&self, key: &str) -> Vec<Sym> {
+        self.bindings
+            .iter()
+            .filter(|(_, keys)| keys.iter().any(|bound| bound == key))
+            .map(|(action, _)| *action)
+            .collect()
+    }
+
+    /// Handle a key being pressed, emitting the events of the actions that are now down.
+    ///
+    /// While an action is being rebound the key is bound to it instead. Must be called inside
+    /// the GameLisp runtime.
+    pub fn key_down(key: &str) -> GResult<()> {
+        let pressed = {
+            let mut actions = Actions::borrow_mut();
+            let actions = &mut *actions;
+            if let Some(action) = actions.rebinding.take() {
+                actions.bind(action, vec![key.to_string()]);
+
+                return Events::emit_engine_event("action-rebound", (action, glsp::sym(key)?));
+            }
+
+            let was_down: Vec<Sym> = actions
+                .actions_of(key)
+                .into_iter()
+                .filter(|action| !actions.is_down(*action))
+                .collect();
+            actions.held.insert(key.to_string());
+            actions.pressed.extend(was_down.iter().copied());
+
+            was_down
+        };
+
+        for action in pressed {
+            Events::emit_engine_event("action-pressed", action)?;
+        }
+
+        Ok(())
+    }
+
+    /// Handle a key being released, emitting the events of the actions that are now up.
+    ///
+    /// Must be called inside the GameLisp runtime.
+    pub fn key_up(key: &str) -> GResult<()> {
+        let released = {
+            let mut actions = Actions::borrow_mut();
+            let actions = &mut *actions;
+            if !actions.held.remove(key) {
+                return Ok(());
+            }
+            let released: Vec<Sym> = actions
+                .actions_of(key)
+                .into_iter()
+                .filter(|action| !actions.is_down(*action))
+                .collect();
+            actions.released.extend(released.iter().copied());
+
+            released
+        };
+
+        for action in released {
+            Events::emit_engine_event("action-released", action)?;
+        }
+
+        Ok(())
+    }
+
+    /// Forget which actions were pressed & released, at the end of every update.
+    pub fn end_update(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+    }
+
+    /// Bind an action to keys from a script, replacing its previous keys.
+    ///
+    /// ```gamelisp
+    /// (bind-action 'pause '(escape p))
+    /// ```
+    fn bind_action(&mut self, action: Sym, keys: Vec<Sym>) -> GResult<()> {
+        let keys: Vec<String> = keys.iter().map(|key| key.name().to_string()).collect();
+        if let Some(button) = keys.iter().find(|key| is_gamepad_button(key)) {
+            bail!(
+                "can't bind {} to {}: gamepad input isn't supported",
+                action,
+                button
+            );
+        }
+        self.bind(action, keys);
+
+        Ok(())
+    }
+
+    /// Bind an action to the next key that's pressed from a script, replacing its keys.
+    ///
+    /// ```gamelisp
+    /// (rebind-action 'jump)
+    /// (on-event 'action-rebound (fn (binding)
+    ///   (save-action-bindings)))
+    /// ```
+    fn rebind_action(&mut self, action: Sym) {
+        self.rebinding = Some(action);
+    }
+
+    /// Stop waiting for the key of the action that's being rebound from a script.
+    fn cancel_rebind(&mut self) {
+        self.rebinding = None;
+    }
+
+    /// Whether any key of an action is held from a script.
+    fn action_down(&self, action: Sym) -> bool {
+        self.is_down(action)
+    }
+
+    /// Whether an action was pressed during this update from a script.
+    fn action_pressed(&self, action: Sym) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    /// Whether an action was released during this update from a script.
+    fn action_released(&self, action: Sym) -> bool {
+        self.released.contains(&action)
+    }
+
+    /// The keys of an action from a script, for showing them in menus.
+    fn action_keys(&self, action: Sym) -> GResult<Vec<Sym>> {
+        match self.bindings.get(&action) {
+            Some(keys) => keys.iter().map(|key| glsp::sym(key)).collect(),
+            None => bail!("action {} isn't bound", action),
+        }
+    }
+
+    /// Restore the keys the actions were first bound with from a script.
+    fn reset_action_bindings(&mut self) {
+        self.bindings = self.defaults.clone();
+    }
+
+    /// Write the keys of all actions to the key-value store from a script.
+    fn save_action_bindings(&self) -> GResult<()> {
+        let bindings: Map<String, Value> = self
+            .bindings
+            .iter()
+            .map(|(action, keys)| {
+                (
+                    action.name().to_string(),
+                    Value::Array(keys.iter().cloned().map(Value::String).collect()),
+                )
+            })
+            .collect();
+
+        Store::borrow_mut().set_value(STORE_KEY, Value::Object(bindings))
+    }
+
+    /// Bind the actions to the keys saved in the key-value store from a script, returns whether
+    /// any were saved.
+    ///
+    /// Only actions that are already bound are changed, so saved actions the game no longer has
+    /// are ignored.
+    fn load_action_bindings(&mut self) -> GResult<bool> {
+        let bindings = match Store::borrow_mut().value(STORE_KEY)? {
+            Some(Value::Object(bindings)) => bindings,
+            Some(_) => bail!("saved action bindings are corrupt: not an object"),
+            None => return Ok(false),
+        };
+
+        for (action, keys) in bindings {
+            let action = glsp::sym(&action)?;
+            if !self.bindings.contains_key(&action) {
+                continue;
+            }
+
+            let keys = match keys {
+                Value::Array(keys) => keys
+                    .into_iter()
+                    .filter_map(|key| match key {
+                        // Gamepad buttons saved by earlier versions can't be pressed
+                        Value::String(key) if !is_gamepad_button(&key) => Some(key),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => bail!("saved keys of action {} are corrupt", action),
+            };
+            self.bindings.insert(action, keys);
+        }
+
+        Ok(true)
+    }
+
+    /// Register the actions and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Actions::new());
+
+            glsp::bind_rfn("bind-action", rfn!(Actions::bind_action))?;
+            glsp::bind_rfn("rebind-action", rfn!(Actions::rebind_action))?;
+            glsp::bind_rfn("cancel-rebind", rfn!(Actions::cancel_rebind))?;
+            glsp::bind_rfn("action-down?", rfn!(Actions::action_down))?;
+            glsp::bind_rfn("action-pressed?", rfn!(Actions::action_pressed))?;
+            glsp::bind_rfn("action-released?", rfn!(Actions::action_released))?;
+            glsp::bind_rfn("action-keys", rfn!(Actions::action_keys))?;
+            glsp::bind_rfn(
+                "reset-action-bindings",
+                rfn!(Actions::reset_action_bindings),
+            )?;
+            glsp::bind_rfn("save-action-bindings", rfn!(Actions::save_action_bindings))?;
+            glsp::bind_rfn("load-action-bindings", rfn!(Actions::load_action_bindings))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// Whether a binding names a gamepad button instead of a key.
+fn is_gamepad_button(name: &str) -> bool {
+    name.starts_with("gamepad-")
+}
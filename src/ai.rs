@@ -0,0 +1,346 @@
+use crate::sandbox;
+use glsp::{bail, lib, rdata, rfn, GFn, GResult, Root, Runtime, Sym, Val};
+use std::{collections::HashSet, mem};
+
+rdata! {
+/// A reference to a state machine or behavior tree ticked by the engine.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AiRef(u32);
+}
+
+rdata! {
+/// A node of a behavior tree, returned by `bt-sequence`, `bt-selector`, `bt-condition` &
+/// `bt-action`.
+#[derive(Debug, Clone)]
+pub struct BtNode(Node);
+}
+
+/// The result of ticking a behavior tree node.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Status {
+    Success,
+    Failure,
+    /// The node isn't done yet, it's ticked again during the next update.
+    Running,
+}
+
+/// A node of a behavior tree with the child it's waiting for.
+#[derive(Debug, Clone)]
+enum Node {
+    /// Ticks the children in order until one fails.
+    Sequence { children: Vec<Node>, running: usize },
+    /// Ticks the children in order until one succeeds.
+    Selector { children: Vec<Node>, running: usize },
+    /// Succeeds when the function returns a truthy value.
+    Condition(Root<GFn>),
+    /// Calls the function, which returns `'running`, `'failure` or `#f`, or anything else for
+    /// success.
+    Action(Root<GFn>),
+}
+
+impl Node {
+    /// Tick the node, composite nodes continue at the child that was still running.
+    fn tick(&mut self) -> GResult<Status> {
+        match self {
+            Node::Sequence { children, running } => {
+                Self::tick_children(children, running, Status::Success)
+            }
+            Node::Selector { children, running } => {
+                Self::tick_children(children, running, Status::Failure)
+            }
+            Node::Condition(function) => {
+                Ok(if sandbox::call::<_, _, Val>(function, &())?.is_truthy() {
+                    Status::Success
+                } else {
+                    Status::Failure
+                })
+            }
+            Node::Action(function) => Ok(match sandbox::call::<_, _, Val>(function, &())? {
+                Val::Sym(sym) if &*sym.name() == "running" => Status::Running,
+                Val::Sym(sym) if &*sym.name() == "failure" => Status::Failure,
+                Val::Bool(false) => Status::Failure,
+                _ => Status::Success,
+            }),
+        }
+    }
+
+    /// Tick the children in order while they return the status that continues, it's a sequence
+    /// when that's success & a selector when it's failure.
+    fn tick_children(children: &mut [Node], running: &mut usize, next: Status) -> GResult<Status> {
+        while *running < children.len() {
+            match children[*running].tick()? {
+                Status::Running => return Ok(Status::Running),
+                status if status == next => *running += 1,
+                status => {
+                    *running = 0;
+
+                    return Ok(status);
+                }
+            }
+        }
+        *running = 0;
+
+        Ok(next)
+    }
+}
+
+/// States with the function called every update while the state is active.
+#[derive(Debug)]
+struct StateMachine {
+    /// The name & function of every state.
+    states: Vec<(Sym, Root<GFn>)>,
+    /// The active state.
+    current: Sym,
+}
+
+impl StateMachine {
+    /// Call the function of the active state, switching to the state it returns.
+    fn tick(&mut self) -> GResult<()> {
+        let function = self.function(self.current)?.clone();
+        if let Val::Sym(next) = sandbox::call::<_, _, Val>(&function, &())? {
+            self.switch(next)?;
+        }
+
+        Ok(())
+    }
+
+    /// Make a state the active one, fails when it doesn't exist.
+    fn switch(&mut self, state: Sym) -> GResult<()> {
+        self.function(state)?;
+        self.current = state;
+
+        Ok(())
+    }
+
+    /// The function of a state.
+    fn function(&self, state: Sym) -> GResult<&Root<GFn>> {
+        match self.states.iter().find(|(name, _)| *name == state) {
+            Some((_, function)) => Ok(function),
+            None => bail!("state machine has no state {}", state),
+        }
+    }
+}
+
+/// What decides the behavior of an agent.
+#[derive(Debug)]
+enum Brain {
+    Machine(StateMachine),
+    Tree(Node),
+}
+
+/// A state machine or behavior tree with the identifier referenced by the scripts.
+#[derive(Debug)]
+struct Agent {
+    id: u32,
+    brain: Brain,
+}
+
+lib! {
+/// State machines & behavior trees for the AI of scripts, ticked by the engine every update.
+///
+/// Every state of a state machine has a function that's called while it's active, returning the
+/// symbol of another state switches to it. Behavior trees are built from sequences, selectors,
+/// conditions & actions. An action returns `'running` to be ticked again during the next update,
+/// the sequence or selector it's in continues there instead of starting over.
+///
+/// ```gamelisp
+/// (create-fsm 'patrol
+///   'patrol (fn () (walk-route guard) (when (sees-player? guard) 'chase))
+///   'chase (fn () (walk-to guard player) (unless (sees-player? guard) 'patrol)))
+///
+/// (create-behavior-tree
+///   (bt-selector
+///     (bt-sequence (bt-condition (fn () (low-health? bat))) (bt-action (fn () (flee bat))))
+///     (bt-action (fn () (attack bat)))))
+/// ```
+pub struct Ai {
+    /// The agents that are ticked every update.
+    agents: Vec<Agent>,
+    /// The identifier of the next agent.
+    next_id: u32,
+    /// Agents stopped while the agents are being ticked.
+    stopped: HashSet<u32>,
+}
+}
+
+impl Ai {
+    /// Create without any agents.
+    pub fn new() -> Self {
+        Self {
+            agents: vec![],
+            next_id: 0,
+            stopped: HashSet::new(),
+        }
+    }
+
+    /// Start ticking a brain.
+    fn add(&mut self, brain: Brain) -> AiRef {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.agents.push(Agent { id, brain });
+
+        AiRef(id)
+    }
+
+    /// Tick all state machines & behavior trees.
+    ///
+    /// An agent that fails is stopped. Must be called inside the GameLisp runtime.
+    pub fn tick_all() -> GResult<()> {
+        // Don't hold the borrow while calling the scripts, they might create new agents
+        let agents = mem::take(&mut Ai::borrow_mut().agents);
+
+        let mut ticking = Vec::with_capacity(agents.len());
+        for mut agent in agents {
+            if Ai::borrow().stopped.contains(&agent.id) {
+                continue;
+            }
+
+            let result = match agent.brain {
+                Brain::Machine(ref mut machine) => machine.tick(),
+                Brain::Tree(ref mut root) => root.tick().map(|_| ()),
+            };
+            match result {
+                Ok(()) => ticking.push(agent),
+                Err(err) => log::error!("error in AI: {}", err),
+            }
+        }
+
+        // Keep the agents created during this update after the existing ones
+        let mut ai = Ai::borrow_mut();
+        let stopped = mem::take(&mut ai.stopped);
+        ticking.retain(|agent| !stopped.contains(&agent.id));
+        ticking.append(&mut ai.agents);
+        ai.agents = ticking;
+
+        Ok(())
+    }
+
+    /// Create a state machine from a script, from the initial state & pairs of a state name
+    /// with its function.
+    fn create_fsm(&mut self, initial: Sym, states: &[Val]) -> GResult<AiRef> {
+        if states.len() % 2 != 0 {
+            bail!("create-fsm expects pairs of a state name & a function");
+        }
+
+        let states = states
+            .chunks_exact(2)
+            .map(|pair| match (&pair[0], &pair[1]) {
+                (Val::Sym(name), Val::GFn(function)) => Ok((*name, function.clone())),
+                (name, function) => bail!(
+                    "state {} with {} must be a symbol & a function",
+                    name,
+                    function
+                ),
+            })
+            .collect::<GResult<Vec<_>>>()?;
+        let mut machine = StateMachine {
+            states,
+            current: initial,
+        };
+        machine.switch(initial)?;
+
+        Ok(self.add(Brain::Machine(machine)))
+    }
+
+    /// The active state of a state machine from a script, `#n` for a behavior tree or when it's
+    /// stopped.
+    fn fsm_state(&self, agent: &AiRef) -> Option<Sym> {
+        self.agents
+            .iter()
+            .find(|other| other.id == agent.0)
+            .and_then(|other| match other.brain {
+                Brain::Machine(ref machine) => Some(machine.current),
+                Brain::Tree(_) => None,
+            })
+    }
+
+    /// Switch the state of a state machine from a script.
+    ///
+    /// ```gamelisp
+    /// (set-fsm-state guard-ai 'stunned)
+    /// ```
+    fn set_fsm_state(&mut self, agent: &AiRef, state: Sym) -> GResult<()> {
+        match self.agents.iter_mut().find(|other| other.id == agent.0) {
+            Some(Agent {
+                brain: Brain::Machine(machine),
+                ..
+            }) => machine.switch(state),
+            Some(_) => bail!("only the state of a state machine can be set"),
+            None => bail!("state machine is stopped"),
+        }
+    }
+
+    /// Start ticking a behavior tree from a script.
+    fn create_behavior_tree(&mut self, root: &BtNode) -> AiRef {
+        self.add(Brain::Tree(root.0.clone()))
+    }
+
+    /// Create a node ticking its children until one fails from a script.
+    fn bt_sequence(children: &[Val]) -> GResult<BtNode> {
+        Ok(BtNode(Node::Sequence {
+            children: Self::children(children)?,
+            running: 0,
+        }))
+    }
+
+    /// Create a node ticking its children until one succeeds from a script.
+    fn bt_selector(children: &[Val]) -> GResult<BtNode> {
+        Ok(BtNode(Node::Selector {
+            children: Self::children(children)?,
+            running: 0,
+        }))
+    }
+
+    /// Create a node succeeding when the function returns a truthy value from a script.
+    fn bt_condition(function: Root<GFn>) -> BtNode {
+        BtNode(Node::Condition(function))
+    }
+
+    /// Create a node calling a function from a script.
+    fn bt_action(function: Root<GFn>) -> BtNode {
+        BtNode(Node::Action(function))
+    }
+
+    /// The nodes passed to a sequence or selector.
+    fn children(children: &[Val]) -> GResult<Vec<Node>> {
+        children
+            .iter()
+            .map(|child| match child {
+                Val::RData(ref rdata) if rdata.is::<BtNode>() => {
+                    Ok(rdata.borrow::<BtNode>().0.clone())
+                }
+                _ => bail!("{} is not a behavior tree node", child),
+            })
+            .collect()
+    }
+
+    /// Stop ticking a state machine or behavior tree from a script, returns whether it was
+    /// still ticking.
+    fn stop_ai(&mut self, agent: &AiRef) -> bool {
+        self.stopped.insert(agent.0);
+
+        let count = self.agents.len();
+        self.agents.retain(|other| other.id != agent.0);
+
+        self.agents.len() != count
+    }
+
+    /// Register the agents and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Ai::new());
+
+            glsp::bind_rfn("create-fsm", rfn!(Ai::create_fsm))?;
+            glsp::bind_rfn("fsm-state", rfn!(Ai::fsm_state))?;
+            glsp::bind_rfn("set-fsm-state", rfn!(Ai::set_fsm_state))?;
+            glsp::bind_rfn("create-behavior-tree", rfn!(Ai::create_behavior_tree))?;
+            glsp::bind_rfn("bt-sequence", rfn!(Ai::bt_sequence))?;
+            glsp::bind_rfn("bt-selector", rfn!(Ai::bt_selector))?;
+            glsp::bind_rfn("bt-condition", rfn!(Ai::bt_condition))?;
+            glsp::bind_rfn("bt-action", rfn!(Ai::bt_action))?;
+            glsp::bind_rfn("stop-ai", rfn!(Ai::stop_ai))?;
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,432 @@
+use crate::{assets::Assets, curve::Curve, event::Events, puppet::Puppet, render::Render};
+use anyhow::{anyhow, Result};
+use glsp::{bail, lib, rfn, GResult, Runtime};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A property of a layer that changes over time.
+#[derive(Debug, Clone)]
+struct Track {
+    /// The seconds since the start, the value & the easing towards the next keyframe, sorted by
+    /// time.
+    keyframes: Vec<(f32, [f32; 2], Curve)>,
+}
+
+impl Track {
+    /// The value at a time, blended with the easing curves between the keyframes.
+    fn sample(&self, time: f32) -> [f32; 2] {
+        let (first_time, first, _) = self.keyframes[0];
+        if time <= first_time {
+            return first;
+        }
+
+        for pair in self.keyframes.windows(2) {
+            let ((start, from, curve), (end, to, _)) = (pair[0], pair[1]);
+            if time < end {
+                if end <= start {
+                    return from;
+                }
+
+                let fraction = curve.at((time - start) / (end - start));
+                return [
+                    from[0] + (to[0] - from[0]) * fraction,
+                    from[1] + (to[1] - from[1]) * fraction,
+                ];
+            }
+        }
+
+        self.keyframes[self.keyframes.len() - 1].1
+    }
+
+    /// Apply a conversion to every value.
+    fn map<F>(mut self, convert: F) -> Self
+    where
+        F: Fn([f32; 2]) -> [f32; 2],
+    {
+        for keyframe in self.keyframes.iter_mut() {
+            keyframe.1 = convert(keyframe.1);
+        }
+
+        self
+    }
+}
+
+/// The baked transform of a single layer, applied to the puppet part with the same name.
+#[derive(Debug, Clone)]
+struct LayerAnimation {
+    /// The name of the layer & the part.
+    name: String,
+    /// The rotation in radians, only the first value is used.
+    rotation: Track,
+    /// The movement away from the position at the start.
+    offset: Track,
+    /// The horizontal & vertical scale.
+    scale: Track,
+}
+
+/// A keyframed vector animation baked from a Lottie file.
+///
+/// Only the rotation, position & scale of the layers are used, the shapes of the layers come
+/// from the SVG of the puppet that plays the animation. Only the easing of the first dimension
+/// of a value is used.
+#[derive(Debug, Clone)]
+pub struct Animation {
+    /// How many seconds the animation takes.
+    duration: f32,
+    /// The animated layers.
+    layers: Vec<LayerAnimation>,
+}
+
+impl Animation {
+    /// Parse & bake a Lottie animation in the JSON format.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let lottie: Value = serde_json::from_slice(data)
+            .map_err(|err| anyhow!("parsing Lottie JSON failed: {}", err))?;
+
+        let frame_rate = lottie["fr"]
+            .as_f64()
+            .filter(|frame_rate| *frame_rate > 0.0)
+            .ok_or_else(|| anyhow!("Lottie animation doesn't have a frame rate"))?
+            as f32;
+        let in_point = lottie["ip"].as_f64().unwrap_or(0.0) as f32;
+        let out_point = lottie["op"]
+            .as_f64()
+            .ok_or_else(|| anyhow!("Lottie animation doesn't have an out point"))?
+            as f32;
+        // Convert frames to seconds since the start
+        let seconds = |frame: f32| (frame - in_point) / frame_rate;
+
+        let layers = lottie["layers"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Lottie animation doesn't have layers"))?
+            .iter()
+            .filter_map(|layer| {
+                let name = layer["nm"].as_str()?;
+                let transform = &layer["ks"];
+
+                Some(
+                    Self::layer(name, transform, &seconds)
+                        .map_err(|err| anyhow!("layer \"{}\": {}", name, err)),
+                )
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            duration: seconds(out_point).max(0.0),
+            layers,
+        })
+    }
+
+    /// Bake the transform of a layer.
+    fn layer<F>(name: &str, transform: &Value, seconds: &F) -> Result<LayerAnimation>
+    where
+        F: Fn(f32) -> f32,
+    {
+        let rotation = track(&transform["r"], [0.0, 0.0], seconds)?
+            .map(|[degrees, _]| [degrees.to_radians(), 0.0]);
+
+        if transform["p"]["s"].as_bool() == Some(true) {
+            return Err(anyhow!("separated position dimensions aren't supported"));
+        }
+        let position = track(&transform["p"], [0.0, 0.0], seconds)?;
+        let start = position.sample(0.0);
+        let offset = position.map(|[x, y]| [x - start[0], y - start[1]]);
+
+        let scale =
+            track(&transform["s"], [100.0, 100.0], seconds)?.map(|[x, y]| [x / 100.0, y / 100.0]);
+
+        Ok(LayerAnimation {
+            name: name.to_string(),
+            rotation,
+            offset,
+            scale,
+        })
+    }
+
+    /// How many seconds the animation takes.
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+}
+
+/// Convert a static or keyframed Lottie property, a missing property has the default value.
+fn track<F>(property: &Value, default: [f32; 2], seconds: &F) -> Result<Track>
+where
+    F: Fn(f32) -> f32,
+{
+    let value = &property["k"];
+    if value.is_null() {
+        return Ok(Track {
+            keyframes: vec![(0.0, default, Curve::linear())],
+        });
+    }
+    if property["a"].as_u64() != Some(1) {
+        return Ok(Track {
+            keyframes: vec![(0.0, values(value)?, Curve::linear())],
+        });
+    }
+
+    let mut keyframes: Vec<(f32, [f32; 2], Curve)> = vec![];
+    for keyframe in value
+        .as_array()
+        .ok_or_else(|| anyhow!("keyframes aren't a list"))?
+    {
+        let time = keyframe["t"]
+            .as_f64()
+            .ok_or_else(|| anyhow!("keyframe doesn't have a time"))? as f32;
+        let curve = if keyframe["h"].as_u64() == Some(1) {
+            Curve::hold()
+        } else {
+            easing(keyframe).unwrap_or_else(Curve::linear)
+        };
+
+        // Older files only store the end value in the previous keyframe
+        let value = if !keyframe["s"].is_null() {
+            values(&keyframe["s"])?
+        } else if let Some(previous) = value_end(&keyframes, value) {
+            previous
+        } else {
+            return Err(anyhow!("keyframe doesn't have a value"));
+        };
+
+        keyframes.push((seconds(time), value, curve));
+    }
+    if keyframes.is_empty() {
+        return Err(anyhow!("property doesn't have keyframes"));
+    }
+
+    Ok(Track { keyframes })
+}
+
+/// The cubic bezier easing from a keyframe to the next one, made from the out & in tangents.
+fn easing(keyframe: &Value) -> Option<Curve> {
+    // A handle coordinate is a number or a number for every dimension
+    let coordinate = |handle: &str, axis: &str| {
+        let value = &keyframe[handle][axis];
+
+        value
+            .as_f64()
+            .or_else(|| value.get(0).and_then(Value::as_f64))
+            .map(|number| number as f32)
+    };
+
+    Some(Curve::cubic_bezier(
+        coordinate("o", "x")?,
+        coordinate("o", "y")?,
+        coordinate("i", "x")?,
+        coordinate("i", "y")?,
+    ))
+}
+
+/// The end value of the last keyframe in the older format.
+fn value_end(keyframes: &[(f32, [f32; 2], Curve)], all: &Value) -> Option<[f32; 2]> {
+    let previous = all.as_array()?.get(keyframes.len().checked_sub(1)?)?;
+
+    values(&previous["e"]).ok()
+}
+
+/// The first two dimensions of a value, a single number is used for both.
+fn values(value: &Value) -> Result<[f32; 2]> {
+    if let Some(number) = value.as_f64() {
+        return Ok([number as f32, number as f32]);
+    }
+
+    let list = value
+        .as_array()
+        .ok_or_else(|| anyhow!("value isn't a number or a list"))?;
+    let number = |index: usize| list.get(index).and_then(Value::as_f64).map(|n| n as f32);
+    let first = number(0).ok_or_else(|| anyhow!("value is an empty list"))?;
+
+    Ok([first, number(1).unwrap_or(first)])
+}
+
+/// An animation that's played by a puppet.
+#[derive(Debug)]
+struct Player {
+    /// The puppet of which the parts are moved.
+    puppet: Puppet,
+    /// The reference name of the animation asset.
+    animation: String,
+    /// How far the animation is in seconds.
+    time: f32,
+    /// How fast the animation is played, negative plays it backwards.
+    speed: f32,
+    /// Whether the animation starts over when the end is reached.
+    looping: bool,
+}
+
+lib! {
+/// Lottie animations played back by moving the parts of puppets.
+///
+/// Every layer of the animation moves the part of the puppet with the same name. When an
+/// animation that doesn't loop finishes an `animation-finished` event is emitted with the
+/// instance of the puppet.
+///
+/// ```gamelisp
+/// (let player (create-puppet "character" 0 0))
+/// (play-animation player "walk" #t)
+/// ```
+pub struct Animations {
+    /// The baked animations, by the reference name of the asset.
+    animations: HashMap<String, Animation>,
+    /// The animations that are playing.
+    players: Vec<Player>,
+}
+}
+
+impl Animations {
+    /// Create without any animations playing.
+    pub fn new() -> Self {
+        Self {
+            animations: HashMap::new(),
+            players: vec![],
+        }
+    }
+
+    /// Advance all playing animations & move the parts of the puppets.
+    ///
+    /// Must be called inside the GameLisp runtime.
+    pub fn update(&mut self, delta: f64, render: &mut Render) -> GResult<()> {
+        let mut finished = vec![];
+
+        for player in self.players.iter_mut() {
+            // The puppet is removed so the animation stops
+            if render.instance(player.puppet.root()).is_none() {
+                finished.push((player.puppet.root(), false));
+
+                continue;
+            }
+
+            let animation = &self.animations[&player.animation];
+            player.time += player.speed * delta as f32;
+            if player.looping && animation.duration > 0.0 {
+                player.time = player.time.rem_euclid(animation.duration);
+            } else if (player.speed > 0.0 && player.time >= animation.duration)
+                || (player.speed < 0.0 && player.time <= 0.0)
+            {
+                player.time = player.time.max(0.0).min(animation.duration);
+                finished.push((player.puppet.root(), true));
+            }
+
+            for layer in animation.layers.iter() {
+                let (node, rest) = match player.puppet.part_node(&layer.name) {
+                    Some(part) => part,
+                    // Layers without a matching part are skipped
+                    None => continue,
+                };
+                if let Some(instance) = render.instance_mut(node) {
+                    let offset = layer.offset.sample(player.time);
+                    let scale = layer.scale.sample(player.time);
+
+                    instance.set_rotation(layer.rotation.sample(player.time)[0]);
+                    instance.set_x(rest[0] + offset[0]);
+                    instance.set_y(rest[1] + offset[1]);
+                    instance.set_scale_x(scale[0]);
+                    instance.set_scale_y(scale[1]);
+                }
+            }
+        }
+
+        self.players.retain(|player| {
+            !finished
+                .iter()
+                .any(|(root, _)| *root == player.puppet.root())
+        });
+        for (root, reached_end) in finished {
+            if reached_end {
+                Events::emit_engine_event("animation-finished", root)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The baked animation of a loaded asset, it's parsed the first time.
+    fn animation(&mut self, asset: &str) -> GResult<&Animation> {
+        if !self.animations.contains_key(asset) {
+            let animation = match Assets::borrow().data(asset) {
+                Some(data) => Animation::parse(data),
+                None => bail!("animation asset \"{}\" isn't loaded", asset),
+            };
+            match animation {
+                Ok(animation) => self.animations.insert(asset.to_string(), animation),
+                Err(err) => bail!("loading animation \"{}\" failed: {}", asset, err),
+            };
+        }
+
+        Ok(&self.animations[asset])
+    }
+
+    /// Play a loaded animation with a puppet from a script, replacing the animation it plays.
+    ///
+    /// The speed defaults to `1`, a negative speed starts at the end and plays it backwards.
+    ///
+    /// ```gamelisp
+    /// (play-animation player "jump")
+    /// (play-animation player "walk" #t 1.5)
+    /// ```
+    fn play_animation(
+        &mut self,
+        puppet: &Puppet,
+        asset: &str,
+        looping: Option<bool>,
+        speed: Option<f32>,
+    ) -> GResult<()> {
+        let speed = speed.unwrap_or(1.0);
+        let time = if speed < 0.0 {
+            self.animation(asset)?.duration
+        } else {
+            self.animation(asset)?;
+
+            0.0
+        };
+
+        self.stop_animation(puppet);
+        self.players.push(Player {
+            puppet: puppet.clone(),
+            animation: asset.to_string(),
+            time,
+            speed,
+            looping: looping.unwrap_or(false),
+        });
+
+        Ok(())
+    }
+
+    /// Stop the animation of a puppet from a script, the parts stay where they are.
+    ///
+    /// Returns whether the puppet was playing an animation.
+    fn stop_animation(&mut self, puppet: &Puppet) -> bool {
+        let count = self.players.len();
+        self.players
+            .retain(|player| player.puppet.root() != puppet.root());
+
+        self.players.len() != count
+    }
+
+    /// Check from a script whether a puppet is playing an animation.
+    fn is_playing(&self, puppet: &Puppet) -> bool {
+        self.players
+            .iter()
+            .any(|player| player.puppet.root() == puppet.root())
+    }
+
+    /// Get how many seconds a loaded animation takes from a script.
+    fn animation_duration(&mut self, asset: &str) -> GResult<f32> {
+        Ok(self.animation(asset)?.duration())
+    }
+
+    /// Register the animations and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Animations::new());
+
+            glsp::bind_rfn("play-animation", rfn!(Animations::play_animation))?;
+            glsp::bind_rfn("stop-animation", rfn!(Animations::stop_animation))?;
+            glsp::bind_rfn("animation-playing?", rfn!(Animations::is_playing))?;
+            glsp::bind_rfn("animation-duration", rfn!(Animations::animation_duration))?;
+
+            Ok(())
+        });
+    }
+}
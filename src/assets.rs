@@ -0,0 +1,776 @@
+use crate::{
+    animation::Animation,
+    atlas,
+    event::Events,
+    render::{Mesh, Render, SvgGeometry},
+    sandbox::Sandbox,
+    tilemap::Tilemap,
+};
+use anyhow::{anyhow, Result};
+use glsp::{lib, rfn, GResult, Runtime};
+use lyon::tessellation::FillOptions;
+use miniquad::fs::Response;
+use serde_json::Value;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
+
+/// How many seconds of every frame can be spent on processing assets while loading, so the
+/// loading screen keeps being drawn.
+const FRAME_BUDGET: f64 = 0.008;
+
+/// The type of an asset, determines how it's processed after loading.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AssetKind {
+    /// A SVG image, will be tessellated into a mesh.
+    Svg,
+    /// A TTF font.
+    Font,
+    /// A PNG texture, will be packed in an atlas and uploaded as a textured rectangle.
+    Texture,
+    /// A SVG image that will be rendered to a texture instead of tessellated.
+    #[cfg(feature = "rasterize")]
+    RasterizedSvg,
+    /// A mesh in the `.clogmesh` format, like a SVG baked with `bake_svg`, uploaded without
+    /// tessellating it.
+    BakedMesh,
+    /// A sound file.
+    Sound,
+    /// A Tiled map in the TMX or JSON format.
+    Tilemap,
+    /// A Lottie animation in the JSON format.
+    Animation,
+}
+
+impl AssetKind {
+    /// Guess the kind of asset from the extension of a path.
+    ///
+    /// `.json` files are assumed to be Lottie animations until their data shows they're Tiled
+    /// maps, see [`AssetKind::sniffed`].
+    pub fn from_path<P>(path: P) -> Option<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "svg" | "svgz" => Some(AssetKind::Svg),
+            "clogmesh" => Some(AssetKind::BakedMesh),
+            "ttf" | "otf" => Some(AssetKind::Font),
+            "png" => Some(AssetKind::Texture),
+            "ogg" | "wav" => Some(AssetKind::Sound),
+            "tmx" | "tmj" => Some(AssetKind::Tilemap),
+            "json" => Some(AssetKind::Animation),
+            _ => None,
+        }
+    }
+
+    /// Tell the kinds of assets sharing the `.json` extension apart by their data.
+    ///
+    /// Tiled maps have a `tiledversion` or a `type` of `map`, other JSON files stay animations.
+    pub fn sniffed(self, data: &[u8]) -> Self {
+        match self {
+            AssetKind::Animation if is_tiled_json(data) => AssetKind::Tilemap,
+            kind => kind,
+        }
+    }
+}
+
+/// Whether JSON data is a map saved by Tiled.
+fn is_tiled_json(data: &[u8]) -> bool {
+    match serde_json::from_slice::<Value>(data) {
+        Ok(json) => json.get("tiledversion").is_some() || json["type"] == "map",
+        Err(_) => false,
+    }
+}
+
+/// Where the data of an asset comes from.
+#[derive(Debug)]
+enum AssetSource {
+    /// The data is already in memory.
+    Bytes(Vec<u8>),
+    /// The data must be read from a file, or fetched from the server on WASM.
+    Path(PathBuf),
+}
+
+/// The result of a file that's being loaded asynchronously, filled when it's done.
+type PendingFile = Rc<RefCell<Option<Response>>>;
+
+/// In what phase of loading an asset is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetState {
+    /// The asset is waiting to be loaded.
+    Queued,
+    /// The file of the asset is being read or fetched.
+    Loading,
+    /// The asset is loaded and can be used.
+    Ready,
+    /// Something went wrong loading the asset.
+    Failed(String),
+}
+
+/// The data of the loaded fonts, shared with the tessellation thread.
+type Fonts = Arc<Vec<Vec<u8>>>;
+
+/// A SVG asset that's tessellated, with its raw data & its levels of detail.
+type TessellatedSvg = (String, Vec<u8>, Result<Vec<SvgGeometry>>);
+
+/// How detailed a SVG asset is tessellated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct SvgDetail {
+    /// The maximum distance between the curves & the triangles in SVG units.
+    tolerance: f32,
+    /// How many coarser meshes are generated for when the camera is zoomed out.
+    lod_levels: usize,
+    /// Whether the edges get a transparent fringe instead of relying on MSAA.
+    antialiasing: bool,
+}
+
+impl Default for SvgDetail {
+    fn default() -> Self {
+        Self {
+            tolerance: FillOptions::DEFAULT_TOLERANCE,
+            lod_levels: 0,
+            antialiasing: false,
+        }
+    }
+}
+
+/// Tessellates SVG assets, on a background thread where threads are available.
+///
+/// On WASM the SVGs are tessellated one at a time during the frame they are queued.
+#[derive(Default)]
+struct Tessellator {
+    /// The queue of the background thread & the tessellated SVGs it sends back.
+    #[cfg(not(target_arch = "wasm32"))]
+    thread: Option<(
+        Sender<(String, Vec<u8>, SvgDetail, Fonts)>,
+        Receiver<TessellatedSvg>,
+    )>,
+    /// The tessellated SVGs.
+    #[cfg(target_arch = "wasm32")]
+    finished: Vec<TessellatedSvg>,
+}
+
+impl Tessellator {
+    /// Queue the data of a SVG asset, the thread is started for the first asset.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn tessellate(
+        &mut self,
+        reference_name: &str,
+        data: Vec<u8>,
+        detail: SvgDetail,
+        fonts: Fonts,
+    ) -> Result<()> {
+        let (jobs, _) = self.thread.get_or_insert_with(|| {
+            let (jobs, queue) = mpsc::channel::<(String, Vec<u8>, SvgDetail, Fonts)>();
+            let (sender, finished) = mpsc::channel();
+            // The thread stops when the asset manager is dropped
+            std::thread::spawn(move || {
+                for (reference_name, data, detail, fonts) in queue {
+                    let geometry = Render::tessellate_svg_lods(
+                        &data,
+                        detail.tolerance,
+                        detail.lod_levels,
+                        detail.antialiasing,
+                        &fonts,
+                    );
+                    if sender.send((reference_name, data, geometry)).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            (jobs, finished)
+        });
+
+        jobs.send((reference_name.to_string(), data, detail, fonts))
+            .map_err(|_| anyhow!("SVG tessellation thread stopped"))
+    }
+
+    /// Tessellate the data of a SVG asset.
+    #[cfg(target_arch = "wasm32")]
+    fn tessellate(
+        &mut self,
+        reference_name: &str,
+        data: Vec<u8>,
+        detail: SvgDetail,
+        fonts: Fonts,
+    ) -> Result<()> {
+        let geometry = Render::tessellate_svg_lods(
+            &data,
+            detail.tolerance,
+            detail.lod_levels,
+            detail.antialiasing,
+            &fonts,
+        );
+        self.finished
+            .push((reference_name.to_string(), data, geometry));
+
+        Ok(())
+    }
+
+    /// Take the SVG assets that are tessellated since the last call.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn finished(&mut self) -> Vec<TessellatedSvg> {
+        match self.thread {
+            Some((_, ref finished)) => finished.try_iter().collect(),
+            None => vec![],
+        }
+    }
+
+    /// Take the SVG assets that are tessellated since the last call.
+    #[cfg(target_arch = "wasm32")]
+    fn finished(&mut self) -> Vec<TessellatedSvg> {
+        std::mem::take(&mut self.finished)
+    }
+}
+
+/// A single asset.
+#[derive(Debug)]
+struct Asset {
+    /// What type of asset this is.
+    kind: AssetKind,
+    /// Where to load the data from, taken when the asset is loaded.
+    source: Option<AssetSource>,
+    /// The file that's being loaded.
+    pending_file: Option<PendingFile>,
+    /// The raw data of the asset after loading.
+    data: Vec<u8>,
+    /// The uploaded mesh if this is a SVG, baked mesh or texture asset.
+    mesh: Option<Mesh>,
+    /// How many pixels a single unit of a rasterized SVG becomes.
+    raster_scale: f32,
+    /// How detailed a SVG is tessellated.
+    detail: SvgDetail,
+    /// The loading state.
+    state: AssetState,
+}
+
+lib! {
+/// All assets that are loaded or will be loaded, identified by a reference name.
+pub struct Assets {
+    /// The assets by reference name.
+    assets: HashMap<String, Asset>,
+    /// Whether some assets are queued for loading or still loading.
+    has_queued: bool,
+    /// Tessellates the SVG assets without blocking the loading screen.
+    tessellator: Tessellator,
+    /// Whether SVGs get a transparent fringe around their edges.
+    vertex_antialiasing: bool,
+    /// The data of the loaded fonts, the text in SVGs is converted to paths with them.
+    fonts: Fonts,
+}
+}
+
+impl Assets {
+    /// Create an empty asset manager.
+    pub fn new() -> Self {
+        Self {
+            assets: HashMap::new(),
+            has_queued: false,
+            tessellator: Tessellator::default(),
+            vertex_antialiasing: false,
+            fonts: Fonts::default(),
+        }
+    }
+
+    /// Set whether SVGs that are tessellated afterwards get a transparent fringe around their
+    /// edges, so they are smooth without MSAA.
+    pub fn set_vertex_antialiasing(&mut self, vertex_antialiasing: bool) {
+        self.vertex_antialiasing = vertex_antialiasing;
+    }
+
+    /// Queue an asset from memory.
+    ///
+    /// Returns `false` when an asset with the same reference name already exists.
+    pub fn add_bytes<R>(&mut self, reference_name: R, kind: AssetKind, bytes: Vec<u8>) -> bool
+    where
+        R: Into<String>,
+    {
+        self.add(reference_name.into(), kind, AssetSource::Bytes(bytes))
+    }
+
+    /// Queue an asset from a file.
+    ///
+    /// Returns `false` when an asset with the same reference name already exists.
+    pub fn add_path<R, P>(&mut self, reference_name: R, kind: AssetKind, path: P) -> bool
+    where
+        R: Into<String>,
+        P: Into<PathBuf>,
+    {
+        self.add(reference_name.into(), kind, AssetSource::Path(path.into()))
+    }
+
+    /// Queue a SVG from memory that will be rendered to a texture at a scale.
+    ///
+    /// Returns `false` when an asset with the same reference name already exists.
+    #[cfg(feature = "rasterize")]
+    pub fn add_rasterized_svg<R>(&mut self, reference_name: R, bytes: Vec<u8>, scale: f32) -> bool
+    where
+        R: Into<String>,
+    {
+        let reference_name = reference_name.into();
+        if !self.add(
+            reference_name.clone(),
+            AssetKind::RasterizedSvg,
+            AssetSource::Bytes(bytes),
+        ) {
+            return false;
+        }
+        if let Some(asset) = self.assets.get_mut(&reference_name) {
+            asset.raster_scale = scale;
+        }
+
+        true
+    }
+
+    /// Queue a SVG from memory with a tessellation tolerance & levels of detail.
+    ///
+    /// Returns `false` when an asset with the same reference name already exists.
+    pub fn add_svg_with_tolerance<R>(
+        &mut self,
+        reference_name: R,
+        bytes: Vec<u8>,
+        tolerance: f32,
+        lod_levels: usize,
+    ) -> bool
+    where
+        R: Into<String>,
+    {
+        let reference_name = reference_name.into();
+        if !self.add(
+            reference_name.clone(),
+            AssetKind::Svg,
+            AssetSource::Bytes(bytes),
+        ) {
+            return false;
+        }
+        if let Some(asset) = self.assets.get_mut(&reference_name) {
+            asset.detail = SvgDetail {
+                tolerance,
+                lod_levels,
+                ..SvgDetail::default()
+            };
+        }
+
+        true
+    }
+
+    /// Queue an asset, deduplicated by the reference name.
+    fn add(&mut self, reference_name: String, kind: AssetKind, source: AssetSource) -> bool {
+        if self.assets.contains_key(&reference_name) {
+            return false;
+        }
+
+        self.assets.insert(
+            reference_name,
+            Asset {
+                kind,
+                source: Some(source),
+                pending_file: None,
+                data: vec![],
+                mesh: None,
+                raster_scale: 1.0,
+                detail: SvgDetail::default(),
+                state: AssetState::Queued,
+            },
+        );
+        self.has_queued = true;
+
+        true
+    }
+
+    /// Remove an asset, freeing the GPU buffers of its mesh.
+    ///
+    /// Assets that are still loading are dropped when they finish. The handles of the mesh stay
+    /// safe to use, but it isn't drawn anymore. Returns `false` when the asset doesn't exist.
+    pub fn unload(&mut self, reference_name: &str, render: &mut Render) -> bool {
+        let asset = match self.assets.remove(reference_name) {
+            Some(asset) => asset,
+            None => return false,
+        };
+
+        if let Some(mesh) = asset.mesh {
+            if let Err(err) = render.unload_mesh(mesh) {
+                log::warn!("error unloading asset \"{}\": {}", reference_name, err);
+            }
+        }
+
+        true
+    }
+
+    /// The loading state of an asset, `None` if it doesn't exist.
+    pub fn state(&self, reference_name: &str) -> Option<&AssetState> {
+        self.assets.get(reference_name).map(|asset| &asset.state)
+    }
+
+    /// Whether an asset is loaded and can be used.
+    pub fn is_ready(&self, reference_name: &str) -> bool {
+        self.state(reference_name) == Some(&AssetState::Ready)
+    }
+
+    /// The mesh of a loaded SVG or texture asset.
+    pub fn mesh(&self, reference_name: &str) -> Option<Mesh> {
+        self.assets.get(reference_name).and_then(|asset| asset.mesh)
+    }
+
+    /// The reference name of the asset a mesh is loaded from, `None` for meshes created by the
+    /// scripts.
+    pub fn mesh_reference_name(&self, mesh: Mesh) -> Option<&str> {
+        self.assets
+            .iter()
+            .find(|(_, asset)| asset.mesh == Some(mesh))
+            .map(|(reference_name, _)| reference_name.as_str())
+    }
+
+    /// The raw data of a loaded asset.
+    pub fn data(&self, reference_name: &str) -> Option<&[u8]> {
+        self.assets
+            .get(reference_name)
+            .filter(|asset| asset.state == AssetState::Ready)
+            .map(|asset| asset.data.as_slice())
+    }
+
+    /// Whether all assets are either loaded or failed to load.
+    pub fn is_done(&self) -> bool {
+        !self.has_queued
+    }
+
+    /// The fraction of assets that are done loading, between `0.0` and `1.0`.
+    pub fn progress(&self) -> f32 {
+        if self.assets.is_empty() {
+            return 1.0;
+        }
+
+        let done = self
+            .assets
+            .values()
+            .filter(|asset| asset.state != AssetState::Queued && asset.state != AssetState::Loading)
+            .count();
+
+        done as f32 / self.assets.len() as f32
+    }
+
+    /// Progress the loading of all queued assets, uploading the SVGs to the GPU.
+    ///
+    /// Files are loaded asynchronously on WASM and SVGs are tessellated in the background, so
+    /// this must be called every frame until `is_done` returns `true`. Only a part of every frame
+    /// is spent on processing, the rest continues during the next call. Returns whether the last
+    /// queued asset finished loading during this call.
+    pub fn process(&mut self, render: &mut Render) -> bool {
+        if !self.has_queued {
+            return false;
+        }
+
+        let started = miniquad::date::now();
+        let mut finished = vec![];
+
+        // Upload the SVGs that are tessellated in the meantime
+        for (reference_name, data, geometry) in self.tessellator.finished() {
+            if let Some(asset) = self.assets.get_mut(&reference_name) {
+                asset.data = data;
+                if let Some(detailed) = geometry.as_ref().ok().and_then(|levels| levels.first()) {
+                    for warning in detailed.warnings() {
+                        log::warn!("SVG asset \"{}\": {}", reference_name, warning);
+                    }
+                }
+                let result = geometry
+                    .and_then(|levels| render.upload_svg_lods(levels))
+                    .map(|mesh| asset.mesh = Some(mesh));
+
+                finished.push(Self::set_result(&reference_name, asset, result));
+            }
+        }
+
+        // SVGs wait for the fonts, so their text can be converted to paths
+        let fonts_loading = self.assets.values().any(|asset| {
+            asset.kind == AssetKind::Font
+                && (asset.state == AssetState::Queued || asset.state == AssetState::Loading)
+        });
+        let font_count = self
+            .assets
+            .values()
+            .filter(|asset| asset.kind == AssetKind::Font && asset.state == AssetState::Ready)
+            .count();
+        if font_count != self.fonts.len() {
+            self.fonts = Arc::new(
+                self.assets
+                    .values()
+                    .filter(|asset| {
+                        asset.kind == AssetKind::Font && asset.state == AssetState::Ready
+                    })
+                    .map(|asset| asset.data.clone())
+                    .collect(),
+            );
+        }
+
+        for (reference_name, asset) in self.assets.iter_mut() {
+            // Continue during the next frame so the loading screen can be drawn
+            if miniquad::date::now() - started > FRAME_BUDGET {
+                break;
+            }
+            let is_svg = asset.kind == AssetKind::Svg;
+            #[cfg(feature = "rasterize")]
+            let is_svg = is_svg || asset.kind == AssetKind::RasterizedSvg;
+            if is_svg && fonts_loading {
+                continue;
+            }
+
+            let bytes = match asset.source.take() {
+                // The data is already available
+                Some(AssetSource::Bytes(bytes)) => bytes,
+                // Start loading the file, the result will be handled in a later call
+                Some(AssetSource::Path(path)) => {
+                    asset.pending_file = Some(Self::load_file(&path));
+                    asset.state = AssetState::Loading;
+
+                    continue;
+                }
+                None => {
+                    // Check if the file that's being loaded is finished
+                    let response = match asset.pending_file {
+                        Some(ref pending_file) => pending_file.borrow_mut().take(),
+                        None => continue,
+                    };
+                    match response {
+                        Some(Ok(bytes)) => bytes,
+                        Some(Err(err)) => {
+                            asset.pending_file = None;
+                            let result = Err(anyhow!("loading file failed: {:?}", err));
+                            finished.push(Self::set_result(reference_name, asset, result));
+
+                            continue;
+                        }
+                        None => continue,
+                    }
+                }
+            };
+            asset.pending_file = None;
+
+            // Tessellating is slow so it doesn't block the loading screen
+            let result = if asset.kind == AssetKind::Svg {
+                match self.tessellator.tessellate(
+                    reference_name,
+                    bytes,
+                    SvgDetail {
+                        antialiasing: self.vertex_antialiasing,
+                        ..asset.detail
+                    },
+                    self.fonts.clone(),
+                ) {
+                    Ok(()) => {
+                        asset.state = AssetState::Loading;
+
+                        continue;
+                    }
+                    Err(err) => Err(err),
+                }
+            } else {
+                Self::finish(asset, bytes, render, &self.fonts)
+            };
+            finished.push(Self::set_result(reference_name, asset, result));
+        }
+
+        // Notify the scripts subscribed with `on-event`
+        for (reference_name, ready) in finished {
+            let event = if ready {
+                "asset-loaded"
+            } else {
+                "asset-failed"
+            };
+            if let Err(err) = Events::emit_engine_event(event, reference_name) {
+                log::error!("error emitting {} event: {}", event, err);
+            }
+        }
+
+        self.has_queued = self
+            .assets
+            .values()
+            .any(|asset| asset.state == AssetState::Queued || asset.state == AssetState::Loading);
+
+        !self.has_queued
+    }
+
+    /// Store whether loading an asset succeeded, returns the reference name & whether it's ready.
+    fn set_result(reference_name: &str, asset: &mut Asset, result: Result<()>) -> (String, bool) {
+        asset.state = match result {
+            Ok(()) => AssetState::Ready,
+            Err(err) => {
+                log::error!("error loading asset \"{}\": {}", reference_name, err);
+
+                AssetState::Failed(err.to_string())
+            }
+        };
+
+        (reference_name.to_string(), asset.state == AssetState::Ready)
+    }
+
+    /// Start loading a file, on WASM this will be fetched from the server.
+    fn load_file(path: &Path) -> PendingFile {
+        let pending_file = Rc::new(RefCell::new(None));
+
+        let result = pending_file.clone();
+        miniquad::fs::load_file(&path.to_string_lossy(), move |response| {
+            *result.borrow_mut() = Some(response);
+        });
+
+        pending_file
+    }
+
+    /// Process the loaded data of a single asset that's not a SVG.
+    ///
+    /// The fonts are only used by rasterized SVGs.
+    #[cfg_attr(not(feature = "rasterize"), allow(unused_variables))]
+    fn finish(
+        asset: &mut Asset,
+        data: Vec<u8>,
+        render: &mut Render,
+        fonts: &[Vec<u8>],
+    ) -> Result<()> {
+        asset.kind = asset.kind.sniffed(&data);
+        asset.data = data;
+
+        // Report broken maps & animations while loading instead of when they are used
+        match asset.kind {
+            AssetKind::Texture => {
+                let (width, height, pixels) = atlas::decode_png(&asset.data)?;
+                asset.mesh = Some(render.upload_image(width, height, &pixels)?);
+            }
+            #[cfg(feature = "rasterize")]
+            AssetKind::RasterizedSvg => {
+                let scale = asset.raster_scale;
+                let (width, height, pixels) = Render::rasterize_svg(&asset.data, scale, fonts)?;
+                // Cover the same area as the tessellated SVG would
+                let bounds = [0.0, 0.0, width as f32 / scale, height as f32 / scale];
+                asset.mesh = Some(render.upload_image_at(width, height, &pixels, bounds)?);
+            }
+            AssetKind::BakedMesh => {
+                asset.mesh = Some(render.import_mesh(&asset.data)?);
+            }
+            AssetKind::Tilemap => {
+                Tilemap::parse(&asset.data)?;
+            }
+            AssetKind::Animation => {
+                Animation::parse(&asset.data)?;
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Queue an asset from a script, the kind is determined by the file extension.
+    pub fn load_asset(&mut self, reference_name: &str, path: &str) -> GResult<bool> {
+        Sandbox::borrow().deny_filesystem(&format!("loading asset \"{}\"", path))?;
+
+        match AssetKind::from_path(path) {
+            Some(kind) => Ok(self.add_path(reference_name, kind, path)),
+            None => {
+                log::warn!("unknown asset type for file \"{}\"", path);
+
+                Ok(false)
+            }
+        }
+    }
+
+    /// Remove an asset from a script, see `unload`.
+    ///
+    /// ```gamelisp
+    /// (unload-asset "boss")
+    /// ```
+    fn unload_asset(&mut self, reference_name: &str) -> bool {
+        self.unload(reference_name, &mut Render::borrow_mut())
+    }
+
+    /// Get the loading progress of all assets from a script, between `0.0` and `1.0`.
+    ///
+    /// ```gamelisp
+    /// (set-scale-x progress-bar (loading-progress))
+    /// ```
+    fn loading_progress(&self) -> f32 {
+        self.progress()
+    }
+
+    /// Check from a script whether an asset is ready to be used.
+    fn asset_ready(&self, reference_name: &str) -> bool {
+        self.is_ready(reference_name)
+    }
+
+    /// Get the mesh of a loaded SVG or texture asset from a script.
+    fn asset_mesh(&self, reference_name: &str) -> Option<Mesh> {
+        self.mesh(reference_name)
+    }
+
+    /// Register the asset manager and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Assets::new());
+
+            glsp::bind_rfn("load-asset", rfn!(Self::load_asset))?;
+            glsp::bind_rfn("unload-asset", rfn!(Self::unload_asset))?;
+            glsp::bind_rfn("asset-ready?", rfn!(Self::asset_ready))?;
+            glsp::bind_rfn("asset-mesh", rfn!(Self::asset_mesh))?;
+            glsp::bind_rfn("loading-progress", rfn!(Self::loading_progress))?;
+
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Tiled map of 2 by 2 tiles saved in the JSON format.
+    const TILED_JSON: &str = r#"{
+        "tiledversion": "1.4.3",
+        "type": "map",
+        "width": 2,
+        "height": 2,
+        "tilewidth": 16,
+        "tileheight": 16,
+        "infinite": false,
+        "layers": [{ "type": "tilelayer", "name": "ground", "data": [1, 2, 0, 1] }]
+    }"#;
+
+    /// The start of a Lottie animation.
+    const LOTTIE_JSON: &str =
+        r#"{ "v": "5.7.4", "fr": 30, "ip": 0, "op": 60, "w": 100, "h": 100, "layers": [] }"#;
+
+    #[test]
+    fn tiled_json_files_are_tilemaps() {
+        let kind = AssetKind::from_path("levels/level.json").unwrap();
+
+        assert_eq!(kind.sniffed(TILED_JSON.as_bytes()), AssetKind::Tilemap);
+        assert!(Tilemap::parse(TILED_JSON.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn other_json_files_are_animations() {
+        let kind = AssetKind::from_path("effects/explosion.json").unwrap();
+
+        assert_eq!(kind.sniffed(LOTTIE_JSON.as_bytes()), AssetKind::Animation);
+        assert_eq!(kind.sniffed(b"not json"), AssetKind::Animation);
+    }
+
+    #[test]
+    fn other_kinds_are_kept() {
+        assert_eq!(
+            AssetKind::from_path("maps/level.tmj")
+                .unwrap()
+                .sniffed(LOTTIE_JSON.as_bytes()),
+            AssetKind::Tilemap
+        );
+        assert_eq!(
+            AssetKind::Texture.sniffed(TILED_JSON.as_bytes()),
+            AssetKind::Texture
+        );
+    }
+}
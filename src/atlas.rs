@@ -0,0 +1,258 @@
+use anyhow::{anyhow, Result};
+use miniquad::{graphics::*, Context};
+
+/// The width & height of every atlas page in pixels.
+const PAGE_SIZE: u32 = 2048;
+/// The transparent pixels around every image, so neighbours don't bleed in when filtering.
+const PADDING: u32 = 1;
+
+/// Where an image is packed in the atlas.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AtlasRegion {
+    /// The index of the page the image is on.
+    pub page: usize,
+    /// The texture coordinates of the image as `[min_u, min_v, max_u, max_v]`.
+    pub uv: [f32; 4],
+}
+
+/// A row of images with the height of the first image placed in it.
+#[derive(Debug)]
+struct Shelf {
+    /// The top of the row in pixels.
+    y: u32,
+    /// The height of the row in pixels.
+    height: u32,
+    /// Where the next image in the row is placed.
+    x: u32,
+}
+
+/// A single texture the images are packed in.
+#[derive(Debug)]
+struct Page {
+    /// The rows of images, from top to bottom.
+    shelves: Vec<Shelf>,
+    /// The area covered by images in pixels, without their padding.
+    used_area: u64,
+    /// The images that still have to be copied to the texture, with their padding.
+    pending: Vec<([u32; 4], Vec<u8>)>,
+    /// The texture on the GPU, created when the page is uploaded for the first time.
+    texture: Option<Texture>,
+}
+
+impl Page {
+    /// Create without any images.
+    fn new() -> Self {
+        Self {
+            shelves: vec![],
+            used_area: 0,
+            pending: vec![],
+            texture: None,
+        }
+    }
+
+    /// Find a place for a padded image, starting a new row when no row has space left.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        // Pick the shortest row that fits to waste the least amount of space
+        let best = self
+            .shelves
+            .iter_mut()
+            .filter(|shelf| shelf.height >= height && PAGE_SIZE - shelf.x >= width)
+            .min_by_key(|shelf| shelf.height);
+        if let Some(shelf) = best {
+            let position = (shelf.x, shelf.y);
+            shelf.x += width;
+
+            return Some(position);
+        }
+
+        let y = self
+            .shelves
+            .last()
+            .map(|shelf| shelf.y + shelf.height)
+            .unwrap_or(0);
+        if PAGE_SIZE - y < height {
+            return None;
+        }
+        self.shelves.push(Shelf {
+            y,
+            height,
+            x: width,
+        });
+
+        Some((0, y))
+    }
+}
+
+/// Packs the pixels of many small images into a few large textures.
+///
+/// Meshes drawn with images from the same page don't need to bind another texture. The images
+/// are placed in rows on the first page with space left, when none has a new page is started.
+#[derive(Debug)]
+pub struct Atlas {
+    /// The textures the images are packed in.
+    pages: Vec<Page>,
+}
+
+impl Atlas {
+    /// Create without any pages.
+    pub fn new() -> Self {
+        Self { pages: vec![] }
+    }
+
+    /// Pack the RGBA pixels of an image, it's copied to the GPU during the next upload.
+    pub fn pack(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<AtlasRegion> {
+        if width == 0 || height == 0 {
+            return Err(anyhow!("image is empty"));
+        }
+        if pixels.len() != width as usize * height as usize * 4 {
+            return Err(anyhow!(
+                "image of {}x{} pixels has {} bytes instead of {}",
+                width,
+                height,
+                pixels.len(),
+                width * height * 4
+            ));
+        }
+        let (padded_width, padded_height) = (width + PADDING * 2, height + PADDING * 2);
+        if padded_width > PAGE_SIZE || padded_height > PAGE_SIZE {
+            return Err(anyhow!(
+                "image of {}x{} pixels is larger than an atlas page of {}x{}",
+                width,
+                height,
+                PAGE_SIZE - PADDING * 2,
+                PAGE_SIZE - PADDING * 2
+            ));
+        }
+
+        let found = self.pages.iter_mut().enumerate().find_map(|(index, page)| {
+            page.allocate(padded_width, padded_height)
+                .map(|position| (index, position))
+        });
+        let (page_index, (x, y)) = match found {
+            Some(found) => found,
+            None => {
+                let mut page = Page::new();
+                let position = page
+                    .allocate(padded_width, padded_height)
+                    .expect("image doesn't fit on an empty page");
+                self.pages.push(page);
+
+                (self.pages.len() - 1, position)
+            }
+        };
+
+        // Surround the image with transparent pixels
+        let row_size = padded_width as usize * 4;
+        let mut padded = vec![0; row_size * padded_height as usize];
+        for (row, pixel_row) in pixels.chunks_exact(width as usize * 4).enumerate() {
+            let start = (row + PADDING as usize) * row_size + PADDING as usize * 4;
+            padded[start..start + pixel_row.len()].copy_from_slice(pixel_row);
+        }
+
+        let page = &mut self.pages[page_index];
+        page.used_area += width as u64 * height as u64;
+        page.pending
+            .push(([x, y, padded_width, padded_height], padded));
+
+        let size = PAGE_SIZE as f32;
+        let (min_x, min_y) = (x + PADDING, y + PADDING);
+
+        Ok(AtlasRegion {
+            page: page_index,
+            uv: [
+                min_x as f32 / size,
+                min_y as f32 / size,
+                (min_x + width) as f32 / size,
+                (min_y + height) as f32 / size,
+            ],
+        })
+    }
+
+    /// Copy the images packed since the last call to the textures of their pages.
+    pub fn upload(&mut self, ctx: &mut Context) {
+        for page in self
+            .pages
+            .iter_mut()
+            .filter(|page| !page.pending.is_empty())
+        {
+            let texture = *page.texture.get_or_insert_with(|| {
+                Texture::new(
+                    ctx,
+                    TextureAccess::Static,
+                    None,
+                    TextureParams {
+                        format: TextureFormat::RGBA8,
+                        wrap: TextureWrap::Clamp,
+                        filter: FilterMode::Linear,
+                        width: PAGE_SIZE,
+                        height: PAGE_SIZE,
+                    },
+                )
+            });
+
+            for ([x, y, width, height], pixels) in page.pending.drain(..) {
+                texture.update_texture_part(
+                    ctx,
+                    x as i32,
+                    y as i32,
+                    width as i32,
+                    height as i32,
+                    &pixels,
+                );
+            }
+        }
+    }
+
+    /// The texture of a page, `None` when it isn't uploaded yet.
+    pub fn texture(&self, page: usize) -> Option<Texture> {
+        self.pages.get(page).and_then(|page| page.texture)
+    }
+
+    /// How many pages the images are packed in.
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// The fraction of the area of all pages covered by images, between `0.0` and `1.0`.
+    pub fn utilization(&self) -> f32 {
+        if self.pages.is_empty() {
+            return 0.0;
+        }
+
+        let used_area: u64 = self.pages.iter().map(|page| page.used_area).sum();
+        let total_area = self.pages.len() as u64 * PAGE_SIZE as u64 * PAGE_SIZE as u64;
+
+        used_area as f32 / total_area as f32
+    }
+}
+
+/// Decode a PNG image to its width, height & RGBA pixels with the top row first.
+pub fn decode_png(png: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(png);
+    // Convert palettes & low bit depths to 8 bit channels
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder
+        .read_info()
+        .map_err(|err| anyhow!("reading PNG failed: {}", err))?;
+    let mut pixels = vec![0; info.buffer_size()];
+    reader
+        .next_frame(&mut pixels)
+        .map_err(|err| anyhow!("decoding PNG failed: {}", err))?;
+
+    // Convert all color types to RGBA
+    let rgba: Vec<u8> = match info.color_type {
+        png::ColorType::RGBA => pixels,
+        png::ColorType::RGB => pixels
+            .chunks_exact(3)
+            .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|ga| vec![ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|g| vec![*g, *g, *g, 255]).collect(),
+        png::ColorType::Indexed => return Err(anyhow!("PNG palette couldn't be expanded")),
+    };
+
+    Ok((info.width, info.height, rgba))
+}
@@ -0,0 +1,303 @@
+use crate::render::{Render, SvgGeometry, Vertex};
+use anyhow::{anyhow, Result};
+use lyon::tessellation::VertexBuffers;
+use std::{convert::TryInto, path::Path};
+
+/// The first bytes of every `.clogmesh` file.
+const MAGIC: &[u8; 8] = b"CLOGMESH";
+/// The version of the format that's written, every version up to it can be read.
+const VERSION: u32 = 1;
+
+/// Tessellate a SVG to a baked mesh that's loaded without parsing or tessellating it again.
+///
+/// The levels of detail are tessellated as with `Clog::load_svg_with_tolerance`, text is converted
+/// to paths with the TTF or OTF fonts passed. Load the result with `Clog::load_baked_mesh` or as
+/// a `.clogmesh` asset file.
+pub fn bake_svg(
+    svg: &[u8],
+    tolerance: f32,
+    lod_levels: usize,
+    antialiasing: bool,
+    fonts: &[Vec<u8>],
+) -> Result<Vec<u8>> {
+    let levels = Render::tessellate_svg_lods(svg, tolerance, lod_levels, antialiasing, fonts)?;
+    for warning in levels[0].warnings() {
+        log::warn!("SVG {}", warning);
+    }
+
+    Ok(encode(&levels))
+}
+
+/// Bake a SVG file to a `.clogmesh` file, meant to be called from a build script.
+///
+/// ```rust,no_run
+/// // build.rs
+/// fn main() -> anyhow::Result<()> {
+///     let out_dir = std::env::var("OUT_DIR")?;
+///     clogs::bake_svg_file("assets/ship.svg", format!("{}/ship.clogmesh", out_dir), 0.1, 2)
+/// }
+/// ```
+pub fn bake_svg_file<P, Q>(input: P, output: Q, tolerance: f32, lod_levels: usize) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let input = input.as_ref();
+    println!("cargo:rerun-if-changed={}", input.display());
+
+    let svg = std::fs::read(input)?;
+    let baked = bake_svg(&svg, tolerance, lod_levels, false, &[])?;
+
+    std::fs::write(output, baked)?;
+
+    Ok(())
+}
+
+/// Write levels of detail in the `.clogmesh` format, from the most detailed to the coarsest.
+///
+/// The layout is documented at `Render::export_mesh`.
+pub fn encode(levels: &[SvgGeometry]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+
+    for level in levels {
+        let geometry = level.geometry();
+        bytes.extend_from_slice(&level.tolerance().to_le_bytes());
+
+        bytes.extend_from_slice(&(geometry.vertices.len() as u32).to_le_bytes());
+        for vertex in geometry.vertices.iter() {
+            for float in vertex.to_floats().iter() {
+                bytes.extend_from_slice(&float.to_le_bytes());
+            }
+        }
+
+        bytes.extend_from_slice(&(geometry.indices.len() as u32).to_le_bytes());
+        for index in geometry.indices.iter() {
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&(level.outline().len() as u32).to_le_bytes());
+        for point in level.outline() {
+            bytes.extend_from_slice(&point[0].to_le_bytes());
+            bytes.extend_from_slice(&point[1].to_le_bytes());
+        }
+    }
+
+    bytes
+}
+
+/// Read the levels of detail of a `.clogmesh` file written by `encode`.
+pub fn decode(bytes: &[u8]) -> Result<Vec<SvgGeometry>> {
+    let mut reader = Reader { bytes };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(anyhow!("not a .clogmesh file"));
+    }
+    let version = reader.u32()?;
+    if version == 0 || version > VERSION {
+        return Err(anyhow!(
+            ".clogmesh version {} isn't supported, the newest is {}",
+            version,
+            VERSION
+        ));
+    }
+
+    let level_count = reader.u32()?;
+    if level_count == 0 {
+        return Err(anyhow!(".clogmesh file doesn't have any levels of detail"));
+    }
+
+    (0..level_count)
+        .map(|_| {
+            // The zoom of the levels is divided by their tolerance when they're uploaded
+            let tolerance = reader.f32()?;
+            if !tolerance.is_finite() || tolerance <= 0.0 {
+                return Err(anyhow!(".clogmesh tolerance {} isn't positive", tolerance));
+            }
+
+            let mut geometry: VertexBuffers<Vertex, u16> = VertexBuffers::new();
+            for _ in 0..reader.u32()? {
+                let mut floats = [0.0; Vertex::FLOAT_COUNT];
+                for float in floats.iter_mut() {
+                    *float = reader.f32()?;
+                }
+                geometry.vertices.push(Vertex::from_floats(floats));
+            }
+            for _ in 0..reader.u32()? {
+                let index = u16::from_le_bytes(reader.take(2)?.try_into()?);
+                if index as usize >= geometry.vertices.len() {
+                    return Err(anyhow!(".clogmesh index {} is out of bounds", index));
+                }
+                geometry.indices.push(index);
+            }
+
+            if geometry.indices.len() % 3 != 0 {
+                return Err(anyhow!(".clogmesh indices don't form whole triangles"));
+            }
+
+            let mut outline = vec![];
+            for _ in 0..reader.u32()? {
+                outline.push([reader.f32()?, reader.f32()?]);
+            }
+
+            Ok(SvgGeometry::new(geometry, outline, tolerance))
+        })
+        .collect()
+}
+
+/// Reads the numbers of a `.clogmesh` file from the start of the remaining bytes.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Take the next bytes, fails when the data ends before them.
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < count {
+            return Err(anyhow!(".clogmesh file ends unexpectedly"));
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A unit square of two triangles.
+    fn square(tolerance: f32, indices: Vec<u16>) -> SvgGeometry {
+        let color = [1.0, 0.5, 0.25, 1.0];
+        let vertices = vec![
+            Vertex::new([0.0, 0.0], color),
+            Vertex::new([1.0, 0.0], color),
+            Vertex::new([1.0, 1.0], color),
+            Vertex::new([0.0, 1.0], color),
+        ];
+        let outline = vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0], [0.0, 0.0]];
+
+        SvgGeometry::new(VertexBuffers { vertices, indices }, outline, tolerance)
+    }
+
+    /// The numbers of all vertices of a level.
+    fn floats(level: &SvgGeometry) -> Vec<[f32; Vertex::FLOAT_COUNT]> {
+        level
+            .geometry()
+            .vertices
+            .iter()
+            .map(Vertex::to_floats)
+            .collect()
+    }
+
+    /// The message of a failed decode.
+    fn decode_error(bytes: &[u8]) -> String {
+        decode(bytes).err().expect("decoding succeeded").to_string()
+    }
+
+    #[test]
+    fn levels_round_trip() {
+        let levels = vec![
+            square(0.1, vec![0, 1, 2, 0, 2, 3]),
+            square(0.4, vec![0, 1, 2]),
+        ];
+        let decoded = decode(&encode(&levels)).unwrap();
+
+        assert_eq!(decoded.len(), levels.len());
+        for (decoded, level) in decoded.iter().zip(levels.iter()) {
+            assert_eq!(floats(decoded), floats(level));
+            assert_eq!(decoded.geometry().indices, level.geometry().indices);
+            assert_eq!(decoded.outline(), level.outline());
+            assert_eq!(decoded.tolerance(), level.tolerance());
+        }
+    }
+
+    #[test]
+    fn exported_meshes_round_trip() {
+        let mut render = Render::headless((320.0, 240.0), false);
+        let levels = vec![
+            square(0.1, vec![0, 1, 2, 0, 2, 3]),
+            square(0.4, vec![0, 1, 2]),
+        ];
+        let mesh = render.import_mesh(&encode(&levels)).unwrap();
+        let exported = decode(&render.export_mesh(mesh).unwrap()).unwrap();
+
+        assert_eq!(exported.len(), levels.len());
+        for (exported, level) in exported.iter().zip(levels.iter()) {
+            assert_eq!(floats(exported), floats(level));
+            assert_eq!(exported.geometry().indices, level.geometry().indices);
+        }
+        // Only the ratio between the tolerances is kept
+        assert_eq!(exported[1].tolerance() / exported[0].tolerance(), 4.0);
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let bytes = encode(&[square(0.1, vec![0, 1, 2, 0, 2, 3])]);
+        for length in 0..bytes.len() {
+            assert!(decode(&bytes[..length]).is_err(), "length {}", length);
+        }
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let mut bytes = encode(&[square(0.1, vec![0, 1, 2])]);
+        bytes[..MAGIC.len()].copy_from_slice(b"NOTAMESH");
+
+        assert_eq!(decode_error(&bytes), "not a .clogmesh file");
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        for version in [0, VERSION + 1].iter() {
+            let mut bytes = encode(&[square(0.1, vec![0, 1, 2])]);
+            bytes[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&version.to_le_bytes());
+
+            assert!(decode_error(&bytes).contains("isn't supported"));
+        }
+    }
+
+    #[test]
+    fn empty_files_are_rejected() {
+        assert!(decode_error(&encode(&[])).contains("doesn't have any levels"));
+    }
+
+    #[test]
+    fn out_of_bounds_indices_are_rejected() {
+        let bytes = encode(&[square(0.1, vec![0, 1, 4])]);
+
+        assert!(decode_error(&bytes).contains("index 4 is out of bounds"));
+    }
+
+    #[test]
+    fn partial_triangles_are_rejected() {
+        let bytes = encode(&[square(0.1, vec![0, 1, 2, 0])]);
+
+        assert!(decode_error(&bytes).contains("whole triangles"));
+    }
+
+    #[test]
+    fn invalid_tolerances_are_rejected() {
+        for tolerance in [0.0, -0.1, f32::NAN, f32::INFINITY].iter() {
+            let bytes = encode(&[
+                square(0.1, vec![0, 1, 2]),
+                square(*tolerance, vec![0, 1, 2]),
+            ]);
+
+            assert!(
+                decode_error(&bytes).contains("isn't positive"),
+                "tolerance {}",
+                tolerance
+            );
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use crate::render::{Instance, Vertex};
+use miniquad::{graphics::*, Context};
+
+/// Maximum amount of vertices in a single batch, limited by the 16 bit indices.
+const MAX_BATCH_VERTICES: usize = u16::MAX as usize + 1;
+/// Maximum amount of vertices of all instances of a mesh together for it to be merged with other
+/// meshes, copying more vertices every frame is slower than another draw call.
+pub const MAX_MERGED_VERTICES: usize = 1024;
+
+/// The geometry of multiple meshes packed into a single vertex & index buffer.
+///
+/// The indices of every mesh are offset by the amount of vertices before it, so meshes can be
+/// drawn from the shared buffers with only an offset into the index buffer.
+///
+/// This saves buffers & switching them between meshes. Meshes with many vertices or instances are
+/// drawn with a draw call of their own from these buffers, small ones are merged with
+/// [`MergedDraw`].
+#[derive(Debug)]
+pub struct MeshBatch {
+    /// The vertices of all meshes, cleared when uploaded.
+    vertices: Vec<Vertex>,
+    /// The offset indices of all meshes, cleared when uploaded.
+    indices: Vec<u16>,
+    /// The vertex & index buffer, created when the batch is used for the first time.
+    buffers: Option<(Buffer, Buffer)>,
+    /// How many meshes in the batch are still loaded.
+    mesh_count: usize,
+}
+
+impl MeshBatch {
+    /// Create an empty batch.
+    pub fn new() -> Self {
+        Self {
+            vertices: vec![],
+            indices: vec![],
+            buffers: None,
+            mesh_count: 0,
+        }
+    }
+
+    /// Whether the geometry of a mesh with this amount of vertices can still be added.
+    pub fn fits(&self, vertex_count: usize) -> bool {
+        self.buffers.is_none() && self.vertices.len() + vertex_count <= MAX_BATCH_VERTICES
+    }
+
+    /// Add the geometry of a mesh.
+    ///
+    /// Returns the position of the first index of the mesh in the index buffer.
+    pub fn push(&mut self, vertices: &[Vertex], indices: &[u16]) -> usize {
+        let base_vertex = self.vertices.len() as u32;
+        let first_index = self.indices.len();
+        self.mesh_count += 1;
+
+        self.vertices.extend_from_slice(vertices);
+        self.indices.extend(
+            indices
+                .iter()
+                .map(|index| (base_vertex + *index as u32) as u16),
+        );
+
+        first_index
+    }
+
+    /// The shared vertex & index buffer, uploaded when called for the first time.
+    ///
+    /// No meshes can be added to the batch anymore afterwards.
+    pub fn buffers(&mut self, ctx: &mut Context) -> (Buffer, Buffer) {
+        if self.buffers.is_none() {
+            let vertex_buffer = Buffer::immutable(ctx, BufferType::VertexBuffer, &self.vertices);
+            let index_buffer = Buffer::immutable(ctx, BufferType::IndexBuffer, &self.indices);
+            self.buffers = Some((vertex_buffer, index_buffer));
+
+            // The geometry lives on the GPU now
+            self.vertices = vec![];
+            self.indices = vec![];
+        }
+
+        self.buffers.unwrap()
+    }
+
+    /// Remove a mesh from the batch, the buffers are deleted when it was the last one.
+    ///
+    /// The emptied batch starts over when another mesh is added to it.
+    pub fn release(&mut self) {
+        self.mesh_count = self.mesh_count.saturating_sub(1);
+        if self.mesh_count > 0 {
+            return;
+        }
+
+        if let Some((vertex_buffer, index_buffer)) = self.buffers.take() {
+            vertex_buffer.delete();
+            index_buffer.delete();
+        }
+        self.vertices = vec![];
+        self.indices = vec![];
+    }
+
+    /// The size of the vertex & index buffer in bytes, zero when they aren't uploaded.
+    pub fn memory(&self) -> (usize, usize) {
+        self.buffers
+            .map_or((0, 0), |(vertex_buffer, index_buffer)| {
+                (vertex_buffer.size(), index_buffer.size())
+            })
+    }
+}
+
+/// The geometry of small meshes drawn after each other with the same pipeline, drawn in a single
+/// draw call.
+///
+/// The vertices of a mesh are copied for every instance & the instance is stored with every vertex
+/// instead of in an instance buffer, so meshes with different amounts of instances can be drawn
+/// together. The indices of every copy are offset by the amount of vertices before it.
+#[derive(Debug, Default)]
+pub struct MergedDraw {
+    /// The vertices of every instance of every mesh.
+    vertices: Vec<Vertex>,
+    /// The instance of every vertex.
+    instances: Vec<Instance>,
+    /// The offset indices of every instance of every mesh.
+    indices: Vec<u16>,
+}
+
+impl MergedDraw {
+    /// Create without any geometry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether meshes with this amount of vertices for all their instances together can be drawn
+    /// in a single merged draw, limited by the 16 bit indices.
+    pub fn fits(vertex_count: usize) -> bool {
+        vertex_count <= MAX_BATCH_VERTICES
+    }
+
+    /// Add an instance of a mesh.
+    pub fn push(&mut self, vertices: &[Vertex], indices: &[u16], instance: Instance) {
+        let base_vertex = self.vertices.len() as u32;
+
+        self.vertices.extend_from_slice(vertices);
+        self.instances
+            .extend(std::iter::repeat(instance).take(vertices.len()));
+        self.indices.extend(
+            indices
+                .iter()
+                .map(|index| (base_vertex + *index as u32) as u16),
+        );
+    }
+
+    /// The amount of indices drawn.
+    pub fn index_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Remove the geometry so it can be filled with the next meshes.
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.instances.clear();
+        self.indices.clear();
+    }
+}
+
+/// The buffers the merged draws are streamed to every frame.
+///
+/// Every merged draw of a view has its own buffers, which are reused by the next view & frame.
+#[derive(Debug, Default)]
+pub struct MergedBuffers {
+    /// The vertex, instance & index buffer of every merged draw.
+    buffers: Vec<(Buffer, Buffer, Buffer)>,
+}
+
+impl MergedBuffers {
+    /// Create without any buffers, they are created when used.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Upload the geometry of a merged draw to the buffers at an index, growing them when it
+    /// doesn't fit.
+    ///
+    /// Returns the bindings for drawing it.
+    pub fn upload(
+        &mut self,
+        ctx: &mut Context,
+        index: usize,
+        draw: &MergedDraw,
+        images: Vec<Texture>,
+    ) -> Bindings {
+        let sizes = [
+            draw.vertices.len() * std::mem::size_of::<Vertex>(),
+            draw.instances.len() * std::mem::size_of::<Instance>(),
+            draw.indices.len() * std::mem::size_of::<u16>(),
+        ];
+        let fits = self.buffers.get(index).map_or(false, |buffers| {
+            buffers.0.size() >= sizes[0]
+                && buffers.1.size() >= sizes[1]
+                && buffers.2.size() >= sizes[2]
+        });
+        if !fits {
+            // Grow to the next power of two so the buffers aren't recreated every frame
+            let buffers = (
+                Buffer::stream(ctx, BufferType::VertexBuffer, sizes[0].next_power_of_two()),
+                Buffer::stream(ctx, BufferType::VertexBuffer, sizes[1].next_power_of_two()),
+                Buffer::stream(ctx, BufferType::IndexBuffer, sizes[2].next_power_of_two()),
+            );
+            if index < self.buffers.len() {
+                let (vertex_buffer, instance_buffer, index_buffer) =
+                    std::mem::replace(&mut self.buffers[index], buffers);
+                vertex_buffer.delete();
+                instance_buffer.delete();
+                index_buffer.delete();
+            } else {
+                self.buffers.push(buffers);
+            }
+        }
+
+        let (vertex_buffer, instance_buffer, index_buffer) = self.buffers[index];
+        vertex_buffer.update(ctx, &draw.vertices);
+        instance_buffer.update(ctx, &draw.instances);
+        index_buffer.update(ctx, &draw.indices);
+
+        Bindings {
+            vertex_buffers: vec![vertex_buffer, instance_buffer],
+            index_buffer,
+            images,
+        }
+    }
+}
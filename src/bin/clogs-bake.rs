@@ -0,0 +1,77 @@
+//! Bake SVGs to `.clogmesh` files that are loaded without parsing or tessellating them.
+//!
+//! ```sh
+//! clogs-bake ship.svg ship.clogmesh --tolerance 0.05 --lod-levels 2 --font fonts/Roboto.ttf
+//! ```
+
+use anyhow::{anyhow, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+use lyon::tessellation::FillOptions;
+
+const USAGE: &str = "usage: clogs-bake <input.svg> <output.clogmesh> [--tolerance <units>] \
+                     [--lod-levels <count>] [--antialiasing] [--font <file.ttf>]...";
+
+/// Prints the warnings about what can't be drawn.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        eprintln!(
+            "{}: {}",
+            record.level().to_string().to_lowercase(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+fn main() -> Result<()> {
+    log::set_logger(&StderrLogger).map_err(|err| anyhow!("{}", err))?;
+    log::set_max_level(LevelFilter::Warn);
+
+    let mut args = std::env::args().skip(1);
+    let mut paths = vec![];
+    let mut tolerance = FillOptions::DEFAULT_TOLERANCE;
+    let mut lod_levels = 0;
+    let mut antialiasing = false;
+    let mut fonts = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tolerance" => tolerance = value(&mut args, &arg)?.parse()?,
+            "--lod-levels" => lod_levels = value(&mut args, &arg)?.parse()?,
+            "--font" => fonts.push(std::fs::read(value(&mut args, &arg)?)?),
+            "--antialiasing" => antialiasing = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+
+                return Ok(());
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let (input, output) = match paths.as_slice() {
+        [input, output] => (input, output),
+        _ => return Err(anyhow!(USAGE)),
+    };
+
+    let svg = std::fs::read(input)?;
+    let baked = clogs::bake_svg(&svg, tolerance, lod_levels, antialiasing, &fonts)?;
+    std::fs::write(output, &baked)?;
+
+    println!("baked {} to {} ({} bytes)", input, output, baked.len());
+
+    Ok(())
+}
+
+/// The value following an option.
+fn value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String> {
+    args.next()
+        .ok_or_else(|| anyhow!("{} is missing its value\n{}", option, USAGE))
+}
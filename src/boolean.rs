@@ -0,0 +1,298 @@
+use crate::{
+    render::{Mesh, Render, Vertex},
+    shapes::Style,
+};
+use geo_booleanop::boolean::BooleanOp;
+use geo_types::{Coordinate, LineString, MultiPolygon, Polygon};
+use glsp::{bail, lib, rfn, GResult, Runtime};
+use lyon::{
+    math::point,
+    path::Path,
+    tessellation::{FillTessellator, StrokeTessellator, VertexBuffers},
+};
+
+/// The boolean operations that can be applied to two meshes.
+#[derive(Debug, Copy, Clone)]
+enum Operation {
+    Union,
+    Intersection,
+    Difference,
+    Xor,
+}
+
+lib! {
+/// Combines the outlines of meshes with boolean operations into new meshes.
+///
+/// A mesh is used as the polygon of its outline, so only meshes with a closed outline like the
+/// primitive shapes can be combined. The polygons of the meshes created here are kept, holes &
+/// separate parts included, so the results can be combined again for destructible terrain.
+///
+/// ```gamelisp
+/// (let crater (create-circle 20))
+/// (= terrain (mesh-difference terrain crater impact-x impact-y (arr 90 60 30)))
+/// ```
+pub struct BooleanOps {
+    /// The polygons of every mesh created by an operation, with the outline the mesh had then.
+    shapes: Vec<(Mesh, Vec<[f32; 2]>, MultiPolygon<f64>)>,
+}
+}
+
+impl BooleanOps {
+    /// Create without any combined meshes.
+    pub fn new() -> Self {
+        Self { shapes: vec![] }
+    }
+
+    /// The polygons of a mesh, moved by an offset.
+    pub(crate) fn polygons(
+        &self,
+        render: &Render,
+        mesh: Mesh,
+        offset: [f32; 2],
+    ) -> GResult<MultiPolygon<f64>> {
+        let outline = render.mesh_path(mesh);
+
+        // Meshes created by an operation keep their holes, unless the geometry was replaced
+        let stored = self
+            .shapes
+            .iter()
+            .find(|(other, other_outline, _)| *other == mesh && other_outline == outline);
+        let polygons = match stored {
+            Some((_, _, polygons)) => polygons.clone(),
+            None => {
+                if outline.len() < 4 || outline.first() != outline.last() {
+                    bail!("only meshes with a closed outline can be combined");
+                }
+
+                MultiPolygon(vec![Polygon::new(line_string(outline), vec![])])
+            }
+        };
+
+        Ok(MultiPolygon(
+            polygons
+                .0
+                .into_iter()
+                .map(|polygon| {
+                    let (exterior, interiors) = polygon.into_inner();
+
+                    Polygon::new(
+                        translate(exterior, offset),
+                        interiors
+                            .into_iter()
+                            .map(|interior| translate(interior, offset))
+                            .collect(),
+                    )
+                })
+                .collect(),
+        ))
+    }
+
+    /// Apply an operation to two meshes and upload the result as a new mesh.
+    fn combine(
+        &mut self,
+        operation: Operation,
+        a: &Mesh,
+        b: &Mesh,
+        offset: [f32; 2],
+        color: Option<(u8, u8, u8)>,
+        stroke_width: Option<f32>,
+    ) -> GResult<Mesh> {
+        let mut render = Render::borrow_mut();
+        let a_polygons = self.polygons(&render, *a, [0.0, 0.0])?;
+        let b_polygons = self.polygons(&render, *b, offset)?;
+
+        let result = match operation {
+            Operation::Union => a_polygons.union(&b_polygons),
+            Operation::Intersection => a_polygons.intersection(&b_polygons),
+            Operation::Difference => a_polygons.difference(&b_polygons),
+            Operation::Xor => a_polygons.xor(&b_polygons),
+        };
+
+        let path = polygons_path(&result);
+
+        let mut geometry: VertexBuffers<Vertex, u16> = VertexBuffers::new();
+        Style::new(color, stroke_width).tessellate(
+            &mut geometry,
+            |options, output| FillTessellator::new().tessellate(path.iter(), options, output),
+            |options, output| StrokeTessellator::new().tessellate(path.iter(), options, output),
+        )?;
+
+        let mesh = match render.upload_buffers(&geometry) {
+            Ok(mesh) => mesh,
+            Err(err) => bail!("uploading combined mesh failed: {}", err),
+        };
+
+        // Instances follow the outside of the first polygon
+        let outline: Vec<[f32; 2]> = result
+            .0
+            .first()
+            .map(|polygon| {
+                polygon
+                    .exterior()
+                    .0
+                    .iter()
+                    .map(|coord| [coord.x as f32, coord.y as f32])
+                    .collect()
+            })
+            .unwrap_or_default();
+        render.set_mesh_path(mesh, outline.clone());
+        self.shapes.push((mesh, outline, result));
+
+        Ok(mesh)
+    }
+
+    /// Create a mesh covering both meshes from a script.
+    ///
+    /// The second mesh is moved by the optional offset.
+    ///
+    /// ```gamelisp
+    /// (mesh-union (create-circle 20) (create-rect 40 10) 20 0)
+    /// ```
+    fn union(
+        &mut self,
+        a: &Mesh,
+        b: &Mesh,
+        x: Option<f32>,
+        y: Option<f32>,
+        color: Option<(u8, u8, u8)>,
+        stroke_width: Option<f32>,
+    ) -> GResult<Mesh> {
+        self.combine(
+            Operation::Union,
+            a,
+            b,
+            [x.unwrap_or(0.0), y.unwrap_or(0.0)],
+            color,
+            stroke_width,
+        )
+    }
+
+    /// Create a mesh covering only where both meshes overlap from a script.
+    ///
+    /// ```gamelisp
+    /// (mesh-intersection light-cone walls 0 0 (arr 255 255 200))
+    /// ```
+    fn intersection(
+        &mut self,
+        a: &Mesh,
+        b: &Mesh,
+        x: Option<f32>,
+        y: Option<f32>,
+        color: Option<(u8, u8, u8)>,
+        stroke_width: Option<f32>,
+    ) -> GResult<Mesh> {
+        self.combine(
+            Operation::Intersection,
+            a,
+            b,
+            [x.unwrap_or(0.0), y.unwrap_or(0.0)],
+            color,
+            stroke_width,
+        )
+    }
+
+    /// Create a mesh of the first mesh with the second cut out from a script.
+    ///
+    /// ```gamelisp
+    /// (= terrain (mesh-difference terrain crater 40 -10))
+    /// ```
+    fn difference(
+        &mut self,
+        a: &Mesh,
+        b: &Mesh,
+        x: Option<f32>,
+        y: Option<f32>,
+        color: Option<(u8, u8, u8)>,
+        stroke_width: Option<f32>,
+    ) -> GResult<Mesh> {
+        self.combine(
+            Operation::Difference,
+            a,
+            b,
+            [x.unwrap_or(0.0), y.unwrap_or(0.0)],
+            color,
+            stroke_width,
+        )
+    }
+
+    /// Create a mesh covering where exactly one of the meshes is from a script.
+    fn xor(
+        &mut self,
+        a: &Mesh,
+        b: &Mesh,
+        x: Option<f32>,
+        y: Option<f32>,
+        color: Option<(u8, u8, u8)>,
+        stroke_width: Option<f32>,
+    ) -> GResult<Mesh> {
+        self.combine(
+            Operation::Xor,
+            a,
+            b,
+            [x.unwrap_or(0.0), y.unwrap_or(0.0)],
+            color,
+            stroke_width,
+        )
+    }
+
+    /// Register the boolean operations and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(BooleanOps::new());
+
+            glsp::bind_rfn("mesh-union", rfn!(BooleanOps::union))?;
+            glsp::bind_rfn("mesh-intersection", rfn!(BooleanOps::intersection))?;
+            glsp::bind_rfn("mesh-difference", rfn!(BooleanOps::difference))?;
+            glsp::bind_rfn("mesh-xor", rfn!(BooleanOps::xor))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// Convert a list of points to a ring of a polygon.
+/// A path of the polygons that's filled with the even-odd fill rule.
+pub(crate) fn polygons_path(polygons: &MultiPolygon<f64>) -> Path {
+    // Every ring is a closed sub-path, holes are cut out by the even-odd fill rule
+    let mut builder = Path::builder();
+    for polygon in polygons.0.iter() {
+        for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors().iter()) {
+            let mut points = ring
+                .0
+                .iter()
+                .map(|coord| point(coord.x as f32, coord.y as f32));
+            if let Some(first) = points.next() {
+                builder.move_to(first);
+                points.for_each(|to| builder.line_to(to));
+                builder.close();
+            }
+        }
+    }
+
+    builder.build()
+}
+
+pub(crate) fn line_string(points: &[[f32; 2]]) -> LineString<f64> {
+    LineString(
+        points
+            .iter()
+            .map(|[x, y]| Coordinate {
+                x: *x as f64,
+                y: *y as f64,
+            })
+            .collect(),
+    )
+}
+
+/// Move all points of a ring of a polygon.
+fn translate(ring: LineString<f64>, [x, y]: [f32; 2]) -> LineString<f64> {
+    LineString(
+        ring.0
+            .into_iter()
+            .map(|coord| Coordinate {
+                x: coord.x + x as f64,
+                y: coord.y + y as f64,
+            })
+            .collect(),
+    )
+}
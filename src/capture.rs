@@ -0,0 +1,140 @@
+use crate::sandbox::Sandbox;
+use anyhow::{anyhow, Result};
+use glsp::{lib, rfn, GResult, Runtime};
+use std::path::{Path, PathBuf};
+
+lib! {
+/// Screenshots & frame sequences requested by scripts, saved as PNG images.
+pub struct Capture {
+    /// The paths of the screenshots taken at the end of the current frame.
+    screenshots: Vec<PathBuf>,
+    /// The directory every frame is saved to with the number of the next frame.
+    sequence: Option<(PathBuf, u32)>,
+    /// Whether the pixels of the current frame are kept in memory.
+    pixels_requested: bool,
+    /// The width, height & RGBA pixels of the last frame that was kept in memory.
+    pixels: Option<(u32, u32, Vec<u8>)>,
+}
+}
+
+impl Capture {
+    /// Create without any requested captures.
+    pub fn new() -> Self {
+        Self {
+            screenshots: vec![],
+            sequence: None,
+            pixels_requested: false,
+            pixels: None,
+        }
+    }
+
+    /// Save a screenshot of the current frame.
+    pub fn screenshot<P>(&mut self, path: P)
+    where
+        P: Into<PathBuf>,
+    {
+        self.screenshots.push(path.into());
+    }
+
+    /// Save every frame as a numbered PNG in a directory, until stopped.
+    pub fn start_sequence<P>(&mut self, directory: P)
+    where
+        P: Into<PathBuf>,
+    {
+        self.sequence = Some((directory.into(), 0));
+    }
+
+    /// Stop saving every frame.
+    pub fn stop_sequence(&mut self) {
+        self.sequence = None;
+    }
+
+    /// Keep the pixels of the current frame in memory instead of saving them.
+    pub fn request_pixels(&mut self) {
+        self.pixels_requested = true;
+    }
+
+    /// Whether the pixels of the current frame must be kept in memory, resets the request.
+    pub fn take_pixel_request(&mut self) -> bool {
+        std::mem::take(&mut self.pixels_requested)
+    }
+
+    /// Keep the captured pixels of the current frame.
+    pub fn store_pixels(&mut self, width: u32, height: u32, pixels: Vec<u8>) {
+        self.pixels = Some((width, height, pixels));
+    }
+
+    /// The width, height & RGBA pixels with the top row first of the last requested frame.
+    pub fn take_pixels(&mut self) -> Option<(u32, u32, Vec<u8>)> {
+        self.pixels.take()
+    }
+
+    /// The paths the current frame must be saved to, empty when it doesn't have to be captured.
+    pub fn take_frame_paths(&mut self) -> Vec<PathBuf> {
+        let mut paths = std::mem::take(&mut self.screenshots);
+
+        if let Some((ref directory, ref mut frame)) = self.sequence {
+            paths.push(directory.join(format!("frame-{:06}.png", frame)));
+            *frame += 1;
+        }
+
+        paths
+    }
+
+    /// Save a screenshot from a script at the end of the frame.
+    ///
+    /// ```gamelisp
+    /// (screenshot "screenshots/level-1.png")
+    /// ```
+    fn screenshot_script(&mut self, path: &str) -> GResult<()> {
+        Sandbox::borrow().deny_filesystem("saving a screenshot")?;
+        self.screenshot(path);
+
+        Ok(())
+    }
+
+    /// Save every frame from a script as `frame-000000.png`, `frame-000001.png`, etc.
+    ///
+    /// ```gamelisp
+    /// (start-frame-capture "recording")
+    /// ```
+    fn start_sequence_script(&mut self, directory: &str) -> GResult<()> {
+        Sandbox::borrow().deny_filesystem("capturing frames")?;
+        self.start_sequence(directory);
+
+        Ok(())
+    }
+
+    /// Register the captures and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Capture::new());
+
+            glsp::bind_rfn("screenshot", rfn!(Capture::screenshot_script))?;
+            glsp::bind_rfn("start-frame-capture", rfn!(Capture::start_sequence_script))?;
+            glsp::bind_rfn("stop-frame-capture", rfn!(Capture::stop_sequence))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// Encode RGBA pixels with the top row first as a PNG file.
+pub fn save_png<P>(path: P, width: u32, height: u32, pixels: &[u8]) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+
+    let file = std::fs::File::create(path)?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(pixels))
+        .map_err(|err| anyhow!("encoding PNG \"{}\" failed: {}", path.display(), err))
+}
@@ -0,0 +1,176 @@
+use crate::{boolean, path};
+use geo_booleanop::boolean::BooleanOp;
+use geo_types::{MultiPolygon, Polygon};
+use lyon::path::Path;
+use usvg::{Node, NodeExt, NodeKind, Transform, TransformFromBBox, Tree, Units};
+
+/// Whether a node is the content of a clip path, a mask or another definition, which are only
+/// drawn through the elements referring to them.
+pub fn is_definition(node: &Node) -> bool {
+    node.ancestors().any(|ancestor| {
+        matches!(
+            *ancestor.borrow(),
+            NodeKind::Defs | NodeKind::ClipPath(_) | NodeKind::Mask(_)
+        )
+    })
+}
+
+/// The area a path of a SVG is visible in, in the coordinates of the mesh.
+///
+/// This is where the `clipPath` & `mask` elements of all groups the path is in overlap. Masks are
+/// used by the shapes of their content, how bright & opaque the content is doesn't matter.
+/// Returns `None` when the path isn't clipped.
+pub fn region(tree: &Tree, node: &Node, tolerance: f32) -> Option<MultiPolygon<f64>> {
+    let mut region: Option<MultiPolygon<f64>> = None;
+    for ancestor in node.ancestors().skip(1) {
+        if let NodeKind::Group(ref group) = *ancestor.borrow() {
+            // The content of the clip is in the coordinates of the group itself
+            let mut transform = ancestor.abs_transform();
+            transform.append(&group.transform);
+
+            for id in group.clip_path.iter().chain(group.mask.iter()) {
+                if let Some(link) = tree.defs_by_id(id) {
+                    let area = link_region(tree, &link, &ancestor, transform, tolerance);
+                    region = Some(match region {
+                        Some(region) => region.intersection(&area),
+                        None => area,
+                    });
+                }
+            }
+        }
+    }
+
+    region
+}
+
+/// Cut away the parts of a path outside of a region.
+///
+/// The result is a path of polygons filled with the even-odd fill rule.
+pub fn apply(path: &Path, region: &MultiPolygon<f64>, tolerance: f32) -> Path {
+    boolean::polygons_path(&even_odd_area(path, tolerance).intersection(region))
+}
+
+/// The area covered by a clip path or a mask an element refers to.
+fn link_region(
+    tree: &Tree,
+    link: &Node,
+    element: &Node,
+    transform: Transform,
+    tolerance: f32,
+) -> MultiPolygon<f64> {
+    let (area, nested) = match *link.borrow() {
+        NodeKind::ClipPath(ref clip) => {
+            let area = units_transform(clip.units, element, transform).map(|mut transform| {
+                transform.append(&clip.transform);
+
+                content_area(link, transform, tolerance)
+            });
+
+            (area, clip.clip_path.clone())
+        }
+        NodeKind::Mask(ref mask) => {
+            let content = units_transform(mask.content_units, element, transform)
+                .map(|transform| content_area(link, transform, tolerance));
+            // Nothing outside of the rectangle of the mask is visible
+            let bounds = units_transform(mask.units, element, transform).map(|transform| {
+                let rect = mask.rect;
+                let corners = [
+                    (rect.left(), rect.top()),
+                    (rect.right(), rect.top()),
+                    (rect.right(), rect.bottom()),
+                    (rect.left(), rect.bottom()),
+                ];
+                let points: Vec<[f32; 2]> = corners
+                    .iter()
+                    .map(|&(x, y)| {
+                        let (x, y) = transform.apply(x, y);
+
+                        [x as f32, y as f32]
+                    })
+                    .collect();
+
+                MultiPolygon(vec![Polygon::new(boolean::line_string(&points), vec![])])
+            });
+            let area = match (content, bounds) {
+                (Some(content), Some(bounds)) => Some(content.intersection(&bounds)),
+                _ => None,
+            };
+
+            (area, mask.mask.clone())
+        }
+        // Anything else doesn't clip
+        _ => return MultiPolygon(vec![]),
+    };
+
+    // An element without a size can't have an area relative to its bounding box
+    let area = area.unwrap_or_else(|| MultiPolygon(vec![]));
+
+    // Clip paths & masks can be clipped themselves
+    match nested.and_then(|id| tree.defs_by_id(&id)) {
+        Some(nested) => {
+            area.intersection(&link_region(tree, &nested, element, transform, tolerance))
+        }
+        None => area,
+    }
+}
+
+/// The transform of the content of a clip path or mask in its units.
+///
+/// Returns `None` when the units are relative to the bounding box of an element without one. The
+/// bounding box is taken in the coordinates of the mesh, so it's only exact for elements that are
+/// moved & scaled without rotating.
+fn units_transform(units: Units, element: &Node, transform: Transform) -> Option<Transform> {
+    match units {
+        Units::UserSpaceOnUse => Some(transform),
+        Units::ObjectBoundingBox => element.calculate_bbox().map(Transform::from_bbox),
+    }
+}
+
+/// The area covered by the filled paths inside a clip path or mask.
+fn content_area(link: &Node, transform: Transform, tolerance: f32) -> MultiPolygon<f64> {
+    link.descendants()
+        .filter_map(|node| match *node.borrow() {
+            NodeKind::Path(ref svg_path) if svg_path.fill.is_some() => {
+                let mut transform = transform;
+                transform.append(&relative_transform(&node, link));
+
+                Some(even_odd_area(
+                    &path::from_svg(&svg_path.data, transform),
+                    tolerance,
+                ))
+            }
+            _ => None,
+        })
+        .fold(MultiPolygon(vec![]), |area, path_area| {
+            area.union(&path_area)
+        })
+}
+
+/// The transforms of a node & its ancestors up to another ancestor, which is excluded.
+fn relative_transform(node: &Node, ancestor: &Node) -> Transform {
+    let transforms: Vec<Transform> = node
+        .ancestors()
+        .take_while(|parent| parent != ancestor)
+        .map(|parent| parent.transform())
+        .collect();
+
+    let mut transform = Transform::default();
+    for parent_transform in transforms.iter().rev() {
+        transform.append(parent_transform);
+    }
+
+    transform
+}
+
+/// The area filled by a path with the even-odd fill rule, the same rule the meshes are drawn with.
+fn even_odd_area(path: &Path, tolerance: f32) -> MultiPolygon<f64> {
+    path::sub_paths(path.iter(), tolerance)
+        .into_iter()
+        .filter(|points| points.len() >= 3)
+        .fold(MultiPolygon(vec![]), |area, points| {
+            area.xor(&MultiPolygon(vec![Polygon::new(
+                boolean::line_string(&points),
+                vec![],
+            )]))
+        })
+}
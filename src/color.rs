@@ -0,0 +1,299 @@
+use crate::render::{PaletteSwap, Render};
+use anyhow::{anyhow, Result};
+use glsp::{bail, lib, rfn, FromVal, GResult, Root, Runtime, Sym, Tab, Val};
+use std::collections::HashMap;
+
+/// A solid color of a mesh with channels from 0 to 255.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    /// Create from the red, green & blue channels.
+    pub fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+}
+
+/// Parse a hexadecimal color like `#ff8800` or `#f80`, the `#` is optional.
+pub fn parse_hex(hex: &str) -> Result<(u8, u8, u8)> {
+    let digits = hex.trim().trim_start_matches('#');
+    let channel = |index: usize, width: usize| {
+        let value = u8::from_str_radix(&digits[index * width..(index + 1) * width], 16)
+            .map_err(|err| anyhow!("invalid color \"{}\": {}", hex, err))?;
+
+        // Every digit of the short form is doubled, `f` is `ff`
+        Ok(if width == 1 { value * 17 } else { value })
+    };
+
+    let width = match digits.len() {
+        3 if digits.is_ascii() => 1,
+        6 if digits.is_ascii() => 2,
+        _ => return Err(anyhow!("color \"{}\" must be #rgb or #rrggbb", hex)),
+    };
+
+    Ok((channel(0, width)?, channel(1, width)?, channel(2, width)?))
+}
+
+/// Convert a hue in degrees with a saturation & value from 0 to 1 to a color.
+pub fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> (u8, u8, u8) {
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let saturation = saturation.max(0.0).min(1.0);
+    let value = value.max(0.0).min(1.0);
+
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (red, green, blue) = match hue as u8 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let min = value - chroma;
+    let channel = |channel: f32| ((channel + min) * 255.0).round() as u8;
+
+    (channel(red), channel(green), channel(blue))
+}
+
+/// Convert a color to a hue in degrees with a saturation & value from 0 to 1.
+pub fn rgb_to_hsv((red, green, blue): (u8, u8, u8)) -> (f32, f32, f32) {
+    let (red, green, blue) = (
+        red as f32 / 255.0,
+        green as f32 / 255.0,
+        blue as f32 / 255.0,
+    );
+    let max = red.max(green).max(blue);
+    let min = red.min(green).min(blue);
+    let chroma = max - min;
+
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == red {
+        60.0 * ((green - blue) / chroma).rem_euclid(6.0)
+    } else if max == green {
+        60.0 * ((blue - red) / chroma + 2.0)
+    } else {
+        60.0 * ((red - green) / chroma + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+
+    (hue, saturation, max)
+}
+
+/// A color from 0 to 255 as channels from 0 to 1.
+fn normalize((red, green, blue): (u8, u8, u8)) -> [f32; 3] {
+    [
+        red as f32 / 255.0,
+        green as f32 / 255.0,
+        blue as f32 / 255.0,
+    ]
+}
+
+lib! {
+/// Named palettes of colors, with a palette swap replacing the colors of one palette with those
+/// of another when drawing, for day & night cycles, team colors & accessibility modes.
+///
+/// Colors are passed as `(arr red green blue)` with values from 0 to 255 or as hexadecimal
+/// strings. The swap is applied to every mesh & texture in the fragment shader, so nothing has to
+/// be uploaded again; only the colors the palettes define exactly are replaced, gradients between
+/// them aren't. At most 8 colors can be swapped at the same time. The tint is multiplied with
+/// everything that's drawn.
+///
+/// ```gamelisp
+/// (define-palette 'day (tab ('sky "#88ccff") ('grass "#44aa22")))
+/// (define-palette 'night (tab ('sky "#101838") ('grass (arr 20 50 30))))
+///
+/// (create-rect 100 50 (palette-color 'day 'grass))
+/// (swap-palette 'day 'night)
+/// (set-color-tint (arr 200 200 255))
+/// ```
+pub struct Palettes {
+    palettes: HashMap<Sym, HashMap<Sym, (u8, u8, u8)>>,
+    /// The palettes whose colors are swapped.
+    swap: Option<(Sym, Sym)>,
+    /// The color everything is multiplied with.
+    tint: (u8, u8, u8),
+}
+}
+
+impl Palettes {
+    /// Create without any palettes.
+    pub fn new() -> Self {
+        Self {
+            palettes: HashMap::new(),
+            swap: None,
+            tint: (255, 255, 255),
+        }
+    }
+
+    /// The color of a palette by its name.
+    pub fn color(&self, palette: Sym, name: Sym) -> Option<(u8, u8, u8)> {
+        self.palettes.get(&palette)?.get(&name).copied()
+    }
+
+    /// The colors replaced by the swap & the tint as used by the shaders.
+    fn palette_swap(&self) -> Result<PaletteSwap> {
+        let swaps = match self.swap {
+            Some((from, to)) => {
+                let (from_colors, to_colors) =
+                    match (self.palettes.get(&from), self.palettes.get(&to)) {
+                        (Some(from_colors), Some(to_colors)) => (from_colors, to_colors),
+                        _ => return Err(anyhow!("palette {} or {} isn't defined", from, to)),
+                    };
+
+                // Colors that are the same in both palettes don't have to be swapped
+                let mut swaps: Vec<_> = from_colors
+                    .iter()
+                    .filter_map(|(name, from)| Some((*from, *to_colors.get(name)?)))
+                    .filter(|(from, to)| from != to)
+                    .collect();
+                swaps.sort();
+                swaps.dedup_by_key(|(from, _)| *from);
+                if swaps.len() > PaletteSwap::MAX_COLORS {
+                    return Err(anyhow!(
+                        "swapping palette {} with {} changes {} colors, at most {} can be swapped",
+                        from,
+                        to,
+                        swaps.len(),
+                        PaletteSwap::MAX_COLORS
+                    ));
+                }
+
+                swaps
+                    .into_iter()
+                    .map(|(from, to)| (normalize(from), normalize(to)))
+                    .collect()
+            }
+            None => vec![],
+        };
+
+        Ok(PaletteSwap::new(normalize(self.tint), &swaps))
+    }
+
+    /// Pass the swap & the tint to the render.
+    fn apply(&self) -> GResult<()> {
+        match self.palette_swap() {
+            Ok(palette) => {
+                Render::borrow_mut().set_palette_swap(palette);
+
+                Ok(())
+            }
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Parse a hexadecimal color from a script.
+    ///
+    /// ```gamelisp
+    /// (create-circle 10 (color "#ff8800"))
+    /// ```
+    fn color_script(hex: &str) -> GResult<(u8, u8, u8)> {
+        match parse_hex(hex) {
+            Ok(color) => Ok(color),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Convert a hue in degrees with a saturation & value from 0 to 1 to a color from a script.
+    ///
+    /// ```gamelisp
+    /// (hsv->rgb (* (game-time) 90) 0.8 1.0)
+    /// ```
+    fn hsv_to_rgb_script(hue: f32, saturation: f32, value: f32) -> (u8, u8, u8) {
+        hsv_to_rgb(hue, saturation, value)
+    }
+
+    /// Convert a color to `(arr hue saturation value)` from a script.
+    fn rgb_to_hsv_script(color: (u8, u8, u8)) -> (f32, f32, f32) {
+        rgb_to_hsv(color)
+    }
+
+    /// Define a palette from a script, replacing the palette with the same name.
+    ///
+    /// The colors are `(arr red green blue)` or hexadecimal strings by name.
+    fn define_palette(&mut self, palette: Sym, colors: Root<Tab>) -> GResult<()> {
+        let mut parsed = HashMap::new();
+        for (name, color) in colors.entries().iter() {
+            let name = match name {
+                Val::Sym(name) => name,
+                _ => bail!(
+                    "color name {} of palette {} must be a symbol",
+                    name,
+                    palette
+                ),
+            };
+            let color = match color {
+                Val::Str(hex) => Palettes::color_script(&hex.to_string())?,
+                color => FromVal::from_val(&color)?,
+            };
+
+            parsed.insert(name, color);
+        }
+        self.palettes.insert(palette, parsed);
+
+        // Redefining a swapped palette changes the swap
+        match self.swap {
+            Some((from, to)) if from == palette || to == palette => self.apply(),
+            _ => Ok(()),
+        }
+    }
+
+    /// A color of a palette from a script.
+    fn palette_color(&self, palette: Sym, name: Sym) -> GResult<(u8, u8, u8)> {
+        match self.color(palette, name) {
+            Some(color) => Ok(color),
+            None => bail!("palette {} doesn't have color {}", palette, name),
+        }
+    }
+
+    /// Replace the colors of a palette with the colors with the same names of another palette
+    /// when drawing from a script.
+    fn swap_palette(&mut self, from: Sym, to: Sym) -> GResult<()> {
+        let previous = self.swap.replace((from, to));
+        if let Err(err) = self.apply() {
+            self.swap = previous;
+
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Multiply everything that's drawn with a color from a script, white restores it.
+    fn set_color_tint(&mut self, tint: (u8, u8, u8)) -> GResult<()> {
+        self.tint = tint;
+
+        self.apply()
+    }
+
+    /// Draw the colors unchanged again from a script, removing the swap & the tint.
+    fn reset_palette_swap(&mut self) {
+        self.swap = None;
+        self.tint = (255, 255, 255);
+
+        Render::borrow_mut().set_palette_swap(PaletteSwap::default());
+    }
+
+    /// Register the palettes and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Palettes::new());
+
+            glsp::bind_rfn("color", rfn!(Palettes::color_script))?;
+            glsp::bind_rfn("hsv->rgb", rfn!(Palettes::hsv_to_rgb_script))?;
+            glsp::bind_rfn("rgb->hsv", rfn!(Palettes::rgb_to_hsv_script))?;
+            glsp::bind_rfn("define-palette", rfn!(Palettes::define_palette))?;
+            glsp::bind_rfn("palette-color", rfn!(Palettes::palette_color))?;
+            glsp::bind_rfn("swap-palette", rfn!(Palettes::swap_palette))?;
+            glsp::bind_rfn("set-color-tint", rfn!(Palettes::set_color_tint))?;
+            glsp::bind_rfn("reset-palette-swap", rfn!(Palettes::reset_palette_swap))?;
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,296 @@
+use crate::sandbox::Sandbox;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::script::Scripts;
+use glsp::{bail, lib, rfn, GResult, Root, Runtime, Tab, Val};
+use serde_json::Value;
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the files of the loaded configs are checked for changes.
+#[cfg(not(target_arch = "wasm32"))]
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A config that's loaded, with everything needed to load it again when it changes.
+#[derive(Debug)]
+struct LoadedConfig {
+    name: String,
+    /// The table returned to the script, updated in place when the config is reloaded.
+    table: Root<Tab>,
+    schema: Option<Val>,
+    /// When the file was last changed, `None` when it's added with `Clog::add_config`.
+    #[cfg(not(target_arch = "wasm32"))]
+    modified: Option<SystemTime>,
+}
+
+lib! {
+/// Game settings loaded from TOML, RON or JSON files into GameLisp tables, so values can be
+/// tuned without changing the scripts.
+///
+/// The format is chosen by the extension of the file. Keys become symbols, a schema checks the
+/// types of the values with `'int`, `'float`, `'bool`, `'str`, `'arr`, `'tab` & `'any`, a nested
+/// table for tables & an array with a single type for arrays of that type. Files in the script
+/// directory are checked for changes every second, the table is updated in place & the
+/// `engine:config-reloaded` function is called with the name of the config.
+///
+/// ```gamelisp
+/// (let balance (load-config "balance.toml" (tab
+///   ('jump-height 'float)
+///   ('enemies (tab ('speed 'float) ('health 'int)))
+///   ('levels (arr 'str)))))
+///
+/// (set! jump-velocity (sqrt (* 2.0 gravity [balance 'jump-height])))
+/// ```
+pub struct Configs {
+    /// The sources added with `Clog::add_config` by their name.
+    sources: HashMap<String, String>,
+    loaded: Vec<LoadedConfig>,
+    /// When the files were last checked for changes.
+    #[cfg(not(target_arch = "wasm32"))]
+    last_check: Instant,
+}
+}
+
+impl Configs {
+    /// Create without any sources or loaded configs.
+    pub fn new() -> Self {
+        Self {
+            sources: HashMap::new(),
+            loaded: vec![],
+            #[cfg(not(target_arch = "wasm32"))]
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Add the source of a config so it can be loaded without reading a file.
+    pub fn add<N, S>(&mut self, name: N, source: S)
+    where
+        N: Into<String>,
+        S: Into<String>,
+    {
+        self.sources.insert(name.into(), source.into());
+    }
+
+    /// The source of a config, from the added sources or the file in the script directory.
+    fn source(&self, name: &str) -> GResult<String> {
+        if let Some(source) = self.sources.get(name) {
+            return Ok(source.clone());
+        }
+
+        Sandbox::borrow().deny_filesystem(&format!("reading config \"{}\"", name))?;
+
+        #[cfg(target_arch = "wasm32")]
+        bail!(
+            "config \"{}\" isn't added with `Clog::add_config`, files can't be read",
+            name
+        );
+
+        #[cfg(not(target_arch = "wasm32"))]
+        match std::fs::read_to_string(Scripts::borrow().root().join(name)) {
+            Ok(source) => Ok(source),
+            Err(err) => bail!("reading config \"{}\" failed: {}", name, err),
+        }
+    }
+
+    /// When the file of a config was last changed.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn modified(&self, name: &str) -> Option<SystemTime> {
+        if self.sources.contains_key(name) {
+            return None;
+        }
+
+        std::fs::metadata(Scripts::borrow().root().join(name))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Parse the source of a config into a table & check it against the schema.
+    fn parse(&self, name: &str, schema: Option<&Val>) -> GResult<Root<Tab>> {
+        let source = self.source(name)?;
+        let value: Value = if name.ends_with(".toml") {
+            match toml::from_str(&source) {
+                Ok(value) => value,
+                Err(err) => bail!("parsing config \"{}\" failed: {}", name, err),
+            }
+        } else if name.ends_with(".ron") {
+            match ron::from_str(&source) {
+                Ok(value) => value,
+                Err(err) => bail!("parsing config \"{}\" failed: {}", name, err),
+            }
+        } else if name.ends_with(".json") {
+            match serde_json::from_str(&source) {
+                Ok(value) => value,
+                Err(err) => bail!("parsing config \"{}\" failed: {}", name, err),
+            }
+        } else {
+            bail!(
+                "config \"{}\" must be a \".toml\", \".ron\" or \".json\" file",
+                name
+            );
+        };
+
+        let table = match to_val(&value)? {
+            Val::Tab(table) => table,
+            _ => bail!("config \"{}\" must contain a table", name),
+        };
+        if let Some(schema) = schema {
+            check(&Val::Tab(table.clone()), schema, name)?;
+        }
+
+        Ok(table)
+    }
+
+    /// Load a config from a script, checking it against the optional schema.
+    ///
+    /// The same table is returned when the config is already loaded.
+    fn load_config(&mut self, name: &str, schema: Option<Val>) -> GResult<Root<Tab>> {
+        if let Some(config) = self.loaded.iter().find(|config| config.name == name) {
+            if let Some(schema) = &schema {
+                check(&Val::Tab(config.table.clone()), schema, name)?;
+            }
+
+            return Ok(config.table.clone());
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let modified = self.modified(name);
+        let table = self.parse(name, schema.as_ref())?;
+        self.loaded.push(LoadedConfig {
+            name: name.to_string(),
+            table: table.clone(),
+            schema,
+            #[cfg(not(target_arch = "wasm32"))]
+            modified,
+        });
+
+        Ok(table)
+    }
+
+    /// Load the configs again that changed since they were loaded, returns their names.
+    ///
+    /// A config that fails to parse or doesn't match its schema keeps its previous values.
+    /// Must be called inside the GameLisp runtime.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload_changed() -> GResult<Vec<String>> {
+        let mut configs = Configs::borrow_mut();
+        if configs.loaded.is_empty() || configs.last_check.elapsed() < RELOAD_INTERVAL {
+            return Ok(vec![]);
+        }
+        configs.last_check = Instant::now();
+
+        let mut reloaded = vec![];
+        for index in 0..configs.loaded.len() {
+            let modified = configs.modified(&configs.loaded[index].name);
+            if modified.is_none() || modified == configs.loaded[index].modified {
+                continue;
+            }
+            configs.loaded[index].modified = modified;
+
+            let config = &configs.loaded[index];
+            let table = match configs.parse(&config.name, config.schema.as_ref()) {
+                Ok(table) => table,
+                Err(err) => {
+                    log::error!("{}", err);
+
+                    continue;
+                }
+            };
+
+            // Update the existing table so the scripts holding it see the new values
+            config.table.clear()?;
+            for (key, value) in table.entries().iter() {
+                config.table.set(key, value)?;
+            }
+
+            log::info!("reloaded config \"{}\"", config.name);
+            reloaded.push(config.name.clone());
+        }
+
+        Ok(reloaded)
+    }
+
+    /// Register the configs and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Configs::new());
+
+            glsp::bind_rfn("load-config", rfn!(Configs::load_config))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// Convert parsed config data to a GameLisp value, the keys of tables become symbols.
+fn to_val(value: &Value) -> GResult<Val> {
+    Ok(match value {
+        Value::Null => Val::Nil,
+        Value::Bool(b) => Val::Bool(*b),
+        Value::Number(number) => match number.as_i64() {
+            Some(i) if i >= i32::MIN as i64 && i <= i32::MAX as i64 => Val::Int(i as i32),
+            _ => Val::Flo(number.as_f64().unwrap_or(0.0) as f32),
+        },
+        Value::String(s) => Val::Str(glsp::str_from_rust_str(s)),
+        Value::Array(array) => Val::Arr(glsp::arr_from_iter(
+            array.iter().map(to_val).collect::<GResult<Vec<_>>>()?,
+        )?),
+        Value::Object(map) => {
+            let tab = glsp::tab();
+            for (key, value) in map {
+                tab.set(glsp::sym(key)?, to_val(value)?)?;
+            }
+
+            Val::Tab(tab)
+        }
+    })
+}
+
+/// Check the types of a value against a schema, the path is shown in the error.
+fn check(val: &Val, schema: &Val, path: &str) -> GResult<()> {
+    match schema {
+        Val::Sym(kind) => {
+            let matches = match &*kind.name() {
+                "int" => matches!(val, Val::Int(_)),
+                // Whole numbers are parsed as integers
+                "float" => matches!(val, Val::Flo(_) | Val::Int(_)),
+                "bool" => matches!(val, Val::Bool(_)),
+                "str" => matches!(val, Val::Str(_)),
+                "arr" => matches!(val, Val::Arr(_)),
+                "tab" => matches!(val, Val::Tab(_)),
+                "any" => true,
+                _ => bail!("unknown type '{} in the schema of {}", kind, path),
+            };
+            if !matches {
+                bail!("{} must be of type '{}, got {}", path, kind, val);
+            }
+        }
+        Val::Arr(items) if items.len() == 1 => match val {
+            Val::Arr(arr) => {
+                let item: Val = items.get(0)?;
+                for (index, element) in arr.iter().enumerate() {
+                    check(&element, &item, &format!("{}[{}]", path, index))?;
+                }
+            }
+            _ => bail!("{} must be an array, got {}", path, val),
+        },
+        Val::Tab(fields) => match val {
+            Val::Tab(tab) => {
+                for (key, field) in fields.entries().iter() {
+                    let path = format!("{}.{}", path, key);
+                    match tab.get_if_present::<_, Val>(key)? {
+                        Some(value) => check(&value, &field, &path)?,
+                        None => bail!("{} is missing", path),
+                    }
+                }
+            }
+            _ => bail!("{} must be a table, got {}", path, val),
+        },
+        _ => bail!(
+            "schema of {} must be a type symbol, a table or an array with one type, got {}",
+            path,
+            schema
+        ),
+    }
+
+    Ok(())
+}
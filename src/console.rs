@@ -0,0 +1,231 @@
+use crate::{debug, event, render::Render};
+use anyhow::Result;
+use glsp::{lib, Runtime, Val};
+use miniquad::KeyCode;
+use std::{collections::VecDeque, mem};
+
+/// How many lines of output are kept.
+const MAX_OUTPUT_LINES: usize = 10;
+/// How many evaluated expressions are kept in the history.
+const MAX_HISTORY: usize = 100;
+/// The height of a line of text in the console in pixels.
+const LINE_HEIGHT: f32 = 16.0;
+/// The color of the text that's being typed.
+const INPUT_COLOR: (u8, u8, u8) = (255, 255, 255);
+/// The color of the results.
+const OUTPUT_COLOR: (u8, u8, u8) = (180, 180, 180);
+
+lib! {
+/// An in-game developer console evaluating GameLisp expressions in the running runtime.
+pub struct Console {
+    /// Whether the console is shown and receives the keyboard input.
+    open: bool,
+    /// The key that opens & closes the console, `None` when the console is disabled.
+    toggle_key: Option<KeyCode>,
+    /// The characters typed by the toggle key without & with shift, ignored when they're typed
+    /// right after the console is toggled.
+    ignored_chars: Option<(char, char)>,
+    /// The expression that's being typed.
+    input: String,
+    /// Previously evaluated expressions, the newest one is last.
+    history: Vec<String>,
+    /// The entry of the history that's selected with the arrow keys.
+    history_index: Option<usize>,
+    /// The results & errors of the evaluated expressions.
+    output: VecDeque<String>,
+}
+}
+
+impl Console {
+    /// Create a closed console that's toggled with a key, or disabled without one.
+    pub fn new(toggle_key: Option<KeyCode>) -> Self {
+        Self {
+            open: false,
+            toggle_key,
+            ignored_chars: None,
+            input: String::new(),
+            history: vec![],
+            history_index: None,
+            output: VecDeque::with_capacity(MAX_OUTPUT_LINES),
+        }
+    }
+
+    /// Set the key that opens & closes the console, `None` disables & closes it.
+    pub fn set_toggle_key(&mut self, toggle_key: Option<KeyCode>) {
+        self.toggle_key = toggle_key;
+        if toggle_key.is_none() {
+            self.open = false;
+        }
+    }
+
+    /// Whether the console is open and captures the keyboard.
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Handle a pressed key.
+    ///
+    /// Returns the expression that must be evaluated when enter is pressed.
+    pub fn key_down(&mut self, keycode: KeyCode) -> Option<String> {
+        // The character of the toggle key is typed before the next key is pressed, if at all
+        self.ignored_chars = None;
+        if Some(keycode) == self.toggle_key {
+            self.open = !self.open;
+            self.ignored_chars = key_chars(keycode);
+
+            return None;
+        }
+        if !self.open {
+            return None;
+        }
+
+        match keycode {
+            KeyCode::Enter | KeyCode::KpEnter => {
+                let input = mem::take(&mut self.input);
+                if input.trim().is_empty() {
+                    return None;
+                }
+
+                if self.history.len() == MAX_HISTORY {
+                    self.history.remove(0);
+                }
+                self.history.push(input.clone());
+                self.history_index = None;
+                self.print(format!("> {}", input));
+
+                return Some(input);
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Up => {
+                let index = match self.history_index {
+                    Some(index) => index.saturating_sub(1),
+                    None => self.history.len().checked_sub(1)?,
+                };
+                self.history_index = Some(index);
+                self.input = self.history[index].clone();
+            }
+            KeyCode::Down => {
+                if let Some(index) = self.history_index {
+                    if index + 1 < self.history.len() {
+                        self.history_index = Some(index + 1);
+                        self.input = self.history[index + 1].clone();
+                    } else {
+                        self.history_index = None;
+                        self.input.clear();
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        None
+    }
+
+    /// Handle a typed character, returns whether it's used by the console.
+    pub fn char_typed(&mut self, character: char) -> bool {
+        if let Some((unshifted, shifted)) = self.ignored_chars.take() {
+            if character == unshifted || character == shifted {
+                return true;
+            }
+        }
+        if !self.open {
+            return false;
+        }
+
+        if !character.is_control() {
+            self.input.push(character);
+        }
+
+        true
+    }
+
+    /// Add a line to the output.
+    pub fn print(&mut self, line: String) {
+        if self.output.len() == MAX_OUTPUT_LINES {
+            self.output.pop_front();
+        }
+        self.output.push_back(line);
+    }
+
+    /// Evaluate an expression and print the result.
+    ///
+    /// Must be called inside the GameLisp runtime without the console being borrowed, since
+    /// the expression can do anything.
+    pub fn eval(input: &str) {
+        let result = glsp::parse_all(input, None).and_then(|forms| glsp::eval_multi(&forms, None));
+
+        let line = match result {
+            Ok(Val::Nil) => return,
+            Ok(val) => format!("{}", val),
+            Err(err) => format!("error: {}", err),
+        };
+        Console::borrow_mut().print(line);
+    }
+
+    /// Draw the console at the bottom of the screen if it's open.
+    pub fn draw(&self, render: &mut Render) -> Result<()> {
+        if !self.open {
+            return Ok(());
+        }
+
+        let scale = render.world_units_per_pixel();
+        let (x, bottom) = render.screen_to_world(4.0, render.screen_size().1 - 4.0);
+        let line_height = LINE_HEIGHT * scale;
+
+        // Draw the input line at the bottom with the output above it
+        let input_line = format!("> {}_", self.input);
+        let lines = self
+            .output
+            .iter()
+            .map(|line| (line.as_str(), OUTPUT_COLOR))
+            .chain(std::iter::once((input_line.as_str(), INPUT_COLOR)));
+        let line_count = self.output.len() + 1;
+        for (index, (line, color)) in lines.enumerate() {
+            let y = bottom - (line_count - index) as f32 * line_height;
+
+            debug::draw_text(render, x, y, line, color, line_height, debug::DEFAULT_FONT)?;
+        }
+
+        Ok(())
+    }
+
+    /// Register the console in the runtime.
+    pub fn register(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Console::new(None));
+
+            Ok(())
+        });
+    }
+}
+
+/// The characters a key types without & with shift, `None` for keys that don't type any.
+fn key_chars(keycode: KeyCode) -> Option<(char, char)> {
+    let chars = match keycode {
+        KeyCode::GraveAccent => ('`', '~'),
+        KeyCode::Apostrophe => ('\'', '"'),
+        KeyCode::Comma => (',', '<'),
+        KeyCode::Minus => ('-', '_'),
+        KeyCode::Period => ('.', '>'),
+        KeyCode::Slash => ('/', '?'),
+        KeyCode::Semicolon => (';', ':'),
+        KeyCode::Equal => ('=', '+'),
+        KeyCode::LeftBracket => ('[', '{'),
+        KeyCode::RightBracket => (']', '}'),
+        KeyCode::Backslash => ('\\', '|'),
+        KeyCode::Space => (' ', ' '),
+        _ => {
+            // Letters are named after the character they type
+            let name = event::key_name(keycode);
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(character), None) => (character, character.to_ascii_uppercase()),
+                _ => return None,
+            }
+        }
+    };
+
+    Some(chars)
+}
@@ -0,0 +1,249 @@
+use crate::render::{Instance, InstanceRef, Mesh, Render};
+use glsp::{bail, lib, rfn, GResult, Runtime, Sym};
+use miniquad::{Context, CursorIcon};
+
+/// A mesh drawn at the position of the mouse instead of the cursor of the system.
+#[derive(Debug)]
+struct CursorImage {
+    /// The instance of the mesh.
+    instance: InstanceRef,
+    /// The point of the mesh that's at the position of the mouse, in pixels.
+    hotspot: (f32, f32),
+}
+
+lib! {
+/// The look & behavior of the mouse cursor requested by scripts, applied during the next update.
+///
+/// A locked cursor is hidden & can't leave the window, the movement of the mouse is emitted as
+/// `mouse-delta` events with the horizontal & vertical distance in pixels instead. A custom
+/// cursor image is a mesh drawn in screen space at the front, hiding the cursor of the system.
+///
+/// ```gamelisp
+/// (set-cursor 'hand)
+/// (lock-cursor)
+/// (on-event 'mouse-delta (fn ((dx dy))
+///   (= yaw (+ yaw (* dx 0.01)))))
+/// ```
+pub struct Cursor {
+    /// The icon of the system cursor that must be shown.
+    icon: Option<CursorIcon>,
+    /// Whether the system cursor is shown.
+    visible: bool,
+    /// Whether the cursor is grabbed by the window.
+    locked: bool,
+    /// Whether the visibility or the grab must be applied to the window.
+    changed: bool,
+    /// The mesh drawn as the cursor.
+    image: Option<CursorImage>,
+    /// The last position of the mouse in pixels.
+    position: (f32, f32),
+}
+}
+
+impl Cursor {
+    /// Create with the default cursor of the system.
+    pub fn new() -> Self {
+        Self {
+            icon: None,
+            visible: true,
+            locked: false,
+            changed: false,
+            image: None,
+            position: (0.0, 0.0),
+        }
+    }
+
+    /// Show a cursor icon of the system.
+    pub fn set_icon(&mut self, icon: CursorIcon) {
+        self.icon = Some(icon);
+    }
+
+    /// Show or hide the cursor.
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+        self.changed = true;
+    }
+
+    /// Grab or release the cursor, a grabbed cursor is hidden & can't leave the window.
+    pub fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+        self.changed = true;
+    }
+
+    /// Whether the cursor is grabbed by the window.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Handle a moved mouse.
+    pub fn mouse_moved(&mut self, x: f32, y: f32) {
+        self.position = (x, y);
+    }
+
+    /// Move the cursor image to the position of the mouse.
+    ///
+    /// Must be called before rendering.
+    pub fn update(&mut self, render: &mut Render) {
+        let image = match self.image {
+            Some(ref image) => image,
+            None => return,
+        };
+
+        let mut new = Instance::new(0.0, 0.0);
+        // The camera doesn't move instances at the front
+        new.set_z(u8::MAX);
+        if self.visible && !self.locked {
+            let (world_x, world_y) = render.screen_to_world(
+                self.position.0 - image.hotspot.0,
+                self.position.1 - image.hotspot.1,
+            );
+
+            new.set_x(world_x);
+            new.set_y(world_y);
+            new.set_scale(render.world_units_per_pixel());
+        } else {
+            new.set_scale(0.0);
+        }
+
+        // Only upload the instance again when it moved
+        if render.instance(image.instance) != Some(&new) {
+            if let Some(stored) = render.instance_mut(image.instance) {
+                *stored = new;
+            }
+        }
+    }
+
+    /// Apply the requested changes to the window.
+    pub fn apply(&mut self, ctx: &mut Context) {
+        if let Some(icon) = self.icon.take() {
+            ctx.set_mouse_cursor(icon);
+        }
+
+        if std::mem::replace(&mut self.changed, false) {
+            ctx.set_cursor_grab(self.locked);
+            ctx.show_mouse(self.visible && !self.locked && self.image.is_none());
+        }
+    }
+
+    /// Show a cursor icon of the system from a script.
+    ///
+    /// The icon is `default`, `hand`, `help`, `wait`, `crosshair`, `text`, `move`,
+    /// `not-allowed`, `resize-horizontal`, `resize-vertical`, `resize-diagonal` or
+    /// `resize-antidiagonal`.
+    ///
+    /// ```gamelisp
+    /// (set-cursor 'hand)
+    /// ```
+    fn set_icon_script(&mut self, icon: Sym) -> GResult<()> {
+        let icon = match &*icon.name() {
+            "default" => CursorIcon::Default,
+            "hand" => CursorIcon::Pointer,
+            "help" => CursorIcon::Help,
+            "wait" => CursorIcon::Wait,
+            "crosshair" => CursorIcon::Crosshair,
+            "text" => CursorIcon::Text,
+            "move" => CursorIcon::Move,
+            "not-allowed" => CursorIcon::NotAllowed,
+            "resize-horizontal" => CursorIcon::EWResize,
+            "resize-vertical" => CursorIcon::NSResize,
+            "resize-diagonal" => CursorIcon::NWSEResize,
+            "resize-antidiagonal" => CursorIcon::NESWResize,
+            name => bail!("unknown cursor icon {}", name),
+        };
+        self.set_icon(icon);
+
+        Ok(())
+    }
+
+    /// Hide the cursor from a script.
+    fn hide(&mut self) {
+        self.set_visible(false);
+    }
+
+    /// Show the cursor from a script.
+    fn show(&mut self) {
+        self.set_visible(true);
+    }
+
+    /// Grab the cursor from a script, for controlling the camera with the mouse.
+    ///
+    /// ```gamelisp
+    /// (lock-cursor)
+    /// ```
+    fn lock(&mut self) {
+        self.set_locked(true);
+    }
+
+    /// Release the grabbed cursor from a script.
+    fn unlock(&mut self) {
+        self.set_locked(false);
+    }
+
+    /// Draw a mesh as the cursor from a script, replacing the previous cursor image.
+    ///
+    /// The mesh is drawn with a unit for every pixel, the hotspot is the point of the mesh at
+    /// the position of the mouse and defaults to the origin.
+    ///
+    /// ```gamelisp
+    /// (set-cursor-image (create-polygon (arr '(0 0) '(12 4) '(4 12)) (arr 255 255 255)))
+    /// ```
+    fn set_image(&mut self, mesh: &Mesh, hotspot_x: Option<f32>, hotspot_y: Option<f32>) {
+        self.clear_image();
+
+        let mut render = Render::borrow_mut();
+        let mut instance = Instance::new(0.0, 0.0);
+        instance.set_scale(0.0);
+        self.image = Some(CursorImage {
+            instance: render.add_instance(*mesh, instance),
+            hotspot: (hotspot_x.unwrap_or(0.0), hotspot_y.unwrap_or(0.0)),
+        });
+        self.changed = true;
+    }
+
+    /// Show the cursor of the system again from a script, returns whether an image was shown.
+    fn clear_image(&mut self) -> bool {
+        match self.image.take() {
+            Some(image) => {
+                Render::borrow_mut().remove_instance(image.instance);
+                self.changed = true;
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get the last position of the mouse in pixels from a script.
+    fn position_script(&self) -> (f32, f32) {
+        self.position
+    }
+
+    /// Get the world position under the mouse at the default Z from a script.
+    ///
+    /// ```gamelisp
+    /// (let (x y) (mouse-world-position))
+    /// ```
+    fn world_position_script(&self) -> (f32, f32) {
+        Render::borrow().screen_to_world(self.position.0, self.position.1)
+    }
+
+    /// Register the cursor and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Cursor::new());
+
+            glsp::bind_rfn("set-cursor", rfn!(Cursor::set_icon_script))?;
+            glsp::bind_rfn("hide-cursor", rfn!(Cursor::hide))?;
+            glsp::bind_rfn("show-cursor", rfn!(Cursor::show))?;
+            glsp::bind_rfn("lock-cursor", rfn!(Cursor::lock))?;
+            glsp::bind_rfn("unlock-cursor", rfn!(Cursor::unlock))?;
+            glsp::bind_rfn("cursor-locked?", rfn!(Cursor::is_locked))?;
+            glsp::bind_rfn("set-cursor-image", rfn!(Cursor::set_image))?;
+            glsp::bind_rfn("clear-cursor-image", rfn!(Cursor::clear_image))?;
+            glsp::bind_rfn("mouse-position", rfn!(Cursor::position_script))?;
+            glsp::bind_rfn("mouse-world-position", rfn!(Cursor::world_position_script))?;
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,243 @@
+use glsp::{bail, rdata, rfn, GResult, Runtime, Sym};
+use std::f32::consts::PI;
+
+/// How many Newton iterations are used to find a point on a bezier curve.
+const NEWTON_ITERATIONS: usize = 8;
+/// How close a point found on a bezier curve must be.
+const BEZIER_PRECISION: f32 = 1e-5;
+
+/// The kind of easing of a curve.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Shape {
+    /// The value moves at a constant speed.
+    Linear,
+    /// A CSS-style cubic bezier from `(0, 0)` to `(1, 1)` with two control points.
+    CubicBezier([f32; 4]),
+    /// A damped spring overshooting the end & settling on it.
+    Spring {
+        /// How fast the oscillation dies out, from `0` for never to `1` for not at all.
+        damping: f32,
+        /// How many times the spring oscillates during the transition.
+        frequency: f32,
+    },
+    /// The value jumps in equal steps.
+    Steps {
+        /// The amount of jumps.
+        count: u32,
+        /// Whether the first jump is at the start instead of after the first step.
+        jump_start: bool,
+    },
+}
+
+rdata! {
+/// An easing curve mapping the progress of a transition to how far the value has moved.
+///
+/// Both are `0` at the start and `1` at the end, in between a curve can go below `0` or above
+/// `1` like the overshoot of a spring.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Curve(Shape);
+}
+
+impl Curve {
+    /// A curve moving at a constant speed.
+    pub fn linear() -> Self {
+        Self(Shape::Linear)
+    }
+
+    /// A CSS-style cubic bezier curve with the control points `(x1, y1)` & `(x2, y2)`.
+    ///
+    /// The horizontal positions are clamped between `0` & `1` so there's a single value for every
+    /// moment.
+    pub fn cubic_bezier(x1: f32, y1: f32, x2: f32, y2: f32) -> Self {
+        Self(Shape::CubicBezier([
+            x1.max(0.0).min(1.0),
+            y1,
+            x2.max(0.0).min(1.0),
+            y2,
+        ]))
+    }
+
+    /// A curve that holds the start value until the end, like a hold keyframe.
+    pub fn hold() -> Self {
+        Self(Shape::Steps {
+            count: 1,
+            jump_start: false,
+        })
+    }
+
+    /// How far the value has moved at a moment of the transition, both from `0` to `1`.
+    pub fn at(&self, t: f32) -> f32 {
+        if t <= 0.0 {
+            return 0.0;
+        }
+        if t >= 1.0 {
+            return 1.0;
+        }
+
+        match self.0 {
+            Shape::Linear => t,
+            Shape::CubicBezier([x1, y1, x2, y2]) => {
+                let s = bezier_parameter(t, x1, x2);
+
+                bezier(s, y1, y2)
+            }
+            Shape::Spring { damping, frequency } => {
+                let omega = 2.0 * PI * frequency;
+                let damped_omega = omega * (1.0 - damping * damping).max(0.0).sqrt();
+                let decay = (-damping * omega * t).exp();
+
+                if damped_omega > 0.0 {
+                    1.0 - decay
+                        * ((damped_omega * t).cos()
+                            + damping * omega / damped_omega * (damped_omega * t).sin())
+                } else {
+                    // Critically damped, it never overshoots
+                    1.0 - decay * (1.0 + omega * t)
+                }
+            }
+            Shape::Steps { count, jump_start } => {
+                let count = count.max(1) as f32;
+                let step = if jump_start {
+                    (t * count).floor() + 1.0
+                } else {
+                    (t * count).floor()
+                };
+
+                (step / count).min(1.0)
+            }
+        }
+    }
+
+    /// Blend between two values at a moment of the transition.
+    pub fn interpolate(&self, from: f32, to: f32, t: f32) -> f32 {
+        from + (to - from) * self.at(t)
+    }
+}
+
+/// A coordinate of a cubic bezier from `0` to `1` with the control points `p1` & `p2`.
+fn bezier(s: f32, p1: f32, p2: f32) -> f32 {
+    let inverse = 1.0 - s;
+
+    3.0 * inverse * inverse * s * p1 + 3.0 * inverse * s * s * p2 + s * s * s
+}
+
+/// The parameter of the cubic bezier where the horizontal coordinate is `x`.
+fn bezier_parameter(x: f32, x1: f32, x2: f32) -> f32 {
+    // Newton's method converges fast for most curves
+    let mut s = x;
+    for _ in 0..NEWTON_ITERATIONS {
+        let error = bezier(s, x1, x2) - x;
+        if error.abs() < BEZIER_PRECISION {
+            return s;
+        }
+
+        let inverse = 1.0 - s;
+        let slope =
+            3.0 * inverse * inverse * x1 + 6.0 * inverse * s * (x2 - x1) + 3.0 * s * s * (1.0 - x2);
+        if slope.abs() < BEZIER_PRECISION {
+            break;
+        }
+        s -= error / slope;
+    }
+
+    // Fall back to bisection for flat parts
+    let (mut low, mut high) = (0.0, 1.0);
+    s = x;
+    while high - low > BEZIER_PRECISION {
+        if bezier(s, x1, x2) < x {
+            low = s;
+        } else {
+            high = s;
+        }
+        s = (low + high) / 2.0;
+    }
+
+    s
+}
+
+/// Create a curve moving at a constant speed from a script.
+fn linear_curve() -> Curve {
+    Curve::linear()
+}
+
+/// Create a CSS-style cubic bezier curve from a script.
+///
+/// ```gamelisp
+/// (let bounce-in (cubic-bezier 0.68 -0.55 0.27 1.55))
+/// ```
+fn cubic_bezier(x1: f32, y1: f32, x2: f32, y2: f32) -> Curve {
+    Curve::cubic_bezier(x1, y1, x2, y2)
+}
+
+/// Create one of the standard CSS easing curves from a script.
+///
+/// The name is `ease`, `ease-in`, `ease-out` or `ease-in-out`.
+///
+/// ```gamelisp
+/// (let curve (ease-curve 'ease-out))
+/// ```
+fn ease_curve(name: Sym) -> GResult<Curve> {
+    Ok(match &*name.name() {
+        "ease" => Curve::cubic_bezier(0.25, 0.1, 0.25, 1.0),
+        "ease-in" => Curve::cubic_bezier(0.42, 0.0, 1.0, 1.0),
+        "ease-out" => Curve::cubic_bezier(0.0, 0.0, 0.58, 1.0),
+        "ease-in-out" => Curve::cubic_bezier(0.42, 0.0, 0.58, 1.0),
+        name => bail!("unknown easing curve {}", name),
+    })
+}
+
+/// Create a curve of a damped spring from a script.
+///
+/// The damping is from `0`, oscillating until the end, to `1`, not overshooting at all, and
+/// defaults to `0.5`. The frequency is how many times it oscillates and defaults to `2`.
+///
+/// ```gamelisp
+/// (let wobble (spring-curve 0.3 4))
+/// ```
+fn spring_curve(damping: Option<f32>, frequency: Option<f32>) -> Curve {
+    Curve(Shape::Spring {
+        damping: damping.unwrap_or(0.5).max(0.0).min(1.0),
+        frequency: frequency.unwrap_or(2.0).max(0.0),
+    })
+}
+
+/// Create a curve jumping in equal steps from a script.
+///
+/// When the jump start is set the first jump happens immediately.
+///
+/// ```gamelisp
+/// (let ticking (step-curve 4))
+/// ```
+fn step_curve(count: u32, jump_start: Option<bool>) -> GResult<Curve> {
+    if count == 0 {
+        bail!("a step curve needs at least 1 step");
+    }
+
+    Ok(Curve(Shape::Steps {
+        count,
+        jump_start: jump_start.unwrap_or(false),
+    }))
+}
+
+/// Get how far the value has moved at a moment of the transition from a script.
+///
+/// ```gamelisp
+/// (= [sprite 'x] (+ start (* distance (curve-at curve (/ elapsed duration)))))
+/// ```
+fn curve_at(curve: &Curve, t: f32) -> f32 {
+    curve.at(t)
+}
+
+/// Bind the GameLisp functions for creating & evaluating easing curves.
+pub fn bind_functions(runtime: &Runtime) {
+    runtime.run(|| {
+        glsp::bind_rfn("linear-curve", rfn!(linear_curve))?;
+        glsp::bind_rfn("cubic-bezier", rfn!(cubic_bezier))?;
+        glsp::bind_rfn("ease-curve", rfn!(ease_curve))?;
+        glsp::bind_rfn("spring-curve", rfn!(spring_curve))?;
+        glsp::bind_rfn("step-curve", rfn!(step_curve))?;
+        glsp::bind_rfn("curve-at", rfn!(curve_at))?;
+
+        Ok(())
+    });
+}
@@ -0,0 +1,186 @@
+use crate::{
+    assets::Assets,
+    color::Color,
+    render::{Render, Vertex, VertexCtor, MAX_DEBUG_INDICES, MAX_DEBUG_VERTICES},
+    shapes::Style,
+    text,
+};
+use anyhow::{anyhow, Result};
+use glsp::{bail, rfn, GResult, Runtime};
+use lyon::{
+    math::{point, rect},
+    tessellation::{basic_shapes, BuffersBuilder, FillOptions, FillTessellator, VertexBuffers},
+};
+
+/// The color of the debug shapes when none is supplied.
+const DEFAULT_COLOR: (u8, u8, u8) = (255, 0, 255);
+/// The font asset used for the debug text when none is supplied.
+pub(crate) const DEFAULT_FONT: &str = "default";
+/// The height of the debug text in pixels when none is supplied.
+const DEFAULT_TEXT_SIZE: f32 = 16.0;
+
+/// Tessellate a shape into the debug geometry of this frame.
+///
+/// The geometry of a shape that fails is dropped, as is a shape that doesn't fit in the buffers
+/// anymore, which isn't an error so drawing too much debug geometry doesn't halt the game.
+pub(crate) fn tessellate<F, E>(render: &mut Render, tessellate: F) -> std::result::Result<(), E>
+where
+    F: FnOnce(&mut VertexBuffers<Vertex, u16>) -> std::result::Result<(), E>,
+{
+    let geometry = render.debug_geometry();
+    let (vertex_count, index_count) = (geometry.vertices.len(), geometry.indices.len());
+    if vertex_count >= MAX_DEBUG_VERTICES {
+        return Ok(());
+    }
+
+    let result = tessellate(geometry);
+
+    // The 16-bit indices can't reference the vertices past the limit
+    let full =
+        geometry.vertices.len() >= MAX_DEBUG_VERTICES || geometry.indices.len() > MAX_DEBUG_INDICES;
+    if result.is_err() || full {
+        geometry.vertices.truncate(vertex_count);
+        geometry.indices.truncate(index_count);
+    }
+
+    if full {
+        Ok(())
+    } else {
+        result
+    }
+}
+
+/// Draw a line for a single frame.
+fn debug_line(
+    render: &mut Render,
+    x1: f32,
+    y1: f32,
+    x2: f32,
+    y2: f32,
+    color: Option<(u8, u8, u8)>,
+    width: Option<f32>,
+) -> GResult<()> {
+    let points = [point(x1, y1), point(x2, y2)];
+
+    tessellate(render, |geometry| {
+        Style::new(color.or(Some(DEFAULT_COLOR)), Some(width.unwrap_or(1.0))).tessellate(
+            geometry,
+            |_, _| unreachable!(),
+            |options, output| {
+                basic_shapes::stroke_polyline(points.iter().copied(), false, options, output)
+            },
+        )
+    })
+}
+
+/// Draw the outline of a rectangle for a single frame, `x` & `y` are the top left corner.
+fn debug_rect(
+    render: &mut Render,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    color: Option<(u8, u8, u8)>,
+) -> GResult<()> {
+    let shape = rect(x, y, width, height);
+
+    tessellate(render, |geometry| {
+        Style::new(color.or(Some(DEFAULT_COLOR)), Some(1.0)).tessellate(
+            geometry,
+            |_, _| unreachable!(),
+            |options, output| basic_shapes::stroke_rectangle(&shape, options, output),
+        )
+    })
+}
+
+/// Draw the outline of a circle for a single frame.
+fn debug_circle(
+    render: &mut Render,
+    x: f32,
+    y: f32,
+    radius: f32,
+    color: Option<(u8, u8, u8)>,
+) -> GResult<()> {
+    tessellate(render, |geometry| {
+        Style::new(color.or(Some(DEFAULT_COLOR)), Some(1.0)).tessellate(
+            geometry,
+            |_, _| unreachable!(),
+            |options, output| basic_shapes::stroke_circle(point(x, y), radius, options, output),
+        )
+    })
+}
+
+/// Draw a line of text for a single frame, `x` & `y` are the top left corner.
+///
+/// Uses the font asset with the reference name `default` unless another one is supplied.
+fn debug_text(
+    render: &mut Render,
+    x: f32,
+    y: f32,
+    text: &str,
+    color: Option<(u8, u8, u8)>,
+    size: Option<f32>,
+    font: Option<&str>,
+) -> GResult<()> {
+    match draw_text(
+        render,
+        x,
+        y,
+        text,
+        color.unwrap_or(DEFAULT_COLOR),
+        size.unwrap_or(DEFAULT_TEXT_SIZE),
+        font.unwrap_or(DEFAULT_FONT),
+    ) {
+        Ok(()) => Ok(()),
+        Err(err) => bail!("drawing debug text failed: {}", err),
+    }
+}
+
+/// Tessellate a line of text into the debug geometry.
+pub(crate) fn draw_text(
+    render: &mut Render,
+    x: f32,
+    y: f32,
+    text: &str,
+    (red, green, blue): (u8, u8, u8),
+    size: f32,
+    font: &str,
+) -> Result<()> {
+    let assets = Assets::borrow();
+    let font_data = assets
+        .data(font)
+        .ok_or_else(|| anyhow!("font asset \"{}\" is not loaded", font))?;
+
+    let path = text::text_path(font_data, text, size, point(x, y))?;
+    // Flatten the glyphs to the physical pixels of the screen
+    let tolerance =
+        FillOptions::DEFAULT_TOLERANCE * render.world_units_per_pixel() / render.dpi_scale();
+
+    tessellate(render, |geometry| {
+        FillTessellator::new()
+            .tessellate(
+                path.iter(),
+                &FillOptions::tolerance(tolerance),
+                &mut BuffersBuilder::new(
+                    geometry,
+                    VertexCtor::new(Color::new(red, green, blue), 1.0),
+                ),
+            )
+            .map(|_| ())
+            .map_err(|err| anyhow!("tessellating text failed: {:?}", err))
+    })
+}
+
+/// Bind the GameLisp functions for drawing debug shapes on top of everything.
+///
+/// The shapes are only drawn during the frame they are called in.
+pub fn bind_functions(runtime: &Runtime) {
+    runtime.run(|| {
+        glsp::bind_rfn("debug-line", rfn!(debug_line))?;
+        glsp::bind_rfn("debug-rect", rfn!(debug_rect))?;
+        glsp::bind_rfn("debug-circle", rfn!(debug_circle))?;
+        glsp::bind_rfn("debug-text", rfn!(debug_text))?;
+
+        Ok(())
+    });
+}
@@ -0,0 +1,323 @@
+use crate::sandbox;
+use glsp::{bail, lib, rdata, rfn, GFn, GResult, IntoVal, Rest, Root, Runtime, Sym, Val};
+use std::collections::HashMap;
+
+rdata! {
+/// A reference to an entity, stays invalid after the entity is despawned.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Entity {
+    /// The slot of the entity, reused after despawning.
+    index: u32,
+    /// How often the slot has been reused.
+    generation: u32,
+}
+
+meths {
+    get "id": Entity::id,
+}
+}
+
+impl Entity {
+    /// The slot of the entity, only unique among the living entities.
+    pub fn id(&self) -> u32 {
+        self.index
+    }
+}
+
+/// All values of a single component, stored densely for fast iteration.
+#[derive(Debug, Default)]
+struct ComponentStorage {
+    /// The position in the dense arrays of every entity slot that has the component.
+    sparse: HashMap<u32, usize>,
+    /// The entities that have the component.
+    entities: Vec<Entity>,
+    /// The component values, in the same order as the entities.
+    values: Vec<Val>,
+}
+
+impl ComponentStorage {
+    /// Add or replace the component of an entity.
+    fn insert(&mut self, entity: Entity, value: Val) {
+        match self.sparse.get(&entity.index) {
+            Some(&dense) => {
+                self.entities[dense] = entity;
+                self.values[dense] = value;
+            }
+            None => {
+                self.sparse.insert(entity.index, self.entities.len());
+                self.entities.push(entity);
+                self.values.push(value);
+            }
+        }
+    }
+
+    /// The component of an entity.
+    fn get(&self, entity: Entity) -> Option<&Val> {
+        let dense = *self.sparse.get(&entity.index)?;
+
+        if self.entities[dense] == entity {
+            Some(&self.values[dense])
+        } else {
+            None
+        }
+    }
+
+    /// Remove the component of an entity, returns whether it had it.
+    fn remove(&mut self, entity: Entity) -> bool {
+        let dense = match self.sparse.get(&entity.index) {
+            Some(&dense) if self.entities[dense] == entity => dense,
+            _ => return false,
+        };
+
+        // Move the last component into the gap
+        self.sparse.remove(&entity.index);
+        self.entities.swap_remove(dense);
+        self.values.swap_remove(dense);
+        if let Some(moved) = self.entities.get(dense) {
+            self.sparse.insert(moved.index, dense);
+        }
+
+        true
+    }
+}
+
+lib! {
+/// Entities with components that are GameLisp values, so scripts don't have to keep parallel
+/// arrays for every property of their game objects.
+///
+/// ```gamelisp
+/// (let player (spawn))
+/// (set-component player 'position (tab ('x 0.0) ('y 0.0)))
+/// (set-component player 'velocity (tab ('x 1.0) ('y 0.0)))
+///
+/// (for-each-with 'position 'velocity (fn (entity position velocity)
+///   (inc! [position 'x] [velocity 'x])
+///   (inc! [position 'y] [velocity 'y])))
+/// ```
+pub struct World {
+    /// The current generation of every entity slot.
+    generations: Vec<u32>,
+    /// Whether the entity slot is in use.
+    alive: Vec<bool>,
+    /// The despawned slots that can be reused.
+    free: Vec<u32>,
+    /// The components by name.
+    components: HashMap<Sym, ComponentStorage>,
+}
+}
+
+impl World {
+    /// Create a world without any entities.
+    pub fn new() -> Self {
+        Self {
+            generations: vec![],
+            alive: vec![],
+            free: vec![],
+            components: HashMap::new(),
+        }
+    }
+
+    /// Create a new entity without any components.
+    pub fn spawn(&mut self) -> Entity {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.generations.push(0);
+                self.alive.push(false);
+
+                self.generations.len() as u32 - 1
+            }
+        };
+        self.alive[index as usize] = true;
+
+        Entity {
+            index,
+            generation: self.generations[index as usize],
+        }
+    }
+
+    /// Remove an entity with all its components, returns whether it was alive.
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        for storage in self.components.values_mut() {
+            storage.remove(entity);
+        }
+
+        // Invalidate all references to the slot
+        let index = entity.index as usize;
+        self.alive[index] = false;
+        self.generations[index] = self.generations[index].wrapping_add(1);
+        self.free.push(entity.index);
+
+        true
+    }
+
+    /// Whether the entity hasn't been despawned.
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        let index = entity.index as usize;
+
+        index < self.alive.len()
+            && self.alive[index]
+            && self.generations[index] == entity.generation
+    }
+
+    /// The amount of living entities.
+    pub fn entity_count(&self) -> usize {
+        self.alive.len() - self.free.len()
+    }
+
+    /// All living entities.
+    pub fn entities(&self) -> Vec<Entity> {
+        self.alive
+            .iter()
+            .enumerate()
+            .filter(|(_, alive)| **alive)
+            .map(|(index, _)| Entity {
+                index: index as u32,
+                generation: self.generations[index],
+            })
+            .collect()
+    }
+
+    /// All components of an entity with their names.
+    pub fn components_of(&self, entity: Entity) -> Vec<(Sym, Val)> {
+        self.components
+            .iter()
+            .filter_map(|(name, storage)| Some((*name, storage.get(entity)?.clone())))
+            .collect()
+    }
+
+    /// Add or replace a component of an entity.
+    pub fn set_component(&mut self, entity: Entity, component: Sym, value: Val) -> GResult<()> {
+        if !self.is_alive(entity) {
+            bail!("entity {} is despawned", entity.index);
+        }
+
+        self.components
+            .entry(component)
+            .or_default()
+            .insert(entity, value);
+
+        Ok(())
+    }
+
+    /// A component of an entity.
+    pub fn component(&self, entity: Entity, component: Sym) -> Option<Val> {
+        self.components.get(&component)?.get(entity).cloned()
+    }
+
+    /// Whether the entity has a component.
+    pub fn has_component(&self, entity: Entity, component: Sym) -> bool {
+        self.component(entity, component).is_some()
+    }
+
+    /// Remove a component from an entity, returns whether it had it.
+    pub fn remove_component(&mut self, entity: Entity, component: Sym) -> bool {
+        match self.components.get_mut(&component) {
+            Some(storage) => storage.remove(entity),
+            None => false,
+        }
+    }
+
+    /// All entities that have every component, with the values of those components.
+    pub fn query(&self, components: &[Sym]) -> Vec<(Entity, Vec<Val>)> {
+        let storages = match components
+            .iter()
+            .map(|component| self.components.get(component))
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(storages) => storages,
+            None => return vec![],
+        };
+
+        // Iterate over the smallest storage, the others are only looked up
+        let smallest = match storages.iter().min_by_key(|storage| storage.entities.len()) {
+            Some(smallest) => smallest,
+            None => return vec![],
+        };
+
+        smallest
+            .entities
+            .iter()
+            .filter_map(|entity| {
+                let values = storages
+                    .iter()
+                    .map(|storage| storage.get(*entity).cloned())
+                    .collect::<Option<Vec<_>>>()?;
+
+                Some((*entity, values))
+            })
+            .collect()
+    }
+
+    /// Get all entities with the components from a script.
+    ///
+    /// ```gamelisp
+    /// (for entity in (entities-with 'enemy 'position)
+    ///   (despawn entity))
+    /// ```
+    fn entities_with(&self, components: Rest<Sym>) -> Vec<Entity> {
+        self.query(&components)
+            .into_iter()
+            .map(|(entity, _)| entity)
+            .collect()
+    }
+
+    /// Register the world and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(World::new());
+
+            glsp::bind_rfn("spawn", rfn!(World::spawn))?;
+            glsp::bind_rfn("despawn", rfn!(World::despawn))?;
+            glsp::bind_rfn("entity-alive?", rfn!(World::is_alive))?;
+            glsp::bind_rfn("entity-count", rfn!(World::entity_count))?;
+            glsp::bind_rfn("set-component", rfn!(World::set_component))?;
+            glsp::bind_rfn("component", rfn!(World::component))?;
+            glsp::bind_rfn("has-component?", rfn!(World::has_component))?;
+            glsp::bind_rfn("remove-component", rfn!(World::remove_component))?;
+            glsp::bind_rfn("entities-with", rfn!(World::entities_with))?;
+            glsp::bind_rfn("for-each-with", rfn!(for_each_with))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// Call a function for every entity with all the components from a script.
+///
+/// The last argument is the function, it receives the entity followed by the component values.
+/// Entities can be spawned & despawned from the function.
+fn for_each_with(args: Rest<Val>) -> GResult<()> {
+    let (function, components) = match args.split_last() {
+        Some((Val::GFn(function), components)) => (function.clone(), components),
+        _ => bail!("for-each-with expects a function as the last argument"),
+    };
+    let function: Root<GFn> = function;
+    let components = components
+        .iter()
+        .map(|component| match component {
+            Val::Sym(component) => Ok(*component),
+            val => bail!("component name must be a symbol, got {}", val),
+        })
+        .collect::<GResult<Vec<_>>>()?;
+
+    // Don't hold the borrow while calling the function, it might change the components
+    let matches = World::borrow().query(&components);
+    for (entity, values) in matches {
+        if !World::borrow().is_alive(entity) {
+            continue;
+        }
+
+        let mut args = Vec::with_capacity(values.len() + 1);
+        args.push(entity.into_val()?);
+        args.extend(values);
+
+        let _: Val = sandbox::call(&function, &args[..])?;
+    }
+
+    Ok(())
+}
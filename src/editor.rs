@@ -0,0 +1,546 @@
+use crate::{
+    assets::Assets,
+    ecs::World,
+    level,
+    render::{Instance, InstanceRef, Render},
+    script::Scripts,
+    system::System,
+};
+use egui::{CtxRef, DragValue, ScrollArea, Slider, TextEdit, Ui, Window};
+use egui_miniquad::EguiMq;
+use glsp::Runtime;
+use miniquad::{graphics::Context, KeyCode, KeyMods, MouseButton};
+
+/// Maximum amount of entities & instances listed in the inspectors.
+const MAX_LISTED: usize = 256;
+/// The step rotations snap to, in radians.
+const ROTATION_SNAP: f32 = std::f32::consts::PI / 12.0;
+/// The step scales snap to.
+const SCALE_SNAP: f32 = 0.25;
+
+/// What dragging with the mouse does to instances.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Tool {
+    Move,
+    Rotate,
+    Scale,
+    /// Clicking adds an instance of the mesh of an asset.
+    Place,
+}
+
+/// An instance that's being transformed with the mouse.
+#[derive(Debug, Copy, Clone)]
+struct Drag {
+    instance: InstanceRef,
+    /// The world position where the mouse was pressed.
+    start: (f32, f32),
+    /// The instance before it was dragged.
+    original: Instance,
+}
+
+/// What to do with the level file after drawing the tools.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum LevelAction {
+    Save,
+    Load,
+}
+
+/// The settings of the level editing tools.
+#[derive(Debug, Clone)]
+struct LevelTools {
+    /// What dragging does.
+    tool: Tool,
+    /// Whether positions snap to the grid & rotations & scales to steps.
+    snap: bool,
+    /// The size of the grid cells in world units.
+    grid_size: f32,
+    /// The reference name of the asset placed with the place tool.
+    asset: String,
+    /// The layout file relative to the script directory.
+    file: String,
+    /// The result of the last save or load.
+    status: String,
+}
+
+impl LevelTools {
+    /// Snap a world position to the grid if snapping.
+    fn snap_position(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        if !self.snap || self.grid_size <= 0.0 {
+            return (x, y);
+        }
+
+        (
+            (x / self.grid_size).round() * self.grid_size,
+            (y / self.grid_size).round() * self.grid_size,
+        )
+    }
+
+    /// Snap a value to a step if snapping.
+    fn snap_step(&self, value: f32, step: f32) -> f32 {
+        if self.snap {
+            (value / step).round() * step
+        } else {
+            value
+        }
+    }
+
+    /// Transform an instance being dragged with the tool, the mouse is at a world position.
+    fn drag(&self, drag: &Drag, (mouse_x, mouse_y): (f32, f32)) -> Instance {
+        let mut edited = drag.original;
+        let (x, y) = (drag.original.x(), drag.original.y());
+        let (start_x, start_y) = drag.start;
+
+        match self.tool {
+            Tool::Move => {
+                let (x, y) = self.snap_position((x + mouse_x - start_x, y + mouse_y - start_y));
+                edited.set_x(x);
+                edited.set_y(y);
+            }
+            Tool::Rotate => {
+                // Rotate by the angle the mouse moved around the instance
+                let angle = (mouse_y - y).atan2(mouse_x - x) - (start_y - y).atan2(start_x - x);
+                edited
+                    .set_rotation(self.snap_step(drag.original.rotation() + angle, ROTATION_SNAP));
+            }
+            Tool::Scale => {
+                // Scale by how much further the mouse is from the instance
+                let start_distance = (start_x - x).hypot(start_y - y).max(f32::EPSILON);
+                let factor = (mouse_x - x).hypot(mouse_y - y) / start_distance;
+                edited.set_scale_x(self.snap_step(drag.original.scale_x() * factor, SCALE_SNAP));
+                edited.set_scale_y(self.snap_step(drag.original.scale_y() * factor, SCALE_SNAP));
+            }
+            Tool::Place => (),
+        }
+
+        edited
+    }
+}
+
+/// Development tools drawn with egui on top of the game, toggled with a key.
+///
+/// Shows a camera, instance & entity inspector and the windows of the native systems. The level
+/// tools place, move, rotate & scale instances with the mouse and save the instances of asset
+/// meshes as a layout script that `load-level` places again.
+pub struct Editor {
+    /// The egui context with its miniquad renderer.
+    egui: EguiMq,
+    /// Whether the tools are shown and receive input.
+    open: bool,
+    /// The key that shows & hides the tools.
+    toggle_key: KeyCode,
+    /// The instance shown in the property editor.
+    selected: Option<InstanceRef>,
+    /// The settings of the level tools.
+    level: LevelTools,
+    /// The instance being transformed with the mouse.
+    drag: Option<Drag>,
+}
+
+impl Editor {
+    /// Create the editor, shown immediately when it's open.
+    pub fn new(ctx: &mut Context, toggle_key: KeyCode, open: bool) -> Self {
+        Self {
+            egui: EguiMq::new(ctx),
+            open,
+            toggle_key,
+            selected: None,
+            level: LevelTools {
+                tool: Tool::Move,
+                snap: false,
+                grid_size: 16.0,
+                asset: String::new(),
+                file: "level.glsp".to_string(),
+                status: String::new(),
+            },
+            drag: None,
+        }
+    }
+
+    /// Handle a pressed key, returns whether it must not be passed to the game.
+    pub fn key_down(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods) -> bool {
+        if keycode == self.toggle_key {
+            self.open = !self.open;
+
+            return true;
+        }
+        if !self.open {
+            return false;
+        }
+
+        self.egui.key_down_event(ctx, keycode, keymods);
+
+        self.wants_keyboard()
+    }
+
+    /// Handle a released key, returns whether it must not be passed to the game.
+    pub fn key_up(&mut self, keycode: KeyCode, keymods: KeyMods) -> bool {
+        if !self.open {
+            return false;
+        }
+
+        self.egui.key_up_event(keycode, keymods);
+
+        self.wants_keyboard()
+    }
+
+    /// Handle a typed character, returns whether it must not be passed to the console.
+    pub fn char_typed(&mut self, character: char) -> bool {
+        if !self.open {
+            return false;
+        }
+
+        self.egui.char_event(character);
+
+        self.wants_keyboard()
+    }
+
+    /// Handle a moved mouse, transforming the dragged instance.
+    pub fn mouse_motion(&mut self, ctx: &mut Context, runtime: &Runtime, x: f32, y: f32) {
+        if !self.open {
+            return;
+        }
+
+        self.egui.mouse_motion_event(ctx, x, y);
+
+        let drag = match self.drag {
+            Some(drag) => drag,
+            None => return,
+        };
+        // Egui gets the physical pixels, the render logical ones
+        let (x, y) = (x / ctx.dpi_scale(), y / ctx.dpi_scale());
+        let level = &self.level;
+        runtime.run(|| {
+            let mut render = Render::borrow_mut();
+            let edited = level.drag(&drag, render.screen_to_world(x, y));
+            if let Some(stored) = render.instance_mut(drag.instance) {
+                *stored = edited;
+            }
+
+            Ok(())
+        });
+    }
+
+    /// Handle a scrolled mouse wheel.
+    pub fn mouse_wheel(&mut self, ctx: &mut Context, dx: f32, dy: f32) {
+        if self.open {
+            self.egui.mouse_wheel_event(ctx, dx, dy);
+        }
+    }
+
+    /// Handle a pressed mouse button, selecting or placing an instance when it's not on a window.
+    pub fn mouse_button_down(
+        &mut self,
+        ctx: &mut Context,
+        runtime: &Runtime,
+        button: MouseButton,
+        x: f32,
+        y: f32,
+    ) {
+        if !self.open {
+            return;
+        }
+
+        self.egui.mouse_button_down_event(ctx, button, x, y);
+        if button != MouseButton::Left || self.egui.egui_ctx().wants_pointer_input() {
+            return;
+        }
+
+        // Egui gets the physical pixels, the render logical ones
+        let (x, y) = (x / ctx.dpi_scale(), y / ctx.dpi_scale());
+        let (level, selected, drag) = (&self.level, &mut self.selected, &mut self.drag);
+        runtime.run(|| {
+            let mut render = Render::borrow_mut();
+            let position = render.screen_to_world(x, y);
+
+            if level.tool == Tool::Place {
+                let mesh = match Assets::borrow().mesh(&level.asset) {
+                    Some(mesh) => mesh,
+                    None => {
+                        log::warn!("asset "{}" isn't a loaded mesh", level.asset);
+
+                        return Ok(());
+                    }
+                };
+                let (x, y) = level.snap_position(position);
+                *selected = Some(render.add_instance(mesh, Instance::new(x, y)));
+
+                return Ok(());
+            }
+
+            *selected = render.pick_instance(x, y);
+            *drag = selected.and_then(|instance| {
+                render.instance(instance).map(|original| Drag {
+                    instance,
+                    start: position,
+                    original: *original,
+                })
+            });
+
+            Ok(())
+        });
+    }
+
+    /// Handle a released mouse button, ending the drag.
+    pub fn mouse_button_up(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        if self.open {
+            self.egui.mouse_button_up_event(ctx, button, x, y);
+        }
+        if button == MouseButton::Left {
+            self.drag = None;
+        }
+    }
+
+    /// Whether a text field has the focus.
+    fn wants_keyboard(&self) -> bool {
+        self.egui.egui_ctx().wants_keyboard_input()
+    }
+
+    /// Draw the tools on top of the frame if they are shown.
+    ///
+    /// Must be called after the game is rendered, before the frame is committed.
+    pub fn draw(&mut self, ctx: &mut Context, runtime: &Runtime, systems: &mut [Box<dyn System>]) {
+        if !self.open {
+            return;
+        }
+
+        self.egui.begin_frame(ctx);
+        let egui = self.egui.egui_ctx().clone();
+
+        let (selected, level) = (&mut self.selected, &mut self.level);
+        let action = runtime
+            .run(|| {
+                let mut render = Render::borrow_mut();
+                camera_window(&egui, &mut render);
+                instances_window(&egui, &mut render, selected);
+                entities_window(&egui, &World::borrow());
+
+                Ok(level_window(&egui, level))
+            })
+            .flatten();
+
+        // The scripts can't be borrowed while loading the layout
+        if let Some(action) = action {
+            let status = runtime.run(|| {
+                Ok(match action {
+                    LevelAction::Save => save_level(&self.level.file),
+                    LevelAction::Load => load_level(&self.level.file),
+                })
+            });
+            self.level.status = status.unwrap_or_else(|| "running the script failed".to_string());
+            self.selected = None;
+            self.drag = None;
+        }
+
+        for system in systems.iter_mut() {
+            runtime.run(|| {
+                system.editor_ui(&egui);
+
+                Ok(())
+            });
+        }
+
+        self.egui.end_frame(ctx);
+        self.egui.draw(ctx);
+    }
+}
+
+/// Pick the tool & snapping, and save or load the layout of the level.
+fn level_window(egui: &CtxRef, level: &mut LevelTools) -> Option<LevelAction> {
+    let mut action = None;
+
+    Window::new("Level").show(egui, |ui| {
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut level.tool, Tool::Move, "Move");
+            ui.radio_value(&mut level.tool, Tool::Rotate, "Rotate");
+            ui.radio_value(&mut level.tool, Tool::Scale, "Scale");
+            ui.radio_value(&mut level.tool, Tool::Place, "Place");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Asset");
+            ui.add(TextEdit::singleline(&mut level.asset));
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut level.snap, "Snap");
+            ui.add(
+                DragValue::f32(&mut level.grid_size)
+                    .range(1.0..=1024.0)
+                    .prefix("grid "),
+            );
+        });
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.add(TextEdit::singleline(&mut level.file));
+            if ui.button("Save").clicked() {
+                action = Some(LevelAction::Save);
+            }
+            if ui.button("Load").clicked() {
+                action = Some(LevelAction::Load);
+            }
+        });
+        if !level.status.is_empty() {
+            ui.label(&level.status);
+        }
+    });
+
+    action
+}
+
+/// Write the layout of the instances of asset meshes to a file in the script directory.
+fn save_level(file: &str) -> String {
+    let source = {
+        let render = Render::borrow();
+        let placements = level::placements(&Assets::borrow(), &render);
+
+        level::layout_source(&placements)
+    };
+    let path = Scripts::borrow().root().join(file);
+
+    match std::fs::write(&path, source) {
+        Ok(()) => format!("saved to \"{}\"", path.display()),
+        Err(err) => format!("saving \"{}\" failed: {}", path.display(), err),
+    }
+}
+
+/// Replace the instances of asset meshes with the layout in a file in the script directory.
+fn load_level(file: &str) -> String {
+    {
+        let mut render = Render::borrow_mut();
+        for (_, instance) in level::placed_instances(&Assets::borrow(), &render) {
+            render.remove_instance(instance);
+        }
+    }
+
+    match level::load(file) {
+        Ok(instances) => format!("loaded {} instances", instances.len()),
+        Err(err) => format!("loading \"{}\" failed: {}", file, err),
+    }
+}
+
+/// Move & zoom the camera.
+fn camera_window(egui: &CtxRef, render: &mut Render) {
+    Window::new("Camera").show(egui, |ui| {
+        let (mut x, mut y) = render.camera_pos();
+        let mut zoom = render.camera_zoom();
+
+        ui.horizontal(|ui| {
+            ui.label("Position");
+            ui.add(DragValue::f32(&mut x));
+            ui.add(DragValue::f32(&mut y));
+        });
+        ui.add(Slider::f32(&mut zoom, 0.05..=20.0).text("Zoom"));
+        if ui.button("Reset").clicked() {
+            x = 0.0;
+            y = 0.0;
+            zoom = 1.0;
+        }
+
+        render.set_camera_pos(x, y);
+        render.set_camera_zoom(zoom);
+    });
+}
+
+/// List the meshes with their instances and edit the properties of the selected one.
+fn instances_window(egui: &CtxRef, render: &mut Render, selected: &mut Option<InstanceRef>) {
+    // The selected instance might have been removed by a script
+    if let Some(instance) = *selected {
+        if render.instance(instance).is_none() {
+            *selected = None;
+        }
+    }
+
+    Window::new("Instances").show(egui, |ui| {
+        if let Some(instance) = *selected {
+            instance_properties(ui, render, instance);
+            ui.separator();
+        }
+
+        ScrollArea::auto_sized().show(ui, |ui| {
+            for (mesh, count) in render.instance_counts() {
+                ui.collapsing(format!("mesh {} ({})", mesh.index(), count), |ui| {
+                    for (index, instance) in render
+                        .instances(mesh)
+                        .into_iter()
+                        .take(MAX_LISTED)
+                        .enumerate()
+                    {
+                        let is_selected = *selected == Some(instance);
+                        if ui
+                            .selectable_label(is_selected, format!("instance {}", index))
+                            .clicked()
+                        {
+                            *selected = Some(instance);
+                        }
+                    }
+                });
+            }
+        });
+    });
+}
+
+/// Edit the transformation & color of an instance.
+fn instance_properties(ui: &mut Ui, render: &mut Render, instance: InstanceRef) {
+    let mut edited = match render.instance(instance) {
+        Some(instance) => *instance,
+        None => return,
+    };
+
+    let (mut x, mut y, mut z) = (edited.x(), edited.y(), edited.z());
+    let mut rotation = edited.rotation();
+    let (mut scale_x, mut scale_y) = (edited.scale_x(), edited.scale_y());
+    let (mut red, mut green, mut blue) = edited.color_multiplier();
+
+    ui.horizontal(|ui| {
+        ui.label("Position");
+        ui.add(DragValue::f32(&mut x));
+        ui.add(DragValue::f32(&mut y));
+    });
+    ui.add(Slider::u8(&mut z, 0..=255).text("Z"));
+    ui.add(
+        Slider::f32(&mut rotation, -std::f32::consts::PI..=std::f32::consts::PI).text("Rotation"),
+    );
+    ui.horizontal(|ui| {
+        ui.label("Scale");
+        ui.add(DragValue::f32(&mut scale_x).speed(0.01));
+        ui.add(DragValue::f32(&mut scale_y).speed(0.01));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Color");
+        ui.add(DragValue::f32(&mut red).speed(0.01).range(0.0..=1.0));
+        ui.add(DragValue::f32(&mut green).speed(0.01).range(0.0..=1.0));
+        ui.add(DragValue::f32(&mut blue).speed(0.01).range(0.0..=1.0));
+    });
+
+    edited.set_x(x);
+    edited.set_y(y);
+    edited.set_z(z);
+    edited.set_rotation(rotation);
+    edited.set_scale_x(scale_x);
+    edited.set_scale_y(scale_y);
+    edited.set_color_multiplier(red, green, blue);
+
+    // Only upload the instance again when it changed
+    if render.instance(instance) != Some(&edited) {
+        if let Some(stored) = render.instance_mut(instance) {
+            *stored = edited;
+        }
+    }
+}
+
+/// List the living entities with their components.
+fn entities_window(egui: &CtxRef, world: &World) {
+    Window::new("Entities").show(egui, |ui| {
+        ui.label(format!("{} entities", world.entity_count()));
+        ui.separator();
+
+        ScrollArea::auto_sized().show(ui, |ui| {
+            for entity in world.entities().into_iter().take(MAX_LISTED) {
+                ui.collapsing(format!("entity {}", entity.id()), |ui| {
+                    for (name, value) in world.components_of(entity) {
+                        ui.label(format!("{}: {}", name, value));
+                    }
+                });
+            }
+        });
+    });
+}
@@ -0,0 +1,564 @@
+use crate::{curve::Curve, render::Render, sandbox};
+use glsp::{lib, rfn, GFn, GResult, Root, Runtime, Val};
+use miniquad::{graphics::*, Context};
+use std::mem;
+
+/// The maximum amount of overlay quads drawn during a frame.
+const MAX_OVERLAY_QUADS: usize = 8;
+
+/// The vertex shader of the overlays, the positions are already in screen coordinates.
+const OVERLAY_VERTEX: &str = r#"#version 100
+
+attribute vec2 a_pos;
+attribute vec4 a_color;
+
+varying lowp vec4 color;
+
+void main() {
+    gl_Position = vec4(a_pos, 0.0, 1.0);
+    color = a_color;
+}
+"#;
+
+/// The fragment shader of the overlays.
+const OVERLAY_FRAGMENT: &str = r#"#version 100
+
+varying lowp vec4 color;
+
+void main() {
+    gl_FragColor = color;
+}
+"#;
+
+/// A transition of the fade overlay to another opacity.
+#[derive(Debug)]
+struct Fade {
+    /// The opacity when the fade started.
+    from: f32,
+    /// The opacity when the fade is finished.
+    to: f32,
+    /// How long the fade takes in seconds.
+    duration: f64,
+    /// How long the fade is running in seconds.
+    elapsed: f64,
+    /// The function called when the fade is finished.
+    callback: Option<Root<GFn>>,
+}
+
+/// A camera transition to another position & zoom.
+#[derive(Debug)]
+struct CameraMove {
+    /// The camera position when the move started.
+    from: (f32, f32),
+    /// The camera position when the move is finished.
+    to: (f32, f32),
+    /// The camera zoom when the move started & when it's finished.
+    zoom: (f32, f32),
+    /// How long the move takes in seconds.
+    duration: f64,
+    /// How long the move is running in seconds.
+    elapsed: f64,
+    /// The easing of the move.
+    curve: Curve,
+}
+
+/// An effect that fades out over time.
+#[derive(Debug, Clone)]
+struct Fading<T> {
+    /// The strength of the effect at the start.
+    value: T,
+    /// How long the effect lasts in seconds.
+    duration: f64,
+    /// How long the effect is running in seconds.
+    elapsed: f64,
+}
+
+impl<T> Fading<T> {
+    /// How much of the effect is left, from `1.0` at the start to `0.0` at the end.
+    fn remaining(&self) -> f32 {
+        if self.duration <= 0.0 {
+            0.0
+        } else {
+            (1.0 - self.elapsed / self.duration).max(0.0) as f32
+        }
+    }
+}
+
+lib! {
+/// Screen-space effects for game feel & cutscenes.
+///
+/// The camera shake moves the view without changing the camera position, the flash, the fade &
+/// the letterbox bars are drawn on top of everything. Colors are passed as `(arr red green blue)`
+/// with values from 0 to 255. The effects only advance while the game isn't paused.
+///
+/// ```gamelisp
+/// (shake-camera 8 0.3)
+/// (flash-screen (arr 255 255 255) 0.1)
+/// (fade-out 1.0 (fn () (push-scene 'game-over)))
+/// (letterbox 0.12 0.5)
+/// (move-camera 200 0 1.5 (ease-curve 'ease-in-out))
+/// ```
+pub struct Effects {
+    /// The maximum distance the view moves in world units.
+    shake: Option<Fading<f32>>,
+    /// The color of the flash.
+    flash: Option<Fading<[f32; 3]>>,
+    /// The color of the fade overlay.
+    fade_color: [f32; 3],
+    /// The opacity of the fade overlay, kept after a fade is finished.
+    fade_opacity: f32,
+    /// The fade that's running.
+    fade: Option<Fade>,
+    /// The height of each letterbox bar as a fraction of the screen height.
+    letterbox: f32,
+    /// The height the letterbox bars are moving to & how fast per second.
+    letterbox_target: (f32, f32),
+    /// The camera transition that's running.
+    camera_move: Option<CameraMove>,
+    /// The time the effects are running in seconds, used for the shake pattern.
+    time: f64,
+}
+}
+
+impl Effects {
+    /// Create without any effects.
+    pub fn new() -> Self {
+        Self {
+            shake: None,
+            flash: None,
+            fade_color: [0.0, 0.0, 0.0],
+            fade_opacity: 0.0,
+            fade: None,
+            letterbox: 0.0,
+            letterbox_target: (0.0, 0.0),
+            camera_move: None,
+            time: 0.0,
+        }
+    }
+
+    /// Advance all effects and call the functions of the fades that finished.
+    ///
+    /// Must be called inside the GameLisp runtime.
+    pub fn update(delta: f64) -> GResult<()> {
+        // Don't hold the borrow while calling the scripts, they might start another fade
+        let callback = {
+            let mut effects = Effects::borrow_mut();
+            effects.time += delta;
+
+            for shake in effects.shake.iter_mut() {
+                shake.elapsed += delta;
+            }
+            if effects
+                .shake
+                .as_ref()
+                .map_or(false, |shake| shake.remaining() <= 0.0)
+            {
+                effects.shake = None;
+            }
+
+            for flash in effects.flash.iter_mut() {
+                flash.elapsed += delta;
+            }
+            if effects
+                .flash
+                .as_ref()
+                .map_or(false, |flash| flash.remaining() <= 0.0)
+            {
+                effects.flash = None;
+            }
+
+            let (target, speed) = effects.letterbox_target;
+            let step = speed * delta as f32;
+            effects.letterbox = if (target - effects.letterbox).abs() <= step {
+                target
+            } else {
+                effects.letterbox + step * (target - effects.letterbox).signum()
+            };
+
+            if let Some(mut camera_move) = effects.camera_move.take() {
+                camera_move.elapsed += delta;
+                let fraction = if camera_move.duration <= 0.0 {
+                    1.0
+                } else {
+                    (camera_move.elapsed / camera_move.duration).min(1.0) as f32
+                };
+
+                let curve = camera_move.curve;
+                let mut render = Render::borrow_mut();
+                render.set_camera_pos(
+                    curve.interpolate(camera_move.from.0, camera_move.to.0, fraction),
+                    curve.interpolate(camera_move.from.1, camera_move.to.1, fraction),
+                );
+                render.set_camera_zoom(curve.interpolate(
+                    camera_move.zoom.0,
+                    camera_move.zoom.1,
+                    fraction,
+                ));
+
+                if fraction < 1.0 {
+                    effects.camera_move = Some(camera_move);
+                }
+            }
+
+            match effects.fade.take() {
+                Some(mut fade) => {
+                    fade.elapsed += delta;
+                    let fraction = if fade.duration <= 0.0 {
+                        1.0
+                    } else {
+                        (fade.elapsed / fade.duration).min(1.0) as f32
+                    };
+                    effects.fade_opacity = fade.from + (fade.to - fade.from) * fraction;
+
+                    if fraction >= 1.0 {
+                        fade.callback
+                    } else {
+                        effects.fade = Some(fade);
+
+                        None
+                    }
+                }
+                None => None,
+            }
+        };
+
+        if let Some(callback) = callback {
+            let _: Val = sandbox::call(&callback, &())?;
+        }
+
+        Ok(())
+    }
+
+    /// Move the view for the shake and fill the overlay with the flash, fade & letterbox bars.
+    ///
+    /// Must be called before rendering.
+    pub fn apply(&self, render: &mut Render) {
+        let (x, y) = match self.shake {
+            Some(ref shake) => {
+                // Mix waves with unrelated frequencies so the pattern doesn't look repetitive
+                let time = self.time as f32;
+                let strength = shake.value * shake.remaining();
+
+                (
+                    strength * ((time * 47.0).sin() + (time * 73.1).sin()) / 2.0,
+                    strength * ((time * 53.3).cos() + (time * 67.9).sin()) / 2.0,
+                )
+            }
+            None => (0.0, 0.0),
+        };
+        render.set_camera_offset(x, y);
+
+        let overlay = render.overlay_mut();
+        overlay.clear();
+        if self.letterbox > 0.0 {
+            // The coordinates of the screen go from -1 to 1
+            let height = self.letterbox * 2.0;
+            overlay.push([-1.0, -1.0, 1.0, -1.0 + height], [0.0, 0.0, 0.0, 1.0]);
+            overlay.push([-1.0, 1.0 - height, 1.0, 1.0], [0.0, 0.0, 0.0, 1.0]);
+        }
+        if let Some(ref flash) = self.flash {
+            let [red, green, blue] = flash.value;
+            overlay.push(
+                [-1.0, -1.0, 1.0, 1.0],
+                [red, green, blue, flash.remaining()],
+            );
+        }
+        if self.fade_opacity > 0.0 {
+            let [red, green, blue] = self.fade_color;
+            overlay.push(
+                [-1.0, -1.0, 1.0, 1.0],
+                [red, green, blue, self.fade_opacity],
+            );
+        }
+    }
+
+    /// Shake the camera from a script, the intensity is the maximum distance in world units.
+    ///
+    /// A stronger shake replaces the one that's running.
+    ///
+    /// ```gamelisp
+    /// (shake-camera 10 0.4)
+    /// ```
+    fn shake_camera(&mut self, intensity: f32, duration: f64) {
+        let current = self
+            .shake
+            .as_ref()
+            .map_or(0.0, |shake| shake.value * shake.remaining());
+        if intensity >= current {
+            self.shake = Some(Fading {
+                value: intensity,
+                duration,
+                elapsed: 0.0,
+            });
+        }
+    }
+
+    /// Fill the screen with a color that fades out from a script.
+    ///
+    /// ```gamelisp
+    /// (flash-screen (arr 255 0 0) 0.15)
+    /// ```
+    fn flash_screen(&mut self, (red, green, blue): (u8, u8, u8), duration: f64) {
+        self.flash = Some(Fading {
+            value: [
+                red as f32 / 255.0,
+                green as f32 / 255.0,
+                blue as f32 / 255.0,
+            ],
+            duration,
+            elapsed: 0.0,
+        });
+    }
+
+    /// Cover the screen with a color, black by default, from a script.
+    ///
+    /// The screen stays covered until `fade-in` is called. The optional function is called when
+    /// the screen is fully covered.
+    ///
+    /// ```gamelisp
+    /// (fade-out 0.5 (fn () (switch-scene 'level-2) (fade-in 0.5)))
+    /// ```
+    fn fade_out(
+        &mut self,
+        duration: f64,
+        callback: Option<Root<GFn>>,
+        color: Option<(u8, u8, u8)>,
+    ) {
+        let (red, green, blue) = color.unwrap_or((0, 0, 0));
+        self.fade_color = [
+            red as f32 / 255.0,
+            green as f32 / 255.0,
+            blue as f32 / 255.0,
+        ];
+
+        self.start_fade(1.0, duration, callback);
+    }
+
+    /// Uncover the screen after `fade-out` from a script.
+    ///
+    /// The optional function is called when the screen is fully visible.
+    fn fade_in(&mut self, duration: f64, callback: Option<Root<GFn>>) {
+        self.start_fade(0.0, duration, callback);
+    }
+
+    /// Fade the overlay from the current opacity, replacing the running fade.
+    fn start_fade(&mut self, to: f32, duration: f64, callback: Option<Root<GFn>>) {
+        self.fade = Some(Fade {
+            from: self.fade_opacity,
+            to,
+            duration: duration.max(0.0),
+            elapsed: 0.0,
+            callback,
+        });
+    }
+
+    /// Whether a fade is running from a script.
+    fn is_fading(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    /// Slide cinematic bars in at the top & the bottom of the screen from a script.
+    ///
+    /// The size is the height of each bar as a fraction of the screen height, zero removes them.
+    /// Without a duration the bars are shown immediately.
+    ///
+    /// ```gamelisp
+    /// (letterbox 0.1 0.5)
+    /// (letterbox 0 0.5)
+    /// ```
+    fn letterbox(&mut self, size: f32, duration: Option<f32>) {
+        let size = size.max(0.0).min(0.5);
+        let speed = match duration {
+            Some(duration) if duration > 0.0 => (size - self.letterbox).abs() / duration,
+            _ => {
+                self.letterbox = size;
+
+                0.0
+            }
+        };
+
+        self.letterbox_target = (size, speed);
+    }
+
+    /// Move the camera to a position over time from a script.
+    ///
+    /// The move is linear without a curve, the optional zoom is reached at the end. A new move
+    /// starts from wherever the camera is, replacing the running one.
+    ///
+    /// ```gamelisp
+    /// (move-camera boss-x boss-y 2.0 (spring-curve 0.4 1) 1.5)
+    /// ```
+    fn move_camera(
+        &mut self,
+        x: f32,
+        y: f32,
+        duration: f64,
+        curve: Option<&Curve>,
+        zoom: Option<f32>,
+    ) {
+        let render = Render::borrow();
+        let current_zoom = render.camera_zoom();
+
+        self.camera_move = Some(CameraMove {
+            from: render.camera_pos(),
+            to: (x, y),
+            zoom: (current_zoom, zoom.unwrap_or(current_zoom)),
+            duration: duration.max(0.0),
+            elapsed: 0.0,
+            curve: curve.copied().unwrap_or_else(Curve::linear),
+        });
+    }
+
+    /// Whether the camera is moving from a script.
+    fn is_camera_moving(&self) -> bool {
+        self.camera_move.is_some()
+    }
+
+    /// Register the effects and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Effects::new());
+
+            glsp::bind_rfn("shake-camera", rfn!(Effects::shake_camera))?;
+            glsp::bind_rfn("flash-screen", rfn!(Effects::flash_screen))?;
+            glsp::bind_rfn("fade-out", rfn!(Effects::fade_out))?;
+            glsp::bind_rfn("fade-in", rfn!(Effects::fade_in))?;
+            glsp::bind_rfn("fading?", rfn!(Effects::is_fading))?;
+            glsp::bind_rfn("letterbox", rfn!(Effects::letterbox))?;
+            glsp::bind_rfn("move-camera", rfn!(Effects::move_camera))?;
+            glsp::bind_rfn("camera-moving?", rfn!(Effects::is_camera_moving))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// A vertex of an overlay quad.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct OverlayVertex {
+    /// The position in screen coordinates from -1 to 1.
+    pos: [f32; 2],
+    /// The color with the opacity.
+    color: [f32; 4],
+}
+
+/// Translucent quads drawn on top of the scene in screen coordinates.
+pub struct ScreenOverlay {
+    /// The rectangles as `[min_x, min_y, max_x, max_y]` with their colors, drawn in order.
+    quads: Vec<([f32; 4], [f32; 4])>,
+    /// The blending pipeline with the dynamic buffers, created when first used.
+    bindings: Option<(Pipeline, Bindings)>,
+    /// Whether the colors are converted to linear space for gamma correct blending.
+    linear: bool,
+}
+
+impl ScreenOverlay {
+    /// Create without any quads.
+    pub fn new() -> Self {
+        Self {
+            quads: vec![],
+            bindings: None,
+            linear: false,
+        }
+    }
+
+    /// Convert the colors of the quads to linear space, when the scene is gamma corrected.
+    pub fn set_linear(&mut self, linear: bool) {
+        self.linear = linear;
+    }
+
+    /// Remove all quads.
+    pub fn clear(&mut self) {
+        self.quads.clear();
+    }
+
+    /// Add a quad, the rectangle is `[min_x, min_y, max_x, max_y]` in screen coordinates from -1
+    /// to 1 and the color includes the opacity.
+    pub fn push(&mut self, rect: [f32; 4], mut color: [f32; 4]) {
+        if self.linear {
+            for channel in color.iter_mut().take(3) {
+                *channel = channel.powf(2.2);
+            }
+        }
+        if self.quads.len() < MAX_OVERLAY_QUADS {
+            self.quads.push((rect, color));
+        }
+    }
+
+    /// Draw the quads on top of everything in the current render pass.
+    pub fn draw(&mut self, ctx: &mut Context) {
+        if self.quads.is_empty() {
+            return;
+        }
+
+        let (pipeline, bindings) = self.bindings.get_or_insert_with(|| {
+            let shader = Shader::new(
+                ctx,
+                OVERLAY_VERTEX,
+                OVERLAY_FRAGMENT,
+                ShaderMeta {
+                    images: &[],
+                    uniforms: UniformBlockLayout { uniforms: &[] },
+                },
+            )
+            .expect("Building overlay shader failed");
+            let pipeline = Pipeline::with_params(
+                ctx,
+                &[BufferLayout::default()],
+                &[
+                    VertexAttribute::new("a_pos", VertexFormat::Float2),
+                    VertexAttribute::new("a_color", VertexFormat::Float4),
+                ],
+                shader,
+                PipelineParams {
+                    color_blend: Some(BlendState::new(
+                        Equation::Add,
+                        BlendFactor::Value(BlendValue::SourceAlpha),
+                        BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+                    )),
+                    ..Default::default()
+                },
+            );
+
+            let bindings = Bindings {
+                vertex_buffers: vec![Buffer::stream(
+                    ctx,
+                    BufferType::VertexBuffer,
+                    MAX_OVERLAY_QUADS * 4 * mem::size_of::<OverlayVertex>(),
+                )],
+                index_buffer: Buffer::stream(
+                    ctx,
+                    BufferType::IndexBuffer,
+                    MAX_OVERLAY_QUADS * 6 * mem::size_of::<u16>(),
+                ),
+                images: vec![],
+            };
+
+            (pipeline, bindings)
+        });
+
+        let mut vertices = Vec::with_capacity(self.quads.len() * 4);
+        let mut indices = Vec::with_capacity(self.quads.len() * 6);
+        for ([min_x, min_y, max_x, max_y], color) in self.quads.iter().copied() {
+            let first = vertices.len() as u16;
+            for pos in [
+                [min_x, min_y],
+                [max_x, min_y],
+                [max_x, max_y],
+                [min_x, max_y],
+            ]
+            .iter()
+            {
+                vertices.push(OverlayVertex { pos: *pos, color });
+            }
+            indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+        bindings.vertex_buffers[0].update(ctx, &vertices);
+        bindings.index_buffer.update(ctx, &indices);
+
+        ctx.apply_pipeline(pipeline);
+        ctx.apply_bindings(bindings);
+        ctx.draw(0, indices.len() as i32, 1);
+    }
+}
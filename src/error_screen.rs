@@ -0,0 +1,158 @@
+use crate::{debug, render::Render, shapes::Style};
+use anyhow::{anyhow, Result};
+use glsp::{lib, GError};
+use lyon::{math::rect, tessellation::basic_shapes};
+
+/// The height of a line of text in the panel in pixels.
+const LINE_HEIGHT: f32 = 16.0;
+/// The distance between the edges of the screen & the panel in pixels.
+const MARGIN: f32 = 24.0;
+/// The maximum amount of stack trace lines shown, the outermost calls are left out.
+const MAX_TRACE_LINES: usize = 24;
+/// The text of the button that reloads the script.
+const RELOAD_LABEL: &str = "[ Reload script (F5) ]";
+/// The width of the reload button in pixels.
+const RELOAD_WIDTH: f32 = 200.0;
+/// The color of the panel behind the text.
+const PANEL_COLOR: (u8, u8, u8) = (40, 0, 0);
+/// The color of the error message.
+const MESSAGE_COLOR: (u8, u8, u8) = (255, 120, 120);
+/// The color of the stack trace & the button.
+const TRACE_COLOR: (u8, u8, u8) = (220, 220, 220);
+
+/// An error raised by a script function.
+#[derive(Debug, Clone)]
+struct ScriptError {
+    /// The name of the function the engine called.
+    function: String,
+    /// The short description of the error.
+    message: String,
+    /// The GameLisp stack trace, innermost call last.
+    stack_trace: Option<String>,
+}
+
+lib! {
+/// A panel covering the game when a script function fails, until the script is reloaded.
+///
+/// While the panel is shown the scripts aren't called, so a broken game doesn't keep running
+/// with half of its state updated. Pressing F5 or clicking the button evaluates the main script
+/// again.
+pub struct ErrorScreen {
+    /// The error that's shown.
+    error: Option<ScriptError>,
+}
+}
+
+impl ErrorScreen {
+    /// Create without an error.
+    pub fn new() -> Self {
+        Self { error: None }
+    }
+
+    /// Show the panel for an error of a function called by the engine.
+    ///
+    /// Only the first error is kept, the later ones are often caused by it.
+    pub fn report(&mut self, function: &str, error: &GError) {
+        if self.error.is_some() {
+            return;
+        }
+
+        self.error = Some(ScriptError {
+            function: function.to_string(),
+            message: error.val().to_string(),
+            stack_trace: error.stack_trace().map(str::to_string),
+        });
+    }
+
+    /// Whether a script failed and the game is halted.
+    pub fn is_shown(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// Hide the panel, before the script is reloaded.
+    pub fn clear(&mut self) {
+        self.error = None;
+    }
+
+    /// The rectangle of the reload button in pixels, `x`, `y`, width & height.
+    fn reload_button(&self, render: &Render) -> [f32; 4] {
+        let (_, height) = render.screen_size();
+
+        [
+            MARGIN * 2.0,
+            height - MARGIN * 2.0 - LINE_HEIGHT,
+            RELOAD_WIDTH,
+            LINE_HEIGHT,
+        ]
+    }
+
+    /// Whether a position on the screen in pixels is on the reload button.
+    pub fn is_on_reload_button(&self, render: &Render, x: f32, y: f32) -> bool {
+        let [left, top, width, height] = self.reload_button(render);
+
+        self.is_shown() && x >= left && x <= left + width && y >= top && y <= top + height
+    }
+
+    /// Draw the panel with the error on top of everything if a script failed.
+    pub fn draw(&self, render: &mut Render) -> Result<()> {
+        let error = match self.error {
+            Some(ref error) => error,
+            None => return Ok(()),
+        };
+
+        let scale = render.world_units_per_pixel();
+        let line_height = LINE_HEIGHT * scale;
+        let (width, height) = render.screen_size();
+
+        let (left, top) = render.screen_to_world(MARGIN, MARGIN);
+        let (right, bottom) = render.screen_to_world(width - MARGIN, height - MARGIN);
+        let panel = rect(left, top, right - left, bottom - top);
+        debug::tessellate(render, |geometry| {
+            Style::new(Some(PANEL_COLOR), None).tessellate(
+                geometry,
+                |options, output| basic_shapes::fill_rectangle(&panel, options, output),
+                |_, _| unreachable!(),
+            )
+        })
+        .map_err(|err| anyhow!("{}", err))?;
+
+        let (x, mut y) = render.screen_to_world(MARGIN * 2.0, MARGIN * 2.0);
+        let mut line = |render: &mut Render, text: &str, color: (u8, u8, u8)| {
+            let result =
+                debug::draw_text(render, x, y, text, color, line_height, debug::DEFAULT_FONT);
+            y += line_height;
+
+            result
+        };
+
+        line(
+            render,
+            &format!("error in {}", error.function),
+            MESSAGE_COLOR,
+        )?;
+        for message_line in error.message.lines() {
+            line(render, message_line, MESSAGE_COLOR)?;
+        }
+        if let Some(ref stack_trace) = error.stack_trace {
+            line(render, "", TRACE_COLOR)?;
+
+            let lines: Vec<&str> = stack_trace.lines().collect();
+            let skipped = lines.len().saturating_sub(MAX_TRACE_LINES);
+            for trace_line in &lines[skipped..] {
+                line(render, trace_line, TRACE_COLOR)?;
+            }
+        }
+
+        let [button_x, button_y, _, _] = self.reload_button(render);
+        let (button_x, button_y) = render.screen_to_world(button_x, button_y);
+        debug::draw_text(
+            render,
+            button_x,
+            button_y,
+            RELOAD_LABEL,
+            TRACE_COLOR,
+            line_height,
+            debug::DEFAULT_FONT,
+        )
+    }
+}
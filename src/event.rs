@@ -0,0 +1,186 @@
+use crate::sandbox;
+use glsp::{lib, rdata, rfn, GFn, GResult, IntoVal, Root, Runtime, Sym, Val};
+use miniquad::KeyCode;
+use std::{collections::HashMap, mem};
+
+rdata! {
+/// A reference to a subscribed event handler, used to unsubscribe it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HandlerRef(u32);
+}
+
+/// A function subscribed to an event.
+#[derive(Debug)]
+struct Handler {
+    /// The identifier referenced by the scripts.
+    id: u32,
+    /// The function called with the payload of the event.
+    function: Root<GFn>,
+}
+
+lib! {
+/// A queue of named events between the engine & the scripts.
+///
+/// The engine emits these events, scripts can emit their own:
+///
+/// - `asset-loaded` & `asset-failed`, with the reference name of the asset.
+/// - `key-down` & `key-up`, with the name of the key as a symbol, e.g. `left-shift`.
+/// - `action-pressed` & `action-released`, with the name of the action bound with `bind-action`.
+/// - `action-rebound`, with `(action key)` when `rebind-action` bound the action to a key.
+/// - `touch`, with the same table as `engine:touch`.
+/// - `path-finished`, with the instance that reached the end of its path.
+/// - `animation-finished`, with the instance of the puppet that finished its animation.
+/// - `language-changed`, with the new language.
+/// - `clipboard`, with the text requested with `clipboard-get`.
+/// - `text-input-changed`, `text-input-submitted` & `text-input-cancelled`, with the value.
+/// - `mouse-delta`, with the distance the mouse moved as `(dx dy)` while the cursor is locked.
+/// - `dpi-changed`, with the new scale when the window moved to a monitor with another density.
+/// - `chunk-loaded` & `chunk-unloaded`, with the name of the chunk defined with `define-chunk`.
+///
+/// The queued events are dispatched once per update, before `engine:update`:
+///
+/// ```gamelisp
+/// (on-event 'key-down (fn (key)
+///   (when (== key 'space)
+///     (emit-event 'jump))))
+/// ```
+pub struct Events {
+    /// The events that haven't been dispatched yet with their payload.
+    queue: Vec<(Sym, Val)>,
+    /// The subscribed functions by event name.
+    handlers: HashMap<Sym, Vec<Handler>>,
+    /// The identifier of the next handler.
+    next_id: u32,
+}
+}
+
+impl Events {
+    /// Create without any events or handlers.
+    pub fn new() -> Self {
+        Self {
+            queue: vec![],
+            handlers: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Queue an event, it's dispatched during the next update.
+    pub fn emit(&mut self, event: Sym, payload: Val) {
+        self.queue.push((event, payload));
+    }
+
+    /// Queue an event from the engine.
+    ///
+    /// Must be called inside the GameLisp runtime.
+    pub fn emit_engine_event<P>(event: &str, payload: P) -> GResult<()>
+    where
+        P: IntoVal,
+    {
+        let event = glsp::sym(event)?;
+        let payload = payload.into_val()?;
+        Events::borrow_mut().emit(event, payload);
+
+        Ok(())
+    }
+
+    /// Queue the event of a pressed or released key with the name of the key.
+    ///
+    /// Must be called inside the GameLisp runtime.
+    pub fn emit_key_event(event: &str, key: &str) -> GResult<()> {
+        Self::emit_engine_event(event, glsp::sym(key)?)
+    }
+
+    /// Call the subscribed functions of all queued events.
+    ///
+    /// Events emitted by the functions are dispatched during the next update. Must be called
+    /// inside the GameLisp runtime.
+    pub fn dispatch() -> GResult<()> {
+        // Don't hold the borrow while calling the scripts, they might emit new events
+        let queue = mem::take(&mut Events::borrow_mut().queue);
+
+        for (event, payload) in queue {
+            let functions: Vec<Root<GFn>> = match Events::borrow().handlers.get(&event) {
+                Some(handlers) => handlers
+                    .iter()
+                    .map(|handler| handler.function.clone())
+                    .collect(),
+                None => continue,
+            };
+
+            for function in functions {
+                let _: Val = sandbox::call(&function, (payload.clone(),))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Subscribe a function to an event from a script.
+    ///
+    /// ```gamelisp
+    /// (let handler (on-event 'collision (fn (other) (prn other))))
+    /// ```
+    fn on_event(&mut self, event: Sym, function: Root<GFn>) -> HandlerRef {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        self.handlers
+            .entry(event)
+            .or_default()
+            .push(Handler { id, function });
+
+        HandlerRef(id)
+    }
+
+    /// Unsubscribe a function from a script, returns whether it was subscribed.
+    ///
+    /// ```gamelisp
+    /// (off-event handler)
+    /// ```
+    fn off_event(&mut self, handler: &HandlerRef) -> bool {
+        let mut removed = false;
+        for handlers in self.handlers.values_mut() {
+            let count = handlers.len();
+            handlers.retain(|h| h.id != handler.0);
+
+            removed |= handlers.len() != count;
+        }
+
+        removed
+    }
+
+    /// Queue a custom event from a script, the payload is optional.
+    ///
+    /// ```gamelisp
+    /// (emit-event 'collision enemy)
+    /// ```
+    fn emit_event(&mut self, event: Sym, payload: Option<Val>) {
+        self.emit(event, payload.unwrap_or(Val::Nil));
+    }
+
+    /// Register the events and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Events::new());
+
+            glsp::bind_rfn("on-event", rfn!(Events::on_event))?;
+            glsp::bind_rfn("off-event", rfn!(Events::off_event))?;
+            glsp::bind_rfn("emit-event", rfn!(Events::emit_event))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// The name of a key as used in GameLisp, e.g. `LeftShift` becomes `left-shift`.
+pub fn key_name(keycode: KeyCode) -> String {
+    let mut name = String::new();
+    for (index, character) in format!("{:?}", keycode).chars().enumerate() {
+        if character.is_uppercase() && index > 0 {
+            name.push('-');
+        }
+        name.extend(character.to_lowercase());
+    }
+
+    name
+}
@@ -0,0 +1,377 @@
+use crate::{
+    assets::Assets,
+    event::Events,
+    render::{Instance, InstanceRef, Mesh, Render},
+};
+use glsp::{bail, lib, rfn, GResult, Runtime, Sym, Val};
+use std::collections::HashMap;
+
+/// What happens when a clip reaches its last frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PlayMode {
+    /// Stop on the last frame.
+    Once,
+    /// Start over at the first frame.
+    Loop,
+    /// Play backwards to the first frame and forwards again.
+    PingPong,
+}
+
+/// A named list of frames with how long each frame is shown.
+#[derive(Debug, Clone)]
+struct Clip {
+    /// The mesh of every frame.
+    frames: Vec<Mesh>,
+    /// How many seconds every frame is shown.
+    durations: Vec<f32>,
+}
+
+/// The state of a flipbook.
+#[derive(Debug)]
+struct Book {
+    /// The instance of the frame that's shown, attached to the node of the flipbook.
+    shown: Option<InstanceRef>,
+    /// The clip that's playing or paused.
+    clip: Option<String>,
+    /// The index of the frame that's shown.
+    frame: usize,
+    /// How many seconds the frame has been shown.
+    time: f32,
+    /// What happens at the end of the clip.
+    mode: PlayMode,
+    /// How fast the clip is played.
+    speed: f32,
+    /// Whether the frames go backwards, when ping-ponging.
+    reversed: bool,
+    /// Whether the frames advance.
+    playing: bool,
+}
+
+impl Book {
+    /// Move to the next frame, returns whether a clip that plays once reached its end.
+    fn advance(&mut self, frame_count: usize) -> bool {
+        match self.mode {
+            PlayMode::Once if self.frame + 1 >= frame_count => {
+                self.playing = false;
+
+                return true;
+            }
+            PlayMode::Loop => self.frame = (self.frame + 1) % frame_count,
+            PlayMode::PingPong if frame_count > 1 => {
+                if (self.reversed && self.frame == 0)
+                    || (!self.reversed && self.frame + 1 >= frame_count)
+                {
+                    self.reversed = !self.reversed;
+                }
+                if self.reversed {
+                    self.frame -= 1;
+                } else {
+                    self.frame += 1;
+                }
+            }
+            PlayMode::PingPong => (),
+            PlayMode::Once => self.frame += 1,
+        }
+
+        false
+    }
+}
+
+lib! {
+/// Flipbook animations, showing one mesh after the other like the frames of a cartoon.
+///
+/// The frames are loaded SVG assets grouped in named clips. A flipbook is a node that's moved
+/// like any other instance, the mesh of the current frame is attached to it. When a clip that's
+/// played once reaches its last frame an `animation-finished` event is emitted with the node.
+///
+/// ```gamelisp
+/// (define-flipbook-clip "walk" (arr "walk-1" "walk-2" "walk-3") 0.1)
+/// (define-flipbook-clip "blink" (arr "open" "closed" "open") (arr 2.0 0.1 0.5))
+///
+/// (let player (create-flipbook 100 200))
+/// (play-flipbook player "walk" 'ping-pong)
+/// (= [player 'x] 150)
+/// ```
+pub struct Flipbooks {
+    /// The clips by name.
+    clips: HashMap<String, Clip>,
+    /// The state of every flipbook by its node.
+    books: HashMap<InstanceRef, Book>,
+}
+}
+
+impl Flipbooks {
+    /// Create without any clips.
+    pub fn new() -> Self {
+        Self {
+            clips: HashMap::new(),
+            books: HashMap::new(),
+        }
+    }
+
+    /// Advance the frames of all playing flipbooks.
+    ///
+    /// Must be called inside the GameLisp runtime.
+    pub fn update(&mut self, delta: f64, render: &mut Render) -> GResult<()> {
+        // The flipbooks of which the node was removed are removed as well
+        let removed: Vec<InstanceRef> = self
+            .books
+            .keys()
+            .filter(|node| render.instance(**node).is_none())
+            .copied()
+            .collect();
+        for node in removed {
+            if let Some(shown) = self.books.remove(&node).and_then(|book| book.shown) {
+                render.remove_instance(shown);
+            }
+        }
+
+        let mut finished = vec![];
+        for (node, book) in self.books.iter_mut() {
+            let clip = match book.clip {
+                Some(ref clip) if book.playing => &self.clips[clip],
+                _ => continue,
+            };
+
+            let frame = book.frame;
+            book.time += book.speed * delta as f32;
+            while book.playing && book.time >= clip.durations[book.frame] {
+                book.time -= clip.durations[book.frame];
+                if book.advance(clip.frames.len()) {
+                    finished.push(*node);
+                }
+            }
+
+            if book.frame != frame {
+                show(render, *node, book, clip.frames[book.frame])?;
+            }
+        }
+
+        for node in finished {
+            Events::emit_engine_event("animation-finished", node)?;
+        }
+
+        Ok(())
+    }
+
+    /// Define a clip from a script, the frames are the names of loaded SVG assets.
+    ///
+    /// The duration in seconds is either a single number for all frames or an array with a
+    /// number for every frame.
+    ///
+    /// ```gamelisp
+    /// (define-flipbook-clip "explode" (arr "boom-1" "boom-2" "boom-3") (arr 0.05 0.05 0.2))
+    /// ```
+    fn define_flipbook_clip(
+        &mut self,
+        name: &str,
+        frames: Vec<String>,
+        durations: Val,
+    ) -> GResult<()> {
+        if frames.is_empty() {
+            bail!("flipbook clip \"{}\" doesn't have any frames", name);
+        }
+
+        let durations: Vec<f32> = match durations {
+            Val::Int(_) | Val::Flo(_) => vec![number(&durations)?; frames.len()],
+            Val::Arr(ref arr) if arr.len() == frames.len() => arr
+                .iter()
+                .map(|duration| number(&duration))
+                .collect::<GResult<_>>()?,
+            Val::Arr(ref arr) => bail!(
+                "flipbook clip \"{}\" has {} frames but {} durations",
+                name,
+                frames.len(),
+                arr.len()
+            ),
+            _ => bail!("flipbook durations must be a number or an array of numbers"),
+        };
+        if durations.iter().any(|duration| *duration <= 0.0) {
+            bail!("flipbook clip \"{}\" has a frame without a duration", name);
+        }
+
+        let assets = Assets::borrow();
+        let frames = frames
+            .iter()
+            .map(|frame| match assets.mesh(frame) {
+                Some(mesh) => Ok(mesh),
+                None => bail!("SVG asset \"{}\" isn't loaded", frame),
+            })
+            .collect::<GResult<_>>()?;
+
+        self.clips
+            .insert(name.to_string(), Clip { frames, durations });
+
+        Ok(())
+    }
+
+    /// Create a flipbook from a script, returns the node the frames are attached to.
+    ///
+    /// Nothing is shown until a clip is played.
+    fn create_flipbook(&mut self, x: f32, y: f32) -> InstanceRef {
+        let node = Render::borrow_mut().add_transform_node(x, y);
+        self.books.insert(
+            node,
+            Book {
+                shown: None,
+                clip: None,
+                frame: 0,
+                time: 0.0,
+                mode: PlayMode::Loop,
+                speed: 1.0,
+                reversed: false,
+                playing: false,
+            },
+        );
+
+        node
+    }
+
+    /// The state of a flipbook, failing when it isn't one.
+    fn book(&mut self, flipbook: &InstanceRef) -> GResult<&mut Book> {
+        match self.books.get_mut(flipbook) {
+            Some(book) => Ok(book),
+            None => bail!("instance isn't a flipbook"),
+        }
+    }
+
+    /// Play a clip from the first frame with a flipbook from a script.
+    ///
+    /// The mode is `once`, `loop` or `ping-pong`, `loop` by default. The speed defaults to `1`.
+    ///
+    /// ```gamelisp
+    /// (play-flipbook player "jump" 'once)
+    /// (play-flipbook player "run" 'loop 1.5)
+    /// ```
+    fn play_flipbook(
+        &mut self,
+        flipbook: &InstanceRef,
+        clip: &str,
+        mode: Option<Sym>,
+        speed: Option<f32>,
+    ) -> GResult<()> {
+        let mode = match mode.as_ref().map(|mode| mode.name()).as_deref() {
+            None | Some("loop") => PlayMode::Loop,
+            Some("once") => PlayMode::Once,
+            Some("ping-pong") => PlayMode::PingPong,
+            Some(mode) => bail!("unknown flipbook mode {}", mode),
+        };
+        let speed = speed.unwrap_or(1.0);
+        if speed <= 0.0 {
+            bail!("flipbook speed must be positive, found {}", speed);
+        }
+        let first = match self.clips.get(clip) {
+            Some(found) => found.frames[0],
+            None => bail!("unknown flipbook clip \"{}\"", clip),
+        };
+
+        let book = self.book(flipbook)?;
+        book.clip = Some(clip.to_string());
+        book.frame = 0;
+        book.time = 0.0;
+        book.mode = mode;
+        book.speed = speed;
+        book.reversed = false;
+        book.playing = true;
+
+        show(&mut Render::borrow_mut(), *flipbook, book, first)
+    }
+
+    /// Stop the frames of a flipbook from a script, the current frame stays visible.
+    fn stop_flipbook(&mut self, flipbook: &InstanceRef) -> GResult<()> {
+        self.book(flipbook)?.playing = false;
+
+        Ok(())
+    }
+
+    /// Show a frame of the clip of a flipbook from a script, stopping it.
+    ///
+    /// ```gamelisp
+    /// (set-flipbook-frame player 0)
+    /// ```
+    fn set_flipbook_frame(&mut self, flipbook: &InstanceRef, frame: usize) -> GResult<()> {
+        let clip = match self.books.get(flipbook).and_then(|book| book.clip.as_ref()) {
+            Some(clip) => self.clips[clip].clone(),
+            None => bail!("flipbook isn't showing a clip"),
+        };
+        if frame >= clip.frames.len() {
+            bail!("flipbook clip only has {} frames", clip.frames.len());
+        }
+
+        let book = self.book(flipbook)?;
+        book.frame = frame;
+        book.time = 0.0;
+        book.playing = false;
+
+        show(
+            &mut Render::borrow_mut(),
+            *flipbook,
+            book,
+            clip.frames[frame],
+        )
+    }
+
+    /// Get the index of the frame a flipbook shows from a script.
+    fn flipbook_frame(&mut self, flipbook: &InstanceRef) -> GResult<usize> {
+        Ok(self.book(flipbook)?.frame)
+    }
+
+    /// Check from a script whether the frames of a flipbook are advancing.
+    fn is_playing(&mut self, flipbook: &InstanceRef) -> GResult<bool> {
+        Ok(self.book(flipbook)?.playing)
+    }
+
+    /// Remove a flipbook and its frame from a script.
+    fn remove_flipbook(&mut self, flipbook: &InstanceRef) {
+        let mut render = Render::borrow_mut();
+        if let Some(shown) = self.books.remove(flipbook).and_then(|book| book.shown) {
+            render.remove_instance(shown);
+        }
+        render.remove_instance(*flipbook);
+    }
+
+    /// Register the flipbooks and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Flipbooks::new());
+
+            glsp::bind_rfn(
+                "define-flipbook-clip",
+                rfn!(Flipbooks::define_flipbook_clip),
+            )?;
+            glsp::bind_rfn("create-flipbook", rfn!(Flipbooks::create_flipbook))?;
+            glsp::bind_rfn("play-flipbook", rfn!(Flipbooks::play_flipbook))?;
+            glsp::bind_rfn("stop-flipbook", rfn!(Flipbooks::stop_flipbook))?;
+            glsp::bind_rfn("set-flipbook-frame", rfn!(Flipbooks::set_flipbook_frame))?;
+            glsp::bind_rfn("flipbook-frame", rfn!(Flipbooks::flipbook_frame))?;
+            glsp::bind_rfn("flipbook-playing?", rfn!(Flipbooks::is_playing))?;
+            glsp::bind_rfn("remove-flipbook", rfn!(Flipbooks::remove_flipbook))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// Replace the frame a flipbook shows with an instance of a mesh attached to its node.
+fn show(render: &mut Render, node: InstanceRef, book: &mut Book, mesh: Mesh) -> GResult<()> {
+    if let Some(shown) = book.shown.take() {
+        render.remove_instance(shown);
+    }
+
+    let instance = render.add_instance(mesh, Instance::new(0.0, 0.0));
+    book.shown = Some(instance);
+    if let Err(err) = render.set_parent(instance, node) {
+        bail!("attaching flipbook frame failed: {}", err);
+    }
+
+    Ok(())
+}
+
+/// Convert a number passed by a script to seconds.
+fn number(val: &Val) -> GResult<f32> {
+    match val {
+        Val::Int(i) => Ok(*i as f32),
+        Val::Flo(f) => Ok(*f),
+        _ => bail!("flipbook durations must be numbers, found {}", val),
+    }
+}
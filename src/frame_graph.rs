@@ -0,0 +1,365 @@
+use anyhow::{anyhow, bail, Result};
+
+/// A texture a pass reads from or draws to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// The texture the lights are added to.
+    LightMap,
+    /// The texture the scene is drawn to before post-processing, the output when there's none.
+    Scene,
+    /// The screen, or the texture a frame is captured to.
+    Output,
+}
+
+/// What a pass draws, executed by the render.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PassKind {
+    /// The lights added to the light map.
+    Lights,
+    /// The lit meshes of the main camera.
+    World,
+    /// The light map multiplied with the scene drawn so far.
+    Lighting,
+    /// The unlit meshes of the main camera drawn over the lights.
+    Ui,
+    /// The cameras of the viewports drawn over the scene.
+    Viewports,
+    /// The screen effects drawn over everything.
+    Overlay,
+    /// The geometry of the debug functions.
+    Debug,
+    /// The fullscreen passes applied to the scene.
+    PostProcessing,
+}
+
+impl PassKind {
+    /// Whether the pass begins & ends the render passes of its targets itself.
+    ///
+    /// The other passes are drawn inside a render pass opened for their output.
+    pub fn owns_render_pass(self) -> bool {
+        matches!(self, PassKind::Lights | PassKind::PostProcessing)
+    }
+}
+
+/// A named step of drawing a frame.
+#[derive(Debug, Clone)]
+pub struct Pass {
+    /// The name scripts refer to the pass with.
+    pub name: &'static str,
+    /// What the pass draws.
+    pub kind: PassKind,
+    /// The targets that must be drawn before the pass runs.
+    pub inputs: Vec<Target>,
+    /// The target the pass draws to.
+    pub output: Target,
+    /// Whether the pass is executed.
+    ///
+    /// A pass with an input that no other enabled pass draws to is skipped as well, like
+    /// `lighting` when `lights` is disabled.
+    pub enabled: bool,
+}
+
+impl Pass {
+    /// Create an enabled pass.
+    pub fn new(name: &'static str, kind: PassKind, inputs: &[Target], output: Target) -> Self {
+        Self {
+            name,
+            kind,
+            inputs: inputs.to_vec(),
+            output,
+            enabled: true,
+        }
+    }
+}
+
+/// The passes drawing a frame & the order they run in.
+///
+/// Passes run in the order they are added, except that a pass always runs after every pass
+/// drawing to one of its inputs. A pass is skipped when none of the passes that run draw to one of
+/// its inputs, so it never reads a target that's stale from an earlier frame. Consecutive passes
+/// with the same output share a render pass, which is cleared by the first pass drawing to it
+/// during the frame.
+#[derive(Debug, Clone)]
+pub struct FrameGraph {
+    /// All passes in the order they're added.
+    passes: Vec<Pass>,
+    /// The indices of the passes that run in the order they run, `None` when it must be sorted
+    /// again.
+    order: Option<Vec<usize>>,
+}
+
+impl FrameGraph {
+    /// Create with the builtin passes of the render.
+    pub fn new() -> Self {
+        let mut graph = Self {
+            passes: Vec::new(),
+            order: None,
+        };
+        graph.add_pass(Pass::new("lights", PassKind::Lights, &[], Target::LightMap));
+        graph.add_pass(Pass::new("world", PassKind::World, &[], Target::Scene));
+        graph.add_pass(Pass::new(
+            "lighting",
+            PassKind::Lighting,
+            &[Target::LightMap],
+            Target::Scene,
+        ));
+        graph.add_pass(Pass::new("ui", PassKind::Ui, &[], Target::Scene));
+        graph.add_pass(Pass::new(
+            "viewports",
+            PassKind::Viewports,
+            &[],
+            Target::Scene,
+        ));
+        graph.add_pass(Pass::new("overlay", PassKind::Overlay, &[], Target::Scene));
+        graph.add_pass(Pass::new("debug", PassKind::Debug, &[], Target::Scene));
+        graph.add_pass(Pass::new(
+            "post-processing",
+            PassKind::PostProcessing,
+            &[Target::Scene],
+            Target::Output,
+        ));
+
+        graph
+    }
+
+    /// Add a pass after the existing ones, the name must be unique.
+    pub fn add_pass(&mut self, pass: Pass) {
+        debug_assert!(self.pass(pass.name).is_none());
+
+        self.passes.push(pass);
+        self.order = None;
+    }
+
+    /// A pass by its name.
+    pub fn pass(&self, name: &str) -> Option<&Pass> {
+        self.passes.iter().find(|pass| pass.name == name)
+    }
+
+    /// Set whether a pass is executed.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<()> {
+        let pass = self
+            .passes
+            .iter_mut()
+            .find(|pass| pass.name == name)
+            .ok_or_else(|| anyhow!("render pass \"{}\" doesn't exist", name))?;
+        pass.enabled = enabled;
+        self.order = None;
+
+        Ok(())
+    }
+
+    /// Move a pass to run directly before another one when their inputs allow it.
+    pub fn move_before(&mut self, name: &str, other: &str) -> Result<()> {
+        let index = self.index(name)?;
+        let pass = self.passes.remove(index);
+        let other_index = match self.index(other) {
+            Ok(other_index) => other_index,
+            Err(err) => {
+                self.passes.insert(index, pass);
+
+                return Err(err);
+            }
+        };
+        self.passes.insert(other_index, pass);
+        self.order = None;
+
+        Ok(())
+    }
+
+    /// The passes that run in the order they run.
+    ///
+    /// Fails when the inputs of the passes depend on each other.
+    pub fn sorted(&mut self) -> Result<Vec<&Pass>> {
+        if self.order.is_none() {
+            self.order = Some(self.sort()?);
+        }
+
+        let passes = &self.passes;
+
+        Ok(self
+            .order
+            .iter()
+            .flatten()
+            .map(|index| &passes[*index])
+            .collect())
+    }
+
+    /// Sort the passes that run so every pass runs after the passes drawing to its inputs, keeping
+    /// the order they're added in otherwise.
+    fn sort(&self) -> Result<Vec<usize>> {
+        let mut enabled: Vec<usize> = (0..self.passes.len())
+            .filter(|index| self.passes[*index].enabled)
+            .collect();
+
+        // Skip the passes missing an input, until the passes drawing to every input are left
+        while let Some(missing) = enabled.iter().position(|index| {
+            self.passes[*index].inputs.iter().any(|input| {
+                enabled
+                    .iter()
+                    .all(|other| other == index || self.passes[*other].output != *input)
+            })
+        }) {
+            enabled.remove(missing);
+        }
+
+        let mut order = Vec::with_capacity(enabled.len());
+        while order.len() < enabled.len() {
+            // Take the first pass whose inputs are all drawn
+            let next = enabled.iter().copied().find(|index| {
+                !order.contains(index)
+                    && self.passes[*index].inputs.iter().all(|input| {
+                        enabled.iter().all(|other| {
+                            other == index
+                                || order.contains(other)
+                                || self.passes[*other].output != *input
+                        })
+                    })
+            });
+
+            match next {
+                Some(next) => order.push(next),
+                None => bail!("the inputs of the render passes depend on each other"),
+            }
+        }
+
+        Ok(order)
+    }
+
+    /// The index of a pass by its name.
+    fn index(&self, name: &str) -> Result<usize> {
+        self.passes
+            .iter()
+            .position(|pass| pass.name == name)
+            .ok_or_else(|| anyhow!("render pass \"{}\" doesn't exist", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The names of the passes that run in the order they run.
+    fn order(graph: &mut FrameGraph) -> Vec<&'static str> {
+        graph
+            .sorted()
+            .unwrap()
+            .iter()
+            .map(|pass| pass.name)
+            .collect()
+    }
+
+    #[test]
+    fn builtin_passes_run_in_order() {
+        assert_eq!(
+            order(&mut FrameGraph::new()),
+            [
+                "lights",
+                "world",
+                "lighting",
+                "ui",
+                "viewports",
+                "overlay",
+                "debug",
+                "post-processing"
+            ]
+        );
+    }
+
+    #[test]
+    fn passes_run_after_their_inputs() {
+        let mut graph = FrameGraph::new();
+        graph.move_before("lighting", "lights").unwrap();
+        graph.move_before("post-processing", "world").unwrap();
+
+        let order = order(&mut graph);
+        let position = |name| order.iter().position(|pass| *pass == name).unwrap();
+        assert!(position("lights") < position("lighting"));
+        assert_eq!(order.last(), Some(&"post-processing"));
+    }
+
+    #[test]
+    fn moved_passes_run_earlier() {
+        let mut graph = FrameGraph::new();
+        graph.move_before("ui", "world").unwrap();
+
+        assert_eq!(&order(&mut graph)[..3], ["lights", "ui", "world"]);
+    }
+
+    #[test]
+    fn moving_unknown_passes_fails() {
+        let mut graph = FrameGraph::new();
+        assert!(graph.move_before("bloom", "world").is_err());
+        assert!(graph.move_before("world", "bloom").is_err());
+
+        // The passes aren't changed by a failed move
+        assert_eq!(order(&mut graph), order(&mut FrameGraph::new()));
+    }
+
+    #[test]
+    fn disabled_passes_are_skipped() {
+        let mut graph = FrameGraph::new();
+        graph.set_enabled("debug", false).unwrap();
+
+        assert!(!order(&mut graph).contains(&"debug"));
+        assert!(graph.set_enabled("bloom", false).is_err());
+    }
+
+    #[test]
+    fn passes_without_producers_are_skipped() {
+        let mut graph = FrameGraph::new();
+        graph.set_enabled("lights", false).unwrap();
+
+        let order = order(&mut graph);
+        assert!(!order.contains(&"lights"));
+        assert!(!order.contains(&"lighting"));
+        assert!(order.contains(&"world"));
+
+        // Enabling the producer again runs the pass reading its output
+        graph.set_enabled("lights", true).unwrap();
+        assert!(graph
+            .sorted()
+            .unwrap()
+            .iter()
+            .any(|pass| pass.name == "lighting"));
+    }
+
+    #[test]
+    fn skipped_passes_skip_their_consumers() {
+        let mut graph = FrameGraph::new();
+        graph.add_pass(Pass::new(
+            "shadows",
+            PassKind::Lighting,
+            &[Target::Scene],
+            Target::LightMap,
+        ));
+        for pass in &["world", "lighting", "ui", "viewports", "overlay", "debug"] {
+            graph.set_enabled(pass, false).unwrap();
+        }
+
+        // Without a pass drawing the scene the shadows aren't drawn, so neither is the output
+        assert_eq!(order(&mut graph), ["lights"]);
+    }
+
+    #[test]
+    fn dependency_cycles_fail() {
+        let mut graph = FrameGraph::new();
+        graph.add_pass(Pass::new(
+            "reflections",
+            PassKind::World,
+            &[Target::LightMap],
+            Target::Scene,
+        ));
+        graph.add_pass(Pass::new(
+            "bounce",
+            PassKind::Lights,
+            &[Target::Scene],
+            Target::LightMap,
+        ));
+
+        assert!(graph.sorted().is_err());
+
+        // Breaking the cycle sorts the passes again
+        graph.set_enabled("bounce", false).unwrap();
+        assert!(graph.sorted().is_ok());
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{
+    action::Actions,
+    assets::Assets,
+    render::{Mesh, Render},
+    timer::Timers,
+    window::Window,
+};
+use anyhow::Result;
+
+/// A game written in Rust instead of GameLisp, registered with `Clog::game`.
+///
+/// The functions are called where the scripts' `engine:update` & `engine:render` would be,
+/// before them when a main script is also set. Without a main script the scripts' functions are
+/// optional. Meshes & instances are managed through `Engine::render` like the scripts do.
+///
+/// ```rust,no_run
+/// use clogs::{Clog, Engine, Game, Instance, InstanceRef};
+///
+/// #[derive(Default)]
+/// struct Spinner {
+///     ship: Option<InstanceRef>,
+/// }
+///
+/// impl Game for Spinner {
+///     fn init(&mut self, engine: &mut Engine) -> anyhow::Result<()> {
+///         let mesh = engine.mesh("ship").ok_or_else(|| anyhow::anyhow!("ship isn't loaded"))?;
+///         self.ship = Some(engine.render().add_instance(mesh, Instance::new(0.0, 0.0)));
+///
+///         Ok(())
+///     }
+///
+///     fn update(&mut self, engine: &mut Engine) -> anyhow::Result<()> {
+///         let delta = engine.delta() as f32;
+///         if let Some(ship) = self.ship.and_then(|ship| engine.render().instance_mut(ship)) {
+///             ship.set_rotation(ship.rotation() + delta);
+///         }
+///
+///         Ok(())
+///     }
+/// }
+///
+/// # fn main() -> anyhow::Result<()> {
+/// Clog::new("Spinner")
+///     .load_svg(
+///         "ship",
+///         r#"<svg xmlns="http://www.w3.org/2000/svg"><path d="M0,-10 L8,10 L-8,10 Z"/></svg>"#,
+///     )
+///     .game(Spinner::default())
+///     .start();
+/// # Ok(())
+/// # }
+/// ```
+pub trait Game {
+    /// Called once when the assets queued before starting are loaded, before the first update.
+    fn init(&mut self, _engine: &mut Engine) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called every update, unless the game is paused.
+    fn update(&mut self, engine: &mut Engine) -> Result<()>;
+
+    /// Called every frame before everything is drawn.
+    fn render(&mut self, _engine: &mut Engine) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once when the game is closed.
+    fn shutdown(&mut self) {}
+}
+
+/// Access to the engine for a game written in Rust.
+pub struct Engine<'a> {
+    render: &'a mut Render,
+    assets: &'a mut Assets,
+    delta: f64,
+}
+
+impl<'a> Engine<'a> {
+    /// Borrow the engine, must be called inside the GameLisp runtime.
+    pub(crate) fn run<F>(delta: f64, function: F) -> Result<()>
+    where
+        F: FnOnce(&mut Engine) -> Result<()>,
+    {
+        let mut render = Render::borrow_mut();
+        let mut assets = Assets::borrow_mut();
+        let mut engine = Engine {
+            render: &mut render,
+            assets: &mut assets,
+            delta,
+        };
+
+        function(&mut engine)
+    }
+
+    /// The renderer for adding & changing meshes, instances & the camera.
+    pub fn render(&mut self) -> &mut Render {
+        self.render
+    }
+
+    /// The assets for loading & looking up meshes, fonts & textures.
+    pub fn assets(&mut self) -> &mut Assets {
+        self.assets
+    }
+
+    /// The mesh of a loaded asset by its reference name.
+    pub fn mesh(&self, reference_name: &str) -> Option<Mesh> {
+        self.assets.mesh(reference_name)
+    }
+
+    /// The seconds of game time that passed since the previous update.
+    pub fn delta(&self) -> f64 {
+        self.delta
+    }
+
+    /// The seconds of game time since the game started.
+    pub fn time(&self) -> f64 {
+        Timers::borrow().time()
+    }
+
+    /// Whether a key is held, with the name used by the scripts, e.g. `left-shift`.
+    pub fn is_key_down(&self, key: &str) -> bool {
+        Actions::borrow().is_key_held(key)
+    }
+
+    /// Close the game after the current frame.
+    pub fn quit(&mut self) {
+        Window::borrow_mut().quit();
+    }
+}
@@ -0,0 +1,205 @@
+use usvg::{NodeKind, PathData, SpreadMethod, Stop, Transform, TransformFromBBox, Units};
+
+/// The shape of a gradient in its own coordinate system.
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    /// Colors change along the line between two points.
+    Linear { start: [f64; 2], end: [f64; 2] },
+    /// Colors change in circles growing from the focal point to the outer circle.
+    Radial {
+        center: [f64; 2],
+        focal: [f64; 2],
+        radius: f64,
+    },
+}
+
+/// A linear or radial SVG gradient that can be evaluated at any position.
+///
+/// Gradients are evaluated for every vertex, so stops between the vertices of a path are blended
+/// linearly.
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    /// The shape in the coordinate system of the gradient.
+    shape: Shape,
+    /// Converts mesh positions to the coordinate system of the gradient.
+    inverse: Transform,
+    /// What happens outside of the gradient.
+    spread: SpreadMethod,
+    /// The offsets with the RGBA colors, sorted by offset.
+    stops: Vec<(f64, [f32; 4])>,
+}
+
+impl Gradient {
+    /// Convert the gradient definition a path links to.
+    ///
+    /// The transform converts the coordinates of the path to mesh positions. Returns `None` when
+    /// the node isn't a gradient or it can't be drawn.
+    pub fn from_node(
+        kind: &NodeKind,
+        data: &PathData,
+        transform: Transform,
+        opacity: f32,
+    ) -> Option<Self> {
+        let (shape, base) = match kind {
+            NodeKind::LinearGradient(gradient) => (
+                Shape::Linear {
+                    start: [gradient.x1, gradient.y1],
+                    end: [gradient.x2, gradient.y2],
+                },
+                &gradient.base,
+            ),
+            NodeKind::RadialGradient(gradient) => (
+                Shape::Radial {
+                    center: [gradient.cx, gradient.cy],
+                    focal: [gradient.fx, gradient.fy],
+                    radius: gradient.r.value(),
+                },
+                &gradient.base,
+            ),
+            _ => return None,
+        };
+        if base.stops.is_empty() {
+            return None;
+        }
+
+        // Combine the transformations from the gradient to the mesh
+        let mut to_mesh = transform;
+        if base.units == Units::ObjectBoundingBox {
+            // Paths without an area can't have a gradient relative to their size
+            to_mesh.append(&Transform::from_bbox(data.bbox()?));
+        }
+        to_mesh.append(&base.transform);
+
+        Some(Self {
+            shape,
+            inverse: invert(&to_mesh)?,
+            spread: base.spread_method,
+            stops: base
+                .stops
+                .iter()
+                .map(|stop| (stop.offset.value(), stop_color(stop, opacity)))
+                .collect(),
+        })
+    }
+
+    /// The RGBA color at a mesh position.
+    pub fn color_at(&self, x: f32, y: f32) -> [f32; 4] {
+        let (x, y) = self.inverse.apply(x as f64, y as f64);
+        let offset = self.spread(self.offset(x, y));
+
+        // Blend the colors of the stops around the offset
+        let (first_offset, first_color) = self.stops[0];
+        if offset <= first_offset {
+            return first_color;
+        }
+        for pair in self.stops.windows(2) {
+            let ((start, from), (end, to)) = (pair[0], pair[1]);
+            if offset <= end {
+                if end <= start {
+                    return to;
+                }
+
+                let fraction = ((offset - start) / (end - start)) as f32;
+                return [
+                    from[0] + (to[0] - from[0]) * fraction,
+                    from[1] + (to[1] - from[1]) * fraction,
+                    from[2] + (to[2] - from[2]) * fraction,
+                    from[3] + (to[3] - from[3]) * fraction,
+                ];
+            }
+        }
+
+        self.stops[self.stops.len() - 1].1
+    }
+
+    /// Where a position in the coordinate system of the gradient is between the first & last
+    /// stop, before it's spread.
+    fn offset(&self, x: f64, y: f64) -> f64 {
+        match self.shape {
+            Shape::Linear { start, end } => {
+                let (dx, dy) = (end[0] - start[0], end[1] - start[1]);
+                let length_squared = dx * dx + dy * dy;
+                if length_squared == 0.0 {
+                    return 1.0;
+                }
+
+                // Project the position on the line
+                ((x - start[0]) * dx + (y - start[1]) * dy) / length_squared
+            }
+            Shape::Radial {
+                center,
+                focal,
+                radius,
+            } => {
+                // Find the circle through the position, its center moves from the focal point to
+                // the center while its radius grows from zero
+                let (px, py) = (x - focal[0], y - focal[1]);
+                let (cx, cy) = (center[0] - focal[0], center[1] - focal[1]);
+                let a = cx * cx + cy * cy - radius * radius;
+                let b = px * cx + py * cy;
+                let c = px * px + py * py;
+
+                if a.abs() < std::f64::EPSILON {
+                    if b == 0.0 {
+                        0.0
+                    } else {
+                        c / (2.0 * b)
+                    }
+                } else {
+                    (b - (b * b - a * c).max(0.0).sqrt()) / a
+                }
+            }
+        }
+    }
+
+    /// Map an offset outside of the gradient back onto it.
+    fn spread(&self, offset: f64) -> f64 {
+        match self.spread {
+            SpreadMethod::Pad => offset.max(0.0).min(1.0),
+            SpreadMethod::Repeat => offset.rem_euclid(1.0),
+            SpreadMethod::Reflect => {
+                let offset = offset.rem_euclid(2.0);
+
+                if offset > 1.0 {
+                    2.0 - offset
+                } else {
+                    offset
+                }
+            }
+        }
+    }
+}
+
+/// The RGBA color of a stop with the opacity of the fill applied.
+fn stop_color(stop: &Stop, opacity: f32) -> [f32; 4] {
+    [
+        stop.color.red as f32 / 255.0,
+        stop.color.green as f32 / 255.0,
+        stop.color.blue as f32 / 255.0,
+        stop.opacity.value() as f32 * opacity,
+    ]
+}
+
+/// The transformation undoing another one, `None` if it flattens everything.
+fn invert(ts: &Transform) -> Option<Transform> {
+    let determinant = ts.a * ts.d - ts.b * ts.c;
+    if determinant.abs() < std::f64::EPSILON {
+        return None;
+    }
+
+    let (a, b, c, d) = (
+        ts.d / determinant,
+        -ts.b / determinant,
+        -ts.c / determinant,
+        ts.a / determinant,
+    );
+
+    Some(Transform::new(
+        a,
+        b,
+        c,
+        d,
+        -(a * ts.e + c * ts.f),
+        -(b * ts.e + d * ts.f),
+    ))
+}
@@ -0,0 +1,125 @@
+use crate::render::{Instance, InstanceRef};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// Parent relationships between instances.
+///
+/// The transforms of child instances are relative to their parent, the world transforms that
+/// are rendered are calculated on the CPU before uploading.
+#[derive(Debug, Default)]
+pub struct Hierarchy {
+    /// The parent of every child instance.
+    parents: HashMap<InstanceRef, InstanceRef>,
+    /// The transforms of the child instances relative to their parent.
+    local: HashMap<InstanceRef, Instance>,
+    /// Whether an instance changed since the world transforms were calculated.
+    dirty: bool,
+}
+
+impl Hierarchy {
+    /// Create a hierarchy without any children.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether an instance is the ancestor of another instance or the instance itself.
+    pub fn is_ancestor(&self, ancestor: InstanceRef, mut instance: InstanceRef) -> bool {
+        loop {
+            if instance == ancestor {
+                return true;
+            }
+
+            instance = match self.parents.get(&instance) {
+                Some(parent) => *parent,
+                None => return false,
+            };
+        }
+    }
+
+    /// Make an instance the child of another with a transform relative to the parent.
+    pub fn set_parent(&mut self, child: InstanceRef, parent: InstanceRef, local: Instance) {
+        self.parents.insert(child, parent);
+        self.local.insert(child, local);
+        self.dirty = true;
+    }
+
+    /// Detach an instance from its parent.
+    ///
+    /// Returns the transform relative to the parent if it had one.
+    pub fn clear_parent(&mut self, child: InstanceRef) -> Option<Instance> {
+        self.parents.remove(&child);
+
+        self.local.remove(&child)
+    }
+
+    /// The parent of an instance.
+    pub fn parent(&self, child: InstanceRef) -> Option<InstanceRef> {
+        self.parents.get(&child).copied()
+    }
+
+    /// The transform of a child instance relative to its parent.
+    pub fn local(&self, child: InstanceRef) -> Option<&Instance> {
+        self.local.get(&child)
+    }
+
+    /// The transform of a child instance relative to its parent for modifying it.
+    pub fn local_mut(&mut self, child: InstanceRef) -> Option<&mut Instance> {
+        self.local.get_mut(&child)
+    }
+
+    /// Forget a removed instance, its children keep their last world transform.
+    pub fn remove(&mut self, instance: InstanceRef) {
+        self.clear_parent(instance);
+
+        let children: Vec<InstanceRef> = self
+            .parents
+            .iter()
+            .filter(|(_, parent)| **parent == instance)
+            .map(|(child, _)| *child)
+            .collect();
+        for child in children {
+            self.clear_parent(child);
+        }
+    }
+
+    /// Calculate the world transforms again before the next render.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = !self.parents.is_empty();
+    }
+
+    /// Calculate the world transforms of all children if anything changed.
+    ///
+    /// The transforms of instances without a parent are retrieved with the function. With the
+    /// `rayon` feature the children are calculated on multiple threads.
+    pub fn world_transforms<F>(&mut self, root_transform: F) -> Vec<(InstanceRef, Instance)>
+    where
+        F: Fn(InstanceRef) -> Option<Instance> + Sync,
+    {
+        if !std::mem::replace(&mut self.dirty, false) {
+            return vec![];
+        }
+
+        #[cfg(feature = "rayon")]
+        let children = self.local.par_iter();
+        #[cfg(not(feature = "rayon"))]
+        let children = self.local.iter();
+
+        children
+            .filter_map(|(child, _)| Some((*child, self.world_transform(*child, &root_transform)?)))
+            .collect()
+    }
+
+    /// Combine the transforms of an instance with all its ancestors.
+    fn world_transform<F>(&self, instance: InstanceRef, root_transform: &F) -> Option<Instance>
+    where
+        F: Fn(InstanceRef) -> Option<Instance>,
+    {
+        match (self.parents.get(&instance), self.local.get(&instance)) {
+            (Some(parent), Some(local)) => {
+                Some(local.relative_to(&self.world_transform(*parent, root_transform)?))
+            }
+            _ => root_transform(instance),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::sandbox;
+use glsp::{lib, rfn, GFn, GResult, Root, Runtime, Val};
+use quad_net::http_request::{Method, Request, RequestBuilder};
+
+/// A request that's waiting for a response.
+struct PendingRequest {
+    /// The URL, for error messages.
+    url: String,
+    /// The request that's in flight.
+    request: Request,
+    /// The function called with the response.
+    callback: Root<GFn>,
+}
+
+lib! {
+/// HTTP requests from scripts for leaderboards, telemetry & downloading levels, only available
+/// with the `http` feature.
+///
+/// Requests never block the game, the callback is called with the body of the response and `#n`
+/// when it arrives, or with `#n` and the error message when the request failed. On WASM the
+/// browser's `fetch` is used, so the server must allow the origin of the game.
+///
+/// ```gamelisp
+/// (http-get "https://example.com/scores.json" (fn (body error)
+///   (if error
+///     (log-warn "loading scores failed: " error)
+///     (= scores body))))
+/// ```
+pub struct Http {
+    /// The requests waiting for a response.
+    pending: Vec<PendingRequest>,
+}
+}
+
+impl Http {
+    /// Create without requests.
+    pub fn new() -> Self {
+        Self { pending: vec![] }
+    }
+
+    /// Send a request, the callback is called when the response arrives.
+    fn send(&mut self, url: &str, request: RequestBuilder, callback: Root<GFn>) {
+        log::debug!("sending HTTP request to \"{}\"", url);
+
+        self.pending.push(PendingRequest {
+            url: url.to_string(),
+            request: request.send(),
+            callback,
+        });
+    }
+
+    /// Call the functions of the requests that got a response.
+    pub fn finish_done() -> GResult<()> {
+        // Don't hold the borrow while calling the scripts, they might send new requests
+        let done: Vec<(Root<GFn>, Val, Val)> = {
+            let mut http = Http::borrow_mut();
+
+            let mut done = vec![];
+            let mut index = 0;
+            while index < http.pending.len() {
+                match http.pending[index].request.try_recv() {
+                    Some(result) => {
+                        let pending = http.pending.remove(index);
+                        done.push(match result {
+                            Ok(body) => (
+                                pending.callback,
+                                Val::Str(glsp::str_from_rust_str(&body)),
+                                Val::Nil,
+                            ),
+                            Err(err) => {
+                                let message = format!("{:?}", err);
+                                log::warn!(
+                                    "HTTP request to \"{}\" failed: {}",
+                                    pending.url,
+                                    message
+                                );
+
+                                (
+                                    pending.callback,
+                                    Val::Nil,
+                                    Val::Str(glsp::str_from_rust_str(&message)),
+                                )
+                            }
+                        });
+                    }
+                    None => index += 1,
+                }
+            }
+
+            done
+        };
+
+        for (callback, body, error) in done {
+            let _: Val = sandbox::call(&callback, &(body, error))?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a GET request from a script.
+    ///
+    /// ```gamelisp
+    /// (http-get "https://example.com/level-2.glsp" (fn (body error)
+    ///   (when body (= level (parse-level body)))))
+    /// ```
+    fn http_get(&mut self, url: &str, callback: Root<GFn>) {
+        self.send(url, RequestBuilder::new(url).method(Method::Get), callback);
+    }
+
+    /// Send a POST request with a body from a script, the content type is `application/json`
+    /// by default.
+    ///
+    /// ```gamelisp
+    /// (http-post "https://example.com/scores" (str "{\"score\": " score "}") (fn (body error)))
+    /// ```
+    fn http_post(
+        &mut self,
+        url: &str,
+        body: &str,
+        callback: Root<GFn>,
+        content_type: Option<&str>,
+    ) {
+        let request = RequestBuilder::new(url)
+            .method(Method::Post)
+            .header("Content-Type", content_type.unwrap_or("application/json"))
+            .body(body);
+
+        self.send(url, request, callback);
+    }
+
+    /// Register the requests and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Http::new());
+
+            glsp::bind_rfn("http-get", rfn!(Http::http_get))?;
+            glsp::bind_rfn("http-post", rfn!(Http::http_post))?;
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,280 @@
+use crate::{
+    postprocess,
+    render::{self, Instance},
+};
+use miniquad::{graphics::*, Context};
+use std::mem;
+
+/// The vertex shader blending the layer with the scene, a quad covering the whole screen.
+const COMPOSITE_VERTEX: &str = r#"#version 100
+
+attribute vec2 a_pos;
+
+void main() {
+    gl_Position = vec4(a_pos, 0.0, 1.0);
+}
+"#;
+
+/// The fragment shader blending the layer with the scene at the opacity of the group.
+///
+/// The layer is sampled at the pixel that's drawn so it lines up with viewports.
+const COMPOSITE_FRAGMENT: &str = r#"#version 100
+
+precision mediump float;
+
+uniform sampler2D u_layer;
+uniform vec2 u_size;
+uniform float u_opacity;
+
+void main() {
+    gl_FragColor = texture2D(u_layer, gl_FragCoord.xy / u_size) * u_opacity;
+}
+"#;
+
+/// The uniforms of the composite shader.
+#[repr(C)]
+struct CompositeUniforms {
+    size: (f32, f32),
+    opacity: f32,
+}
+
+/// How the instances of a translucent mesh are blended with the scene.
+///
+/// Without a group every triangle is blended on its own, so the overlapping parts of instances &
+/// of the shapes inside a mesh show through each other.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OpacityGroup {
+    /// All instances are drawn opaque to a layer that's blended with the scene at the opacity.
+    Mesh(f32),
+    /// Every instance is drawn opaque to the layer & blended with the scene at its own alpha.
+    Instances,
+}
+
+/// The GPU resources of the layer, recreated when the screen is resized.
+struct LayerTarget {
+    /// The texture the group is drawn to.
+    color: Texture,
+    /// The depth texture of the render pass, so the group is ordered by itself.
+    depth: Texture,
+    /// The pass drawing to the texture.
+    pass: RenderPass,
+    /// The fullscreen quad sampling the texture.
+    quad: Bindings,
+}
+
+/// An offscreen layer meshes are drawn to at full alpha, composited once at the opacity of their
+/// group.
+pub struct OpacityLayer {
+    /// The pipelines drawing untextured & textured meshes to the layer with premultiplied alpha,
+    /// `None` when headless.
+    pipelines: Option<(Pipeline, Pipeline)>,
+    /// The pipeline blending the premultiplied layer with the scene.
+    composite_pipeline: Option<Pipeline>,
+    /// The buffer holding the single instance drawn when every instance is its own group.
+    instance_buffer: Option<Buffer>,
+    /// The texture & pass, created when a group is first drawn.
+    target: Option<LayerTarget>,
+}
+
+impl OpacityLayer {
+    /// Create without pipelines, nothing can be drawn.
+    pub fn new() -> Self {
+        Self {
+            pipelines: None,
+            composite_pipeline: None,
+            instance_buffer: None,
+            target: None,
+        }
+    }
+
+    /// Create the pipelines drawing meshes with the defines of the mesh shaders.
+    pub fn with_pipelines(ctx: &mut Context, defines: &[&str], depth_buffer: bool) -> Self {
+        // The colors are premultiplied so the layer can be blended at any opacity afterwards
+        let params = PipelineParams {
+            color_blend: Some(BlendState::new(
+                Equation::Add,
+                BlendFactor::Value(BlendValue::SourceAlpha),
+                BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+            )),
+            alpha_blend: Some(BlendState::new(
+                Equation::Add,
+                BlendFactor::One,
+                BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+            )),
+            ..render::mesh_pipeline_params(depth_buffer)
+        };
+
+        let shader = Shader::new(
+            ctx,
+            render::geom_shader::VERTEX,
+            &render::with_defines(render::geom_shader::FRAGMENT, defines),
+            render::geom_shader::META,
+        )
+        .expect("Building opacity group shader failed");
+        let pipeline = render::instanced_pipeline(ctx, shader, params);
+
+        let sprite_shader = Shader::new(
+            ctx,
+            render::sprite_shader::VERTEX,
+            &render::with_defines(render::sprite_shader::FRAGMENT, defines),
+            render::sprite_shader::META,
+        )
+        .expect("Building opacity group sprite shader failed");
+        let sprite_pipeline = render::instanced_pipeline(ctx, sprite_shader, params);
+
+        let composite_shader = Shader::new(
+            ctx,
+            COMPOSITE_VERTEX,
+            COMPOSITE_FRAGMENT,
+            ShaderMeta {
+                images: &["u_layer"],
+                uniforms: UniformBlockLayout {
+                    uniforms: &[
+                        UniformDesc::new("u_size", UniformType::Float2),
+                        UniformDesc::new("u_opacity", UniformType::Float1),
+                    ],
+                },
+            },
+        )
+        .expect("Building opacity group composite shader failed");
+        let composite_pipeline = Pipeline::with_params(
+            ctx,
+            &[BufferLayout::default()],
+            &[VertexAttribute::new("a_pos", VertexFormat::Float2)],
+            composite_shader,
+            PipelineParams {
+                color_blend: Some(BlendState::new(
+                    Equation::Add,
+                    BlendFactor::One,
+                    BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+                )),
+                ..Default::default()
+            },
+        );
+
+        let instance_buffer =
+            Buffer::stream(ctx, BufferType::VertexBuffer, mem::size_of::<Instance>());
+
+        Self {
+            pipelines: Some((pipeline, sprite_pipeline)),
+            composite_pipeline: Some(composite_pipeline),
+            instance_buffer: Some(instance_buffer),
+            target: None,
+        }
+    }
+
+    /// The pipeline drawing meshes to the layer, `None` when headless.
+    pub fn pipeline(&self, textured: bool) -> Option<Pipeline> {
+        self.pipelines
+            .map(|(pipeline, sprite_pipeline)| if textured { sprite_pipeline } else { pipeline })
+    }
+
+    /// Upload a single instance to draw on its own, returns the buffer holding it.
+    pub fn upload_instance(&mut self, ctx: &mut Context, instance: Instance) -> Option<Buffer> {
+        let buffer = self.instance_buffer?;
+        buffer.update(ctx, &[instance]);
+
+        Some(buffer)
+    }
+
+    /// Stop drawing to the scene & clear the layer, resized to the frame when needed.
+    ///
+    /// Must be called inside the render pass of the scene, the layer is drawn to until
+    /// [`OpacityLayer::composite`] is called.
+    pub fn begin(
+        &mut self,
+        ctx: &mut Context,
+        size: (u32, u32),
+        rect: [i32; 4],
+        scissor: [i32; 4],
+    ) -> bool {
+        if self.pipelines.is_none() {
+            return false;
+        }
+
+        let target = match self.target.take() {
+            Some(target) if (target.color.width, target.color.height) == size => target,
+            Some(target) => {
+                target.pass.delete(ctx);
+                target.color.delete();
+                target.depth.delete();
+
+                LayerTarget::new(ctx, size)
+            }
+            None => LayerTarget::new(ctx, size),
+        };
+
+        ctx.end_render_pass();
+        ctx.begin_pass(
+            Some(target.pass),
+            PassAction::clear_color(0.0, 0.0, 0.0, 0.0),
+        );
+        apply_rects(ctx, rect, scissor);
+
+        self.target = Some(target);
+
+        true
+    }
+
+    /// Stop drawing to the layer & blend it with the scene at the opacity.
+    ///
+    /// The render pass of the scene, `None` for the screen, is continued without clearing it.
+    pub fn composite(
+        &mut self,
+        ctx: &mut Context,
+        pass: Option<RenderPass>,
+        rect: [i32; 4],
+        scissor: [i32; 4],
+        opacity: f32,
+    ) {
+        ctx.end_render_pass();
+        ctx.begin_pass(pass, PassAction::Nothing);
+        apply_rects(ctx, rect, scissor);
+
+        if let (Some(pipeline), Some(target)) = (self.composite_pipeline, &self.target) {
+            ctx.apply_pipeline(&pipeline);
+            ctx.apply_bindings(&target.quad);
+            ctx.apply_uniforms(&CompositeUniforms {
+                size: (target.color.width as f32, target.color.height as f32),
+                opacity,
+            });
+            ctx.draw(0, 6, 1);
+        }
+    }
+}
+
+impl LayerTarget {
+    /// Create the texture with the size of the screen and the quad sampling it.
+    fn new(ctx: &mut Context, (width, height): (u32, u32)) -> Self {
+        let (color, depth, pass) = postprocess::render_target(ctx, width, height);
+
+        #[rustfmt::skip]
+        let vertices: [f32; 8] = [
+            -1.0, -1.0,
+            1.0, -1.0,
+            1.0, 1.0,
+            -1.0, 1.0,
+        ];
+        let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
+        let quad = Bindings {
+            vertex_buffers: vec![Buffer::immutable(ctx, BufferType::VertexBuffer, &vertices)],
+            index_buffer: Buffer::immutable(ctx, BufferType::IndexBuffer, &indices),
+            images: vec![color],
+        };
+
+        Self {
+            color,
+            depth,
+            pass,
+            quad,
+        }
+    }
+}
+
+/// Draw to the same part of the pass as the view the group belongs to.
+fn apply_rects(ctx: &mut Context, rect: [i32; 4], scissor: [i32; 4]) {
+    let [x, y, width, height] = rect;
+    ctx.apply_viewport(x, y, width, height);
+    let [x, y, width, height] = scissor;
+    ctx.apply_scissor_rect(x, y, width, height);
+}
@@ -0,0 +1,155 @@
+use crate::{
+    assets::Assets,
+    render::{Instance, InstanceRef, Render},
+    script::Scripts,
+};
+use glsp::{rfn, FromVal, GResult, Runtime};
+use std::fmt::Write;
+
+/// An instance of the mesh of an asset placed in a level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    /// The reference name of the asset the mesh is loaded from.
+    pub asset: String,
+    pub x: f32,
+    pub y: f32,
+    pub z: u8,
+    pub rotation: f32,
+    pub scale_x: f32,
+    pub scale_y: f32,
+}
+
+/// The instances of meshes loaded from assets, with the reference names of their assets.
+///
+/// Children of other instances & instances of meshes created by the scripts aren't part of the
+/// level.
+pub fn placed_instances(assets: &Assets, render: &Render) -> Vec<(String, InstanceRef)> {
+    let mut placed = vec![];
+    for (mesh, _) in render.instance_counts() {
+        let asset = match assets.mesh_reference_name(mesh) {
+            Some(asset) => asset,
+            None => continue,
+        };
+
+        placed.extend(
+            render
+                .instances(mesh)
+                .into_iter()
+                .filter(|instance| render.parent(*instance).is_none())
+                .map(|instance| (asset.to_string(), instance)),
+        );
+    }
+
+    placed
+}
+
+/// The placements of the instances that are part of the level.
+pub fn placements(assets: &Assets, render: &Render) -> Vec<Placement> {
+    placed_instances(assets, render)
+        .into_iter()
+        .filter_map(|(asset, instance)| {
+            render.instance(instance).map(|instance| Placement {
+                asset,
+                x: instance.x(),
+                y: instance.y(),
+                z: instance.z(),
+                rotation: instance.rotation(),
+                scale_x: instance.scale_x(),
+                scale_y: instance.scale_y(),
+            })
+        })
+        .collect()
+}
+
+/// A GameLisp script evaluating to the placements, loaded again with `load-level`.
+pub fn layout_source(placements: &[Placement]) -> String {
+    let mut source = String::from("; Level layout, place it with (load-level \"name.glsp\")\n(arr");
+    for placement in placements {
+        // Writing to a string can't fail
+        let _ = write!(
+            source,
+            "\n  (arr {:?} {} {} {} {} {} {})",
+            placement.asset,
+            placement.x,
+            placement.y,
+            placement.z,
+            placement.rotation,
+            placement.scale_x,
+            placement.scale_y
+        );
+    }
+    source.push_str(")\n");
+
+    source
+}
+
+/// Add an instance for every placement.
+///
+/// Placements of assets that aren't loaded are skipped with a warning.
+pub fn place(assets: &Assets, render: &mut Render, placements: &[Placement]) -> Vec<InstanceRef> {
+    placements
+        .iter()
+        .filter_map(|placement| {
+            let mesh = match assets.mesh(&placement.asset) {
+                Some(mesh) => mesh,
+                None => {
+                    log::warn!("level asset \"{}\" isn't loaded", placement.asset);
+
+                    return None;
+                }
+            };
+
+            let mut instance = Instance::new(placement.x, placement.y);
+            instance.set_z(placement.z);
+            instance.set_rotation(placement.rotation);
+            instance.set_scale_x(placement.scale_x);
+            instance.set_scale_y(placement.scale_y);
+
+            Some(render.add_instance(mesh, instance))
+        })
+        .collect()
+}
+
+/// Evaluate a level layout script and add the instances it places.
+///
+/// Must be called inside the GameLisp runtime without the scripts being borrowed.
+pub fn load(name: &str) -> GResult<Vec<InstanceRef>> {
+    let layout = Scripts::load(name)?;
+    let placements: Vec<(String, f32, f32, u8, f32, f32, f32)> = FromVal::from_val(&layout)?;
+    let placements: Vec<Placement> = placements
+        .into_iter()
+        .map(|(asset, x, y, z, rotation, scale_x, scale_y)| Placement {
+            asset,
+            x,
+            y,
+            z,
+            rotation,
+            scale_x,
+            scale_y,
+        })
+        .collect();
+
+    Ok(place(
+        &Assets::borrow(),
+        &mut Render::borrow_mut(),
+        &placements,
+    ))
+}
+
+/// Place the instances of a level saved with the editor from a script, returns the instances.
+///
+/// ```gamelisp
+/// (let props (load-level "levels/forest.glsp"))
+/// ```
+fn load_level(name: &str) -> GResult<Vec<InstanceRef>> {
+    load(name)
+}
+
+/// Bind the GameLisp functions.
+pub fn bind_functions(runtime: &Runtime) {
+    runtime.run(|| {
+        glsp::bind_rfn("load-level", rfn!(load_level))?;
+
+        Ok(())
+    });
+}
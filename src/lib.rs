@@ -1,14 +1,161 @@
+// Must come before the other modules so they can use the macros
+#[macro_use]
+mod profile;
+
+mod action;
+mod ai;
+mod animation;
+mod assets;
+mod atlas;
+mod bake;
+mod batch;
+mod boolean;
+mod capture;
+#[cfg(feature = "svg")]
+mod clip;
+mod color;
+mod config;
+mod console;
+mod cursor;
+mod curve;
+mod debug;
+mod ecs;
+#[cfg(feature = "editor-ui")]
+mod editor;
+mod effects;
+mod error_screen;
+mod event;
+mod flipbook;
+mod frame_graph;
+mod game;
+#[cfg(feature = "svg")]
+mod gradient;
+mod hierarchy;
+#[cfg(feature = "http")]
+mod http;
+mod layer;
+mod level;
+mod lighting;
+mod limiter;
+mod locale;
+mod logging;
+mod math;
+mod mods;
+#[cfg(feature = "net")]
+mod net;
+mod path;
+mod pathfinding;
+mod postprocess;
+mod prefab;
+mod puppet;
+mod random;
 mod render;
+mod replay;
+mod rewind;
+mod sandbox;
+mod save;
+mod scene;
+mod script;
+mod shapes;
+mod stats;
+mod storage;
+mod store;
+mod streaming;
+mod system;
+mod task;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod testing;
+mod text;
+mod text_input;
+mod tilemap;
+mod timer;
+mod touch;
+mod ui;
+mod uniforms;
+mod window;
+
+#[cfg(feature = "compiler")]
+pub use crate::script::compile_script_file;
+pub use crate::{
+    assets::{AssetKind, AssetState, Assets},
+    bake::{bake_svg, bake_svg_file},
+    color::Color,
+    ecs::{Entity, World},
+    game::{Engine, Game},
+    layer::OpacityGroup,
+    prefab::Prefabs,
+    render::{
+        Instance, InstanceRef, Mesh, MeshMemory, RaycastHit, Render, RenderedFrame, Viewport,
+    },
+    sandbox::RuntimeOptions,
+    streaming::Streaming,
+    system::System,
+};
+#[cfg(feature = "editor-ui")]
+pub use egui;
+pub use log::LevelFilter;
+pub use miniquad::graphics::UniformType;
 
-use crate::render::Render;
+#[cfg(feature = "editor-ui")]
+use crate::editor::Editor;
+#[cfg(feature = "http")]
+use crate::http::Http;
+#[cfg(feature = "net")]
+use crate::net::Net;
+use crate::{
+    action::Actions,
+    ai::Ai,
+    animation::Animations,
+    assets::Assets,
+    boolean::BooleanOps,
+    capture::Capture,
+    color::Palettes,
+    config::Configs,
+    console::Console,
+    cursor::Cursor,
+    effects::Effects,
+    error_screen::ErrorScreen,
+    event::Events,
+    flipbook::Flipbooks,
+    lighting::Lighting,
+    limiter::FrameLimiter,
+    locale::Locales,
+    logging::Logger,
+    mods::Mods,
+    path::Paths,
+    pathfinding::Pathfinding,
+    postprocess::PostPassDescriptor,
+    puppet::Puppets,
+    random::Random,
+    render::{Render, ShaderDescriptor},
+    replay::{InputEvent, Replay},
+    rewind::Rewind,
+    sandbox,
+    scene::Scenes,
+    script::{Scripts, MAIN_SCRIPT},
+    stats::Stats,
+    storage::Storage,
+    store::Store,
+    task::Tasks,
+    text_input::TextInput,
+    tilemap::Tilemaps,
+    timer::Timers,
+    touch::Touches,
+    ui::Ui,
+    window::Window,
+};
 use anyhow::{anyhow, Result};
-use glsp::{GFn, GSend, Root, Runtime, Val};
+use glsp::{GFn, GResult, GSend, Root, Runtime, Val};
 use miniquad::{
-    conf::{Conf, Loading},
+    conf::{Conf, Icon, Loading, Platform},
     graphics::Context,
-    EventHandler, UserData,
+    EventHandler, KeyCode, KeyMods, MouseButton, TouchPhase, UserData,
 };
 use smart_default::SmartDefault;
+use std::path::PathBuf;
+
+/// How many seconds of game time pass during every update when running headless.
+const HEADLESS_UPDATE_TIME: f64 = 1.0 / 60.0;
 
 /// The main game object.
 ///
@@ -45,11 +192,54 @@ pub struct Clog {
     #[default = 8]
     sample_count: i32,
 
-    /// SVGs to load.
-    svgs: Vec<(String, String)>,
+    /// Whether the window starts in fullscreen.
+    fullscreen: bool,
+
+    /// Whether the window can be resized by the user.
+    #[default = true]
+    resizable: bool,
+
+    /// Whether the full resolution is used on high-DPI displays.
+    high_dpi: bool,
+
+    /// Whether presenting frames waits for the display, the driver decides when `None`.
+    vsync: Option<bool>,
+
+    /// The icon of the window.
+    icon: Option<Icon>,
+
+    /// Fullscreen post-processing passes applied in order.
+    post_passes: Vec<PostPassDescriptor>,
+
+    /// Custom shaders that can be assigned to meshes.
+    shaders: Vec<ShaderDescriptor>,
+
+    /// Whether instances are ordered by their Z position with a depth buffer.
+    #[default = true]
+    depth_buffer: bool,
+
+    /// Whether the edges of SVGs are smoothed with a transparent fringe instead of MSAA.
+    vertex_antialiasing: bool,
+
+    /// Whether colors are blended in linear space instead of sRGB.
+    gamma_correct: bool,
+
+    /// The area of the world that's always visible at zoom `1.0`.
+    design_resolution: Option<(f32, f32)>,
+
+    /// Native systems updated & rendered alongside the scripts.
+    systems: Vec<Box<dyn System>>,
 
-    /// Fonts to load.
-    fonts: Vec<(String, String)>,
+    /// The game written in Rust instead of GameLisp.
+    game: Option<Box<dyn Game>>,
+
+    /// The key that shows the development tools.
+    #[cfg(feature = "editor-ui")]
+    editor_key: Option<KeyCode>,
+
+    /// Whether the development tools are shown when the game starts.
+    #[cfg(feature = "editor-ui")]
+    editor_open: bool,
 }
 
 impl Clog {
@@ -58,9 +248,86 @@ impl Clog {
     where
         T: Into<String>,
     {
+        Self::with_runtime_options(title, RuntimeOptions::new())
+    }
+
+    /// Setup a new game with a restricted script runtime, for running untrusted scripts.
+    ///
+    /// ```rust
+    /// use clogs::{Clog, RuntimeOptions};
+    /// # fn main() {
+    /// let game = Clog::with_runtime_options(
+    ///     "Modded game",
+    ///     RuntimeOptions::new().sandboxed(true).max_call_time(0.05),
+    /// );
+    /// # }
+    /// ```
+    pub fn with_runtime_options<T>(title: T, options: RuntimeOptions) -> Self
+    where
+        T: Into<String>,
+    {
+        let title = title.into();
+        let runtime = options.build_runtime();
+        // Registered first so errors of the other subsystems are logged
+        Logger::bind_functions(&runtime);
+        Scripts::bind_functions(&runtime);
+        Configs::bind_functions(&runtime);
+        Scenes::bind_functions(&runtime);
+        Assets::bind_functions(&runtime);
+        Render::bind_functions(&runtime);
+        shapes::bind_functions(&runtime);
+        Palettes::bind_functions(&runtime);
+        curve::bind_functions(&runtime);
+        math::bind_functions(&runtime);
+        BooleanOps::bind_functions(&runtime);
+        level::bind_functions(&runtime);
+        Lighting::bind_functions(&runtime);
+        Effects::bind_functions(&runtime);
+        text::bind_functions(&runtime);
+        Locales::bind_functions(&runtime);
+        TextInput::bind_functions(&runtime);
+        Cursor::bind_functions(&runtime);
+        debug::bind_functions(&runtime);
+        Stats::bind_functions(&runtime);
+        Console::register(&runtime);
+        save::bind_functions(&runtime);
+        Touches::bind_functions(&runtime);
+        Tilemaps::bind_functions(&runtime);
+        Actions::bind_functions(&runtime);
+        World::bind_functions(&runtime);
+        Prefabs::bind_functions(&runtime);
+        Streaming::bind_functions(&runtime);
+        Timers::bind_functions(&runtime);
+        Tasks::bind_functions(&runtime);
+        Ai::bind_functions(&runtime);
+        Events::bind_functions(&runtime);
+        Capture::bind_functions(&runtime);
+        Random::bind_functions(&runtime);
+        Replay::bind_functions(&runtime);
+        Rewind::bind_functions(&runtime);
+        Ui::bind_functions(&runtime);
+        Paths::bind_functions(&runtime);
+        Pathfinding::bind_functions(&runtime);
+        Puppets::bind_functions(&runtime);
+        Animations::bind_functions(&runtime);
+        Flipbooks::bind_functions(&runtime);
+        Window::bind_functions(&runtime);
+        Mods::bind_functions(&runtime);
+        Store::bind_functions(&runtime, &title);
+        #[cfg(feature = "net")]
+        Net::bind_functions(&runtime);
+        #[cfg(feature = "http")]
+        Http::bind_functions(&runtime);
+        runtime.run(|| {
+            glsp::add_lib(Storage::new("saves"));
+            glsp::add_lib(ErrorScreen::new());
+
+            Ok(())
+        });
+
         Self {
-            title: title.into(),
-            runtime: Runtime::new(),
+            title,
+            runtime,
             ..Default::default()
         }
     }
@@ -73,10 +340,72 @@ impl Clog {
     /// engine:update
     /// engine:render
     /// ```
+    ///
+    /// Scenes can be managed with `push-scene`, `pop-scene` & `switch-scene`, the update & render
+    /// functions of the active scene are called after the engine ones. A scene covered by a pushed
+    /// scene is paused until it's active again & the assets loaded with `load-scene-asset` are
+    /// unloaded when their scene is removed.
+    ///
+    /// When `engine:quit-requested` is defined it's called when the window is closed, returning
+    /// `#f` cancels closing. `engine:shutdown` is called once before the game is closed.
+    ///
+    /// When `engine:touch` is defined it's called with a table for every touch & gesture, the
+    /// `kind` is one of `start`, `move`, `end`, `cancel`, `tap`, `drag` or `pinch`.
+    ///
+    /// When `engine:dpi-changed` is defined it's called with the new DPI scale when the window
+    /// moved to a monitor with another pixel density.
+    ///
+    /// When `engine:loading-done` is defined it's called before the first update when the assets
+    /// queued before starting are loaded, and again whenever assets requested with `load-asset`
+    /// are loaded.
+    ///
+    /// When a function fails while the game is running, the game halts and shows the error with
+    /// the stack trace until the script is reloaded with F5 or the button on the panel.
     pub fn main_script<S>(self, script: S) -> Result<Self>
     where
         S: AsRef<str> + GSend,
     {
+        self.runtime.run(|| {
+            Scripts::borrow_mut().add(MAIN_SCRIPT, script.as_ref());
+
+            Ok(())
+        });
+
+        self.load_main_script()
+    }
+
+    /// The main script of the game as bytecode compiled with `compile_script` or
+    /// `compile_script_file`, see `main_script`.
+    ///
+    /// Only available with the `compiler` feature.
+    #[cfg(feature = "compiler")]
+    pub fn main_script_compiled<B>(self, bytecode: B) -> Result<Self>
+    where
+        B: Into<Vec<u8>>,
+    {
+        self.runtime.run(|| {
+            Scripts::borrow_mut().add_compiled(MAIN_SCRIPT, bytecode.into());
+
+            Ok(())
+        });
+
+        self.load_main_script()
+    }
+
+    /// Compile a script to bytecode, so games can ship without the source and don't need to
+    /// parse it when starting.
+    ///
+    /// The functions bound by the engine are available while compiling, but the script is
+    /// evaluated without a window. Only available with the `compiler` feature.
+    #[cfg(feature = "compiler")]
+    pub fn compile_script(&self, name: &str, source: &str) -> Result<Vec<u8>> {
+        self.runtime
+            .run(|| Ok(glsp::load_and_compile_str(source, name)?.1))
+            .ok_or_else(|| anyhow!("compiling script \"{}\" failed", name))
+    }
+
+    /// Evaluate the main script and check that the required functions are defined.
+    fn load_main_script(self) -> Result<Self> {
         struct LoadingResult {
             has_update: bool,
             has_render: bool,
@@ -85,7 +414,9 @@ impl Clog {
             .runtime
             .run(|| {
                 // Execute the main lisp script
-                glsp::eval_multi(&glsp::parse_all(script.as_ref(), None)?, None)?;
+                Scripts::load(MAIN_SCRIPT)?;
+                // Loaded after the main script so mods can redefine its functions
+                Mods::load();
 
                 // Check if the required functions are loaded
                 Ok(LoadingResult {
@@ -95,7 +426,10 @@ impl Clog {
             })
             .ok_or(anyhow!("executing main script failed"))?;
 
-        if !result.has_update {
+        if self.game.is_some() {
+            // The game written in Rust updates & renders instead
+            Ok(self)
+        } else if !result.has_update {
             Err(anyhow!(
                 "function 'engine:update' is missing from main script"
             ))
@@ -108,6 +442,101 @@ impl Clog {
         }
     }
 
+    /// Add the source of a script file, so `load-script` & `require-script` don't need to read
+    /// it.
+    ///
+    /// Must be called before `main_script` for the scripts it requires. On WASM files can't be
+    /// read, so every script must be added:
+    ///
+    /// ```rust
+    /// use clogs::Clog;
+    /// # fn main() -> anyhow::Result<()> {
+    /// let game = Clog::new("Title of the game")
+    ///     .add_script("player.glsp", "(defn player:update ())")
+    ///     .main_script(
+    ///         r#"(require-script "player.glsp")
+    ///            (defn engine:update () (player:update))
+    ///            (defn engine:render ())"#,
+    ///     )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn add_script<N, S>(self, name: N, source: S) -> Self
+    where
+        N: Into<String>,
+        S: Into<String>,
+    {
+        self.runtime.run(|| {
+            Scripts::borrow_mut().add(name, source);
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Add the source of a TOML, RON or JSON config file, so `load-config` doesn't need to read
+    /// it.
+    ///
+    /// On WASM files can't be read, so every config must be added. Added configs aren't
+    /// reloaded when they change.
+    pub fn add_config<N, S>(self, name: N, source: S) -> Self
+    where
+        N: Into<String>,
+        S: Into<String>,
+    {
+        self.runtime.run(|| {
+            Configs::borrow_mut().add(name, source);
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Set the directory the script files required by the scripts are read from, the working
+    /// directory by default.
+    pub fn script_root<P>(self, root: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.runtime.run(|| {
+            Scripts::borrow_mut().set_root(root);
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Load the mods in the directories inside a directory after the main script.
+    ///
+    /// Every mod is a directory with a `mod.json` manifest containing its `name`, `version`,
+    /// `description`, the `dependencies` loaded before it and the `scripts` that are evaluated,
+    /// `main.glsp` by default. Its scripts & the files in its `assets` directory are prefixed
+    /// with the name of its directory, `(require-script "hard-mode/enemies.glsp")`.
+    ///
+    /// Must be called before `main_script`, mods aren't supported on WASM.
+    ///
+    /// ```rust
+    /// use clogs::Clog;
+    /// # fn main() {
+    /// let game = Clog::new("Title of the game").mods_directory("mods");
+    /// # }
+    /// ```
+    pub fn mods_directory<P>(self, directory: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.runtime.run(|| {
+            Mods::borrow_mut().set_directory(directory);
+
+            Ok(())
+        });
+
+        self
+    }
+
     /// Set the initial window width.
     pub fn width(mut self, width: i32) -> Self {
         self.width = width;
@@ -129,99 +558,1637 @@ impl Clog {
         self
     }
 
-    /// Add a SVG that will be uploaded to the GPU during the loading phase.
+    /// Smooth the edges of SVGs with a transparent fringe of a pixel wide, disabled by default.
     ///
-    /// The `reference_name` argument can be later used in scripts to create instances of the SVG
-    /// mesh.
-    /// The SVG format must not violate the [usvg limitations](https://github.com/RazrFalcon/resvg/tree/master/usvg#limitations).
-    pub fn load_svg<R, S>(mut self, reference_name: R, svg_source: S) -> Self
+    /// MSAA is expensive on mobile & web GPUs, with this the edges stay smooth with
+    /// `sample_count(1)`. Only SVGs loaded afterwards get the fringe, meshes that overlap with
+    /// the same Z position might show a seam where their fringes blend.
+    pub fn vertex_antialiasing(mut self, vertex_antialiasing: bool) -> Self {
+        self.vertex_antialiasing = vertex_antialiasing;
+        self.runtime.run(|| {
+            Assets::borrow_mut().set_vertex_antialiasing(vertex_antialiasing);
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Blend the colors in linear space & convert the result to sRGB, disabled by default.
+    ///
+    /// The colors of SVGs, textures, lights & screen effects are sRGB. Without gamma correction
+    /// they're blended as they are, which makes the edges of translucent shapes & gradients
+    /// darker than they should be. The conversion is a post-processing pass, which runs before
+    /// the custom passes so they still receive sRGB colors. Custom mesh shaders are blended
+    /// linearly as well, so they must convert their colors themselves.
+    pub fn gamma_correct(mut self, gamma_correct: bool) -> Self {
+        self.gamma_correct = gamma_correct;
+
+        self
+    }
+
+    /// Keep an area of the world visible regardless of the window size & pixel density.
+    ///
+    /// Positions in the scripts keep their meaning when the window is resized, a camera zoom of
+    /// `1.0` shows at least the width & height in world units. Scripts can change it with
+    /// `set-design-resolution` and convert between pixels & world units with `screen-to-world` &
+    /// `world-to-screen`.
+    ///
+    /// ```rust
+    /// use clogs::Clog;
+    /// # fn main() {
+    /// let game = Clog::new("Pixel art game").design_resolution(320.0, 180.0);
+    /// # }
+    /// ```
+    pub fn design_resolution(mut self, width: f32, height: f32) -> Self {
+        self.design_resolution = Some((width, height));
+
+        self
+    }
+
+    /// Start the window in fullscreen, scripts can switch with `set-fullscreen`.
+    pub fn fullscreen(mut self) -> Self {
+        self.fullscreen = true;
+
+        self
+    }
+
+    /// Set whether the window can be resized by the user, defaults to `true`.
+    pub fn resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+
+        self
+    }
+
+    /// Set whether the full resolution is used on high-DPI displays, defaults to `false`.
+    ///
+    /// The pixel positions & sizes of the API stay in logical pixels, scripts can get the amount
+    /// of physical pixels for every logical pixel with `dpi-scale`.
+    pub fn high_dpi(mut self, high_dpi: bool) -> Self {
+        self.high_dpi = high_dpi;
+
+        self
+    }
+
+    /// Limit the amount of frames per second, unlimited by default.
+    ///
+    /// Waiting between frames keeps battery-powered devices from drawing more frames than
+    /// needed. Scripts can change the limit with `set-target-fps`, on the web the browser
+    /// decides the frame rate.
+    pub fn target_fps(self, target_fps: u32) -> Self {
+        self.runtime.run(|| {
+            Window::borrow_mut().set_target_fps(Some(target_fps));
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Set whether presenting a frame waits for the display to refresh.
+    ///
+    /// Without calling this the graphics driver decides, which might ignore the setting.
+    pub fn vsync(mut self, vsync: bool) -> Self {
+        self.vsync = Some(vsync);
+
+        self
+    }
+
+    /// Set the icon of the window from a PNG image, it's scaled to 16, 32 & 64 pixels.
+    pub fn window_icon(mut self, png: &[u8]) -> Result<Self> {
+        self.icon = Some(window::icon_from_png(png)?);
+
+        Ok(self)
+    }
+
+    /// Whether instances are ordered by their Z position with a depth buffer, defaults to `true`.
+    ///
+    /// When disabled everything is drawn with the painter's algorithm: meshes are drawn in the
+    /// order set with `set-draw-order` and instances of a mesh by their `draw-order`.
+    pub fn depth_buffer(mut self, depth_buffer: bool) -> Self {
+        self.depth_buffer = depth_buffer;
+
+        self
+    }
+
+    /// Save every frame as a numbered PNG image in a directory, for recording gameplay.
+    ///
+    /// Scripts can also capture frames with `screenshot` & `start-frame-capture`, native systems
+    /// with `Render::capture_frame`.
+    pub fn capture_frames<P>(self, directory: P) -> Self
     where
-        S: Into<String>,
-        R: Into<String>,
+        P: Into<PathBuf>,
     {
-        self.svgs.push((reference_name.into(), svg_source.into()));
+        self.runtime.run(|| {
+            Capture::borrow_mut().start_sequence(directory);
+
+            Ok(())
+        });
 
         self
     }
 
-    /// Start the game.
-    pub fn start(self) {
-        miniquad::start(
-            Conf {
-                window_title: self.title.clone(),
-                window_width: self.width,
-                window_height: self.height,
-                loading: Loading::Embedded,
-                sample_count: self.sample_count,
-                ..Default::default()
-            },
-            |mut ctx| UserData::owning(ClogRun::new(&mut ctx, self.runtime), ctx),
-        );
+    /// Set the most detailed level of the messages that are logged, `Info` by default.
+    ///
+    /// The engine prints the messages to stderr unless the game installed its own logger before
+    /// calling `Clog::new`. Scripts can change it with `set-log-level`.
+    pub fn log_level(self, level: LevelFilter) -> Self {
+        self.runtime.run(|| {
+            Logger::borrow_mut().set_level(level);
+
+            Ok(())
+        });
+
+        self
     }
 
-    /// Check if a GLSP function is defined.
-    fn has_function(function_name: &str) -> bool {
-        match glsp::global(function_name) {
-            Ok(Val::GFn(_)) => true,
-            _ => false,
-        }
+    /// Record the input & timing of every update, saved in the storage when the game is closed.
+    ///
+    /// Scripts can also record with `start-recording` & `save-recording`.
+    pub fn record_replay<K>(self, key: K) -> Self
+    where
+        K: Into<String>,
+    {
+        self.runtime.run(|| {
+            Replay::borrow_mut().record_until_shutdown(key);
+
+            Ok(())
+        });
+
+        self
     }
-}
 
-/// The actual game runtime.
-struct ClogRun {
-    /// The GameLisp runtime.
-    runtime: Runtime,
+    /// Play back a recorded replay log from the first update, ignoring the input of the player.
+    ///
+    /// Combined with `run_headless` recordings can be used as regression tests.
+    pub fn play_replay(self, log: &str) -> Result<Self> {
+        self.runtime
+            .run(|| Ok(Replay::borrow_mut().play(log)))
+            .ok_or_else(|| anyhow!("starting replay failed"))??;
 
-    /// The render system.
-    render: Render,
-}
+        Ok(self)
+    }
 
-impl ClogRun {
-    /// Create a new runtime.
-    pub fn new(ctx: &mut Context, runtime: Runtime) -> Self {
-        Self {
-            runtime,
-            render: Render::new(ctx),
-        }
+    /// Register a native Rust system, its GameLisp functions are bound immediately.
+    ///
+    /// Systems are updated & rendered in the order they are added.
+    pub fn add_system<S>(mut self, mut system: S) -> Self
+    where
+        S: System + 'static,
+    {
+        system.bind_functions(&self.runtime);
+        self.systems.push(Box::new(system));
+
+        self
     }
 
-    /// Run a GameLisp function.
-    fn call(&self, function: &str) -> bool {
-        struct RuntimeResult(bool);
+    /// Drive the engine from Rust, with or without a main script.
+    ///
+    /// The game is initialized when the assets queued before starting are loaded & is updated &
+    /// rendered before the scripts. Without a main script `engine:update` & `engine:render` are
+    /// optional, but the main script must be set after the game for them to be optional in it.
+    pub fn game<G>(mut self, game: G) -> Self
+    where
+        G: Game + 'static,
+    {
+        self.game = Some(Box::new(game));
 
-        let result: RuntimeResult = self
-            .runtime
-            .run(|| {
-                let update_func: Root<GFn> = match glsp::global(function) {
-                    Ok(Val::GFn(update)) => update,
-                    Ok(val) => {
-                        eprintln!("invalid {} function: {}", function, val);
+        self
+    }
 
-                        return Ok(RuntimeResult(false));
-                    }
-                    Err(err) => {
-                        eprintln!("error finding {} function: {}", function, err);
+    /// Show a performance overlay with the FPS, draw calls, instances & garbage collector stats.
+    ///
+    /// The overlay uses the font asset with the reference name `default`.
+    /// It can also be toggled from scripts with `show-debug-overlay`.
+    pub fn debug_overlay(self, show: bool) -> Self {
+        self.runtime.run(|| {
+            Stats::borrow_mut().show_debug_overlay(show);
 
-                        return Ok(RuntimeResult(false));
-                    }
-                };
-                let _: Val = glsp::call(&update_func, &())?;
+            Ok(())
+        });
 
-                Ok(RuntimeResult(true))
-            })
-            .expect("Something unexpected went wrong with calling a GameLisp function");
+        self
+    }
+
+    /// Set the key that shows the egui development tools.
+    ///
+    /// The tools contain a camera, instance & entity inspector and the windows drawn by the
+    /// native systems with `System::editor_ui`. Only available with the `editor-ui` feature.
+    #[cfg(feature = "editor-ui")]
+    pub fn editor_key(mut self, key: KeyCode) -> Self {
+        self.editor_key = Some(key);
+
+        self
+    }
+
+    /// Show the egui development tools when the game starts, for editing levels.
+    ///
+    /// The level tools place, move, rotate & scale the instances of asset meshes with the mouse
+    /// and save them as a layout script that's placed with `load-level`. Only available with the
+    /// `editor-ui` feature, the tools still need a key set with `editor_key` to be hidden.
+    #[cfg(feature = "editor-ui")]
+    pub fn editor_open(mut self, editor_open: bool) -> Self {
+        self.editor_open = editor_open;
 
-        result.0
+        self
     }
-}
 
-impl EventHandler for ClogRun {
-    fn update(&mut self, _: &mut Context) {
-        self.call("engine:update");
+    /// Enable the developer console, opened & closed with a key.
+    ///
+    /// The console evaluates GameLisp expressions in the running game and uses the font asset
+    /// with the reference name `default`. It's disabled by default, so released games don't let
+    /// players run arbitrary code.
+    pub fn console_key(self, key: KeyCode) -> Self {
+        self.runtime.run(|| {
+            Console::borrow_mut().set_toggle_key(Some(key));
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Set whether pinching with two fingers changes the zoom of the camera, enabled by default.
+    pub fn touch_pinch_zoom(self, pinch_zoom: bool) -> Self {
+        self.runtime.run(|| {
+            Touches::borrow_mut().set_pinch_zoom(pinch_zoom);
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Set whether the update functions are skipped while the window is minimized, enabled by
+    /// default.
+    ///
+    /// `engine:pause` & `engine:resume` are called when the window is minimized & restored
+    /// regardless, scripts can also stop the updates themselves with `set-paused`.
+    pub fn pause_when_minimized(self, pause: bool) -> Self {
+        self.runtime.run(|| {
+            Window::borrow_mut().set_pause_when_minimized(pause);
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Set the directory where the save slots are written to, by default this is `saves`.
+    ///
+    /// On WASM the browser's local storage is used instead.
+    pub fn save_directory<P>(self, directory: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.runtime.run(|| {
+            Storage::borrow_mut().set_directory(directory);
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Set the directory where the values of `store-set` are written to, by default this is a
+    /// directory named after the game in the data directory of the platform.
+    ///
+    /// On WASM the browser's local storage is used instead.
+    pub fn store_directory<P>(self, directory: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.runtime.run(|| {
+            Store::borrow_mut().set_directory(directory);
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Add a fullscreen post-processing pass, applied after all previously added passes.
+    ///
+    /// When any pass is added the scene is rendered to a texture, which disables MSAA.
+    /// The GLSL fragment shader receives the result of the previous pass:
+    ///
+    /// ```glsl
+    /// uniform sampler2D u_texture;
+    /// uniform vec2 u_resolution;
+    /// uniform float u_time;
+    /// varying lowp vec2 uv;
+    /// ```
+    ///
+    /// The uniforms are declared in the order they appear in the shader after the builtin ones,
+    /// scripts can set them with `set-post-uniform`.
+    pub fn post_process<N, F>(
+        mut self,
+        name: N,
+        fragment_shader: F,
+        uniforms: &[(&str, UniformType)],
+    ) -> Self
+    where
+        N: Into<String>,
+        F: Into<String>,
+    {
+        self.post_passes.push(PostPassDescriptor {
+            name: name.into(),
+            fragment: fragment_shader.into(),
+            uniforms: uniforms
+                .iter()
+                .map(|(name, uniform_type)| (name.to_string(), *uniform_type))
+                .collect(),
+        });
+
+        self
     }
 
-    fn draw(&mut self, _: &mut Context) {
-        self.call("engine:render");
+    /// Add a custom shader that scripts can assign to meshes with `set-mesh-shader`.
+    ///
+    /// The shaders receive the same attributes & builtin uniforms as the default shader:
+    ///
+    /// ```glsl
+    /// uniform vec2 u_zoom;
+    /// uniform vec2 u_pan;
+    ///
+    /// attribute vec2 a_pos;
+    /// attribute vec4 a_color;
+    /// attribute vec2 a_slice;
+    /// attribute vec2 a_uv;
+    /// attribute vec2 a_fringe;
+    /// attribute vec3 a_inst_pos;
+    /// attribute float a_inst_rot;
+    /// attribute vec2 a_inst_scale;
+    /// attribute vec2 a_inst_skew;
+    /// attribute vec4 a_inst_color;
+    /// attribute vec2 a_inst_panel;
+    /// ```
+    ///
+    /// The uniforms are declared in the order they appear in the shader after the builtin ones,
+    /// scripts can set them with `set-shader-uniform`.
+    pub fn load_shader<N, V, F>(
+        mut self,
+        name: N,
+        vertex_shader: V,
+        fragment_shader: F,
+        uniforms: &[(&str, UniformType)],
+    ) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
+        F: Into<String>,
+    {
+        self.shaders.push(ShaderDescriptor {
+            name: name.into(),
+            vertex: vertex_shader.into(),
+            fragment: fragment_shader.into(),
+            uniforms: uniforms
+                .iter()
+                .map(|(name, uniform_type)| (name.to_string(), *uniform_type))
+                .collect(),
+        });
+
+        self
+    }
+
+    /// Add the translated strings of a language, the first language added is shown by default.
+    ///
+    /// Every line of the source is a `key = value` pair, `#` starts a comment. Scripts translate
+    /// keys with `tr`, replacing `{0}`, `{1}` etc. with the arguments, and switch languages with
+    /// `set-language`.
+    pub fn load_locale<L, S>(self, language: L, source: S) -> Result<Self>
+    where
+        L: AsRef<str>,
+        S: AsRef<str>,
+    {
+        self.runtime
+            .run(|| Ok(Locales::borrow_mut().load(language.as_ref(), source.as_ref())))
+            .ok_or_else(|| anyhow!("loading locale failed"))??;
+
+        Ok(self)
+    }
+
+    /// Set the font asset used for text in a language, for glyphs missing from the default font.
+    ///
+    /// Text blocks & widgets without an explicit font use the font of the current language.
+    pub fn locale_font<L, F>(self, language: L, font: F) -> Self
+    where
+        L: AsRef<str>,
+        F: AsRef<str>,
+    {
+        self.runtime.run(|| {
+            Locales::borrow_mut().set_font(language.as_ref(), font.as_ref());
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Add a SVG that will be uploaded to the GPU during the loading phase.
+    ///
+    /// The `reference_name` argument can be later used in scripts to create instances of the SVG
+    /// mesh.
+    /// The SVG format must not violate the [usvg limitations](https://github.com/RazrFalcon/resvg/tree/master/usvg#limitations).
+    pub fn load_svg<R, S>(self, reference_name: R, svg_source: S) -> Self
+    where
+        S: Into<String>,
+        R: Into<String>,
+    {
+        self.load_asset_bytes(
+            reference_name,
+            AssetKind::Svg,
+            svg_source.into().into_bytes(),
+        )
+    }
+
+    /// Add a SVG that will be tessellated with a tolerance during the loading phase.
+    ///
+    /// The tolerance is the maximum distance between the curves & the triangles in SVG units,
+    /// lower is smoother but has more triangles, the default is `0.1`. With `lod_levels` above
+    /// zero extra meshes are generated that are each four times coarser, they are drawn instead
+    /// when the camera is zoomed out far enough that the difference isn't visible.
+    pub fn load_svg_with_tolerance<R, S>(
+        self,
+        reference_name: R,
+        svg_source: S,
+        tolerance: f32,
+        lod_levels: usize,
+    ) -> Self
+    where
+        S: Into<String>,
+        R: Into<String>,
+    {
+        self.runtime.run(|| {
+            Assets::borrow_mut().add_svg_with_tolerance(
+                reference_name,
+                svg_source.into().into_bytes(),
+                tolerance,
+                lod_levels,
+            );
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Add a SVG that will be rendered to a texture during the loading phase instead of being
+    /// tessellated.
+    ///
+    /// Meant for very detailed SVGs that would tessellate into millions of triangles. The
+    /// `scale` is how many pixels a single unit of the SVG becomes, the mesh covers the same area
+    /// as with `load_svg` but gets blurry when zoomed in further. Only available with the
+    /// `rasterize` feature.
+    #[cfg(feature = "rasterize")]
+    pub fn load_svg_rasterized<R, S>(self, reference_name: R, svg_source: S, scale: f32) -> Self
+    where
+        S: Into<String>,
+        R: Into<String>,
+    {
+        self.runtime.run(|| {
+            Assets::borrow_mut().add_rasterized_svg(
+                reference_name,
+                svg_source.into().into_bytes(),
+                scale,
+            );
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Add a SVG baked with `bake_svg` or `bake_svg_file` that will be uploaded during the
+    /// loading phase.
+    ///
+    /// The SVG isn't parsed or tessellated at runtime, so it's available much faster than with
+    /// `load_svg`. Scripts get its mesh with `asset-mesh` as with any other SVG.
+    pub fn load_baked_mesh<R, B>(self, reference_name: R, baked_bytes: B) -> Self
+    where
+        B: Into<Vec<u8>>,
+        R: Into<String>,
+    {
+        self.load_asset_bytes(reference_name, AssetKind::BakedMesh, baked_bytes.into())
+    }
+
+    /// Add a TTF font that will be loaded during the loading phase.
+    pub fn load_font<R, B>(self, reference_name: R, font_bytes: B) -> Self
+    where
+        B: Into<Vec<u8>>,
+        R: Into<String>,
+    {
+        self.load_asset_bytes(reference_name, AssetKind::Font, font_bytes.into())
+    }
+
+    /// Add a PNG texture that will be loaded during the loading phase.
+    ///
+    /// The texture is packed in a shared atlas and scripts get a textured rectangle mesh with
+    /// `asset-mesh`, one world unit per pixel.
+    pub fn load_texture<R, B>(self, reference_name: R, png_bytes: B) -> Self
+    where
+        B: Into<Vec<u8>>,
+        R: Into<String>,
+    {
+        self.load_asset_bytes(reference_name, AssetKind::Texture, png_bytes.into())
+    }
+
+    /// Add a sound that will be loaded during the loading phase.
+    pub fn load_sound<R, B>(self, reference_name: R, sound_bytes: B) -> Self
+    where
+        B: Into<Vec<u8>>,
+        R: Into<String>,
+    {
+        self.load_asset_bytes(reference_name, AssetKind::Sound, sound_bytes.into())
+    }
+
+    /// Add a Tiled map in the TMX or JSON format that will be loaded during the loading phase.
+    ///
+    /// Only finite maps are supported, scripts create a tilemap from it with `create-tilemap`.
+    pub fn load_tilemap<R, B>(self, reference_name: R, map_bytes: B) -> Self
+    where
+        B: Into<Vec<u8>>,
+        R: Into<String>,
+    {
+        self.load_asset_bytes(reference_name, AssetKind::Tilemap, map_bytes.into())
+    }
+
+    /// Add a Lottie animation in the JSON format that will be loaded during the loading phase.
+    ///
+    /// Scripts play it with a puppet with `play-animation`, the layers move the parts with the
+    /// same name.
+    pub fn load_animation<R, B>(self, reference_name: R, json_bytes: B) -> Self
+    where
+        B: Into<Vec<u8>>,
+        R: Into<String>,
+    {
+        self.load_asset_bytes(reference_name, AssetKind::Animation, json_bytes.into())
+    }
+
+    /// Add an asset file that will be read during the loading phase.
+    ///
+    /// The type of the asset is determined by the file extension.
+    /// Assets can also be loaded at runtime from scripts with `load-asset`.
+    pub fn load_asset_file<R, P>(self, reference_name: R, path: P) -> Result<Self>
+    where
+        R: Into<String>,
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+        let kind = AssetKind::from_path(&path)
+            .ok_or_else(|| anyhow!("unknown asset type for file \"{}\"", path.display()))?;
+
+        self.runtime.run(|| {
+            Assets::borrow_mut().add_path(reference_name, kind, path);
+
+            Ok(())
+        });
+
+        Ok(self)
+    }
+
+    /// Queue an asset from memory in the asset manager.
+    fn load_asset_bytes<R>(self, reference_name: R, kind: AssetKind, bytes: Vec<u8>) -> Self
+    where
+        R: Into<String>,
+    {
+        self.runtime.run(|| {
+            Assets::borrow_mut().add_bytes(reference_name, kind, bytes);
+
+            Ok(())
+        });
+
+        self
+    }
+
+    /// Start the game.
+    pub fn start(mut self) {
+        miniquad::start(
+            Conf {
+                window_title: self.title.clone(),
+                window_width: self.width,
+                window_height: self.height,
+                loading: Loading::Embedded,
+                sample_count: self.sample_count,
+                fullscreen: self.fullscreen,
+                window_resizable: self.resizable,
+                high_dpi: self.high_dpi,
+                icon: self.icon.take(),
+                platform: Platform {
+                    swap_interval: self.vsync.map(|vsync| if vsync { 1 } else { 0 }),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            |mut ctx| UserData::owning(ClogRun::new(&mut ctx, self), ctx),
+        );
+    }
+
+    /// Run the game without a window or OpenGL context for a fixed amount of frames.
+    ///
+    /// Every frame `engine:update` & `engine:render` are called as usual with a fixed time step
+    /// of 1/60th of a second, but nothing is drawn. Instead what would have been rendered is
+    /// returned for every frame, so game logic can be tested. The assets queued before starting
+    /// are loaded before the first frame:
+    ///
+    /// ```rust
+    /// use clogs::Clog;
+    /// # fn main() -> anyhow::Result<()> {
+    /// let frames = Clog::new("Test")
+    ///     .main_script("(defn engine:update ()) (defn engine:render ())")?
+    ///     .run_headless(10)?;
+    /// assert_eq!(frames.len(), 10);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Fails when a script function fails, native systems aren't updated or rendered.
+    pub fn run_headless(self, frames: usize) -> Result<Vec<RenderedFrame>> {
+        let mut run = ClogRun::headless(self);
+
+        // Don't let the amount of frames depend on how fast the SVGs are tessellated
+        while !run
+            .runtime
+            .run(|| Ok(Assets::borrow().is_done()))
+            .unwrap_or(true)
+        {
+            run.runtime.run(|| {
+                Assets::borrow_mut().process(&mut Render::borrow_mut());
+
+                Ok(())
+            });
+            std::thread::yield_now();
+        }
+
+        let mut rendered = Vec::with_capacity(frames);
+        for frame in 0..frames {
+            let quit = run
+                .runtime
+                .run(|| Ok(Window::borrow().is_quitting()))
+                .unwrap_or(false);
+            if quit {
+                break;
+            }
+
+            if !run.update_game(None) {
+                return Err(anyhow!("engine:update failed during frame {}", frame));
+            }
+            if !run.started {
+                continue;
+            }
+
+            match run.render_game(None) {
+                Some(frame) => rendered.push(frame),
+                None => return Err(anyhow!("engine:render failed during frame {}", frame)),
+            }
+        }
+
+        run.shutdown();
+
+        Ok(rendered)
+    }
+
+    /// Check if a GLSP function is defined.
+    fn has_function(function_name: &str) -> bool {
+        match glsp::global(function_name) {
+            Ok(Val::GFn(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The actual game runtime.
+struct ClogRun {
+    /// The GameLisp runtime, also owns the render system.
+    runtime: Runtime,
+
+    /// Whether all assets queued before starting are loaded and the game is running.
+    started: bool,
+
+    /// Whether `engine:shutdown` is already called.
+    shut_down: bool,
+
+    /// Native systems updated & rendered alongside the scripts.
+    systems: Vec<Box<dyn System>>,
+
+    /// The game written in Rust, updated & rendered before the scripts.
+    game: Option<Box<dyn Game>>,
+
+    /// The seconds of game time the last update took.
+    delta: f64,
+
+    /// Waits between frames to keep the target frame rate.
+    limiter: FrameLimiter,
+
+    /// The seconds of game time every update takes, the time that really passed when `None`.
+    fixed_update_time: Option<f64>,
+
+    /// The development tools, only when a key is set to show them.
+    #[cfg(feature = "editor-ui")]
+    editor: Option<Editor>,
+}
+
+impl ClogRun {
+    /// Create a new runtime.
+    pub fn new(ctx: &mut Context, clog: Clog) -> Self {
+        let Clog {
+            runtime,
+            post_passes,
+            shaders,
+            depth_buffer,
+            vertex_antialiasing,
+            gamma_correct,
+            design_resolution,
+            systems,
+            game,
+            #[cfg(feature = "editor-ui")]
+            editor_key,
+            #[cfg(feature = "editor-ui")]
+            editor_open,
+            ..
+        } = clog;
+
+        // Make the render system available to the scripts
+        runtime.run(|| {
+            let mut render = Render::new(ctx, depth_buffer, vertex_antialiasing, gamma_correct);
+            if let Err(err) = render.set_design_resolution(design_resolution) {
+                log::error!("error setting design resolution: {}", err);
+            }
+            for post_pass in &post_passes {
+                if let Err(err) = render.add_post_pass(ctx, post_pass) {
+                    log::error!("error adding post-processing pass: {}", err);
+                }
+            }
+            // Applied last so it also filters the colors of the custom passes
+            if let Err(err) = render.add_colorblind_pass(ctx) {
+                log::error!("error adding colorblind filter: {}", err);
+            }
+            for shader in &shaders {
+                if let Err(err) = render.add_shader(ctx, shader) {
+                    log::error!("error adding shader: {}", err);
+                }
+            }
+            glsp::add_lib(render);
+
+            Ok(())
+        });
+
+        Self {
+            runtime,
+            started: false,
+            shut_down: false,
+            systems,
+            game,
+            delta: 0.0,
+            limiter: FrameLimiter::new(),
+            fixed_update_time: None,
+            #[cfg(feature = "editor-ui")]
+            editor: editor_key.map(|key| Editor::new(ctx, key, editor_open)),
+        }
+    }
+
+    /// Create a runtime without a window, nothing is drawn.
+    ///
+    /// Post-processing passes & custom shaders are ignored.
+    pub fn headless(clog: Clog) -> Self {
+        let Clog {
+            runtime,
+            width,
+            height,
+            depth_buffer,
+            design_resolution,
+            systems,
+            game,
+            ..
+        } = clog;
+
+        runtime.run(|| {
+            let mut render = Render::headless((width as f32, height as f32), depth_buffer);
+            if let Err(err) = render.set_design_resolution(design_resolution) {
+                log::error!("error setting design resolution: {}", err);
+            }
+            glsp::add_lib(render);
+
+            Ok(())
+        });
+
+        Self {
+            runtime,
+            started: false,
+            shut_down: false,
+            systems,
+            game,
+            delta: 0.0,
+            limiter: FrameLimiter::new(),
+            fixed_update_time: Some(HEADLESS_UPDATE_TIME),
+            #[cfg(feature = "editor-ui")]
+            editor: None,
+        }
+    }
+
+    /// Run a function of the main script, it may be missing when the game is written in Rust.
+    fn call_main(&self, function: &str) -> bool {
+        if self.game.is_some()
+            && !self
+                .runtime
+                .run(|| Ok(Clog::has_function(function)))
+                .unwrap_or(false)
+        {
+            return true;
+        }
+
+        self.call(function)
+    }
+
+    /// Run a function of the game written in Rust, returns whether it succeeded.
+    fn call_game<F>(&mut self, function: F) -> bool
+    where
+        F: FnOnce(&mut dyn Game, &mut Engine) -> Result<()>,
+    {
+        let game = match self.game {
+            Some(ref mut game) => game,
+            None => return true,
+        };
+
+        let delta = self.delta;
+        let result = self
+            .runtime
+            .run(|| Ok(Engine::run(delta, |engine| function(game.as_mut(), engine))));
+        match result {
+            Some(Ok(())) => true,
+            Some(Err(err)) => {
+                log::error!("error in game: {}", err);
+
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Run a GameLisp function.
+    fn call(&self, function: &str) -> bool {
+        self.call_with(function, || Ok(vec![]))
+    }
+
+    /// Run a GameLisp function with arguments created inside the runtime.
+    fn call_with<F>(&self, function: &str, args: F) -> bool
+    where
+        F: FnOnce() -> GResult<Vec<Val>>,
+    {
+        profile_scope!("script", function);
+
+        self.run_scripts(function, || {
+            let update_func: Root<GFn> = match glsp::global(function) {
+                Ok(Val::GFn(update)) => update,
+                Ok(val) => {
+                    log::error!("invalid {} function: {}", function, val);
+
+                    return Ok(false);
+                }
+                Err(err) => {
+                    log::error!("error finding {} function: {}", function, err);
+
+                    return Ok(false);
+                }
+            };
+            let _: Val = sandbox::call(&update_func, &args()?[..])?;
+
+            Ok(true)
+        })
+        .unwrap_or(false)
+    }
+
+    /// Run engine code calling script functions, showing the error screen when one fails.
+    ///
+    /// Returns `None` when a script failed, now or before, because a failed script halts the game
+    /// until it's reloaded.
+    fn run_scripts<F, R>(&self, name: &str, run: F) -> Option<R>
+    where
+        F: FnOnce() -> GResult<R>,
+    {
+        self.runtime
+            .run(|| {
+                if ErrorScreen::borrow().is_shown() {
+                    return Ok(None);
+                }
+
+                match run() {
+                    Ok(result) => Ok(Some(result)),
+                    Err(err) => {
+                        log::error!("error in {}: {}", name, err);
+                        ErrorScreen::borrow_mut().report(name, &err);
+
+                        Ok(None)
+                    }
+                }
+            })
+            .flatten()
+    }
+
+    /// Run a GameLisp function if it's defined, ignore it otherwise.
+    fn call_if_defined(&self, function: &str) -> bool {
+        let defined = self
+            .runtime
+            .run(|| Ok(Clog::has_function(function)))
+            .unwrap_or(false);
+
+        defined && self.call(function)
+    }
+
+    /// Run a GameLisp function with arguments if it's defined, ignore it otherwise.
+    fn call_with_if_defined<F>(&self, function: &str, args: F) -> bool
+    where
+        F: FnOnce() -> GResult<Vec<Val>>,
+    {
+        let defined = self
+            .runtime
+            .run(|| Ok(Clog::has_function(function)))
+            .unwrap_or(false);
+
+        defined && self.call_with(function, args)
+    }
+
+    /// Hide the error screen and evaluate the main script again, redefining its functions.
+    fn reload_script(&self) {
+        self.runtime.run(|| {
+            if !Scripts::borrow().is_added(MAIN_SCRIPT) {
+                return Ok(());
+            }
+
+            ErrorScreen::borrow_mut().clear();
+            // Evaluate the required scripts again so their changes are picked up
+            Scripts::borrow_mut().forget_loaded();
+            Mods::borrow_mut().forget_loaded();
+
+            match Scripts::load(MAIN_SCRIPT) {
+                Ok(_) => Mods::load(),
+                Err(err) => {
+                    log::error!("error reloading main script: {}", err);
+                    ErrorScreen::borrow_mut().report("main script", &err);
+                }
+            }
+
+            Ok(())
+        });
+    }
+
+    /// Call `engine:shutdown` once if it's defined.
+    fn shutdown(&mut self) {
+        if !self.shut_down {
+            self.shut_down = true;
+
+            if let Some(ref mut game) = self.game {
+                game.shutdown();
+            }
+            self.call_if_defined("engine:shutdown");
+
+            self.runtime.run(|| {
+                if let Err(err) = Replay::borrow_mut().save_on_shutdown() {
+                    log::error!("error saving recording: {}", err);
+                }
+
+                Ok(())
+            });
+
+            let runtime = &self.runtime;
+            for system in self.systems.iter_mut() {
+                runtime.run(|| {
+                    system.shutdown();
+
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    /// Run the function of the active scene if there is one.
+    fn call_scene(&self, function: &str) -> bool {
+        let name = self
+            .runtime
+            .run(|| {
+                Ok(Scenes::borrow()
+                    .active_scene()
+                    .map(|scene| Scenes::function_name(scene, function)))
+            })
+            .flatten();
+
+        match name {
+            Some(name) => self.call_if_defined(&name),
+            None => false,
+        }
+    }
+    /// Load the assets and update the game once.
+    ///
+    /// Headless & in screenshot tests the game clock advances with a fixed step, without a window
+    /// the native systems aren't updated. Returns whether `engine:update` succeeded.
+    fn update_game(&mut self, ctx: Option<&mut Context>) -> bool {
+        profile_scope!("update");
+
+        // Nothing moves while the error screen is shown
+        let failed = self
+            .runtime
+            .run(|| Ok(ErrorScreen::borrow().is_shown()))
+            .unwrap_or(false);
+        if failed {
+            return false;
+        }
+
+        // Load the assets that are queued, also the ones requested by scripts
+        let loading_done = self
+            .runtime
+            .run(|| Ok(Assets::borrow_mut().process(&mut Render::borrow_mut())))
+            .unwrap_or(false);
+
+        // Wait with running the game until all initial assets are loaded
+        if !self.started {
+            self.started = self
+                .runtime
+                .run(|| Ok(Assets::borrow().is_done()))
+                .unwrap_or(true);
+
+            if !self.started {
+                return true;
+            }
+
+            self.runtime.run(|| {
+                Render::borrow_mut().hide_loading_screen();
+
+                Ok(())
+            });
+            if !self.call_game(|game, engine| game.init(engine)) {
+                return false;
+            }
+            self.call_if_defined("engine:loading-done");
+        } else if loading_done {
+            // Assets requested by scripts are loaded
+            self.call_if_defined("engine:loading-done");
+        }
+
+        // Don't update the game while it's paused, but keep rendering
+        let fixed_update_time = self.fixed_update_time;
+        let update = self.runtime.run(|| {
+            let mut timers = Timers::borrow_mut();
+            // Without a window every update takes the same time so it's deterministic
+            let delta = match fixed_update_time {
+                Some(delta) => delta,
+                None => timers.tick(),
+            };
+
+            // The game clock doesn't advance so the paused time is skipped
+            if Window::borrow().is_paused() {
+                timers.advance_by(0.0);
+
+                return Ok(None);
+            }
+
+            // A recording that's played back replaces the time & input of the update
+            let (delta, input) = Replay::borrow_mut().next_frame(delta);
+            timers.advance_by(delta);
+
+            Ok(Some((delta, input)))
+        });
+        let (delta, input) = match update {
+            Some(Some(update)) => update,
+            _ => return true,
+        };
+
+        // Pass the recorded input to the game
+        for event in input {
+            self.input(event);
+        }
+
+        // Enter & exit the scenes requested during the previous frame
+        self.run_scripts("scene transition", Scenes::apply_transitions);
+
+        // Load the chunks of the world near the camera & unload the distant ones
+        self.runtime.run(Streaming::update);
+
+        // Pass the messages that arrived on the connections to the game
+        #[cfg(feature = "net")]
+        {
+            let messages = self
+                .runtime
+                .run(|| Ok(Net::borrow_mut().receive()))
+                .unwrap_or_default();
+            for (id, message) in messages {
+                self.call_with_if_defined("engine:net-message", || {
+                    Ok(vec![
+                        Val::Int(id),
+                        Val::Str(glsp::str_from_rust_str(&message)),
+                    ])
+                });
+            }
+        }
+
+        // Load the configs again that changed on disk
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let reloaded = self
+                .runtime
+                .run(Configs::reload_changed)
+                .unwrap_or_default();
+            for name in reloaded {
+                self.call_with_if_defined("engine:config-reloaded", || {
+                    Ok(vec![Val::Str(glsp::str_from_rust_str(&name))])
+                });
+            }
+        }
+
+        // Pass the events queued since the previous update to the subscribed functions
+        self.run_scripts("event handler", Events::dispatch);
+
+        // Update the native systems before the scripts
+        if let Some(ctx) = ctx {
+            let runtime = &self.runtime;
+            for system in self.systems.iter_mut() {
+                runtime.run(|| system.update(ctx));
+            }
+        }
+
+        // Call the functions of the HTTP requests that got a response
+        #[cfg(feature = "http")]
+        self.run_scripts("HTTP callback", Http::finish_done);
+
+        // Call the functions of the paths found in the background
+        self.run_scripts("pathfinding callback", Pathfinding::finish_done);
+
+        // Call the scheduled functions that are due
+        self.run_scripts("timer", Timers::fire_due);
+
+        // Continue the coroutines that are done waiting
+        self.run_scripts("task", Tasks::resume_all);
+
+        // Tick the state machines & behavior trees
+        self.run_scripts("AI", Ai::tick_all);
+
+        // Move the instances along the paths they follow
+        self.runtime
+            .run(|| Paths::borrow_mut().update(delta, &mut Render::borrow_mut()));
+
+        // Move the parts of the puppets playing an animation
+        self.runtime
+            .run(|| Animations::borrow_mut().update(delta, &mut Render::borrow_mut()));
+
+        // Show the next frames of the flipbooks
+        self.runtime
+            .run(|| Flipbooks::borrow_mut().update(delta, &mut Render::borrow_mut()));
+
+        // Advance the screen effects, calling the functions of the finished fades
+        self.run_scripts("fade callback", || Effects::update(delta));
+
+        self.delta = delta;
+        let succeeded =
+            self.call_game(|game, engine| game.update(engine)) && self.call_main("engine:update");
+        self.call_scene("update");
+
+        // Store the state for rewinding after the scripts changed it
+        self.run_scripts("rewind", || Rewind::capture(delta));
+
+        // The actions pressed during this update aren't pressed during the next
+        self.runtime.run(|| {
+            Actions::borrow_mut().end_update();
+
+            Ok(())
+        });
+
+        succeeded
+    }
+
+    /// Pass input from the player or a recording to the game.
+    fn input(&mut self, event: InputEvent) {
+        self.runtime.run(|| {
+            Rewind::borrow_mut().record(&event);
+
+            Ok(())
+        });
+
+        match event {
+            InputEvent::KeyDown(key) => {
+                self.runtime.run(|| {
+                    // Keys used for typing aren't passed to the game
+                    if TextInput::borrow_mut().key_down(&key)? {
+                        return Ok(());
+                    }
+
+                    Actions::key_down(&key)?;
+                    Events::emit_key_event("key-down", &key)
+                });
+            }
+            InputEvent::KeyUp(key) => {
+                self.runtime.run(|| {
+                    TextInput::borrow_mut().key_up(&key);
+
+                    Actions::key_up(&key)?;
+                    Events::emit_key_event("key-up", &key)
+                });
+            }
+            InputEvent::Char(character) => {
+                self.runtime
+                    .run(|| TextInput::borrow_mut().char_typed(character));
+            }
+            InputEvent::MouseDelta { dx, dy } => {
+                self.runtime
+                    .run(|| Events::emit_engine_event("mouse-delta", (dx, dy)));
+            }
+            InputEvent::Touch { phase, id, x, y } => {
+                let events = self
+                    .runtime
+                    .run(|| {
+                        Ok(Touches::borrow_mut().touch(phase, id, x, y, &mut Render::borrow_mut()))
+                    })
+                    .unwrap_or_default();
+
+                for event in events {
+                    // Touches on the widgets aren't passed to the game
+                    if self.run_scripts("widget", || Ui::touch(&event)) == Some(true) {
+                        continue;
+                    }
+
+                    self.call_with_if_defined("engine:touch", || Ok(vec![event.to_val()?]));
+                    self.runtime
+                        .run(|| Events::emit_engine_event("touch", event.to_val()?));
+                }
+            }
+        }
+    }
+
+    /// Record input from the player and pass it to the game, unless a recording is played back.
+    fn player_input(&mut self, event: InputEvent) {
+        let playing = self
+            .runtime
+            .run(|| {
+                let mut replay = Replay::borrow_mut();
+                replay.record(event.clone());
+
+                Ok(replay.is_playing())
+            })
+            .unwrap_or(false);
+
+        if !playing {
+            self.input(event);
+        }
+    }
+
+    /// Call the render functions and draw the frame.
+    ///
+    /// Without a window nothing is drawn and the native systems aren't rendered, instead what
+    /// would have been rendered is returned if `engine:render` succeeded.
+    fn render_game(&mut self, mut ctx: Option<&mut Context>) -> Option<RenderedFrame> {
+        profile_scope!("render");
+
+        let succeeded =
+            self.call_game(|game, engine| game.render(engine)) && self.call_main("engine:render");
+        self.call_scene("render");
+
+        if let Some(ref mut ctx) = ctx {
+            let runtime = &self.runtime;
+            for system in self.systems.iter_mut() {
+                runtime.run(|| system.render(ctx, &mut Render::borrow_mut()));
+            }
+        }
+
+        let frame = self.runtime.run(|| {
+            let mut render = Render::borrow_mut();
+            // Show the tilemap chunks that came on screen
+            Tilemaps::borrow_mut().update(&mut render);
+            // Lay out the widgets for the current screen size & camera
+            Ui::borrow_mut().update(&mut render);
+            Cursor::borrow_mut().update(&mut render);
+            // Cast the shadows for the current positions of the occluders
+            Lighting::borrow_mut().update(&mut render);
+            Effects::borrow().apply(&mut render);
+            if let Err(err) = Stats::borrow().draw_overlay(&mut render) {
+                log::error!("error drawing debug overlay: {}", err);
+            }
+            if let Err(err) = Logger::borrow().draw_overlay(&mut render) {
+                log::error!("error drawing log overlay: {}", err);
+            }
+            if let Err(err) = Console::borrow().draw(&mut render) {
+                log::error!("error drawing console: {}", err);
+            }
+            if let Err(err) = ErrorScreen::borrow().draw(&mut render) {
+                log::error!("error drawing error screen: {}", err);
+            }
+
+            let ctx = match ctx.as_mut() {
+                Some(ctx) => ctx,
+                None => return Ok(Some(render.record_frame())),
+            };
+
+            // Save the frame when a screenshot is requested
+            let (paths, keep_pixels) = {
+                let mut capture = Capture::borrow_mut();
+
+                (capture.take_frame_paths(), capture.take_pixel_request())
+            };
+            if !paths.is_empty() || keep_pixels {
+                let (width, height, pixels) = render.capture_frame(ctx);
+                for path in paths {
+                    if let Err(err) = capture::save_png(&path, width, height, &pixels) {
+                        log::error!("error saving frame \"{}\": {}", path.display(), err);
+                    }
+                }
+                if keep_pixels {
+                    Capture::borrow_mut().store_pixels(width, height, pixels);
+                }
+            }
+
+            render.draw(ctx);
+
+            Ok(None)
+        });
+
+        if let Some(ctx) = ctx {
+            // Draw the development tools on top of the game
+            #[cfg(feature = "editor-ui")]
+            {
+                if let Some(ref mut editor) = self.editor {
+                    editor.draw(ctx, &self.runtime, &mut self.systems);
+                }
+            }
+
+            ctx.commit_frame();
+        }
+
+        frame.flatten().filter(|_| succeeded)
+    }
+}
+
+impl EventHandler for ClogRun {
+    fn update(&mut self, ctx: &mut Context) {
+        // Close the game when a script called `quit` during the previous frame
+        let quit = self
+            .runtime
+            .run(|| Ok(Window::borrow().is_quitting()))
+            .unwrap_or(false);
+        if quit {
+            self.shutdown();
+            ctx.order_quit();
+
+            return;
+        }
+
+        // Don't draw more frames than requested
+        let target_fps = self
+            .runtime
+            .run(|| Ok(Window::borrow().target_fps()))
+            .flatten();
+        self.limiter.wait(target_fps);
+
+        // Apply the window changes requested by scripts
+        self.runtime.run(|| {
+            TextInput::borrow_mut().apply(ctx);
+            Cursor::borrow_mut().apply(ctx);
+
+            Window::borrow_mut().apply(ctx)
+        });
+
+        // The window moved to a monitor with another pixel density
+        let dpi_scale = ctx.dpi_scale();
+        let dpi_changed = self
+            .runtime
+            .run(|| Ok(Render::borrow_mut().set_dpi_scale(dpi_scale)))
+            .unwrap_or(false);
+        if dpi_changed && self.started {
+            self.call_with_if_defined("engine:dpi-changed", || Ok(vec![Val::Flo(dpi_scale)]));
+            self.runtime
+                .run(|| Events::emit_engine_event("dpi-changed", dpi_scale));
+        }
+
+        self.update_game(Some(ctx));
+    }
+
+    fn draw(&mut self, ctx: &mut Context) {
+        self.runtime.run(|| {
+            Stats::borrow_mut().begin_frame();
+
+            Ok(())
+        });
+
+        if !self.started {
+            self.runtime.run(|| {
+                let progress = Assets::borrow().progress();
+                Render::borrow_mut().render_loading_screen(ctx, progress);
+
+                Ok(())
+            });
+
+            return;
+        }
+
+        self.render_game(Some(ctx));
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, keycode: KeyCode, _keymods: KeyMods, _: bool) {
+        if keycode == KeyCode::F5 {
+            let failed = self
+                .runtime
+                .run(|| Ok(ErrorScreen::borrow().is_shown()))
+                .unwrap_or(false);
+            if failed {
+                self.reload_script();
+
+                return;
+            }
+        }
+
+        // Keys used by the development tools aren't passed to the console or the game
+        #[cfg(feature = "editor-ui")]
+        {
+            if let Some(ref mut editor) = self.editor {
+                if editor.key_down(ctx, keycode, _keymods) {
+                    return;
+                }
+            }
+        }
+
+        let started = self.started;
+        let pass_to_game = self.runtime.run(|| {
+            // Don't hold the borrow during evaluation, the expression might use the console
+            let was_open = Console::borrow().is_open();
+            let input = Console::borrow_mut().key_down(keycode);
+            if let Some(input) = input {
+                Console::eval(&input);
+            }
+
+            // Keys typed in the console or opening it aren't passed to the game
+            Ok(started && !was_open && !Console::borrow().is_open())
+        });
+
+        if pass_to_game == Some(true) {
+            let key = event::key_name(keycode);
+            let paste = self
+                .runtime
+                .run(|| Ok(TextInput::borrow().is_paste(&key)))
+                .unwrap_or(false);
+            self.player_input(InputEvent::KeyDown(key));
+
+            // Pasted text is typed so it's part of recorded replays
+            if paste {
+                for character in ctx.clipboard_get().unwrap_or_default().chars() {
+                    self.player_input(InputEvent::Char(character));
+                }
+            }
+        }
+    }
+
+    fn key_up_event(&mut self, _: &mut Context, keycode: KeyCode, _keymods: KeyMods) {
+        #[cfg(feature = "editor-ui")]
+        {
+            if let Some(ref mut editor) = self.editor {
+                if editor.key_up(keycode, _keymods) {
+                    return;
+                }
+            }
+        }
+
+        let console_open = self
+            .runtime
+            .run(|| Ok(Console::borrow().is_open()))
+            .unwrap_or(false);
+
+        if self.started && !console_open {
+            self.player_input(InputEvent::KeyUp(event::key_name(keycode)));
+        }
+    }
+
+    fn touch_event(&mut self, ctx: &mut Context, phase: TouchPhase, id: u64, x: f32, y: f32) {
+        let (x, y) = (x / ctx.dpi_scale(), y / ctx.dpi_scale());
+
+        // Only pass the touches to the game when it's running
+        if self.started {
+            self.player_input(InputEvent::Touch { phase, id, x, y });
+        }
+    }
+
+    fn mouse_motion_event(&mut self, ctx: &mut Context, x: f32, y: f32) {
+        #[cfg(feature = "editor-ui")]
+        {
+            if let Some(ref mut editor) = self.editor {
+                editor.mouse_motion(ctx, &self.runtime, x, y);
+            }
+        }
+
+        let (x, y) = (x / ctx.dpi_scale(), y / ctx.dpi_scale());
+        self.runtime.run(|| {
+            Cursor::borrow_mut().mouse_moved(x, y);
+
+            Ok(())
+        });
+    }
+
+    fn raw_mouse_motion(&mut self, ctx: &mut Context, dx: f32, dy: f32) {
+        let (dx, dy) = (dx / ctx.dpi_scale(), dy / ctx.dpi_scale());
+
+        // The movement is only used for controlling the game while the cursor is grabbed
+        let locked = self.runtime.run(|| Ok(Cursor::borrow().is_locked()));
+        if self.started && locked == Some(true) {
+            self.player_input(InputEvent::MouseDelta { dx, dy });
+        }
+    }
+
+    #[cfg(feature = "editor-ui")]
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, dx: f32, dy: f32) {
+        if let Some(ref mut editor) = self.editor {
+            editor.mouse_wheel(ctx, dx, dy);
+        }
+    }
+
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, _button: MouseButton, x: f32, y: f32) {
+        #[cfg(feature = "editor-ui")]
+        {
+            if let Some(ref mut editor) = self.editor {
+                editor.mouse_button_down(ctx, &self.runtime, _button, x, y);
+            }
+        }
+
+        let (x, y) = (x / ctx.dpi_scale(), y / ctx.dpi_scale());
+        let reload = self
+            .runtime
+            .run(|| Ok(ErrorScreen::borrow().is_on_reload_button(&Render::borrow(), x, y)))
+            .unwrap_or(false);
+        if reload {
+            self.reload_script();
+        }
+    }
+
+    #[cfg(feature = "editor-ui")]
+    fn mouse_button_up_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        if let Some(ref mut editor) = self.editor {
+            editor.mouse_button_up(ctx, button, x, y);
+        }
+    }
+
+    fn window_minimized_event(&mut self, _: &mut Context) {
+        self.runtime.run(|| {
+            Window::borrow_mut().set_minimized(true);
+
+            Ok(())
+        });
+
+        if self.started {
+            self.call_if_defined("engine:pause");
+        }
+    }
+
+    fn window_restored_event(&mut self, _: &mut Context) {
+        self.runtime.run(|| {
+            Window::borrow_mut().set_minimized(false);
+
+            Ok(())
+        });
+
+        if self.started {
+            self.call_if_defined("engine:resume");
+        }
+    }
+
+    fn quit_requested_event(&mut self, ctx: &mut Context) {
+        // The game can cancel closing by returning `#f`, for example to ask for saving first
+        let cancel = self
+            .runtime
+            .run(|| {
+                if !Clog::has_function("engine:quit-requested") {
+                    return Ok(false);
+                }
+
+                let quit_requested: Root<GFn> = glsp::global("engine:quit-requested")?;
+                let result: Val = sandbox::call(&quit_requested, &())?;
+
+                Ok(matches!(result, Val::Bool(false)))
+            })
+            .unwrap_or(false);
+
+        if cancel {
+            ctx.cancel_quit();
+        } else {
+            self.shutdown();
+        }
+    }
+
+    fn char_event(&mut self, _: &mut Context, character: char, _: KeyMods, _: bool) {
+        #[cfg(feature = "editor-ui")]
+        {
+            if let Some(ref mut editor) = self.editor {
+                if editor.char_typed(character) {
+                    return;
+                }
+            }
+        }
+
+        let used_by_console = self
+            .runtime
+            .run(|| Ok(Console::borrow_mut().char_typed(character)))
+            .unwrap_or(false);
+
+        if self.started && !used_by_console {
+            self.player_input(InputEvent::Char(character));
+        }
+    }
+}
+
+impl Drop for ClogRun {
+    fn drop(&mut self) {
+        self.shutdown();
     }
 }
@@ -0,0 +1,645 @@
+use crate::{
+    postprocess,
+    render::{self, Instance, InstanceRef, Render, Vertex},
+};
+use glsp::{bail, lib, rdata, rfn, GResult, Runtime};
+use lyon::tessellation::VertexBuffers;
+use miniquad::{graphics::*, Context};
+use std::{f32::consts::PI, mem};
+
+/// Meshes with this draw order or higher, like the UI, are drawn on top of the lighting.
+pub const UNLIT_DRAW_ORDER: i32 = 1 << 20;
+/// In how many rays a full circle of light is divided when nothing blocks it.
+const LIGHT_SEGMENTS: usize = 64;
+/// How far next to the corners of occluders extra rays are cast, in radians, so the light
+/// continues past them.
+const CORNER_OFFSET: f32 = 0.0001;
+/// The maximum amount of vertices of all lights together.
+const MAX_LIGHT_VERTICES: usize = u16::MAX as usize;
+/// The maximum amount of indices of all lights together.
+const MAX_LIGHT_INDICES: usize = MAX_LIGHT_VERTICES * 3;
+
+/// The vertex shader multiplying the light map with the scene, a quad covering the whole screen.
+const COMPOSITE_VERTEX: &str = r#"#version 100
+
+attribute vec2 a_pos;
+
+varying lowp vec2 uv;
+
+void main() {
+    gl_Position = vec4(a_pos, 0.0, 1.0);
+    uv = a_pos * 0.5 + 0.5;
+}
+"#;
+
+/// The fragment shader multiplying the light map with the scene.
+const COMPOSITE_FRAGMENT: &str = r#"#version 100
+
+varying lowp vec2 uv;
+
+uniform sampler2D u_light;
+
+void main() {
+    gl_FragColor = texture2D(u_light, uv);
+}
+"#;
+
+rdata! {
+/// A reference to a light.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LightRef(u32);
+}
+
+/// A light casting shadows behind the occluders.
+#[derive(Debug, Clone)]
+struct Light {
+    /// The unique identifier of the light.
+    id: u32,
+    /// The position in world coordinates.
+    position: [f32; 2],
+    /// How far the light reaches, it fades out linearly towards it.
+    radius: f32,
+    /// The color multiplied by the intensity.
+    color: [f32; 3],
+    /// The direction & half the width of a cone light in radians, `None` for point lights.
+    cone: Option<(f32, f32)>,
+}
+
+lib! {
+/// Point & cone lights, with shadows cast by the outlines of occluder instances.
+///
+/// When lighting is enabled the lights are added together on top of an ambient color and the
+/// result is multiplied with the scene. Meshes with a draw order of `1048576` or higher, like the
+/// UI, are drawn on top of it. Colors are passed as `(arr red green blue)` with values from 0 to
+/// 255.
+///
+/// ```gamelisp
+/// (set-ambient-light 20 20 40)
+/// (let torch (add-light 0 0 300 (arr 255 200 120)))
+/// (add-occluder (add-instance (create-rect 40 40) 100 0))
+/// ```
+pub struct Lighting {
+    /// The color of unlit areas, lighting is disabled when `None`.
+    ambient: Option<[f32; 3]>,
+    /// All lights in the order they were added.
+    lights: Vec<Light>,
+    /// The identifier that will be given to the next light.
+    next_id: u32,
+    /// The instances whose outlines block the light.
+    occluders: Vec<InstanceRef>,
+}
+}
+
+impl Lighting {
+    /// Create with lighting disabled.
+    pub fn new() -> Self {
+        Self {
+            ambient: None,
+            lights: vec![],
+            next_id: 0,
+            occluders: vec![],
+        }
+    }
+
+    /// Cast the shadows of all lights and pass the result to the light map.
+    ///
+    /// Must be called before rendering.
+    pub fn update(&mut self, render: &mut Render) {
+        render.light_map_mut().set_ambient(self.ambient);
+        if self.ambient.is_none() {
+            return;
+        }
+
+        // Forget the occluders that were removed
+        self.occluders
+            .retain(|occluder| render.instance(*occluder).is_some());
+
+        // The edges of all occluders in world coordinates
+        let edges: Vec<[[f32; 2]; 2]> = self
+            .occluders
+            .iter()
+            .filter_map(|occluder| render.instance_outline(*occluder))
+            .flat_map(|outline| {
+                outline
+                    .windows(2)
+                    .map(|edge| [edge[0], edge[1]])
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let geometry = render.light_map_mut().geometry_mut();
+        geometry.vertices.clear();
+        geometry.indices.clear();
+        for light in self.lights.iter() {
+            let points = cast_rays(light, &edges);
+            if geometry.vertices.len() + points.len() + 1 > MAX_LIGHT_VERTICES {
+                break;
+            }
+
+            // A triangle fan from the center, fading out towards the radius
+            let [red, green, blue] = light.color;
+            let center = geometry.vertices.len() as u16;
+            geometry
+                .vertices
+                .push(Vertex::new(light.position, [red, green, blue, 1.0]));
+            for (point, fraction) in points.iter() {
+                let falloff = (1.0 - fraction).max(0.0);
+                geometry.vertices.push(Vertex::new(
+                    *point,
+                    [red * falloff, green * falloff, blue * falloff, 1.0],
+                ));
+            }
+            for index in 1..points.len() as u16 {
+                geometry
+                    .indices
+                    .extend_from_slice(&[center, center + index, center + index + 1]);
+            }
+        }
+    }
+
+    /// Enable lighting with the color of unlit areas from a script.
+    ///
+    /// ```gamelisp
+    /// (set-ambient-light 30 30 50)
+    /// ```
+    fn set_ambient_light(&mut self, red: u8, green: u8, blue: u8) {
+        self.ambient = Some(light_color(Some((red, green, blue)), None));
+    }
+
+    /// Disable lighting from a script, the lights & occluders are kept.
+    fn disable_lighting(&mut self) {
+        self.ambient = None;
+    }
+
+    /// Add a light shining in every direction from a script.
+    ///
+    /// ```gamelisp
+    /// (let lamp (add-light 100 50 250 (arr 255 220 180)))
+    /// ```
+    fn add_light(&mut self, x: f32, y: f32, radius: f32, color: Option<(u8, u8, u8)>) -> LightRef {
+        self.push_light([x, y], radius, color, None)
+    }
+
+    /// Add a light shining in a cone from a script.
+    ///
+    /// The direction & the width of the cone are in radians, a direction of zero is to the right.
+    ///
+    /// ```gamelisp
+    /// (let flashlight (add-cone-light 0 0 400 0 0.8))
+    /// ```
+    fn add_cone_light(
+        &mut self,
+        x: f32,
+        y: f32,
+        radius: f32,
+        direction: f32,
+        angle: f32,
+        color: Option<(u8, u8, u8)>,
+    ) -> LightRef {
+        let cone = Some((direction, (angle / 2.0).max(0.0).min(PI)));
+
+        self.push_light([x, y], radius, color, cone)
+    }
+
+    /// Add a light with a new identifier.
+    fn push_light(
+        &mut self,
+        position: [f32; 2],
+        radius: f32,
+        color: Option<(u8, u8, u8)>,
+        cone: Option<(f32, f32)>,
+    ) -> LightRef {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.lights.push(Light {
+            id,
+            position,
+            radius: radius.max(0.0),
+            color: light_color(color, None),
+            cone,
+        });
+
+        LightRef(id)
+    }
+
+    /// Get a light that still exists.
+    fn light_mut(&mut self, light: &LightRef) -> GResult<&mut Light> {
+        match self.lights.iter_mut().find(|other| other.id == light.0) {
+            Some(light) => Ok(light),
+            None => bail!("light doesn't exist anymore"),
+        }
+    }
+
+    /// Move a light from a script.
+    fn set_light_position(&mut self, light: &LightRef, x: f32, y: f32) -> GResult<()> {
+        self.light_mut(light)?.position = [x, y];
+
+        Ok(())
+    }
+
+    /// Point a cone light in another direction from a script, the direction is in radians.
+    ///
+    /// ```gamelisp
+    /// (set-light-direction flashlight (atan2 (- mouse-y y) (- mouse-x x)))
+    /// ```
+    fn set_light_direction(&mut self, light: &LightRef, direction: f32) -> GResult<()> {
+        match self.light_mut(light)?.cone {
+            Some((ref mut cone_direction, _)) => *cone_direction = direction,
+            None => bail!("only cone lights have a direction"),
+        }
+
+        Ok(())
+    }
+
+    /// Change how far a light reaches from a script.
+    fn set_light_radius(&mut self, light: &LightRef, radius: f32) -> GResult<()> {
+        self.light_mut(light)?.radius = radius.max(0.0);
+
+        Ok(())
+    }
+
+    /// Change the color & the intensity of a light from a script, the intensity defaults to 1.
+    ///
+    /// ```gamelisp
+    /// (set-light-color lamp (arr 255 220 180) (+ 1 (* (sin time) 0.1)))
+    /// ```
+    fn set_light_color(
+        &mut self,
+        light: &LightRef,
+        color: (u8, u8, u8),
+        intensity: Option<f32>,
+    ) -> GResult<()> {
+        self.light_mut(light)?.color = light_color(Some(color), intensity);
+
+        Ok(())
+    }
+
+    /// Remove a light from a script, returns whether it still existed.
+    fn remove_light(&mut self, light: &LightRef) -> bool {
+        let count = self.lights.len();
+        self.lights.retain(|other| other.id != light.0);
+
+        self.lights.len() != count
+    }
+
+    /// Let the outline of the mesh of an instance cast shadows from a script.
+    ///
+    /// Only meshes with an outline, like the primitive shapes, cast shadows.
+    fn add_occluder(&mut self, instance: &InstanceRef) {
+        if !self.occluders.contains(instance) {
+            self.occluders.push(*instance);
+        }
+    }
+
+    /// Stop an instance from casting shadows from a script.
+    fn remove_occluder(&mut self, instance: &InstanceRef) {
+        self.occluders.retain(|occluder| occluder != instance);
+    }
+
+    /// Register the lighting and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Lighting::new());
+
+            glsp::bind_rfn("set-ambient-light", rfn!(Lighting::set_ambient_light))?;
+            glsp::bind_rfn("disable-lighting", rfn!(Lighting::disable_lighting))?;
+            glsp::bind_rfn("add-light", rfn!(Lighting::add_light))?;
+            glsp::bind_rfn("add-cone-light", rfn!(Lighting::add_cone_light))?;
+            glsp::bind_rfn("set-light-position", rfn!(Lighting::set_light_position))?;
+            glsp::bind_rfn("set-light-direction", rfn!(Lighting::set_light_direction))?;
+            glsp::bind_rfn("set-light-radius", rfn!(Lighting::set_light_radius))?;
+            glsp::bind_rfn("set-light-color", rfn!(Lighting::set_light_color))?;
+            glsp::bind_rfn("remove-light", rfn!(Lighting::remove_light))?;
+            glsp::bind_rfn("add-occluder", rfn!(Lighting::add_occluder))?;
+            glsp::bind_rfn("remove-occluder", rfn!(Lighting::remove_occluder))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// The GPU resources of the light map, created when lighting is first enabled.
+struct LightTarget {
+    /// The texture the lights are drawn to.
+    color: Texture,
+    /// The depth texture of the render pass, unused.
+    depth: Texture,
+    /// The pass drawing to the texture.
+    pass: RenderPass,
+    /// The pipeline adding the lights together.
+    light_pipeline: Pipeline,
+    /// The dynamic buffers for the geometry of the lights.
+    light_bindings: Bindings,
+    /// The pipeline multiplying the texture with the scene.
+    composite_pipeline: Pipeline,
+    /// The fullscreen quad sampling the texture.
+    quad: Bindings,
+}
+
+/// The lights drawn to a texture that's multiplied with the scene.
+pub struct LightMap {
+    /// The color of unlit areas, lighting is disabled when `None`.
+    ambient: Option<[f32; 3]>,
+    /// The triangles of all lights in world coordinates, with the light in the vertex colors.
+    geometry: VertexBuffers<Vertex, u16>,
+    /// The texture & pipelines, created when lighting is first enabled.
+    target: Option<LightTarget>,
+    /// Whether the lights are added in linear space, when the scene is gamma corrected.
+    linear: bool,
+}
+
+impl LightMap {
+    /// Create with lighting disabled.
+    pub fn new() -> Self {
+        Self {
+            ambient: None,
+            geometry: VertexBuffers::new(),
+            target: None,
+            linear: false,
+        }
+    }
+
+    /// Convert the colors of the lights to linear space, must be set before lighting is enabled.
+    pub fn set_linear(&mut self, linear: bool) {
+        self.linear = linear;
+    }
+
+    /// Enable lighting with the color of unlit areas, or disable it with `None`.
+    pub fn set_ambient(&mut self, ambient: Option<[f32; 3]>) {
+        self.ambient = ambient;
+    }
+
+    /// Whether the light map is multiplied with the scene.
+    pub fn is_enabled(&self) -> bool {
+        self.ambient.is_some()
+    }
+
+    /// The triangles of the lights that will be drawn.
+    pub fn geometry_mut(&mut self) -> &mut VertexBuffers<Vertex, u16> {
+        &mut self.geometry
+    }
+
+    /// Draw the lights to the texture, resized to the frame when needed.
+    ///
+    /// Must be called before the scene is drawn.
+    pub fn draw_lights(
+        &mut self,
+        ctx: &mut Context,
+        size: (u32, u32),
+        uniforms: &render::geom_shader::Uniforms,
+    ) {
+        let [red, green, blue] = match self.ambient {
+            Some(ambient) if self.linear => [
+                ambient[0].powf(2.2),
+                ambient[1].powf(2.2),
+                ambient[2].powf(2.2),
+            ],
+            Some(ambient) => ambient,
+            None => return,
+        };
+
+        let target = match self.target.take() {
+            Some(target) if (target.color.width, target.color.height) == size => target,
+            Some(target) => {
+                target.pass.delete(ctx);
+                target.color.delete();
+                target.depth.delete();
+
+                LightTarget::new(ctx, size, target.light_pipeline, target.light_bindings)
+            }
+            None => {
+                let defines: &[&str] = if self.linear { &["LINEAR"] } else { &[] };
+                let shader = Shader::new(
+                    ctx,
+                    render::geom_shader::VERTEX,
+                    &render::with_defines(render::geom_shader::FRAGMENT, defines),
+                    render::geom_shader::META,
+                )
+                .expect("Building light shader failed");
+                // Lights are added together
+                let light_pipeline = render::instanced_pipeline(
+                    ctx,
+                    shader,
+                    PipelineParams {
+                        color_blend: Some(BlendState::new(
+                            Equation::Add,
+                            BlendFactor::One,
+                            BlendFactor::One,
+                        )),
+                        ..Default::default()
+                    },
+                );
+
+                let vertex_buffer = Buffer::stream(
+                    ctx,
+                    BufferType::VertexBuffer,
+                    MAX_LIGHT_VERTICES * mem::size_of::<Vertex>(),
+                );
+                let index_buffer = Buffer::stream(
+                    ctx,
+                    BufferType::IndexBuffer,
+                    MAX_LIGHT_INDICES * mem::size_of::<u16>(),
+                );
+                let instance_buffer =
+                    Buffer::stream(ctx, BufferType::VertexBuffer, mem::size_of::<Instance>());
+                instance_buffer.update(ctx, &[Instance::new(0.0, 0.0)]);
+                let light_bindings = Bindings {
+                    vertex_buffers: vec![vertex_buffer, instance_buffer],
+                    index_buffer,
+                    images: vec![],
+                };
+
+                LightTarget::new(ctx, size, light_pipeline, light_bindings)
+            }
+        };
+
+        ctx.begin_pass(
+            Some(target.pass),
+            PassAction::clear_color(red, green, blue, 1.0),
+        );
+        let index_count = self.geometry.indices.len().min(MAX_LIGHT_INDICES) / 3 * 3;
+        if index_count > 0 {
+            target.light_bindings.vertex_buffers[0].update(ctx, &self.geometry.vertices);
+            target
+                .light_bindings
+                .index_buffer
+                .update(ctx, &self.geometry.indices[..index_count]);
+
+            ctx.apply_pipeline(&target.light_pipeline);
+            ctx.apply_bindings(&target.light_bindings);
+            ctx.apply_uniforms(uniforms);
+            ctx.draw(0, index_count as i32, 1);
+        }
+        ctx.end_render_pass();
+
+        self.target = Some(target);
+    }
+
+    /// Multiply the scene that's drawn so far with the light map.
+    ///
+    /// Must be called inside the render pass of the scene.
+    pub fn composite(&mut self, ctx: &mut Context) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        if let Some(ref target) = self.target {
+            ctx.apply_pipeline(&target.composite_pipeline);
+            ctx.apply_bindings(&target.quad);
+            ctx.draw(0, 6, 1);
+        }
+    }
+}
+
+impl LightTarget {
+    /// Create the texture with the size of the screen and the pipeline sampling it.
+    fn new(
+        ctx: &mut Context,
+        (width, height): (u32, u32),
+        light_pipeline: Pipeline,
+        light_bindings: Bindings,
+    ) -> Self {
+        let (color, depth, pass) = postprocess::render_target(ctx, width, height);
+
+        let shader = Shader::new(
+            ctx,
+            COMPOSITE_VERTEX,
+            COMPOSITE_FRAGMENT,
+            ShaderMeta {
+                images: &["u_light"],
+                uniforms: UniformBlockLayout { uniforms: &[] },
+            },
+        )
+        .expect("Building light composite shader failed");
+        // The destination is multiplied with the light
+        let composite_pipeline = Pipeline::with_params(
+            ctx,
+            &[BufferLayout::default()],
+            &[VertexAttribute::new("a_pos", VertexFormat::Float2)],
+            shader,
+            PipelineParams {
+                color_blend: Some(BlendState::new(
+                    Equation::Add,
+                    BlendFactor::Value(BlendValue::DestinationColor),
+                    BlendFactor::Zero,
+                )),
+                ..Default::default()
+            },
+        );
+
+        #[rustfmt::skip]
+        let vertices: [f32; 8] = [
+            -1.0, -1.0,
+            1.0, -1.0,
+            1.0, 1.0,
+            -1.0, 1.0,
+        ];
+        let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
+        let quad = Bindings {
+            vertex_buffers: vec![Buffer::immutable(ctx, BufferType::VertexBuffer, &vertices)],
+            index_buffer: Buffer::immutable(ctx, BufferType::IndexBuffer, &indices),
+            images: vec![color],
+        };
+
+        Self {
+            color,
+            depth,
+            pass,
+            light_pipeline,
+            light_bindings,
+            composite_pipeline,
+            quad,
+        }
+    }
+}
+
+/// The points where the rays cast from a light stop, in the order of their angle, with the
+/// fraction of the radius they traveled.
+///
+/// Rays are cast evenly over the light and to both sides of every corner of the occluders in
+/// reach, so the shadows start exactly at the corners.
+fn cast_rays(light: &Light, edges: &[[[f32; 2]; 2]]) -> Vec<([f32; 2], f32)> {
+    let [x, y] = light.position;
+    let radius = light.radius;
+
+    // Skip the edges that are too far away to block anything
+    let edges: Vec<[[f32; 2]; 2]> = edges
+        .iter()
+        .filter(|[a, b]| {
+            a[0].min(b[0]) <= x + radius
+                && a[0].max(b[0]) >= x - radius
+                && a[1].min(b[1]) <= y + radius
+                && a[1].max(b[1]) >= y - radius
+        })
+        .copied()
+        .collect();
+
+    // The angles are relative to the direction of the light, a point light covers a full circle
+    let (direction, half_angle) = light.cone.unwrap_or((0.0, PI));
+    let segments = ((LIGHT_SEGMENTS as f32 * half_angle / PI).ceil() as usize).max(1);
+    let mut angles: Vec<f32> = (0..=segments)
+        .map(|index| -half_angle + 2.0 * half_angle * index as f32 / segments as f32)
+        .collect();
+    for corner in edges.iter().flatten() {
+        let angle = (corner[1] - y).atan2(corner[0] - x) - direction;
+        // Wrap the angle to be between -PI & PI
+        let angle = (angle + PI).rem_euclid(2.0 * PI) - PI;
+        for offset in [-CORNER_OFFSET, 0.0, CORNER_OFFSET].iter() {
+            if (angle + offset).abs() <= half_angle {
+                angles.push(angle + offset);
+            }
+        }
+    }
+    // Positions of NaN from scripts make the angles NaN, which can't be cast
+    angles.retain(|angle| angle.is_finite());
+    angles.sort_by(|a, b| a.total_cmp(b));
+    angles.dedup();
+
+    angles
+        .into_iter()
+        .map(|angle| {
+            let (sin, cos) = (angle + direction).sin_cos();
+            let distance = edges
+                .iter()
+                .filter_map(|edge| ray_distance([x, y], [cos, sin], edge))
+                .fold(radius, f32::min);
+
+            let fraction = if radius > 0.0 { distance / radius } else { 1.0 };
+
+            ([x + cos * distance, y + sin * distance], fraction)
+        })
+        .collect()
+}
+
+/// The distance along a ray where it hits an edge, `None` when it misses.
+fn ray_distance(origin: [f32; 2], direction: [f32; 2], [a, b]: &[[f32; 2]; 2]) -> Option<f32> {
+    let edge = [b[0] - a[0], b[1] - a[1]];
+    let denominator = direction[0] * edge[1] - direction[1] * edge[0];
+    // Parallel lines never hit
+    if denominator.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let offset = [a[0] - origin[0], a[1] - origin[1]];
+    let distance = (offset[0] * edge[1] - offset[1] * edge[0]) / denominator;
+    let along_edge = (offset[0] * direction[1] - offset[1] * direction[0]) / denominator;
+
+    if distance >= 0.0 && (0.0..=1.0).contains(&along_edge) {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+/// Convert a script color & intensity to the color added by a light.
+fn light_color(color: Option<(u8, u8, u8)>, intensity: Option<f32>) -> [f32; 3] {
+    let (red, green, blue) = color.unwrap_or((255, 255, 255));
+    let intensity = intensity.unwrap_or(1.0);
+
+    [
+        red as f32 / 255.0 * intensity,
+        green as f32 / 255.0 * intensity,
+        blue as f32 / 255.0 * intensity,
+    ]
+}
@@ -0,0 +1,61 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+/// How long before the next frame sleeping stops & waiting continues by spinning, because
+/// sleeping isn't precise on most platforms.
+#[cfg(not(target_arch = "wasm32"))]
+const SPIN_DURATION: Duration = Duration::from_millis(2);
+
+/// Waits between frames so the game isn't updated more often than a target frame rate.
+///
+/// On the web the browser already schedules the frames, so nothing is limited there.
+#[derive(Debug, Default)]
+pub struct FrameLimiter {
+    /// When the next frame may start.
+    #[cfg(not(target_arch = "wasm32"))]
+    next_frame: Option<Instant>,
+}
+
+impl FrameLimiter {
+    /// Create without waiting for the first frame.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Block until the next frame may start, doesn't wait without a target frame rate.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn wait(&mut self, target_fps: Option<u32>) {
+        let frame_time = match target_fps.filter(|fps| *fps > 0) {
+            Some(fps) => Duration::from_secs_f64(1.0 / fps as f64),
+            None => {
+                self.next_frame = None;
+
+                return;
+            }
+        };
+
+        let next_frame = self.next_frame.unwrap_or_else(Instant::now);
+        let now = Instant::now();
+        if next_frame > now {
+            let remaining = next_frame - now;
+            if remaining > SPIN_DURATION {
+                std::thread::sleep(remaining - SPIN_DURATION);
+            }
+            while Instant::now() < next_frame {
+                std::hint::spin_loop();
+            }
+        }
+
+        // Don't try to catch up with frames that took too long
+        let now = Instant::now();
+        self.next_frame = Some(if next_frame + frame_time < now {
+            now + frame_time
+        } else {
+            next_frame + frame_time
+        });
+    }
+
+    /// Block until the next frame may start, the browser already limits the frame rate.
+    #[cfg(target_arch = "wasm32")]
+    pub fn wait(&mut self, _target_fps: Option<u32>) {}
+}
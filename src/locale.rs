@@ -0,0 +1,203 @@
+use crate::{debug, event::Events};
+use anyhow::{anyhow, Result};
+use glsp::{bail, lib, rfn, GResult, Rest, Runtime, Val};
+use std::collections::HashMap;
+
+/// The translated strings & the font of a single language.
+#[derive(Debug, Default)]
+struct Locale {
+    /// The translation of every key.
+    strings: HashMap<String, String>,
+    /// The font asset used for text in this language, the default font when `None`.
+    font: Option<String>,
+}
+
+lib! {
+/// Translated strings for every language and the language that's currently shown.
+///
+/// Strings missing from the current language are taken from the first language loaded. Text
+/// without an explicit font uses the font of the current language, so scripts that need glyphs
+/// missing from the default font only have to configure it once per language.
+///
+/// ```gamelisp
+/// (tr "greeting" player-name)
+/// (set-language "nl")
+/// ```
+pub struct Locales {
+    /// The strings & font of every loaded language.
+    locales: HashMap<String, Locale>,
+    /// The language strings are translated to.
+    language: Option<String>,
+    /// The language used for strings missing from the current one.
+    fallback: Option<String>,
+}
+}
+
+impl Locales {
+    /// Create without any languages, the keys are shown untranslated.
+    pub fn new() -> Self {
+        Self {
+            locales: HashMap::new(),
+            language: None,
+            fallback: None,
+        }
+    }
+
+    /// Add the strings of a language from a file with a `key = value` pair on every line.
+    ///
+    /// Empty lines & lines starting with `#` are skipped, `\n` in a value is a newline and `{0}`,
+    /// `{1}` etc. are replaced with the arguments passed to `tr`. The first language loaded is
+    /// the one that's shown.
+    pub fn load(&mut self, language: &str, source: &str) -> Result<()> {
+        let mut strings = HashMap::new();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if !key.trim().is_empty() => {
+                    strings.insert(key.trim().to_string(), value.trim().replace("\\n", "\n"));
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "line {} of locale \"{}\" is not a key = value pair",
+                        index + 1,
+                        language
+                    ))
+                }
+            }
+        }
+
+        self.locales
+            .entry(language.to_string())
+            .or_default()
+            .strings
+            .extend(strings);
+        if self.fallback.is_none() {
+            self.fallback = Some(language.to_string());
+            self.language = Some(language.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Set the font asset used for text in a language.
+    pub fn set_font(&mut self, language: &str, font: &str) {
+        self.locales.entry(language.to_string()).or_default().font = Some(font.to_string());
+    }
+
+    /// Show the strings of another loaded language.
+    pub fn set_language(&mut self, language: &str) -> Result<()> {
+        if !self.locales.contains_key(language) {
+            return Err(anyhow!("locale \"{}\" is not loaded", language));
+        }
+
+        self.language = Some(language.to_string());
+
+        Ok(())
+    }
+
+    /// The language strings are translated to.
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    /// The translation of a key in the current language or the fallback language.
+    pub fn translate(&self, key: &str) -> Option<&str> {
+        [&self.language, &self.fallback]
+            .iter()
+            .filter_map(|language| self.locales.get(language.as_deref()?))
+            .find_map(|locale| locale.strings.get(key))
+            .map(|string| string.as_str())
+    }
+
+    /// The font asset for text in the current language.
+    pub fn font(&self) -> &str {
+        self.language
+            .as_ref()
+            .and_then(|language| self.locales.get(language)?.font.as_deref())
+            .unwrap_or(debug::DEFAULT_FONT)
+    }
+
+    /// Translate a key from a script, replacing `{0}`, `{1}` etc. with the arguments.
+    ///
+    /// Keys without a translation are returned as is.
+    ///
+    /// ```gamelisp
+    /// (tr "score" 100)
+    /// ```
+    fn tr(&self, key: &str, args: Rest<Val>) -> String {
+        let string = self.translate(key).unwrap_or(key);
+
+        let mut translated = String::with_capacity(string.len());
+        let mut rest = string;
+        while let Some(start) = rest.find('{') {
+            translated.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            // Only replace placeholders with a matching argument
+            let argument = rest
+                .find('}')
+                .and_then(|end| Some((end, rest[1..end].parse::<usize>().ok()?)))
+                .and_then(|(end, index)| Some((end, args.get(index)?)));
+            match argument {
+                Some((end, argument)) => {
+                    translated.push_str(&argument.to_string());
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    translated.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        translated.push_str(rest);
+
+        translated
+    }
+
+    /// Show the strings of another language from a script, emits `language-changed`.
+    fn set_language_script(&mut self, language: &str) -> GResult<()> {
+        if let Err(err) = self.set_language(language) {
+            bail!("changing language failed: {}", err);
+        }
+
+        Events::emit_engine_event("language-changed", language)
+    }
+
+    /// Get the current language from a script.
+    fn language_script(&self) -> Option<String> {
+        self.language.clone()
+    }
+
+    /// Get all loaded languages from a script, sorted alphabetically.
+    fn languages(&self) -> Vec<String> {
+        let mut languages: Vec<String> = self.locales.keys().cloned().collect();
+        languages.sort();
+
+        languages
+    }
+
+    /// Get the font asset for text in the current language from a script.
+    fn font_script(&self) -> String {
+        self.font().to_string()
+    }
+
+    /// Register the locales and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Locales::new());
+
+            glsp::bind_rfn("tr", rfn!(Locales::tr))?;
+            glsp::bind_rfn("set-language", rfn!(Locales::set_language_script))?;
+            glsp::bind_rfn("language", rfn!(Locales::language_script))?;
+            glsp::bind_rfn("languages", rfn!(Locales::languages))?;
+            glsp::bind_rfn("locale-font", rfn!(Locales::font_script))?;
+
+            Ok(())
+        });
+    }
+}
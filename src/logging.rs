@@ -0,0 +1,213 @@
+use crate::{debug, render::Render};
+use anyhow::Result;
+use glsp::{bail, lib, rfn, GResult, Runtime, Sym, Val};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{collections::VecDeque, fmt::Write, sync::Mutex};
+
+/// How many of the most recent messages are kept for the overlay.
+const MAX_OVERLAY_LINES: usize = 12;
+/// The height of a line of text in the overlay in pixels.
+const LINE_HEIGHT: f32 = 16.0;
+/// The distance of the overlay from the bottom of the screen in lines, above the console.
+const OVERLAY_OFFSET_LINES: usize = 12;
+
+/// The logger installed by the engine when the game didn't install one, printing to stderr.
+///
+/// The most recent messages are kept so they can be shown in the overlay.
+struct EngineLogger {
+    /// The level & text of the most recent messages, the newest one is last.
+    recent: Mutex<VecDeque<(Level, String)>>,
+}
+
+impl Log for EngineLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!("[{} {}] {}", record.level(), record.target(), record.args());
+        eprintln!("{}", line);
+
+        if let Ok(mut recent) = self.recent.lock() {
+            if recent.len() == MAX_OVERLAY_LINES {
+                recent.pop_front();
+            }
+            recent.push_back((record.level(), line));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+lib! {
+/// Routes the messages of the scripts & the engine through the `log` crate.
+///
+/// When the game didn't install a logger before creating the engine, messages are printed to
+/// stderr and the most recent ones can be shown on screen with `show-log-overlay`. Messages of
+/// the scripts use the `script` target and start with the file & line they were logged from.
+///
+/// ```gamelisp
+/// (log-info "spawned " (len enemies) " enemies")
+/// (set-log-level 'warn)
+/// ```
+pub struct Logger {
+    /// The logger of the engine, `None` when the game installed its own.
+    engine_logger: Option<&'static EngineLogger>,
+    /// Whether the most recent messages are drawn on top of the game.
+    show_overlay: bool,
+}
+}
+
+impl Logger {
+    /// Install the logger of the engine if there's none yet, logging informational messages.
+    pub fn new() -> Self {
+        let engine_logger: &'static EngineLogger = Box::leak(Box::new(EngineLogger {
+            recent: Mutex::new(VecDeque::with_capacity(MAX_OVERLAY_LINES)),
+        }));
+
+        // Installing fails when the game already installed a logger, which is then used
+        let engine_logger = match log::set_logger(engine_logger) {
+            Ok(()) => {
+                log::set_max_level(LevelFilter::Info);
+
+                Some(engine_logger)
+            }
+            Err(_) => None,
+        };
+
+        Self {
+            engine_logger,
+            show_overlay: false,
+        }
+    }
+
+    /// Set the most detailed level of the messages that are logged.
+    pub fn set_level(&mut self, level: LevelFilter) {
+        log::set_max_level(level);
+    }
+
+    /// Draw the most recent messages in the bottom left of the screen if it's enabled.
+    pub fn draw_overlay(&self, render: &mut Render) -> Result<()> {
+        let engine_logger = match self.engine_logger {
+            Some(engine_logger) if self.show_overlay => engine_logger,
+            _ => return Ok(()),
+        };
+        let recent = match engine_logger.recent.lock() {
+            Ok(recent) => recent,
+            Err(_) => return Ok(()),
+        };
+
+        let scale = render.world_units_per_pixel();
+        let line_height = LINE_HEIGHT * scale;
+        let (x, bottom) = render.screen_to_world(4.0, render.screen_size().1 - 4.0);
+        let top = bottom - (OVERLAY_OFFSET_LINES + recent.len()) as f32 * line_height;
+        for (index, (level, line)) in recent.iter().enumerate() {
+            let color = match level {
+                Level::Error => (255, 80, 80),
+                Level::Warn => (255, 200, 0),
+                _ => (200, 200, 200),
+            };
+
+            debug::draw_text(
+                render,
+                x,
+                top + index as f32 * line_height,
+                line,
+                color,
+                line_height,
+                debug::DEFAULT_FONT,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Set whether the most recent messages are shown from a script.
+    ///
+    /// Only works when the game didn't install its own logger.
+    fn show_log_overlay(&mut self, show: bool) {
+        self.show_overlay = show;
+    }
+
+    /// Set the most detailed level of the messages that are logged from a script.
+    ///
+    /// The level is `off`, `error`, `warn`, `info`, `debug` or `trace`.
+    ///
+    /// ```gamelisp
+    /// (set-log-level 'debug)
+    /// ```
+    fn set_log_level(&mut self, level: Sym) -> GResult<()> {
+        self.set_level(match &*level.name() {
+            "off" => LevelFilter::Off,
+            "error" => LevelFilter::Error,
+            "warn" => LevelFilter::Warn,
+            "info" => LevelFilter::Info,
+            "debug" => LevelFilter::Debug,
+            "trace" => LevelFilter::Trace,
+            name => bail!("unknown log level {}", name),
+        });
+
+        Ok(())
+    }
+
+    /// Register the logger and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Logger::new());
+
+            glsp::bind_rfn("log-error", rfn!(log_error))?;
+            glsp::bind_rfn("log-warn", rfn!(log_warn))?;
+            glsp::bind_rfn("log-info", rfn!(log_info))?;
+            glsp::bind_rfn("log-debug", rfn!(log_debug))?;
+            glsp::bind_rfn("set-log-level", rfn!(Logger::set_log_level))?;
+            glsp::bind_rfn("show-log-overlay", rfn!(Logger::show_log_overlay))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// Log the values passed by a script, prefixed with the location in the script.
+fn log_script(level: Level, values: &[Val]) {
+    if level > log::max_level() {
+        return;
+    }
+
+    let mut message = String::new();
+    if let Some(location) = glsp::file_location() {
+        let _ = write!(message, "{}: ", location);
+    }
+    for value in values {
+        let _ = write!(message, "{}", value);
+    }
+
+    log::log!(target: "script", level, "{}", message);
+}
+
+/// Log an error from a script, the values are concatenated like `str`.
+///
+/// ```gamelisp
+/// (log-error "can't find spawn point " name)
+/// ```
+fn log_error(values: &[Val]) {
+    log_script(Level::Error, values);
+}
+
+/// Log a warning from a script.
+fn log_warn(values: &[Val]) {
+    log_script(Level::Warn, values);
+}
+
+/// Log information from a script.
+fn log_info(values: &[Val]) {
+    log_script(Level::Info, values);
+}
+
+/// Log details for debugging from a script, hidden by default.
+fn log_debug(values: &[Val]) {
+    log_script(Level::Debug, values);
+}
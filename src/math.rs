@@ -0,0 +1,392 @@
+use glsp::{bail, rdata, rfn, GResult, Runtime};
+use std::f32::consts::PI;
+
+rdata! {
+/// A two-dimensional vector for positions, directions & velocities in scripts.
+///
+/// The operations return new vectors, the vector itself is only changed by setting `x` or `y`.
+///
+/// ```gamelisp
+/// (let velocity (.scale (.normalize (.sub target position)) speed))
+/// (= position (.add position (.scale velocity delta)))
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Vec2 {
+    /// The horizontal component.
+    pub x: f32,
+    /// The vertical component.
+    pub y: f32,
+}
+
+meths {
+    get "x": Vec2::x,
+    set "x": Vec2::set_x,
+    get "y": Vec2::y,
+    set "y": Vec2::set_y,
+    "add": Vec2::add,
+    "sub": Vec2::sub,
+    "scale": Vec2::scale,
+    "dot": Vec2::dot,
+    "cross": Vec2::cross,
+    "length": Vec2::length,
+    "distance": Vec2::distance,
+    "normalize": Vec2::normalize,
+    "rotate": Vec2::rotate,
+    "angle": Vec2::angle,
+    "lerp": Vec2::lerp,
+    "clamp-length": Vec2::clamp_length,
+}
+}
+
+impl Vec2 {
+    /// Create from the components.
+    pub fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+
+    /// A vector pointing in the direction of an angle in radians.
+    pub fn from_angle(angle: f32, length: f32) -> Self {
+        Self::new(angle.cos() * length, angle.sin() * length)
+    }
+
+    /// The horizontal component.
+    pub fn x(&self) -> f32 {
+        self.x
+    }
+
+    /// Set the horizontal component.
+    pub fn set_x(&mut self, x: f32) {
+        self.x = x;
+    }
+
+    /// The vertical component.
+    pub fn y(&self) -> f32 {
+        self.y
+    }
+
+    /// Set the vertical component.
+    pub fn set_y(&mut self, y: f32) {
+        self.y = y;
+    }
+
+    /// The sum of both vectors.
+    pub fn add(&self, other: &Vec2) -> Vec2 {
+        Self::new(self.x + other.x, self.y + other.y)
+    }
+
+    /// The difference between both vectors, pointing from the other one to this one.
+    pub fn sub(&self, other: &Vec2) -> Vec2 {
+        Self::new(self.x - other.x, self.y - other.y)
+    }
+
+    /// The vector with both components multiplied.
+    pub fn scale(&self, factor: f32) -> Vec2 {
+        Self::new(self.x * factor, self.y * factor)
+    }
+
+    /// The dot product, zero when both vectors are perpendicular.
+    pub fn dot(&self, other: &Vec2) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+
+    /// The signed area of the parallelogram of both vectors, positive when the other vector is
+    /// counter-clockwise.
+    pub fn cross(&self, other: &Vec2) -> f32 {
+        self.x * other.y - self.y * other.x
+    }
+
+    /// The length of the vector.
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    /// The distance between the points of both vectors.
+    pub fn distance(&self, other: &Vec2) -> f32 {
+        self.sub(other).length()
+    }
+
+    /// The vector with a length of one, or zero when it has no length.
+    pub fn normalize(&self) -> Vec2 {
+        let length = self.length();
+        if length > 0.0 {
+            self.scale(1.0 / length)
+        } else {
+            Self::default()
+        }
+    }
+
+    /// The vector rotated counter-clockwise by an angle in radians.
+    pub fn rotate(&self, angle: f32) -> Vec2 {
+        let (sin, cos) = angle.sin_cos();
+
+        Self::new(self.x * cos - self.y * sin, self.x * sin + self.y * cos)
+    }
+
+    /// The angle in radians from the horizontal axis, from `-pi` to `pi`.
+    pub fn angle(&self) -> f32 {
+        self.y.atan2(self.x)
+    }
+
+    /// Blend towards the other vector, `0` is this vector & `1` the other.
+    pub fn lerp(&self, other: &Vec2, t: f32) -> Vec2 {
+        Self::new(lerp(self.x, other.x, t), lerp(self.y, other.y, t))
+    }
+
+    /// The vector shortened when it's longer than a maximum length.
+    pub fn clamp_length(&self, max: f32) -> Vec2 {
+        let length = self.length();
+        if length > max && length > 0.0 {
+            self.scale(max / length)
+        } else {
+            *self
+        }
+    }
+}
+
+rdata! {
+/// An axis-aligned bounding box for overlap tests & keeping positions within an area.
+///
+/// ```gamelisp
+/// (let arena (aabb 0 0 800 600))
+/// (= position (.clamp arena position))
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    /// The corner with the smallest coordinates.
+    pub min: Vec2,
+    /// The corner with the largest coordinates.
+    pub max: Vec2,
+}
+
+meths {
+    get "x": Aabb::x,
+    get "y": Aabb::y,
+    get "width": Aabb::width,
+    get "height": Aabb::height,
+    "min": Aabb::min,
+    "max": Aabb::max,
+    "center": Aabb::center,
+    "contains?": Aabb::contains,
+    "overlaps?": Aabb::overlaps,
+    "union": Aabb::union,
+    "clamp": Aabb::clamp,
+}
+}
+
+impl Aabb {
+    /// Create from the corner with the smallest coordinates & the size.
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            min: Vec2::new(x.min(x + width), y.min(y + height)),
+            max: Vec2::new(x.max(x + width), y.max(y + height)),
+        }
+    }
+
+    /// The smallest horizontal coordinate.
+    pub fn x(&self) -> f32 {
+        self.min.x
+    }
+
+    /// The smallest vertical coordinate.
+    pub fn y(&self) -> f32 {
+        self.min.y
+    }
+
+    /// The horizontal size.
+    pub fn width(&self) -> f32 {
+        self.max.x - self.min.x
+    }
+
+    /// The vertical size.
+    pub fn height(&self) -> f32 {
+        self.max.y - self.min.y
+    }
+
+    /// The corner with the smallest coordinates.
+    pub fn min(&self) -> Vec2 {
+        self.min
+    }
+
+    /// The corner with the largest coordinates.
+    pub fn max(&self) -> Vec2 {
+        self.max
+    }
+
+    /// The point in the middle.
+    pub fn center(&self) -> Vec2 {
+        self.min.lerp(&self.max, 0.5)
+    }
+
+    /// Whether a point is inside or on the edge.
+    pub fn contains(&self, point: &Vec2) -> bool {
+        point.x >= self.min.x
+            && point.x <= self.max.x
+            && point.y >= self.min.y
+            && point.y <= self.max.y
+    }
+
+    /// Whether both boxes overlap, touching edges don't count.
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.min.x < other.max.x
+            && self.max.x > other.min.x
+            && self.min.y < other.max.y
+            && self.max.y > other.min.y
+    }
+
+    /// The smallest box containing both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Self {
+            min: Vec2::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y)),
+            max: Vec2::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y)),
+        }
+    }
+
+    /// The point inside the box closest to a point.
+    pub fn clamp(&self, point: &Vec2) -> Vec2 {
+        Vec2::new(
+            point.x.max(self.min.x).min(self.max.x),
+            point.y.max(self.min.y).min(self.max.y),
+        )
+    }
+}
+
+/// Blend between two values, `0` is the first value & `1` the second.
+pub fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+/// The angle wrapped to the range from `-pi` to `pi`.
+pub fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = (angle + PI).rem_euclid(2.0 * PI) - PI;
+
+    // The remainder can round up to the end of the range
+    if wrapped >= PI {
+        wrapped - 2.0 * PI
+    } else {
+        wrapped
+    }
+}
+
+/// Create a vector from a script.
+///
+/// ```gamelisp
+/// (let position (vec2 10 20))
+/// ```
+fn vec2(x: f32, y: f32) -> Vec2 {
+    Vec2::new(x, y)
+}
+
+/// Create a vector pointing in the direction of an angle in radians from a script.
+///
+/// Without a length the vector has a length of one.
+///
+/// ```gamelisp
+/// (let bullet-velocity (vec2-from-angle aim-angle 300))
+/// ```
+fn vec2_from_angle(angle: f32, length: Option<f32>) -> Vec2 {
+    Vec2::from_angle(angle, length.unwrap_or(1.0))
+}
+
+/// Create an axis-aligned bounding box from a script.
+///
+/// ```gamelisp
+/// (let hitbox (aabb (- x 8) (- y 8) 16 16))
+/// ```
+fn aabb(x: f32, y: f32, width: f32, height: f32) -> Aabb {
+    Aabb::new(x, y, width, height)
+}
+
+/// Blend between two numbers from a script.
+///
+/// ```gamelisp
+/// (= [camera 'zoom] (lerp [camera 'zoom] target-zoom 0.1))
+/// ```
+fn lerp_script(from: f32, to: f32, t: f32) -> f32 {
+    lerp(from, to, t)
+}
+
+/// How far a number is between two numbers from a script, the inverse of `lerp`.
+///
+/// ```gamelisp
+/// (let progress (inverse-lerp start-x finish-x x))
+/// ```
+fn inverse_lerp(from: f32, to: f32, value: f32) -> GResult<f32> {
+    if from == to {
+        bail!("can't find a value between {} and itself", from);
+    }
+
+    Ok((value - from) / (to - from))
+}
+
+/// Map a number from one range to another from a script, optionally clamped to the new range.
+///
+/// The ranges are passed as `(arr start end)`.
+///
+/// ```gamelisp
+/// (let volume (remap distance (arr 0 500) (arr 1 0) #t))
+/// ```
+fn remap(
+    value: f32,
+    (from_start, from_end): (f32, f32),
+    (to_start, to_end): (f32, f32),
+    clamped: Option<bool>,
+) -> GResult<f32> {
+    let mut t = inverse_lerp(from_start, from_end, value)?;
+    if clamped.unwrap_or(false) {
+        t = t.max(0.0).min(1.0);
+    }
+
+    Ok(lerp(to_start, to_end, t))
+}
+
+/// Convert degrees to radians from a script.
+fn deg_to_rad(degrees: f32) -> f32 {
+    degrees.to_radians()
+}
+
+/// Convert radians to degrees from a script.
+fn rad_to_deg(radians: f32) -> f32 {
+    radians.to_degrees()
+}
+
+/// Wrap an angle in radians to the range from `-pi` to `pi` from a script.
+fn wrap_angle_script(angle: f32) -> f32 {
+    wrap_angle(angle)
+}
+
+/// The shortest signed rotation in radians from one angle to another from a script.
+///
+/// ```gamelisp
+/// (let turn (angle-difference facing (.angle (.sub target position))))
+/// ```
+fn angle_difference(from: f32, to: f32) -> f32 {
+    wrap_angle(to - from)
+}
+
+/// Blend between two angles in radians along the shortest rotation from a script.
+///
+/// ```gamelisp
+/// (= facing (lerp-angle facing target-facing 0.2))
+/// ```
+fn lerp_angle(from: f32, to: f32, t: f32) -> f32 {
+    wrap_angle(from + wrap_angle(to - from) * t)
+}
+
+/// Bind the GameLisp functions for vectors, bounding boxes & angles.
+pub fn bind_functions(runtime: &Runtime) {
+    runtime.run(|| {
+        glsp::bind_rfn("vec2", rfn!(vec2))?;
+        glsp::bind_rfn("vec2-from-angle", rfn!(vec2_from_angle))?;
+        glsp::bind_rfn("aabb", rfn!(aabb))?;
+        glsp::bind_rfn("lerp", rfn!(lerp_script))?;
+        glsp::bind_rfn("inverse-lerp", rfn!(inverse_lerp))?;
+        glsp::bind_rfn("remap", rfn!(remap))?;
+        glsp::bind_rfn("deg->rad", rfn!(deg_to_rad))?;
+        glsp::bind_rfn("rad->deg", rfn!(rad_to_deg))?;
+        glsp::bind_rfn("wrap-angle", rfn!(wrap_angle_script))?;
+        glsp::bind_rfn("angle-difference", rfn!(angle_difference))?;
+        glsp::bind_rfn("lerp-angle", rfn!(lerp_angle))?;
+
+        Ok(())
+    });
+}
@@ -0,0 +1,477 @@
+use crate::{
+    assets::{AssetKind, Assets},
+    script::Scripts,
+    storage::Storage,
+};
+use glsp::{bail, lib, rfn, GResult, Root, Runtime, Tab};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+/// The file in a mod directory describing the mod.
+const MANIFEST: &str = "mod.json";
+/// The storage key of the mods the player disabled.
+const DISABLED_KEY: &str = "disabled-mods";
+
+/// A mod found in the mods directory.
+#[derive(Debug, Clone)]
+struct Mod {
+    /// The directory of the mod.
+    directory: PathBuf,
+    /// The name shown to the player, the directory name when the manifest doesn't have one.
+    name: String,
+    /// The version from the manifest.
+    version: String,
+    /// The description from the manifest.
+    description: String,
+    /// The mods that must be loaded before this one.
+    dependencies: Vec<String>,
+    /// The scripts evaluated when the mod is loaded, relative to its directory.
+    scripts: Vec<String>,
+    /// Whether the scripts were evaluated & the assets queued.
+    loaded: bool,
+}
+
+impl Mod {
+    /// Read the manifest in a mod directory.
+    fn from_directory(id: &str, directory: &Path) -> GResult<Self> {
+        let manifest_path = directory.join(MANIFEST);
+        let manifest: Value = match std::fs::read_to_string(&manifest_path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| serde_json::from_str(&text).map_err(|err| err.to_string()))
+        {
+            Ok(manifest) => manifest,
+            Err(err) => bail!("reading \"{}\" failed: {}", manifest_path.display(), err),
+        };
+
+        let string = |key: &str| manifest[key].as_str().map(str::to_string);
+        let strings = |key: &str| -> Option<Vec<String>> {
+            manifest[key].as_array().map(|array| {
+                array
+                    .iter()
+                    .filter_map(|value| value.as_str().map(str::to_string))
+                    .collect()
+            })
+        };
+
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            name: string("name").unwrap_or_else(|| id.to_string()),
+            version: string("version").unwrap_or_default(),
+            description: string("description").unwrap_or_default(),
+            dependencies: strings("dependencies").unwrap_or_default(),
+            scripts: strings("scripts").unwrap_or_else(|| vec!["main.glsp".to_string()]),
+            loaded: false,
+        })
+    }
+}
+
+lib! {
+/// Finds the mods in the mods directory and loads their scripts & assets.
+///
+/// Every mod is a directory with a `mod.json` manifest:
+///
+/// ```json
+/// {
+///     "name": "Hard mode",
+///     "version": "1.0",
+///     "description": "Twice as many enemies",
+///     "dependencies": ["more-enemies"],
+///     "scripts": ["main.glsp"]
+/// }
+/// ```
+///
+/// The scripts & assets of a mod are namespaced with the name of its directory, the asset
+/// `hard-mode/assets/boss.svg` in the mod `hard-mode` has the reference name
+/// `hard-mode/boss.svg` and its scripts can require each other with
+/// `(require-script "hard-mode/enemies.glsp")`. Mods are loaded after the main script so they
+/// can redefine its functions, a mod is loaded after its dependencies and otherwise in
+/// alphabetical order. Disabling a mod is remembered & takes effect the next time the game
+/// starts, since the definitions of its scripts can't be undone.
+///
+/// ```gamelisp
+/// (for id in (mod-list)
+///   (prn [(mod-info id) 'name]))
+/// (disable-mod "hard-mode")
+/// ```
+pub struct Mods {
+    /// The directory containing a directory for every mod, `None` when mods aren't supported.
+    directory: Option<PathBuf>,
+    /// The mods found in the directory by their directory name.
+    mods: BTreeMap<String, Mod>,
+    /// The mods the player disabled.
+    disabled: HashSet<String>,
+    /// The enabled mods in the order they were loaded in when last loading.
+    order: Vec<String>,
+}
+}
+
+impl Mods {
+    /// Create without mods.
+    pub fn new() -> Self {
+        Self {
+            directory: None,
+            mods: BTreeMap::new(),
+            disabled: HashSet::new(),
+            order: vec![],
+        }
+    }
+
+    /// Set the directory the mods are in.
+    pub fn set_directory<P>(&mut self, directory: P)
+    where
+        P: Into<PathBuf>,
+    {
+        self.directory = Some(directory.into());
+    }
+
+    /// Forget which mods were loaded, so their scripts are evaluated again after reloading the
+    /// main script.
+    pub fn forget_loaded(&mut self) {
+        for found in self.mods.values_mut() {
+            found.loaded = false;
+        }
+    }
+
+    /// Find the mods that were added to the directory since the last scan.
+    ///
+    /// Mods with invalid manifests are skipped with an error message.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn scan(&mut self) {
+        let directory = match self.directory {
+            Some(ref directory) => directory.clone(),
+            None => return,
+        };
+        let entries = match std::fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::warn!(
+                    "reading mods directory \"{}\" failed: {}",
+                    directory.display(),
+                    err
+                );
+
+                return;
+            }
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let id = match path.file_name().and_then(|name| name.to_str()) {
+                Some(id) if path.join(MANIFEST).is_file() => id.to_string(),
+                _ => continue,
+            };
+            if self.mods.contains_key(&id) {
+                continue;
+            }
+
+            match Mod::from_directory(&id, &path) {
+                Ok(found) => {
+                    self.mods.insert(id, found);
+                }
+                Err(err) => log::error!("skipping mod \"{}\": {}", id, err),
+            }
+        }
+    }
+
+    /// Mods can't be read from the filesystem on WASM.
+    #[cfg(target_arch = "wasm32")]
+    fn scan(&mut self) {
+        if let Some(ref directory) = self.directory {
+            log::warn!(
+                "mods in \"{}\" can't be loaded on WASM",
+                directory.display()
+            );
+        }
+    }
+
+    /// The enabled mods in the order they are loaded in, every mod after its dependencies.
+    ///
+    /// Mods missing a dependency or depending on each other are left out with an error message.
+    fn load_order(&self) -> Vec<String> {
+        let mut order = vec![];
+        let mut skipped = HashSet::new();
+        for id in self.mods.keys() {
+            self.visit(id, &mut order, &mut vec![], &mut skipped);
+        }
+
+        order
+    }
+
+    /// Add a mod to the load order after its dependencies, returns whether it can be loaded.
+    fn visit(
+        &self,
+        id: &str,
+        order: &mut Vec<String>,
+        visiting: &mut Vec<String>,
+        skipped: &mut HashSet<String>,
+    ) -> bool {
+        if order.iter().any(|ordered| ordered == id) {
+            return true;
+        }
+        if skipped.contains(id) {
+            return false;
+        }
+        if visiting.iter().any(|visited| visited == id) {
+            log::error!(
+                "skipping mod \"{}\", it depends on itself: {} -> {}",
+                id,
+                visiting.join(" -> "),
+                id
+            );
+            skipped.insert(id.to_string());
+
+            return false;
+        }
+
+        let found = match self.mods.get(id) {
+            Some(found) if !self.disabled.contains(id) => found,
+            _ => return false,
+        };
+
+        visiting.push(id.to_string());
+        let mut loadable = true;
+        for dependency in &found.dependencies {
+            if !self.visit(dependency, order, visiting, skipped) {
+                log::error!(
+                    "skipping mod \"{}\", dependency \"{}\" is missing or disabled",
+                    id,
+                    dependency
+                );
+                loadable = false;
+                break;
+            }
+        }
+        visiting.pop();
+
+        if loadable {
+            order.push(id.to_string());
+        } else {
+            skipped.insert(id.to_string());
+        }
+
+        loadable
+    }
+
+    /// Add the scripts & queue the assets of a mod, returns the names of the scripts to evaluate.
+    fn prepare(&mut self, id: &str) -> Vec<String> {
+        let found = &self.mods[id];
+
+        let mut scripts = Scripts::borrow_mut();
+        let mut assets = Assets::borrow_mut();
+        for path in files(&found.directory) {
+            let relative = match path.strip_prefix(&found.directory) {
+                Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+                Err(_) => continue,
+            };
+
+            if relative.ends_with(".glsp") {
+                match std::fs::read_to_string(&path) {
+                    Ok(source) => scripts.add(format!("{}/{}", id, relative), source),
+                    Err(err) => log::error!("reading \"{}\" failed: {}", path.display(), err),
+                }
+            } else if let Some(asset) = relative.strip_prefix("assets/") {
+                if let Some(kind) = AssetKind::from_path(&path) {
+                    assets.add_path(format!("{}/{}", id, asset), kind, path.clone());
+                }
+            }
+        }
+
+        found
+            .scripts
+            .iter()
+            .map(|script| format!("{}/{}", id, script))
+            .collect()
+    }
+
+    /// Find the mods in the directory and load the enabled ones that aren't loaded yet.
+    ///
+    /// Must be called inside the GameLisp runtime without the mods being borrowed, since the
+    /// scripts of the mods are evaluated. A mod with a failing script is skipped with an error
+    /// message.
+    pub fn load() {
+        let order: Vec<String> = {
+            let mut mods = Mods::borrow_mut();
+            mods.read_disabled();
+            mods.scan();
+
+            mods.order = mods.load_order();
+
+            mods.order
+                .iter()
+                .filter(|id| !mods.mods[*id].loaded)
+                .cloned()
+                .collect()
+        };
+
+        for id in order {
+            let scripts = Mods::borrow_mut().prepare(&id);
+
+            // Evaluated without borrowing the mods, the scripts can list them
+            let result = scripts
+                .iter()
+                .try_for_each(|script| Scripts::require(script).map(|_| ()));
+            match result {
+                Ok(()) => {
+                    log::info!("loaded mod \"{}\"", id);
+                    if let Some(found) = Mods::borrow_mut().mods.get_mut(&id) {
+                        found.loaded = true;
+                    }
+                }
+                Err(err) => log::error!("loading mod \"{}\" failed: {}", id, err),
+            }
+        }
+    }
+
+    /// Read the mods the player disabled from the storage.
+    fn read_disabled(&mut self) {
+        let contents = match Storage::borrow().read(DISABLED_KEY) {
+            Ok(Some(contents)) => contents,
+            Ok(None) => return,
+            Err(err) => {
+                log::error!("reading disabled mods failed: {}", err);
+
+                return;
+            }
+        };
+
+        match serde_json::from_str::<Vec<String>>(&contents) {
+            Ok(disabled) => self.disabled = disabled.into_iter().collect(),
+            Err(err) => log::error!("disabled mods are corrupt: {}", err),
+        }
+    }
+
+    /// Remember the mods the player disabled in the storage.
+    fn write_disabled(&self) -> GResult<()> {
+        let mut disabled: Vec<&String> = self.disabled.iter().collect();
+        disabled.sort();
+
+        let contents = Value::from(disabled.into_iter().cloned().collect::<Vec<_>>());
+        if let Err(err) = Storage::borrow().write(DISABLED_KEY, &contents.to_string()) {
+            bail!("writing disabled mods failed: {}", err);
+        }
+
+        Ok(())
+    }
+
+    /// The directory names of the mods found from a script, in the order they are loaded in.
+    ///
+    /// Mods that are disabled or couldn't be loaded are listed last.
+    ///
+    /// ```gamelisp
+    /// (mod-list)
+    /// ```
+    fn mod_list(&self) -> Vec<String> {
+        let mut list = self.order.clone();
+        for id in self.mods.keys() {
+            if !list.contains(id) {
+                list.push(id.clone());
+            }
+        }
+
+        list
+    }
+
+    /// The manifest of a mod as a table from a script, `#n` when it isn't found.
+    ///
+    /// The table contains `name`, `version`, `description`, `dependencies`, `enabled?` &
+    /// `loaded?`.
+    ///
+    /// ```gamelisp
+    /// (let info (mod-info "hard-mode"))
+    /// (prn [info 'name] " " [info 'version])
+    /// ```
+    fn mod_info(&self, id: &str) -> GResult<Option<Root<Tab>>> {
+        let found = match self.mods.get(id) {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        let tab = glsp::tab();
+        tab.set(glsp::sym("name")?, found.name.as_str())?;
+        tab.set(glsp::sym("version")?, found.version.as_str())?;
+        tab.set(glsp::sym("description")?, found.description.as_str())?;
+        tab.set(glsp::sym("dependencies")?, found.dependencies.clone())?;
+        tab.set(glsp::sym("enabled?")?, !self.disabled.contains(id))?;
+        tab.set(glsp::sym("loaded?")?, found.loaded)?;
+
+        Ok(Some(tab))
+    }
+
+    /// Enable a mod from a script, it's loaded the next time `load-mods` is called.
+    ///
+    /// ```gamelisp
+    /// (enable-mod "hard-mode")
+    /// (load-mods)
+    /// ```
+    fn enable_mod(&mut self, id: &str) -> GResult<()> {
+        if !self.mods.contains_key(id) {
+            bail!("unknown mod \"{}\"", id);
+        }
+
+        if self.disabled.remove(id) {
+            self.write_disabled()?;
+        }
+
+        Ok(())
+    }
+
+    /// Disable a mod from a script, a loaded mod keeps running until the game is restarted.
+    fn disable_mod(&mut self, id: &str) -> GResult<()> {
+        if !self.mods.contains_key(id) {
+            bail!("unknown mod \"{}\"", id);
+        }
+
+        if self.disabled.insert(id.to_string()) {
+            self.write_disabled()?;
+        }
+
+        Ok(())
+    }
+
+    /// Load the mods that were added or enabled since the game started from a script.
+    fn load_mods() {
+        Mods::load();
+    }
+
+    /// Register the mods and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Mods::new());
+
+            glsp::bind_rfn("mod-list", rfn!(Mods::mod_list))?;
+            glsp::bind_rfn("mod-info", rfn!(Mods::mod_info))?;
+            glsp::bind_rfn("enable-mod", rfn!(Mods::enable_mod))?;
+            glsp::bind_rfn("disable-mod", rfn!(Mods::disable_mod))?;
+            glsp::bind_rfn("load-mods", rfn!(Mods::load_mods))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// All files in a directory & its subdirectories, sorted so mods load the same everywhere.
+fn files(directory: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    let mut directories = vec![directory.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let entries = match std::fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.is_dir() {
+                directories.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+
+    files
+}
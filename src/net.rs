@@ -0,0 +1,210 @@
+use glsp::{bail, lib, rfn, GResult, Runtime};
+use quad_net::web_socket::WebSocket;
+use std::collections::BTreeMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{io::ErrorKind, net::UdpSocket};
+
+/// The largest UDP datagram that can be received in bytes.
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// An open connection to a server.
+enum Connection {
+    /// A UDP socket sending to & receiving from a single address.
+    #[cfg(not(target_arch = "wasm32"))]
+    Udp(UdpSocket),
+    /// A WebSocket, using the browser's WebSockets on WASM.
+    WebSocket(WebSocket),
+}
+
+impl Connection {
+    /// Open a connection, the protocol is determined by the scheme of the URL.
+    fn open(url: &str) -> GResult<Self> {
+        match url.split("://").next() {
+            Some("ws") | Some("wss") => match WebSocket::connect(url) {
+                Ok(socket) => Ok(Connection::WebSocket(socket)),
+                Err(err) => bail!("connecting to \"{}\" failed: {:?}", url, err),
+            },
+            #[cfg(not(target_arch = "wasm32"))]
+            Some("udp") => match Connection::open_udp(&url["udp://".len()..]) {
+                Ok(socket) => Ok(Connection::Udp(socket)),
+                Err(err) => bail!("connecting to \"{}\" failed: {}", url, err),
+            },
+            _ => bail!(
+                "unsupported URL \"{}\", it must start with ws://, wss:// or udp:// (not on WASM)",
+                url
+            ),
+        }
+    }
+
+    /// Open a non-blocking UDP socket on a random port sending to an address.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn open_udp(address: &str) -> std::io::Result<UdpSocket> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(address)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(socket)
+    }
+
+    /// Whether messages can be sent, WebSockets take a while to connect.
+    fn is_connected(&self) -> bool {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Connection::Udp(_) => true,
+            Connection::WebSocket(socket) => socket.connected(),
+        }
+    }
+
+    /// Send a message, UDP messages can get lost.
+    fn send(&mut self, message: &str) -> GResult<()> {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Connection::Udp(socket) => {
+                if let Err(err) = socket.send(message.as_bytes()) {
+                    bail!("sending message failed: {}", err);
+                }
+            }
+            Connection::WebSocket(socket) => socket.send_text(message),
+        }
+
+        Ok(())
+    }
+
+    /// The messages that arrived since the previous call, without waiting.
+    fn receive(&mut self) -> Vec<String> {
+        let mut messages = vec![];
+
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Connection::Udp(socket) => {
+                let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+                loop {
+                    match socket.recv(&mut buffer) {
+                        Ok(size) => {
+                            messages.push(String::from_utf8_lossy(&buffer[..size]).into_owned())
+                        }
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                        Err(err) => {
+                            log::warn!("receiving message failed: {}", err);
+                            break;
+                        }
+                    }
+                }
+            }
+            Connection::WebSocket(socket) => {
+                while let Some(message) = socket.try_recv() {
+                    messages.push(String::from_utf8_lossy(&message).into_owned());
+                }
+            }
+        }
+
+        messages
+    }
+}
+
+lib! {
+/// Connections to servers for multiplayer prototypes, only available with the `net` feature.
+///
+/// The connections never block, the messages that arrived are passed to `engine:net-message`
+/// with the ID of the connection before every update. Messages are strings, tables can be sent
+/// by converting them with a format like JSON.
+///
+/// ```gamelisp
+/// (def server (net-connect "ws://localhost:8080"))
+///
+/// (defn engine:net-message (connection message)
+///   (prn "received " message))
+///
+/// (when (net-connected? server)
+///   (net-send server "hello"))
+/// ```
+pub struct Net {
+    /// The open connections by their ID.
+    connections: BTreeMap<i32, Connection>,
+    /// The ID of the next connection.
+    next_id: i32,
+}
+}
+
+impl Net {
+    /// Create without connections.
+    pub fn new() -> Self {
+        Self {
+            connections: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// The messages that arrived on all connections since the previous call, with the ID of the
+    /// connection they arrived on.
+    pub fn receive(&mut self) -> Vec<(i32, String)> {
+        self.connections
+            .iter_mut()
+            .flat_map(|(id, connection)| {
+                connection
+                    .receive()
+                    .into_iter()
+                    .map(move |message| (*id, message))
+            })
+            .collect()
+    }
+
+    /// Open a connection from a script, returns the ID to send messages with.
+    ///
+    /// The URL starts with `ws://` or `wss://` for WebSockets and `udp://` for UDP, which isn't
+    /// available on WASM.
+    ///
+    /// ```gamelisp
+    /// (def server (net-connect "udp://127.0.0.1:9000"))
+    /// ```
+    fn net_connect(&mut self, url: &str) -> GResult<i32> {
+        let connection = Connection::open(url)?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.connections.insert(id, connection);
+
+        Ok(id)
+    }
+
+    /// Check from a script whether a connection is ready to send messages.
+    fn net_connected(&self, id: i32) -> bool {
+        self.connections
+            .get(&id)
+            .map(Connection::is_connected)
+            .unwrap_or(false)
+    }
+
+    /// Send a message on a connection from a script.
+    ///
+    /// ```gamelisp
+    /// (net-send server (str "move " x " " y))
+    /// ```
+    fn net_send(&mut self, id: i32, message: &str) -> GResult<()> {
+        match self.connections.get_mut(&id) {
+            Some(connection) if connection.is_connected() => connection.send(message),
+            Some(_) => bail!("connection {} isn't connected yet", id),
+            None => bail!("unknown connection {}", id),
+        }
+    }
+
+    /// Close a connection from a script.
+    fn net_close(&mut self, id: i32) {
+        self.connections.remove(&id);
+    }
+
+    /// Register the connections and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Net::new());
+
+            glsp::bind_rfn("net-connect", rfn!(Net::net_connect))?;
+            glsp::bind_rfn("net-connected?", rfn!(Net::net_connected))?;
+            glsp::bind_rfn("net-send", rfn!(Net::net_send))?;
+            glsp::bind_rfn("net-close", rfn!(Net::net_close))?;
+
+            Ok(())
+        });
+    }
+}
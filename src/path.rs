@@ -0,0 +1,408 @@
+use crate::{
+    event::Events,
+    render::{InstanceRef, Mesh, Render},
+};
+use anyhow::{anyhow, Result};
+use glsp::{bail, lib, rfn, GResult, Runtime};
+use lyon::path::{iterator::PathIterator, PathEvent};
+#[cfg(feature = "svg")]
+use lyon::{math::point, path::Path};
+#[cfg(feature = "svg")]
+use usvg::{PathData, PathSegment, Transform};
+
+/// The maximum distance between a curve and the line segments following it.
+const FLATTEN_TOLERANCE: f32 = 0.1;
+/// The maximum amount of dashes a polyline is split into.
+const MAX_DASHES: usize = 4096;
+
+/// The points of the first sub-path of a path, with the curves converted to line segments.
+///
+/// A closed sub-path ends with its first point.
+pub fn outline<P>(path: P) -> Vec<[f32; 2]>
+where
+    P: IntoIterator<Item = PathEvent>,
+{
+    let mut points = vec![];
+    for event in path.into_iter().flattened(FLATTEN_TOLERANCE) {
+        match event {
+            PathEvent::Begin { at } => points.push(at.to_array()),
+            PathEvent::Line { to, .. } => points.push(to.to_array()),
+            PathEvent::End { first, close, .. } => {
+                if close {
+                    points.push(first.to_array());
+                }
+
+                break;
+            }
+            // Curves are already flattened
+            _ => (),
+        }
+    }
+
+    points
+}
+
+/// The points of every sub-path of a path, with the curves converted to line segments.
+///
+/// Unlike `outline` the first point isn't repeated at the end of closed sub-paths.
+#[cfg(feature = "svg")]
+pub fn sub_paths<P>(path: P, tolerance: f32) -> Vec<Vec<[f32; 2]>>
+where
+    P: IntoIterator<Item = PathEvent>,
+{
+    let mut sub_paths = vec![];
+    let mut points = vec![];
+    for event in path.into_iter().flattened(tolerance) {
+        match event {
+            PathEvent::Begin { at } => points.push(at.to_array()),
+            PathEvent::Line { to, .. } => points.push(to.to_array()),
+            PathEvent::End { .. } => sub_paths.push(std::mem::take(&mut points)),
+            // Curves are already flattened
+            _ => (),
+        }
+    }
+
+    sub_paths
+}
+
+/// Convert the data of a SVG path to a lyon path with a transformation applied.
+#[cfg(feature = "svg")]
+pub fn from_svg(data: &PathData, transform: Transform) -> Path {
+    let to_point = |x: f64, y: f64| {
+        let (x, y) = transform.apply(x, y);
+        point(x as f32, y as f32)
+    };
+
+    let mut builder = Path::builder();
+    for segment in data.iter() {
+        match *segment {
+            PathSegment::MoveTo { x, y } => {
+                builder.move_to(to_point(x, y));
+            }
+            PathSegment::LineTo { x, y } => {
+                builder.line_to(to_point(x, y));
+            }
+            PathSegment::CurveTo {
+                x1,
+                y1,
+                x2,
+                y2,
+                x,
+                y,
+            } => {
+                builder.cubic_bezier_to(to_point(x1, y1), to_point(x2, y2), to_point(x, y));
+            }
+            PathSegment::ClosePath => {
+                builder.close();
+            }
+        }
+    }
+
+    builder.build()
+}
+
+/// Split a polyline into the dashes of a dash pattern, starting with a dash.
+///
+/// Every dash is a separate polyline that follows the corners of the original one. Fails when
+/// the dash isn't positive, the gap is negative or the line would be split into more than
+/// `MAX_DASHES` dashes.
+pub fn dashes(points: Vec<[f32; 2]>, dash: f32, gap: f32) -> Result<Vec<Vec<[f32; 2]>>> {
+    if !dash.is_finite() || dash <= 0.0 || !gap.is_finite() || gap < 0.0 {
+        return Err(anyhow!(
+            "dash length must be positive and the gap can't be negative, got {} & {}",
+            dash,
+            gap
+        ));
+    }
+
+    let line = Polyline::new(points);
+    let length = line.length();
+    if length / (dash + gap) > MAX_DASHES as f32 {
+        return Err(anyhow!(
+            "dashes of {} with gaps of {} split a line of {} into more than {} dashes",
+            dash,
+            gap,
+            length,
+            MAX_DASHES
+        ));
+    }
+
+    let mut dashes = vec![];
+    let mut start = 0.0;
+    while start < length {
+        let end = (start + dash).min(length);
+        // Lengths that are tiny compared to the line are lost in the precision of the floats
+        if end <= start || dashes.len() == MAX_DASHES {
+            break;
+        }
+
+        // Keep the corners between the start & the end of the dash
+        let mut points = vec![];
+        points.extend(line.position(start));
+        points.extend(
+            line.points
+                .iter()
+                .zip(line.distances.iter())
+                .filter(|(_, distance)| **distance > start && **distance < end)
+                .map(|(point, _)| *point),
+        );
+        points.extend(line.position(end));
+        dashes.push(points);
+
+        start = end + gap;
+    }
+
+    Ok(dashes)
+}
+
+/// A polyline with the distance along it at every point.
+#[derive(Debug, Clone)]
+struct Polyline {
+    /// The points of the line.
+    points: Vec<[f32; 2]>,
+    /// The distance from the start at every point.
+    distances: Vec<f32>,
+}
+
+impl Polyline {
+    /// Measure the segments of a list of points.
+    fn new(points: Vec<[f32; 2]>) -> Self {
+        let mut distances = Vec::with_capacity(points.len());
+        let mut total = 0.0;
+        for (index, point) in points.iter().enumerate() {
+            if index > 0 {
+                let previous = points[index - 1];
+                total +=
+                    ((point[0] - previous[0]).powi(2) + (point[1] - previous[1]).powi(2)).sqrt();
+            }
+            distances.push(total);
+        }
+
+        Self { points, distances }
+    }
+
+    /// The distance from the start to the end.
+    fn length(&self) -> f32 {
+        self.distances.last().copied().unwrap_or(0.0)
+    }
+
+    /// The position at a distance along the line.
+    fn position(&self, distance: f32) -> Option<[f32; 2]> {
+        self.sample(distance).map(|(x, y, _)| [x, y])
+    }
+
+    /// The position & the angle of the direction at a distance along the line.
+    fn sample(&self, distance: f32) -> Option<(f32, f32, f32)> {
+        let first = *self.points.first()?;
+        if self.points.len() == 1 {
+            return Some((first[0], first[1], 0.0));
+        }
+
+        // The segment that contains the distance
+        let segment = match self
+            .distances
+            .binary_search_by(|probe| probe.total_cmp(&distance))
+        {
+            Ok(index) | Err(index) => index.max(1).min(self.points.len() - 1),
+        };
+        let (start, end) = (self.points[segment - 1], self.points[segment]);
+        let (start_distance, end_distance) = (self.distances[segment - 1], self.distances[segment]);
+
+        let fraction = if end_distance > start_distance {
+            ((distance - start_distance) / (end_distance - start_distance))
+                .max(0.0)
+                .min(1.0)
+        } else {
+            0.0
+        };
+        let (dx, dy) = (end[0] - start[0], end[1] - start[1]);
+
+        Some((
+            start[0] + dx * fraction,
+            start[1] + dy * fraction,
+            dy.atan2(dx),
+        ))
+    }
+}
+
+/// An instance moving along a path.
+#[derive(Debug)]
+struct Follower {
+    /// The moving instance.
+    instance: InstanceRef,
+    /// The path that's followed.
+    line: Polyline,
+    /// How far along the path the instance is.
+    distance: f32,
+    /// How many units the instance moves every second, negative moves backwards.
+    speed: f32,
+    /// Whether the instance starts over when the end is reached.
+    looping: bool,
+}
+
+lib! {
+/// Instances following the outline of meshes, rotated along the direction of the path.
+///
+/// The path is the first outline of the mesh in world coordinates, as if the mesh were drawn at
+/// the origin. When an instance reaches the end of a path that doesn't loop a `path-finished`
+/// event is emitted with the instance.
+///
+/// ```gamelisp
+/// (let track (create-polygon (arr '(0 0) '(200 0) '(200 100)) (arr 80 80 80) 2))
+/// (follow-path car track 50 #t)
+/// ```
+pub struct Paths {
+    /// The instances moving along a path.
+    followers: Vec<Follower>,
+}
+}
+
+impl Paths {
+    /// Create without any instances following a path.
+    pub fn new() -> Self {
+        Self { followers: vec![] }
+    }
+
+    /// Move all instances along their paths.
+    ///
+    /// Must be called inside the GameLisp runtime.
+    pub fn update(&mut self, delta: f64, render: &mut Render) -> GResult<()> {
+        let mut finished = vec![];
+
+        for follower in self.followers.iter_mut() {
+            let length = follower.line.length();
+            follower.distance += follower.speed * delta as f32;
+
+            if follower.looping && length > 0.0 {
+                follower.distance = follower.distance.rem_euclid(length);
+            } else if (follower.speed > 0.0 && follower.distance >= length)
+                || (follower.speed < 0.0 && follower.distance <= 0.0)
+            {
+                follower.distance = follower.distance.max(0.0).min(length);
+                finished.push(follower.instance);
+            }
+
+            let instance = match render.instance_mut(follower.instance) {
+                Some(instance) => instance,
+                // The instance is removed so it doesn't follow the path anymore
+                None => {
+                    finished.push(follower.instance);
+
+                    continue;
+                }
+            };
+            if let Some((x, y, rotation)) = follower.line.sample(follower.distance) {
+                instance.set_x(x);
+                instance.set_y(y);
+                instance.set_rotation(rotation);
+            }
+        }
+
+        self.followers
+            .retain(|follower| !finished.contains(&follower.instance));
+        for instance in finished {
+            if render.instance(instance).is_some() {
+                Events::emit_engine_event("path-finished", instance)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Let an instance follow the outline of a mesh from a script, replacing a path it follows.
+    ///
+    /// The speed is in units per second, the instance starts at the beginning of the path or at
+    /// the end when the speed is negative.
+    ///
+    /// ```gamelisp
+    /// (follow-path enemy rail 120)
+    /// ```
+    fn follow_path(
+        &mut self,
+        instance: &InstanceRef,
+        mesh: &Mesh,
+        speed: f32,
+        looping: Option<bool>,
+    ) -> GResult<()> {
+        if !speed.is_finite() {
+            bail!("speed {} of the path isn't a finite number", speed);
+        }
+
+        let line = Polyline::new(Render::borrow().mesh_path(*mesh).to_vec());
+        if line.points.is_empty() {
+            bail!("mesh {} doesn't have a path", mesh.index());
+        }
+
+        let distance = if speed < 0.0 { line.length() } else { 0.0 };
+        self.stop_following(instance);
+        self.followers.push(Follower {
+            instance: *instance,
+            line,
+            distance,
+            speed,
+            looping: looping.unwrap_or(false),
+        });
+
+        Ok(())
+    }
+
+    /// Stop an instance from following its path from a script, returns whether it was following
+    /// one.
+    ///
+    /// ```gamelisp
+    /// (stop-following enemy)
+    /// ```
+    fn stop_following(&mut self, instance: &InstanceRef) -> bool {
+        let count = self.followers.len();
+        self.followers
+            .retain(|follower| follower.instance != *instance);
+
+        self.followers.len() != count
+    }
+
+    /// Check from a script whether an instance is following a path.
+    fn is_following(&self, instance: &InstanceRef) -> bool {
+        self.followers
+            .iter()
+            .any(|follower| follower.instance == *instance)
+    }
+
+    /// Get the length of the path of a mesh from a script.
+    ///
+    /// ```gamelisp
+    /// (path-length track)
+    /// ```
+    fn path_length(mesh: &Mesh) -> f32 {
+        Polyline::new(Render::borrow().mesh_path(*mesh).to_vec()).length()
+    }
+
+    /// Get the position & the direction in radians at a distance along the path of a mesh from a
+    /// script, for example to move the camera along a path.
+    ///
+    /// ```gamelisp
+    /// (let (x y angle) (path-point dolly 150))
+    /// (set_camera_pos x y)
+    /// ```
+    fn path_point(mesh: &Mesh, distance: f32) -> GResult<(f32, f32, f32)> {
+        let line = Polyline::new(Render::borrow().mesh_path(*mesh).to_vec());
+        match line.sample(distance.max(0.0).min(line.length())) {
+            Some(point) => Ok(point),
+            None => bail!("mesh {} doesn't have a path", mesh.index()),
+        }
+    }
+
+    /// Register the paths and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Paths::new());
+
+            glsp::bind_rfn("follow-path", rfn!(Paths::follow_path))?;
+            glsp::bind_rfn("stop-following", rfn!(Paths::stop_following))?;
+            glsp::bind_rfn("following-path?", rfn!(Paths::is_following))?;
+            glsp::bind_rfn("path-length", rfn!(Paths::path_length))?;
+            glsp::bind_rfn("path-point", rfn!(Paths::path_point))?;
+
+            Ok(())
+        });
+    }
+}
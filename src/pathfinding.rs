@@ -0,0 +1,1023 @@
+use crate::{
+    boolean::{self, BooleanOps},
+    render::{Mesh, Render},
+    sandbox,
+};
+use anyhow::{anyhow, Result};
+use geo_booleanop::boolean::BooleanOp;
+use glsp::{bail, lib, rdata, rfn, GFn, GResult, Root, Runtime, Val};
+use lyon::tessellation::{
+    BuffersBuilder, FillAttributes, FillOptions, FillTessellator, VertexBuffers,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    sync::Arc,
+};
+
+/// The cost of moving diagonally to a neighbouring grid cell relative to a straight move.
+const DIAGONAL_COST: f32 = std::f32::consts::SQRT_2;
+
+rdata! {
+/// A reference to a grid or navmesh paths can be found on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NavRef(usize);
+}
+
+/// A path to find on a map on the background thread, with the function receiving it.
+type PathJob = (u32, Arc<NavMap>, [f32; 2], [f32; 2]);
+
+/// Cells that can be walked over with a cost, moving to all eight neighbours.
+#[derive(Debug, Clone)]
+struct NavGrid {
+    columns: usize,
+    rows: usize,
+    /// The size of a single cell in world units.
+    cell_size: f32,
+    /// The world position of the top left corner of the first cell.
+    origin: [f32; 2],
+    /// The cost of walking over each cell row by row, `None` if it's blocked.
+    costs: Vec<Option<f32>>,
+}
+
+impl NavGrid {
+    /// The cell containing a world position.
+    fn cell(&self, [x, y]: [f32; 2]) -> Option<usize> {
+        let column = ((x - self.origin[0]) / self.cell_size).floor();
+        let row = ((y - self.origin[1]) / self.cell_size).floor();
+        if column < 0.0 || row < 0.0 || column >= self.columns as f32 || row >= self.rows as f32 {
+            return None;
+        }
+
+        Some(row as usize * self.columns + column as usize)
+    }
+
+    /// The world position of the center of a cell.
+    fn center(&self, cell: usize) -> [f32; 2] {
+        let (column, row) = (cell % self.columns, cell / self.columns);
+
+        [
+            self.origin[0] + (column as f32 + 0.5) * self.cell_size,
+            self.origin[1] + (row as f32 + 0.5) * self.cell_size,
+        ]
+    }
+
+    /// The walkable cells next to a cell with the cost of moving there.
+    ///
+    /// Diagonal moves aren't allowed to cut the corners of blocked cells.
+    fn neighbours(&self, cell: usize) -> Vec<(usize, f32)> {
+        let (column, row) = (
+            (cell % self.columns) as isize,
+            (cell / self.columns) as isize,
+        );
+        let walkable = |column: isize, row: isize| {
+            if column < 0 || row < 0 || column >= self.columns as isize || row >= self.rows as isize
+            {
+                return None;
+            }
+            let cell = row as usize * self.columns + column as usize;
+
+            self.costs[cell].map(|cost| (cell, cost))
+        };
+
+        let mut neighbours = vec![];
+        for (dx, dy) in [
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+        ]
+        .iter()
+        {
+            let (neighbour, cost) = match walkable(column + dx, row + dy) {
+                Some(neighbour) => neighbour,
+                None => continue,
+            };
+
+            if *dx != 0 && *dy != 0 {
+                if walkable(column + dx, row).is_none() || walkable(column, row + dy).is_none() {
+                    continue;
+                }
+                neighbours.push((neighbour, cost * DIAGONAL_COST));
+            } else {
+                neighbours.push((neighbour, cost));
+            }
+        }
+
+        neighbours
+    }
+
+    /// The cost of the cheapest walkable cell.
+    fn cheapest_cost(&self) -> f32 {
+        self.costs
+            .iter()
+            .flatten()
+            .copied()
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    /// Find the shortest path between the cells of two world positions.
+    fn find_path(&self, from: [f32; 2], to: [f32; 2]) -> Option<Vec<[f32; 2]>> {
+        let (start, goal) = (self.cell(from)?, self.cell(to)?);
+        self.costs[start]?;
+        self.costs[goal]?;
+
+        // Diagonal distance in cells over the cheapest cells, so it never overestimates
+        let goal_center = self.center(goal);
+        let cheapest = self.cheapest_cost();
+        let heuristic = |cell: usize| {
+            let center = self.center(cell);
+            let dx = (center[0] - goal_center[0]).abs() / self.cell_size;
+            let dy = (center[1] - goal_center[1]).abs() / self.cell_size;
+
+            (dx.max(dy) + (DIAGONAL_COST - 1.0) * dx.min(dy)) * cheapest
+        };
+        let cells = a_star(start, goal, |cell| self.neighbours(cell), heuristic)?;
+
+        // Walk from the exact positions instead of the centers of their cells
+        let mut path: Vec<[f32; 2]> = cells.into_iter().map(|cell| self.center(cell)).collect();
+        path[0] = from;
+        *path.last_mut().expect("path is empty") = to;
+
+        Some(remove_collinear(path))
+    }
+}
+
+/// Triangles covering the walkable area, moving between triangles that share an edge.
+#[derive(Debug, Clone)]
+struct NavMesh {
+    /// The corners of the triangles.
+    vertices: Vec<[f32; 2]>,
+    /// The indices of the corners of each triangle.
+    triangles: Vec<[usize; 3]>,
+    /// The neighbouring triangles of each triangle with the indices of the shared corners.
+    neighbours: Vec<Vec<(usize, [usize; 2])>>,
+}
+
+impl NavMesh {
+    /// Connect the triangles of a tessellated area.
+    fn new(vertices: Vec<[f32; 2]>, indices: &[u16]) -> Self {
+        // The tessellator can output the same corner multiple times, merge them by position
+        let mut merged: HashMap<(u32, u32), usize> = HashMap::new();
+        let remap: Vec<usize> = vertices
+            .iter()
+            .enumerate()
+            .map(|(index, [x, y])| *merged.entry((x.to_bits(), y.to_bits())).or_insert(index))
+            .collect();
+        let triangles: Vec<[usize; 3]> = indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                [
+                    remap[triangle[0] as usize],
+                    remap[triangle[1] as usize],
+                    remap[triangle[2] as usize],
+                ]
+            })
+            .collect();
+
+        // Triangles sharing both corners of an edge are neighbours
+        let mut edges: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (index, &[a, b, c]) in triangles.iter().enumerate() {
+            for &(p, q) in [(a, b), (b, c), (c, a)].iter() {
+                edges.entry((p.min(q), p.max(q))).or_default().push(index);
+            }
+        }
+        let mut neighbours = vec![vec![]; triangles.len()];
+        for ((p, q), shared) in edges {
+            for &a in shared.iter() {
+                for &b in shared.iter().filter(|b| **b != a) {
+                    neighbours[a].push((b, [p, q]));
+                }
+            }
+        }
+
+        Self {
+            vertices,
+            triangles,
+            neighbours,
+        }
+    }
+
+    /// The center of a triangle.
+    fn centroid(&self, triangle: usize) -> [f32; 2] {
+        let [a, b, c] = self.triangles[triangle];
+        let (a, b, c) = (self.vertices[a], self.vertices[b], self.vertices[c]);
+
+        [(a[0] + b[0] + c[0]) / 3.0, (a[1] + b[1] + c[1]) / 3.0]
+    }
+
+    /// The triangle containing a position, or the closest one when it's outside the area.
+    fn triangle(&self, position: [f32; 2]) -> Option<usize> {
+        let inside = self.triangles.iter().position(|[a, b, c]| {
+            let (a, b, c) = (self.vertices[*a], self.vertices[*b], self.vertices[*c]);
+            let (ab, bc, ca) = (
+                cross(sub(b, a), sub(position, a)),
+                cross(sub(c, b), sub(position, b)),
+                cross(sub(a, c), sub(position, c)),
+            );
+
+            (ab >= 0.0 && bc >= 0.0 && ca >= 0.0) || (ab <= 0.0 && bc <= 0.0 && ca <= 0.0)
+        });
+
+        inside.or_else(|| {
+            (0..self.triangles.len()).min_by(|a, b| {
+                let distance = |triangle| length(sub(self.centroid(triangle), position));
+
+                distance(*a)
+                    .partial_cmp(&distance(*b))
+                    .unwrap_or(Ordering::Equal)
+            })
+        })
+    }
+
+    /// Find the shortest path through the triangles, pulled tight around the corners.
+    fn find_path(&self, from: [f32; 2], to: [f32; 2]) -> Option<Vec<[f32; 2]>> {
+        let (start, goal) = (self.triangle(from)?, self.triangle(to)?);
+
+        let goal_center = self.centroid(goal);
+        let triangles = a_star(
+            start,
+            goal,
+            |triangle| {
+                let center = self.centroid(triangle);
+
+                self.neighbours[triangle]
+                    .iter()
+                    .map(|(neighbour, _)| {
+                        (*neighbour, length(sub(self.centroid(*neighbour), center)))
+                    })
+                    .collect()
+            },
+            |triangle| length(sub(self.centroid(triangle), goal_center)),
+        )?;
+
+        // The shared edges crossed, with their corners on the left & right side of the walker
+        let mut portals = vec![(from, from)];
+        for pair in triangles.windows(2) {
+            let [p, q] = self.neighbours[pair[0]]
+                .iter()
+                .find(|(neighbour, _)| *neighbour == pair[1])
+                .map(|(_, edge)| *edge)
+                .expect("path crosses triangles that aren't neighbours");
+            let (p, q) = (self.vertices[p], self.vertices[q]);
+            let center = self.centroid(pair[0]);
+
+            portals.push(if cross(sub(p, center), sub(q, center)) > 0.0 {
+                (q, p)
+            } else {
+                (p, q)
+            });
+        }
+        portals.push((to, to));
+
+        Some(pull_string(&portals))
+    }
+}
+
+/// A map paths can be found on.
+#[derive(Debug, Clone)]
+enum NavMap {
+    Grid(NavGrid),
+    Mesh(NavMesh),
+}
+
+impl NavMap {
+    /// Find the shortest path between two world positions, as the positions to walk to.
+    fn find_path(&self, from: [f32; 2], to: [f32; 2]) -> Option<Vec<[f32; 2]>> {
+        match self {
+            NavMap::Grid(grid) => grid.find_path(from, to),
+            NavMap::Mesh(mesh) => mesh.find_path(from, to),
+        }
+    }
+}
+
+/// A node on the open list of the A* search, the cheapest estimate is popped first.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Open {
+    estimate: f32,
+    node: usize,
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed so the binary heap is a min-heap
+        other
+            .estimate
+            .partial_cmp(&self.estimate)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Find the cheapest route between two nodes, from the start to the goal.
+fn a_star<N, H>(start: usize, goal: usize, neighbours: N, heuristic: H) -> Option<Vec<usize>>
+where
+    N: Fn(usize) -> Vec<(usize, f32)>,
+    H: Fn(usize) -> f32,
+{
+    let mut open = BinaryHeap::new();
+    let mut costs: HashMap<usize, f32> = HashMap::new();
+    let mut came_from: HashMap<usize, usize> = HashMap::new();
+
+    open.push(Open {
+        estimate: heuristic(start),
+        node: start,
+    });
+    costs.insert(start, 0.0);
+
+    while let Some(Open { estimate, node }) = open.pop() {
+        if node == goal {
+            let mut route = vec![goal];
+            while let Some(previous) = came_from.get(route.last().expect("route is empty")) {
+                route.push(*previous);
+            }
+            route.reverse();
+
+            return Some(route);
+        }
+
+        // Skip nodes that were already reached in a cheaper way
+        let cost = costs[&node];
+        if estimate > cost + heuristic(node) {
+            continue;
+        }
+
+        for (neighbour, step) in neighbours(node) {
+            let neighbour_cost = cost + step;
+            if costs
+                .get(&neighbour)
+                .map_or(false, |existing| *existing <= neighbour_cost)
+            {
+                continue;
+            }
+
+            costs.insert(neighbour, neighbour_cost);
+            came_from.insert(neighbour, node);
+            open.push(Open {
+                estimate: neighbour_cost + heuristic(neighbour),
+                node: neighbour,
+            });
+        }
+    }
+
+    None
+}
+
+/// The shortest line through the left & right corners of the portals, with the funnel algorithm.
+fn pull_string(portals: &[([f32; 2], [f32; 2])]) -> Vec<[f32; 2]> {
+    let (first, _) = portals[0];
+    let mut path = vec![first];
+    let (mut apex, mut left, mut right) = (first, first, first);
+    let (mut apex_index, mut left_index, mut right_index) = (0, 0, 0);
+
+    let mut index = 1;
+    while index < portals.len() {
+        let (portal_left, portal_right) = portals[index];
+
+        // Narrow the funnel from the right, or turn around the left corner when it crosses it
+        if cross(sub(right, apex), sub(portal_right, apex)) >= 0.0 {
+            if apex == right || cross(sub(left, apex), sub(portal_right, apex)) < 0.0 {
+                right = portal_right;
+                right_index = index;
+            } else {
+                // Corners shared by multiple portals become the apex again
+                if path.last() != Some(&left) {
+                    path.push(left);
+                }
+                apex = left;
+                apex_index = left_index;
+                right = apex;
+                right_index = apex_index;
+                index = apex_index + 1;
+                continue;
+            }
+        }
+
+        // Narrow the funnel from the left, or turn around the right corner when it crosses it
+        if cross(sub(left, apex), sub(portal_left, apex)) <= 0.0 {
+            if apex == left || cross(sub(right, apex), sub(portal_left, apex)) > 0.0 {
+                left = portal_left;
+                left_index = index;
+            } else {
+                // Corners shared by multiple portals become the apex again
+                if path.last() != Some(&right) {
+                    path.push(right);
+                }
+                apex = right;
+                apex_index = right_index;
+                left = apex;
+                left_index = apex_index;
+                index = apex_index + 1;
+                continue;
+            }
+        }
+
+        index += 1;
+    }
+
+    let (last, _) = portals[portals.len() - 1];
+    if path.last() != Some(&last) {
+        path.push(last);
+    }
+
+    path
+}
+
+/// Remove the points in the middle of straight lines.
+fn remove_collinear(path: Vec<[f32; 2]>) -> Vec<[f32; 2]> {
+    let mut simplified: Vec<[f32; 2]> = Vec::with_capacity(path.len());
+    for point in path {
+        if simplified.len() >= 2 {
+            let (a, b) = (
+                simplified[simplified.len() - 2],
+                simplified[simplified.len() - 1],
+            );
+            if cross(sub(b, a), sub(point, b)).abs() <= f32::EPSILON {
+                simplified.pop();
+            }
+        }
+        simplified.push(point);
+    }
+
+    simplified
+}
+
+fn sub(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn cross(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+fn length(a: [f32; 2]) -> f32 {
+    a[0].hypot(a[1])
+}
+
+/// Finds paths on the maps, on a background thread where threads are available.
+///
+/// On WASM the paths are found during the frame they are requested.
+#[derive(Default)]
+struct Searcher {
+    /// The queue of the background thread & the paths it sends back.
+    #[cfg(not(target_arch = "wasm32"))]
+    thread: Option<(Sender<PathJob>, Receiver<(u32, Option<Vec<[f32; 2]>>)>)>,
+    /// The found paths.
+    #[cfg(target_arch = "wasm32")]
+    finished: Vec<(u32, Option<Vec<[f32; 2]>>)>,
+}
+
+impl Searcher {
+    /// Queue a path, the thread is started for the first path.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn search(&mut self, job: PathJob) {
+        let (jobs, _) = self.thread.get_or_insert_with(|| {
+            let (jobs, queue) = mpsc::channel::<PathJob>();
+            let (sender, finished) = mpsc::channel();
+            // The thread stops when the pathfinding is dropped
+            std::thread::spawn(move || {
+                for (id, map, from, to) in queue {
+                    if sender.send((id, map.find_path(from, to))).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            (jobs, finished)
+        });
+
+        if jobs.send(job).is_err() {
+            log::error!("pathfinding thread stopped");
+        }
+    }
+
+    /// Find a path.
+    #[cfg(target_arch = "wasm32")]
+    fn search(&mut self, (id, map, from, to): PathJob) {
+        self.finished.push((id, map.find_path(from, to)));
+    }
+
+    /// Take the paths that are found since the last call.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn finished(&mut self) -> Vec<(u32, Option<Vec<[f32; 2]>>)> {
+        match self.thread {
+            Some((_, ref finished)) => finished.try_iter().collect(),
+            None => vec![],
+        }
+    }
+
+    /// Take the paths that are found since the last call.
+    #[cfg(target_arch = "wasm32")]
+    fn finished(&mut self) -> Vec<(u32, Option<Vec<[f32; 2]>>)> {
+        std::mem::take(&mut self.finished)
+    }
+}
+
+lib! {
+/// A* pathfinding on grids & navmeshes defined by scripts.
+///
+/// Grids have a cost for every cell, navmeshes are generated from meshes covering the walkable
+/// area with the meshes of obstacles cut out. Paths are arrays of positions to walk to, from the
+/// start to the goal. On large maps a path can be found on a background thread, the callback is
+/// called with the path during a later update.
+///
+/// ```gamelisp
+/// (let grid (create-nav-grid 64 64 16))
+/// (set-nav-cell grid 10 12 #f)
+/// (let path (find-path grid 8 8 400 300))
+///
+/// (let navmesh (create-navmesh floor (arr (arr pillar 100 40))))
+/// (find-path-async navmesh 0 0 800 600 (fn (path) (= route path)))
+/// ```
+pub struct Pathfinding {
+    /// The maps, shared with the background thread while paths are found on them.
+    maps: Vec<Arc<NavMap>>,
+    /// The finder of asynchronous paths.
+    searcher: Searcher,
+    /// The functions waiting for an asynchronous path.
+    callbacks: HashMap<u32, Root<GFn>>,
+    /// The identifier of the next asynchronous path.
+    next_id: u32,
+}
+}
+
+impl Pathfinding {
+    /// Create without any maps.
+    pub fn new() -> Self {
+        Self {
+            maps: vec![],
+            searcher: Searcher::default(),
+            callbacks: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Create a grid where every cell can be walked over with a cost of 1.
+    ///
+    /// The top left corner of the grid is at the origin in world units.
+    pub fn add_grid(
+        &mut self,
+        columns: usize,
+        rows: usize,
+        cell_size: f32,
+        origin: [f32; 2],
+    ) -> NavRef {
+        self.maps.push(Arc::new(NavMap::Grid(NavGrid {
+            columns,
+            rows,
+            cell_size,
+            origin,
+            costs: vec![Some(1.0); columns * rows],
+        })));
+
+        NavRef(self.maps.len() - 1)
+    }
+
+    /// Set the cost of walking over a cell of a grid, `None` blocks it.
+    ///
+    /// The cost must be positive, cells cheaper than `1.0` like roads are preferred over the
+    /// default cells.
+    pub fn set_cell(
+        &mut self,
+        grid: NavRef,
+        column: usize,
+        row: usize,
+        cost: Option<f32>,
+    ) -> Result<()> {
+        if let Some(cost) = cost {
+            if !cost.is_finite() || cost <= 0.0 {
+                return Err(anyhow!("cell cost {} must be a positive number", cost));
+            }
+        }
+
+        // Paths being found on the background thread keep using the previous version
+        match Arc::make_mut(&mut self.maps[grid.0]) {
+            NavMap::Grid(grid) => {
+                if column >= grid.columns || row >= grid.rows {
+                    return Err(anyhow!(
+                        "cell {}, {} is outside of the grid of {}x{}",
+                        column,
+                        row,
+                        grid.columns,
+                        grid.rows
+                    ));
+                }
+                grid.costs[row * grid.columns + column] = cost;
+
+                Ok(())
+            }
+            NavMap::Mesh(_) => Err(anyhow!("cells can only be set on a grid, not on a navmesh")),
+        }
+    }
+
+    /// Create a navmesh from triangles covering the walkable area.
+    pub fn add_navmesh(&mut self, vertices: Vec<[f32; 2]>, indices: &[u16]) -> NavRef {
+        self.maps
+            .push(Arc::new(NavMap::Mesh(NavMesh::new(vertices, indices))));
+
+        NavRef(self.maps.len() - 1)
+    }
+
+    /// Find the shortest path between two world positions, `None` when the goal can't be reached.
+    pub fn find_path(&self, map: NavRef, from: [f32; 2], to: [f32; 2]) -> Option<Vec<[f32; 2]>> {
+        self.maps[map.0].find_path(from, to)
+    }
+
+    /// Call the functions of the asynchronous paths that are found.
+    pub fn finish_done() -> GResult<()> {
+        // Don't hold the borrow while calling the scripts, they might request new paths
+        let done: Vec<(Root<GFn>, Option<Vec<[f32; 2]>>)> = {
+            let mut pathfinding = Pathfinding::borrow_mut();
+
+            pathfinding
+                .searcher
+                .finished()
+                .into_iter()
+                .filter_map(|(id, path)| {
+                    pathfinding
+                        .callbacks
+                        .remove(&id)
+                        .map(|callback| (callback, path))
+                })
+                .collect()
+        };
+
+        for (callback, path) in done {
+            let _: Val = sandbox::call(&callback, &(path_pairs(path),))?;
+        }
+
+        Ok(())
+    }
+
+    /// Create a grid from a script, with the top left corner at an optional world position.
+    ///
+    /// ```gamelisp
+    /// (create-nav-grid 32 24 16 -256 -192)
+    /// ```
+    fn add_grid_script(
+        &mut self,
+        columns: usize,
+        rows: usize,
+        cell_size: f32,
+        x: Option<f32>,
+        y: Option<f32>,
+    ) -> GResult<NavRef> {
+        if columns == 0 || rows == 0 || cell_size <= 0.0 {
+            bail!(
+                "grid of {}x{} cells of size {} is empty",
+                columns,
+                rows,
+                cell_size
+            );
+        }
+
+        Ok(self.add_grid(
+            columns,
+            rows,
+            cell_size,
+            [x.unwrap_or(0.0), y.unwrap_or(0.0)],
+        ))
+    }
+
+    /// Set whether a cell of a grid can be walked over from a script, with an optional cost.
+    ///
+    /// ```gamelisp
+    /// (set-nav-cell grid 4 7 #f)
+    /// (set-nav-cell grid 5 7 #t 3.0) ; mud
+    /// ```
+    fn set_cell_script(
+        &mut self,
+        grid: &NavRef,
+        column: usize,
+        row: usize,
+        walkable: bool,
+        cost: Option<f32>,
+    ) -> GResult<()> {
+        let cost = cost.unwrap_or(1.0);
+
+        match self.set_cell(*grid, column, row, if walkable { Some(cost) } else { None }) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Create a navmesh from a script, from a mesh covering the walkable area with optional
+    /// obstacles cut out as arrays of a mesh & its position.
+    ///
+    /// Only meshes with a closed outline can be used, like for the boolean mesh operations.
+    ///
+    /// ```gamelisp
+    /// (create-navmesh room (arr (arr table 40 20) (arr crate -60 35)))
+    /// ```
+    fn add_navmesh_script(
+        &mut self,
+        area: &Mesh,
+        obstacles: Option<Vec<(Mesh, f32, f32)>>,
+    ) -> GResult<NavRef> {
+        let walkable = {
+            let boolean_ops = BooleanOps::borrow();
+            let render = Render::borrow();
+
+            let mut walkable = boolean_ops.polygons(&render, *area, [0.0, 0.0])?;
+            for (obstacle, x, y) in obstacles.unwrap_or_default() {
+                walkable = walkable.difference(&boolean_ops.polygons(&render, obstacle, [x, y])?);
+            }
+
+            walkable
+        };
+
+        let path = boolean::polygons_path(&walkable);
+        let mut geometry: VertexBuffers<[f32; 2], u16> = VertexBuffers::new();
+        if let Err(err) = FillTessellator::new().tessellate(
+            path.iter(),
+            &FillOptions::default(),
+            &mut BuffersBuilder::new(
+                &mut geometry,
+                |pos: lyon::math::Point, _: FillAttributes| [pos.x, pos.y],
+            ),
+        ) {
+            bail!("tessellating navmesh failed: {:?}", err);
+        }
+        if geometry.indices.is_empty() {
+            bail!("navmesh has no walkable area");
+        }
+
+        Ok(self.add_navmesh(geometry.vertices, &geometry.indices))
+    }
+
+    /// Find a path from a script, returns an array of positions or `#n` when there's none.
+    ///
+    /// ```gamelisp
+    /// (let path (find-path grid (.x enemy) (.y enemy) (.x player) (.y player)))
+    /// ```
+    fn find_path_script(
+        &self,
+        map: &NavRef,
+        from_x: f32,
+        from_y: f32,
+        to_x: f32,
+        to_y: f32,
+    ) -> Option<Vec<(f32, f32)>> {
+        path_pairs(self.find_path(*map, [from_x, from_y], [to_x, to_y]))
+    }
+
+    /// Find a path in the background from a script, the function is called with the path or `#n`
+    /// during a later update.
+    #[allow(clippy::too_many_arguments)]
+    fn find_path_async_script(
+        &mut self,
+        map: &NavRef,
+        from_x: f32,
+        from_y: f32,
+        to_x: f32,
+        to_y: f32,
+        callback: Root<GFn>,
+    ) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.callbacks.insert(id, callback);
+
+        self.searcher.search((
+            id,
+            Arc::clone(&self.maps[map.0]),
+            [from_x, from_y],
+            [to_x, to_y],
+        ));
+    }
+
+    /// Register the pathfinding and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Pathfinding::new());
+
+            glsp::bind_rfn("create-nav-grid", rfn!(Pathfinding::add_grid_script))?;
+            glsp::bind_rfn("set-nav-cell", rfn!(Pathfinding::set_cell_script))?;
+            glsp::bind_rfn("create-navmesh", rfn!(Pathfinding::add_navmesh_script))?;
+            glsp::bind_rfn("find-path", rfn!(Pathfinding::find_path_script))?;
+            glsp::bind_rfn("find-path-async", rfn!(Pathfinding::find_path_async_script))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// Convert a path to `(x y)` pairs that become arrays in the scripts.
+fn path_pairs(path: Option<Vec<[f32; 2]>>) -> Option<Vec<(f32, f32)>> {
+    path.map(|path| path.into_iter().map(|[x, y]| (x, y)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grid of cells of size `1.0` at the origin, `.` is walkable, `#` is blocked & a digit
+    /// is walkable with the digit as a tenth of its cost.
+    fn grid(rows: &[&str]) -> NavGrid {
+        NavGrid {
+            columns: rows[0].len(),
+            rows: rows.len(),
+            cell_size: 1.0,
+            origin: [0.0, 0.0],
+            costs: rows
+                .iter()
+                .flat_map(|row| row.chars())
+                .map(|cell| match cell {
+                    '.' => Some(1.0),
+                    '#' => None,
+                    digit => Some(digit.to_digit(10).expect("invalid cell") as f32 / 10.0),
+                })
+                .collect(),
+        }
+    }
+
+    /// The cost of walking a path through the centers of the cells, like the search does.
+    fn path_cost(grid: &NavGrid, path: &[[f32; 2]]) -> f32 {
+        let cells: Vec<usize> = path
+            .iter()
+            .map(|position| grid.cell(*position).expect("path leaves the grid"))
+            .collect();
+
+        let mut cost = 0.0;
+        for pair in cells.windows(2) {
+            let (from, to) = (grid.center(pair[0]), grid.center(pair[1]));
+            let steps = (to[0] - from[0]).abs().max((to[1] - from[1]).abs()) as usize;
+            let (dx, dy) = (
+                ((to[0] - from[0]) as isize).signum(),
+                ((to[1] - from[1]) as isize).signum(),
+            );
+            let mut cell = pair[0];
+            for _ in 0..steps {
+                let next = (cell as isize + dy * grid.columns as isize + dx) as usize;
+                cost += grid
+                    .neighbours(cell)
+                    .into_iter()
+                    .find(|(neighbour, _)| *neighbour == next)
+                    .expect("path moves to a cell that isn't a neighbour")
+                    .1;
+                cell = next;
+            }
+        }
+
+        cost
+    }
+
+    #[test]
+    fn a_star_finds_cheapest_route() {
+        // 0 -> 1 -> 3 is cheaper than the direct edge 0 -> 3
+        let edges = |node: usize| match node {
+            0 => vec![(1, 1.0), (3, 5.0)],
+            1 => vec![(2, 10.0), (3, 1.0)],
+            _ => vec![],
+        };
+
+        assert_eq!(a_star(0, 3, edges, |_| 0.0), Some(vec![0, 1, 3]));
+        assert_eq!(a_star(2, 3, edges, |_| 0.0), None);
+    }
+
+    #[test]
+    fn grid_walks_straight_lines() {
+        let grid = grid(&["....", "....", "...."]);
+
+        assert_eq!(
+            grid.find_path([0.5, 1.5], [3.5, 1.5]),
+            Some(vec![[0.5, 1.5], [3.5, 1.5]])
+        );
+        assert_eq!(
+            grid.find_path([0.5, 0.5], [2.5, 2.5]),
+            Some(vec![[0.5, 0.5], [2.5, 2.5]])
+        );
+    }
+
+    #[test]
+    fn grid_walks_around_walls() {
+        let grid = grid(&["....", "##.#", "...."]);
+
+        let path = grid
+            .find_path([0.5, 0.5], [0.5, 2.5])
+            .expect("no path found");
+        // Through the gap, where the diagonal moves can't cut the corners of the wall
+        assert!(path.contains(&[2.5, 0.5]));
+        assert!(path.contains(&[2.5, 2.5]));
+        assert_eq!(path.first(), Some(&[0.5, 0.5]));
+        assert_eq!(path.last(), Some(&[0.5, 2.5]));
+    }
+
+    #[test]
+    fn grid_blocked_routes() {
+        let walled = grid(&["....", "####", "...."]);
+        assert_eq!(walled.find_path([0.5, 0.5], [0.5, 2.5]), None);
+
+        let blocked = grid(&["#..", "...", "..#"]);
+        assert_eq!(blocked.find_path([0.5, 0.5], [2.5, 1.5]), None);
+        assert_eq!(blocked.find_path([1.5, 1.5], [2.5, 2.5]), None);
+
+        // Outside of the grid
+        assert_eq!(blocked.find_path([-1.0, 0.5], [1.5, 1.5]), None);
+    }
+
+    #[test]
+    fn grid_does_not_cut_corners() {
+        let grid = grid(&[".#", "#."]);
+
+        assert_eq!(grid.find_path([0.5, 0.5], [1.5, 1.5]), None);
+        assert!(grid.neighbours(0).is_empty());
+    }
+
+    #[test]
+    fn grid_prefers_cheap_cells() {
+        // The detour over the road is cheaper than walking straight through the field, an
+        // overestimating heuristic walks straight
+        let grid = grid(&["11111111111", "1.........1", "1.........1", "..........."]);
+
+        let path = grid
+            .find_path([0.5, 3.5], [10.5, 3.5])
+            .expect("no path found");
+        assert!(
+            path.iter().any(|[_, y]| *y < 3.0),
+            "road isn't used: {:?}",
+            path
+        );
+
+        let straight = vec![[0.5, 3.5], [10.5, 3.5]];
+        assert!(path_cost(&grid, &path) < path_cost(&grid, &straight));
+    }
+
+    #[test]
+    fn grid_heuristic_never_overestimates() {
+        let grid = grid(&["5...", "...."]);
+
+        assert!((grid.cheapest_cost() - 0.5).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn navmesh_straight_line_in_convex_area() {
+        let mesh = NavMesh::new(
+            vec![[0.0, 0.0], [2.0, 0.0], [2.0, 2.0], [0.0, 2.0]],
+            &[0, 1, 2, 0, 2, 3],
+        );
+
+        assert_eq!(
+            mesh.find_path([0.5, 1.5], [1.5, 0.5]),
+            Some(vec![[0.5, 1.5], [1.5, 0.5]])
+        );
+    }
+
+    #[test]
+    fn navmesh_pulls_around_corners() {
+        // An L shape, the inner corner at 1, 1 must be walked around
+        let mesh = NavMesh::new(
+            vec![
+                [0.0, 0.0],
+                [1.0, 0.0],
+                [2.0, 0.0],
+                [0.0, 1.0],
+                [1.0, 1.0],
+                [2.0, 1.0],
+                [1.0, 2.0],
+                [2.0, 2.0],
+            ],
+            &[0, 1, 4, 0, 4, 3, 1, 2, 5, 1, 5, 4, 4, 5, 7, 4, 7, 6],
+        );
+
+        for (from, to) in [([0.2, 0.5], [1.5, 1.8]), ([1.5, 1.8], [0.2, 0.5])].iter() {
+            assert_eq!(
+                mesh.find_path(*from, *to),
+                Some(vec![*from, [1.0, 1.0], *to])
+            );
+        }
+    }
+
+    #[test]
+    fn funnel_keeps_straight_corridors() {
+        let portals = [
+            ([0.0, 0.0], [0.0, 0.0]),
+            ([1.0, -1.0], [1.0, 1.0]),
+            ([2.0, -1.0], [2.0, 1.0]),
+            ([3.0, 0.0], [3.0, 0.0]),
+        ];
+        let straight = pull_string(&portals);
+        let flipped: Vec<_> = portals
+            .iter()
+            .map(|(left, right)| (*right, *left))
+            .collect();
+
+        // Only one of the sides is the left one, that one must give a straight line
+        assert!(
+            straight == vec![[0.0, 0.0], [3.0, 0.0]]
+                || pull_string(&flipped) == vec![[0.0, 0.0], [3.0, 0.0]]
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use crate::uniforms::UniformBlock;
+use anyhow::{anyhow, Result};
+use miniquad::{graphics::*, Context};
+
+/// The vertex shader of all post-processing passes, a quad covering the whole screen.
+const VERTEX: &str = r#"#version 100
+
+attribute vec2 a_pos;
+
+varying lowp vec2 uv;
+
+void main() {
+    gl_Position = vec4(a_pos, 0.0, 1.0);
+    uv = a_pos * 0.5 + 0.5;
+}
+"#;
+
+/// The uniforms that are always available in the post-processing fragment shaders.
+const BUILTIN_UNIFORMS: &[(&str, UniformType)] = &[
+    ("u_resolution", UniformType::Float2),
+    ("u_time", UniformType::Float1),
+];
+
+/// The name of the builtin pass converting the linear colors of the scene to sRGB.
+pub const GAMMA_PASS: &str = "gamma";
+
+/// The fragment shader of the gamma correction pass.
+const GAMMA_FRAGMENT: &str = r#"#version 100
+
+precision mediump float;
+
+uniform sampler2D u_texture;
+uniform vec2 u_resolution;
+uniform float u_time;
+
+varying lowp vec2 uv;
+
+void main() {
+    vec4 color = texture2D(u_texture, uv);
+    gl_FragColor = vec4(pow(color.rgb, vec3(1.0 / 2.2)), color.a);
+}
+"#;
+
+/// The name of the builtin pass simulating & compensating color vision deficiencies.
+pub const COLORBLIND_PASS: &str = "colorblind";
+
+/// The fragment shader of the colorblind pass.
+///
+/// The colors are converted to the LMS color space, where the response of the missing cone is
+/// derived from the other two. Compensating shifts the colors that are lost towards the ones that
+/// can still be seen.
+const COLORBLIND_FRAGMENT: &str = r#"#version 100
+
+precision mediump float;
+
+uniform sampler2D u_texture;
+uniform vec2 u_resolution;
+uniform float u_time;
+// 1 for protanopia, 2 for deuteranopia & 3 for tritanopia
+uniform float u_deficiency;
+// 0 to simulate, 1 to compensate
+uniform float u_compensate;
+
+varying lowp vec2 uv;
+
+vec3 simulate(vec3 rgb) {
+    vec3 lms = vec3(
+        dot(rgb, vec3(17.8824, 43.5161, 4.11935)),
+        dot(rgb, vec3(3.45565, 27.1554, 3.86714)),
+        dot(rgb, vec3(0.0299566, 0.184309, 1.46709))
+    );
+
+    if (u_deficiency < 1.5) {
+        lms.x = 2.02344 * lms.y - 2.52581 * lms.z;
+    } else if (u_deficiency < 2.5) {
+        lms.y = 0.494207 * lms.x + 1.24827 * lms.z;
+    } else {
+        lms.z = -0.395913 * lms.x + 0.801109 * lms.y;
+    }
+
+    return vec3(
+        dot(lms, vec3(0.0809444479, -0.130504409, 0.116721066)),
+        dot(lms, vec3(-0.0102485335, 0.0540193266, -0.113614708)),
+        dot(lms, vec3(-0.000365296938, -0.00412161469, 0.693511405))
+    );
+}
+
+void main() {
+    vec4 color = texture2D(u_texture, uv);
+    vec3 simulated = simulate(color.rgb);
+
+    if (u_compensate > 0.5) {
+        vec3 lost = color.rgb - simulated;
+        vec3 shifted = color.rgb + vec3(0.0, 0.7 * lost.r + lost.g, 0.7 * lost.r + lost.b);
+        gl_FragColor = vec4(clamp(shifted, 0.0, 1.0), color.a);
+    } else {
+        gl_FragColor = vec4(clamp(simulated, 0.0, 1.0), color.a);
+    }
+}
+"#;
+
+/// A post-processing pass that still has to be compiled.
+#[derive(Debug, Clone)]
+pub struct PostPassDescriptor {
+    /// The name used by scripts to set the uniforms.
+    pub name: String,
+    /// The GLSL source of the fragment shader.
+    pub fragment: String,
+    /// The uniforms of the fragment shader besides the builtin ones.
+    pub uniforms: Vec<(String, UniformType)>,
+}
+
+impl PostPassDescriptor {
+    /// The builtin pass converting the linear colors of the scene to sRGB.
+    pub fn gamma() -> Self {
+        Self {
+            name: GAMMA_PASS.to_string(),
+            fragment: GAMMA_FRAGMENT.to_string(),
+            uniforms: vec![],
+        }
+    }
+
+    /// The builtin pass simulating & compensating color vision deficiencies.
+    pub fn colorblind() -> Self {
+        Self {
+            name: COLORBLIND_PASS.to_string(),
+            fragment: COLORBLIND_FRAGMENT.to_string(),
+            uniforms: vec![
+                ("u_deficiency".to_string(), UniformType::Float1),
+                ("u_compensate".to_string(), UniformType::Float1),
+            ],
+        }
+    }
+}
+
+/// A compiled fullscreen post-processing pass.
+struct PostPass {
+    /// The name used by scripts to set the uniforms.
+    name: String,
+    /// The pipeline with the fragment shader.
+    pipeline: Pipeline,
+    /// The values of the builtin & custom uniforms.
+    uniforms: UniformBlock,
+    /// Whether the pass is applied, skipped passes cost nothing.
+    enabled: bool,
+}
+
+/// Renders the scene to a texture and applies a chain of fullscreen passes to it.
+pub struct PostProcessing {
+    /// The passes in the order they are applied.
+    passes: Vec<PostPass>,
+    /// Two render targets with their color & depth textures, alternated between passes.
+    targets: Vec<(Texture, Texture, RenderPass)>,
+    /// The size of the render targets.
+    target_size: (u32, u32),
+    /// The fullscreen quad, the image is replaced for every pass, created with the first pass.
+    quad: Option<Bindings>,
+    /// When the post-processing was created, used for the time uniform.
+    start_time: f64,
+}
+
+impl PostProcessing {
+    /// Create without any passes.
+    pub fn new() -> Self {
+        Self {
+            passes: vec![],
+            targets: vec![],
+            target_size: (0, 0),
+            quad: None,
+            start_time: miniquad::date::now(),
+        }
+    }
+
+    /// Compile a pass and add it to the end of the chain.
+    ///
+    /// The fragment shader receives the previous result as `uniform sampler2D u_texture`, the
+    /// texture coordinates as `varying lowp vec2 uv` and the builtin uniforms
+    /// `uniform vec2 u_resolution` & `uniform float u_time`.
+    pub fn add_pass(&mut self, ctx: &mut Context, descriptor: &PostPassDescriptor) -> Result<()> {
+        let (uniforms, layout) = UniformBlock::new(BUILTIN_UNIFORMS, &descriptor.uniforms)?;
+
+        let meta = ShaderMeta {
+            images: &["u_texture"],
+            uniforms: layout,
+        };
+        let shader = Shader::new(ctx, VERTEX, &descriptor.fragment, meta).map_err(|err| {
+            anyhow!(
+                "compiling post-processing shader \"{}\" failed: {:?}",
+                descriptor.name,
+                err
+            )
+        })?;
+        let pipeline = Pipeline::new(
+            ctx,
+            &[BufferLayout::default()],
+            &[VertexAttribute::new("a_pos", VertexFormat::Float2)],
+            shader,
+        );
+
+        // Create the fullscreen quad
+        if self.quad.is_none() {
+            #[rustfmt::skip]
+            let vertices: [f32; 8] = [
+                -1.0, -1.0,
+                1.0, -1.0,
+                1.0, 1.0,
+                -1.0, 1.0,
+            ];
+            let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
+
+            self.quad = Some(Bindings {
+                vertex_buffers: vec![Buffer::immutable(ctx, BufferType::VertexBuffer, &vertices)],
+                index_buffer: Buffer::immutable(ctx, BufferType::IndexBuffer, &indices),
+                images: vec![],
+            });
+        }
+
+        self.passes.push(PostPass {
+            name: descriptor.name.clone(),
+            pipeline,
+            uniforms,
+            enabled: true,
+        });
+
+        Ok(())
+    }
+
+    /// Whether there are any enabled passes, if not the scene is rendered directly to the screen.
+    pub fn is_enabled(&self) -> bool {
+        self.passes.iter().any(|pass| pass.enabled)
+    }
+
+    /// Set whether a pass is applied.
+    pub fn set_pass_enabled(&mut self, pass: &str, enabled: bool) -> Result<()> {
+        self.pass_mut(pass)?.enabled = enabled;
+
+        Ok(())
+    }
+
+    /// Set the value of a uniform of a pass.
+    pub fn set_uniform(&mut self, pass: &str, uniform: &str, values: &[f32]) -> Result<()> {
+        self.pass_mut(pass)?.uniforms.set(uniform, values)
+    }
+
+    /// A pass by its name.
+    fn pass_mut(&mut self, pass: &str) -> Result<&mut PostPass> {
+        self.passes
+            .iter_mut()
+            .find(|p| p.name == pass)
+            .ok_or_else(|| anyhow!("post-processing pass \"{}\" doesn't exist", pass))
+    }
+
+    /// The render pass the scene must be rendered to, resized to the frame when needed.
+    pub fn scene_pass(&mut self, ctx: &mut Context, size: (u32, u32)) -> Option<RenderPass> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        if self.target_size != size {
+            self.create_targets(ctx, size);
+        }
+
+        Some(self.targets[0].2)
+    }
+
+    /// Apply all passes, the last one renders to the output or the screen if there's none.
+    pub fn apply(&mut self, ctx: &mut Context, output: Option<RenderPass>, size: (u32, u32)) {
+        // The quad only exists when there are passes
+        let quad = match self.quad {
+            Some(ref mut quad) => quad,
+            None => return,
+        };
+
+        let (width, height) = (size.0 as f32, size.1 as f32);
+        let time = (miniquad::date::now() - self.start_time) as f32;
+
+        let pass_count = self.passes.iter().filter(|pass| pass.enabled).count();
+        let passes = self.passes.iter_mut().filter(|pass| pass.enabled);
+        for (index, pass) in passes.enumerate() {
+            let source = self.targets[index % 2].0;
+            let destination = if index + 1 == pass_count {
+                output
+            } else {
+                Some(self.targets[(index + 1) % 2].2)
+            };
+
+            // Fill the builtin uniforms
+            pass.uniforms.values_mut()[..3].copy_from_slice(&[width, height, time]);
+
+            quad.images = vec![source];
+            ctx.begin_pass(destination, PassAction::Nothing);
+            ctx.apply_pipeline(&pass.pipeline);
+            ctx.apply_bindings(quad);
+            pass.uniforms.apply(ctx);
+            ctx.draw(0, 6, 1);
+            ctx.end_render_pass();
+        }
+    }
+
+    /// (Re)create the render targets with a new size.
+    fn create_targets(&mut self, ctx: &mut Context, (width, height): (u32, u32)) {
+        for (color, depth, pass) in self.targets.drain(..) {
+            pass.delete(ctx);
+            color.delete();
+            depth.delete();
+        }
+
+        for _ in 0..2 {
+            self.targets.push(render_target(ctx, width, height));
+        }
+        self.target_size = (width, height);
+    }
+}
+
+/// Create a texture with a depth texture and a render pass to render to them.
+pub fn render_target(ctx: &mut Context, width: u32, height: u32) -> (Texture, Texture, RenderPass) {
+    let color = Texture::new_render_texture(
+        ctx,
+        TextureParams {
+            width,
+            height,
+            format: TextureFormat::RGBA8,
+            ..Default::default()
+        },
+    );
+    let depth = Texture::new_render_texture(
+        ctx,
+        TextureParams {
+            width,
+            height,
+            format: TextureFormat::Depth,
+            ..Default::default()
+        },
+    );
+    let pass = RenderPass::new(ctx, color, Some(depth));
+
+    (color, depth, pass)
+}
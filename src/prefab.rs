@@ -0,0 +1,472 @@
+use crate::{
+    assets::Assets,
+    ecs::{Entity, World},
+    render::{Instance, InstanceRef, Render},
+    sandbox,
+    script::Scripts,
+};
+use glsp::{bail, lib, rfn, GFn, GResult, IntoVal, Root, Runtime, Tab, Val};
+use std::collections::HashMap;
+
+/// How deep prefabs can be nested, deeper nesting is treated as a prefab containing itself.
+const MAX_DEPTH: usize = 32;
+
+/// The transform of a prefab, unset values fall back to the prefab it's based on.
+#[derive(Debug, Default, Copy, Clone)]
+struct Transform {
+    x: Option<f32>,
+    y: Option<f32>,
+    z: Option<u8>,
+    rotation: Option<f32>,
+    scale_x: Option<f32>,
+    scale_y: Option<f32>,
+}
+
+impl Transform {
+    /// Read the transform from a definition table.
+    fn parse(tab: &Root<Tab>) -> GResult<Self> {
+        let scale: Option<f32> = tab.get_if_present(glsp::sym("scale")?)?;
+
+        Ok(Self {
+            x: tab.get_if_present(glsp::sym("x")?)?,
+            y: tab.get_if_present(glsp::sym("y")?)?,
+            z: tab.get_if_present(glsp::sym("z")?)?,
+            rotation: tab.get_if_present(glsp::sym("rotation")?)?,
+            scale_x: tab.get_if_present(glsp::sym("scale-x")?)?.or(scale),
+            scale_y: tab.get_if_present(glsp::sym("scale-y")?)?.or(scale),
+        })
+    }
+
+    /// Use the values of another transform where this one doesn't set them.
+    fn or(self, base: Transform) -> Self {
+        Self {
+            x: self.x.or(base.x),
+            y: self.y.or(base.y),
+            z: self.z.or(base.z),
+            rotation: self.rotation.or(base.rotation),
+            scale_x: self.scale_x.or(base.scale_x),
+            scale_y: self.scale_y.or(base.scale_y),
+        }
+    }
+
+    /// The instance data, offset by a position.
+    fn instance(&self, x: f32, y: f32) -> Instance {
+        let mut instance = Instance::new(x + self.x.unwrap_or(0.0), y + self.y.unwrap_or(0.0));
+        instance.set_z(self.z.unwrap_or(0));
+        instance.set_rotation(self.rotation.unwrap_or(0.0));
+        instance.set_scale_x(self.scale_x.unwrap_or(1.0));
+        instance.set_scale_y(self.scale_y.unwrap_or(1.0));
+
+        instance
+    }
+}
+
+/// A named instance template with its components & nested prefabs.
+#[derive(Debug, Clone)]
+struct Prefab {
+    /// The prefab this one is based on, it's spawned with the transform of this one.
+    base: Option<String>,
+    /// The reference name of the asset of the mesh, a transform node is used without one.
+    mesh: Option<String>,
+    /// The default transform, relative to the parent for nested prefabs.
+    transform: Transform,
+    /// The components every spawned entity gets a copy of.
+    components: Option<Root<Tab>>,
+    /// Prefabs spawned as children of the instance.
+    children: Vec<Prefab>,
+    /// Called with the spawned entity.
+    init: Option<Root<GFn>>,
+}
+
+impl Prefab {
+    /// Read a prefab from a definition, a table or the name of the prefab it's based on.
+    fn parse(definition: &Val) -> GResult<Self> {
+        let tab = match definition {
+            Val::Str(name) => return Ok(Self::based_on(name.to_string())),
+            Val::Sym(name) => return Ok(Self::based_on(name.name().to_string())),
+            Val::Tab(tab) => tab,
+            _ => bail!("prefab {} must be a table or a prefab name", definition),
+        };
+
+        let children: Option<Vec<Val>> = tab.get_if_present(glsp::sym("children")?)?;
+
+        Ok(Self {
+            base: tab.get_if_present(glsp::sym("prefab")?)?,
+            mesh: tab.get_if_present(glsp::sym("mesh")?)?,
+            transform: Transform::parse(tab)?,
+            components: tab.get_if_present(glsp::sym("components")?)?,
+            children: children
+                .unwrap_or_default()
+                .iter()
+                .map(Prefab::parse)
+                .collect::<GResult<_>>()?,
+            init: tab.get_if_present(glsp::sym("init")?)?,
+        })
+    }
+
+    /// A prefab spawning another one with its defaults.
+    fn based_on(name: String) -> Self {
+        Self {
+            base: Some(name),
+            mesh: None,
+            transform: Transform::default(),
+            components: None,
+            children: vec![],
+            init: None,
+        }
+    }
+}
+
+/// An entity of a prefab that's about to be spawned.
+struct Node {
+    /// The name the prefab was spawned or nested with.
+    name: String,
+    mesh: Option<String>,
+    instance: Instance,
+    /// The index of the parent node.
+    parent: Option<usize>,
+    components: Vec<Root<Tab>>,
+    init: Vec<Root<GFn>>,
+}
+
+lib! {
+/// Named templates of instances with their components, spawned as entities by name.
+///
+/// A prefab is a table with the asset reference name of its `mesh`, a default transform with `x`,
+/// `y`, `z`, `rotation`, `scale`, `scale-x` & `scale-y`, the `components` every entity gets a copy
+/// of, an `init` function called with the spawned entity & `children` that are spawned as child
+/// instances. A prefab with a `prefab` name is based on that prefab, its own values override it.
+/// The spawned entities have the `instance` & `prefab` components and children get the `parent`
+/// component.
+///
+/// ```gamelisp
+/// (define-prefab "turret" (tab ('mesh "turret") ('components (tab ('fire-rate 0.5)))))
+/// (define-prefab "tank" (tab
+///   ('mesh "tank")
+///   ('z 2)
+///   ('components (tab ('health 100)))
+///   ('children (arr (tab ('prefab "turret") ('y -10.0))))))
+///
+/// (let tank (spawn-prefab "tank" 100.0 50.0))
+/// ```
+pub struct Prefabs {
+    prefabs: HashMap<String, Prefab>,
+}
+}
+
+impl Prefabs {
+    /// Create without any prefabs.
+    pub fn new() -> Self {
+        Self {
+            prefabs: HashMap::new(),
+        }
+    }
+
+    /// Whether a prefab is defined.
+    pub fn contains(&self, name: &str) -> bool {
+        self.prefabs.contains_key(name)
+    }
+
+    /// The names of all defined prefabs.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.prefabs.keys().cloned().collect();
+        names.sort();
+
+        names
+    }
+
+    /// Define or replace a prefab from a script.
+    fn define_prefab(&mut self, name: &str, definition: Val) -> GResult<()> {
+        let prefab = Prefab::parse(&definition)?;
+        self.prefabs.insert(name.to_string(), prefab);
+
+        Ok(())
+    }
+
+    /// Flatten a prefab & everything it's based on into nodes, parents before their children.
+    fn resolve(
+        &self,
+        prefab: &Prefab,
+        [x, y]: [f32; 2],
+        parent: Option<usize>,
+        name: Option<&str>,
+        nodes: &mut Vec<Node>,
+        depth: usize,
+    ) -> GResult<()> {
+        if depth > MAX_DEPTH {
+            bail!("prefabs are nested too deep, does one contain itself?");
+        }
+
+        // Collect the chain of prefabs this one is based on, the most specific first
+        let mut chain = vec![prefab];
+        let mut name = name.map(|name| name.to_string());
+        while let Some(base) = &chain[chain.len() - 1].base {
+            if chain.len() > MAX_DEPTH {
+                bail!("prefab \"{}\" is based on itself", base);
+            }
+            match self.prefabs.get(base) {
+                Some(prefab) => chain.push(prefab),
+                None => bail!("prefab \"{}\" isn't defined", base),
+            }
+            name = name.or_else(|| Some(base.clone()));
+        }
+
+        let transform = chain
+            .iter()
+            .fold(Transform::default(), |transform, prefab| {
+                transform.or(prefab.transform)
+            });
+        let index = nodes.len();
+        nodes.push(Node {
+            name: name.clone().unwrap_or_default(),
+            mesh: chain.iter().find_map(|prefab| prefab.mesh.clone()),
+            instance: transform.instance(x, y),
+            parent,
+            // The components & initialization of the bases come first so they can be overridden
+            components: chain
+                .iter()
+                .rev()
+                .filter_map(|prefab| prefab.components.clone())
+                .collect(),
+            init: chain
+                .iter()
+                .rev()
+                .filter_map(|prefab| prefab.init.clone())
+                .collect(),
+        });
+
+        for prefab in chain.iter().rev() {
+            for child in prefab.children.iter() {
+                self.resolve(child, [0.0, 0.0], Some(index), None, nodes, depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Spawn a prefab with its nested prefabs as entities, returns the outermost entity.
+    ///
+    /// Must be called inside the GameLisp runtime without the prefabs, assets, render or world
+    /// being borrowed.
+    pub fn spawn(name: &str, x: f32, y: f32) -> GResult<Entity> {
+        let nodes = {
+            let prefabs = Prefabs::borrow();
+            let prefab = Prefab::based_on(name.to_string());
+            let mut nodes = vec![];
+            prefabs.resolve(&prefab, [x, y], None, Some(name), &mut nodes, 0)?;
+
+            nodes
+        };
+
+        // Find all meshes before adding anything so a missing one doesn't leave a partial prefab
+        let meshes = {
+            let assets = Assets::borrow();
+            nodes
+                .iter()
+                .map(|node| match &node.mesh {
+                    Some(mesh) => match assets.mesh(mesh) {
+                        Some(mesh) => Ok(Some(mesh)),
+                        None => bail!("mesh \"{}\" of prefab \"{}\" isn't loaded", mesh, node.name),
+                    },
+                    None => Ok(None),
+                })
+                .collect::<GResult<Vec<_>>>()?
+        };
+
+        let instances = {
+            let mut render = Render::borrow_mut();
+            let mut instances: Vec<InstanceRef> = Vec::with_capacity(nodes.len());
+            for (node, mesh) in nodes.iter().zip(meshes) {
+                let instance = match mesh {
+                    Some(mesh) => render.add_instance(mesh, node.instance),
+                    None => {
+                        let transform_node = render.add_transform_node(0.0, 0.0);
+                        if let Some(data) = render.instance_mut(transform_node) {
+                            *data = node.instance;
+                        }
+
+                        transform_node
+                    }
+                };
+                if let Some(parent) = node.parent {
+                    if let Err(err) = render.set_parent(instance, instances[parent]) {
+                        bail!("{}", err);
+                    }
+                }
+
+                instances.push(instance);
+            }
+
+            instances
+        };
+
+        let entities = {
+            let mut world = World::borrow_mut();
+            let mut entities: Vec<Entity> = Vec::with_capacity(nodes.len());
+            for (node, instance) in nodes.iter().zip(instances) {
+                let entity = world.spawn();
+                world.set_component(entity, glsp::sym("instance")?, instance.into_val()?)?;
+                world.set_component(
+                    entity,
+                    glsp::sym("prefab")?,
+                    node.name.as_str().into_val()?,
+                )?;
+                if let Some(parent) = node.parent {
+                    world.set_component(
+                        entity,
+                        glsp::sym("parent")?,
+                        entities[parent].into_val()?,
+                    )?;
+                }
+                // Every entity gets its own copy so changing one doesn't change the others
+                for components in node.components.iter() {
+                    for (component, value) in components.deep_clone()?.entries().iter() {
+                        match component {
+                            Val::Sym(component) => world.set_component(entity, component, value)?,
+                            _ => bail!(
+                                "component name {} of prefab \"{}\" must be a symbol",
+                                component,
+                                node.name
+                            ),
+                        }
+                    }
+                }
+
+                entities.push(entity);
+            }
+
+            entities
+        };
+
+        // Don't hold any borrows while calling the scripts, they might spawn more prefabs
+        for (node, entity) in nodes.iter().zip(entities.iter()) {
+            for init in node.init.iter() {
+                let _: Val = sandbox::call(init, &(*entity,))?;
+            }
+        }
+
+        Ok(entities[0])
+    }
+
+    /// Despawn an entity spawned from a prefab with the entities of its nested prefabs, removing
+    /// their instances.
+    ///
+    /// Returns whether the entity was alive. Must be called inside the GameLisp runtime without
+    /// the render or world being borrowed.
+    pub fn despawn(entity: Entity) -> GResult<bool> {
+        let mut world = World::borrow_mut();
+        if !world.is_alive(entity) {
+            return Ok(false);
+        }
+
+        // Collect the nested entities breadth-first, parents before their children
+        let children = world.query(&[glsp::sym("parent")?]);
+        let mut entities = vec![entity];
+        let mut index = 0;
+        while index < entities.len() {
+            let parent = entities[index];
+            entities.extend(
+                children
+                    .iter()
+                    .filter(|(_, values)| match values[0] {
+                        Val::RData(ref rdata) if rdata.is::<Entity>() => {
+                            *rdata.borrow::<Entity>() == parent
+                        }
+                        _ => false,
+                    })
+                    .map(|(child, _)| *child),
+            );
+            index += 1;
+        }
+
+        let instance = glsp::sym("instance")?;
+        let mut render = Render::borrow_mut();
+        for entity in entities {
+            if let Some(Val::RData(rdata)) = world.component(entity, instance) {
+                if rdata.is::<InstanceRef>() {
+                    render.remove_instance(*rdata.borrow::<InstanceRef>());
+                }
+            }
+            world.despawn(entity);
+        }
+
+        Ok(true)
+    }
+
+    /// Spawn a prefab from a script.
+    ///
+    /// ```gamelisp
+    /// (let enemy (spawn-prefab "enemy" 200.0 0.0))
+    /// (inc! [(component enemy 'health)] 10)
+    /// ```
+    fn spawn_prefab(name: &str, x: f32, y: f32) -> GResult<Entity> {
+        Prefabs::spawn(name, x, y)
+    }
+
+    /// Despawn a prefab with its nested prefabs & instances from a script.
+    ///
+    /// ```gamelisp
+    /// (despawn-prefab enemy)
+    /// ```
+    fn despawn_prefab(entity: &Entity) -> GResult<bool> {
+        Prefabs::despawn(*entity)
+    }
+
+    /// Define the prefabs from a script evaluating to a table of names with their definitions,
+    /// returns the names.
+    ///
+    /// ```gamelisp
+    /// ; prefabs.glsp
+    /// (tab
+    ///   ("coin" (tab ('mesh "coin") ('components (tab ('value 1)))))
+    ///   ("big-coin" (tab ('prefab "coin") ('scale 2.0) ('components (tab ('value 5))))))
+    ///
+    /// (load-prefabs "prefabs.glsp")
+    /// ```
+    fn load_prefabs(name: &str) -> GResult<Vec<String>> {
+        let definitions = match Scripts::load(name)? {
+            Val::Tab(definitions) => definitions,
+            val => bail!(
+                "prefab script \"{}\" must evaluate to a table, got {}",
+                name,
+                val
+            ),
+        };
+
+        let mut names = vec![];
+        let mut prefabs = Prefabs::borrow_mut();
+        for (name, definition) in definitions.entries().iter() {
+            let name = match name {
+                Val::Str(name) => name.to_string(),
+                Val::Sym(name) => name.name().to_string(),
+                _ => bail!("prefab name {} must be a string or a symbol", name),
+            };
+            prefabs.define_prefab(&name, definition)?;
+
+            names.push(name);
+        }
+
+        Ok(names)
+    }
+
+    /// Whether a prefab is defined from a script.
+    fn has_prefab(&self, name: &str) -> bool {
+        self.contains(name)
+    }
+
+    /// Register the prefabs and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Prefabs::new());
+
+            glsp::bind_rfn("define-prefab", rfn!(Prefabs::define_prefab))?;
+            glsp::bind_rfn("load-prefabs", rfn!(Prefabs::load_prefabs))?;
+            glsp::bind_rfn("spawn-prefab", rfn!(Prefabs::spawn_prefab))?;
+            glsp::bind_rfn("despawn-prefab", rfn!(Prefabs::despawn_prefab))?;
+            glsp::bind_rfn("prefab?", rfn!(Prefabs::has_prefab))?;
+            glsp::bind_rfn("prefab-names", rfn!(Prefabs::names))?;
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,16 @@
+/// Open a profiling span that lasts until the end of the enclosing scope.
+///
+/// With the `profiling` feature this emits a `tracing` span, any `tracing` subscriber can collect
+/// them, like `tracing-chrome` for a flamegraph in the browser or `tracing-tracy` for a live
+/// view. Without the feature it compiles to nothing. Local variables can be recorded as fields of
+/// the span:
+///
+/// ```ignore
+/// profile_scope!("script", function);
+/// ```
+macro_rules! profile_scope {
+    ($name:literal $(, $field:ident)*) => {
+        #[cfg(feature = "profiling")]
+        let _profile_span = tracing::info_span!($name $(, $field)*).entered();
+    };
+}
@@ -0,0 +1,283 @@
+use crate::{
+    assets::Assets,
+    render::{Instance, InstanceRef, Mesh, Render},
+};
+use glsp::{bail, lib, rdata, rfn, GResult, Runtime};
+use std::collections::HashMap;
+
+/// A named part of a SVG asset, uploaded as its own mesh.
+#[derive(Debug, Clone)]
+struct RigPart {
+    /// The ID of the group in the SVG.
+    name: String,
+    /// The index of the part it's attached to, `None` for the paths that aren't in a part.
+    parent: Option<usize>,
+    /// The mesh with the paths of the part, `None` when it only contains other parts.
+    mesh: Option<Mesh>,
+    /// The point the part rotates & scales around in the coordinates of the SVG.
+    pivot: [f32; 2],
+}
+
+rdata! {
+/// A SVG asset drawn as separate parts that can be transformed individually, like a cutout
+/// puppet.
+///
+/// Every part is a transform node at its pivot, attached to the node of the part it's in. The
+/// whole puppet is moved with the root instance, the parts with their own instance.
+#[derive(Debug, Clone)]
+pub struct Puppet {
+    /// The node the whole puppet is attached to.
+    root: InstanceRef,
+    /// The node of every part by name, with the position relative to its parent at rest.
+    parts: HashMap<String, (InstanceRef, [f32; 2])>,
+    /// All instances of the puppet, so they can be removed together.
+    instances: Vec<InstanceRef>,
+}
+}
+
+lib! {
+/// The parts of every SVG asset that's used as a puppet.
+///
+/// Every group with an `id` attribute in the SVG is a part, groups inside a part are attached to
+/// it. Parts rotate around the center of their geometry unless another pivot is set, which
+/// applies to the puppets of the asset created afterwards.
+///
+/// ```gamelisp
+/// (set-part-pivot "character" "arm" 42 80)
+/// (let player (create-puppet "character" 0 0))
+/// (set-part-rotation player "arm" (sin time))
+/// ```
+pub struct Puppets {
+    /// The uploaded parts of every asset, by the reference name of the asset.
+    rigs: HashMap<String, Vec<RigPart>>,
+    /// Pivots set by scripts for parts of assets that aren't uploaded yet.
+    pivots: HashMap<(String, String), [f32; 2]>,
+}
+}
+
+impl Puppets {
+    /// Create without any uploaded parts.
+    pub fn new() -> Self {
+        Self {
+            rigs: HashMap::new(),
+            pivots: HashMap::new(),
+        }
+    }
+
+    /// The parts of a loaded SVG asset, they are uploaded the first time.
+    fn rig(&mut self, asset: &str, render: &mut Render) -> GResult<&[RigPart]> {
+        if !self.rigs.contains_key(asset) {
+            let assets = Assets::borrow();
+            let data = match assets.data(asset) {
+                Some(data) => data,
+                None => bail!("SVG asset \"{}\" is not loaded", asset),
+            };
+            let parts = match Render::tessellate_svg_parts(data) {
+                Ok(parts) => parts,
+                Err(err) => bail!("splitting SVG asset \"{}\" in parts failed: {}", asset, err),
+            };
+
+            let mut rig = Vec::with_capacity(parts.len());
+            for part in parts {
+                let mesh = if part.geometry.is_empty() {
+                    None
+                } else {
+                    match render.upload_svg_geometry(part.geometry) {
+                        Ok(mesh) => Some(mesh),
+                        Err(err) => bail!("uploading part \"{}\" failed: {}", part.name, err),
+                    }
+                };
+                let pivot = self
+                    .pivots
+                    .get(&(asset.to_string(), part.name.clone()))
+                    .copied()
+                    .unwrap_or(part.pivot);
+
+                rig.push(RigPart {
+                    name: part.name,
+                    parent: part.parent,
+                    mesh,
+                    pivot,
+                });
+            }
+            self.rigs.insert(asset.to_string(), rig);
+        }
+
+        Ok(&self.rigs[asset])
+    }
+
+    /// Add the instances of all parts of a loaded SVG asset from a script.
+    ///
+    /// The puppet is placed with the origin of the SVG at the position.
+    ///
+    /// ```gamelisp
+    /// (let enemy (create-puppet "robot" 100 50))
+    /// ```
+    fn create_puppet(&mut self, asset: &str, x: f32, y: f32) -> GResult<Puppet> {
+        let mut render = Render::borrow_mut();
+        let rig = self.rig(asset, &mut render)?.to_vec();
+
+        let root = render.add_transform_node(x, y);
+        let mut puppet = Puppet {
+            root,
+            parts: HashMap::new(),
+            instances: vec![root],
+        };
+
+        // Parents come before their children so their nodes already exist
+        let mut nodes: Vec<InstanceRef> = Vec::with_capacity(rig.len());
+        for part in rig.iter() {
+            let (parent, parent_pivot) = match part.parent {
+                Some(parent) => (nodes[parent], rig[parent].pivot),
+                None => (root, [0.0, 0.0]),
+            };
+
+            // The paths outside of the parts are attached to the root directly
+            let (node, pivot) = if part.parent.is_none() {
+                (root, [0.0, 0.0])
+            } else {
+                let rest = [
+                    part.pivot[0] - parent_pivot[0],
+                    part.pivot[1] - parent_pivot[1],
+                ];
+                let node = render.add_transform_node(rest[0], rest[1]);
+                attach(&mut render, node, parent)?;
+                puppet.instances.push(node);
+                puppet.parts.insert(part.name.clone(), (node, rest));
+
+                (node, part.pivot)
+            };
+            nodes.push(node);
+
+            if let Some(mesh) = part.mesh {
+                let instance = render.add_instance(mesh, Instance::new(-pivot[0], -pivot[1]));
+                attach(&mut render, instance, node)?;
+                puppet.instances.push(instance);
+            }
+        }
+
+        Ok(puppet)
+    }
+
+    /// Set the point a part of an asset rotates & scales around from a script.
+    ///
+    /// The pivot is in the coordinates of the SVG and only applies to puppets created after
+    /// calling this for the first time.
+    fn set_part_pivot(&mut self, asset: &str, part: &str, x: f32, y: f32) {
+        self.pivots
+            .insert((asset.to_string(), part.to_string()), [x, y]);
+
+        if let Some(rig_part) = self
+            .rigs
+            .get_mut(asset)
+            .and_then(|rig| rig.iter_mut().find(|rig_part| rig_part.name == part))
+        {
+            rig_part.pivot = [x, y];
+        }
+    }
+
+    /// Register the puppets and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Puppets::new());
+
+            glsp::bind_rfn("create-puppet", rfn!(Puppets::create_puppet))?;
+            glsp::bind_rfn("set-part-pivot", rfn!(Puppets::set_part_pivot))?;
+            glsp::bind_rfn("puppet-instance", rfn!(Puppet::root))?;
+            glsp::bind_rfn("puppet-part", rfn!(Puppet::part))?;
+            glsp::bind_rfn("puppet-parts", rfn!(Puppet::part_names))?;
+            glsp::bind_rfn("set-part-rotation", rfn!(Puppet::set_part_rotation))?;
+            glsp::bind_rfn("set-part-offset", rfn!(Puppet::set_part_offset))?;
+            glsp::bind_rfn("set-part-scale", rfn!(Puppet::set_part_scale))?;
+            glsp::bind_rfn("remove-puppet", rfn!(Puppet::remove))?;
+
+            Ok(())
+        });
+    }
+}
+
+impl Puppet {
+    /// The node the whole puppet is attached to, for moving it with the instance functions.
+    pub fn root(&self) -> InstanceRef {
+        self.root
+    }
+
+    /// The node of a part with its position relative to its parent at rest.
+    pub fn part_node(&self, name: &str) -> Option<(InstanceRef, [f32; 2])> {
+        self.parts.get(name).copied()
+    }
+
+    /// The node of a part, for transforming it with the instance functions.
+    ///
+    /// ```gamelisp
+    /// (= [(puppet-part player "head") 'scale] 1.2)
+    /// ```
+    fn part(&self, name: &str) -> GResult<InstanceRef> {
+        match self.parts.get(name) {
+            Some((node, _)) => Ok(*node),
+            None => bail!("puppet doesn't have a part \"{}\"", name),
+        }
+    }
+
+    /// The names of all parts, sorted alphabetically.
+    fn part_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.parts.keys().cloned().collect();
+        names.sort();
+
+        names
+    }
+
+    /// Rotate a part around its pivot from a script, the angle is in radians.
+    ///
+    /// ```gamelisp
+    /// (set-part-rotation player "arm" (* (sin time) 0.5))
+    /// ```
+    fn set_part_rotation(&self, name: &str, rotation: f32) -> GResult<()> {
+        let node = self.part(name)?;
+        if let Some(instance) = Render::borrow_mut().instance_mut(node) {
+            instance.set_rotation(rotation);
+        }
+
+        Ok(())
+    }
+
+    /// Move a part away from its position at rest from a script.
+    fn set_part_offset(&self, name: &str, x: f32, y: f32) -> GResult<()> {
+        let node = self.part(name)?;
+        let rest = self.parts[name].1;
+        if let Some(instance) = Render::borrow_mut().instance_mut(node) {
+            instance.set_x(rest[0] + x);
+            instance.set_y(rest[1] + y);
+        }
+
+        Ok(())
+    }
+
+    /// Scale a part around its pivot from a script, the vertical scale defaults to the
+    /// horizontal one.
+    fn set_part_scale(&self, name: &str, scale_x: f32, scale_y: Option<f32>) -> GResult<()> {
+        let node = self.part(name)?;
+        if let Some(instance) = Render::borrow_mut().instance_mut(node) {
+            instance.set_scale_x(scale_x);
+            instance.set_scale_y(scale_y.unwrap_or(scale_x));
+        }
+
+        Ok(())
+    }
+
+    /// Remove all instances of the puppet from a script.
+    fn remove(&self) {
+        let mut render = Render::borrow_mut();
+        for instance in self.instances.iter().rev() {
+            render.remove_instance(*instance);
+        }
+    }
+}
+
+/// Attach an instance to the node of a part.
+fn attach(render: &mut Render, child: InstanceRef, parent: InstanceRef) -> GResult<()> {
+    match render.set_parent(child, parent) {
+        Ok(()) => Ok(()),
+        Err(err) => bail!("attaching puppet part failed: {}", err),
+    }
+}
@@ -0,0 +1,319 @@
+use glsp::{bail, lib, rfn, Arr, DequeOps, GResult, Num, Runtime, Val};
+
+/// The amount of entries in the permutation table of the noise.
+const PERMUTATION_SIZE: usize = 256;
+
+/// The directions of the gradients of the 2D noise.
+const GRADIENTS_2D: [[f32; 2]; 8] = [
+    [1.0, 1.0],
+    [-1.0, 1.0],
+    [1.0, -1.0],
+    [-1.0, -1.0],
+    [1.0, 0.0],
+    [-1.0, 0.0],
+    [0.0, 1.0],
+    [0.0, -1.0],
+];
+
+lib! {
+/// Seeded random numbers & gradient noise for scripts.
+///
+/// Everything is derived from the seed, so the same seed always produces the same numbers &
+/// the same noise, on every platform. The seed & the state of the generator are stored in replay
+/// recordings so random events play out the same way.
+///
+/// ```gamelisp
+/// (set-seed 1234)
+/// (let damage (rand-range 10 20))
+/// (let height (* 50 (perlin-noise (* x 0.01))))
+/// ```
+pub struct Random {
+    /// The seed the noise & the generator were created with.
+    seed: u64,
+    /// The state of the generator, advanced for every number.
+    state: u64,
+    /// The shuffled hashes of the lattice points of the noise.
+    permutation: Vec<u8>,
+}
+}
+
+impl Random {
+    /// Create with a seed.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            state: seed,
+            permutation: shuffled_permutation(seed),
+        }
+    }
+
+    /// The seed the noise & the generator were created with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The state of the generator, to continue the same sequence with `restore`.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Continue a sequence from a seed & the state of the generator at a moment.
+    pub fn restore(&mut self, seed: u64, state: u64) {
+        *self = Self::new(seed);
+        self.state = state;
+    }
+
+    /// The next random number, using SplitMix64.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+        z ^ (z >> 31)
+    }
+
+    /// A random number from `0` up to but excluding `1`.
+    pub fn next_f32(&mut self) -> f32 {
+        // The top 24 bits fit exactly in the mantissa
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// A random integer from zero up to but excluding the bound.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            0
+        } else {
+            self.next_u64() % bound
+        }
+    }
+
+    /// Perlin noise on a line, roughly from `-1` to `1`.
+    pub fn perlin_1d(&self, x: f32) -> f32 {
+        let cell = x.floor();
+        let offset = x - cell;
+        let fade = fade(offset);
+
+        let left = gradient_1d(self.hash(cell as i32), offset);
+        let right = gradient_1d(self.hash(cell as i32 + 1), offset - 1.0);
+
+        (left + (right - left) * fade) * 2.0
+    }
+
+    /// Perlin noise on a plane, roughly from `-1` to `1`.
+    pub fn perlin_2d(&self, x: f32, y: f32) -> f32 {
+        let (cell_x, cell_y) = (x.floor(), y.floor());
+        let (offset_x, offset_y) = (x - cell_x, y - cell_y);
+        let (fade_x, fade_y) = (fade(offset_x), fade(offset_y));
+        let (cell_x, cell_y) = (cell_x as i32, cell_y as i32);
+
+        let corner = |dx: i32, dy: i32| {
+            let hash = self.hash(self.hash(cell_x + dx) as i32 + cell_y + dy);
+
+            gradient_2d(hash, offset_x - dx as f32, offset_y - dy as f32)
+        };
+        let bottom = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * fade_x;
+        let top = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * fade_x;
+
+        bottom + (top - bottom) * fade_y
+    }
+
+    /// Simplex noise on a line, roughly from `-1` to `1`.
+    pub fn simplex_1d(&self, x: f32) -> f32 {
+        let cell = x.floor();
+        let offset = x - cell;
+
+        let corner = |index: i32, distance: f32| {
+            let falloff = (1.0 - distance * distance).max(0.0);
+            let falloff = falloff * falloff;
+
+            // The gradients are spread from -8 to 8 except zero
+            let hash = self.hash(index);
+            let gradient = (1 + (hash & 7)) as f32;
+            let gradient = if hash & 8 == 0 { gradient } else { -gradient };
+
+            falloff * falloff * gradient * distance
+        };
+
+        0.395 * (corner(cell as i32, offset) + corner(cell as i32 + 1, offset - 1.0))
+    }
+
+    /// Simplex noise on a plane, roughly from `-1` to `1`.
+    pub fn simplex_2d(&self, x: f32, y: f32) -> f32 {
+        // Skew the plane so the triangles become squares & back
+        let skew = 0.5 * (3.0f32.sqrt() - 1.0);
+        let unskew = (3.0 - 3.0f32.sqrt()) / 6.0;
+
+        let stretch = (x + y) * skew;
+        let (cell_x, cell_y) = ((x + stretch).floor(), (y + stretch).floor());
+        let shrink = (cell_x + cell_y) * unskew;
+        let first = [x - (cell_x - shrink), y - (cell_y - shrink)];
+
+        // Which of the two triangles of the square the point is in
+        let (step_x, step_y) = if first[0] > first[1] { (1, 0) } else { (0, 1) };
+        let second = [
+            first[0] - step_x as f32 + unskew,
+            first[1] - step_y as f32 + unskew,
+        ];
+        let third = [first[0] - 1.0 + 2.0 * unskew, first[1] - 1.0 + 2.0 * unskew];
+
+        let (cell_x, cell_y) = (cell_x as i32, cell_y as i32);
+        let corner = |dx: i32, dy: i32, [x, y]: [f32; 2]| {
+            let falloff = 0.5 - x * x - y * y;
+            if falloff < 0.0 {
+                0.0
+            } else {
+                let hash = self.hash(self.hash(cell_x + dx) as i32 + cell_y + dy);
+                let falloff = falloff * falloff;
+
+                falloff * falloff * gradient_2d(hash, x, y)
+            }
+        };
+
+        70.0 * (corner(0, 0, first) + corner(step_x, step_y, second) + corner(1, 1, third))
+    }
+
+    /// The hash of a lattice coordinate, wrapping around the table.
+    fn hash(&self, coordinate: i32) -> u8 {
+        self.permutation[(coordinate & (PERMUTATION_SIZE as i32 - 1)) as usize]
+    }
+
+    /// Set the seed of the generator & the noise from a script.
+    ///
+    /// ```gamelisp
+    /// (set-seed (hash-level-name level))
+    /// ```
+    fn set_seed(&mut self, seed: i32) {
+        *self = Self::new(seed as u32 as u64);
+    }
+
+    /// A random number from the minimum up to but excluding the maximum from a script.
+    ///
+    /// When both are integers the result is an integer, otherwise it's a float.
+    ///
+    /// ```gamelisp
+    /// (let dice (rand-range 1 7))
+    /// (let angle (rand-range 0.0 6.28))
+    /// ```
+    fn rand_range(&mut self, min: Num, max: Num) -> GResult<Num> {
+        Ok(match (min, max) {
+            (Num::Int(min), Num::Int(max)) => {
+                if max <= min {
+                    bail!("maximum {} must be larger than minimum {}", max, min);
+                }
+
+                let range = (max as i64 - min as i64) as u64;
+                Num::Int((min as i64 + self.below(range) as i64) as i32)
+            }
+            (min, max) => {
+                let (min, max) = (num_to_f32(min), num_to_f32(max));
+                if max < min {
+                    bail!("maximum {} must not be smaller than minimum {}", max, min);
+                }
+
+                Num::Flo(min + (max - min) * self.next_f32())
+            }
+        })
+    }
+
+    /// A random element of an array from a script.
+    ///
+    /// ```gamelisp
+    /// (let enemy (rand-choice (arr 'slime 'bat 'skeleton)))
+    /// ```
+    fn rand_choice(&mut self, arr: &Arr) -> GResult<Val> {
+        if arr.len() == 0 {
+            bail!("can't choose from an empty array");
+        }
+
+        arr.get(self.below(arr.len() as u64) as usize)
+    }
+
+    /// Perlin noise at a position on a line or a plane from a script.
+    ///
+    /// ```gamelisp
+    /// (let wind (perlin-noise (* time 0.5)))
+    /// (let height (perlin-noise (* x 0.05) (* y 0.05)))
+    /// ```
+    fn perlin_noise(&self, x: f32, y: Option<f32>) -> f32 {
+        match y {
+            Some(y) => self.perlin_2d(x, y),
+            None => self.perlin_1d(x),
+        }
+    }
+
+    /// Simplex noise at a position on a line or a plane from a script.
+    ///
+    /// ```gamelisp
+    /// (let density (simplex-noise (* x 0.02) (* y 0.02)))
+    /// ```
+    fn simplex_noise(&self, x: f32, y: Option<f32>) -> f32 {
+        match y {
+            Some(y) => self.simplex_2d(x, y),
+            None => self.simplex_1d(x),
+        }
+    }
+
+    /// Register the generator with a seed from the clock and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Random::new(miniquad::date::now().to_bits()));
+
+            glsp::bind_rfn("set-seed", rfn!(Random::set_seed))?;
+            glsp::bind_rfn("rand-range", rfn!(Random::rand_range))?;
+            glsp::bind_rfn("rand-choice", rfn!(Random::rand_choice))?;
+            glsp::bind_rfn("perlin-noise", rfn!(Random::perlin_noise))?;
+            glsp::bind_rfn("simplex-noise", rfn!(Random::simplex_noise))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// The permutation table of the noise shuffled with a generator from the seed.
+fn shuffled_permutation(seed: u64) -> Vec<u8> {
+    let mut generator = Random {
+        seed,
+        state: seed,
+        permutation: vec![],
+    };
+
+    // Fisher-Yates shuffle
+    let mut permutation: Vec<u8> = (0..PERMUTATION_SIZE).map(|index| index as u8).collect();
+    for index in (1..PERMUTATION_SIZE).rev() {
+        let other = generator.below(index as u64 + 1) as usize;
+        permutation.swap(index, other);
+    }
+
+    permutation
+}
+
+/// The smooth step used to blend between lattice points, with a flat start & end.
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+/// The influence of the gradient at a lattice point on a line.
+fn gradient_1d(hash: u8, distance: f32) -> f32 {
+    if hash & 1 == 0 {
+        distance
+    } else {
+        -distance
+    }
+}
+
+/// The influence of the gradient at a lattice point on a plane.
+fn gradient_2d(hash: u8, x: f32, y: f32) -> f32 {
+    let [gradient_x, gradient_y] = GRADIENTS_2D[(hash & 7) as usize];
+
+    gradient_x * x + gradient_y * y
+}
+
+/// Convert a GameLisp number to a float.
+fn num_to_f32(num: Num) -> f32 {
+    match num {
+        Num::Int(i) => i as f32,
+        Num::Flo(f) => f,
+    }
+}
@@ -1,19 +1,69 @@
-use anyhow::Result;
-use glsp::{bail, lib, rdata, rdata_impls, rfn, GResult, Runtime};
+use crate::{
+    atlas::Atlas,
+    bake,
+    batch::{MergedBuffers, MergedDraw, MeshBatch, MAX_MERGED_VERTICES},
+    color::Color,
+    effects::ScreenOverlay,
+    frame_graph::{FrameGraph, PassKind, Target},
+    hierarchy::Hierarchy,
+    layer::{OpacityGroup, OpacityLayer},
+    lighting::{LightMap, UNLIT_DRAW_ORDER},
+    path,
+    postprocess::{self, PostPassDescriptor, PostProcessing},
+    uniforms::UniformBlock,
+};
+#[cfg(feature = "svg")]
+use crate::{clip, gradient::Gradient};
+use anyhow::{anyhow, Result};
+use glsp::{bail, lib, rdata, rdata_impls, rfn, GResult, Root, Runtime, Sym, Tab, Val};
 use lyon::{
-    math::Point,
-    path::PathEvent,
+    math::{point, Point},
+    path::{Path, PathEvent},
     tessellation::{
-        geometry_builder::{FillVertexConstructor, StrokeVertexConstructor},
+        geometry_builder::{
+            BasicVertexConstructor, FillVertexConstructor, StrokeVertexConstructor,
+        },
         BuffersBuilder, FillAttributes, FillOptions, FillTessellator, StrokeAttributes,
-        VertexBuffers,
+        StrokeOptions, StrokeTessellator, VertexBuffers,
     },
 };
 use miniquad::{graphics::*, Context};
-use std::mem;
-use usvg::Color;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::{collections::HashMap, mem, ops::Add};
+#[cfg(feature = "svg")]
+use usvg::{Node, NodeExt, NodeKind, Paint, Tree};
 
-const MAX_MESH_INSTANCES: usize = 1024 * 1024;
+pub(crate) use self::geom_shader::PaletteSwap;
+
+/// How many instances of a mesh share a single instance buffer.
+///
+/// Only the chunks containing changed instances are uploaded again.
+const INSTANCE_CHUNK_SIZE: usize = 4096;
+/// How many instances fit in the instance buffer of a mesh before it has to grow.
+const DEFAULT_INSTANCE_CAPACITY: usize = 16;
+/// The uniforms that are always available in custom mesh shaders.
+const SHADER_BUILTIN_UNIFORMS: &[(&str, UniformType)] = &[
+    ("u_zoom", UniformType::Float2),
+    ("u_pan", UniformType::Float2),
+];
+/// Maximum amount of vertices that can be drawn with the debug functions each frame.
+///
+/// Every vertex can be referenced by the 16-bit indices.
+pub(crate) const MAX_DEBUG_VERTICES: usize = u16::MAX as usize + 1;
+/// Maximum amount of indices that can be drawn with the debug functions each frame.
+pub(crate) const MAX_DEBUG_INDICES: usize = MAX_DEBUG_VERTICES * 3;
+/// In how many blocks the loading bar is divided.
+const LOADING_BAR_SEGMENTS: usize = 20;
+/// The size of a single block of the loading bar.
+const LOADING_BAR_SEGMENT_SIZE: f32 = 24.0;
+/// How much coarser every level of detail of a SVG is than the previous one.
+#[cfg(feature = "svg")]
+const LOD_TOLERANCE_FACTOR: f32 = 4.0;
+/// Why SVGs can't be loaded when they can't be parsed.
+#[cfg(not(feature = "svg"))]
+const SVG_DISABLED: &str =
+    "SVGs can't be loaded without the `svg` feature, bake them to meshes with `clogs-bake`";
 
 rdata! {
 /// A reference to an uploaded vector path.
@@ -21,64 +71,422 @@ rdata! {
 pub struct Mesh(usize);
 }
 
+rdata! {
+/// A reference to an instance of a mesh that's being rendered.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct InstanceRef {
+    /// The mesh the instance belongs to.
+    mesh: usize,
+    /// The unique identifier of the instance within the mesh.
+    id: u32,
+}
+
+meths {
+    get "x": InstanceRef::x,
+    set "x": InstanceRef::set_x,
+    get "y": InstanceRef::y,
+    set "y": InstanceRef::set_y,
+    get "z": InstanceRef::z,
+    set "z": InstanceRef::set_z,
+    get "rotation": InstanceRef::rotation,
+    set "rotation": InstanceRef::set_rotation,
+    get "scale": InstanceRef::scale,
+    set "scale": InstanceRef::set_scale,
+    get "scale-x": InstanceRef::scale_x,
+    set "scale-x": InstanceRef::set_scale_x,
+    get "scale-y": InstanceRef::scale_y,
+    set "scale-y": InstanceRef::set_scale_y,
+    get "skew-x": InstanceRef::skew_x,
+    set "skew-x": InstanceRef::set_skew_x,
+    get "skew-y": InstanceRef::skew_y,
+    set "skew-y": InstanceRef::set_skew_y,
+    "set-scale-x": InstanceRef::set_scale_x,
+    "set-scale-y": InstanceRef::set_scale_y,
+    get "draw-order": InstanceRef::draw_order,
+    set "draw-order": InstanceRef::set_draw_order,
+    "set-color-multiplier": InstanceRef::set_color_multiplier,
+    "set-panel-size": InstanceRef::set_panel_size,
+}
+}
+
+rdata! {
+/// A reference to a part of the screen the world is drawn to a second time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Viewport(usize);
+}
+
+/// A part of the screen the world is drawn to a second time with its own camera.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ViewportCamera {
+    /// The rectangle in pixels from the top left of the screen as `[x, y, width, height]`.
+    rect: [f32; 4],
+    camera_pan: (f32, f32),
+    camera_zoom: f32,
+    /// The lowest & highest draw order of the meshes drawn, all are drawn if `None`.
+    draw_orders: Option<(i32, i32)>,
+    /// The slice of the screen as `(index, count, side_by_side)` when splitting the screen, the
+    /// rectangle follows the size of the screen then.
+    split: Option<(usize, usize, bool)>,
+}
+
+impl ViewportCamera {
+    /// The rectangle in pixels from the top left of the screen as `[x, y, width, height]`.
+    fn screen_rect(&self, (width, height): (f32, f32)) -> [f32; 4] {
+        match self.split {
+            Some((index, count, true)) => {
+                let slice = (width / count as f32).floor();
+
+                [slice * index as f32, 0.0, slice, height]
+            }
+            Some((index, count, false)) => {
+                let slice = (height / count as f32).floor();
+
+                [0.0, slice * index as f32, width, slice]
+            }
+            None => self.rect,
+        }
+    }
+}
+
+/// How the meshes are seen while drawing them to a part of the render pass.
+#[derive(Debug, Copy, Clone)]
+struct View {
+    /// The part of the render pass in pixels from the bottom left as `[x, y, width, height]`.
+    rect: [i32; 4],
+    pan: (f32, f32),
+    zoom: f32,
+    /// The lowest & highest draw order of the meshes drawn, all are drawn if `None`.
+    draw_orders: Option<(i32, i32)>,
+    /// Whether it's the view of the main camera, the only one that's clipped.
+    main: bool,
+    /// The render pass the view is drawn to, `None` for the screen.
+    pass: Option<RenderPass>,
+}
+
+/// A rectangle as `[x, y, width, height]` that meshes are clipped to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ClipRect {
+    /// In pixels on the screen, from the top left.
+    Screen([f32; 4]),
+    /// In world units at the default Z position, moving along with the camera.
+    World([f32; 4]),
+}
+
+/// What would have been rendered during a frame when running headless.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedFrame {
+    /// The amount of instances for each mesh that has instances.
+    pub instance_counts: Vec<(Mesh, usize)>,
+    /// The position of the camera.
+    pub camera_pan: (f32, f32),
+    /// The zoom of the camera.
+    pub camera_zoom: f32,
+    /// The amount of vertices drawn with the debug functions.
+    pub debug_vertex_count: usize,
+}
+
+/// The GPU memory used by the buffers of meshes, in bytes.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct MeshMemory {
+    /// The size of the vertex buffers.
+    pub vertex_bytes: usize,
+    /// The size of the index buffers.
+    pub index_bytes: usize,
+    /// The size of the instance buffers, including the capacity that isn't used yet.
+    pub instance_bytes: usize,
+}
+
+impl MeshMemory {
+    /// The size of all buffers together.
+    pub fn total(&self) -> usize {
+        self.vertex_bytes + self.index_bytes + self.instance_bytes
+    }
+
+    /// Convert to a table for scripts.
+    fn to_table(self) -> GResult<Root<Tab>> {
+        let tab = glsp::tab();
+        tab.set(glsp::sym("vertex")?, self.vertex_bytes as i32)?;
+        tab.set(glsp::sym("index")?, self.index_bytes as i32)?;
+        tab.set(glsp::sym("instance")?, self.instance_bytes as i32)?;
+        tab.set(glsp::sym("total")?, self.total() as i32)?;
+
+        Ok(tab)
+    }
+}
+
+impl Add for MeshMemory {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            vertex_bytes: self.vertex_bytes + other.vertex_bytes,
+            index_bytes: self.index_bytes + other.index_bytes,
+            instance_bytes: self.instance_bytes + other.instance_bytes,
+        }
+    }
+}
+
+/// Where a ray first hits an instance.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RaycastHit {
+    /// The instance that's hit.
+    pub instance: InstanceRef,
+    /// The position of the hit in the world.
+    pub point: (f32, f32),
+    /// The direction perpendicular to the hit edge of the instance, facing the ray.
+    pub normal: (f32, f32),
+    /// The distance between the start of the ray and the hit.
+    pub distance: f32,
+}
+
 lib! {
 /// A wrapper around the OpenGL calls so the main file won't be polluted.
 pub struct Render {
-    /// The OpenGL pipeline for the pass rendering to the render target.
-    pipeline: Pipeline,
+    /// The OpenGL pipeline for the pass rendering to the render target, `None` when headless.
+    pipeline: Option<Pipeline>,
+    /// The OpenGL pipeline for meshes textured with an atlas page, `None` when headless.
+    sprite_pipeline: Option<Pipeline>,
+    /// The OpenGL pipeline writing the shapes of masks to the stencil buffer, `None` when headless.
+    mask_pipeline: Option<Pipeline>,
+    /// The pipelines for meshes & textured meshes merged into a single draw call, with the
+    /// instance stored with every vertex, `None` when headless.
+    merged_pipelines: Option<(Pipeline, Pipeline)>,
+    /// The geometry of the meshes that are merged into the next draw call.
+    merged_draw: MergedDraw,
+    /// The buffers the merged draw calls are streamed to.
+    merged_buffers: MergedBuffers,
     /// A list of draw calls with bindings that will be generated.
     draw_calls: Vec<DrawCall>,
+    /// The shared vertex & index buffers the meshes are packed in.
+    batches: Vec<MeshBatch>,
+    /// The textures the images of textured meshes are packed in.
+    atlas: Atlas,
     /// Whether some draw calls are missing bindings.
     missing_bindings: bool,
+    /// The block mesh used to render the loading bar.
+    loading_bar: Mesh,
+    /// A mesh without geometry for instances that are only used as a parent.
+    transform_node: Mesh,
+    /// The parent relationships between instances.
+    hierarchy: Hierarchy,
+    /// Geometry drawn on top of everything for a single frame.
+    debug_geometry: VertexBuffers<Vertex, u16>,
+    /// The dynamic buffers for the debug geometry, created when first used.
+    debug_bindings: Option<Bindings>,
+    /// The size of the screen during the last render.
+    screen_size: (f32, f32),
+    /// The size frames are rendered at when they're rendered offscreen instead of the window.
+    offscreen_size: Option<(f32, f32)>,
+    /// How many draw calls were issued during the last render.
+    draw_call_count: usize,
+    /// Whether instances outside of the screen are skipped when drawing.
+    culling: bool,
+    /// How many instances were skipped during the last render.
+    culled_count: usize,
+    /// The fullscreen passes applied after rendering the scene.
+    post_processing: PostProcessing,
+    /// The passes drawing a frame in the order they run.
+    frame_graph: FrameGraph,
+    /// The lights multiplied with the scene.
+    light_map: LightMap,
+    /// The screen effects drawn on top of everything.
+    overlay: ScreenOverlay,
+    /// The layer meshes with an opacity group are drawn to before they're blended with the scene.
+    opacity_layer: OpacityLayer,
+    /// Shaders that can be used instead of the default one for specific meshes.
+    shaders: Vec<CustomShader>,
+    /// Whether instances are ordered by their Z position with a depth buffer.
+    depth_buffer: bool,
+    /// The colors replaced when drawing the meshes & the tint they're multiplied with.
+    palette_swap: PaletteSwap,
+    /// Whether the meshes are blended in linear space & converted to sRGB afterwards.
+    gamma_correct: bool,
+    /// The indices of the draw calls in the order they are submitted.
+    draw_queue: Vec<usize>,
+    /// Whether the draw queue must be sorted again.
+    draw_order_changed: bool,
+    /// The texture with its depth texture & pass that frames are captured to, created when used.
+    capture_target: Option<(Texture, Texture, RenderPass)>,
+    /// The clip rectangles applied to meshes uploaded while they're pushed.
+    clip_stack: Vec<ClipRect>,
+    /// The parts of the screen drawn over the scene with their own cameras, `None` if removed.
+    viewports: Vec<Option<ViewportCamera>>,
 
     camera_pan: (f32, f32),
     camera_zoom: f32,
+    /// Added to the camera position when drawing.
+    camera_offset: (f32, f32),
+    /// The area in world units that's always visible at zoom `1.0`, the zoom is relative to the
+    /// pixels when `None`.
+    design_resolution: Option<(f32, f32)>,
+    /// How many physical pixels of the screen a pixel of the API is.
+    dpi_scale: f32,
 }
 }
 
+impl Mesh {
+    /// The index of the mesh, unique for each uploaded mesh.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
 impl Render {
     /// Setup the OpenGL pipeline and the texture for the framebuffer.
-    pub fn new(ctx: &mut Context) -> Self {
+    ///
+    /// Without a depth buffer everything is drawn in the draw order. With vertex anti-aliasing
+    /// the meshes are blended so the transparent fringes around their edges are smooth. With
+    /// gamma correction the meshes are blended in linear space & converted back to sRGB by a
+    /// post-processing pass.
+    pub fn new(
+        ctx: &mut Context,
+        depth_buffer: bool,
+        vertex_antialiasing: bool,
+        gamma_correct: bool,
+    ) -> Self {
+        let defines: &[&str] = if gamma_correct { &["LINEAR"] } else { &[] };
+
         // Create an OpenGL pipeline for rendering to the render target
         let shader = Shader::new(
             ctx,
             geom_shader::VERTEX,
-            geom_shader::FRAGMENT,
+            &with_defines(geom_shader::FRAGMENT, defines),
             geom_shader::META,
         )
         .expect("Building offscreen shader failed");
-        let pipeline = Pipeline::with_params(
+        let params = PipelineParams {
+            color_blend: if vertex_antialiasing {
+                Some(alpha_blend())
+            } else {
+                None
+            },
+            ..mesh_pipeline_params(depth_buffer)
+        };
+        let pipeline = instanced_pipeline(ctx, shader, params);
+        let merged_mesh_pipeline = merged_pipeline(ctx, shader, params);
+
+        let sprite_shader = Shader::new(
             ctx,
-            &[
-                BufferLayout::default(),
-                BufferLayout {
-                    step_func: VertexStep::PerInstance,
-                    ..Default::default()
-                },
-            ],
-            &[
-                VertexAttribute::with_buffer("a_pos", VertexFormat::Float2, 0),
-                VertexAttribute::with_buffer("a_color", VertexFormat::Float4, 0),
-                VertexAttribute::with_buffer("a_inst_pos", VertexFormat::Float3, 1),
-                VertexAttribute::with_buffer("a_inst_rot", VertexFormat::Float1, 1),
-                VertexAttribute::with_buffer("a_inst_scale", VertexFormat::Float1, 1),
-                VertexAttribute::with_buffer("a_inst_color", VertexFormat::Float4, 1),
-            ],
-            shader,
+            sprite_shader::VERTEX,
+            &with_defines(sprite_shader::FRAGMENT, defines),
+            sprite_shader::META,
+        )
+        .expect("Building sprite shader failed");
+        // Textures have transparent pixels around their shapes
+        let sprite_params = PipelineParams {
+            color_blend: Some(alpha_blend()),
+            ..mesh_pipeline_params(depth_buffer)
+        };
+        let sprite_pipeline = instanced_pipeline(ctx, sprite_shader, sprite_params);
+        let merged_sprite_pipeline = merged_pipeline(ctx, sprite_shader, sprite_params);
+
+        let mask_shader = Shader::new(
+            ctx,
+            geom_shader::VERTEX,
+            geom_shader::FRAGMENT,
+            geom_shader::META,
+        )
+        .expect("Building mask shader failed");
+        // Masks only mark the stencil buffer, they are never visible themselves
+        let mask_pipeline = instanced_pipeline(
+            ctx,
+            mask_shader,
             PipelineParams {
-                depth_test: Comparison::LessOrEqual,
-                depth_write: true,
+                color_write: (false, false, false, false),
+                stencil_test: Some(stencil_state(CompareFunc::Always, StencilOp::Replace)),
                 ..Default::default()
             },
         );
 
-        Self {
+        let mut render = Self::with_pipeline(Some(pipeline), ctx.screen_size(), depth_buffer);
+        render.dpi_scale = ctx.dpi_scale();
+        render.sprite_pipeline = Some(sprite_pipeline);
+        render.mask_pipeline = Some(mask_pipeline);
+        render.merged_pipelines = Some((merged_mesh_pipeline, merged_sprite_pipeline));
+        render.opacity_layer = OpacityLayer::with_pipelines(ctx, defines, depth_buffer);
+
+        if gamma_correct {
+            render.gamma_correct = true;
+            render.overlay.set_linear(true);
+            render.light_map.set_linear(true);
+            // Added first so the custom passes receive sRGB colors
+            render
+                .post_processing
+                .add_pass(ctx, &PostPassDescriptor::gamma())
+                .expect("Building gamma correction shader failed");
+        }
+
+        render
+    }
+
+    /// Setup without a window or OpenGL context, nothing is drawn but the frames are recorded.
+    pub fn headless(screen_size: (f32, f32), depth_buffer: bool) -> Self {
+        Self::with_pipeline(None, screen_size, depth_buffer)
+    }
+
+    /// Setup with the pipeline for rendering meshes if there's an OpenGL context.
+    fn with_pipeline(
+        pipeline: Option<Pipeline>,
+        screen_size: (f32, f32),
+        depth_buffer: bool,
+    ) -> Self {
+        let mut render = Self {
             pipeline,
+            sprite_pipeline: None,
+            mask_pipeline: None,
+            merged_pipelines: None,
+            merged_draw: MergedDraw::new(),
+            merged_buffers: MergedBuffers::new(),
             draw_calls: vec![],
+            batches: vec![],
+            atlas: Atlas::new(),
             missing_bindings: false,
+            loading_bar: Mesh(0),
+            transform_node: Mesh(0),
+            hierarchy: Hierarchy::new(),
+            debug_geometry: VertexBuffers::new(),
+            debug_bindings: None,
+            screen_size,
+            offscreen_size: None,
+            draw_call_count: 0,
+            culling: true,
+            culled_count: 0,
+            post_processing: PostProcessing::new(),
+            frame_graph: FrameGraph::new(),
+            light_map: LightMap::new(),
+            overlay: ScreenOverlay::new(),
+            opacity_layer: OpacityLayer::new(),
+            shaders: vec![],
+            depth_buffer,
+            palette_swap: PaletteSwap::default(),
+            gamma_correct: false,
+            draw_queue: vec![],
+            draw_order_changed: false,
+            capture_target: None,
+            clip_stack: vec![],
+            viewports: vec![],
             camera_pan: (0.0, 0.0),
             camera_zoom: 1.0,
-        }
+            camera_offset: (0.0, 0.0),
+            design_resolution: None,
+            dpi_scale: 1.0,
+        };
+
+        // Upload a square for the blocks of the loading bar
+        let half_size = LOADING_BAR_SEGMENT_SIZE * 0.4;
+        let mut builder = Path::builder();
+        builder.move_to(point(-half_size, -half_size));
+        builder.line_to(point(half_size, -half_size));
+        builder.line_to(point(half_size, half_size));
+        builder.line_to(point(-half_size, half_size));
+        builder.close();
+        render.loading_bar =
+            render.upload_path(builder.build().iter(), Color::new(255, 255, 255), 1.0);
+
+        // Transform nodes are never drawn
+        render.transform_node = render.add_draw_call(vec![], vec![]);
+
+        render
     }
 
     /// Upload a lyon path.
@@ -88,177 +496,3905 @@ impl Render {
     where
         P: IntoIterator<Item = PathEvent>,
     {
+        let events: Vec<PathEvent> = path.into_iter().collect();
+
         // Tessalate the path, converting it to vertices & indices
         let mut geometry: VertexBuffers<Vertex, u16> = VertexBuffers::new();
         let mut tessellator = FillTessellator::new();
         {
             tessellator
                 .tessellate(
-                    path,
+                    events.iter().copied(),
                     &FillOptions::default(),
                     &mut BuffersBuilder::new(&mut geometry, VertexCtor::new(color, opacity)),
                 )
                 .unwrap();
         }
-        let vertices = geometry.vertices.clone();
-        let indices = geometry.indices;
+        let mesh = self.add_draw_call(geometry.vertices, geometry.indices);
+        self.set_mesh_path(mesh, path::outline(events));
 
-        // Create an OpenGL draw call for the path
-        let draw_call = DrawCall {
-            vertices,
-            indices,
-            bindings: None,
-            instances: vec![],
-            refresh_instances: false,
-        };
-        self.draw_calls.push(draw_call);
+        mesh
+    }
 
-        // Tell the next render loop to create bindings for this
-        self.missing_bindings = true;
+    /// Upload the outline of a lyon path.
+    ///
+    /// Returns a reference that can be used to add instances.
+    pub fn upload_stroked_path<P>(
+        &mut self,
+        path: P,
+        color: Color,
+        opacity: f32,
+        options: &StrokeOptions,
+    ) -> Result<Mesh>
+    where
+        P: IntoIterator<Item = PathEvent>,
+    {
+        let events: Vec<PathEvent> = path.into_iter().collect();
 
-        // Return the draw call in a newtype struct so it can be used as a reference
-        Mesh(self.draw_calls.len() - 1)
+        let mut geometry: VertexBuffers<Vertex, u16> = VertexBuffers::new();
+        StrokeTessellator::new()
+            .tessellate(
+                events.iter().copied(),
+                options,
+                &mut BuffersBuilder::new(&mut geometry, VertexCtor::new(color, opacity)),
+            )
+            .map_err(|err| anyhow!("tessellating stroke failed: {:?}", err))?;
+
+        let mesh = self.upload_buffers(&geometry)?;
+        self.set_mesh_path(mesh, path::outline(events));
+
+        Ok(mesh)
+    }
+
+    /// Set the outline a mesh was created from, so instances can follow it.
+    pub fn set_mesh_path(&mut self, mesh: Mesh, path: Vec<[f32; 2]>) {
+        self.draw_calls[mesh.0].path = path;
+    }
+
+    /// The outline a mesh was created from, empty when it's unknown.
+    pub fn mesh_path(&self, mesh: Mesh) -> &[[f32; 2]] {
+        &self.draw_calls[mesh.0].path
     }
 
     /// Upload lyon geometry.
     ///
     /// Returns a reference that can be used to add instances.
     pub fn upload_buffers(&mut self, geometry: &VertexBuffers<Vertex, u16>) -> Result<Mesh> {
-        let vertices = geometry.vertices.clone();
-        let indices = geometry.indices.clone();
+        Ok(self.add_draw_call(geometry.vertices.clone(), geometry.indices.clone()))
+    }
 
-        // Create an OpenGL draw call for the path
-        let draw_call = DrawCall {
-            vertices,
-            indices,
-            bindings: None,
-            instances: vec![],
-            refresh_instances: false,
+    /// Replace the geometry of an uploaded mesh, the mesh & its instances stay valid.
+    ///
+    /// The mesh gets its own vertex & index buffers that are updated in place afterwards, so
+    /// it can be changed every frame for morphing shapes or deformable terrain.
+    pub fn update_mesh(&mut self, mesh: Mesh, geometry: &VertexBuffers<Vertex, u16>) -> Result<()> {
+        profile_scope!("update_mesh");
+
+        if geometry.vertices.len() > u16::MAX as usize {
+            return Err(anyhow!(
+                "mesh has {} vertices, more than the maximum of {}",
+                geometry.vertices.len(),
+                u16::MAX
+            ));
+        }
+
+        let draw_call = self
+            .draw_calls
+            .get_mut(mesh.0)
+            .ok_or_else(|| anyhow!("mesh doesn't exist"))?;
+        if draw_call.unloaded {
+            return Err(anyhow!("mesh is unloaded"));
+        }
+        draw_call.set_geometry(geometry.vertices.clone(), geometry.indices.clone());
+
+        // Tell the next render loop to upload the geometry
+        self.missing_bindings = true;
+
+        Ok(())
+    }
+
+    /// Pack the geometry of a mesh in a batch and create a draw call for it.
+    fn add_draw_call(&mut self, vertices: Vec<Vertex>, indices: Vec<u16>) -> Mesh {
+        // Start a new batch when the last one is full or already uploaded
+        let batch = match self.batches.last() {
+            Some(batch) if batch.fits(vertices.len()) => self.batches.len() - 1,
+            _ => {
+                self.batches.push(MeshBatch::new());
+
+                self.batches.len() - 1
+            }
         };
+        let first_index = self.batches[batch].push(&vertices, &indices);
+
+        // Create an OpenGL draw call for the path, clipped by the pushed rectangles
+        let mut draw_call = DrawCall::new(vertices, indices, batch, first_index);
+        draw_call.clips = self.clip_stack.clone();
         self.draw_calls.push(draw_call);
 
         // Tell the next render loop to create bindings for this
         self.missing_bindings = true;
+        self.draw_order_changed = true;
 
         // Return the draw call in a newtype struct so it can be used as a reference
-        Ok(Mesh(self.draw_calls.len() - 1))
+        Mesh(self.draw_calls.len() - 1)
     }
 
-    /// Render the graphics.
-    pub fn render(&mut self, ctx: &mut Context) {
-        let (width, height) = ctx.screen_size();
+    /// Tessellate a SVG and upload all filled paths as a single mesh.
+    ///
+    /// What can't be drawn is logged as a warning.
+    ///
+    /// Paths are filled with a solid color or a linear or radial gradient. Returns a reference that can be used to add instances.
+    ///
+    /// Parts of paths outside of the `clipPath` & `mask` elements applied to them are cut away,
+    /// masks only use the shapes of their content.
+    pub fn upload_svg(&mut self, svg: &[u8]) -> Result<Mesh> {
+        let svg = Self::tessellate_svg(svg)?;
+        for warning in svg.warnings() {
+            log::warn!("SVG {}", warning);
+        }
 
-        // Create bindings & update the instance vertices if necessary
-        if self.missing_bindings {
-            self.draw_calls.iter_mut().for_each(|dc| {
-                // Create bindings if missing
-                if dc.bindings.is_none() {
-                    dc.create_bindings(ctx);
-                }
-            });
+        self.upload_svg_geometry(svg)
+    }
 
-            self.missing_bindings = false;
+    /// Upload a SVG that's already tessellated as a single mesh.
+    pub fn upload_svg_geometry(&mut self, svg: SvgGeometry) -> Result<Mesh> {
+        let mesh = self.upload_buffers(&svg.geometry)?;
+        // Instances can follow the first filled path
+        self.set_mesh_path(mesh, svg.outline);
+
+        Ok(mesh)
+    }
+
+    /// Upload the levels of detail of a tessellated SVG, from the most detailed to the coarsest.
+    ///
+    /// Instances are added to the most detailed mesh, which is returned. When the camera zooms
+    /// out far enough that a coarser level looks the same as the detailed one at zoom `1.0`,
+    /// the coarser geometry is drawn instead.
+    pub fn upload_svg_lods(&mut self, levels: Vec<SvgGeometry>) -> Result<Mesh> {
+        let mut levels = levels.into_iter();
+        let detailed = levels
+            .next()
+            .ok_or_else(|| anyhow!("SVG doesn't have any levels of detail"))?;
+        let tolerance = detailed.tolerance;
+        let mesh = self.upload_svg_geometry(detailed)?;
+
+        for level in levels {
+            // The error on the screen is the tolerance multiplied with the zoom
+            let max_zoom = tolerance / level.tolerance;
+            let lod = self.upload_buffers(&level.geometry)?;
+            self.draw_calls[mesh.0].lods.push((max_zoom, lod.0));
         }
 
-        // Render the pass to the render target
-        ctx.begin_default_pass(PassAction::clear_color(0.4, 0.7, 1.0, 1.0));
+        Ok(mesh)
+    }
 
-        // Render the separate draw calls
-        for dc in self.draw_calls.iter_mut() {
-            // Only render when we actually have instances
-            if dc.instances.is_empty() {
-                continue;
-            }
+    /// Upload a mesh with coarser levels of detail, each paired with the highest zoom of the
+    /// main camera it's drawn at instead of the detailed mesh.
+    ///
+    /// The levels must go from the most detailed to the coarsest, instances are added to the
+    /// returned detailed mesh.
+    pub fn upload_buffers_lods(
+        &mut self,
+        detailed: &VertexBuffers<Vertex, u16>,
+        levels: &[(f32, VertexBuffers<Vertex, u16>)],
+    ) -> Result<Mesh> {
+        let mesh = self.upload_buffers(detailed)?;
+        for (max_zoom, geometry) in levels {
+            let lod = self.upload_buffers(geometry)?;
+            self.draw_calls[mesh.0].lods.push((*max_zoom, lod.0));
+        }
 
-            let bindings = dc.bindings.as_ref().unwrap();
-            if dc.refresh_instances {
-                // Upload the instance positions
-                bindings.vertex_buffers[1].update(ctx, &dc.instances);
+        Ok(mesh)
+    }
 
-                dc.refresh_instances = false;
-            }
+    /// Write a mesh & its coarser levels of detail in the versioned `.clogmesh` format.
+    ///
+    /// The result can be cached on disk, loaded with `import_mesh` or as an asset, or read by
+    /// other tools. Textured meshes can't be exported because their pixels are in a shared atlas.
+    ///
+    /// All numbers are little-endian, `u32` unless noted otherwise:
+    ///
+    /// | Field | Contents |
+    /// |-------|----------|
+    /// | magic | the 8 bytes `CLOGMESH` |
+    /// | version | currently `1`, bumped when the layout changes; readers accept every version up to their own |
+    /// | level count | at least one, the most detailed level first |
+    /// | levels | every level after each other |
+    ///
+    /// Every level is:
+    ///
+    /// | Field | Contents |
+    /// |-------|----------|
+    /// | tolerance | `f32`, a coarser level is drawn when the camera zoom is at most the tolerance of the first level divided by its own |
+    /// | vertex count | |
+    /// | vertices | 12 `f32`s each: the position `x y`, the color `r g b a` from 0 to 1, the nine-slice weights `x y`, the atlas position `u v` & the anti-aliasing fringe direction `x y` |
+    /// | index count | a multiple of 3 |
+    /// | indices | `u16`s, every 3 indices form a triangle |
+    /// | outline point count | zero when the outline is unknown |
+    /// | outline | 2 `f32`s each, the points of the outline instances can follow |
+    pub fn export_mesh(&self, mesh: Mesh) -> Result<Vec<u8>> {
+        let draw_call = &self.draw_calls[mesh.0];
+        if draw_call.atlas_page.is_some() {
+            return Err(anyhow!("textured meshes can't be exported"));
+        }
+
+        let level = |draw_call: &DrawCall, tolerance| {
+            SvgGeometry::new(
+                VertexBuffers {
+                    vertices: draw_call.vertices.clone(),
+                    indices: draw_call.indices.clone(),
+                },
+                draw_call.path.clone(),
+                tolerance,
+            )
+        };
 
-            ctx.apply_pipeline(&self.pipeline);
-            ctx.apply_scissor_rect(0, 0, width as i32, height as i32);
-            ctx.apply_bindings(bindings);
-            ctx.apply_uniforms(&geom_shader::Uniforms {
-                zoom: (self.camera_zoom / width, self.camera_zoom / height),
-                pan: (self.camera_pan.0, self.camera_pan.1),
-            });
-            ctx.draw(0, dc.indices.len() as i32, dc.instances.len() as i32);
+        // Only the ratios between the tolerances matter, they become the zoom of the levels again
+        let mut levels = vec![level(draw_call, 1.0)];
+        for (max_zoom, lod) in draw_call.lods.iter() {
+            levels.push(level(&self.draw_calls[*lod], 1.0 / max_zoom));
         }
 
-        ctx.end_render_pass();
+        Ok(bake::encode(&levels))
+    }
 
-        ctx.commit_frame();
+    /// Upload a mesh in the `.clogmesh` format written by `export_mesh` or `bake_svg`.
+    ///
+    /// Returns a reference that can be used to add instances.
+    pub fn import_mesh(&mut self, bytes: &[u8]) -> Result<Mesh> {
+        self.upload_svg_lods(bake::decode(bytes)?)
     }
 
-    /// Set the camera panning position.
-    pub fn set_camera_pos(&mut self, x: f32, y: f32) {
-        self.camera_pan.0 = x;
-        self.camera_pan.1 = y;
+    /// Upload the RGBA pixels of an image as a textured rectangle centered around the origin.
+    ///
+    /// The image is packed in a shared atlas page so textured meshes rarely need another texture
+    /// bound. Every pixel is a single world unit. Returns a reference that can be used to add
+    /// instances.
+    pub fn upload_image(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<Mesh> {
+        let (half_width, half_height) = (width as f32 / 2.0, height as f32 / 2.0);
+
+        self.upload_image_at(
+            width,
+            height,
+            pixels,
+            [-half_width, -half_height, half_width, half_height],
+        )
     }
 
-    /// Set the camera zooming.
-    pub fn set_camera_zoom(&mut self, zoom: f32) {
-        self.camera_zoom = zoom;
+    /// Upload the RGBA pixels of an image as a textured rectangle covering the bounds
+    /// `[min_x, min_y, max_x, max_y]`.
+    pub fn upload_image_at(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        bounds: [f32; 4],
+    ) -> Result<Mesh> {
+        let region = self.atlas.pack(width, height, pixels)?;
+        let [min_u, min_v, max_u, max_v] = region.uv;
+
+        let [min_x, min_y, max_x, max_y] = bounds;
+        let vertices = vec![
+            Vertex::textured([min_x, min_y], [min_u, min_v]),
+            Vertex::textured([max_x, min_y], [max_u, min_v]),
+            Vertex::textured([max_x, max_y], [max_u, max_v]),
+            Vertex::textured([min_x, max_y], [min_u, max_v]),
+        ];
+        let outline = vertices.iter().map(|vertex| vertex.pos).collect();
+
+        let mesh = self.add_draw_call(vertices, vec![0, 1, 2, 0, 2, 3]);
+        self.draw_calls[mesh.0].atlas_page = Some(region.page);
+        self.set_mesh_path(mesh, outline);
+
+        Ok(mesh)
     }
 
-    /// Bind the GameLisp functions.
-    pub fn bind_functions(runtime: &Runtime) {
-        runtime.run(|| {
-            glsp::bind_rfn("set_camera_pos", rfn!(Self::set_camera_pos))?;
-            glsp::bind_rfn("set_camera_zoom", rfn!(Self::set_camera_zoom))?;
+    /// How many atlas pages the images of textured meshes are packed in.
+    pub fn atlas_page_count(&self) -> usize {
+        self.atlas.page_count()
+    }
 
-            Ok(())
-        });
+    /// The fraction of the atlas pages covered by images, between `0.0` and `1.0`.
+    pub fn atlas_utilization(&self) -> f32 {
+        self.atlas.utilization()
     }
-}
 
-/// A single uploaded mesh as a draw call.
-#[derive(Debug)]
-struct DrawCall {
-    /// Render vertices, build by lyon path.
-    vertices: Vec<Vertex>,
-    /// Render indices, build by lyon path.
-    indices: Vec<u16>,
-    /// Render bindings, generated on render loop if empty.
-    bindings: Option<Bindings>,
-    /// List of instances to render.
-    instances: Vec<Instance>,
-    /// Whether the instance information should be reuploaded to the GPU.
-    refresh_instances: bool,
-}
+    /// Tessellate all filled paths of a SVG without uploading them.
+    ///
+    /// Doesn't need the GPU, so it can be done on another thread. Text is dropped because no
+    /// fonts are passed.
+    pub fn tessellate_svg(svg: &[u8]) -> Result<SvgGeometry> {
+        let mut levels =
+            Self::tessellate_svg_lods(svg, FillOptions::DEFAULT_TOLERANCE, 0, false, &[])?;
 
-impl DrawCall {
-    /// Create bindings if they are missing.
-    fn create_bindings(&mut self, ctx: &mut Context) {
-        // The vertex buffer of the vector paths
-        let vertex_buffer = Buffer::immutable(ctx, BufferType::VertexBuffer, &self.vertices);
-        // The index buffer of the vector paths
-        let index_buffer = Buffer::immutable(ctx, BufferType::IndexBuffer, &self.indices);
-
-        // A dynamic buffer that will contain all positions for all instances
-        let instance_positions = Buffer::stream(
-            ctx,
-            BufferType::VertexBuffer,
-            MAX_MESH_INSTANCES * mem::size_of::<Instance>(),
-        );
+        Ok(levels.remove(0))
+    }
 
-        let bindings = Bindings {
-            vertex_buffers: vec![vertex_buffer, instance_positions],
-            index_buffer,
-            images: vec![],
+    /// Tessellate all filled paths of a SVG at multiple levels of detail without uploading them.
+    ///
+    /// The tolerance is the maximum distance between the curves and the triangles in SVG units,
+    /// every extra level is coarser than the previous one. Returns the most detailed level first.
+    /// With anti-aliasing the edges get a transparent fringe, so they are smooth without MSAA.
+    /// Text is converted to paths with the TTF or OTF fonts passed, text in fonts that aren't
+    /// passed is dropped. What can't be drawn is reported by the warnings of the geometry.
+    #[cfg(feature = "svg")]
+    pub fn tessellate_svg_lods(
+        svg: &[u8],
+        tolerance: f32,
+        lod_levels: usize,
+        antialiasing: bool,
+        fonts: &[Vec<u8>],
+    ) -> Result<Vec<SvgGeometry>> {
+        profile_scope!("tessellate_svg");
+
+        if tolerance <= 0.0 {
+            return Err(anyhow!("SVG tessellation tolerance must be positive"));
+        }
+
+        let tree = Tree::from_data(svg, &svg_options(fonts))
+            .map_err(|err| anyhow!("parsing SVG failed: {}", err))?;
+
+        let mut levels = (0..=lod_levels)
+            .map(|level| {
+                let tolerance = tolerance * LOD_TOLERANCE_FACTOR.powi(level as i32);
+
+                Self::tessellate_svg_nodes(
+                    &tree,
+                    tree.root().descendants(),
+                    tolerance,
+                    antialiasing,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // The text nodes are already converted or dropped while parsing
+        if fonts.is_empty() && svg.windows(5).any(|window| window == b"<text") {
+            levels[0]
+                .warnings
+                .push("text isn't drawn because no fonts are loaded".to_string());
+        }
+
+        Ok(levels)
+    }
+
+    /// Fails because SVGs can't be parsed without the `svg` feature, bake them to meshes with
+    /// `clogs-bake` instead.
+    #[cfg(not(feature = "svg"))]
+    pub fn tessellate_svg_lods(
+        _svg: &[u8],
+        _tolerance: f32,
+        _lod_levels: usize,
+        _antialiasing: bool,
+        _fonts: &[Vec<u8>],
+    ) -> Result<Vec<SvgGeometry>> {
+        Err(anyhow!(SVG_DISABLED))
+    }
+
+    /// Render a SVG to RGBA pixels with the top row first, returns the width, height & pixels.
+    ///
+    /// The scale is how many pixels a single unit of the SVG becomes. Detailed SVGs that would
+    /// tessellate into too many triangles are drawn faster this way, at the cost of getting
+    /// blurry when zoomed in.
+    #[cfg(feature = "rasterize")]
+    pub fn rasterize_svg(svg: &[u8], scale: f32, fonts: &[Vec<u8>]) -> Result<(u32, u32, Vec<u8>)> {
+        profile_scope!("rasterize_svg");
+
+        if scale <= 0.0 {
+            return Err(anyhow!("SVG rasterization scale must be positive"));
+        }
+
+        let tree = Tree::from_data(svg, &svg_options(fonts))
+            .map_err(|err| anyhow!("parsing SVG failed: {}", err))?;
+
+        let size = tree.svg_node().size;
+        let width = (size.width() as f32 * scale).ceil() as u32;
+        let height = (size.height() as f32 * scale).ceil() as u32;
+        let mut pixmap = tiny_skia::Pixmap::new(width, height)
+            .ok_or_else(|| anyhow!("SVG of {}x{} pixels is empty", width, height))?;
+        resvg::render(&tree, usvg::FitTo::Zoom(scale), &mut pixmap)
+            .ok_or_else(|| anyhow!("rendering SVG failed"))?;
+
+        // The pixels are premultiplied with their alpha, while the textures are blended without
+        let mut pixels = pixmap.take();
+        for pixel in pixels.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u32;
+            if alpha > 0 && alpha < 255 {
+                for channel in pixel[..3].iter_mut() {
+                    *channel = ((*channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+                }
+            }
+        }
+
+        Ok((width, height, pixels))
+    }
+
+    /// Tessellate the paths of every group with an ID in a SVG separately, without uploading
+    /// them.
+    ///
+    /// The first part has an empty name and contains the paths that aren't in a named group.
+    /// Parts are ordered so a parent always comes before its children, the positions of all
+    /// parts are in the coordinates of the SVG.
+    #[cfg(feature = "svg")]
+    pub fn tessellate_svg_parts(svg: &[u8]) -> Result<Vec<SvgPart>> {
+        profile_scope!("tessellate_svg_parts");
+
+        let options = usvg::Options {
+            keep_named_groups: true,
+            ..usvg::Options::default()
         };
-        self.bindings = Some(bindings);
+        let tree =
+            Tree::from_data(svg, &options).map_err(|err| anyhow!("parsing SVG failed: {}", err))?;
+
+        // Assign every path to the closest group with an ID
+        let mut parts: Vec<(String, Option<usize>, Vec<Node>)> =
+            vec![(String::new(), None, vec![])];
+        let mut part_indices = HashMap::new();
+        for node in tree
+            .root()
+            .descendants()
+            .filter(|node| !clip::is_definition(node))
+        {
+            let parent = node
+                .ancestors()
+                .skip(1)
+                .find_map(|ancestor| part_indices.get(&*ancestor.id()).copied())
+                .unwrap_or(0);
+
+            match *node.borrow() {
+                NodeKind::Group(ref group) if !group.id.is_empty() => {
+                    part_indices.insert(group.id.clone(), parts.len());
+                    parts.push((group.id.clone(), Some(parent), vec![]));
+                }
+                NodeKind::Path(_) => parts[parent].2.push(node.clone()),
+                _ => (),
+            }
+        }
+
+        let mut parts = parts
+            .into_iter()
+            .map(|(name, parent, nodes)| {
+                Ok(SvgPart {
+                    name,
+                    parent,
+                    geometry: Self::tessellate_svg_nodes(
+                        &tree,
+                        nodes,
+                        FillOptions::DEFAULT_TOLERANCE,
+                        false,
+                    )?,
+                    pivot: [0.0, 0.0],
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Rotate parts around the center of their own geometry & their children's by default
+        let mut bounds: Vec<Option<[f32; 4]>> =
+            parts.iter().map(|part| part.geometry.bounds()).collect();
+        for index in (1..parts.len()).rev() {
+            if let (Some(parent), Some([min_x, min_y, max_x, max_y])) =
+                (parts[index].parent, bounds[index])
+            {
+                let merged = bounds[parent].get_or_insert([min_x, min_y, max_x, max_y]);
+                *merged = [
+                    merged[0].min(min_x),
+                    merged[1].min(min_y),
+                    merged[2].max(max_x),
+                    merged[3].max(max_y),
+                ];
+            }
+        }
+        for (part, bounds) in parts.iter_mut().zip(bounds).skip(1) {
+            if let Some([min_x, min_y, max_x, max_y]) = bounds {
+                part.pivot = [(min_x + max_x) / 2.0, (min_y + max_y) / 2.0];
+            }
+        }
+
+        Ok(parts)
     }
-}
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone, Default)]
-pub struct Vertex {
-    pos: [f32; 2],
-    color: [f32; 4],
+    /// Fails because SVGs can't be parsed without the `svg` feature.
+    #[cfg(not(feature = "svg"))]
+    pub fn tessellate_svg_parts(_svg: &[u8]) -> Result<Vec<SvgPart>> {
+        Err(anyhow!(SVG_DISABLED))
+    }
+
+    /// Tessellate the filled paths from a list of nodes of a SVG as a single geometry.
+    ///
+    /// With anti-aliasing every filled path gets a transparent fringe around its edges.
+    #[cfg(feature = "svg")]
+    fn tessellate_svg_nodes<I>(
+        tree: &Tree,
+        nodes: I,
+        tolerance: f32,
+        antialiasing: bool,
+    ) -> Result<SvgGeometry>
+    where
+        I: IntoIterator<Item = Node>,
+    {
+        let mut geometry: VertexBuffers<Vertex, u16> = VertexBuffers::new();
+        let mut tessellator = FillTessellator::new();
+        let mut outline = vec![];
+        let mut warnings = vec![];
+        for node in nodes {
+            // The contents of clip paths, masks & other definitions aren't drawn themselves
+            if clip::is_definition(&node) {
+                continue;
+            }
+
+            // Report what isn't drawn instead of dropping it silently
+            warnings.extend(unsupported_node(&node));
+
+            if let NodeKind::Path(ref svg_path) = *node.borrow() {
+                let mut transform = node.abs_transform();
+                transform.append(&svg_path.transform);
+
+                // Only solid color & gradient fills are supported
+                let fill = match svg_path.fill {
+                    Some(ref fill) => fill,
+                    None => continue,
+                };
+                let opacity = fill.opacity.value() as f32;
+                let vertex_ctor = match fill.paint {
+                    Paint::Color(color) => {
+                        VertexCtor::new(Color::new(color.red, color.green, color.blue), opacity)
+                    }
+                    Paint::Link(ref id) => {
+                        let gradient = tree.defs_by_id(id).and_then(|gradient| {
+                            Gradient::from_node(
+                                &gradient.borrow(),
+                                &svg_path.data,
+                                transform,
+                                opacity,
+                            )
+                        });
+                        match gradient {
+                            Some(gradient) => VertexCtor::with_gradient(gradient),
+                            // Patterns aren't supported
+                            None => {
+                                warnings
+                                    .push(node_warning(&svg_path.id, "pattern fill isn't drawn"));
+
+                                continue;
+                            }
+                        }
+                    }
+                };
+
+                // Convert the usvg path to a lyon path with the transformations applied
+                let mut path = path::from_svg(&svg_path.data, transform);
+
+                // Cut away the parts outside of the clip paths & masks of the groups
+                if let Some(region) = clip::region(tree, &node, tolerance) {
+                    path = clip::apply(&path, &region, tolerance);
+                }
+                if outline.is_empty() {
+                    outline = path::outline(path.iter());
+                }
+
+                // Append the tessellated path to the shared geometry
+                tessellator
+                    .tessellate(
+                        path.iter(),
+                        &FillOptions::tolerance(tolerance),
+                        &mut BuffersBuilder::new(&mut geometry, vertex_ctor.clone()),
+                    )
+                    .map_err(|err| anyhow!("tessellating SVG path failed: {:?}", err))?;
+
+                if antialiasing {
+                    for points in path::sub_paths(path.iter(), tolerance) {
+                        append_fringe(&mut geometry, &points, &vertex_ctor)?;
+                    }
+                }
+            }
+        }
+
+        warnings.sort();
+        warnings.dedup();
+
+        Ok(SvgGeometry {
+            geometry,
+            outline,
+            tolerance,
+            warnings,
+        })
+    }
+
+    /// Copy a mesh as a nine-slice panel with margins in the order left, top, right & bottom.
+    ///
+    /// The parts within the margins keep their size when the panel size of an instance is set,
+    /// only the parts between them are stretched. Geometry crossing a margin is stretched
+    /// linearly, so the corners should have their own vertices.
+    pub fn upload_nine_slice(&mut self, mesh: Mesh, margins: [f32; 4]) -> Result<Mesh> {
+        let draw_call = &self.draw_calls[mesh.0];
+        let [min_x, min_y, max_x, max_y] = draw_call.bounds;
+        let [left, top, right, bottom] = margins;
+        if margins.iter().any(|margin| *margin < 0.0) {
+            return Err(anyhow!("nine-slice margins can't be negative"));
+        }
+        if left + right > max_x - min_x || top + bottom > max_y - min_y {
+            return Err(anyhow!("nine-slice margins are larger than the mesh"));
+        }
+
+        let vertices = draw_call
+            .vertices
+            .iter()
+            .map(|vertex| Vertex {
+                slice: [
+                    slice_weight(vertex.pos[0], min_x + left, max_x - right),
+                    slice_weight(vertex.pos[1], min_y + top, max_y - bottom),
+                ],
+                ..*vertex
+            })
+            .collect();
+        let indices = draw_call.indices.clone();
+        let atlas_page = draw_call.atlas_page;
+
+        let panel = self.add_draw_call(vertices, indices);
+        self.draw_calls[panel.0].atlas_page = atlas_page;
+
+        Ok(panel)
+    }
+
+    /// Render the graphics.
+    pub fn render(&mut self, ctx: &mut Context) {
+        self.draw(ctx);
+
+        ctx.commit_frame();
+    }
+
+    /// Render the graphics without finishing the frame, so more can be drawn on top.
+    pub fn draw(&mut self, ctx: &mut Context) {
+        profile_scope!("draw");
+
+        self.prepare(ctx);
+        if self.offscreen_size.is_none() {
+            self.execute_frame_graph(ctx, None);
+        }
+
+        // The debug geometry is only drawn for a single frame
+        self.debug_geometry.vertices.clear();
+        self.debug_geometry.indices.clear();
+    }
+
+    /// Record what would be rendered without drawing anything, used when running headless.
+    pub fn record_frame(&mut self) -> RenderedFrame {
+        if self.draw_order_changed {
+            self.draw_queue = self.sorted_draw_calls();
+
+            self.draw_order_changed = false;
+        }
+
+        // Count the draw calls the main camera would take, a mesh takes one for every chunk
+        let views = [
+            self.main_view(None, (i32::MIN, UNLIT_DRAW_ORDER - 1)),
+            self.main_view(None, (UNLIT_DRAW_ORDER, i32::MAX)),
+        ];
+        self.draw_call_count = views
+            .iter()
+            .flat_map(|view| self.draw_runs(view))
+            .map(|(run, _)| match run[..] {
+                [index] => {
+                    (self.draw_calls[index].instances.len() + INSTANCE_CHUNK_SIZE - 1)
+                        / INSTANCE_CHUNK_SIZE
+                }
+                _ => 1,
+            })
+            .sum();
+
+        let debug_vertex_count = self.debug_geometry.vertices.len();
+        self.debug_geometry.vertices.clear();
+        self.debug_geometry.indices.clear();
+
+        RenderedFrame {
+            instance_counts: self.instance_counts(),
+            camera_pan: self.camera_pan,
+            camera_zoom: self.camera_zoom,
+            debug_vertex_count,
+        }
+    }
+
+    /// Render the graphics to a texture and read back the pixels.
+    ///
+    /// Returns the width, height & RGBA pixels with the top row first. Must be called before
+    /// `render` so the same frame is shown on the screen.
+    pub fn capture_frame(&mut self, ctx: &mut Context) -> (u32, u32, Vec<u8>) {
+        self.prepare(ctx);
+
+        // (Re)create the capture target with the size of the frame
+        let (width, height) = self.frame_size();
+        match self.capture_target {
+            Some((color, _, _)) if color.width == width && color.height == height => (),
+            _ => {
+                if let Some((color, depth, pass)) = self.capture_target.take() {
+                    pass.delete(ctx);
+                    color.delete();
+                    depth.delete();
+                }
+
+                self.capture_target = Some(postprocess::render_target(ctx, width, height));
+            }
+        }
+        let (color, _, capture_pass) = self.capture_target.unwrap();
+
+        self.execute_frame_graph(ctx, Some(capture_pass));
+
+        let mut pixels = vec![0; width as usize * height as usize * 4];
+        color.read_pixels(&mut pixels);
+
+        // OpenGL stores the bottom row first
+        let row_size = width as usize * 4;
+        let pixels = pixels
+            .chunks_exact(row_size)
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+
+        (width, height, pixels)
+    }
+
+    /// Render frames offscreen at a size in physical pixels instead of the size of the window, or
+    /// at the size of the window again with `None`.
+    ///
+    /// Offscreen frames are only drawn when they're captured, nothing is drawn to the window.
+    pub fn set_offscreen_size(&mut self, size: Option<(u32, u32)>) {
+        self.offscreen_size = size.map(|(width, height)| (width as f32, height as f32));
+        if let Some(size) = self.offscreen_size {
+            self.screen_size = size;
+        }
+    }
+
+    /// The size of the textures a frame is drawn to in physical pixels.
+    fn frame_size(&self) -> (u32, u32) {
+        (self.screen_size.0 as u32, self.screen_size.1 as u32)
+    }
+
+    /// Update the transforms, buffers & draw order before drawing.
+    fn prepare(&mut self, ctx: &mut Context) {
+        self.screen_size = self.offscreen_size.unwrap_or_else(|| ctx.screen_size());
+        self.draw_call_count = 0;
+        self.culled_count = 0;
+
+        // Apply the transforms of the parents to their children
+        let draw_calls = &self.draw_calls;
+        let world_transforms = self.hierarchy.world_transforms(|instance| {
+            let draw_call = &draw_calls[instance.mesh];
+
+            draw_call
+                .instance_lookup
+                .get(&instance.id)
+                .map(|index| draw_call.instances[*index])
+        });
+        for (instance, world_transform) in world_transforms {
+            if let Some(stored) = self.stored_instance_mut(instance) {
+                *stored = world_transform;
+            }
+        }
+
+        // Assign the buffers of the batches if necessary
+        if self.missing_bindings {
+            profile_scope!("upload_buffers");
+
+            for dc in self.draw_calls.iter_mut() {
+                if dc.unloaded {
+                    continue;
+                } else if dc.geometry_dirty {
+                    dc.upload_geometry(ctx);
+                } else if dc.buffers.is_none() {
+                    dc.buffers = Some(self.batches[dc.batch].buffers(ctx));
+                }
+            }
+
+            self.missing_bindings = false;
+        }
+
+        // Copy the images packed since the last frame to their atlas pages
+        self.atlas.upload(ctx);
+
+        // Sort the draw calls by their draw order, keeping the upload order for equal ones
+        if self.draw_order_changed {
+            self.draw_queue = self.sorted_draw_calls();
+
+            self.draw_order_changed = false;
+        }
+    }
+
+    /// Run the enabled passes of the frame graph, the final passes draw to the output or the
+    /// screen if there's none.
+    fn execute_frame_graph(&mut self, ctx: &mut Context, output: Option<RenderPass>) {
+        let pipeline = match self.pipeline {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+
+        let passes: Vec<(PassKind, Target)> = match self.frame_graph.sorted() {
+            Ok(passes) => passes
+                .into_iter()
+                .map(|pass| (pass.kind, pass.output))
+                .collect(),
+            Err(err) => {
+                log::error!("drawing the frame failed: {}", err);
+
+                return;
+            }
+        };
+
+        // Without post-processing the scene is drawn directly to the output
+        let post_processed = self.post_processing.is_enabled()
+            && passes
+                .iter()
+                .any(|(kind, _)| *kind == PassKind::PostProcessing);
+        let scene_pass = if post_processed {
+            self.post_processing.scene_pass(ctx, self.frame_size())
+        } else {
+            output
+        };
+        let target = |target: Target| match target {
+            Target::Scene if post_processed => Target::Scene,
+            Target::LightMap => Target::LightMap,
+            _ => Target::Output,
+        };
+
+        // The first pass drawing to a target clears it, the next ones draw over it
+        let mut open: Option<Target> = None;
+        let mut cleared: Vec<Target> = Vec::new();
+        for (kind, output_target) in passes {
+            if kind == PassKind::PostProcessing && !post_processed {
+                continue;
+            }
+
+            let output_target = target(output_target);
+            let pass = match output_target {
+                Target::Scene => scene_pass,
+                _ => output,
+            };
+            if kind.owns_render_pass() {
+                if open.take().is_some() {
+                    ctx.end_render_pass();
+                }
+            } else if open != Some(output_target) {
+                if open.is_some() {
+                    ctx.end_render_pass();
+                }
+
+                let action = if cleared.contains(&output_target) {
+                    PassAction::Nothing
+                } else {
+                    self.clear_action()
+                };
+                ctx.begin_pass(pass, action);
+                open = Some(output_target);
+            }
+            cleared.push(output_target);
+
+            self.execute_pass(ctx, kind, pass, pipeline);
+        }
+
+        if open.is_some() {
+            ctx.end_render_pass();
+        }
+    }
+
+    /// Draw a single pass of the frame graph to a render pass, or the screen if there's none.
+    ///
+    /// Passes that don't own their render pass are drawn inside the opened one.
+    fn execute_pass(
+        &mut self,
+        ctx: &mut Context,
+        kind: PassKind,
+        pass: Option<RenderPass>,
+        pipeline: Pipeline,
+    ) {
+        let (width, height) = self.screen_size;
+        match kind {
+            PassKind::Lights => {
+                self.light_map.draw_lights(
+                    ctx,
+                    self.frame_size(),
+                    &geom_shader::Uniforms {
+                        zoom: (self.view_zoom() / width, self.view_zoom() / height),
+                        pan: self.view_pan(),
+                        pixel: self.world_units_per_pixel(),
+                        // The lights aren't swapped
+                        palette: PaletteSwap::default(),
+                    },
+                );
+            }
+            // The main camera isn't visible when the viewports of a split screen cover everything
+            PassKind::World if !self.is_split_screen() => {
+                self.draw_meshes(ctx, self.main_view(pass, (i32::MIN, UNLIT_DRAW_ORDER - 1)));
+            }
+            PassKind::Lighting if !self.is_split_screen() => self.light_map.composite(ctx),
+            PassKind::Ui if !self.is_split_screen() => {
+                self.draw_meshes(ctx, self.main_view(pass, (UNLIT_DRAW_ORDER, i32::MAX)));
+            }
+            PassKind::World | PassKind::Lighting | PassKind::Ui => (),
+            PassKind::Viewports => {
+                // The viewports are drawn over the scene with their own cameras
+                for index in 0..self.viewports.len() {
+                    let viewport = match self.viewports[index] {
+                        Some(viewport) => viewport,
+                        None => continue,
+                    };
+                    let [x, y, viewport_width, viewport_height] = viewport
+                        .screen_rect(self.screen_size())
+                        .map(|pixels| pixels * self.dpi_scale);
+                    let rect = [
+                        x as i32,
+                        (height - y - viewport_height) as i32,
+                        viewport_width as i32,
+                        viewport_height as i32,
+                    ];
+
+                    ctx.apply_scissor_rect(rect[0], rect[1], rect[2], rect[3]);
+                    ctx.clear(Some((0.4, 0.7, 1.0, 1.0)), Some(1.0), None);
+                    self.draw_meshes(
+                        ctx,
+                        View {
+                            rect,
+                            pan: viewport.camera_pan,
+                            zoom: viewport.camera_zoom * self.design_scale(),
+                            draw_orders: viewport.draw_orders,
+                            main: false,
+                            pass,
+                        },
+                    );
+                }
+                if self.viewports.iter().any(Option::is_some) {
+                    ctx.apply_viewport(0, 0, width as i32, height as i32);
+                    ctx.apply_scissor_rect(0, 0, width as i32, height as i32);
+                }
+            }
+            PassKind::Overlay => self.overlay.draw(ctx),
+            PassKind::Debug => self.render_debug_geometry(ctx, pipeline),
+            PassKind::PostProcessing => self.post_processing.apply(ctx, pass, self.frame_size()),
+        }
+    }
+
+    /// The view of the main camera covering the whole screen.
+    fn main_view(&self, pass: Option<RenderPass>, draw_orders: (i32, i32)) -> View {
+        let (width, height) = self.screen_size;
+
+        View {
+            rect: [0, 0, width as i32, height as i32],
+            pan: self.view_pan(),
+            zoom: self.view_zoom(),
+            draw_orders: Some(draw_orders),
+            main: true,
+            pass,
+        }
+    }
+
+    /// Clear the color with the sky color & reset the depth buffer.
+    fn clear_action(&self) -> PassAction {
+        let [red, green, blue] = if self.gamma_correct {
+            [0.4f32.powf(2.2), 0.7f32.powf(2.2), 1.0]
+        } else {
+            [0.4, 0.7, 1.0]
+        };
+
+        PassAction::clear_color(red, green, blue, 1.0)
+    }
+
+    /// Draw all instances as seen by a camera to a part of the render pass.
+    ///
+    /// Only the main view is clipped by the clip rectangles of the meshes.
+    fn draw_meshes(&mut self, ctx: &mut Context, view: View) {
+        let (pipeline, sprite_pipeline, mask_pipeline) =
+            match (self.pipeline, self.sprite_pipeline, self.mask_pipeline) {
+                (Some(pipeline), Some(sprite_pipeline), Some(mask_pipeline)) => {
+                    (pipeline, sprite_pipeline, mask_pipeline)
+                }
+                _ => return,
+            };
+
+        let pan = view.pan;
+        let frame_size = self.frame_size();
+
+        let [x, y, width, height] = view.rect;
+        ctx.apply_viewport(x, y, width, height);
+        let full_screen = view.rect;
+        let mut applied_scissor = full_screen;
+        ctx.apply_scissor_rect(x, y, width, height);
+        let (width, height) = (width as f32, height as f32);
+        let zoom = (view.zoom / width, view.zoom / height);
+        let pixel = 2.0 / view.zoom;
+        let palette = self.palette_swap;
+        // Half the size of the visible part of the world
+        let extent = (width / view.zoom, height / view.zoom);
+
+        // Render the separate draw calls
+        // The pipeline & uniforms are only applied when the shader changes
+        let mut applied_shader = None;
+        let mut merged_count = 0;
+        for (run, scissor) in self.draw_runs(&view) {
+            if scissor != applied_scissor {
+                let [x, y, width, height] = scissor;
+                ctx.apply_scissor_rect(x, y, width, height);
+                applied_scissor = scissor;
+            }
+
+            // Small meshes drawn after each other are merged into a single draw call
+            if run.len() > 1 {
+                let merged = self.merge_run(&run, extent, pan, view.main);
+                let (merged_pipeline, merged_sprite_pipeline) = match self.merged_pipelines {
+                    Some(pipelines) => pipelines,
+                    None => continue,
+                };
+                let texture = match self.draw_calls[run[0]].atlas_page {
+                    Some(page) => match self.atlas.texture(page) {
+                        Some(texture) => Some(texture),
+                        None => continue,
+                    },
+                    None => None,
+                };
+                if !merged {
+                    continue;
+                }
+
+                let bindings = self.merged_buffers.upload(
+                    ctx,
+                    merged_count,
+                    &self.merged_draw,
+                    texture.into_iter().collect(),
+                );
+                merged_count += 1;
+
+                ctx.apply_pipeline(if texture.is_some() {
+                    &merged_sprite_pipeline
+                } else {
+                    &merged_pipeline
+                });
+                ctx.apply_uniforms(&geom_shader::Uniforms {
+                    zoom,
+                    pan,
+                    pixel,
+                    palette,
+                });
+                ctx.apply_bindings(&bindings);
+                ctx.draw(0, self.merged_draw.index_count() as i32, 1);
+                self.draw_call_count += 1;
+
+                applied_shader = None;
+                continue;
+            }
+
+            // Zoomed out meshes are drawn with the geometry of a coarser level of detail
+            let index = run[0];
+            let lod = DrawCall::lod_geometry(&self.draw_calls, index, view.zoom);
+            let dc = &mut self.draw_calls[index];
+
+            // Mark the pixels covered by the mask, the mesh is only drawn on those
+            let mask = dc.mask;
+            if let Some(mask) = mask {
+                ctx.clear(None, None, Some(0));
+
+                let mask_dc = &mut self.draw_calls[mask];
+                if !mask_dc.instances.is_empty() && !mask_dc.indices.is_empty() {
+                    mask_dc.sort_instances();
+                    mask_dc.upload_instances(ctx);
+
+                    ctx.apply_pipeline(&mask_pipeline);
+                    ctx.apply_uniforms(&geom_shader::Uniforms {
+                        zoom,
+                        pan,
+                        pixel,
+                        palette,
+                    });
+                    for (bindings, instance_count) in mask_dc.instance_chunks() {
+                        ctx.apply_bindings(bindings);
+                        ctx.draw(
+                            mask_dc.first_index as i32,
+                            mask_dc.indices.len() as i32,
+                            instance_count as i32,
+                        );
+                        self.draw_call_count += 1;
+                    }
+                }
+
+                applied_shader = None;
+            }
+            let dc = &mut self.draw_calls[index];
+
+            // Sort the instances by their draw order when it changed
+            dc.sort_instances();
+
+            // Upload the instance positions that changed, or only the visible ones when culling
+            // Custom shaders can move the vertices anywhere so they are never culled
+            if self.culling && dc.shader.is_none() {
+                let culled_count = dc.upload_visible_instances(ctx, extent, pan);
+                if view.main {
+                    self.culled_count += culled_count;
+                }
+            } else {
+                dc.upload_instances(ctx);
+            }
+
+            // Textured meshes draw with the texture of their atlas page
+            let texture = match dc.atlas_page {
+                Some(page) => match self.atlas.texture(page) {
+                    Some(texture) => Some(texture),
+                    None => continue,
+                },
+                None => None,
+            };
+            if let Some(texture) = texture {
+                for bindings in dc.chunk_bindings.iter_mut() {
+                    if bindings.images.is_empty() {
+                        bindings.images.push(texture);
+                    }
+                }
+            }
+
+            // Grouped meshes are drawn opaque to the layer & blended with the scene afterwards
+            if let (Some(group), Some(group_pipeline)) = (
+                dc.opacity_group,
+                self.opacity_layer.pipeline(texture.is_some()),
+            ) {
+                let uniforms = geom_shader::Uniforms {
+                    zoom,
+                    pan,
+                    pixel,
+                    palette,
+                };
+                match group {
+                    OpacityGroup::Mesh(opacity) => {
+                        self.opacity_layer
+                            .begin(ctx, frame_size, view.rect, scissor);
+                        ctx.apply_pipeline(&group_pipeline);
+                        ctx.apply_uniforms(&uniforms);
+                        for (bindings, instance_count) in dc.instance_chunks() {
+                            let (first_index, index_count) = match lod {
+                                Some((vertex_buffer, index_buffer, first_index, index_count)) => {
+                                    ctx.apply_bindings(&Bindings {
+                                        vertex_buffers: vec![
+                                            vertex_buffer,
+                                            bindings.vertex_buffers[1],
+                                        ],
+                                        index_buffer,
+                                        images: bindings.images.clone(),
+                                    });
+
+                                    (first_index, index_count)
+                                }
+                                None => {
+                                    ctx.apply_bindings(bindings);
+
+                                    (dc.first_index, dc.indices.len())
+                                }
+                            };
+                            ctx.draw(
+                                first_index as i32,
+                                index_count as i32,
+                                instance_count as i32,
+                            );
+                            self.draw_call_count += 1;
+                        }
+                        self.opacity_layer
+                            .composite(ctx, view.pass, view.rect, scissor, opacity);
+                        self.draw_call_count += 1;
+                    }
+                    OpacityGroup::Instances => {
+                        let geometry = lod.or_else(|| {
+                            dc.buffers.map(|(vertex_buffer, index_buffer)| {
+                                (
+                                    vertex_buffer,
+                                    index_buffer,
+                                    dc.first_index,
+                                    dc.indices.len(),
+                                )
+                            })
+                        });
+                        let (vertex_buffer, index_buffer, first_index, index_count) = match geometry
+                        {
+                            Some(geometry) => geometry,
+                            None => continue,
+                        };
+
+                        // Every instance is a layer of its own, so it's drawn one at a time
+                        for instance in dc.instances.iter() {
+                            if instance.alpha <= 0.0
+                                || (self.culling && !dc.is_visible(instance, extent, pan))
+                            {
+                                continue;
+                            }
+                            let opaque = Instance {
+                                alpha: 1.0,
+                                ..*instance
+                            };
+                            let instance_buffer =
+                                match self.opacity_layer.upload_instance(ctx, opaque) {
+                                    Some(instance_buffer) => instance_buffer,
+                                    None => break,
+                                };
+
+                            self.opacity_layer
+                                .begin(ctx, frame_size, view.rect, scissor);
+                            ctx.apply_pipeline(&group_pipeline);
+                            ctx.apply_uniforms(&uniforms);
+                            ctx.apply_bindings(&Bindings {
+                                vertex_buffers: vec![vertex_buffer, instance_buffer],
+                                index_buffer,
+                                images: texture.into_iter().collect(),
+                            });
+                            ctx.draw(first_index as i32, index_count as i32, 1);
+                            self.opacity_layer.composite(
+                                ctx,
+                                view.pass,
+                                view.rect,
+                                scissor,
+                                instance.alpha,
+                            );
+                            self.draw_call_count += 2;
+                        }
+                    }
+                }
+
+                applied_shader = None;
+                continue;
+            }
+
+            let shader_key = (dc.shader, texture.is_some(), mask.is_some());
+            if applied_shader != Some(shader_key) {
+                match dc.shader {
+                    Some(shader) => {
+                        let shader = &mut self.shaders[shader];
+                        ctx.apply_pipeline(&shader.pipeline);
+
+                        // Fill the builtin uniforms
+                        shader.uniforms.values_mut()[..4]
+                            .copy_from_slice(&[zoom.0, zoom.1, pan.0, pan.1]);
+                        shader.uniforms.apply(ctx);
+                    }
+                    None => {
+                        ctx.apply_pipeline(if texture.is_some() {
+                            &sprite_pipeline
+                        } else {
+                            &pipeline
+                        });
+                        ctx.apply_uniforms(&geom_shader::Uniforms {
+                            zoom,
+                            pan,
+                            pixel,
+                            palette,
+                        });
+                    }
+                }
+                // Applying a pipeline disables the stencil test again
+                if mask.is_some() {
+                    ctx.set_stencil(Some(stencil_state(CompareFunc::Equal, StencilOp::Keep)));
+                }
+                applied_shader = Some(shader_key);
+            }
+
+            // Draw every chunk of instances with its own instance buffer
+            for (bindings, instance_count) in dc.instance_chunks() {
+                let (first_index, index_count) = match lod {
+                    Some((vertex_buffer, index_buffer, first_index, index_count)) => {
+                        ctx.apply_bindings(&Bindings {
+                            vertex_buffers: vec![vertex_buffer, bindings.vertex_buffers[1]],
+                            index_buffer,
+                            images: bindings.images.clone(),
+                        });
+
+                        (first_index, index_count)
+                    }
+                    None => {
+                        ctx.apply_bindings(bindings);
+
+                        (dc.first_index, dc.indices.len())
+                    }
+                };
+                ctx.draw(
+                    first_index as i32,
+                    index_count as i32,
+                    instance_count as i32,
+                );
+                self.draw_call_count += 1;
+            }
+        }
+
+        // The next pass draws to the whole view again
+        if applied_scissor != full_screen {
+            ctx.apply_scissor_rect(x, y, width as i32, height as i32);
+        }
+    }
+
+    /// The meshes of the draw queue a view draws with the scissor rectangle of each, grouped by
+    /// the draw calls they're drawn with.
+    ///
+    /// Small meshes drawn after each other with the same pipeline, texture & clip rectangles are
+    /// grouped so they're merged into a single draw call.
+    fn draw_runs(&self, view: &View) -> Vec<(Vec<usize>, [i32; 4])> {
+        let mut runs: Vec<(Vec<usize>, [i32; 4])> = vec![];
+        // The atlas page of the last run & its amount of vertices when it can be merged with
+        let mut merging = None;
+        for &index in self.draw_queue.iter() {
+            let dc = &self.draw_calls[index];
+
+            // Only render when we actually have instances, masks are only drawn to the stencil
+            if dc.instances.is_empty() || dc.indices.is_empty() || dc.is_mask {
+                continue;
+            }
+            if let Some((min, max)) = view.draw_orders {
+                if dc.draw_order < min || dc.draw_order > max {
+                    continue;
+                }
+            }
+
+            let scissor = if view.main {
+                scissor_rect(&dc.clips, self.screen_size, self.dpi_scale, view.zoom)
+            } else {
+                view.rect
+            };
+            let vertex_count = dc.vertices.len() * dc.instances.len();
+            if !dc.is_mergeable(view.zoom) || vertex_count > MAX_MERGED_VERTICES {
+                runs.push((vec![index], scissor));
+                merging = None;
+
+                continue;
+            }
+
+            match (merging, runs.last_mut()) {
+                (Some((atlas_page, merged_vertices)), Some((run, run_scissor)))
+                    if atlas_page == dc.atlas_page
+                        && *run_scissor == scissor
+                        && MergedDraw::fits(merged_vertices + vertex_count) =>
+                {
+                    run.push(index);
+                    merging = Some((atlas_page, merged_vertices + vertex_count));
+                }
+                _ => {
+                    runs.push((vec![index], scissor));
+                    merging = Some((dc.atlas_page, vertex_count));
+                }
+            }
+        }
+
+        runs
+    }
+
+    /// Fill the merged draw with the instances of meshes that might be visible, returns whether
+    /// anything is drawn.
+    ///
+    /// The view is half the size of the visible part of the world around the camera.
+    fn merge_run(&mut self, run: &[usize], view: (f32, f32), pan: (f32, f32), main: bool) -> bool {
+        self.merged_draw.clear();
+        for &index in run {
+            let dc = &mut self.draw_calls[index];
+
+            // Sort the instances by their draw order when it changed
+            dc.sort_instances();
+
+            for instance in dc.instances.iter() {
+                if self.culling && !dc.is_visible(instance, view, pan) {
+                    if main {
+                        self.culled_count += 1;
+                    }
+
+                    continue;
+                }
+
+                self.merged_draw.push(&dc.vertices, &dc.indices, *instance);
+            }
+        }
+
+        self.merged_draw.index_count() > 0
+    }
+
+    /// How many draw calls were issued during the last render.
+    ///
+    /// Every mesh with visible instances takes at least one, except small meshes drawn after
+    /// each other with the same pipeline, which are merged into a single draw call. Headless the
+    /// draw calls of the meshes of the main camera are counted when the frame is recorded.
+    pub fn draw_call_count(&self) -> usize {
+        self.draw_call_count
+    }
+
+    /// Set whether instances outside of the screen are skipped when drawing, enabled by default.
+    ///
+    /// Culled instances aren't uploaded, which is faster when most instances are off-screen.
+    pub fn set_culling(&mut self, culling: bool) {
+        self.culling = culling;
+    }
+
+    /// Whether instances outside of the screen are skipped when drawing.
+    pub fn is_culling(&self) -> bool {
+        self.culling
+    }
+
+    /// Replace colors of all meshes & textures & multiply them with a tint when drawing.
+    ///
+    /// Meshes with a custom shader, the lights & the debug geometry are drawn unchanged.
+    pub fn set_palette_swap(&mut self, palette: PaletteSwap) {
+        self.palette_swap = palette;
+    }
+
+    /// The colors replaced when drawing & the tint.
+    pub fn palette_swap(&self) -> PaletteSwap {
+        self.palette_swap
+    }
+
+    /// How many instances were skipped because they were outside of the screen during the last
+    /// render.
+    pub fn culled_instance_count(&self) -> usize {
+        self.culled_count
+    }
+
+    /// The bounding box of the vertices of a mesh as `[min_x, min_y, max_x, max_y]`.
+    ///
+    /// Meshes without vertices have an inverted bounding box.
+    pub fn mesh_bounds(&self, mesh: Mesh) -> [f32; 4] {
+        self.draw_calls[mesh.0].bounds
+    }
+
+    /// The amount of instances for each mesh that has instances.
+    pub fn instance_counts(&self) -> Vec<(Mesh, usize)> {
+        self.draw_calls
+            .iter()
+            .enumerate()
+            .filter(|(_, dc)| !dc.instances.is_empty())
+            .map(|(index, dc)| (Mesh(index), dc.instances.len()))
+            .collect()
+    }
+
+    /// Free the geometry & GPU buffers of a mesh and remove all its instances.
+    ///
+    /// The handles of the mesh & its instances stay safe to use afterwards, the mesh just doesn't
+    /// have any geometry anymore and instances added to it aren't drawn. The coarser levels of
+    /// detail are unloaded with it and meshes masked by it are drawn everywhere again. The buffers
+    /// shared by a batch of meshes are freed when the last mesh in it is unloaded.
+    pub fn unload_mesh(&mut self, mesh: Mesh) -> Result<()> {
+        let draw_call = self
+            .draw_calls
+            .get(mesh.0)
+            .ok_or_else(|| anyhow!("mesh doesn't exist"))?;
+        if draw_call.unloaded {
+            return Err(anyhow!("mesh is already unloaded"));
+        }
+        if mesh == self.loading_bar || mesh == self.transform_node {
+            return Err(anyhow!("builtin meshes can't be unloaded"));
+        }
+        let lods: Vec<usize> = draw_call.lods.iter().map(|(_, lod)| *lod).collect();
+
+        for instance in self.instances(mesh) {
+            self.remove_instance(instance);
+        }
+
+        // Masks are only masks as long as they mask another mesh
+        if let Some(mask) = self.draw_calls[mesh.0].mask.take() {
+            self.draw_calls[mask].is_mask = self.draw_calls.iter().any(|dc| dc.mask == Some(mask));
+        }
+        for dc in self.draw_calls.iter_mut() {
+            if dc.mask == Some(mesh.0) {
+                dc.mask = None;
+            }
+        }
+        self.draw_calls[mesh.0].is_mask = false;
+
+        let draw_call = &mut self.draw_calls[mesh.0];
+        draw_call.unload();
+        self.batches[draw_call.batch].release();
+
+        for lod in lods {
+            if !self.draw_calls[lod].unloaded {
+                self.unload_mesh(Mesh(lod))?;
+            }
+        }
+
+        self.draw_order_changed = true;
+
+        Ok(())
+    }
+
+    /// Whether a mesh still has its geometry, `false` after it's unloaded.
+    pub fn is_mesh_loaded(&self, mesh: Mesh) -> bool {
+        self.draw_calls
+            .get(mesh.0)
+            .map_or(false, |draw_call| !draw_call.unloaded)
+    }
+
+    /// The GPU memory used by the vertex, index & instance buffers of a mesh.
+    ///
+    /// Meshes sharing the buffers of a batch only count their own part of them, nothing is counted
+    /// before the mesh is drawn for the first time.
+    pub fn mesh_memory(&self, mesh: Mesh) -> MeshMemory {
+        let draw_call = &self.draw_calls[mesh.0];
+        let lods = draw_call
+            .lods
+            .iter()
+            .map(|(_, lod)| self.draw_calls[*lod].memory());
+
+        lods.fold(draw_call.memory(), |memory, lod| memory + lod)
+    }
+
+    /// The GPU memory used by the vertex, index & instance buffers of all meshes.
+    ///
+    /// Unlike summing the memory of every mesh, this includes the parts of shared buffers that
+    /// belong to meshes that are unloaded or got their own buffers.
+    pub fn gpu_memory(&self) -> MeshMemory {
+        let batches = self.batches.iter().map(|batch| batch.memory()).fold(
+            (0, 0),
+            |(vertices, indices), (vertex_bytes, index_bytes)| {
+                (vertices + vertex_bytes, indices + index_bytes)
+            },
+        );
+        let own_buffers = self
+            .draw_calls
+            .iter()
+            .filter(|dc| dc.own_buffers)
+            .filter_map(|dc| dc.buffers)
+            .fold(
+                (0, 0),
+                |(vertices, indices), (vertex_buffer, index_buffer)| {
+                    (
+                        vertices + vertex_buffer.size(),
+                        indices + index_buffer.size(),
+                    )
+                },
+            );
+
+        MeshMemory {
+            vertex_bytes: batches.0 + own_buffers.0,
+            index_bytes: batches.1 + own_buffers.1,
+            instance_bytes: self.draw_calls.iter().map(DrawCall::instance_bytes).sum(),
+        }
+    }
+
+    /// Unload a mesh from a script.
+    ///
+    /// ```gamelisp
+    /// (unload-mesh level-mesh)
+    /// ```
+    fn unload_mesh_script(&mut self, mesh: &Mesh) -> GResult<()> {
+        match self.unload_mesh(*mesh) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("unloading mesh failed: {}", err),
+        }
+    }
+
+    /// Whether a mesh is loaded from a script.
+    fn is_mesh_loaded_script(&self, mesh: &Mesh) -> bool {
+        self.is_mesh_loaded(*mesh)
+    }
+
+    /// The GPU memory of a mesh in bytes from a script.
+    ///
+    /// ```gamelisp
+    /// (let memory (mesh-memory level-mesh))
+    /// (prn [memory 'vertex] [memory 'index] [memory 'instance] [memory 'total])
+    /// ```
+    fn mesh_memory_script(&self, mesh: &Mesh) -> GResult<Root<Tab>> {
+        self.mesh_memory(*mesh).to_table()
+    }
+
+    /// The GPU memory of all meshes in bytes from a script, with the same keys as `mesh-memory`.
+    fn gpu_memory_script(&self) -> GResult<Root<Tab>> {
+        self.gpu_memory().to_table()
+    }
+
+    /// Convert a pixel position on the screen to a world position for instances at the default Z.
+    pub fn screen_to_world(&self, x: f32, y: f32) -> (f32, f32) {
+        let (width, height) = self.screen_size;
+        let (x, y) = (x * self.dpi_scale, y * self.dpi_scale);
+
+        (
+            (x * 2.0 - width) / self.view_zoom(),
+            (y * 2.0 - height) / self.view_zoom(),
+        )
+    }
+
+    /// Convert a world position at the default Z to a pixel position on the screen.
+    pub fn world_to_screen(&self, x: f32, y: f32) -> (f32, f32) {
+        let (x, y) = world_to_screen(x, y, self.screen_size, self.view_zoom());
+
+        (x / self.dpi_scale, y / self.dpi_scale)
+    }
+
+    /// Draw the world a second time over the scene, to a rectangle on the screen in pixels from
+    /// the top left as `[x, y, width, height]` with its own camera.
+    ///
+    /// Viewports aren't lit & ignore the clip rectangles of meshes, which makes them usable as
+    /// minimaps.
+    pub fn add_viewport(
+        &mut self,
+        rect: [f32; 4],
+        camera_pan: (f32, f32),
+        camera_zoom: f32,
+    ) -> Viewport {
+        self.insert_viewport(ViewportCamera {
+            rect,
+            camera_pan,
+            camera_zoom,
+            draw_orders: None,
+            split: None,
+        })
+    }
+
+    /// Split the screen in equal slices side by side or above each other, each drawn by a
+    /// viewport starting with the camera of the main view.
+    ///
+    /// The main view isn't drawn anymore until the split screen ends, its lights aren't visible
+    /// in the viewports. The slices are ordered from the left or the top.
+    pub fn split_screen(&mut self, count: usize, side_by_side: bool) -> Vec<Viewport> {
+        self.end_split_screen();
+
+        let camera_pan = self.camera_pan;
+        let camera_zoom = self.camera_zoom;
+        (0..count)
+            .map(|index| {
+                self.insert_viewport(ViewportCamera {
+                    rect: [0.0; 4],
+                    camera_pan,
+                    camera_zoom,
+                    draw_orders: None,
+                    split: Some((index, count, side_by_side)),
+                })
+            })
+            .collect()
+    }
+
+    /// Remove the viewports of the split screen, the main view is drawn again.
+    pub fn end_split_screen(&mut self) {
+        for viewport in self.viewports.iter_mut() {
+            if viewport.map_or(false, |viewport| viewport.split.is_some()) {
+                *viewport = None;
+            }
+        }
+    }
+
+    /// Whether the screen is split between viewports.
+    pub fn is_split_screen(&self) -> bool {
+        self.viewports
+            .iter()
+            .flatten()
+            .any(|viewport| viewport.split.is_some())
+    }
+
+    /// Only draw the meshes with a draw order between the lowest & highest in a viewport, `None`
+    /// draws all meshes.
+    pub fn set_viewport_draw_orders(
+        &mut self,
+        viewport: Viewport,
+        draw_orders: Option<(i32, i32)>,
+    ) {
+        if let Some(Some(camera)) = self.viewports.get_mut(viewport.0) {
+            camera.draw_orders = draw_orders;
+        }
+    }
+
+    /// Add a viewport in the slot of a removed one, or after all of them.
+    fn insert_viewport(&mut self, camera: ViewportCamera) -> Viewport {
+        // Reuse the slot of a removed viewport
+        match self.viewports.iter().position(Option::is_none) {
+            Some(index) => {
+                self.viewports[index] = Some(camera);
+
+                Viewport(index)
+            }
+            None => {
+                self.viewports.push(Some(camera));
+
+                Viewport(self.viewports.len() - 1)
+            }
+        }
+    }
+
+    /// Move the camera of a viewport.
+    pub fn set_viewport_camera(
+        &mut self,
+        viewport: Viewport,
+        camera_pan: (f32, f32),
+        camera_zoom: f32,
+    ) {
+        if let Some(Some(camera)) = self.viewports.get_mut(viewport.0) {
+            camera.camera_pan = camera_pan;
+            camera.camera_zoom = camera_zoom;
+        }
+    }
+
+    /// Move the rectangle on the screen a viewport is drawn to, viewports of a split screen
+    /// ignore it.
+    pub fn set_viewport_rect(&mut self, viewport: Viewport, rect: [f32; 4]) {
+        if let Some(Some(camera)) = self.viewports.get_mut(viewport.0) {
+            camera.rect = rect;
+        }
+    }
+
+    /// Stop drawing a viewport.
+    ///
+    /// Returns `false` if the viewport was already removed.
+    pub fn remove_viewport(&mut self, viewport: Viewport) -> bool {
+        match self.viewports.get_mut(viewport.0) {
+            Some(camera) => camera.take().is_some(),
+            None => false,
+        }
+    }
+
+    /// Draw the world a second time from a script, at a rectangle in pixels on the screen from
+    /// the top left with a camera at a position & zoom.
+    ///
+    /// ```gamelisp
+    /// (let minimap (create-viewport 10 10 200 150 0 0 0.1))
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    fn add_viewport_script(
+        &mut self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        camera_x: f32,
+        camera_y: f32,
+        camera_zoom: f32,
+    ) -> GResult<Viewport> {
+        if width <= 0.0 || height <= 0.0 {
+            bail!("viewport of {}x{} pixels is empty", width, height);
+        }
+        if camera_zoom <= 0.0 {
+            bail!("viewport zoom {} must be positive", camera_zoom);
+        }
+
+        Ok(self.add_viewport([x, y, width, height], (camera_x, camera_y), camera_zoom))
+    }
+
+    /// Move the camera of a viewport from a script.
+    ///
+    /// ```gamelisp
+    /// (set-viewport-camera minimap (.x player) (.y player) 0.1)
+    /// ```
+    fn set_viewport_camera_script(
+        &mut self,
+        viewport: &Viewport,
+        camera_x: f32,
+        camera_y: f32,
+        camera_zoom: f32,
+    ) {
+        self.set_viewport_camera(*viewport, (camera_x, camera_y), camera_zoom);
+    }
+
+    /// Stop drawing a viewport from a script.
+    fn remove_viewport_script(&mut self, viewport: &Viewport) -> bool {
+        self.remove_viewport(*viewport)
+    }
+
+    /// Split the screen between viewports from a script, `'side-by-side` or `'stacked`.
+    ///
+    /// ```gamelisp
+    /// (let (left right) (split-screen 2 'side-by-side))
+    /// (set-viewport-camera right (.x player-2) (.y player-2) 1.0)
+    /// ```
+    fn split_screen_script(&mut self, count: usize, layout: Option<Sym>) -> GResult<Vec<Viewport>> {
+        if count == 0 {
+            bail!("the screen can't be split in zero viewports");
+        }
+        let side_by_side = match layout.as_ref().map(|layout| layout.name()).as_deref() {
+            None | Some("side-by-side") => true,
+            Some("stacked") => false,
+            Some(layout) => bail!(
+                "unknown split screen layout {}, must be side-by-side or stacked",
+                layout
+            ),
+        };
+
+        Ok(self.split_screen(count, side_by_side))
+    }
+
+    /// Only draw the meshes with a draw order between the lowest & highest in a viewport from a
+    /// script, without them all meshes are drawn.
+    ///
+    /// ```gamelisp
+    /// (set-viewport-draw-orders minimap 0 10)
+    /// ```
+    fn set_viewport_draw_orders_script(
+        &mut self,
+        viewport: &Viewport,
+        min: Option<i32>,
+        max: Option<i32>,
+    ) -> GResult<()> {
+        let draw_orders = match (min, max) {
+            (Some(min), Some(max)) if min <= max => Some((min, max)),
+            (Some(min), Some(max)) => bail!("lowest draw order {} is above {}", min, max),
+            (None, None) => None,
+            _ => bail!("set-viewport-draw-orders needs both a lowest & highest draw order"),
+        };
+        self.set_viewport_draw_orders(*viewport, draw_orders);
+
+        Ok(())
+    }
+
+    /// Clip meshes uploaded from now on to a rectangle, until it's popped again.
+    ///
+    /// Nested rectangles are intersected with the ones pushed before them.
+    pub fn push_clip_rect(&mut self, clip: ClipRect) {
+        self.clip_stack.push(clip);
+    }
+
+    /// Stop clipping meshes uploaded from now on to the last pushed rectangle.
+    ///
+    /// Returns `false` when no rectangle is pushed.
+    pub fn pop_clip_rect(&mut self) -> bool {
+        self.clip_stack.pop().is_some()
+    }
+
+    /// Clip the meshes uploaded afterwards from a script, until `pop-clip-rect` is called.
+    ///
+    /// The rectangle is in pixels on the screen from the top left, or in world units moving
+    /// along with the camera when the last argument is `'world`.
+    ///
+    /// ```gamelisp
+    /// (push-clip-rect 20 20 300 200)
+    /// (let list-mesh (create-text list-block))
+    /// (pop-clip-rect)
+    /// ```
+    fn push_clip_rect_script(
+        &mut self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        space: Option<Sym>,
+    ) -> GResult<()> {
+        let rect = [x, y, width, height];
+        let clip = match space.as_ref().map(|space| space.name()).as_deref() {
+            None | Some("screen") => ClipRect::Screen(rect),
+            Some("world") => ClipRect::World(rect),
+            Some(space) => bail!("unknown clip space {}, must be screen or world", space),
+        };
+        self.push_clip_rect(clip);
+
+        Ok(())
+    }
+
+    /// Stop clipping the meshes uploaded afterwards to the last pushed rectangle from a script.
+    fn pop_clip_rect_script(&mut self) -> GResult<()> {
+        if !self.pop_clip_rect() {
+            bail!("pop-clip-rect called without a pushed clip rectangle");
+        }
+
+        Ok(())
+    }
+
+    /// The size of the screen in pixels.
+    ///
+    /// Like all pixel positions & sizes of the API these are logical pixels, on high-DPI screens
+    /// every pixel covers multiple physical pixels.
+    pub fn screen_size(&self) -> (f32, f32) {
+        (
+            self.screen_size.0 / self.dpi_scale,
+            self.screen_size.1 / self.dpi_scale,
+        )
+    }
+
+    /// How many physical pixels of the screen a pixel of the API is, `1.0` unless the game is
+    /// started with `high_dpi` on a high-DPI screen.
+    pub fn dpi_scale(&self) -> f32 {
+        self.dpi_scale
+    }
+
+    /// Set how many physical pixels of the screen a pixel of the API is.
+    ///
+    /// Returns whether the scale changed, for example when the window moved to a monitor with
+    /// another pixel density.
+    pub fn set_dpi_scale(&mut self, dpi_scale: f32) -> bool {
+        if dpi_scale <= 0.0 || dpi_scale == self.dpi_scale {
+            return false;
+        }
+
+        self.dpi_scale = dpi_scale;
+
+        true
+    }
+
+    /// How many world units a single pixel on the screen is.
+    pub fn world_units_per_pixel(&self) -> f32 {
+        2.0 * self.dpi_scale / self.view_zoom()
+    }
+
+    /// Find the topmost instance under a pixel position on the screen.
+    pub fn pick_instance(&self, x: f32, y: f32) -> Option<InstanceRef> {
+        let (screen_x, screen_y) = self.screen_to_world(x, y);
+
+        let mut picked: Option<(f32, InstanceRef)> = None;
+        for mesh in self.sorted_draw_calls() {
+            let dc = &self.draw_calls[mesh];
+            for (index, instance) in dc.instances.iter().enumerate() {
+                let depth = instance.position[2];
+                // Instances with a lower depth are rendered on top, on equal depth the last one
+                // Without a depth buffer the last one drawn is always on top
+                if let Some((picked_depth, _)) = picked {
+                    if self.depth_buffer && depth > picked_depth {
+                        continue;
+                    }
+                }
+
+                // Undo the transformations done in the vertex shader
+                let local = instance.to_local(
+                    screen_x - self.camera_pan.0 * depth,
+                    screen_y - self.camera_pan.1 * depth,
+                );
+
+                if local.map_or(false, |(local_x, local_y)| {
+                    dc.contains(local_x, local_y, instance.panel)
+                }) {
+                    picked = Some((
+                        depth,
+                        InstanceRef {
+                            mesh,
+                            id: dc.instance_ids[index],
+                        },
+                    ));
+                }
+            }
+        }
+
+        picked.map(|(_, instance)| instance)
+    }
+
+    /// Find where a ray from a world position first hits the triangles of an instance.
+    ///
+    /// The ray ends after the length of the direction. Instances containing the start of the ray
+    /// are ignored, so a ray cast from within an instance doesn't hit it.
+    pub fn raycast(&self, origin: (f32, f32), direction: (f32, f32)) -> Option<RaycastHit> {
+        self.cast_ray(origin, direction, &[origin])
+    }
+
+    /// Whether no instance is between two world positions.
+    ///
+    /// Instances containing either position don't block the line of sight.
+    pub fn has_line_of_sight(&self, from: (f32, f32), to: (f32, f32)) -> bool {
+        let direction = (to.0 - from.0, to.1 - from.1);
+
+        self.cast_ray(from, direction, &[from, to]).is_none()
+    }
+
+    /// Find the closest hit of a ray, ignoring the instances containing any of the points.
+    fn cast_ray(
+        &self,
+        origin: (f32, f32),
+        direction: (f32, f32),
+        ignored: &[(f32, f32)],
+    ) -> Option<RaycastHit> {
+        let length = direction.0.hypot(direction.1);
+        if length == 0.0 {
+            return None;
+        }
+
+        let mut closest: Option<(f32, (f32, f32), InstanceRef)> = None;
+        for (mesh, dc) in self.draw_calls.iter().enumerate() {
+            // Masks are never visible so they don't block anything
+            if dc.is_mask || dc.indices.is_empty() {
+                continue;
+            }
+
+            for (index, instance) in dc.instances.iter().enumerate() {
+                let max_t = closest.map_or(1.0, |(t, _, _)| t);
+                if !ray_hits_box(origin, direction, dc.world_bounds(instance), max_t) {
+                    continue;
+                }
+                if ignored.iter().any(|(x, y)| {
+                    instance
+                        .to_local(*x, *y)
+                        .map_or(false, |(local_x, local_y)| {
+                            dc.contains(local_x, local_y, instance.panel)
+                        })
+                }) {
+                    continue;
+                }
+
+                if let Some((t, normal)) = dc.raycast(instance, origin, direction, max_t) {
+                    closest = Some((
+                        t,
+                        normal,
+                        InstanceRef {
+                            mesh,
+                            id: dc.instance_ids[index],
+                        },
+                    ));
+                }
+            }
+        }
+
+        closest.map(|(t, normal, instance)| RaycastHit {
+            instance,
+            point: (origin.0 + direction.0 * t, origin.1 + direction.1 * t),
+            normal,
+            distance: t * length,
+        })
+    }
+
+    /// Cast a ray from a script, returning the hit instance, the position & the normal.
+    ///
+    /// ```gamelisp
+    /// (let hit (raycast (.x gun) (.y gun) 500 0))
+    /// (when hit
+    ///   (let (target x y nx ny) hit)
+    ///   (spawn-sparks x y nx ny))
+    /// ```
+    fn raycast_script(
+        &self,
+        x: f32,
+        y: f32,
+        dx: f32,
+        dy: f32,
+    ) -> Option<(InstanceRef, f32, f32, f32, f32)> {
+        self.raycast((x, y), (dx, dy)).map(|hit| {
+            (
+                hit.instance,
+                hit.point.0,
+                hit.point.1,
+                hit.normal.0,
+                hit.normal.1,
+            )
+        })
+    }
+
+    /// Whether no instance is between two world positions from a script.
+    fn has_line_of_sight_script(&self, x1: f32, y1: f32, x2: f32, y2: f32) -> bool {
+        self.has_line_of_sight((x1, y1), (x2, y2))
+    }
+
+    /// The indices of the draw calls sorted by their draw order.
+    fn sorted_draw_calls(&self) -> Vec<usize> {
+        let mut draw_calls: Vec<usize> = (0..self.draw_calls.len())
+            .filter(|index| !self.draw_calls[*index].unloaded)
+            .collect();
+        // The sort is stable so meshes with the same draw order keep the upload order
+        draw_calls.sort_by_key(|index| self.draw_calls[*index].draw_order);
+
+        draw_calls
+    }
+
+    /// Set the order in which a mesh is drawn relative to other meshes, lower is drawn first.
+    ///
+    /// Meshes with the same draw order are drawn in the order they are uploaded.
+    pub fn set_draw_order(&mut self, mesh: Mesh, draw_order: i32) {
+        self.draw_calls[mesh.0].draw_order = draw_order;
+        self.draw_order_changed = true;
+    }
+
+    /// Set the draw order of a mesh from a script.
+    ///
+    /// ```gamelisp
+    /// (set-draw-order background -10)
+    /// ```
+    fn set_draw_order_script(&mut self, mesh: &Mesh, draw_order: i32) {
+        self.set_draw_order(*mesh, draw_order);
+    }
+
+    /// Set the order in which an instance is drawn relative to other instances of the same mesh.
+    ///
+    /// Returns `false` if the instance is removed.
+    pub fn set_instance_draw_order(&mut self, instance: InstanceRef, draw_order: i32) -> bool {
+        let draw_call = &mut self.draw_calls[instance.mesh];
+        let index = match draw_call.instance_lookup.get(&instance.id) {
+            Some(index) => *index,
+            None => return false,
+        };
+
+        if draw_call.instance_orders[index] != draw_order {
+            draw_call.instance_orders[index] = draw_order;
+            draw_call.instances_unsorted = true;
+        }
+
+        true
+    }
+
+    /// The order in which an instance is drawn relative to other instances of the same mesh.
+    pub fn instance_draw_order(&self, instance: InstanceRef) -> Option<i32> {
+        let draw_call = &self.draw_calls[instance.mesh];
+
+        draw_call
+            .instance_lookup
+            .get(&instance.id)
+            .map(|index| draw_call.instance_orders[*index])
+    }
+
+    /// The geometry that will be drawn on top of everything during the next frame.
+    pub fn debug_geometry(&mut self) -> &mut VertexBuffers<Vertex, u16> {
+        &mut self.debug_geometry
+    }
+
+    /// Draw the debug geometry with a single dynamic draw call and clear it.
+    fn render_debug_geometry(&mut self, ctx: &mut Context, pipeline: Pipeline) {
+        if self.debug_geometry.indices.is_empty() {
+            return;
+        }
+
+        let pan = self.view_pan();
+
+        let bindings = self.debug_bindings.get_or_insert_with(|| {
+            let vertex_buffer = Buffer::stream(
+                ctx,
+                BufferType::VertexBuffer,
+                MAX_DEBUG_VERTICES * mem::size_of::<Vertex>(),
+            );
+            let index_buffer = Buffer::stream(
+                ctx,
+                BufferType::IndexBuffer,
+                MAX_DEBUG_INDICES * mem::size_of::<u16>(),
+            );
+            let instance_buffer =
+                Buffer::stream(ctx, BufferType::VertexBuffer, mem::size_of::<Instance>());
+            instance_buffer.update(ctx, &[Instance::new(0.0, 0.0)]);
+
+            Bindings {
+                vertex_buffers: vec![vertex_buffer, instance_buffer],
+                index_buffer,
+                images: vec![],
+            }
+        });
+
+        // Drop the triangles that don't fit in the buffer
+        let vertex_count = self.debug_geometry.vertices.len().min(MAX_DEBUG_VERTICES);
+        let index_count = self.debug_geometry.indices.len().min(MAX_DEBUG_INDICES) / 3 * 3;
+        bindings.vertex_buffers[0].update(ctx, &self.debug_geometry.vertices[..vertex_count]);
+        bindings
+            .index_buffer
+            .update(ctx, &self.debug_geometry.indices[..index_count]);
+
+        let (width, height) = self.screen_size;
+        ctx.apply_pipeline(&pipeline);
+        ctx.apply_bindings(bindings);
+        ctx.apply_uniforms(&geom_shader::Uniforms {
+            zoom: (self.view_zoom() / width, self.view_zoom() / height),
+            pan,
+            pixel: self.world_units_per_pixel(),
+            palette: PaletteSwap::default(),
+        });
+        ctx.draw(0, index_count as i32, 1);
+        self.draw_call_count += 1;
+    }
+
+    /// The lights multiplied with the scene, filled by the lighting every frame.
+    pub fn light_map_mut(&mut self) -> &mut LightMap {
+        &mut self.light_map
+    }
+
+    /// The screen effects drawn on top of everything, filled by the effects every frame.
+    pub fn overlay_mut(&mut self) -> &mut ScreenOverlay {
+        &mut self.overlay
+    }
+
+    /// Compile a post-processing pass and add it to the end of the chain.
+    pub fn add_post_pass(
+        &mut self,
+        ctx: &mut Context,
+        descriptor: &PostPassDescriptor,
+    ) -> Result<()> {
+        self.post_processing.add_pass(ctx, descriptor)
+    }
+
+    /// Compile the builtin colorblind filter at the end of the chain, disabled until a script
+    /// selects it.
+    pub fn add_colorblind_pass(&mut self, ctx: &mut Context) -> Result<()> {
+        self.post_processing
+            .add_pass(ctx, &PostPassDescriptor::colorblind())?;
+
+        self.post_processing
+            .set_pass_enabled(postprocess::COLORBLIND_PASS, false)
+    }
+
+    /// Set whether a pass of the frame is drawn.
+    ///
+    /// The passes are `lights`, `world`, `lighting`, `ui`, `viewports`, `overlay`, `debug` &
+    /// `post-processing`. Passes reading a target that no other pass draws to anymore are skipped
+    /// as well, so disabling `lights` also skips `lighting`.
+    pub fn set_render_pass_enabled(&mut self, pass: &str, enabled: bool) -> Result<()> {
+        self.frame_graph.set_enabled(pass, enabled)
+    }
+
+    /// Draw a pass of the frame directly before another one, unless it needs the output of a
+    /// pass in between.
+    pub fn move_render_pass_before(&mut self, pass: &str, other: &str) -> Result<()> {
+        self.frame_graph.move_before(pass, other)
+    }
+
+    /// The names of the enabled passes of the frame in the order they're drawn.
+    pub fn render_passes(&mut self) -> Result<Vec<&'static str>> {
+        Ok(self
+            .frame_graph
+            .sorted()?
+            .into_iter()
+            .map(|pass| pass.name)
+            .collect())
+    }
+
+    /// Compile a shader that can be assigned to meshes.
+    pub fn add_shader(&mut self, ctx: &mut Context, descriptor: &ShaderDescriptor) -> Result<()> {
+        let (uniforms, layout) = UniformBlock::new(SHADER_BUILTIN_UNIFORMS, &descriptor.uniforms)?;
+
+        let shader = Shader::new(
+            ctx,
+            &descriptor.vertex,
+            &descriptor.fragment,
+            ShaderMeta {
+                images: &[],
+                uniforms: layout,
+            },
+        )
+        .map_err(|err| anyhow!("compiling shader \"{}\" failed: {:?}", descriptor.name, err))?;
+
+        self.shaders.push(CustomShader {
+            name: descriptor.name.clone(),
+            pipeline: mesh_pipeline(ctx, shader, self.depth_buffer),
+            uniforms,
+        });
+
+        Ok(())
+    }
+
+    /// Render a mesh with a custom shader, `None` uses the default shader.
+    pub fn set_mesh_shader(&mut self, mesh: Mesh, shader: Option<&str>) -> Result<()> {
+        let shader = match shader {
+            Some(name) => Some(
+                self.shaders
+                    .iter()
+                    .position(|shader| shader.name == name)
+                    .ok_or_else(|| anyhow!("shader \"{}\" doesn't exist", name))?,
+            ),
+            None => None,
+        };
+        self.draw_calls[mesh.0].shader = shader;
+
+        Ok(())
+    }
+
+    /// Set the shader of a mesh from a script.
+    fn set_mesh_shader_script(&mut self, mesh: &Mesh, shader: Option<&str>) -> GResult<()> {
+        match self.set_mesh_shader(*mesh, shader) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Only draw a mesh where the instances of another mesh are, `None` draws it everywhere.
+    ///
+    /// The mask mesh itself isn't drawn anymore while it masks a mesh. Masks are drawn to the
+    /// stencil buffer, so they have no effect with post-processing effects.
+    pub fn set_mesh_mask(&mut self, mesh: Mesh, mask: Option<Mesh>) -> Result<()> {
+        let mask = mask.map(|mask| mask.0);
+        if mask == Some(mesh.0) {
+            return Err(anyhow!("a mesh can't mask itself"));
+        }
+        if mask.map_or(false, |mask| self.draw_calls[mask].mask.is_some()) {
+            return Err(anyhow!("a masked mesh can't be a mask"));
+        }
+        if self.draw_calls[mesh.0].is_mask && mask.is_some() {
+            return Err(anyhow!("a mask can't be masked"));
+        }
+
+        let previous = std::mem::replace(&mut self.draw_calls[mesh.0].mask, mask);
+
+        // Meshes are only masks as long as they mask another mesh
+        for index in previous.into_iter().chain(mask) {
+            self.draw_calls[index].is_mask =
+                self.draw_calls.iter().any(|dc| dc.mask == Some(index));
+        }
+
+        Ok(())
+    }
+
+    /// Only draw a mesh inside the instances of another mesh from a script.
+    ///
+    /// ```gamelisp
+    /// (set-mask scenery porthole)
+    /// ```
+    fn set_mask_script(&mut self, mesh: &Mesh, mask: &Mesh) -> GResult<()> {
+        match self.set_mesh_mask(*mesh, Some(*mask)) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Draw a masked mesh everywhere again from a script.
+    fn clear_mask_script(&mut self, mesh: &Mesh) -> GResult<()> {
+        match self.set_mesh_mask(*mesh, None) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Blend the instances of a mesh with the scene as a group, `None` blends every triangle on
+    /// its own.
+    ///
+    /// Grouped meshes are drawn to an offscreen layer first, so they are drawn with the default
+    /// shaders and masks have no effect on them. Every instance that's its own group costs a draw
+    /// call of its own.
+    pub fn set_opacity_group(&mut self, mesh: Mesh, group: Option<OpacityGroup>) {
+        self.draw_calls[mesh.0].opacity_group = group.map(|group| match group {
+            OpacityGroup::Mesh(opacity) => OpacityGroup::Mesh(opacity.max(0.0).min(1.0)),
+            group => group,
+        });
+    }
+
+    /// How the instances of a mesh are blended with the scene, `None` if they aren't grouped.
+    pub fn opacity_group(&self, mesh: Mesh) -> Option<OpacityGroup> {
+        self.draw_calls[mesh.0].opacity_group
+    }
+
+    /// Draw all instances of a mesh opaque & blend them with the scene at once from a script.
+    ///
+    /// Overlapping instances don't show through each other, the whole mesh fades as one.
+    ///
+    /// ```gamelisp
+    /// (set-mesh-opacity ghost 0.5)
+    /// ```
+    fn set_mesh_opacity(&mut self, mesh: &Mesh, opacity: f32) {
+        self.set_opacity_group(*mesh, Some(OpacityGroup::Mesh(opacity)));
+    }
+
+    /// Draw every instance of a mesh opaque & blend it with the scene at its own alpha from a
+    /// script, or blend every triangle on its own again.
+    ///
+    /// ```gamelisp
+    /// (set-instance-opacity-groups character #t)
+    /// ```
+    fn set_instance_opacity_groups(&mut self, mesh: &Mesh, grouped: bool) {
+        self.set_opacity_group(*mesh, Some(OpacityGroup::Instances).filter(|_| grouped));
+    }
+
+    /// Blend every triangle of a mesh on its own again from a script.
+    fn clear_opacity_group(&mut self, mesh: &Mesh) {
+        self.set_opacity_group(*mesh, None);
+    }
+
+    /// Set a uniform of a custom shader from a script.
+    ///
+    /// ```gamelisp
+    /// (set-shader-uniform "wobble" "u_time" 1.5)
+    /// ```
+    fn set_shader_uniform(&mut self, shader: &str, uniform: &str, values: &[Val]) -> GResult<()> {
+        let values = uniform_values(values)?;
+        let shader = match self.shaders.iter_mut().find(|s| s.name == shader) {
+            Some(shader) => shader,
+            None => bail!("shader \"{}\" doesn't exist", shader),
+        };
+
+        match shader.uniforms.set(uniform, &values) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Set a uniform of a post-processing pass from a script.
+    ///
+    /// ```gamelisp
+    /// (set-post-uniform "vignette" "u_strength" 0.5)
+    /// (set-post-uniform "tint" "u_color" 1.0 0.5 0.0)
+    /// ```
+    fn set_post_uniform(&mut self, pass: &str, uniform: &str, values: &[Val]) -> GResult<()> {
+        let values = uniform_values(values)?;
+
+        match self.post_processing.set_uniform(pass, uniform, &values) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Set whether a pass of the frame is drawn from a script.
+    ///
+    /// ```gamelisp
+    /// (set-render-pass-enabled "ui" #f)
+    /// ```
+    fn set_render_pass_enabled_script(&mut self, pass: &str, enabled: bool) -> GResult<()> {
+        match self.set_render_pass_enabled(pass, enabled) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Draw a pass of the frame directly before another one from a script.
+    ///
+    /// ```gamelisp
+    /// (move-render-pass-before "overlay" "ui")
+    /// ```
+    fn move_render_pass_before_script(&mut self, pass: &str, other: &str) -> GResult<()> {
+        match self.move_render_pass_before(pass, other) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Get the names of the enabled passes of the frame in the order they're drawn from a script.
+    fn render_passes_script(&mut self) -> GResult<Vec<&'static str>> {
+        match self.render_passes() {
+            Ok(passes) => Ok(passes),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Select a fullscreen filter for a color vision deficiency from a script, `nil` disables it.
+    ///
+    /// The deficiency is `'protanopia`, `'deuteranopia` or `'tritanopia`. The filter simulates
+    /// how the game looks with the deficiency for testing, or with `'compensate` shifts the colors
+    /// that can't be told apart towards ones that can. Players can choose it in a config file:
+    ///
+    /// ```gamelisp
+    /// (let settings (load-config "settings.toml"))
+    /// (set-colorblind-filter [settings 'colorblind] 'compensate)
+    /// ```
+    fn set_colorblind_filter(&mut self, deficiency: Option<Sym>, mode: Option<Sym>) -> GResult<()> {
+        let deficiency = match deficiency.as_ref().map(Sym::name).as_deref() {
+            None => None,
+            Some("protanopia") => Some(1.0),
+            Some("deuteranopia") => Some(2.0),
+            Some("tritanopia") => Some(3.0),
+            Some(other) => bail!("unknown color vision deficiency {}", other),
+        };
+        let compensate = match mode.as_ref().map(Sym::name).as_deref() {
+            None | Some("simulate") => 0.0,
+            Some("compensate") => 1.0,
+            Some(other) => bail!("unknown colorblind filter mode {}", other),
+        };
+
+        // Nothing is drawn without a window
+        if self.pipeline.is_none() {
+            return Ok(());
+        }
+
+        let post_processing = &mut self.post_processing;
+        let pass = postprocess::COLORBLIND_PASS;
+        let result = post_processing
+            .set_pass_enabled(pass, deficiency.is_some())
+            .and_then(|_| {
+                post_processing.set_uniform(pass, "u_deficiency", &[deficiency.unwrap_or(0.0)])
+            })
+            .and_then(|_| post_processing.set_uniform(pass, "u_compensate", &[compensate]));
+        match result {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Add an instance of a mesh that will be rendered every frame.
+    ///
+    /// Instances of unloaded meshes aren't drawn.
+    pub fn add_instance(&mut self, mesh: Mesh, instance: Instance) -> InstanceRef {
+        let draw_call = &mut self.draw_calls[mesh.0];
+        let id = draw_call.next_instance_id;
+        draw_call.next_instance_id += 1;
+
+        draw_call
+            .instance_lookup
+            .insert(id, draw_call.instances.len());
+        draw_call.instance_ids.push(id);
+        draw_call.instances.push(instance);
+        draw_call.mark_dirty(draw_call.instances.len() - 1);
+
+        // New instances have the default draw order
+        let needs_sorting = draw_call
+            .instance_orders
+            .last()
+            .map_or(false, |order| *order > 0);
+        draw_call.instance_orders.push(0);
+        draw_call.instances_unsorted |= needs_sorting;
+
+        InstanceRef { mesh: mesh.0, id }
+    }
+
+    /// All instances of a mesh in the order they are drawn.
+    pub fn instances(&self, mesh: Mesh) -> Vec<InstanceRef> {
+        self.draw_calls[mesh.0]
+            .instance_ids
+            .iter()
+            .map(|id| InstanceRef {
+                mesh: mesh.0,
+                id: *id,
+            })
+            .collect()
+    }
+
+    /// Set how many instances of a mesh fit in its instance buffer before it has to grow.
+    ///
+    /// Only affects buffers that are created or grown afterwards.
+    pub fn set_instance_capacity(&mut self, mesh: Mesh, capacity: usize) {
+        self.draw_calls[mesh.0].instance_capacity = capacity.max(1).min(INSTANCE_CHUNK_SIZE);
+    }
+
+    /// Set the instance capacity of a mesh from a script.
+    ///
+    /// ```gamelisp
+    /// (set-instance-capacity bullet 1024)
+    /// ```
+    fn set_instance_capacity_script(&mut self, mesh: &Mesh, capacity: usize) {
+        self.set_instance_capacity(*mesh, capacity);
+    }
+
+    /// Add an instance without geometry that can be used as a parent for other instances.
+    pub fn add_transform_node(&mut self, x: f32, y: f32) -> InstanceRef {
+        self.add_instance(self.transform_node, Instance::new(x, y))
+    }
+
+    /// Make an instance a child of another instance.
+    ///
+    /// The current position, rotation, scale & skew of the child become relative to the parent.
+    pub fn set_parent(&mut self, child: InstanceRef, parent: InstanceRef) -> Result<()> {
+        if self.stored_instance(parent).is_none() {
+            return Err(anyhow!("parent instance is removed"));
+        }
+        if self.hierarchy.is_ancestor(child, parent) {
+            return Err(anyhow!(
+                "instance can't be parented to itself or its children"
+            ));
+        }
+
+        let local = *self
+            .instance(child)
+            .ok_or_else(|| anyhow!("child instance is removed"))?;
+        self.hierarchy.set_parent(child, parent, local);
+
+        Ok(())
+    }
+
+    /// Detach an instance from its parent, it keeps its current world transform.
+    pub fn clear_parent(&mut self, child: InstanceRef) {
+        self.hierarchy.clear_parent(child);
+    }
+
+    /// The parent of an instance.
+    pub fn parent(&self, child: InstanceRef) -> Option<InstanceRef> {
+        self.hierarchy.parent(child)
+    }
+
+    /// Add a transform node from a script.
+    ///
+    /// ```gamelisp
+    /// (let vehicle (create-node 0.0 0.0))
+    /// ```
+    fn add_transform_node_script(&mut self, x: f32, y: f32) -> InstanceRef {
+        self.add_transform_node(x, y)
+    }
+
+    /// Make an instance a child of another from a script.
+    ///
+    /// ```gamelisp
+    /// (set-parent turret tank)
+    /// ```
+    fn set_parent_script(&mut self, child: &InstanceRef, parent: &InstanceRef) -> GResult<()> {
+        match self.set_parent(*child, *parent) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Detach an instance from its parent from a script.
+    fn clear_parent_script(&mut self, child: &InstanceRef) {
+        self.clear_parent(*child);
+    }
+
+    /// Stop rendering an instance.
+    ///
+    /// Returns `false` if the instance was already removed.
+    pub fn remove_instance(&mut self, instance: InstanceRef) -> bool {
+        self.hierarchy.remove(instance);
+
+        let draw_call = &mut self.draw_calls[instance.mesh];
+        let index = match draw_call.instance_lookup.remove(&instance.id) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        // Move the last instance into the removed spot
+        draw_call.instances.swap_remove(index);
+        draw_call.instance_ids.swap_remove(index);
+        draw_call.instance_orders.swap_remove(index);
+        if let Some(moved_id) = draw_call.instance_ids.get(index) {
+            draw_call.instance_lookup.insert(*moved_id, index);
+            draw_call.mark_dirty(index);
+
+            // The moved instance might be out of order now
+            draw_call.instances_unsorted = true;
+        }
+
+        true
+    }
+
+    /// Get the data of an instance, relative to its parent if it has one.
+    pub fn instance(&self, instance: InstanceRef) -> Option<&Instance> {
+        match self.hierarchy.local(instance) {
+            Some(local) => Some(local),
+            None => self.stored_instance(instance),
+        }
+    }
+
+    /// Get the data of an instance for modifying it, relative to its parent if it has one.
+    pub fn instance_mut(&mut self, instance: InstanceRef) -> Option<&mut Instance> {
+        // The children might have to move along
+        self.hierarchy.mark_dirty();
+
+        match self.hierarchy.local_mut(instance) {
+            Some(local) => Some(local),
+            None => self.stored_instance_mut(instance),
+        }
+    }
+
+    /// The outline of the mesh of an instance in world coordinates as it was last rendered.
+    ///
+    /// Returns `None` when the instance doesn't exist or the outline of the mesh is unknown.
+    pub fn instance_outline(&self, instance: InstanceRef) -> Option<Vec<[f32; 2]>> {
+        let transform = self.stored_instance(instance)?;
+        let path = &self.draw_calls[instance.mesh].path;
+        if path.is_empty() {
+            return None;
+        }
+
+        Some(
+            path.iter()
+                .map(|[x, y]| transform.to_world(*x, *y))
+                .collect(),
+        )
+    }
+
+    /// Get the data of an instance as it's rendered.
+    fn stored_instance(&self, instance: InstanceRef) -> Option<&Instance> {
+        let draw_call = &self.draw_calls[instance.mesh];
+
+        draw_call
+            .instance_lookup
+            .get(&instance.id)
+            .map(|index| &draw_call.instances[*index])
+    }
+
+    /// Get the data of an instance as it's rendered for modifying it.
+    fn stored_instance_mut(&mut self, instance: InstanceRef) -> Option<&mut Instance> {
+        let draw_call = &mut self.draw_calls[instance.mesh];
+        let index = *draw_call.instance_lookup.get(&instance.id)?;
+
+        // The instance will probably be modified so it must be uploaded again
+        draw_call.mark_dirty(index);
+
+        Some(&mut draw_call.instances[index])
+    }
+
+    /// Render a segmented loading bar, `progress` must be between `0.0` and `1.0`.
+    pub fn render_loading_screen(&mut self, ctx: &mut Context, progress: f32) {
+        let blocks = (progress.max(0.0).min(1.0) * LOADING_BAR_SEGMENTS as f32).ceil() as usize;
+        let offset = (LOADING_BAR_SEGMENTS as f32 - 1.0) / 2.0;
+
+        let draw_call = &mut self.draw_calls[self.loading_bar.0];
+        draw_call.instances = (0..blocks)
+            .map(|index| Instance::new((index as f32 - offset) * LOADING_BAR_SEGMENT_SIZE, 0.0))
+            .collect();
+        draw_call.instance_orders = vec![0; blocks];
+        draw_call.mark_all_dirty();
+
+        self.render(ctx);
+    }
+
+    /// Remove the loading bar after loading is finished.
+    pub fn hide_loading_screen(&mut self) {
+        let draw_call = &mut self.draw_calls[self.loading_bar.0];
+        draw_call.instances.clear();
+        draw_call.instance_orders.clear();
+    }
+
+    /// Set the camera panning position.
+    pub fn set_camera_pos(&mut self, x: f32, y: f32) {
+        self.camera_pan.0 = x;
+        self.camera_pan.1 = y;
+    }
+
+    /// The camera position.
+    pub fn camera_pos(&self) -> (f32, f32) {
+        self.camera_pan
+    }
+
+    /// Move the view without moving the camera, for effects like a camera shake.
+    ///
+    /// Picking instances ignores the offset.
+    pub fn set_camera_offset(&mut self, x: f32, y: f32) {
+        self.camera_offset = (x, y);
+    }
+
+    /// The camera position with the offset, as it's used for drawing.
+    fn view_pan(&self) -> (f32, f32) {
+        (
+            self.camera_pan.0 + self.camera_offset.0,
+            self.camera_pan.1 + self.camera_offset.1,
+        )
+    }
+
+    /// The camera zooming.
+    pub fn camera_zoom(&self) -> f32 {
+        self.camera_zoom
+    }
+
+    /// Set the camera zooming.
+    pub fn set_camera_zoom(&mut self, zoom: f32) {
+        self.camera_zoom = zoom;
+    }
+
+    /// Keep an area of the world visible regardless of the size & pixel density of the window.
+    ///
+    /// With a design resolution of `(320.0, 180.0)` a camera zoom of `1.0` shows at least 320 by
+    /// 180 world units around the camera, the shape of the window only decides how much more is
+    /// visible at the sides. The camera zoom is relative to the design resolution afterwards,
+    /// with `None` a world unit is half a pixel at zoom `1.0` again.
+    pub fn set_design_resolution(&mut self, resolution: Option<(f32, f32)>) -> Result<()> {
+        if let Some((width, height)) = resolution {
+            if width <= 0.0 || height <= 0.0 {
+                return Err(anyhow!(
+                    "design resolution {}x{} must be positive",
+                    width,
+                    height
+                ));
+            }
+        }
+
+        self.design_resolution = resolution;
+
+        Ok(())
+    }
+
+    /// The area in world units that's always visible at zoom `1.0`, `None` if it isn't set.
+    pub fn design_resolution(&self) -> Option<(f32, f32)> {
+        self.design_resolution
+    }
+
+    /// What the zoom of the cameras is multiplied with to fit the design resolution on the
+    /// screen.
+    fn design_scale(&self) -> f32 {
+        match self.design_resolution {
+            Some((design_width, design_height)) => {
+                let (width, height) = self.screen_size;
+
+                2.0 * (width / design_width).min(height / design_height)
+            }
+            // A world unit stays the same size on high-DPI screens
+            None => self.dpi_scale,
+        }
+    }
+
+    /// The zoom of the main camera as it's drawn, scaled to the design resolution.
+    ///
+    /// A world unit covers half of this in physical pixels.
+    pub(crate) fn view_zoom(&self) -> f32 {
+        self.camera_zoom * self.design_scale()
+    }
+
+    /// Set the design resolution from a script.
+    ///
+    /// ```gamelisp
+    /// (set-design-resolution 320 180)
+    /// ```
+    fn set_design_resolution_script(&mut self, width: f32, height: f32) -> GResult<()> {
+        match self.set_design_resolution(Some((width, height))) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+
+    /// Remove the design resolution from a script, the zoom is relative to the pixels again.
+    fn clear_design_resolution(&mut self) {
+        self.design_resolution = None;
+    }
+
+    /// Add an instance of a mesh at a position from a script.
+    fn add_instance_at(&mut self, mesh: &Mesh, x: f32, y: f32) -> GResult<InstanceRef> {
+        if !self.is_mesh_loaded(*mesh) {
+            bail!("can't add an instance of an unloaded mesh");
+        }
+
+        Ok(self.add_instance(*mesh, Instance::new(x, y)))
+    }
+
+    /// Create a nine-slice panel from a mesh from a script.
+    ///
+    /// ```gamelisp
+    /// (let button (create-nine-slice (asset-mesh "button") 8 8 8 8))
+    /// (let instance (add-instance button 100 100))
+    /// (.set-panel-size instance 200 40)
+    /// ```
+    fn create_nine_slice(
+        &mut self,
+        mesh: &Mesh,
+        left: f32,
+        top: f32,
+        right: f32,
+        bottom: f32,
+    ) -> GResult<Mesh> {
+        match self.upload_nine_slice(*mesh, [left, top, right, bottom]) {
+            Ok(mesh) => Ok(mesh),
+            Err(err) => bail!("creating nine-slice failed: {}", err),
+        }
+    }
+
+    /// Remove an instance from a script.
+    fn remove_instance_ref(&mut self, instance: &InstanceRef) -> bool {
+        self.remove_instance(*instance)
+    }
+
+    /// Bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::bind_rfn("set_camera_pos", rfn!(Self::set_camera_pos))?;
+            glsp::bind_rfn("set_camera_zoom", rfn!(Self::set_camera_zoom))?;
+            glsp::bind_rfn(
+                "set-design-resolution",
+                rfn!(Self::set_design_resolution_script),
+            )?;
+            glsp::bind_rfn(
+                "clear-design-resolution",
+                rfn!(Self::clear_design_resolution),
+            )?;
+            glsp::bind_rfn("design-resolution", rfn!(Self::design_resolution))?;
+            glsp::bind_rfn("screen-to-world", rfn!(Self::screen_to_world))?;
+            glsp::bind_rfn("world-to-screen", rfn!(Self::world_to_screen))?;
+            glsp::bind_rfn("screen-size", rfn!(Self::screen_size))?;
+            glsp::bind_rfn("world-units-per-pixel", rfn!(Self::world_units_per_pixel))?;
+            glsp::bind_rfn("dpi-scale", rfn!(Self::dpi_scale))?;
+            glsp::bind_rfn("add-instance", rfn!(Self::add_instance_at))?;
+            glsp::bind_rfn("remove-instance", rfn!(Self::remove_instance_ref))?;
+            glsp::bind_rfn("create-nine-slice", rfn!(Self::create_nine_slice))?;
+            glsp::bind_rfn(
+                "set-instance-capacity",
+                rfn!(Self::set_instance_capacity_script),
+            )?;
+            glsp::bind_rfn("pick-instance", rfn!(Self::pick_instance))?;
+            glsp::bind_rfn("raycast", rfn!(Self::raycast_script))?;
+            glsp::bind_rfn("line-of-sight?", rfn!(Self::has_line_of_sight_script))?;
+            glsp::bind_rfn("set-culling", rfn!(Self::set_culling))?;
+            glsp::bind_rfn("culling?", rfn!(Self::is_culling))?;
+            glsp::bind_rfn("culled-instance-count", rfn!(Self::culled_instance_count))?;
+            glsp::bind_rfn("push-clip-rect", rfn!(Self::push_clip_rect_script))?;
+            glsp::bind_rfn("pop-clip-rect", rfn!(Self::pop_clip_rect_script))?;
+            glsp::bind_rfn("create-viewport", rfn!(Self::add_viewport_script))?;
+            glsp::bind_rfn(
+                "set-viewport-camera",
+                rfn!(Self::set_viewport_camera_script),
+            )?;
+            glsp::bind_rfn("remove-viewport", rfn!(Self::remove_viewport_script))?;
+            glsp::bind_rfn("split-screen", rfn!(Self::split_screen_script))?;
+            glsp::bind_rfn("end-split-screen", rfn!(Self::end_split_screen))?;
+            glsp::bind_rfn("split-screen?", rfn!(Self::is_split_screen))?;
+            glsp::bind_rfn(
+                "set-viewport-draw-orders",
+                rfn!(Self::set_viewport_draw_orders_script),
+            )?;
+            glsp::bind_rfn("set-mask", rfn!(Self::set_mask_script))?;
+            glsp::bind_rfn("clear-mask", rfn!(Self::clear_mask_script))?;
+            glsp::bind_rfn("set-mesh-opacity", rfn!(Self::set_mesh_opacity))?;
+            glsp::bind_rfn(
+                "set-instance-opacity-groups",
+                rfn!(Self::set_instance_opacity_groups),
+            )?;
+            glsp::bind_rfn("clear-opacity-group", rfn!(Self::clear_opacity_group))?;
+            glsp::bind_rfn("unload-mesh", rfn!(Self::unload_mesh_script))?;
+            glsp::bind_rfn("mesh-loaded?", rfn!(Self::is_mesh_loaded_script))?;
+            glsp::bind_rfn("mesh-memory", rfn!(Self::mesh_memory_script))?;
+            glsp::bind_rfn("gpu-memory", rfn!(Self::gpu_memory_script))?;
+            glsp::bind_rfn("atlas-page-count", rfn!(Self::atlas_page_count))?;
+            glsp::bind_rfn("atlas-utilization", rfn!(Self::atlas_utilization))?;
+            glsp::bind_rfn("create-node", rfn!(Self::add_transform_node_script))?;
+            glsp::bind_rfn("set-parent", rfn!(Self::set_parent_script))?;
+            glsp::bind_rfn("clear-parent", rfn!(Self::clear_parent_script))?;
+            glsp::bind_rfn("set-draw-order", rfn!(Self::set_draw_order_script))?;
+            glsp::bind_rfn("set-post-uniform", rfn!(Self::set_post_uniform))?;
+            glsp::bind_rfn("set-colorblind-filter", rfn!(Self::set_colorblind_filter))?;
+            glsp::bind_rfn(
+                "set-render-pass-enabled",
+                rfn!(Self::set_render_pass_enabled_script),
+            )?;
+            glsp::bind_rfn(
+                "move-render-pass-before",
+                rfn!(Self::move_render_pass_before_script),
+            )?;
+            glsp::bind_rfn("render-passes", rfn!(Self::render_passes_script))?;
+            glsp::bind_rfn("set-mesh-shader", rfn!(Self::set_mesh_shader_script))?;
+            glsp::bind_rfn("set-shader-uniform", rfn!(Self::set_shader_uniform))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// A custom shader that still has to be compiled.
+#[derive(Debug, Clone)]
+pub struct ShaderDescriptor {
+    /// The name used by scripts to assign the shader.
+    pub name: String,
+    /// The GLSL source of the vertex shader.
+    pub vertex: String,
+    /// The GLSL source of the fragment shader.
+    pub fragment: String,
+    /// The uniforms of the shader besides the builtin ones.
+    pub uniforms: Vec<(String, UniformType)>,
+}
+
+/// A compiled custom shader for meshes.
+struct CustomShader {
+    /// The name used by scripts to assign the shader.
+    name: String,
+    /// The pipeline with the same vertex layout as the default one.
+    pipeline: Pipeline,
+    /// The values of the builtin & custom uniforms.
+    uniforms: UniformBlock,
+}
+
+/// Create a pipeline with the vertex layout of the meshes and their instances.
+fn mesh_pipeline(ctx: &mut Context, shader: Shader, depth_buffer: bool) -> Pipeline {
+    instanced_pipeline(ctx, shader, mesh_pipeline_params(depth_buffer))
+}
+
+/// Convert a world position at the default Z to a pixel position on the screen.
+fn world_to_screen(x: f32, y: f32, screen_size: (f32, f32), camera_zoom: f32) -> (f32, f32) {
+    let (width, height) = screen_size;
+
+    (
+        (x * camera_zoom + width) / 2.0,
+        (y * camera_zoom + height) / 2.0,
+    )
+}
+
+/// Whether a ray crosses a box `[min_x, min_y, max_x, max_y]` before the fraction `max_t` of
+/// its direction.
+fn ray_hits_box(origin: (f32, f32), direction: (f32, f32), bounds: [f32; 4], max_t: f32) -> bool {
+    let [min_x, min_y, max_x, max_y] = bounds;
+    let (mut near, mut far) = (0.0f32, max_t);
+    for (start, delta, min, max) in [
+        (origin.0, direction.0, min_x, max_x),
+        (origin.1, direction.1, min_y, max_y),
+    ]
+    .iter()
+    {
+        // Parallel rays only cross when they start between the sides
+        if *delta == 0.0 {
+            if start < min || start > max {
+                return false;
+            }
+            continue;
+        }
+
+        let (t0, t1) = ((min - start) / delta, (max - start) / delta);
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+        if near > far {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// The fraction of the direction at which a ray crosses the line segment from `p` to `q`.
+fn ray_segment_intersection(
+    origin: (f32, f32),
+    direction: (f32, f32),
+    p: [f32; 2],
+    q: [f32; 2],
+) -> Option<f32> {
+    let (edge_x, edge_y) = (q[0] - p[0], q[1] - p[1]);
+    // Parallel lines never cross
+    let denominator = direction.0 * edge_y - direction.1 * edge_x;
+    if denominator == 0.0 {
+        return None;
+    }
+
+    let (offset_x, offset_y) = (p[0] - origin.0, p[1] - origin.1);
+    let t = (offset_x * edge_y - offset_y * edge_x) / denominator;
+    let u = (offset_x * direction.1 - offset_y * direction.0) / denominator;
+
+    if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// The intersection of clip rectangles as a scissor rectangle `[x, y, width, height]` with the
+/// origin in the bottom left like OpenGL expects.
+///
+/// The screen rectangles are in logical pixels, the result is in physical pixels.
+fn scissor_rect(
+    clips: &[ClipRect],
+    screen_size: (f32, f32),
+    dpi_scale: f32,
+    camera_zoom: f32,
+) -> [i32; 4] {
+    let (width, height) = screen_size;
+
+    let [min_x, min_y, max_x, max_y] = clips.iter().fold(
+        [0.0, 0.0, width, height],
+        |[min_x, min_y, max_x, max_y], clip| {
+            let [left, top, right, bottom] = match *clip {
+                ClipRect::Screen([x, y, clip_width, clip_height]) => [
+                    x * dpi_scale,
+                    y * dpi_scale,
+                    (x + clip_width) * dpi_scale,
+                    (y + clip_height) * dpi_scale,
+                ],
+                ClipRect::World([x, y, clip_width, clip_height]) => {
+                    let (left, top) = world_to_screen(x, y, screen_size, camera_zoom);
+                    let (right, bottom) =
+                        world_to_screen(x + clip_width, y + clip_height, screen_size, camera_zoom);
+
+                    [left, top, right, bottom]
+                }
+            };
+
+            [
+                min_x.max(left),
+                min_y.max(top),
+                max_x.min(right),
+                max_y.min(bottom),
+            ]
+        },
+    );
+
+    let (x, y) = (min_x.round() as i32, min_y.round() as i32);
+    let clip_width = (max_x.round() as i32 - x).max(0);
+    let clip_height = (max_y.round() as i32 - y).max(0);
+
+    [x, height as i32 - y - clip_height, clip_width, clip_height]
+}
+
+/// The options for parsing SVGs, with the fonts the text is converted to paths with.
+#[cfg(feature = "svg")]
+fn svg_options(fonts: &[Vec<u8>]) -> usvg::Options {
+    let mut options = usvg::Options::default();
+    for font in fonts {
+        options.fontdb.load_font_data(font.clone());
+    }
+
+    options
+}
+
+/// Why a node of a SVG isn't drawn completely, `None` when it's supported.
+#[cfg(feature = "svg")]
+fn unsupported_node(node: &Node) -> Option<String> {
+    match *node.borrow() {
+        NodeKind::Path(ref svg_path) if svg_path.stroke.is_some() => Some(node_warning(
+            &svg_path.id,
+            "stroke isn't drawn, convert it to a path",
+        )),
+        NodeKind::Image(ref image) => Some(node_warning(&image.id, "embedded image isn't drawn")),
+        NodeKind::Group(ref group) if group.filter.is_some() => {
+            Some(node_warning(&group.id, "filter isn't applied"))
+        }
+        NodeKind::Group(ref group) if group.opacity.value() < 1.0 => {
+            Some(node_warning(&group.id, "group opacity isn't applied"))
+        }
+        _ => None,
+    }
+}
+
+/// A warning about a node of a SVG, starting with its ID when it has one.
+#[cfg(feature = "svg")]
+fn node_warning(id: &str, message: &str) -> String {
+    if id.is_empty() {
+        message.to_string()
+    } else {
+        format!("\"{}\": {}", id, message)
+    }
+}
+
+/// Blend the colors of meshes with their alpha.
+fn alpha_blend() -> BlendState {
+    BlendState::new(
+        Equation::Add,
+        BlendFactor::Value(BlendValue::SourceAlpha),
+        BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+    )
+}
+
+/// Compare the stencil buffer with the reference value of masks & what to do when it passes.
+fn stencil_state(test_func: CompareFunc, pass_op: StencilOp) -> StencilState {
+    let face = StencilFaceState {
+        fail_op: StencilOp::Keep,
+        depth_fail_op: StencilOp::Keep,
+        pass_op,
+        test_func,
+        test_ref: 1,
+        test_mask: 0xFF,
+        write_mask: 0xFF,
+    };
+
+    StencilState {
+        front: face,
+        back: face,
+    }
+}
+
+/// The depth settings of the pipelines drawing meshes.
+pub(crate) fn mesh_pipeline_params(depth_buffer: bool) -> PipelineParams {
+    let (depth_test, depth_write) = if depth_buffer {
+        (Comparison::LessOrEqual, true)
+    } else {
+        (Comparison::Always, false)
+    };
+
+    PipelineParams {
+        depth_test,
+        depth_write,
+        ..Default::default()
+    }
+}
+
+/// Create a pipeline for drawing the vertices of meshes with an instance buffer.
+pub(crate) fn instanced_pipeline(
+    ctx: &mut Context,
+    shader: Shader,
+    params: PipelineParams,
+) -> Pipeline {
+    mesh_vertex_pipeline(ctx, shader, params, VertexStep::PerInstance)
+}
+
+/// Create a pipeline for drawing merged meshes, with the instance of every vertex stored in the
+/// second buffer.
+fn merged_pipeline(ctx: &mut Context, shader: Shader, params: PipelineParams) -> Pipeline {
+    mesh_vertex_pipeline(ctx, shader, params, VertexStep::PerVertex)
+}
+
+/// Create a pipeline with the vertex layout of the meshes, where the instances in the second
+/// buffer step per instance or per vertex.
+fn mesh_vertex_pipeline(
+    ctx: &mut Context,
+    shader: Shader,
+    params: PipelineParams,
+    instance_step: VertexStep,
+) -> Pipeline {
+    Pipeline::with_params(
+        ctx,
+        &[
+            BufferLayout::default(),
+            BufferLayout {
+                step_func: instance_step,
+                ..Default::default()
+            },
+        ],
+        &[
+            VertexAttribute::with_buffer("a_pos", VertexFormat::Float2, 0),
+            VertexAttribute::with_buffer("a_color", VertexFormat::Float4, 0),
+            VertexAttribute::with_buffer("a_slice", VertexFormat::Float2, 0),
+            VertexAttribute::with_buffer("a_uv", VertexFormat::Float2, 0),
+            VertexAttribute::with_buffer("a_fringe", VertexFormat::Float2, 0),
+            VertexAttribute::with_buffer("a_inst_pos", VertexFormat::Float3, 1),
+            VertexAttribute::with_buffer("a_inst_rot", VertexFormat::Float1, 1),
+            VertexAttribute::with_buffer("a_inst_scale", VertexFormat::Float2, 1),
+            VertexAttribute::with_buffer("a_inst_skew", VertexFormat::Float2, 1),
+            VertexAttribute::with_buffer("a_inst_color", VertexFormat::Float4, 1),
+            VertexAttribute::with_buffer("a_inst_panel", VertexFormat::Float2, 1),
+        ],
+        shader,
+        params,
+    )
+}
+
+/// Convert the numbers passed by a script to uniform values.
+fn uniform_values(values: &[Val]) -> GResult<Vec<f32>> {
+    values
+        .iter()
+        .map(|val| match val {
+            Val::Flo(f) => Ok(*f),
+            Val::Int(i) => Ok(*i as f32),
+            _ => bail!("uniform values must be numbers, found {}", val),
+        })
+        .collect()
+}
+
+/// Copy the values in the order of the indices, on multiple threads with the `rayon` feature.
+fn reorder<T>(values: &[T], order: &[usize]) -> Vec<T>
+where
+    T: Copy + Send + Sync,
+{
+    #[cfg(feature = "rayon")]
+    let order = order.par_iter();
+    #[cfg(not(feature = "rayon"))]
+    let order = order.iter();
+
+    order.map(|index| values[*index]).collect()
+}
+
+/// A single uploaded mesh as a draw call.
+#[derive(Debug)]
+struct DrawCall {
+    /// Render vertices, build by lyon path.
+    vertices: Vec<Vertex>,
+    /// Render indices, build by lyon path.
+    indices: Vec<u16>,
+    /// The batch containing the vertices & indices.
+    batch: usize,
+    /// The position of the first index of this mesh in the index buffer of the batch.
+    first_index: usize,
+    /// The bounding box of the vertices as `[min_x, min_y, max_x, max_y]`.
+    bounds: [f32; 4],
+    /// The distance from the origin to the farthest vertex, instances are rotated around it.
+    radius: f32,
+    /// The outline the mesh was created from that instances can follow, empty if unknown.
+    path: Vec<[f32; 2]>,
+    /// The custom shader used to render this mesh, the default one if `None`.
+    shader: Option<usize>,
+    /// The atlas page the texture of this mesh is packed in, `None` if it isn't textured.
+    atlas_page: Option<usize>,
+    /// The rectangles the mesh is clipped to, empty when it's drawn on the whole screen.
+    clips: Vec<ClipRect>,
+    /// The mesh whose instances the mesh is only visible inside of, `None` if it isn't masked.
+    mask: Option<usize>,
+    /// Whether other meshes are masked by this mesh, it isn't drawn itself then.
+    is_mask: bool,
+    /// The coarser meshes drawn instead when the camera zoom is at most their zoom, from the
+    /// most detailed to the coarsest.
+    lods: Vec<(f32, usize)>,
+    /// When this mesh is drawn relative to other meshes, lower is drawn first.
+    draw_order: i32,
+    /// How the instances are blended with the scene when they're translucent, one triangle at a
+    /// time if `None`.
+    opacity_group: Option<OpacityGroup>,
+    /// The shared vertex & index buffer of the batch, assigned on render loop if empty.
+    buffers: Option<(Buffer, Buffer)>,
+    /// Whether the buffers are only used by this mesh because its geometry was replaced.
+    own_buffers: bool,
+    /// Whether the geometry was replaced and must be uploaded to the buffers of this mesh.
+    geometry_dirty: bool,
+    /// Render bindings for every chunk of instances, each with its own instance buffer.
+    chunk_bindings: Vec<Bindings>,
+    /// List of instances to render.
+    instances: Vec<Instance>,
+    /// How many instances are in the instance buffers, fewer than the instances when culled.
+    drawn_count: usize,
+    /// Whether only the visible instances were uploaded during the last render.
+    culled: bool,
+    /// Whether the instances of each chunk should be reuploaded to the GPU.
+    dirty_chunks: Vec<bool>,
+    /// How many instances the first instance buffer can hold, doubled when it's full.
+    instance_capacity: usize,
+    /// The identifier of each instance, in the same order as the instances.
+    instance_ids: Vec<u32>,
+    /// The draw order of each instance, in the same order as the instances.
+    instance_orders: Vec<i32>,
+    /// Whether the instances might not be sorted by their draw order anymore.
+    instances_unsorted: bool,
+    /// The position in the instances list for each instance identifier.
+    instance_lookup: HashMap<u32, usize>,
+    /// The identifier that will be given to the next instance.
+    next_instance_id: u32,
+    /// Whether the geometry & buffers were freed, the mesh isn't drawn anymore.
+    unloaded: bool,
+}
+
+impl DrawCall {
+    /// Create a draw call without instances, the bindings will be created on render.
+    fn new(vertices: Vec<Vertex>, indices: Vec<u16>, batch: usize, first_index: usize) -> Self {
+        let (bounds, radius) = Self::extent(&vertices);
+
+        Self {
+            bounds,
+            radius,
+            path: vec![],
+            shader: None,
+            atlas_page: None,
+            lods: vec![],
+            clips: vec![],
+            mask: None,
+            is_mask: false,
+            draw_order: 0,
+            opacity_group: None,
+            vertices,
+            indices,
+            batch,
+            first_index,
+            buffers: None,
+            own_buffers: false,
+            geometry_dirty: false,
+            chunk_bindings: vec![],
+            instances: vec![],
+            drawn_count: 0,
+            culled: false,
+            dirty_chunks: vec![],
+            instance_capacity: DEFAULT_INSTANCE_CAPACITY,
+            instance_ids: vec![],
+            instance_orders: vec![],
+            instances_unsorted: false,
+            instance_lookup: HashMap::new(),
+            next_instance_id: 0,
+            unloaded: false,
+        }
+    }
+
+    /// The bounding box of the vertices & the distance from the origin to the farthest one.
+    fn extent(vertices: &[Vertex]) -> ([f32; 4], f32) {
+        // Calculate the bounding box of all vertices
+        let bounds = vertices.iter().fold(
+            [f32::MAX, f32::MAX, f32::MIN, f32::MIN],
+            |[min_x, min_y, max_x, max_y], vertex| {
+                [
+                    min_x.min(vertex.pos[0]),
+                    min_y.min(vertex.pos[1]),
+                    max_x.max(vertex.pos[0]),
+                    max_y.max(vertex.pos[1]),
+                ]
+            },
+        );
+        let radius = vertices
+            .iter()
+            .map(|vertex| vertex.pos[0].hypot(vertex.pos[1]))
+            .fold(0.0, f32::max);
+
+        (bounds, radius)
+    }
+
+    /// Free the geometry & the buffers only used by this mesh, the instances must be removed
+    /// already.
+    fn unload(&mut self) {
+        if let Some((vertex_buffer, index_buffer)) = self.buffers.take() {
+            if self.own_buffers {
+                vertex_buffer.delete();
+                index_buffer.delete();
+            }
+        }
+        for bindings in self.chunk_bindings.drain(..) {
+            bindings.vertex_buffers[1].delete();
+        }
+
+        self.vertices = vec![];
+        self.indices = vec![];
+        self.path = vec![];
+        self.lods = vec![];
+        self.dirty_chunks = vec![];
+        self.drawn_count = 0;
+        self.own_buffers = false;
+        self.geometry_dirty = false;
+        self.unloaded = true;
+    }
+
+    /// The GPU memory used by the buffers of this mesh.
+    ///
+    /// Meshes sharing the buffers of a batch only count their own part of them.
+    fn memory(&self) -> MeshMemory {
+        let (vertex_bytes, index_bytes) = match self.buffers {
+            Some((vertex_buffer, index_buffer)) if self.own_buffers => {
+                (vertex_buffer.size(), index_buffer.size())
+            }
+            Some(_) => (
+                self.vertices.len() * mem::size_of::<Vertex>(),
+                self.indices.len() * mem::size_of::<u16>(),
+            ),
+            None => (0, 0),
+        };
+
+        MeshMemory {
+            vertex_bytes,
+            index_bytes,
+            instance_bytes: self.instance_bytes(),
+        }
+    }
+
+    /// The size of the instance buffers of all chunks in bytes.
+    fn instance_bytes(&self) -> usize {
+        self.chunk_bindings
+            .iter()
+            .map(|bindings| bindings.vertex_buffers[1].size())
+            .sum()
+    }
+
+    /// Replace the geometry, it's uploaded to the buffers of this mesh on the next render.
+    fn set_geometry(&mut self, vertices: Vec<Vertex>, indices: Vec<u16>) {
+        let (bounds, radius) = Self::extent(&vertices);
+        self.bounds = bounds;
+        self.radius = radius;
+        self.vertices = vertices;
+        self.indices = indices;
+        self.first_index = 0;
+        self.geometry_dirty = true;
+    }
+
+    /// Upload the replaced geometry to the buffers of this mesh.
+    ///
+    /// The buffers are updated in place when they're large enough, otherwise they're replaced by
+    /// buffers twice the size so growing meshes don't need new buffers every frame.
+    fn upload_geometry(&mut self, ctx: &mut Context) {
+        let fits = |buffer: &Buffer, len: usize, size: usize| buffer.size() >= len * size;
+        let reusable = match self.buffers {
+            Some((vertex_buffer, index_buffer)) if self.own_buffers => {
+                fits(
+                    &vertex_buffer,
+                    self.vertices.len(),
+                    mem::size_of::<Vertex>(),
+                ) && fits(&index_buffer, self.indices.len(), mem::size_of::<u16>())
+            }
+            _ => false,
+        };
+
+        if !reusable {
+            if let Some((vertex_buffer, index_buffer)) = self.buffers.take() {
+                // The buffers of a batch are shared with other meshes
+                if self.own_buffers {
+                    vertex_buffer.delete();
+                    index_buffer.delete();
+                }
+            }
+
+            let vertex_buffer = Buffer::stream(
+                ctx,
+                BufferType::VertexBuffer,
+                self.vertices.len().max(1).next_power_of_two() * mem::size_of::<Vertex>(),
+            );
+            let index_buffer = Buffer::stream(
+                ctx,
+                BufferType::IndexBuffer,
+                self.indices.len().max(1).next_power_of_two() * mem::size_of::<u16>(),
+            );
+            self.buffers = Some((vertex_buffer, index_buffer));
+            self.own_buffers = true;
+
+            // Point the existing chunks to the new buffers, keeping their instance buffers
+            for bindings in self.chunk_bindings.iter_mut() {
+                bindings.vertex_buffers[0] = vertex_buffer;
+                bindings.index_buffer = index_buffer;
+            }
+        }
+
+        if let Some((vertex_buffer, index_buffer)) = self.buffers {
+            vertex_buffer.update(ctx, &self.vertices);
+            index_buffer.update(ctx, &self.indices);
+        }
+
+        self.geometry_dirty = false;
+    }
+
+    /// Whether a point in the local space of the mesh is inside one of the triangles.
+    ///
+    /// The panel is the size added to a nine-slice by the instance.
+    fn contains(&self, x: f32, y: f32, panel: [f32; 2]) -> bool {
+        let [min_x, min_y, max_x, max_y] = self.bounds;
+        let (grow_x, grow_y) = (panel[0].abs() / 2.0, panel[1].abs() / 2.0);
+        if x < min_x - grow_x || x > max_x + grow_x || y < min_y - grow_y || y > max_y + grow_y {
+            return false;
+        }
+
+        let pos = |index: u16| {
+            let vertex = &self.vertices[index as usize];
+
+            [
+                vertex.pos[0] + vertex.slice[0] * panel[0],
+                vertex.pos[1] + vertex.slice[1] * panel[1],
+            ]
+        };
+        self.indices.chunks_exact(3).any(|triangle| {
+            let (a, b, c) = (pos(triangle[0]), pos(triangle[1]), pos(triangle[2]));
+
+            // The point is inside when it's on the same side of all edges
+            let side =
+                |p: [f32; 2], q: [f32; 2]| (q[0] - p[0]) * (y - p[1]) - (q[1] - p[1]) * (x - p[0]);
+            let (ab, bc, ca) = (side(a, b), side(b, c), side(c, a));
+
+            (ab >= 0.0 && bc >= 0.0 && ca >= 0.0) || (ab <= 0.0 && bc <= 0.0 && ca <= 0.0)
+        })
+    }
+
+    /// The bounding box of an instance of this mesh in the world as `[min_x, min_y, max_x, max_y]`.
+    fn world_bounds(&self, instance: &Instance) -> [f32; 4] {
+        let [min_x, min_y, max_x, max_y] = self.bounds;
+        let (grow_x, grow_y) = (instance.panel[0].abs() / 2.0, instance.panel[1].abs() / 2.0);
+
+        [
+            (min_x - grow_x, min_y - grow_y),
+            (max_x + grow_x, min_y - grow_y),
+            (max_x + grow_x, max_y + grow_y),
+            (min_x - grow_x, max_y + grow_y),
+        ]
+        .iter()
+        .map(|(x, y)| instance.to_world(*x, *y))
+        .fold(
+            [f32::MAX, f32::MAX, f32::MIN, f32::MIN],
+            |[min_x, min_y, max_x, max_y], [x, y]| {
+                [min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)]
+            },
+        )
+    }
+
+    /// Where a ray first crosses an edge of the triangles of an instance of this mesh, as the
+    /// fraction of the direction & the normal of the edge.
+    ///
+    /// Only crossings before `max_t` are returned.
+    fn raycast(
+        &self,
+        instance: &Instance,
+        origin: (f32, f32),
+        direction: (f32, f32),
+        max_t: f32,
+    ) -> Option<(f32, (f32, f32))> {
+        let pos = |index: u16| {
+            let vertex = &self.vertices[index as usize];
+
+            instance.to_world(
+                vertex.pos[0] + vertex.slice[0] * instance.panel[0],
+                vertex.pos[1] + vertex.slice[1] * instance.panel[1],
+            )
+        };
+
+        let mut closest = None;
+        for triangle in self.indices.chunks_exact(3) {
+            let (a, b, c) = (pos(triangle[0]), pos(triangle[1]), pos(triangle[2]));
+            for (p, q) in [(a, b), (b, c), (c, a)].iter() {
+                let t = match ray_segment_intersection(origin, direction, *p, *q) {
+                    Some(t) if t <= closest.map_or(max_t, |(t, _)| t) => t,
+                    _ => continue,
+                };
+
+                // The normal of the edge pointing back at the start of the ray
+                let (edge_x, edge_y) = (q[0] - p[0], q[1] - p[1]);
+                let length = edge_x.hypot(edge_y);
+                let (normal_x, normal_y) = (-edge_y / length, edge_x / length);
+                let normal = if normal_x * direction.0 + normal_y * direction.1 > 0.0 {
+                    (-normal_x, -normal_y)
+                } else {
+                    (normal_x, normal_y)
+                };
+
+                closest = Some((t, normal));
+            }
+        }
+
+        closest
+    }
+
+    /// Sort the instances by their draw order if it might have changed.
+    fn sort_instances(&mut self) {
+        if !mem::replace(&mut self.instances_unsorted, false) {
+            return;
+        }
+        if self
+            .instance_orders
+            .windows(2)
+            .all(|pair| pair[0] <= pair[1])
+        {
+            return;
+        }
+
+        // The sort is stable so instances with the same draw order keep their order
+        let mut order: Vec<usize> = (0..self.instances.len()).collect();
+        let orders = &self.instance_orders;
+        #[cfg(feature = "rayon")]
+        order.par_sort_by_key(|index| orders[*index]);
+        #[cfg(not(feature = "rayon"))]
+        order.sort_by_key(|index| orders[*index]);
+
+        self.instances = reorder(&self.instances, &order);
+        self.instance_ids = reorder(&self.instance_ids, &order);
+        self.instance_orders = reorder(&self.instance_orders, &order);
+        for (index, id) in self.instance_ids.iter().enumerate() {
+            self.instance_lookup.insert(*id, index);
+        }
+
+        self.mark_all_dirty();
+    }
+
+    /// Mark the chunk containing an instance to be uploaded again.
+    fn mark_dirty(&mut self, index: usize) {
+        let chunk = index / INSTANCE_CHUNK_SIZE;
+        if self.dirty_chunks.len() <= chunk {
+            self.dirty_chunks.resize(chunk + 1, false);
+        }
+
+        self.dirty_chunks[chunk] = true;
+    }
+
+    /// Mark all instances to be uploaded again.
+    fn mark_all_dirty(&mut self) {
+        for index in (0..self.instances.len()).step_by(INSTANCE_CHUNK_SIZE) {
+            self.mark_dirty(index);
+        }
+    }
+
+    /// Upload the chunks with changed instances, creating instance buffers when needed.
+    fn upload_instances(&mut self, ctx: &mut Context) {
+        // The buffers only contain the visible instances after culling
+        if mem::replace(&mut self.culled, false) {
+            self.mark_all_dirty();
+        }
+
+        for chunk in 0..self.dirty_chunks.len() {
+            if !mem::replace(&mut self.dirty_chunks[chunk], false) {
+                continue;
+            }
+
+            // The instances of this chunk might have been removed since
+            let start = chunk * INSTANCE_CHUNK_SIZE;
+            if start >= self.instances.len() {
+                continue;
+            }
+            let end = (start + INSTANCE_CHUNK_SIZE).min(self.instances.len());
+
+            self.reserve_chunk(ctx, chunk, end - start);
+            self.chunk_bindings[chunk].vertex_buffers[1].update(ctx, &self.instances[start..end]);
+        }
+
+        self.drawn_count = self.instances.len();
+    }
+
+    /// Upload only the instances that might be visible, returns how many are culled.
+    ///
+    /// The view is half the size of the visible part of the world around the camera.
+    fn upload_visible_instances(
+        &mut self,
+        ctx: &mut Context,
+        view: (f32, f32),
+        pan: (f32, f32),
+    ) -> usize {
+        #[cfg(feature = "rayon")]
+        let instances = self.instances.par_iter();
+        #[cfg(not(feature = "rayon"))]
+        let instances = self.instances.iter();
+        let visible: Vec<Instance> = instances
+            .filter(|instance| self.is_visible(instance, view, pan))
+            .copied()
+            .collect();
+
+        // When everything is visible only the changed chunks have to be uploaded
+        if visible.len() == self.instances.len() {
+            self.upload_instances(ctx);
+
+            return 0;
+        }
+
+        // Which instances are visible changes with the camera, so everything is uploaded
+        for (chunk, instances) in visible.chunks(INSTANCE_CHUNK_SIZE).enumerate() {
+            self.reserve_chunk(ctx, chunk, instances.len());
+            self.chunk_bindings[chunk].vertex_buffers[1].update(ctx, instances);
+        }
+
+        self.culled = true;
+        self.drawn_count = visible.len();
+
+        self.instances.len() - visible.len()
+    }
+
+    /// Whether the bounding circle of an instance overlaps the view.
+    fn is_visible(&self, instance: &Instance, view: (f32, f32), pan: (f32, f32)) -> bool {
+        // The length of the matrix is never less than how much it stretches the mesh
+        let [a, b, c, d] = instance.matrix();
+        let stretch = (a * a + b * b + c * c + d * d).sqrt();
+        // Nine-slice vertices move at most half the panel extent
+        let [panel_x, panel_y] = instance.panel;
+        let radius = (self.radius + panel_x.hypot(panel_y) / 2.0) * stretch;
+
+        // Apply the camera the same way as the vertex shader
+        let [x, y, depth] = instance.position;
+        let (x, y) = (x + pan.0 * depth, y + pan.1 * depth);
+
+        x.abs() - radius <= view.0 && y.abs() - radius <= view.1
+    }
+
+    /// Create the instance buffer of a chunk or grow it when the instances don't fit anymore.
+    fn reserve_chunk(&mut self, ctx: &mut Context, chunk: usize, instance_count: usize) {
+        let (vertex_buffer, index_buffer) = self.buffers.expect("Buffers not assigned");
+
+        let capacity = self
+            .chunk_bindings
+            .get(chunk)
+            .map(|bindings| bindings.vertex_buffers[1].size() / mem::size_of::<Instance>())
+            .unwrap_or(0);
+        if instance_count <= capacity {
+            return;
+        }
+
+        let mut new_capacity = capacity.max(self.instance_capacity);
+        while new_capacity < instance_count {
+            new_capacity *= 2;
+        }
+        let instance_buffer = Buffer::stream(
+            ctx,
+            BufferType::VertexBuffer,
+            new_capacity.min(INSTANCE_CHUNK_SIZE) * mem::size_of::<Instance>(),
+        );
+
+        let bindings = Bindings {
+            vertex_buffers: vec![vertex_buffer, instance_buffer],
+            index_buffer,
+            images: vec![],
+        };
+        if chunk < self.chunk_bindings.len() {
+            // Replace the old buffer, the whole chunk is uploaded after this
+            let old = mem::replace(&mut self.chunk_bindings[chunk], bindings);
+            old.vertex_buffers[1].delete();
+        } else {
+            self.chunk_bindings.push(bindings);
+        }
+    }
+
+    /// The vertex & index buffer, first index & index count of the coarsest level of detail of a
+    /// mesh that can be drawn at a camera zoom, `None` when the mesh itself must be drawn.
+    fn lod_geometry(
+        draw_calls: &[DrawCall],
+        mesh: usize,
+        camera_zoom: f32,
+    ) -> Option<(Buffer, Buffer, usize, usize)> {
+        let (_, lod) = draw_calls[mesh]
+            .lods
+            .iter()
+            .rev()
+            .find(|(max_zoom, _)| camera_zoom <= *max_zoom)?;
+        let lod = &draw_calls[*lod];
+        let (vertex_buffer, index_buffer) = lod.buffers?;
+
+        Some((
+            vertex_buffer,
+            index_buffer,
+            lod.first_index,
+            lod.indices.len(),
+        ))
+    }
+
+    /// Whether the mesh can be merged with other meshes into a single draw call at a camera zoom,
+    /// which needs the default pipelines & its own geometry instead of a level of detail.
+    fn is_mergeable(&self, camera_zoom: f32) -> bool {
+        self.shader.is_none()
+            && self.mask.is_none()
+            && self.opacity_group.is_none()
+            && !self
+                .lods
+                .iter()
+                .any(|(max_zoom, _)| camera_zoom <= *max_zoom)
+    }
+
+    /// The bindings & amount of instances of every chunk that must be drawn.
+    fn instance_chunks(&self) -> impl Iterator<Item = (&Bindings, usize)> {
+        let instance_count = self.drawn_count;
+
+        self.chunk_bindings
+            .iter()
+            .enumerate()
+            .map(move |(chunk, bindings)| {
+                let start = chunk * INSTANCE_CHUNK_SIZE;
+
+                (
+                    bindings,
+                    instance_count
+                        .saturating_sub(start)
+                        .min(INSTANCE_CHUNK_SIZE),
+                )
+            })
+            .filter(|(_, count)| *count > 0)
+    }
+}
+
+/// The tessellated paths of a SVG that's not uploaded yet.
+pub struct SvgGeometry {
+    /// The vertices & indices of all filled paths.
+    geometry: VertexBuffers<Vertex, u16>,
+    /// The outline of the first filled path.
+    outline: Vec<[f32; 2]>,
+    /// The maximum distance between the curves & the triangles.
+    tolerance: f32,
+    /// What couldn't be drawn, like strokes, images & filters.
+    warnings: Vec<String>,
+}
+
+impl SvgGeometry {
+    /// Create from geometry that's already tessellated, like a baked mesh.
+    pub(crate) fn new(
+        geometry: VertexBuffers<Vertex, u16>,
+        outline: Vec<[f32; 2]>,
+        tolerance: f32,
+    ) -> Self {
+        Self {
+            geometry,
+            outline,
+            tolerance,
+            warnings: vec![],
+        }
+    }
+
+    /// The vertices & indices of all filled paths.
+    pub(crate) fn geometry(&self) -> &VertexBuffers<Vertex, u16> {
+        &self.geometry
+    }
+
+    /// The outline of the first filled path, empty when there's none.
+    pub(crate) fn outline(&self) -> &[[f32; 2]] {
+        &self.outline
+    }
+
+    /// The maximum distance between the curves & the triangles.
+    pub(crate) fn tolerance(&self) -> f32 {
+        self.tolerance
+    }
+
+    /// Whether there's nothing to draw.
+    pub fn is_empty(&self) -> bool {
+        self.geometry.indices.is_empty()
+    }
+
+    /// What couldn't be drawn, every unsupported feature is reported once.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// The minimum & maximum position of the vertices as `[min_x, min_y, max_x, max_y]`.
+    fn bounds(&self) -> Option<[f32; 4]> {
+        let first = self.geometry.vertices.first()?.pos;
+
+        Some(self.geometry.vertices.iter().fold(
+            [first[0], first[1], first[0], first[1]],
+            |[min_x, min_y, max_x, max_y], vertex| {
+                [
+                    min_x.min(vertex.pos[0]),
+                    min_y.min(vertex.pos[1]),
+                    max_x.max(vertex.pos[0]),
+                    max_y.max(vertex.pos[1]),
+                ]
+            },
+        ))
+    }
+}
+
+/// The paths of a group with an ID in a SVG, tessellated separately so it can be transformed.
+pub struct SvgPart {
+    /// The ID of the group.
+    pub name: String,
+    /// The index of the part of the closest ancestor group with an ID.
+    pub parent: Option<usize>,
+    /// The paths of the group that aren't in a child part.
+    pub geometry: SvgGeometry,
+    /// The point the part rotates & scales around in the coordinates of the SVG.
+    pub pivot: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Vertex {
+    pos: [f32; 2],
+    color: [f32; 4],
+    /// How much the vertex moves along with the panel size of the instance, zero for meshes that
+    /// aren't nine-slices.
+    slice: [f32; 2],
+    /// The position on the atlas page for textured meshes, unused for other meshes.
+    uv: [f32; 2],
+    /// The direction the vertex is pushed outwards by a pixel on the screen, only set for the
+    /// transparent edge of anti-aliased fills.
+    fringe: [f32; 2],
+}
+
+impl Vertex {
+    /// How many numbers a vertex consists of.
+    pub(crate) const FLOAT_COUNT: usize = 12;
+
+    /// Create a vertex that isn't part of a nine-slice.
+    pub(crate) fn new(pos: [f32; 2], color: [f32; 4]) -> Self {
+        Self {
+            pos,
+            color,
+            slice: [0.0, 0.0],
+            uv: [0.0, 0.0],
+            fringe: [0.0, 0.0],
+        }
+    }
+
+    /// All numbers of the vertex in the order of its fields.
+    pub(crate) fn to_floats(&self) -> [f32; Vertex::FLOAT_COUNT] {
+        let [x, y] = self.pos;
+        let [red, green, blue, alpha] = self.color;
+        let [slice_x, slice_y] = self.slice;
+        let [u, v] = self.uv;
+        let [fringe_x, fringe_y] = self.fringe;
+
+        [
+            x, y, red, green, blue, alpha, slice_x, slice_y, u, v, fringe_x, fringe_y,
+        ]
+    }
+
+    /// Create from the numbers of its fields, in the order of `to_floats`.
+    pub(crate) fn from_floats(floats: [f32; Vertex::FLOAT_COUNT]) -> Self {
+        let [x, y, red, green, blue, alpha, slice_x, slice_y, u, v, fringe_x, fringe_y] = floats;
+
+        Self {
+            pos: [x, y],
+            color: [red, green, blue, alpha],
+            slice: [slice_x, slice_y],
+            uv: [u, v],
+            fringe: [fringe_x, fringe_y],
+        }
+    }
+
+    /// Create a white vertex of a textured mesh.
+    pub(crate) fn textured(pos: [f32; 2], uv: [f32; 2]) -> Self {
+        Self {
+            uv,
+            ..Self::new(pos, [1.0, 1.0, 1.0, 1.0])
+        }
+    }
 }
 
 rdata! {
@@ -268,9 +4404,11 @@ rdata! {
 pub struct Instance {
     position: [f32; 3],
     rotation: f32,
-    scale: f32,
+    scale: [f32; 2],
+    skew: [f32; 2],
     color: [f32; 3],
     alpha: f32,
+    panel: [f32; 2],
 }
 
 meths {
@@ -293,9 +4431,11 @@ impl Instance {
         Self {
             position: [x, y, 0.0],
             rotation: 0.0,
-            scale: 1.0,
+            scale: [1.0, 1.0],
+            skew: [0.0, 0.0],
             color: [1.0, 1.0, 1.0],
             alpha: 1.0,
+            panel: [0.0, 0.0],
         }
     }
 
@@ -329,14 +4469,146 @@ impl Instance {
         u8::MAX - (self.position[2] * 255.0) as u8
     }
 
-    /// Set the scale.
+    /// Set the scale uniformly on both axes.
     pub fn set_scale(&mut self, scale: f32) {
-        self.scale = scale;
+        self.scale = [scale, scale];
     }
 
-    /// Get the scale.
+    /// Get the scale, the X scale when it's not uniform.
     pub fn scale(&self) -> f32 {
-        self.scale
+        self.scale[0]
+    }
+
+    /// Set the scale on the X axis.
+    pub fn set_scale_x(&mut self, scale: f32) {
+        self.scale[0] = scale;
+    }
+
+    /// Get the scale on the X axis.
+    pub fn scale_x(&self) -> f32 {
+        self.scale[0]
+    }
+
+    /// Set the scale on the Y axis.
+    pub fn set_scale_y(&mut self, scale: f32) {
+        self.scale[1] = scale;
+    }
+
+    /// Get the scale on the Y axis.
+    pub fn scale_y(&self) -> f32 {
+        self.scale[1]
+    }
+
+    /// Set how much the X position is shifted by the Y position before rotating.
+    pub fn set_skew_x(&mut self, skew: f32) {
+        self.skew[0] = skew;
+    }
+
+    /// Get how much the X position is shifted by the Y position before rotating.
+    pub fn skew_x(&self) -> f32 {
+        self.skew[0]
+    }
+
+    /// Set how much the Y position is shifted by the X position before rotating.
+    pub fn set_skew_y(&mut self, skew: f32) {
+        self.skew[1] = skew;
+    }
+
+    /// Get how much the Y position is shifted by the X position before rotating.
+    pub fn skew_y(&self) -> f32 {
+        self.skew[1]
+    }
+
+    /// The 2x2 matrix of the scale, skew & rotation as `[x_axis_x, x_axis_y, y_axis_x, y_axis_y]`.
+    fn matrix(&self) -> [f32; 4] {
+        let (sin, cos) = self.rotation.sin_cos();
+        let [scale_x, scale_y] = self.scale;
+        let [skew_x, skew_y] = self.skew;
+
+        [
+            scale_x * (cos - sin * skew_y),
+            scale_x * (sin + cos * skew_y),
+            scale_y * (cos * skew_x - sin),
+            scale_y * (sin * skew_x + cos),
+        ]
+    }
+
+    /// Combine a transform relative to a parent with the world transform of the parent.
+    ///
+    /// The depth & color aren't inherited.
+    pub(crate) fn relative_to(&self, parent: &Instance) -> Instance {
+        let [pa, pb, pc, pd] = parent.matrix();
+        let [ca, cb, cc, cd] = self.matrix();
+        let (x, y) = (self.position[0], self.position[1]);
+
+        // Multiply the matrices of the parent & the child
+        let (a, b, c, d) = (
+            pa * ca + pc * cb,
+            pb * ca + pd * cb,
+            pa * cc + pc * cd,
+            pb * cc + pd * cd,
+        );
+
+        // Decompose the matrix into a rotation, a skew on the X axis and a scale
+        let scale_x = (a * a + b * b).sqrt();
+        let rotation = if scale_x == 0.0 { 0.0 } else { b.atan2(a) };
+        let (sin, cos) = rotation.sin_cos();
+        let sheared = cos * c + sin * d;
+        let scale_y = cos * d - sin * c;
+        let skew_x = if scale_y == 0.0 {
+            0.0
+        } else {
+            sheared / scale_y
+        };
+
+        Instance {
+            position: [
+                parent.position[0] + pa * x + pc * y,
+                parent.position[1] + pb * x + pd * y,
+                self.position[2],
+            ],
+            rotation,
+            scale: [scale_x, scale_y],
+            skew: [skew_x, 0.0],
+            ..*self
+        }
+    }
+
+    /// Convert a position in the local space of the mesh to a position relative to the world
+    /// origin.
+    fn to_world(&self, x: f32, y: f32) -> [f32; 2] {
+        let [a, b, c, d] = self.matrix();
+
+        [
+            self.position[0] + a * x + c * y,
+            self.position[1] + b * x + d * y,
+        ]
+    }
+
+    /// Convert a position relative to the world origin to the local space of the mesh.
+    ///
+    /// Returns `None` when the transformation can't be undone because the instance is flat.
+    fn to_local(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        let (x, y) = (x - self.position[0], y - self.position[1]);
+
+        // Undo the rotation
+        let (sin, cos) = self.rotation.sin_cos();
+        let (x, y) = (x * cos + y * sin, y * cos - x * sin);
+
+        // Undo the skew
+        let [skew_x, skew_y] = self.skew;
+        let determinant = 1.0 - skew_x * skew_y;
+        let [scale_x, scale_y] = self.scale;
+        if determinant == 0.0 || scale_x == 0.0 || scale_y == 0.0 {
+            return None;
+        }
+        let (x, y) = (
+            (x - skew_x * y) / determinant,
+            (y - skew_y * x) / determinant,
+        );
+
+        // Undo the scale
+        Some((x / scale_x, y / scale_y))
     }
 
     /// Set the rotation.
@@ -358,11 +4630,256 @@ impl Instance {
     pub fn color_multiplier(&self) -> (f32, f32, f32) {
         (self.color[0], self.color[1], self.color[2])
     }
+
+    /// Set how much a nine-slice panel is larger than its mesh on both axes.
+    pub fn set_panel_extent(&mut self, width: f32, height: f32) {
+        self.panel = [width, height];
+    }
+}
+
+impl InstanceRef {
+    /// Run a function on the referenced instance, fails when the instance is removed.
+    ///
+    /// The instance is only uploaded again when the function changed it.
+    fn with<F, R>(&self, func: F) -> GResult<R>
+    where
+        F: FnOnce(&mut Instance) -> R,
+    {
+        let mut render = Render::borrow_mut();
+        let original = match render.instance(*self) {
+            Some(instance) => *instance,
+            None => bail!("instance is removed"),
+        };
+
+        let mut instance = original;
+        let result = func(&mut instance);
+        if instance != original {
+            if let Some(stored) = render.instance_mut(*self) {
+                *stored = instance;
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Read a value from the referenced instance, fails when the instance is removed.
+    fn read<F, R>(&self, func: F) -> GResult<R>
+    where
+        F: FnOnce(&Instance) -> R,
+    {
+        match Render::borrow().instance(*self) {
+            Some(instance) => Ok(func(instance)),
+            None => bail!("instance is removed"),
+        }
+    }
+
+    /// Get the X position.
+    pub fn x(&self) -> GResult<f32> {
+        self.read(|instance| instance.x())
+    }
+
+    /// Set the X position.
+    pub fn set_x(&mut self, new: f32) -> GResult<()> {
+        self.with(|instance| instance.set_x(new))
+    }
+
+    /// Get the Y position.
+    pub fn y(&self) -> GResult<f32> {
+        self.read(|instance| instance.y())
+    }
+
+    /// Set the Y position.
+    pub fn set_y(&mut self, new: f32) -> GResult<()> {
+        self.with(|instance| instance.set_y(new))
+    }
+
+    /// Get the Z position.
+    pub fn z(&self) -> GResult<u8> {
+        self.read(|instance| instance.z())
+    }
+
+    /// Set the Z position.
+    pub fn set_z(&mut self, new: u8) -> GResult<()> {
+        self.with(|instance| instance.set_z(new))
+    }
+
+    /// Get the rotation.
+    pub fn rotation(&self) -> GResult<f32> {
+        self.read(|instance| instance.rotation())
+    }
+
+    /// Set the rotation.
+    pub fn set_rotation(&mut self, rotation: f32) -> GResult<()> {
+        self.with(|instance| instance.set_rotation(rotation))
+    }
+
+    /// Get the scale.
+    pub fn scale(&self) -> GResult<f32> {
+        self.read(|instance| instance.scale())
+    }
+
+    /// Set the scale.
+    pub fn set_scale(&mut self, scale: f32) -> GResult<()> {
+        self.with(|instance| instance.set_scale(scale))
+    }
+
+    /// Get the scale on the X axis.
+    pub fn scale_x(&self) -> GResult<f32> {
+        self.read(|instance| instance.scale_x())
+    }
+
+    /// Set the scale on the X axis.
+    pub fn set_scale_x(&mut self, scale: f32) -> GResult<()> {
+        self.with(|instance| instance.set_scale_x(scale))
+    }
+
+    /// Get the scale on the Y axis.
+    pub fn scale_y(&self) -> GResult<f32> {
+        self.read(|instance| instance.scale_y())
+    }
+
+    /// Set the scale on the Y axis.
+    pub fn set_scale_y(&mut self, scale: f32) -> GResult<()> {
+        self.with(|instance| instance.set_scale_y(scale))
+    }
+
+    /// Get the skew on the X axis.
+    pub fn skew_x(&self) -> GResult<f32> {
+        self.read(|instance| instance.skew_x())
+    }
+
+    /// Set the skew on the X axis.
+    pub fn set_skew_x(&mut self, skew: f32) -> GResult<()> {
+        self.with(|instance| instance.set_skew_x(skew))
+    }
+
+    /// Get the skew on the Y axis.
+    pub fn skew_y(&self) -> GResult<f32> {
+        self.read(|instance| instance.skew_y())
+    }
+
+    /// Set the skew on the Y axis.
+    pub fn set_skew_y(&mut self, skew: f32) -> GResult<()> {
+        self.with(|instance| instance.set_skew_y(skew))
+    }
+
+    /// Get the draw order relative to the other instances of the mesh.
+    pub fn draw_order(&self) -> GResult<i32> {
+        match Render::borrow().instance_draw_order(*self) {
+            Some(draw_order) => Ok(draw_order),
+            None => bail!("instance is removed"),
+        }
+    }
+
+    /// Set the draw order relative to the other instances of the mesh, lower is drawn first.
+    pub fn set_draw_order(&mut self, draw_order: i32) -> GResult<()> {
+        if Render::borrow_mut().set_instance_draw_order(*self, draw_order) {
+            Ok(())
+        } else {
+            bail!("instance is removed")
+        }
+    }
+
+    /// Set the color multiplier.
+    pub fn set_color_multiplier(&mut self, r: f32, g: f32, b: f32) -> GResult<()> {
+        self.with(|instance| instance.set_color_multiplier(r, g, b))
+    }
+
+    /// Set the size of a nine-slice panel before scaling, has no effect on other meshes.
+    pub fn set_panel_size(&mut self, width: f32, height: f32) -> GResult<()> {
+        let [min_x, min_y, max_x, max_y] = Render::borrow().draw_calls[self.mesh].bounds;
+
+        self.with(|instance| {
+            instance.set_panel_extent(width - (max_x - min_x), height - (max_y - min_y))
+        })
+    }
+}
+
+/// How much a position moves along with the panel size, between the start & end of the stretched
+/// part of a nine-slice.
+fn slice_weight(position: f32, start: f32, end: f32) -> f32 {
+    if position <= start {
+        -0.5
+    } else if position >= end {
+        0.5
+    } else {
+        (position - start) / (end - start) - 0.5
+    }
+}
+
+/// Append a ring of triangles around a filled polygon that fades from its color to transparent.
+///
+/// The outer vertices are at the same position as the inner ones, the vertex shader pushes them
+/// a pixel outwards so the fringe stays the same size on the screen when zooming.
+#[cfg(feature = "svg")]
+fn append_fringe(
+    geometry: &mut VertexBuffers<Vertex, u16>,
+    points: &[[f32; 2]],
+    vertex_ctor: &VertexCtor,
+) -> Result<()> {
+    if points.len() < 3 {
+        return Ok(());
+    }
+    let first = geometry.vertices.len();
+    if first + points.len() * 2 > u16::MAX as usize {
+        return Err(anyhow!("anti-aliasing fringe doesn't fit in the mesh"));
+    }
+
+    // The outwards side of the edges depends on the winding of the polygon
+    let area: f32 = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| a[0] * b[1] - b[0] * a[1])
+        .sum();
+    let side = if area > 0.0 { 1.0 } else { -1.0 };
+    let normal = |a: [f32; 2], b: [f32; 2]| {
+        let (x, y) = (b[0] - a[0], b[1] - a[1]);
+        let length = x.hypot(y).max(std::f32::EPSILON);
+
+        [y / length * side, -x / length * side]
+    };
+
+    let count = points.len();
+    for (index, pos) in points.iter().enumerate() {
+        let previous = points[(index + count - 1) % count];
+        let next = points[(index + 1) % count];
+        let (a, b) = (normal(previous, *pos), normal(*pos, next));
+
+        // Miter the corners, limited so sharp corners don't shoot out
+        let (x, y) = (a[0] + b[0], a[1] + b[1]);
+        let length = x.hypot(y).max(std::f32::EPSILON);
+        let cos = ((x * a[0] + y * a[1]) / length).max(0.25);
+        let fringe = [x / length / cos, y / length / cos];
+
+        let color = vertex_ctor.color_at(point(pos[0], pos[1]));
+        geometry.vertices.push(Vertex::new(*pos, color));
+        geometry.vertices.push(Vertex {
+            fringe,
+            ..Vertex::new(*pos, [color[0], color[1], color[2], 0.0])
+        });
+    }
+
+    for index in 0..count {
+        let inner = (first + index * 2) as u16;
+        let outer = inner + 1;
+        let next_inner = (first + (index + 1) % count * 2) as u16;
+        let next_outer = next_inner + 1;
+
+        geometry
+            .indices
+            .extend_from_slice(&[inner, outer, next_outer, inner, next_outer, next_inner]);
+    }
+
+    Ok(())
 }
 
 /// Used by lyon to create vertices.
+#[derive(Clone)]
 pub struct VertexCtor {
     color: [f32; 4],
+    /// Overrides the color for every vertex.
+    #[cfg(feature = "svg")]
+    gradient: Option<Gradient>,
 }
 
 impl VertexCtor {
@@ -374,7 +4891,30 @@ impl VertexCtor {
                 color.blue as f32 / 255.0,
                 alpha,
             ],
+            #[cfg(feature = "svg")]
+            gradient: None,
+        }
+    }
+
+    /// Color the vertices with a gradient depending on their position.
+    #[cfg(feature = "svg")]
+    pub fn with_gradient(gradient: Gradient) -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0, 1.0],
+            gradient: Some(gradient),
+        }
+    }
+
+    /// The color of a vertex at a position.
+    fn color_at(&self, position: Point) -> [f32; 4] {
+        #[cfg(feature = "svg")]
+        {
+            if let Some(ref gradient) = self.gradient {
+                return gradient.color_at(position.x, position.y);
+            }
         }
+
+        self.color
     }
 }
 
@@ -382,7 +4922,22 @@ impl FillVertexConstructor<Vertex> for VertexCtor {
     fn new_vertex(&mut self, position: Point, _: FillAttributes) -> Vertex {
         Vertex {
             pos: position.to_array(),
-            color: self.color,
+            color: self.color_at(position),
+            slice: [0.0, 0.0],
+            uv: [0.0, 0.0],
+            fringe: [0.0, 0.0],
+        }
+    }
+}
+
+impl BasicVertexConstructor<Vertex> for VertexCtor {
+    fn new_vertex(&mut self, position: Point) -> Vertex {
+        Vertex {
+            pos: position.to_array(),
+            color: self.color_at(position),
+            slice: [0.0, 0.0],
+            uv: [0.0, 0.0],
+            fringe: [0.0, 0.0],
         }
     }
 }
@@ -391,56 +4946,144 @@ impl StrokeVertexConstructor<Vertex> for VertexCtor {
     fn new_vertex(&mut self, position: Point, _: StrokeAttributes) -> Vertex {
         Vertex {
             pos: position.to_array(),
-            color: self.color,
+            color: self.color_at(position),
+            slice: [0.0, 0.0],
+            uv: [0.0, 0.0],
+            fringe: [0.0, 0.0],
+        }
+    }
+}
+
+/// The GLSL of the palette swap, the tint & the conversion to linear colors, shared by the
+/// fragment shaders of the meshes.
+///
+/// Colors are only converted when `LINEAR` is defined, see `with_defines`.
+macro_rules! mesh_color_glsl {
+    () => {
+        r#"
+uniform lowp vec4 u_tint;
+uniform mediump mat4 u_swap_from0;
+uniform mediump mat4 u_swap_to0;
+uniform mediump mat4 u_swap_from1;
+uniform mediump mat4 u_swap_to1;
+
+// Replace a color with its swapped color if it's one of the columns, unused columns have a zero
+// alpha
+bool find_swap(mediump vec3 rgb, mediump mat4 from, mediump mat4 to, out mediump vec3 swapped) {
+    for (int i = 0; i < 4; i++) {
+        if (from[i].a > 0.5 && distance(rgb, from[i].rgb) < 0.002) {
+            swapped = to[i].rgb;
+
+            return true;
         }
     }
+
+    return false;
+}
+
+mediump vec3 swap_palette(mediump vec3 rgb) {
+    mediump vec3 swapped;
+    if (find_swap(rgb, u_swap_from0, u_swap_to0, swapped)
+        || find_swap(rgb, u_swap_from1, u_swap_to1, swapped)) {
+        return swapped;
+    }
+
+    return rgb;
+}
+
+// The colors of the SVGs & textures are sRGB, with gamma correction they're blended linearly
+mediump vec3 mesh_color(mediump vec3 rgb) {
+    rgb = swap_palette(rgb);
+#ifdef LINEAR
+    rgb = pow(rgb, vec3(2.2));
+#endif
+
+    return rgb;
+}
+"#
+    };
+}
+
+/// Insert preprocessor definitions after the version of a shader.
+pub(crate) fn with_defines(source: &str, defines: &[&str]) -> String {
+    let defines: String = defines
+        .iter()
+        .map(|define| format!("#define {}\n", define))
+        .collect();
+
+    match source.find('\n') {
+        Some(end) => format!("{}{}{}", &source[..=end], defines, &source[end + 1..]),
+        None => format!("{}\n{}", source, defines),
+    }
 }
 
-mod geom_shader {
+pub(crate) mod geom_shader {
     use miniquad::graphics::*;
 
     pub const VERTEX: &str = r#"#version 100
 
 uniform vec2 u_zoom;
 uniform vec2 u_pan;
+uniform float u_pixel;
 
 attribute vec2 a_pos;
 attribute vec4 a_color;
+attribute vec2 a_slice;
+attribute vec2 a_fringe;
 attribute vec3 a_inst_pos;
 attribute float a_inst_rot;
-attribute float a_inst_scale;
+attribute vec2 a_inst_scale;
+attribute vec2 a_inst_skew;
 attribute vec4 a_inst_color;
+attribute vec2 a_inst_panel;
 
-varying lowp vec4 color;
+varying mediump vec4 color;
+varying lowp vec4 inst_color;
 
 void main() {
+    // Stretch nine-slice panels without distorting the corners
+    vec2 panel_pos = a_pos + a_slice * a_inst_panel;
+
+    // Scale the vertices separately on both axes
+    vec2 scaled_pos = panel_pos * a_inst_scale;
+
+    // Shift each axis by the other one
+    vec2 skewed_pos = scaled_pos + scaled_pos.yx * a_inst_skew;
+
     // Rotate vertices around the zero center
     float s = sin(a_inst_rot);
     float c = cos(a_inst_rot);
     mat2 rotation_mat = mat2(c, -s, s, c);
-    vec2 rotated_pos = a_pos * rotation_mat;
+    vec2 rotated_pos = skewed_pos * rotation_mat;
 
-    // Scale the rotated vertices
-    vec2 scaled_pos = rotated_pos * a_inst_scale;
+    // Push the edges of anti-aliased fills a pixel outwards, independent of the scale
+    rotated_pos += a_fringe * rotation_mat * u_pixel;
 
-    // Offset scaled position with instance position
+    // Offset rotated position with instance position
     // Offset with the camera multiplied by the Z position
-    vec2 pos = scaled_pos + a_inst_pos.xy + u_pan * a_inst_pos.z;
+    vec2 pos = rotated_pos + a_inst_pos.xy + u_pan * a_inst_pos.z;
 
     gl_Position = vec4(pos * vec2(1.0, -1.0) * u_zoom, a_inst_pos.z, 1.0);
 
-    color = a_color * a_inst_color;
+    // The palette is swapped before the instance color is applied
+    color = a_color;
+    inst_color = a_inst_color;
 }
 "#;
 
-    pub const FRAGMENT: &str = r#"#version 100
-
-varying lowp vec4 color;
+    pub const FRAGMENT: &str = concat!(
+        r#"#version 100
 
+varying mediump vec4 color;
+varying lowp vec4 inst_color;
+"#,
+        mesh_color_glsl!(),
+        r#"
 void main() {
-    gl_FragColor = color;
+    gl_FragColor = vec4(mesh_color(color.rgb), color.a) * inst_color * u_tint;
 }
-"#;
+"#
+    );
 
     pub const META: ShaderMeta = ShaderMeta {
         images: &[],
@@ -448,6 +5091,12 @@ void main() {
             uniforms: &[
                 UniformDesc::new("u_zoom", UniformType::Float2),
                 UniformDesc::new("u_pan", UniformType::Float2),
+                UniformDesc::new("u_pixel", UniformType::Float1),
+                UniformDesc::new("u_tint", UniformType::Float4),
+                UniformDesc::new("u_swap_from0", UniformType::Mat4),
+                UniformDesc::new("u_swap_to0", UniformType::Mat4),
+                UniformDesc::new("u_swap_from1", UniformType::Mat4),
+                UniformDesc::new("u_swap_to1", UniformType::Mat4),
             ],
         },
     };
@@ -457,5 +5106,202 @@ void main() {
     pub struct Uniforms {
         pub zoom: (f32, f32),
         pub pan: (f32, f32),
+        /// How many world units a single pixel on the screen is.
+        pub pixel: f32,
+        pub palette: PaletteSwap,
+    }
+
+    /// The colors replaced when drawing & the color everything is multiplied with.
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    pub struct PaletteSwap {
+        tint: [f32; 4],
+        /// Columns of `[red, green, blue, used]` for the first 4 colors & their replacements.
+        from0: [f32; 16],
+        to0: [f32; 16],
+        /// The last 4 colors & their replacements.
+        from1: [f32; 16],
+        to1: [f32; 16],
+    }
+
+    impl PaletteSwap {
+        /// The amount of colors that can be swapped.
+        pub const MAX_COLORS: usize = 8;
+
+        /// Replace colors with other colors & multiply everything with a tint, the channels are
+        /// from 0 to 1.
+        ///
+        /// Only the first `MAX_COLORS` swaps are used.
+        pub fn new(tint: [f32; 3], swaps: &[([f32; 3], [f32; 3])]) -> Self {
+            let mut palette = Self {
+                tint: [tint[0], tint[1], tint[2], 1.0],
+                ..Self::default()
+            };
+
+            for (index, (from, to)) in swaps.iter().take(Self::MAX_COLORS).enumerate() {
+                let (from_column, to_column) = if index < 4 {
+                    (&mut palette.from0, &mut palette.to0)
+                } else {
+                    (&mut palette.from1, &mut palette.to1)
+                };
+                let offset = (index % 4) * 4;
+                from_column[offset..offset + 4].copy_from_slice(&[from[0], from[1], from[2], 1.0]);
+                to_column[offset..offset + 4].copy_from_slice(&[to[0], to[1], to[2], 1.0]);
+            }
+
+            palette
+        }
+    }
+
+    impl Default for PaletteSwap {
+        /// Draw the colors unchanged.
+        fn default() -> Self {
+            Self {
+                tint: [1.0; 4],
+                from0: [0.0; 16],
+                to0: [0.0; 16],
+                from1: [0.0; 16],
+                to1: [0.0; 16],
+            }
+        }
+    }
+}
+
+pub(crate) mod sprite_shader {
+    use miniquad::graphics::*;
+
+    pub const VERTEX: &str = r#"#version 100
+
+uniform vec2 u_zoom;
+uniform vec2 u_pan;
+
+attribute vec2 a_pos;
+attribute vec4 a_color;
+attribute vec2 a_slice;
+attribute vec2 a_uv;
+attribute vec3 a_inst_pos;
+attribute float a_inst_rot;
+attribute vec2 a_inst_scale;
+attribute vec2 a_inst_skew;
+attribute vec4 a_inst_color;
+attribute vec2 a_inst_panel;
+
+varying mediump vec4 color;
+varying lowp vec4 inst_color;
+varying mediump vec2 uv;
+
+void main() {
+    // Transform the same way as the untextured meshes
+    vec2 panel_pos = a_pos + a_slice * a_inst_panel;
+    vec2 scaled_pos = panel_pos * a_inst_scale;
+    vec2 skewed_pos = scaled_pos + scaled_pos.yx * a_inst_skew;
+
+    float s = sin(a_inst_rot);
+    float c = cos(a_inst_rot);
+    mat2 rotation_mat = mat2(c, -s, s, c);
+    vec2 rotated_pos = skewed_pos * rotation_mat;
+
+    vec2 pos = rotated_pos + a_inst_pos.xy + u_pan * a_inst_pos.z;
+
+    gl_Position = vec4(pos * vec2(1.0, -1.0) * u_zoom, a_inst_pos.z, 1.0);
+
+    color = a_color;
+    inst_color = a_inst_color;
+    uv = a_uv;
+}
+"#;
+
+    pub const FRAGMENT: &str = concat!(
+        r#"#version 100
+
+varying mediump vec4 color;
+varying lowp vec4 inst_color;
+varying mediump vec2 uv;
+
+uniform sampler2D u_texture;
+"#,
+        mesh_color_glsl!(),
+        r#"
+void main() {
+    lowp vec4 texel = color * texture2D(u_texture, uv);
+    gl_FragColor = vec4(mesh_color(texel.rgb), texel.a) * inst_color * u_tint;
+}
+"#
+    );
+
+    pub const META: ShaderMeta = ShaderMeta {
+        images: &["u_texture"],
+        uniforms: UniformBlockLayout {
+            uniforms: &[
+                UniformDesc::new("u_zoom", UniformType::Float2),
+                UniformDesc::new("u_pan", UniformType::Float2),
+                UniformDesc::new("u_pixel", UniformType::Float1),
+                UniformDesc::new("u_tint", UniformType::Float4),
+                UniformDesc::new("u_swap_from0", UniformType::Mat4),
+                UniformDesc::new("u_swap_to0", UniformType::Mat4),
+                UniformDesc::new("u_swap_from1", UniformType::Mat4),
+                UniformDesc::new("u_swap_to1", UniformType::Mat4),
+            ],
+        },
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Upload a square with sides of a single unit.
+    fn square(render: &mut Render) -> Mesh {
+        let mut geometry = VertexBuffers::new();
+        geometry.vertices = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]
+            .iter()
+            .map(|pos| Vertex::new(*pos, [1.0, 1.0, 1.0, 1.0]))
+            .collect();
+        geometry.indices = vec![0, 1, 2, 0, 2, 3];
+
+        render.upload_buffers(&geometry).unwrap()
+    }
+
+    #[test]
+    fn small_meshes_are_merged() {
+        let mut render = Render::headless((320.0, 240.0), false);
+        for _ in 0..10 {
+            let mesh = square(&mut render);
+            render.add_instance(mesh, Instance::new(0.0, 0.0));
+        }
+        render.record_frame();
+
+        assert_eq!(render.draw_call_count(), 1);
+    }
+
+    #[test]
+    fn large_meshes_split_merged_meshes() {
+        let mut render = Render::headless((320.0, 240.0), false);
+        let meshes: Vec<Mesh> = (0..5).map(|_| square(&mut render)).collect();
+        for mesh in meshes.iter() {
+            render.add_instance(*mesh, Instance::new(0.0, 0.0));
+        }
+        // Copying the vertices of this many instances every frame is slower than drawing them
+        for _ in 0..MAX_MERGED_VERTICES {
+            render.add_instance(meshes[2], Instance::new(0.0, 0.0));
+        }
+        render.record_frame();
+
+        // The meshes before & after it are merged on both sides
+        assert_eq!(render.draw_call_count(), 3);
+    }
+
+    #[test]
+    fn unlit_meshes_are_drawn_separately() {
+        let mut render = Render::headless((320.0, 240.0), false);
+        let meshes: Vec<Mesh> = (0..4).map(|_| square(&mut render)).collect();
+        for mesh in meshes.iter() {
+            render.add_instance(*mesh, Instance::new(0.0, 0.0));
+        }
+        // The unlit meshes are drawn in a pass of their own after the lights
+        render.set_draw_order(meshes[3], UNLIT_DRAW_ORDER);
+        render.record_frame();
+
+        assert_eq!(render.draw_call_count(), 2);
     }
 }
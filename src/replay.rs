@@ -0,0 +1,352 @@
+use crate::{random::Random, storage::Storage};
+use anyhow::{anyhow, Result};
+use glsp::{bail, lib, rfn, GResult, Runtime};
+use miniquad::TouchPhase;
+use std::{collections::VecDeque, mem};
+
+/// The first line of every replay log, the number is the version of the format.
+const HEADER: &str = "clogs-replay 2";
+/// The first line of replay logs without the state of the random number generator.
+const HEADER_WITHOUT_SEED: &str = "clogs-replay 1";
+
+/// Input that reached the game during an update.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputEvent {
+    /// A key was pressed, with the name of the key.
+    KeyDown(String),
+    /// A key was released, with the name of the key.
+    KeyUp(String),
+    /// A character was typed.
+    Char(char),
+    /// The locked mouse moved, with the distance in pixels.
+    MouseDelta { dx: f32, dy: f32 },
+    /// A finger touched, moved or left the screen.
+    Touch {
+        phase: TouchPhase,
+        id: u64,
+        x: f32,
+        y: f32,
+    },
+}
+
+/// The duration & input of a single update.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayFrame {
+    /// The seconds of game time that passed.
+    pub delta: f64,
+    /// The input received since the previous update.
+    pub events: Vec<InputEvent>,
+}
+
+/// Whether input is recorded or played back.
+#[derive(Debug)]
+enum ReplayMode {
+    /// The input comes from the player.
+    Off,
+    /// The input & timing of every update is stored, with the seed & the state of the random
+    /// number generator when the recording started.
+    Recording((u64, u64), Vec<ReplayFrame>),
+    /// The input & timing comes from a recording, the input of the player is ignored.
+    Playing(VecDeque<ReplayFrame>),
+}
+
+lib! {
+/// Records the input & timing of every update, and feeds recordings back into the engine.
+///
+/// Because the game time & input are the same during playback, a deterministic game plays out
+/// exactly as it was recorded. The random number generator is restored to the state it had when
+/// the recording started. The log is plain text with the seed & the state of the generator,
+/// followed by a line for every update:
+///
+/// ```text
+/// clogs-replay 2
+/// seed:1234:8817203961
+/// 0.016667
+/// 0.016702 down:space
+/// 0.016654 up:space touch:start:0:120.5:84
+/// ```
+pub struct Replay {
+    /// Whether input is recorded or played back.
+    mode: ReplayMode,
+    /// The input received since the previous update while recording.
+    pending: Vec<InputEvent>,
+    /// The storage key the recording is saved to when the game is closed.
+    save_on_shutdown: Option<String>,
+}
+}
+
+impl Replay {
+    /// Create without recording or playing back.
+    pub fn new() -> Self {
+        Self {
+            mode: ReplayMode::Off,
+            pending: vec![],
+            save_on_shutdown: None,
+        }
+    }
+
+    /// Start recording, discarding a previous recording.
+    pub fn start_recording(&mut self) {
+        let random = Random::borrow();
+        self.mode = ReplayMode::Recording((random.seed(), random.state()), vec![]);
+        self.pending.clear();
+    }
+
+    /// Record every update from the start and save it to the storage when the game is closed.
+    pub fn record_until_shutdown<K>(&mut self, key: K)
+    where
+        K: Into<String>,
+    {
+        self.start_recording();
+        self.save_on_shutdown = Some(key.into());
+    }
+
+    /// Stop recording, returns the log of the recording.
+    pub fn stop_recording(&mut self) -> Option<String> {
+        match mem::replace(&mut self.mode, ReplayMode::Off) {
+            ReplayMode::Recording(random, frames) => Some(to_log(random, &frames)),
+            mode => {
+                self.mode = mode;
+
+                None
+            }
+        }
+    }
+
+    /// Play back a recorded log, starting at the next update.
+    pub fn play(&mut self, log: &str) -> Result<()> {
+        let (random, frames) = from_log(log)?;
+        if let Some((seed, state)) = random {
+            Random::borrow_mut().restore(seed, state);
+        }
+
+        self.mode = ReplayMode::Playing(frames.into());
+        self.pending.clear();
+
+        Ok(())
+    }
+
+    /// Play back recorded frames, starting at the next update.
+    ///
+    /// The random number generator isn't restored, the game must already be in the state it had
+    /// before the first frame. A recording that's in progress is discarded.
+    pub fn play_frames(&mut self, frames: Vec<ReplayFrame>) {
+        self.mode = ReplayMode::Playing(frames.into());
+        self.pending.clear();
+    }
+
+    /// Whether a recording is played back, the input of the player must be ignored.
+    pub fn is_playing(&self) -> bool {
+        matches!(self.mode, ReplayMode::Playing(_))
+    }
+
+    /// Store input that reached the game if recording.
+    pub fn record(&mut self, event: InputEvent) {
+        if let ReplayMode::Recording(..) = self.mode {
+            self.pending.push(event);
+        }
+    }
+
+    /// Start a new update with the time that passed since the previous one.
+    ///
+    /// Returns the time that must be used for the update with the recorded input to feed into
+    /// the engine while playing back.
+    pub fn next_frame(&mut self, delta: f64) -> (f64, Vec<InputEvent>) {
+        match self.mode {
+            ReplayMode::Off => (delta, vec![]),
+            ReplayMode::Recording(_, ref mut frames) => {
+                frames.push(ReplayFrame {
+                    delta,
+                    events: mem::take(&mut self.pending),
+                });
+
+                (delta, vec![])
+            }
+            ReplayMode::Playing(ref mut frames) => match frames.pop_front() {
+                Some(frame) => (frame.delta, frame.events),
+                None => {
+                    // The player takes over when the recording is finished
+                    self.mode = ReplayMode::Off;
+
+                    (delta, vec![])
+                }
+            },
+        }
+    }
+
+    /// Save the recording if it was started with `record_until_shutdown`.
+    pub fn save_on_shutdown(&mut self) -> Result<()> {
+        let key = match self.save_on_shutdown.take() {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+
+        match self.stop_recording() {
+            Some(log) => Storage::borrow().write(&key, &log),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop recording from a script and save the log in the storage.
+    ///
+    /// ```gamelisp
+    /// (start-recording)
+    /// ; ...
+    /// (save-recording "bug-1234")
+    /// ```
+    fn save_recording(&mut self, key: &str) -> GResult<()> {
+        let log = match self.stop_recording() {
+            Some(log) => log,
+            None => bail!("input isn't being recorded"),
+        };
+
+        match Storage::borrow().write(key, &log) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("saving recording \"{}\" failed: {}", key, err),
+        }
+    }
+
+    /// Play back a recording from the storage from a script.
+    ///
+    /// ```gamelisp
+    /// (play-recording "bug-1234")
+    /// ```
+    fn play_recording(&mut self, key: &str) -> GResult<()> {
+        let log = match Storage::borrow().read(key) {
+            Ok(Some(log)) => log,
+            Ok(None) => bail!("recording \"{}\" doesn't exist", key),
+            Err(err) => bail!("reading recording \"{}\" failed: {}", key, err),
+        };
+
+        match self.play(&log) {
+            Ok(()) => Ok(()),
+            Err(err) => bail!("playing recording \"{}\" failed: {}", key, err),
+        }
+    }
+
+    /// Register the replay & bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Replay::new());
+
+            glsp::bind_rfn("start-recording", rfn!(Replay::start_recording))?;
+            glsp::bind_rfn("save-recording", rfn!(Replay::save_recording))?;
+            glsp::bind_rfn("play-recording", rfn!(Replay::play_recording))?;
+            glsp::bind_rfn("playing-recording?", rfn!(Replay::is_playing))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// Convert the seed & state of the random number generator and the recorded frames to the text
+/// format.
+fn to_log((seed, state): (u64, u64), frames: &[ReplayFrame]) -> String {
+    let mut log = HEADER.to_string();
+    log.push_str(&format!("\nseed:{}:{}", seed, state));
+
+    for frame in frames {
+        log.push_str(&format!("\n{:.6}", frame.delta));
+
+        for event in &frame.events {
+            let event = match event {
+                InputEvent::KeyDown(key) => format!("down:{}", key),
+                InputEvent::KeyUp(key) => format!("up:{}", key),
+                // The code point is stored so whitespace doesn't split the event
+                InputEvent::Char(character) => format!("char:{}", *character as u32),
+                InputEvent::MouseDelta { dx, dy } => format!("delta:{}:{}", dx, dy),
+                InputEvent::Touch { phase, id, x, y } => {
+                    let phase = match phase {
+                        TouchPhase::Started => "start",
+                        TouchPhase::Moved => "move",
+                        TouchPhase::Ended => "end",
+                        TouchPhase::Cancelled => "cancel",
+                    };
+
+                    format!("touch:{}:{}:{}:{}", phase, id, x, y)
+                }
+            };
+
+            log.push(' ');
+            log.push_str(&event);
+        }
+    }
+    log.push('\n');
+
+    log
+}
+
+/// Parse the seed & state of the random number generator, if stored, and the frames from the
+/// text format.
+fn from_log(log: &str) -> Result<(Option<(u64, u64)>, Vec<ReplayFrame>)> {
+    let mut lines = log.lines();
+    let random = match lines.next().map(str::trim) {
+        Some(HEADER) => {
+            let fields: Vec<&str> = lines
+                .next()
+                .map_or(vec![], |line| line.trim().split(':').collect());
+
+            match fields.as_slice() {
+                ["seed", seed, state] => Some((
+                    seed.parse().map_err(|_| anyhow!("invalid replay seed"))?,
+                    state.parse().map_err(|_| anyhow!("invalid replay seed"))?,
+                )),
+                _ => return Err(anyhow!("replay log doesn't contain a seed")),
+            }
+        }
+        Some(HEADER_WITHOUT_SEED) => None,
+        _ => return Err(anyhow!("replay log doesn't start with \"{}\"", HEADER)),
+    };
+
+    let frames = lines
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(index, line)| {
+            let error = |what: &str| anyhow!("invalid {} in replay frame {}", what, index);
+
+            let mut parts = line.split_whitespace();
+            let delta = parts
+                .next()
+                .and_then(|delta| delta.parse().ok())
+                .ok_or_else(|| error("time"))?;
+
+            let events = parts
+                .map(|event| {
+                    let fields: Vec<&str> = event.split(':').collect();
+
+                    Ok(match fields.as_slice() {
+                        ["down", key] => InputEvent::KeyDown(key.to_string()),
+                        ["up", key] => InputEvent::KeyUp(key.to_string()),
+                        ["char", code] => InputEvent::Char(
+                            code.parse()
+                                .ok()
+                                .and_then(std::char::from_u32)
+                                .ok_or_else(|| error("character"))?,
+                        ),
+                        ["delta", dx, dy] => InputEvent::MouseDelta {
+                            dx: dx.parse().map_err(|_| error("mouse distance"))?,
+                            dy: dy.parse().map_err(|_| error("mouse distance"))?,
+                        },
+                        ["touch", phase, id, x, y] => InputEvent::Touch {
+                            phase: match *phase {
+                                "start" => TouchPhase::Started,
+                                "move" => TouchPhase::Moved,
+                                "end" => TouchPhase::Ended,
+                                "cancel" => TouchPhase::Cancelled,
+                                _ => return Err(error("touch phase")),
+                            },
+                            id: id.parse().map_err(|_| error("touch identifier"))?,
+                            x: x.parse().map_err(|_| error("touch position"))?,
+                            y: y.parse().map_err(|_| error("touch position"))?,
+                        },
+                        _ => return Err(error("event")),
+                    })
+                })
+                .collect::<Result<_>>()?;
+
+            Ok(ReplayFrame { delta, events })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok((random, frames))
+}
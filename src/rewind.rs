@@ -0,0 +1,287 @@
+use crate::{
+    random::Random,
+    render::{Instance, InstanceRef, Render},
+    replay::{InputEvent, Replay, ReplayFrame},
+    sandbox,
+};
+use glsp::{bail, lib, rfn, GFn, GResult, Root, Runtime, Val};
+use std::collections::VecDeque;
+
+/// The state of the game at the end of an update.
+#[derive(Debug)]
+struct Snapshot {
+    /// The seconds of game time the update took.
+    delta: f64,
+    /// The input that reached the game during the update.
+    events: Vec<InputEvent>,
+    /// The seed & state of the random number generator.
+    random: (u64, u64),
+    /// The values returned by the capture functions, in the order they were tracked.
+    values: Vec<Val>,
+    /// The transforms of the tracked instances that still existed.
+    instances: Vec<(InstanceRef, Instance)>,
+}
+
+/// A pair of functions storing & restoring part of the state of a script.
+#[derive(Debug, Clone)]
+struct Tracked {
+    capture: Root<GFn>,
+    restore: Root<GFn>,
+}
+
+lib! {
+/// A ring buffer with the input & the selected state of the last seconds of updates, for rewind
+/// mechanics & instant replays.
+///
+/// The state consists of the transforms of tracked instances, the random number generator & the
+/// values returned by tracked capture functions, which get them back when rewinding. The values
+/// should be copies, changing a table after it's captured changes the snapshot too. An instant
+/// replay rewinds & then plays the input of the rewound updates back, so a deterministic game
+/// plays out the same way again.
+///
+/// ```gamelisp
+/// (start-rewind 10.0)
+/// (track-rewind-instance player)
+/// (track-rewind
+///   (fn () (deep-clone inventory))
+///   (fn (saved) (= inventory saved)))
+///
+/// (defn engine:update ()
+///   (when (action-down? 'rewind)
+///     (rewind (/ 1.0 60.0))))
+/// ```
+pub struct Rewind {
+    /// How many seconds of updates are kept, nothing is stored when it's zero.
+    duration: f64,
+    /// The snapshots of the last updates, the oldest first.
+    snapshots: VecDeque<Snapshot>,
+    /// The seconds of game time of all snapshots.
+    stored: f64,
+    /// The input that reached the game since the previous update.
+    pending: Vec<InputEvent>,
+    tracked: Vec<Tracked>,
+    tracked_instances: Vec<InstanceRef>,
+}
+}
+
+impl Rewind {
+    /// Create without storing anything.
+    pub fn new() -> Self {
+        Self {
+            duration: 0.0,
+            snapshots: VecDeque::new(),
+            stored: 0.0,
+            pending: vec![],
+            tracked: vec![],
+            tracked_instances: vec![],
+        }
+    }
+
+    /// Whether updates are stored.
+    pub fn is_enabled(&self) -> bool {
+        self.duration > 0.0
+    }
+
+    /// Store input that reached the game if enabled.
+    pub fn record(&mut self, event: &InputEvent) {
+        if self.is_enabled() {
+            self.pending.push(event.clone());
+        }
+    }
+
+    /// Store the state at the end of an update, dropping the snapshots that are too old.
+    ///
+    /// Must be called inside the GameLisp runtime without the rewind being borrowed.
+    pub fn capture(delta: f64) -> GResult<()> {
+        let (tracked, instances) = {
+            let rewind = Rewind::borrow();
+            if !rewind.is_enabled() {
+                return Ok(());
+            }
+
+            (rewind.tracked.clone(), rewind.tracked_instances.clone())
+        };
+
+        // Don't hold the borrow while calling the scripts
+        let values = tracked
+            .iter()
+            .map(|tracked| sandbox::call(&tracked.capture, &()))
+            .collect::<GResult<Vec<Val>>>()?;
+        let instances = {
+            let render = Render::borrow();
+            instances
+                .into_iter()
+                .filter_map(|instance| Some((instance, *render.instance(instance)?)))
+                .collect()
+        };
+        let random = {
+            let random = Random::borrow();
+
+            (random.seed(), random.state())
+        };
+
+        let mut rewind = Rewind::borrow_mut();
+        let events = std::mem::take(&mut rewind.pending);
+        rewind.snapshots.push_back(Snapshot {
+            delta,
+            events,
+            random,
+            values,
+            instances,
+        });
+        rewind.stored += delta;
+
+        // Keep the oldest snapshot that's needed to go back the full duration
+        while rewind.snapshots.len() > 1
+            && rewind.stored - rewind.snapshots[0].delta >= rewind.duration
+        {
+            if let Some(dropped) = rewind.snapshots.pop_front() {
+                rewind.stored -= dropped.delta;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Go back in time, returns the seconds that were rewound & the rewound updates.
+    ///
+    /// The oldest snapshot is always kept, so less time is rewound when not enough is stored.
+    /// Must be called inside the GameLisp runtime without the rewind, the render or the random
+    /// number generator being borrowed.
+    fn rewind_by(seconds: f64) -> GResult<(f64, Vec<ReplayFrame>)> {
+        let (rewound, frames, tracked, snapshot) = {
+            let mut rewind = Rewind::borrow_mut();
+            let mut rewound = 0.0;
+            let mut frames = vec![];
+            while rewound < seconds && rewind.snapshots.len() > 1 {
+                if let Some(snapshot) = rewind.snapshots.pop_back() {
+                    rewound += snapshot.delta;
+                    rewind.stored -= snapshot.delta;
+                    frames.push(ReplayFrame {
+                        delta: snapshot.delta,
+                        events: snapshot.events,
+                    });
+                }
+            }
+            frames.reverse();
+
+            let snapshot = match rewind.snapshots.back() {
+                Some(snapshot) => (
+                    snapshot.random,
+                    snapshot.values.clone(),
+                    snapshot.instances.clone(),
+                ),
+                None => bail!("nothing is stored to rewind, call start-rewind first"),
+            };
+            rewind.pending.clear();
+
+            (rewound, frames, rewind.tracked.clone(), snapshot)
+        };
+        let ((seed, state), values, instances) = snapshot;
+
+        Random::borrow_mut().restore(seed, state);
+        {
+            let mut render = Render::borrow_mut();
+            for (instance, data) in instances {
+                if let Some(current) = render.instance_mut(instance) {
+                    *current = data;
+                }
+            }
+        }
+
+        // Functions tracked after the snapshot was taken have nothing to restore
+        for (tracked, value) in tracked.iter().zip(values) {
+            let _: Val = sandbox::call(&tracked.restore, &(value,))?;
+        }
+
+        Ok((rewound, frames))
+    }
+
+    /// Start storing the last seconds of updates from a script.
+    ///
+    /// ```gamelisp
+    /// (start-rewind 5.0)
+    /// ```
+    fn start_rewind(&mut self, seconds: f64) -> GResult<()> {
+        if seconds <= 0.0 {
+            bail!("rewind duration must be positive, got {}", seconds);
+        }
+        self.duration = seconds;
+
+        Ok(())
+    }
+
+    /// Stop storing updates from a script, discarding the snapshots.
+    fn stop_rewind(&mut self) {
+        self.duration = 0.0;
+        self.snapshots.clear();
+        self.stored = 0.0;
+        self.pending.clear();
+    }
+
+    /// Capture a value every update & restore it when rewinding from a script.
+    fn track_rewind(&mut self, capture: Root<GFn>, restore: Root<GFn>) {
+        self.tracked.push(Tracked { capture, restore });
+    }
+
+    /// Capture the transform of an instance every update & restore it when rewinding from a
+    /// script.
+    fn track_rewind_instance(&mut self, instance: &InstanceRef) {
+        if !self.tracked_instances.contains(instance) {
+            self.tracked_instances.push(*instance);
+        }
+    }
+
+    /// The seconds that can be rewound from a script.
+    fn rewind_available(&self) -> f64 {
+        // The oldest snapshot is the state that's restored, its own update can't be undone
+        self.snapshots
+            .front()
+            .map_or(0.0, |oldest| self.stored - oldest.delta)
+    }
+
+    /// Go back in time from a script, returns the seconds that were rewound.
+    ///
+    /// ```gamelisp
+    /// (rewind 3.0)
+    /// ```
+    fn rewind(seconds: f64) -> GResult<f64> {
+        let (rewound, _) = Rewind::rewind_by(seconds)?;
+
+        Ok(rewound)
+    }
+
+    /// Go back in time & play the input of the rewound updates back from a script, returns the
+    /// seconds that are replayed.
+    ///
+    /// The input of the player is ignored during the replay, `playing-recording?` is true until
+    /// it's done.
+    ///
+    /// ```gamelisp
+    /// (when goal-scored
+    ///   (instant-replay 5.0))
+    /// ```
+    fn instant_replay(seconds: f64) -> GResult<f64> {
+        let (rewound, frames) = Rewind::rewind_by(seconds)?;
+        Replay::borrow_mut().play_frames(frames);
+
+        Ok(rewound)
+    }
+
+    /// Register the rewind and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Rewind::new());
+
+            glsp::bind_rfn("start-rewind", rfn!(Rewind::start_rewind))?;
+            glsp::bind_rfn("stop-rewind", rfn!(Rewind::stop_rewind))?;
+            glsp::bind_rfn("track-rewind", rfn!(Rewind::track_rewind))?;
+            glsp::bind_rfn("track-rewind-instance", rfn!(Rewind::track_rewind_instance))?;
+            glsp::bind_rfn("rewind-available", rfn!(Rewind::rewind_available))?;
+            glsp::bind_rfn("rewind", rfn!(Rewind::rewind))?;
+            glsp::bind_rfn("instant-replay", rfn!(Rewind::instant_replay))?;
+
+            Ok(())
+        });
+    }
+}
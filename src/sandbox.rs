@@ -0,0 +1,321 @@
+use glsp::{
+    bail, lib, rfn, Arr, CallableOps, FromVal, GResult, IntoCallArgs, Rest, Root, Runtime,
+    RuntimeBuilder, Val,
+};
+
+/// The looping macros that check the watchdog at the start of every iteration, with the amount
+/// of forms before their body.
+const WATCHED_LOOPS: &[(&str, usize)] = &[
+    ("loop", 0),
+    ("while", 1),
+    ("until", 1),
+    ("forn", 1),
+    ("forni", 1),
+];
+/// After how many iterations of loops the watchdog looks at the clock again.
+const WATCHDOG_INTERVAL: u32 = 256;
+
+/// Options for the GameLisp runtime, restricting what scripts can do for running untrusted mods.
+///
+/// ```rust
+/// use clogs::RuntimeOptions;
+/// # fn main() {
+/// let options = RuntimeOptions::new()
+///     .sandboxed(true)
+///     .gc_ratio(1.2)
+///     .max_heap_bytes(64 * 1024 * 1024)
+///     .max_call_time(0.1);
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeOptions {
+    /// Whether scripts are denied access to the filesystem.
+    sandboxed: bool,
+    /// How aggressively the garbage collector runs, `None` for the GameLisp default.
+    gc_ratio: Option<f32>,
+    /// The maximum amount of bytes the scripts can allocate.
+    max_heap_bytes: Option<usize>,
+    /// The maximum amount of seconds a single script function called by the engine can take.
+    max_call_time: Option<f64>,
+}
+
+impl RuntimeOptions {
+    /// Options without any restrictions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deny scripts access to the filesystem.
+    ///
+    /// The GameLisp functions loading files like `load` & `require` aren't available, scripts
+    /// can only be loaded when added with `Clog::add_script`, and assets & screenshots can't be
+    /// loaded or saved from scripts. Saving state with `save-state` stays available.
+    pub fn sandboxed(mut self, sandboxed: bool) -> Self {
+        self.sandboxed = sandboxed;
+
+        self
+    }
+
+    /// Set how much the heap can grow before the garbage collector runs, as a ratio of the
+    /// memory that's in use.
+    ///
+    /// Lower values use less memory at the cost of more time spent collecting.
+    pub fn gc_ratio(mut self, ratio: f32) -> Self {
+        self.gc_ratio = Some(ratio);
+
+        self
+    }
+
+    /// Set the maximum amount of bytes the scripts can allocate.
+    ///
+    /// The memory is checked after every script function called by the engine, when the scripts
+    /// use more the game halts with an error.
+    pub fn max_heap_bytes(mut self, bytes: usize) -> Self {
+        self.max_heap_bytes = Some(bytes);
+
+        self
+    }
+
+    /// Set the maximum amount of seconds a single script function called by the engine can take.
+    ///
+    /// A watchdog checked by every iteration of the looping macros `loop`, `while`, `until`,
+    /// `for`, `forn` & `forni` aborts a function that runs longer, halting the game with an
+    /// error, so runaway loops in mods can't hang the game. Functions that return are checked as
+    /// well, unbounded recursion is stopped by the recursion limit of GameLisp.
+    pub fn max_call_time(mut self, seconds: f64) -> Self {
+        self.max_call_time = Some(seconds);
+
+        self
+    }
+
+    /// Create the runtime with these options.
+    pub(crate) fn build_runtime(&self) -> Runtime {
+        let runtime = RuntimeBuilder::new().sandboxed(self.sandboxed).build();
+
+        runtime.run(|| {
+            if let Some(ratio) = self.gc_ratio {
+                glsp::gc_set_ratio(ratio);
+            }
+
+            glsp::add_lib(Sandbox {
+                sandboxed: self.sandboxed,
+                max_heap_bytes: self.max_heap_bytes,
+                max_call_time: self.max_call_time,
+                call_started: None,
+                iterations: 0,
+            });
+
+            if self.max_call_time.is_some() {
+                watch_loops()?;
+            }
+
+            Ok(())
+        });
+
+        runtime
+    }
+}
+
+lib! {
+/// The restrictions of the runtime, checked by the engine.
+pub struct Sandbox {
+    /// Whether scripts are denied access to the filesystem.
+    sandboxed: bool,
+    /// The maximum amount of bytes the scripts can allocate.
+    max_heap_bytes: Option<usize>,
+    /// The maximum amount of seconds a single script function can take.
+    max_call_time: Option<f64>,
+    /// When the script function called by the engine that's running started.
+    call_started: Option<f64>,
+    /// How many loop iterations passed since the watchdog looked at the clock.
+    iterations: u32,
+}
+}
+
+impl Sandbox {
+    /// Fail when scripts are denied access to the filesystem, describing what was denied.
+    pub fn deny_filesystem(&self, action: &str) -> GResult<()> {
+        if self.sandboxed {
+            bail!("{} isn't allowed in the sandbox", action);
+        }
+
+        Ok(())
+    }
+
+    /// Start the watchdog for a script function called by the engine.
+    fn begin_call(&mut self, started: f64) {
+        self.call_started = Some(started);
+        self.iterations = 0;
+    }
+
+    /// Stop the watchdog after the script function called by the engine returned.
+    fn end_call(&mut self) {
+        self.call_started = None;
+    }
+
+    /// Abort the running script function when it takes longer than allowed, called by every
+    /// iteration of the watched loops.
+    fn watchdog(&mut self) -> GResult<()> {
+        let (started, max_call_time) = match (self.call_started, self.max_call_time) {
+            (Some(started), Some(max_call_time)) => (started, max_call_time),
+            _ => return Ok(()),
+        };
+
+        // Looking at the clock is slow compared to a loop iteration
+        self.iterations += 1;
+        if self.iterations < WATCHDOG_INTERVAL {
+            return Ok(());
+        }
+        self.iterations = 0;
+
+        let duration = miniquad::date::now() - started;
+        if duration > max_call_time {
+            bail!(
+                "script aborted after {:.1} ms, more than the maximum of {:.1} ms",
+                duration * 1000.0,
+                max_call_time * 1000.0
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Fail when a script function that started at a time exceeded the limits.
+    fn check_limits(&self, started: f64) -> GResult<()> {
+        if let Some(max_call_time) = self.max_call_time {
+            let duration = miniquad::date::now() - started;
+            if duration > max_call_time {
+                bail!(
+                    "script took {:.1} ms, more than the maximum of {:.1} ms",
+                    duration * 1000.0,
+                    max_call_time * 1000.0
+                );
+            }
+        }
+
+        if let Some(max_heap_bytes) = self.max_heap_bytes {
+            let heap_bytes = glsp::gc_young_bytes() + glsp::gc_old_bytes();
+            if heap_bytes > max_heap_bytes {
+                bail!(
+                    "scripts use {} KiB, more than the maximum of {} KiB",
+                    heap_bytes / 1024,
+                    max_heap_bytes / 1024
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Run scripts called by the engine with the watchdog, failing when they exceeded the limits.
+///
+/// Every script the engine runs goes through here, the update functions as well as the functions
+/// of events, timers, tasks & the other callbacks. Functions called while a script is running,
+/// like the function passed to `for-each-with`, count towards the limits of that script.
+pub fn guard<F, R>(run: F) -> GResult<R>
+where
+    F: FnOnce() -> GResult<R>,
+{
+    if Sandbox::borrow().call_started.is_some() {
+        return run();
+    }
+
+    let started = miniquad::date::now();
+    Sandbox::borrow_mut().begin_call(started);
+    let result = run();
+    Sandbox::borrow_mut().end_call();
+    let result = result?;
+    Sandbox::borrow().check_limits(started)?;
+
+    Ok(result)
+}
+
+/// Call a script function from the engine with the watchdog, see [`guard`].
+pub fn call<C, A, R>(function: &C, args: A) -> GResult<R>
+where
+    C: CallableOps,
+    A: IntoCallArgs,
+    R: FromVal,
+{
+    guard(|| glsp::call(function, args))
+}
+
+/// Replace the looping macros with ones calling the watchdog at the start of every iteration.
+///
+/// The original macros stay available with a `%unwatched-` prefix, the replacements expand to
+/// them with the call inserted before the body.
+fn watch_loops() -> GResult<()> {
+    glsp::bind_rfn("%watchdog", rfn!(Sandbox::watchdog))?;
+
+    for (name, _) in WATCHED_LOOPS.iter().chain(&[("for", 0)]) {
+        let expander = glsp::get_macro(*name)?;
+        glsp::bind_macro(glsp::sym(&format!("%unwatched-{}", name))?, expander)?;
+        glsp::del_macro(*name)?;
+    }
+
+    glsp::bind_rfn_macro("loop", rfn!(watched_loop))?;
+    glsp::bind_rfn_macro("while", rfn!(watched_while))?;
+    glsp::bind_rfn_macro("until", rfn!(watched_until))?;
+    glsp::bind_rfn_macro("forn", rfn!(watched_forn))?;
+    glsp::bind_rfn_macro("forni", rfn!(watched_forni))?;
+    glsp::bind_rfn_macro("for", rfn!(watched_for))?;
+
+    Ok(())
+}
+
+/// Expand to the original looping macro with the watchdog called before the body, which starts
+/// after the first forms.
+fn watched(name: &str, head: usize, args: &[Val]) -> GResult<Root<Arr>> {
+    if args.len() < head {
+        bail!("invalid {} loop", name);
+    }
+
+    let form = glsp::arr();
+    form.push(glsp::sym(&format!("%unwatched-{}", name))?)?;
+    for arg in &args[..head] {
+        form.push(arg)?;
+    }
+    form.push(glsp::arr_from_iter(vec![glsp::sym("%watchdog")?])?)?;
+    for arg in &args[head..] {
+        form.push(arg)?;
+    }
+
+    Ok(form)
+}
+
+/// `loop` calling the watchdog every iteration.
+fn watched_loop(args: Rest<Val>) -> GResult<Root<Arr>> {
+    watched("loop", WATCHED_LOOPS[0].1, &args)
+}
+
+/// `while` calling the watchdog every iteration.
+fn watched_while(args: Rest<Val>) -> GResult<Root<Arr>> {
+    watched("while", WATCHED_LOOPS[1].1, &args)
+}
+
+/// `until` calling the watchdog every iteration.
+fn watched_until(args: Rest<Val>) -> GResult<Root<Arr>> {
+    watched("until", WATCHED_LOOPS[2].1, &args)
+}
+
+/// `forn` calling the watchdog every iteration.
+fn watched_forn(args: Rest<Val>) -> GResult<Root<Arr>> {
+    watched("forn", WATCHED_LOOPS[3].1, &args)
+}
+
+/// `forni` calling the watchdog every iteration.
+fn watched_forni(args: Rest<Val>) -> GResult<Root<Arr>> {
+    watched("forni", WATCHED_LOOPS[4].1, &args)
+}
+
+/// `for` calling the watchdog every iteration, the body starts after `pattern in iterable`.
+fn watched_for(args: Rest<Val>) -> GResult<Root<Arr>> {
+    let in_sym = glsp::sym("in")?;
+    let head = match args.iter().position(|arg| *arg == Val::Sym(in_sym)) {
+        Some(index) => index + 2,
+        None => bail!("invalid for loop: expected (for pattern in iterable ...)"),
+    };
+
+    watched("for", head, &args)
+}
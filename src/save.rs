@@ -0,0 +1,166 @@
+use crate::storage::Storage;
+use glsp::{bail, rfn, DequeOps, GResult, Root, Runtime, Tab, Val};
+use serde_json::{json, Map, Number, Value};
+
+/// Prefix of the JSON strings that are GameLisp symbols.
+///
+/// Strings that start with this prefix are escaped by doubling it.
+const SYMBOL_PREFIX: char = '\'';
+
+/// Convert a GameLisp value to JSON.
+///
+/// Only data can be converted, functions & objects result in an error.
+pub fn to_json(val: &Val) -> GResult<Value> {
+    Ok(match val {
+        Val::Nil => Value::Null,
+        Val::Bool(b) => Value::Bool(*b),
+        Val::Int(i) => Value::Number((*i).into()),
+        Val::Flo(f) => match Number::from_f64(*f as f64) {
+            Some(number) => Value::Number(number),
+            None => bail!("can't save non-finite number {}", f),
+        },
+        Val::Char(c) => Value::String(escape_string(&c.to_string())),
+        Val::Str(s) => Value::String(escape_string(&s.to_string())),
+        Val::Sym(sym) => Value::String(format!("{}{}", SYMBOL_PREFIX, sym.name())),
+        Val::Arr(arr) => Value::Array(
+            arr.iter()
+                .map(|val| to_json(&val))
+                .collect::<GResult<_>>()?,
+        ),
+        Val::Tab(tab) => {
+            let mut map = Map::new();
+            for (key, val) in tab.entries().iter() {
+                let key = match key {
+                    Val::Str(s) => escape_string(&s.to_string()),
+                    Val::Sym(sym) => format!("{}{}", SYMBOL_PREFIX, sym.name()),
+                    _ => bail!(
+                        "can't save table key {}, only strings & symbols are allowed",
+                        key
+                    ),
+                };
+                map.insert(key, to_json(&val)?);
+            }
+
+            Value::Object(map)
+        }
+        _ => bail!("can't save value {}", val),
+    })
+}
+
+/// Convert JSON to a GameLisp value.
+pub fn from_json(value: &Value) -> GResult<Val> {
+    Ok(match value {
+        Value::Null => Val::Nil,
+        Value::Bool(b) => Val::Bool(*b),
+        Value::Number(number) => match number.as_i64() {
+            Some(i) if i >= i32::MIN as i64 && i <= i32::MAX as i64 => Val::Int(i as i32),
+            _ => Val::Flo(number.as_f64().unwrap_or(0.0) as f32),
+        },
+        Value::String(s) => string_from_json(s)?,
+        Value::Array(array) => Val::Arr(glsp::arr_from_iter(
+            array.iter().map(from_json).collect::<GResult<Vec<_>>>()?,
+        )?),
+        Value::Object(map) => {
+            let tab = glsp::tab();
+            for (key, value) in map {
+                tab.set(string_from_json(key)?, from_json(value)?)?;
+            }
+
+            Val::Tab(tab)
+        }
+    })
+}
+
+/// Escape strings that would otherwise be read back as symbols.
+fn escape_string(s: &str) -> String {
+    if s.starts_with(SYMBOL_PREFIX) {
+        format!("{}{}", SYMBOL_PREFIX, s)
+    } else {
+        s.to_string()
+    }
+}
+
+/// Convert a JSON string to either a GameLisp string or symbol.
+fn string_from_json(s: &str) -> GResult<Val> {
+    let mut chars = s.chars();
+
+    Ok(match (chars.next(), chars.next()) {
+        // An escaped string
+        (Some(SYMBOL_PREFIX), Some(SYMBOL_PREFIX)) => Val::Str(glsp::str_from_rust_str(&s[1..])),
+        // A symbol
+        (Some(SYMBOL_PREFIX), _) => Val::Sym(glsp::sym(&s[1..])?),
+        _ => Val::Str(glsp::str_from_rust_str(s)),
+    })
+}
+
+/// Save a table, or the globals named by an array of symbols, to a save slot.
+///
+/// ```gamelisp
+/// (save-state "slot-1" (tab ('level 3) ('score 1200)))
+/// (save-state "slot-1" '(level score))
+/// ```
+fn save_state(storage: &Storage, slot: &str, data: Val) -> GResult<()> {
+    let state = match data {
+        Val::Tab(_) => json!({ "globals": false, "data": to_json(&data)? }),
+        Val::Arr(names) => {
+            let globals: Root<Tab> = glsp::tab();
+            for name in names.iter() {
+                match name {
+                    Val::Sym(sym) => globals.set(sym, glsp::global::<_, Val>(sym)?)?,
+                    _ => bail!("global names must be symbols, found {}", name),
+                }
+            }
+
+            json!({ "globals": true, "data": to_json(&Val::Tab(globals))? })
+        }
+        _ => bail!("save-state expects a table or an array of global names"),
+    };
+
+    if let Err(err) = storage.write(slot, &state.to_string()) {
+        bail!("writing save slot \"{}\" failed: {}", slot, err);
+    }
+
+    Ok(())
+}
+
+/// Load the data of a save slot, returns `#n` when nothing is saved in it.
+///
+/// When globals were saved they are restored, the data is returned as a table either way.
+fn load_state(storage: &Storage, slot: &str) -> GResult<Val> {
+    let contents = match storage.read(slot) {
+        Ok(Some(contents)) => contents,
+        Ok(None) => return Ok(Val::Nil),
+        Err(err) => bail!("reading save slot \"{}\" failed: {}", slot, err),
+    };
+    let state: Value = match serde_json::from_str(&contents) {
+        Ok(state) => state,
+        Err(err) => bail!("save slot \"{}\" is corrupt: {}", slot, err),
+    };
+
+    let data = from_json(&state["data"])?;
+    if state["globals"] == Value::Bool(true) {
+        if let Val::Tab(ref globals) = data {
+            for (name, val) in globals.entries().iter() {
+                if let Val::Sym(sym) = name {
+                    if glsp::has_global(sym)? {
+                        glsp::set_global(sym, val)?;
+                    } else {
+                        glsp::bind_global(sym, val)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+/// Bind the GameLisp functions for saving & loading the game state.
+pub fn bind_functions(runtime: &Runtime) {
+    runtime.run(|| {
+        glsp::bind_rfn("save-state", rfn!(save_state))?;
+        glsp::bind_rfn("load-state", rfn!(load_state))?;
+
+        Ok(())
+    });
+}
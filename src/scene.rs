@@ -0,0 +1,321 @@
+use crate::{assets::Assets, render::Render, sandbox};
+use glsp::{bail, lib, rfn, GFn, GResult, Root, Runtime, Sym, Val};
+use std::mem;
+
+/// A requested change to the scene stack.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Transition {
+    /// Put a new scene on top of the stack, pausing the current one.
+    Push(Sym),
+    /// Remove the topmost scene, resuming the one below it.
+    Pop,
+    /// Replace the topmost scene with another one.
+    Switch(Sym),
+}
+
+/// A scene on the stack.
+#[derive(Debug)]
+struct Scene {
+    name: Sym,
+    /// The reference names of the assets loaded for the scene, unloaded when it's removed.
+    assets: Vec<String>,
+}
+
+lib! {
+/// A stack of scenes, only the topmost scene receives update & render callbacks.
+///
+/// A scene is a symbol, the functions belonging to it are looked up by name:
+///
+/// ```gamelisp
+/// menu:enter
+/// menu:exit
+/// menu:pause
+/// menu:resume
+/// menu:update
+/// menu:render
+/// ```
+///
+/// All of them are optional. A scene is paused when another scene is pushed on top of it &
+/// resumed when that scene is popped again, switching scenes exits the old one & enters the new
+/// one without pausing anything. Assets loaded with `load-scene-asset` belong to the active scene
+/// & are unloaded when it's popped or switched away from.
+pub struct Scenes {
+    /// The scenes on the stack, the last one is the active scene.
+    stack: Vec<Scene>,
+    /// Transitions requested by scripts, applied at the start of the next update.
+    pending: Vec<Transition>,
+}
+}
+
+impl Scenes {
+    /// Create an empty scene stack.
+    pub fn new() -> Self {
+        Self {
+            stack: vec![],
+            pending: vec![],
+        }
+    }
+
+    /// Push a new scene on top of the stack.
+    pub fn push_scene(&mut self, scene: Sym) {
+        self.pending.push(Transition::Push(scene));
+    }
+
+    /// Pop the topmost scene from the stack.
+    pub fn pop_scene(&mut self) {
+        self.pending.push(Transition::Pop);
+    }
+
+    /// Replace the topmost scene with another one.
+    pub fn switch_scene(&mut self, scene: Sym) {
+        self.pending.push(Transition::Switch(scene));
+    }
+
+    /// The currently active scene.
+    pub fn active_scene(&self) -> Option<Sym> {
+        self.stack.last().map(|scene| scene.name)
+    }
+
+    /// Apply all requested transitions, calling the enter, exit, pause & resume functions of the
+    /// scenes.
+    ///
+    /// Must be called inside the GameLisp runtime.
+    pub fn apply_transitions() -> GResult<()> {
+        // Don't hold the borrow while calling the scripts, they might request new transitions
+        let pending = mem::take(&mut Scenes::borrow_mut().pending);
+
+        for transition in pending {
+            match transition {
+                Transition::Push(scene) => {
+                    let paused = Scenes::borrow().active_scene();
+                    if let Some(paused) = paused {
+                        Self::call_scene_function(paused, "pause")?;
+                    }
+
+                    Self::enter(scene)?;
+                }
+                Transition::Pop => {
+                    if Self::exit()? {
+                        let resumed = Scenes::borrow().active_scene();
+                        if let Some(resumed) = resumed {
+                            Self::call_scene_function(resumed, "resume")?;
+                        }
+                    }
+                }
+                Transition::Switch(scene) => {
+                    Self::exit()?;
+                    Self::enter(scene)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Push a scene on the stack & call its enter function.
+    fn enter(scene: Sym) -> GResult<()> {
+        Scenes::borrow_mut().stack.push(Scene {
+            name: scene,
+            assets: vec![],
+        });
+
+        Self::call_scene_function(scene, "enter")
+    }
+
+    /// Call the exit function of the topmost scene & remove it with its assets, returns whether
+    /// there was a scene.
+    fn exit() -> GResult<bool> {
+        let scene = match Scenes::borrow().active_scene() {
+            Some(scene) => scene,
+            None => return Ok(false),
+        };
+        // The exit function can still use the assets of the scene
+        Self::call_scene_function(scene, "exit")?;
+
+        let popped = Scenes::borrow_mut().stack.pop();
+        if let Some(popped) = popped {
+            if !popped.assets.is_empty() {
+                let mut assets = Assets::borrow_mut();
+                let mut render = Render::borrow_mut();
+                for asset in popped.assets {
+                    assets.unload(&asset, &mut render);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// The global name of a function belonging to a scene, e.g. `menu:update`.
+    pub fn function_name(scene: Sym, function: &str) -> String {
+        format!("{}:{}", scene, function)
+    }
+
+    /// Call a function of a scene if it's defined.
+    fn call_scene_function(scene: Sym, function: &str) -> GResult<()> {
+        if let Ok(Val::GFn(func)) = glsp::global(&*Self::function_name(scene, function)) {
+            let func: Root<GFn> = func;
+            let _: Val = sandbox::call(&func, &())?;
+        }
+
+        Ok(())
+    }
+
+    /// Queue an asset from a script that's unloaded when the active scene is removed from the
+    /// stack, the kind is determined by the file extension.
+    ///
+    /// Returns `false` like `load-asset` when the asset already exists, it isn't unloaded with
+    /// the scene then.
+    ///
+    /// ```gamelisp
+    /// (defn level:enter ()
+    ///   (load-scene-asset "boss" "boss.svg"))
+    /// ```
+    fn load_scene_asset(reference_name: &str, path: &str) -> GResult<bool> {
+        if Scenes::borrow().stack.is_empty() {
+            bail!(
+                "asset \"{}\" can't belong to a scene, no scene is active",
+                reference_name
+            );
+        }
+
+        let queued = Assets::borrow_mut().load_asset(reference_name, path)?;
+        if queued {
+            if let Some(scene) = Scenes::borrow_mut().stack.last_mut() {
+                scene.assets.push(reference_name.to_string());
+            }
+        }
+
+        Ok(queued)
+    }
+
+    /// Register the scene stack and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Scenes::new());
+
+            glsp::bind_rfn("push-scene", rfn!(Self::push_scene))?;
+            glsp::bind_rfn("pop-scene", rfn!(Self::pop_scene))?;
+            glsp::bind_rfn("switch-scene", rfn!(Self::switch_scene))?;
+            glsp::bind_rfn("active-scene", rfn!(Self::active_scene))?;
+            glsp::bind_rfn("load-scene-asset", rfn!(Self::load_scene_asset))?;
+
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::RuntimeOptions;
+
+    /// Scene functions recording when they're called.
+    const HOOKS: &str = r#"
+        (def calls (arr))
+        (defn menu:enter () (push! calls "menu:enter"))
+        (defn menu:exit () (push! calls "menu:exit"))
+        (defn menu:pause () (push! calls "menu:pause"))
+        (defn menu:resume () (push! calls "menu:resume"))
+        (defn pause:enter () (push! calls "pause:enter"))
+        (defn pause:exit () (push! calls "pause:exit"))
+        (defn pause:pause () (push! calls "pause:pause"))
+        (defn pause:resume () (push! calls "pause:resume"))
+    "#;
+
+    /// Create a runtime with the scene stack & the assets.
+    fn runtime() -> Runtime {
+        let runtime = RuntimeOptions::new().build_runtime();
+        Scenes::bind_functions(&runtime);
+        Assets::bind_functions(&runtime);
+        runtime.run(|| {
+            glsp::add_lib(Render::headless((320.0, 240.0), false));
+
+            Ok(())
+        });
+
+        runtime
+    }
+
+    /// Evaluate a script & apply the transitions it requested.
+    fn run_script(source: &str) -> GResult<()> {
+        glsp::eval_multi(&glsp::parse_all(source, None)?, None)?;
+
+        Scenes::apply_transitions()
+    }
+
+    /// The scene functions called by applying the transitions of a script, in order.
+    fn calls(transitions: &str) -> Vec<String> {
+        runtime()
+            .run(|| {
+                run_script(HOOKS)?;
+                run_script(transitions)?;
+
+                glsp::global("calls")
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn pushed_scenes_pause_the_active_scene() {
+        assert_eq!(
+            calls("(push-scene 'menu) (push-scene 'pause)"),
+            ["menu:enter", "menu:pause", "pause:enter"]
+        );
+    }
+
+    #[test]
+    fn popped_scenes_resume_the_scene_below() {
+        assert_eq!(
+            calls("(push-scene 'menu) (push-scene 'pause) (pop-scene)"),
+            [
+                "menu:enter",
+                "menu:pause",
+                "pause:enter",
+                "pause:exit",
+                "menu:resume"
+            ]
+        );
+    }
+
+    #[test]
+    fn switched_scenes_exit_without_pausing() {
+        assert_eq!(
+            calls("(push-scene 'menu) (switch-scene 'pause) (pop-scene)"),
+            ["menu:enter", "menu:exit", "pause:enter", "pause:exit"]
+        );
+    }
+
+    #[test]
+    fn removed_scenes_unload_their_assets() {
+        let loaded = runtime()
+            .run(|| {
+                run_script(r#"(load-asset "logo" "logo.png") (push-scene 'level)"#)?;
+                run_script(
+                    r#"(load-scene-asset "boss" "boss.png") (load-scene-asset "logo" "logo.png")"#,
+                )?;
+                let during = Assets::borrow().state("boss").is_some();
+                run_script("(pop-scene)")?;
+
+                let assets = Assets::borrow();
+                Ok((
+                    during,
+                    assets.state("boss").is_some(),
+                    assets.state("logo").is_some(),
+                ))
+            })
+            .unwrap();
+
+        // The logo was loaded before the scene, so it stays loaded
+        assert_eq!(loaded, (true, false, true));
+    }
+
+    #[test]
+    fn scene_assets_need_an_active_scene() {
+        let failed = runtime()
+            .run(|| Ok(Scenes::load_scene_asset("boss", "boss.png").is_err()))
+            .unwrap();
+
+        assert!(failed);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::sandbox::Sandbox;
+use glsp::{bail, lib, rfn, GResult, Runtime, Val};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+/// The name the main script is stored with.
+pub const MAIN_SCRIPT: &str = "main";
+/// The extension of script files with compiled bytecode.
+const COMPILED_EXTENSION: &str = ".glspc";
+
+/// The contents of a script.
+#[derive(Debug, Clone)]
+enum Source {
+    /// GameLisp source code.
+    Text(String),
+    /// Bytecode compiled with `Clog::compile_script`.
+    #[cfg(feature = "compiler")]
+    Compiled(Vec<u8>),
+}
+
+lib! {
+/// Loads the script files a game is split into, from the sources added to the engine or from
+/// the script directory.
+///
+/// Added sources take precedence over files, on WASM & in a sandboxed runtime only added sources
+/// can be loaded. Errors in a loaded script point at the file & line they are in. With the
+/// `compiler` feature files ending with `.glspc` are loaded as compiled bytecode.
+///
+/// ```gamelisp
+/// (require-script "enemies.glsp")
+/// (require-script "ui/menu.glsp")
+/// ```
+pub struct Scripts {
+    /// The directory the script files are loaded from.
+    root: PathBuf,
+    /// The sources added with `Clog::add_script` by their name.
+    sources: HashMap<String, Source>,
+    /// The scripts that were evaluated without errors.
+    loaded: HashSet<String>,
+    /// The scripts that are being evaluated, the outermost one first.
+    loading: Vec<String>,
+}
+}
+
+impl Scripts {
+    /// Create without any sources, loading the files from the working directory.
+    pub fn new() -> Self {
+        Self {
+            root: PathBuf::new(),
+            sources: HashMap::new(),
+            loaded: HashSet::new(),
+            loading: vec![],
+        }
+    }
+
+    /// Set the directory the script files are loaded from.
+    pub fn set_root<P>(&mut self, root: P)
+    where
+        P: Into<PathBuf>,
+    {
+        self.root = root.into();
+    }
+
+    /// The directory the script files are loaded from.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Add the source of a script so it can be loaded without reading a file.
+    pub fn add<N, S>(&mut self, name: N, source: S)
+    where
+        N: Into<String>,
+        S: Into<String>,
+    {
+        self.sources
+            .insert(name.into(), Source::Text(source.into()));
+    }
+
+    /// Add the compiled bytecode of a script so it can be loaded without reading a file.
+    #[cfg(feature = "compiler")]
+    pub fn add_compiled<N>(&mut self, name: N, bytecode: Vec<u8>)
+    where
+        N: Into<String>,
+    {
+        self.sources.insert(name.into(), Source::Compiled(bytecode));
+    }
+
+    /// Whether a script is added.
+    pub fn is_added(&self, name: &str) -> bool {
+        self.sources.contains_key(name)
+    }
+
+    /// Forget which scripts were loaded, so they are evaluated again when required.
+    pub fn forget_loaded(&mut self) {
+        self.loaded.clear();
+    }
+
+    /// The source of a script, from the added sources or the file in the script directory.
+    fn source(&self, name: &str) -> GResult<Source> {
+        if let Some(source) = self.sources.get(name) {
+            return Ok(source.clone());
+        }
+
+        Sandbox::borrow().deny_filesystem(&format!("reading script \"{}\"", name))?;
+
+        if name.ends_with(COMPILED_EXTENSION) {
+            #[cfg(not(feature = "compiler"))]
+            bail!(
+                "loading compiled script \"{}\" requires the `compiler` feature",
+                name
+            );
+
+            #[cfg(all(feature = "compiler", not(target_arch = "wasm32")))]
+            return match std::fs::read(self.root.join(name)) {
+                Ok(bytecode) => Ok(Source::Compiled(bytecode)),
+                Err(err) => bail!("reading compiled script \"{}\" failed: {}", name, err),
+            };
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        bail!(
+            "script \"{}\" isn't added with `Clog::add_script`, files in \"{}\" can't be read",
+            name,
+            self.root.display()
+        );
+
+        #[cfg(not(target_arch = "wasm32"))]
+        match std::fs::read_to_string(self.root.join(name)) {
+            Ok(source) => Ok(Source::Text(source)),
+            Err(err) => bail!("reading script \"{}\" failed: {}", name, err),
+        }
+    }
+
+    /// Evaluate a script, the result is the value of the last expression.
+    ///
+    /// Must be called inside the GameLisp runtime without the scripts being borrowed, since the
+    /// script can load other scripts.
+    pub fn load(name: &str) -> GResult<Val> {
+        let source = {
+            let mut scripts = Scripts::borrow_mut();
+            if scripts.loading.iter().any(|loading| loading == name) {
+                bail!(
+                    "script \"{}\" requires itself: {} -> {}",
+                    name,
+                    scripts.loading.join(" -> "),
+                    name
+                );
+            }
+
+            let source = scripts.source(name)?;
+            scripts.loading.push(name.to_string());
+
+            source
+        };
+
+        let result = match source {
+            // The name is passed so errors show the file & line
+            Source::Text(source) => glsp::parse_all(&source, Some(name))
+                .and_then(|forms| glsp::eval_multi(&forms, None)),
+            #[cfg(feature = "compiler")]
+            Source::Compiled(bytecode) => glsp::load_compiled(&bytecode),
+        };
+
+        let mut scripts = Scripts::borrow_mut();
+        scripts.loading.pop();
+        if result.is_ok() {
+            scripts.loaded.insert(name.to_string());
+        }
+
+        result
+    }
+
+    /// Evaluate a script if it wasn't loaded before, the result is `#n` when it was.
+    ///
+    /// Must be called inside the GameLisp runtime without the scripts being borrowed.
+    pub fn require(name: &str) -> GResult<Val> {
+        if Scripts::borrow().loaded.contains(name) {
+            return Ok(Val::Nil);
+        }
+
+        Scripts::load(name)
+    }
+
+    /// Evaluate a script from a script, every time it's called.
+    ///
+    /// ```gamelisp
+    /// (load-script "debug-level.glsp")
+    /// ```
+    fn load_script(name: &str) -> GResult<Val> {
+        Scripts::load(name)
+    }
+
+    /// Evaluate a script from a script if it wasn't loaded before.
+    ///
+    /// Scripts requiring each other result in an error.
+    ///
+    /// ```gamelisp
+    /// (require-script "player.glsp")
+    /// ```
+    fn require_script(name: &str) -> GResult<Val> {
+        Scripts::require(name)
+    }
+
+    /// Register the scripts and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Scripts::new());
+
+            glsp::bind_rfn("load-script", rfn!(Scripts::load_script))?;
+            glsp::bind_rfn("require-script", rfn!(Scripts::require_script))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// Compile a script file to bytecode that can be loaded with `load-script` or
+/// `Clog::main_script_compiled`, meant to be called from a build script.
+///
+/// The script is evaluated while compiling, so it should only define functions & values at the
+/// top level. Scripts loaded with `require-script` aren't part of the bytecode and must be
+/// compiled separately.
+///
+/// ```rust,no_run
+/// // build.rs
+/// fn main() -> anyhow::Result<()> {
+///     let out_dir = std::env::var("OUT_DIR")?;
+///     clogs::compile_script_file("scripts/main.glsp", format!("{}/main.glspc", out_dir))
+/// }
+/// ```
+#[cfg(feature = "compiler")]
+pub fn compile_script_file<P, Q>(input: P, output: Q) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let input = input.as_ref();
+    println!("cargo:rerun-if-changed={}", input.display());
+
+    let source = std::fs::read_to_string(input)?;
+    let name = input.to_string_lossy();
+    let bytecode = crate::Clog::new("compiling").compile_script(&name, &source)?;
+
+    std::fs::write(output, bytecode)?;
+
+    Ok(())
+}
@@ -0,0 +1,396 @@
+use crate::{
+    color::Color,
+    path,
+    render::{Mesh, Render, Vertex, VertexCtor},
+};
+use glsp::{bail, rfn, GResult, Runtime, Sym};
+use lyon::{
+    math::{point, rect, Point},
+    tessellation::{
+        basic_shapes::{self, BorderRadii},
+        BuffersBuilder, Count, FillOptions, FillTessellator, LineCap, StrokeOptions,
+        TessellationResult, VertexBuffers,
+    },
+};
+
+/// The color of a shape when none is supplied.
+const DEFAULT_COLOR: (u8, u8, u8) = (255, 255, 255);
+/// In how many line segments the outline of a circle is divided.
+const OUTLINE_SEGMENTS: usize = 64;
+
+/// The lyon output for the shapes.
+pub(crate) type Output<'a> = BuffersBuilder<'a, Vertex, u16, VertexCtor>;
+
+/// How a shape should be tessellated.
+pub(crate) struct Style {
+    /// The color of all vertices.
+    color: Color,
+    /// When set only the outline with this width is tessellated.
+    stroke_width: Option<f32>,
+    /// The shape of the ends of open strokes.
+    line_cap: LineCap,
+}
+
+impl Style {
+    /// Create the style from the optional script arguments.
+    pub(crate) fn new(color: Option<(u8, u8, u8)>, stroke_width: Option<f32>) -> Self {
+        let (red, green, blue) = color.unwrap_or(DEFAULT_COLOR);
+
+        Self {
+            color: Color::new(red, green, blue),
+            stroke_width,
+            line_cap: LineCap::Round,
+        }
+    }
+
+    /// Set the shape of the ends of open strokes, round by default.
+    pub(crate) fn with_line_cap(mut self, line_cap: LineCap) -> Self {
+        self.line_cap = line_cap;
+
+        self
+    }
+
+    /// Tessellate either the fill or the stroke of a shape and upload the result.
+    ///
+    /// The outline is the path instances can follow.
+    fn upload<F, S>(
+        &self,
+        render: &mut Render,
+        outline: &[Point],
+        fill: F,
+        stroke: S,
+    ) -> GResult<Mesh>
+    where
+        F: FnOnce(&FillOptions, &mut Output) -> TessellationResult,
+        S: FnOnce(&StrokeOptions, &mut Output) -> TessellationResult,
+    {
+        self.upload_to(render, None, outline, fill, stroke)
+    }
+
+    /// Tessellate either the fill or the stroke of a shape and replace the geometry of an
+    /// existing mesh with it, or upload it as a new mesh when there's none.
+    fn upload_to<F, S>(
+        &self,
+        render: &mut Render,
+        target: Option<Mesh>,
+        outline: &[Point],
+        fill: F,
+        stroke: S,
+    ) -> GResult<Mesh>
+    where
+        F: FnOnce(&FillOptions, &mut Output) -> TessellationResult,
+        S: FnOnce(&StrokeOptions, &mut Output) -> TessellationResult,
+    {
+        let mut geometry: VertexBuffers<Vertex, u16> = VertexBuffers::new();
+        self.tessellate(&mut geometry, fill, stroke)?;
+
+        let result = match target {
+            Some(mesh) => render.update_mesh(mesh, &geometry).map(|_| mesh),
+            None => render.upload_buffers(&geometry),
+        };
+        let mesh = match result {
+            Ok(mesh) => mesh,
+            Err(err) => bail!("uploading shape failed: {}", err),
+        };
+        render.set_mesh_path(mesh, outline.iter().map(|point| point.to_array()).collect());
+
+        Ok(mesh)
+    }
+
+    /// Tessellate either the fill or the stroke of a shape, appending it to the geometry.
+    pub(crate) fn tessellate<F, S>(
+        &self,
+        geometry: &mut VertexBuffers<Vertex, u16>,
+        fill: F,
+        stroke: S,
+    ) -> GResult<()>
+    where
+        F: FnOnce(&FillOptions, &mut Output) -> TessellationResult,
+        S: FnOnce(&StrokeOptions, &mut Output) -> TessellationResult,
+    {
+        profile_scope!("tessellate_shape");
+
+        let mut output = BuffersBuilder::new(geometry, VertexCtor::new(self.color, 1.0));
+
+        let result = match self.stroke_width {
+            Some(width) => stroke(
+                &StrokeOptions::default()
+                    .with_line_width(width)
+                    .with_line_cap(self.line_cap),
+                &mut output,
+            ),
+            None => fill(&FillOptions::default(), &mut output),
+        };
+        match result {
+            Ok(_) => Ok(()),
+            Err(err) => bail!("tessellating shape failed: {:?}", err),
+        }
+    }
+}
+
+/// Create a circle mesh centered around the origin.
+fn create_circle(
+    render: &mut Render,
+    radius: f32,
+    color: Option<(u8, u8, u8)>,
+    stroke_width: Option<f32>,
+) -> GResult<Mesh> {
+    let center = point(0.0, 0.0);
+    let outline: Vec<Point> = (0..=OUTLINE_SEGMENTS)
+        .map(|index| arc_point(center, radius, index as f32 / OUTLINE_SEGMENTS as f32))
+        .collect();
+
+    Style::new(color, stroke_width).upload(
+        render,
+        &outline,
+        |options, output| basic_shapes::fill_circle(center, radius, options, output),
+        |options, output| basic_shapes::stroke_circle(center, radius, options, output),
+    )
+}
+
+/// Create a rectangle mesh centered around the origin.
+fn create_rect(
+    render: &mut Render,
+    width: f32,
+    height: f32,
+    color: Option<(u8, u8, u8)>,
+    stroke_width: Option<f32>,
+) -> GResult<Mesh> {
+    let shape = rect(-width / 2.0, -height / 2.0, width, height);
+    let outline = [
+        shape.min(),
+        point(shape.max_x(), shape.min_y()),
+        shape.max(),
+        point(shape.min_x(), shape.max_y()),
+        shape.min(),
+    ];
+
+    Style::new(color, stroke_width).upload(
+        render,
+        &outline,
+        |options, output| basic_shapes::fill_rectangle(&shape, options, output),
+        |options, output| basic_shapes::stroke_rectangle(&shape, options, output),
+    )
+}
+
+/// Create a rectangle mesh with rounded corners centered around the origin.
+fn create_rounded_rect(
+    render: &mut Render,
+    width: f32,
+    height: f32,
+    radius: f32,
+    color: Option<(u8, u8, u8)>,
+    stroke_width: Option<f32>,
+) -> GResult<Mesh> {
+    let shape = rect(-width / 2.0, -height / 2.0, width, height);
+    let radii = BorderRadii::new_all_same(radius);
+
+    // Follow a quarter circle in every corner, clockwise from the top left
+    let radius = radius.min(width / 2.0).min(height / 2.0);
+    let corners = [
+        (point(shape.min_x() + radius, shape.min_y() + radius), 0.5),
+        (point(shape.max_x() - radius, shape.min_y() + radius), 0.75),
+        (point(shape.max_x() - radius, shape.max_y() - radius), 0.0),
+        (point(shape.min_x() + radius, shape.max_y() - radius), 0.25),
+    ];
+    let corner_segments = OUTLINE_SEGMENTS / 4;
+    let mut outline: Vec<Point> = corners
+        .iter()
+        .flat_map(|(center, start)| {
+            (0..=corner_segments).map(move |index| {
+                arc_point(
+                    *center,
+                    radius,
+                    start + index as f32 / corner_segments as f32 / 4.0,
+                )
+            })
+        })
+        .collect();
+    outline.push(outline[0]);
+
+    Style::new(color, stroke_width).upload(
+        render,
+        &outline,
+        |options, output| basic_shapes::fill_rounded_rectangle(&shape, &radii, options, output),
+        |options, output| basic_shapes::stroke_rounded_rectangle(&shape, &radii, options, output),
+    )
+}
+
+/// Create a closed polygon mesh from a list of `(x y)` points.
+fn create_polygon(
+    render: &mut Render,
+    points: Vec<(f32, f32)>,
+    color: Option<(u8, u8, u8)>,
+    stroke_width: Option<f32>,
+) -> GResult<Mesh> {
+    polygon(render, None, points, color, stroke_width)
+}
+
+/// Replace the geometry of a mesh with a closed polygon from a list of `(x y)` points.
+///
+/// The mesh & all its instances stay valid, so it can be called every frame for morphing
+/// shapes, health bars or deformable terrain.
+///
+/// ```gamelisp
+/// (update-mesh health-bar (arr '(0 0) (arr (* health 2) 0) (arr (* health 2) 10) '(0 10))
+///   (arr 200 40 40))
+/// ```
+fn update_mesh(
+    render: &mut Render,
+    mesh: &Mesh,
+    points: Vec<(f32, f32)>,
+    color: Option<(u8, u8, u8)>,
+    stroke_width: Option<f32>,
+) -> GResult<()> {
+    polygon(render, Some(*mesh), points, color, stroke_width)?;
+
+    Ok(())
+}
+
+/// Tessellate a closed polygon into a new mesh or an existing one.
+fn polygon(
+    render: &mut Render,
+    target: Option<Mesh>,
+    points: Vec<(f32, f32)>,
+    color: Option<(u8, u8, u8)>,
+    stroke_width: Option<f32>,
+) -> GResult<Mesh> {
+    if points.len() < 3 {
+        bail!(
+            "a polygon needs at least 3 points, {} supplied",
+            points.len()
+        );
+    }
+    let points: Vec<Point> = points.into_iter().map(|(x, y)| point(x, y)).collect();
+    let mut outline = points.clone();
+    outline.push(points[0]);
+
+    Style::new(color, stroke_width).upload_to(
+        render,
+        target,
+        &outline,
+        |options, output| {
+            basic_shapes::fill_polyline(
+                points.iter().copied(),
+                &mut FillTessellator::new(),
+                options,
+                output,
+            )
+        },
+        |options, output| {
+            basic_shapes::stroke_polyline(points.iter().copied(), true, options, output)
+        },
+    )
+}
+
+/// Create a line mesh with rounded caps.
+fn create_line(
+    render: &mut Render,
+    x1: f32,
+    y1: f32,
+    x2: f32,
+    y2: f32,
+    width: f32,
+    color: Option<(u8, u8, u8)>,
+) -> GResult<Mesh> {
+    let points = [point(x1, y1), point(x2, y2)];
+
+    // A line only has a stroke, never a fill
+    Style::new(color, Some(width)).upload(
+        render,
+        &points,
+        |_, _| unreachable!(),
+        |options, output| {
+            basic_shapes::stroke_polyline(points.iter().copied(), false, options, output)
+        },
+    )
+}
+
+/// Create a dashed line mesh through a list of `(x y)` points.
+///
+/// Repeat the first point at the end for a closed outline. The optional cap is `'butt`,
+/// `'square` or `'round`, which is the default.
+///
+/// ```gamelisp
+/// (create-dashed-line (arr '(0 0) '(100 0) '(100 50)) 10 5 2 (arr 255 255 0) 'butt)
+/// ```
+fn create_dashed_line(
+    render: &mut Render,
+    points: Vec<(f32, f32)>,
+    dash: f32,
+    gap: f32,
+    width: f32,
+    color: Option<(u8, u8, u8)>,
+    cap: Option<Sym>,
+) -> GResult<Mesh> {
+    if points.len() < 2 {
+        bail!("a line needs at least 2 points, {} supplied", points.len());
+    }
+    let line_cap = match cap.as_ref().map(|cap| cap.name()).as_deref() {
+        Some("butt") => LineCap::Butt,
+        Some("square") => LineCap::Square,
+        Some("round") | None => LineCap::Round,
+        Some(name) => bail!("unknown line cap {}", name),
+    };
+
+    let points: Vec<Point> = points.into_iter().map(|(x, y)| point(x, y)).collect();
+    let dashes = match path::dashes(
+        points.iter().map(|point| point.to_array()).collect(),
+        dash,
+        gap,
+    ) {
+        Ok(dashes) => dashes,
+        Err(err) => bail!("{}", err),
+    };
+
+    // Every dash is a separate open stroke in the same mesh
+    Style::new(color, Some(width))
+        .with_line_cap(line_cap)
+        .upload(
+            render,
+            &points,
+            |_, _| unreachable!(),
+            |options, output| {
+                dashes.iter().try_fold(
+                    Count {
+                        vertices: 0,
+                        indices: 0,
+                    },
+                    |count, dash| {
+                        Ok(count
+                            + basic_shapes::stroke_polyline(
+                                dash.iter().map(|[x, y]| point(*x, *y)),
+                                false,
+                                options,
+                                output,
+                            )?)
+                    },
+                )
+            },
+        )
+}
+
+/// A point on a circle, the turn is the fraction of a full clockwise rotation from the right.
+fn arc_point(center: Point, radius: f32, turn: f32) -> Point {
+    let (sin, cos) = (turn * std::f32::consts::PI * 2.0).sin_cos();
+
+    point(center.x + cos * radius, center.y + sin * radius)
+}
+
+/// Bind the GameLisp functions for creating primitive shapes.
+///
+/// Colors are passed as `(arr red green blue)` with values from 0 to 255, when the optional
+/// stroke width is supplied only the outline of the shape is created.
+pub fn bind_functions(runtime: &Runtime) {
+    runtime.run(|| {
+        glsp::bind_rfn("create-circle", rfn!(create_circle))?;
+        glsp::bind_rfn("create-rect", rfn!(create_rect))?;
+        glsp::bind_rfn("create-rounded-rect", rfn!(create_rounded_rect))?;
+        glsp::bind_rfn("create-polygon", rfn!(create_polygon))?;
+        glsp::bind_rfn("update-mesh", rfn!(update_mesh))?;
+        glsp::bind_rfn("create-line", rfn!(create_line))?;
+        glsp::bind_rfn("create-dashed-line", rfn!(create_dashed_line))?;
+
+        Ok(())
+    });
+}
@@ -0,0 +1,139 @@
+use crate::{debug, render::Render};
+use anyhow::Result;
+use glsp::{lib, rfn, Runtime};
+use std::collections::VecDeque;
+
+/// Over how many frames the frame time is averaged.
+const FRAME_TIME_SAMPLES: usize = 60;
+/// Maximum amount of meshes listed in the overlay.
+const MAX_LISTED_MESHES: usize = 10;
+/// The height of a line of text in the overlay in pixels.
+const LINE_HEIGHT: f32 = 16.0;
+/// The color of the overlay text.
+const TEXT_COLOR: (u8, u8, u8) = (255, 255, 0);
+
+lib! {
+/// Frame-time statistics and the performance overlay.
+pub struct Stats {
+    /// Whether the overlay is drawn.
+    show_overlay: bool,
+    /// When the last frame started in seconds.
+    last_frame_start: Option<f64>,
+    /// The durations of the most recent frames in seconds.
+    frame_times: VecDeque<f64>,
+}
+}
+
+impl Stats {
+    /// Create the statistics without any measured frames.
+    pub fn new() -> Self {
+        Self {
+            show_overlay: false,
+            last_frame_start: None,
+            frame_times: VecDeque::with_capacity(FRAME_TIME_SAMPLES),
+        }
+    }
+
+    /// Mark the start of a new frame.
+    pub fn begin_frame(&mut self) {
+        let now = miniquad::date::now();
+
+        if let Some(last_frame_start) = self.last_frame_start {
+            if self.frame_times.len() == FRAME_TIME_SAMPLES {
+                self.frame_times.pop_front();
+            }
+            self.frame_times.push_back(now - last_frame_start);
+        }
+
+        self.last_frame_start = Some(now);
+    }
+
+    /// The average duration of a frame in seconds.
+    pub fn frame_time(&self) -> f64 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+
+        self.frame_times.iter().sum::<f64>() / self.frame_times.len() as f64
+    }
+
+    /// The average amount of frames per second.
+    pub fn fps(&self) -> f64 {
+        let frame_time = self.frame_time();
+
+        if frame_time > 0.0 {
+            1.0 / frame_time
+        } else {
+            0.0
+        }
+    }
+
+    /// Set whether the performance overlay is shown.
+    pub fn show_debug_overlay(&mut self, show: bool) {
+        self.show_overlay = show;
+    }
+
+    /// Draw the overlay in the top left of the screen if it's enabled.
+    pub fn draw_overlay(&self, render: &mut Render) -> Result<()> {
+        if !self.show_overlay {
+            return Ok(());
+        }
+
+        let mut lines = vec![
+            format!(
+                "{:.0} FPS, {:.2} ms",
+                self.fps(),
+                self.frame_time() * 1000.0
+            ),
+            format!("{} draw calls", render.draw_call_count()),
+            format!("{} instances culled", render.culled_instance_count()),
+            format!(
+                "{} atlas pages, {:.0}% used",
+                render.atlas_page_count(),
+                render.atlas_utilization() * 100.0
+            ),
+            format!(
+                "GC {} KiB young, {} KiB old",
+                glsp::gc_young_bytes() / 1024,
+                glsp::gc_old_bytes() / 1024
+            ),
+        ];
+
+        // Show the meshes with the most instances
+        let mut instance_counts = render.instance_counts();
+        instance_counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+        lines.extend(
+            instance_counts
+                .into_iter()
+                .take(MAX_LISTED_MESHES)
+                .map(|(mesh, count)| format!("mesh {}: {} instances", mesh.index(), count)),
+        );
+
+        let scale = render.world_units_per_pixel();
+        let (x, y) = render.screen_to_world(4.0, 4.0);
+        for (index, line) in lines.iter().enumerate() {
+            debug::draw_text(
+                render,
+                x,
+                y + index as f32 * LINE_HEIGHT * scale,
+                line,
+                TEXT_COLOR,
+                LINE_HEIGHT * scale,
+                debug::DEFAULT_FONT,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Register the statistics and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Stats::new());
+
+            glsp::bind_rfn("show-debug-overlay", rfn!(Self::show_debug_overlay))?;
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use glsp::lib;
+use std::path::PathBuf;
+
+lib! {
+/// Persistent storage of strings by key.
+///
+/// On native platforms every key is a file in a directory, on WASM the browser's
+/// `localStorage` is used.
+pub struct Storage {
+    /// The directory containing the files on native platforms.
+    directory: PathBuf,
+}
+}
+
+impl Storage {
+    /// Create the storage with the directory where the files are kept.
+    pub fn new<P>(directory: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// Set the directory where the files are kept on native platforms.
+    pub fn set_directory<P>(&mut self, directory: P)
+    where
+        P: Into<PathBuf>,
+    {
+        self.directory = directory.into();
+    }
+
+    /// Store a string.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn write(&self, key: &str, contents: &str) -> Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+        std::fs::write(self.path(key), contents)?;
+
+        Ok(())
+    }
+
+    /// Store a string.
+    #[cfg(target_arch = "wasm32")]
+    pub fn write(&self, key: &str, contents: &str) -> Result<()> {
+        quad_storage::STORAGE.lock().unwrap().set(key, contents);
+
+        Ok(())
+    }
+
+    /// Read a stored string, `None` if nothing is stored for the key.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read(&self, key: &str) -> Result<Option<String>> {
+        let path = self.path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(std::fs::read_to_string(path)?))
+    }
+
+    /// Read a stored string, `None` if nothing is stored for the key.
+    #[cfg(target_arch = "wasm32")]
+    pub fn read(&self, key: &str) -> Result<Option<String>> {
+        Ok(quad_storage::STORAGE.lock().unwrap().get(key))
+    }
+
+    /// The file for a key, characters that are invalid in filenames are replaced.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn path(&self, key: &str) -> PathBuf {
+        let filename: String = key
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+
+        self.directory.join(format!("{}.json", filename))
+    }
+}
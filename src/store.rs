@@ -0,0 +1,171 @@
+use crate::{
+    save::{from_json, to_json},
+    storage::Storage,
+};
+use glsp::{bail, lib, rfn, GResult, Runtime, Val};
+use serde_json::{Map, Value};
+use std::path::PathBuf;
+
+/// The storage key of the file containing all values.
+const STORE_KEY: &str = "key-value-store";
+
+lib! {
+/// Persistent values by key for high scores, settings & unlocks, lighter than full save games.
+///
+/// The values are kept in a single file in the data directory of the platform: `~/.local/share`
+/// on Linux, `~/Library/Application Support` on macOS & `%APPDATA%` on Windows, inside a
+/// directory named after the game. On WASM the browser's `localStorage` is used. The file is
+/// written every time a value is set.
+///
+/// ```gamelisp
+/// (when (> score (store-get "high-score" 0))
+///   (store-set "high-score" score))
+/// ```
+pub struct Store {
+    /// Where the values are written to.
+    storage: Storage,
+    /// The stored values, `None` until they are read.
+    values: Option<Map<String, Value>>,
+}
+}
+
+impl Store {
+    /// Create the store in the data directory of the platform for a game.
+    pub fn new(title: &str) -> Self {
+        Self {
+            storage: Storage::new(data_directory(title)),
+            values: None,
+        }
+    }
+
+    /// Set the directory the values are written to on native platforms.
+    pub fn set_directory<P>(&mut self, directory: P)
+    where
+        P: Into<PathBuf>,
+    {
+        self.storage.set_directory(directory);
+        self.values = None;
+    }
+
+    /// The stored values, read from the storage the first time.
+    fn values(&mut self) -> GResult<&mut Map<String, Value>> {
+        if self.values.is_none() {
+            let values = match self.storage.read(STORE_KEY) {
+                Ok(Some(contents)) => match serde_json::from_str(&contents) {
+                    Ok(Value::Object(values)) => values,
+                    Ok(_) => bail!("stored values are corrupt: not an object"),
+                    Err(err) => bail!("stored values are corrupt: {}", err),
+                },
+                Ok(None) => Map::new(),
+                Err(err) => bail!("reading stored values failed: {}", err),
+            };
+
+            self.values = Some(values);
+        }
+
+        Ok(self.values.get_or_insert_with(Map::new))
+    }
+
+    /// Write the stored values to the storage.
+    fn write(&mut self) -> GResult<()> {
+        let contents = Value::Object(self.values()?.clone()).to_string();
+        if let Err(err) = self.storage.write(STORE_KEY, &contents) {
+            bail!("writing stored values failed: {}", err);
+        }
+
+        Ok(())
+    }
+
+    /// Set a stored value & write it to the storage.
+    pub fn set_value(&mut self, key: &str, value: Value) -> GResult<()> {
+        self.values()?.insert(key.to_string(), value);
+
+        self.write()
+    }
+
+    /// A stored value.
+    pub fn value(&mut self, key: &str) -> GResult<Option<Value>> {
+        Ok(self.values()?.get(key).cloned())
+    }
+
+    /// Store a value from a script, functions & objects can't be stored.
+    ///
+    /// ```gamelisp
+    /// (store-set "settings" (tab ('volume 0.8) ('fullscreen #t)))
+    /// ```
+    fn store_set(&mut self, key: &str, val: Val) -> GResult<()> {
+        let value = to_json(&val)?;
+
+        self.set_value(key, value)
+    }
+
+    /// Get a stored value from a script, the default or `#n` when nothing is stored.
+    ///
+    /// ```gamelisp
+    /// (let volume [(store-get "settings" (tab ('volume 1.0))) 'volume])
+    /// ```
+    fn store_get(&mut self, key: &str, default: Option<Val>) -> GResult<Val> {
+        match self.values()?.get(key) {
+            Some(value) => from_json(value),
+            None => Ok(default.unwrap_or(Val::Nil)),
+        }
+    }
+
+    /// Remove a stored value from a script.
+    fn store_remove(&mut self, key: &str) -> GResult<()> {
+        if self.values()?.remove(key).is_some() {
+            self.write()?;
+        }
+
+        Ok(())
+    }
+
+    /// Register the store for a game and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime, title: &str) {
+        runtime.run(|| {
+            glsp::add_lib(Store::new(title));
+
+            glsp::bind_rfn("store-set", rfn!(Store::store_set))?;
+            glsp::bind_rfn("store-get", rfn!(Store::store_get))?;
+            glsp::bind_rfn("store-remove", rfn!(Store::store_remove))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// The directory for the data of a game in the data directory of the platform.
+///
+/// Falls back to a directory in the working directory when the platform's isn't known.
+fn data_directory(title: &str) -> PathBuf {
+    let base = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| {
+            PathBuf::from(home)
+                .join("Library")
+                .join("Application Support")
+        })
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .map(|home| PathBuf::from(home).join(".local").join("share"))
+            })
+    };
+
+    // Characters that are invalid in directory names are replaced
+    let name: String = title
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    base.unwrap_or_default().join(name)
+}
@@ -0,0 +1,478 @@
+use crate::{
+    assets::{AssetKind, AssetState, Assets},
+    ecs::Entity,
+    event::Events,
+    level,
+    prefab::Prefabs,
+    render::{InstanceRef, Render},
+};
+use glsp::{bail, lib, rfn, FromVal, GResult, Root, Runtime, Sym, Tab};
+use std::collections::{HashMap, HashSet};
+
+/// How far outside of the screen chunks start loading by default, in world units.
+const DEFAULT_LOAD_DISTANCE: f32 = 256.0;
+/// How far outside of the screen loaded chunks are unloaded by default, in world units.
+const DEFAULT_UNLOAD_DISTANCE: f32 = 512.0;
+
+/// Whether the content of a chunk is in the world.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ChunkState {
+    /// Nothing of the chunk is loaded.
+    Unloaded,
+    /// The assets are loading, the prefabs are spawned when they're done.
+    Loading,
+    /// The prefabs & the level are spawned.
+    Loaded,
+}
+
+impl ChunkState {
+    /// The name used by the scripts.
+    fn name(self) -> &'static str {
+        match self {
+            ChunkState::Unloaded => "unloaded",
+            ChunkState::Loading => "loading",
+            ChunkState::Loaded => "loaded",
+        }
+    }
+}
+
+/// A part of the world with the assets & prefabs that only exist while it's near the camera.
+#[derive(Debug)]
+struct Chunk {
+    /// The area of the chunk as `[min_x, min_y, max_x, max_y]`.
+    bounds: [f32; 4],
+    /// The reference names of the assets with the files they're loaded from.
+    assets: Vec<(String, String)>,
+    /// The prefabs spawned when the chunk is loaded, with their positions.
+    prefabs: Vec<(String, f32, f32)>,
+    /// The level layout script placed when the chunk is loaded.
+    level: Option<String>,
+    state: ChunkState,
+    /// The outermost entities of the spawned prefabs.
+    entities: Vec<Entity>,
+    /// The instances placed by the level.
+    instances: Vec<InstanceRef>,
+}
+
+impl Chunk {
+    /// How far the chunk is outside of an area, zero when they overlap.
+    fn distance(&self, [min_x, min_y, max_x, max_y]: [f32; 4]) -> f32 {
+        let [chunk_min_x, chunk_min_y, chunk_max_x, chunk_max_y] = self.bounds;
+        let dx = (chunk_min_x - max_x).max(min_x - chunk_max_x).max(0.0);
+        let dy = (chunk_min_y - max_y).max(min_y - chunk_max_y).max(0.0);
+
+        dx.max(dy)
+    }
+
+    /// Whether the chunk uses an asset while it's loading or loaded.
+    fn uses(&self, reference_name: &str) -> bool {
+        self.state != ChunkState::Unloaded
+            && self.assets.iter().any(|(name, _)| name == reference_name)
+    }
+}
+
+/// What's spawned for a chunk, removed again when it's unloaded.
+type Spawned = (Vec<Entity>, Vec<InstanceRef>);
+
+lib! {
+/// Loads the chunks of a large world when they come near the camera and unloads the distant ones.
+///
+/// The assets of a chunk are queued when the camera comes within the load distance and are
+/// loaded in the background, its prefabs & level are spawned once they're done. Chunks are only
+/// unloaded again beyond the larger unload distance, so moving back & forth along the edge doesn't
+/// load them every frame. The assets loaded for a chunk are unloaded with it when no other chunk
+/// uses them. The `chunk-loaded` & `chunk-unloaded` events are emitted with the name of the chunk.
+///
+/// ```gamelisp
+/// (define-chunk "forest" (tab
+///   ('bounds (arr 0 0 2000 1000))
+///   ('assets (tab ("tree" "assets/tree.svg") ("wolf" "assets/wolf.svg")))
+///   ('prefabs (arr (arr "wolf" 400 300) (arr "wolf" 900 250)))
+///   ('level "levels/forest.glsp")))
+/// (set-streaming-distance 200 600)
+/// ```
+pub struct Streaming {
+    /// The chunks by name.
+    chunks: HashMap<String, Chunk>,
+    /// The assets that are queued by the chunks, only those are unloaded with them.
+    streamed_assets: HashSet<String>,
+    /// How far outside of the screen chunks start loading.
+    load_distance: f32,
+    /// How far outside of the screen chunks are unloaded.
+    unload_distance: f32,
+}
+}
+
+impl Streaming {
+    /// Create without any chunks.
+    pub fn new() -> Self {
+        Self {
+            chunks: HashMap::new(),
+            streamed_assets: HashSet::new(),
+            load_distance: DEFAULT_LOAD_DISTANCE,
+            unload_distance: DEFAULT_UNLOAD_DISTANCE,
+        }
+    }
+
+    /// Add a chunk with the assets it needs, the prefabs it spawns & a level layout script.
+    ///
+    /// The bounds are `[min_x, min_y, max_x, max_y]` in world units, the assets are pairs of
+    /// reference names & paths and the prefabs are spawned by name at a position. Returns `false`
+    /// when a chunk with the same name already exists.
+    pub fn define(
+        &mut self,
+        name: &str,
+        bounds: [f32; 4],
+        assets: Vec<(String, String)>,
+        prefabs: Vec<(String, f32, f32)>,
+        level: Option<String>,
+    ) -> bool {
+        if self.chunks.contains_key(name) {
+            return false;
+        }
+
+        self.chunks.insert(
+            name.to_string(),
+            Chunk {
+                bounds,
+                assets,
+                prefabs,
+                level,
+                state: ChunkState::Unloaded,
+                entities: vec![],
+                instances: vec![],
+            },
+        );
+
+        true
+    }
+
+    /// Set how far outside of the screen chunks are loaded & unloaded in world units.
+    ///
+    /// The unload distance must be at least the load distance.
+    pub fn set_distances(&mut self, load_distance: f32, unload_distance: f32) -> bool {
+        if unload_distance < load_distance {
+            return false;
+        }
+
+        self.load_distance = load_distance;
+        self.unload_distance = unload_distance;
+
+        true
+    }
+
+    /// Whether a chunk is loading, loaded or unloaded, `None` if it doesn't exist.
+    fn state(&self, name: &str) -> Option<ChunkState> {
+        self.chunks.get(name).map(|chunk| chunk.state)
+    }
+
+    /// Remove a chunk, unloading everything it spawned & loaded.
+    ///
+    /// Returns `false` when the chunk doesn't exist. Must be called inside the GameLisp runtime
+    /// without the streaming, assets, render, prefabs or world being borrowed.
+    pub fn remove(name: &str) -> GResult<bool> {
+        let spawned = {
+            let mut streaming = Streaming::borrow_mut();
+            let mut chunk = match streaming.chunks.remove(name) {
+                Some(chunk) => chunk,
+                None => return Ok(false),
+            };
+            let was_loaded = chunk.state == ChunkState::Loaded;
+            let spawned = streaming.unload(&mut chunk);
+
+            // Only chunks that were spawned notify the scripts
+            if !was_loaded {
+                return Ok(true);
+            }
+
+            spawned
+        };
+
+        Self::despawn(spawned)?;
+        Events::emit_engine_event("chunk-unloaded", name)?;
+
+        Ok(true)
+    }
+
+    /// Load the chunks that came near the camera & unload the ones that are far away.
+    ///
+    /// At most a single chunk is spawned every update so spawning many prefabs at once doesn't
+    /// stall a frame. Must be called inside the GameLisp runtime without the streaming, assets,
+    /// render, prefabs or world being borrowed.
+    pub fn update() -> GResult<()> {
+        let view = {
+            let render = Render::borrow();
+            let (width, height) = render.screen_size();
+            let (x1, y1) = render.screen_to_world(0.0, 0.0);
+            let (x2, y2) = render.screen_to_world(width, height);
+
+            [x1.min(x2), y1.min(y2), x1.max(x2), y1.max(y2)]
+        };
+
+        // Decide what changes first, the borrows can't be held while the prefabs are spawned
+        let (unloaded, ready) = {
+            let mut streaming = Streaming::borrow_mut();
+            let (load_distance, unload_distance) =
+                (streaming.load_distance, streaming.unload_distance);
+
+            let mut names: Vec<String> = streaming.chunks.keys().cloned().collect();
+            // Spawn in the same order every time so replays are deterministic
+            names.sort();
+
+            let mut unloaded = vec![];
+            let mut ready = None;
+            for name in names {
+                let (state, distance) = {
+                    let chunk = &streaming.chunks[&name];
+
+                    (chunk.state, chunk.distance(view))
+                };
+
+                match state {
+                    ChunkState::Unloaded if distance <= load_distance => {
+                        streaming.queue_assets(&name);
+                    }
+                    ChunkState::Loading | ChunkState::Loaded if distance > unload_distance => {
+                        let mut chunk = streaming.chunks.remove(&name).unwrap();
+                        let spawned = streaming.unload(&mut chunk);
+                        streaming.chunks.insert(name.clone(), chunk);
+
+                        if state == ChunkState::Loaded {
+                            unloaded.push((name, spawned));
+                        }
+                    }
+                    ChunkState::Loading if ready.is_none() => {
+                        let assets = Assets::borrow();
+                        let done = streaming.chunks[&name].assets.iter().all(|(asset, _)| {
+                            !matches!(
+                                assets.state(asset),
+                                Some(AssetState::Queued) | Some(AssetState::Loading)
+                            )
+                        });
+                        if done {
+                            let chunk = &streaming.chunks[&name];
+                            ready = Some((name, chunk.prefabs.clone(), chunk.level.clone()));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+
+            (unloaded, ready)
+        };
+
+        for (name, spawned) in unloaded {
+            Self::despawn(spawned)?;
+            Events::emit_engine_event("chunk-unloaded", name.as_str())?;
+        }
+
+        if let Some((name, prefabs, level)) = ready {
+            let spawned = Self::spawn(&name, &prefabs, level.as_deref());
+
+            // The chunk might be removed or unloaded by the scripts spawned with it
+            let mut streaming = Streaming::borrow_mut();
+            match streaming.chunks.get_mut(&name) {
+                Some(chunk) if chunk.state == ChunkState::Loading => {
+                    chunk.state = ChunkState::Loaded;
+                    chunk.entities = spawned.0;
+                    chunk.instances = spawned.1;
+                    drop(streaming);
+
+                    Events::emit_engine_event("chunk-loaded", name.as_str())?;
+                }
+                _ => {
+                    drop(streaming);
+
+                    Self::despawn(spawned)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queue the assets of a chunk that aren't loaded yet.
+    fn queue_assets(&mut self, name: &str) {
+        let chunk = match self.chunks.get_mut(name) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        chunk.state = ChunkState::Loading;
+
+        let mut assets = Assets::borrow_mut();
+        for (reference_name, path) in chunk.assets.iter() {
+            let kind = match AssetKind::from_path(path) {
+                Some(kind) => kind,
+                None => {
+                    log::warn!(
+                        "unknown asset type for file \"{}\" of chunk \"{}\"",
+                        path,
+                        name
+                    );
+
+                    continue;
+                }
+            };
+
+            if assets.add_path(reference_name.as_str(), kind, path.as_str()) {
+                self.streamed_assets.insert(reference_name.clone());
+            }
+        }
+    }
+
+    /// Mark a chunk that's taken out of the chunks as unloaded and unload the assets that no
+    /// other chunk uses, returns what still has to be despawned.
+    fn unload(&mut self, chunk: &mut Chunk) -> Spawned {
+        chunk.state = ChunkState::Unloaded;
+
+        let mut assets = Assets::borrow_mut();
+        let mut render = Render::borrow_mut();
+        for (reference_name, _) in chunk.assets.iter() {
+            if !self.streamed_assets.contains(reference_name)
+                || self.chunks.values().any(|other| other.uses(reference_name))
+            {
+                continue;
+            }
+
+            assets.unload(reference_name, &mut render);
+            self.streamed_assets.remove(reference_name);
+        }
+
+        (
+            std::mem::take(&mut chunk.entities),
+            std::mem::take(&mut chunk.instances),
+        )
+    }
+
+    /// Spawn the prefabs & the level of a chunk.
+    ///
+    /// What fails to spawn is logged, so a single broken prefab doesn't keep the rest out.
+    fn spawn(name: &str, prefabs: &[(String, f32, f32)], level: Option<&str>) -> Spawned {
+        let entities = prefabs
+            .iter()
+            .filter_map(|(prefab, x, y)| match Prefabs::spawn(prefab, *x, *y) {
+                Ok(entity) => Some(entity),
+                Err(err) => {
+                    log::error!(
+                        "error spawning prefab \"{}\" of chunk \"{}\": {}",
+                        prefab,
+                        name,
+                        err
+                    );
+
+                    None
+                }
+            })
+            .collect();
+
+        let instances = match level.map(level::load) {
+            Some(Ok(instances)) => instances,
+            Some(Err(err)) => {
+                log::error!("error loading level of chunk \"{}\": {}", name, err);
+
+                vec![]
+            }
+            None => vec![],
+        };
+
+        (entities, instances)
+    }
+
+    /// Remove what was spawned for a chunk.
+    fn despawn((entities, instances): Spawned) -> GResult<()> {
+        for entity in entities {
+            Prefabs::despawn(entity)?;
+        }
+
+        let mut render = Render::borrow_mut();
+        for instance in instances {
+            render.remove_instance(instance);
+        }
+
+        Ok(())
+    }
+
+    /// Add a chunk from a script, replacing the chunk with the same name.
+    ///
+    /// The definition is a table with the `bounds` as `(min-x min-y max-x max-y)`, a table of
+    /// `assets` with their paths by reference name, the `prefabs` as `(name x y)` arrays and a
+    /// `level` layout script, everything besides the bounds is optional.
+    fn define_chunk(name: &str, definition: Root<Tab>) -> GResult<()> {
+        let bounds: [f32; 4] = {
+            let bounds: Vec<f32> = definition.get(glsp::sym("bounds")?)?;
+            match bounds.as_slice() {
+                [min_x, min_y, max_x, max_y] => [*min_x, *min_y, *max_x, *max_y],
+                _ => bail!(
+                    "bounds of chunk \"{}\" must be (min-x min-y max-x max-y)",
+                    name
+                ),
+            }
+        };
+        let assets: Option<Root<Tab>> = definition.get_if_present(glsp::sym("assets")?)?;
+        let assets = match assets {
+            Some(assets) => assets
+                .entries()
+                .iter()
+                .map(|(reference_name, path)| {
+                    Ok((String::from_val(&reference_name)?, String::from_val(&path)?))
+                })
+                .collect::<GResult<_>>()?,
+            None => vec![],
+        };
+        let prefabs: Option<Vec<(String, f32, f32)>> =
+            definition.get_if_present(glsp::sym("prefabs")?)?;
+        let level: Option<String> = definition.get_if_present(glsp::sym("level")?)?;
+
+        Self::remove(name)?;
+        Streaming::borrow_mut().define(name, bounds, assets, prefabs.unwrap_or_default(), level);
+
+        Ok(())
+    }
+
+    /// Remove a chunk from a script.
+    fn remove_chunk(name: &str) -> GResult<bool> {
+        Self::remove(name)
+    }
+
+    /// Get whether a chunk is `unloaded`, `loading` or `loaded` from a script.
+    ///
+    /// ```gamelisp
+    /// (when (== (chunk-state "forest") 'loaded)
+    ///   (open-gate))
+    /// ```
+    fn chunk_state(&self, name: &str) -> GResult<Option<Sym>> {
+        self.state(name)
+            .map(|state| glsp::sym(state.name()))
+            .transpose()
+    }
+
+    /// Set the load & unload distances from a script.
+    fn set_streaming_distance(&mut self, load_distance: f32, unload_distance: f32) -> GResult<()> {
+        if !self.set_distances(load_distance, unload_distance) {
+            bail!(
+                "unload distance {} must be at least the load distance {}",
+                unload_distance,
+                load_distance
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Register the streaming and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Streaming::new());
+
+            glsp::bind_rfn("define-chunk", rfn!(Streaming::define_chunk))?;
+            glsp::bind_rfn("remove-chunk", rfn!(Streaming::remove_chunk))?;
+            glsp::bind_rfn("chunk-state", rfn!(Streaming::chunk_state))?;
+            glsp::bind_rfn(
+                "set-streaming-distance",
+                rfn!(Streaming::set_streaming_distance),
+            )?;
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,56 @@
+use crate::render::Render;
+use glsp::{GResult, Runtime};
+use miniquad::graphics::Context;
+
+/// A native Rust system running alongside the scripts, registered with `Clog::add_system`.
+///
+/// All functions are called inside the GameLisp runtime, so the system can access its own
+/// libraries and the ones of the engine. Every function has an empty default implementation.
+///
+/// ```rust
+/// use clogs::{Clog, System};
+/// use glsp::{rfn, Runtime};
+///
+/// struct Gravity;
+///
+/// fn gravity() -> f32 {
+///     9.81
+/// }
+///
+/// impl System for Gravity {
+///     fn bind_functions(&mut self, runtime: &Runtime) {
+///         runtime.run(|| {
+///             glsp::bind_rfn("gravity", rfn!(gravity))?;
+///
+///             Ok(())
+///         });
+///     }
+/// }
+///
+/// # fn main() {
+/// let game = Clog::new("Title of the game").add_system(Gravity);
+/// # }
+/// ```
+pub trait System {
+    /// Register libraries and bind GameLisp functions, called when the system is added.
+    fn bind_functions(&mut self, _runtime: &Runtime) {}
+
+    /// Called every update before the scripts, unless the game is paused.
+    fn update(&mut self, _ctx: &mut Context) -> GResult<()> {
+        Ok(())
+    }
+
+    /// Called every frame after the scripts, before everything is drawn.
+    fn render(&mut self, _ctx: &mut Context, _render: &mut Render) -> GResult<()> {
+        Ok(())
+    }
+
+    /// Called once when the game is closed, after `engine:shutdown`.
+    fn shutdown(&mut self) {}
+
+    /// Draw development tools with egui, called every frame while the editor is open.
+    ///
+    /// Only available with the `editor-ui` feature.
+    #[cfg(feature = "editor-ui")]
+    fn editor_ui(&mut self, _egui: &egui::CtxRef) {}
+}
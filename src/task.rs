@@ -0,0 +1,222 @@
+use crate::{sandbox, timer::Timers};
+use glsp::{lib, rdata, rfn, Coro, CoroState, GFn, GResult, Root, Runtime, Val};
+use std::{collections::HashSet, mem};
+
+/// What a task is waiting for before it's resumed.
+#[derive(Debug, Clone)]
+enum WaitCondition {
+    /// Until the game time in seconds is reached.
+    Seconds(f64),
+    /// The amount of updates left.
+    Frames(u32),
+    /// Until the function returns a truthy value.
+    Until(Root<GFn>),
+}
+
+rdata! {
+/// A condition yielded by a task, returned by `wait-seconds`, `wait-frames` & `wait-until`.
+#[derive(Debug, Clone)]
+pub struct Wait(WaitCondition);
+}
+
+rdata! {
+/// A reference to a running task.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TaskRef(u32);
+}
+
+/// A coroutine that's resumed by the engine.
+#[derive(Debug)]
+struct Task {
+    /// The identifier referenced by the scripts.
+    id: u32,
+    /// The coroutine of the task.
+    coro: Root<Coro>,
+    /// What the task is waiting for, `None` resumes it during the next update.
+    wait: Option<WaitCondition>,
+}
+
+lib! {
+/// Coroutines started by scripts, resumed every update until they finish.
+///
+/// A task yields a wait condition to sleep, yielding anything else resumes it the next update:
+///
+/// ```gamelisp
+/// (start-task (fn ()
+///   (prn "hello")
+///   (yield (wait-seconds 1.5))
+///   (prn "world")
+///   (yield (wait-until (fn () (>= (game-time) 10.0))))
+///   (yield (wait-frames 2))))
+/// ```
+pub struct Tasks {
+    /// The running tasks.
+    tasks: Vec<Task>,
+    /// The identifier of the next task.
+    next_id: u32,
+    /// Tasks cancelled while the tasks are being resumed.
+    cancelled: HashSet<u32>,
+}
+}
+
+impl Tasks {
+    /// Create without any tasks.
+    pub fn new() -> Self {
+        Self {
+            tasks: vec![],
+            next_id: 0,
+            cancelled: HashSet::new(),
+        }
+    }
+
+    /// Start a task from a script.
+    ///
+    /// The function is called and the coroutine it returns is resumed every update, a function
+    /// that doesn't yield just runs to completion.
+    fn start_task(function: Root<GFn>) -> GResult<Option<TaskRef>> {
+        let result: Val = sandbox::call(&function, &())?;
+        let coro = match result {
+            Val::Coro(coro) => coro,
+            _ => return Ok(None),
+        };
+
+        let mut tasks = Tasks::borrow_mut();
+        let id = tasks.next_id;
+        tasks.next_id = tasks.next_id.wrapping_add(1);
+        tasks.tasks.push(Task {
+            id,
+            coro,
+            wait: None,
+        });
+
+        Ok(Some(TaskRef(id)))
+    }
+
+    /// Stop a task from a script, returns whether it was still running.
+    fn cancel_task(&mut self, task: &TaskRef) -> bool {
+        self.cancelled.insert(task.0);
+
+        let count = self.tasks.len();
+        self.tasks.retain(|t| t.id != task.0);
+
+        self.tasks.len() != count
+    }
+
+    /// Check from a script whether a task hasn't finished yet.
+    fn is_task_running(&self, task: &TaskRef) -> bool {
+        self.tasks.iter().any(|t| t.id == task.0)
+    }
+
+    /// Resume all tasks whose wait condition is met.
+    ///
+    /// A task that fails is stopped & the first error is returned after the other tasks are
+    /// resumed. Must be called inside the GameLisp runtime.
+    pub fn resume_all() -> GResult<()> {
+        // Don't hold the borrow while calling the scripts, they might start new tasks
+        let tasks = mem::take(&mut Tasks::borrow_mut().tasks);
+
+        let mut running = Vec::with_capacity(tasks.len());
+        let mut failed = None;
+        for mut task in tasks {
+            if Tasks::borrow().cancelled.contains(&task.id) {
+                continue;
+            }
+
+            match Self::resume(&mut task) {
+                Ok(true) => running.push(task),
+                Ok(false) => (),
+                Err(err) => {
+                    failed.get_or_insert(err);
+                }
+            }
+        }
+
+        // Keep the tasks started during this update after the existing ones
+        let mut tasks = Tasks::borrow_mut();
+        let cancelled = mem::take(&mut tasks.cancelled);
+        running.retain(|task| !cancelled.contains(&task.id));
+        running.append(&mut tasks.tasks);
+        tasks.tasks = running;
+
+        match failed {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Resume a single task if it's done waiting, returns whether it's still running.
+    fn resume(task: &mut Task) -> GResult<bool> {
+        let ready = match task.wait {
+            Some(WaitCondition::Seconds(until)) => Timers::borrow().time() >= until,
+            Some(WaitCondition::Frames(ref mut frames)) => {
+                *frames = frames.saturating_sub(1);
+
+                *frames == 0
+            }
+            Some(WaitCondition::Until(ref function)) => {
+                sandbox::call::<_, _, Val>(function, &())?.is_truthy()
+            }
+            None => true,
+        };
+        if !ready {
+            return Ok(true);
+        }
+
+        let yielded: Val = sandbox::guard(|| glsp::coro_run(&task.coro, None))?;
+        if task.coro.state() == CoroState::Finished {
+            return Ok(false);
+        }
+
+        task.wait = match yielded {
+            Val::RData(ref rdata) if rdata.is::<Wait>() => Some(rdata.borrow::<Wait>().0.clone()),
+            _ => None,
+        };
+
+        Ok(true)
+    }
+
+    /// Create a condition for a task to wait a duration in seconds of game time.
+    ///
+    /// ```gamelisp
+    /// (yield (wait-seconds 0.5))
+    /// ```
+    fn wait_seconds(seconds: f64) -> Wait {
+        Wait(WaitCondition::Seconds(Timers::borrow().time() + seconds))
+    }
+
+    /// Create a condition for a task to wait an amount of updates.
+    ///
+    /// ```gamelisp
+    /// (yield (wait-frames 10))
+    /// ```
+    fn wait_frames(frames: u32) -> Wait {
+        Wait(WaitCondition::Frames(frames))
+    }
+
+    /// Create a condition for a task to wait until a function returns a truthy value.
+    ///
+    /// The function is called every update.
+    ///
+    /// ```gamelisp
+    /// (yield (wait-until (fn () (>= score 100))))
+    /// ```
+    fn wait_until(function: Root<GFn>) -> Wait {
+        Wait(WaitCondition::Until(function))
+    }
+
+    /// Register the tasks and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Tasks::new());
+
+            glsp::bind_rfn("start-task", rfn!(Tasks::start_task))?;
+            glsp::bind_rfn("cancel-task", rfn!(Tasks::cancel_task))?;
+            glsp::bind_rfn("task-running?", rfn!(Tasks::is_task_running))?;
+            glsp::bind_rfn("wait-seconds", rfn!(Tasks::wait_seconds))?;
+            glsp::bind_rfn("wait-frames", rfn!(Tasks::wait_frames))?;
+            glsp::bind_rfn("wait-until", rfn!(Tasks::wait_until))?;
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,340 @@
+//! Screenshot tests comparing rendered frames against golden PNG images, so regressions in the
+//! tessellation, the shaders or the camera are caught by `cargo test`.
+//!
+//! The frames are rendered offscreen at the size of the game, every update takes 1/60th of a
+//! second so the frames are the same every run. The graphics context still needs a window, so a
+//! window of a single pixel is opened & closed again after the frames are rendered. On machines
+//! without a display a virtual one is needed, e.g. `xvfb-run cargo test`. A golden image that
+//! doesn't exist is written instead of compared against, as is every golden image when the
+//! `CLOGS_UPDATE_GOLDEN` environment variable is set.
+//!
+//! ```rust,no_run
+//! use clogs::{testing::ScreenshotTest, Clog};
+//! # fn main() -> anyhow::Result<()> {
+//! let game = Clog::new("Test")
+//!     .width(320)
+//!     .height(240)
+//!     .main_script("(defn engine:update ()) (defn engine:render ())")?;
+//!
+//! ScreenshotTest::new(10)
+//!     .tolerance(2)
+//!     .compare(game, "tests/golden/empty.png")?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    atlas, capture, capture::Capture, error_screen::ErrorScreen, render::Render, Clog, ClogRun,
+    HEADLESS_UPDATE_TIME,
+};
+use anyhow::{anyhow, Result};
+use miniquad::{
+    conf::{Conf, Loading},
+    Context, EventHandler, UserData,
+};
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// The environment variable that makes the tests write the golden images.
+pub const UPDATE_GOLDEN_VAR: &str = "CLOGS_UPDATE_GOLDEN";
+
+/// The RGBA pixels of a rendered frame with the top row first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Screenshot {
+    /// The width in pixels.
+    pub width: u32,
+    /// The height in pixels.
+    pub height: u32,
+    /// Four bytes for every pixel, rows from left to right starting at the top.
+    pub pixels: Vec<u8>,
+}
+
+impl Screenshot {
+    /// Read a PNG image.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let png = std::fs::read(path)
+            .map_err(|err| anyhow!("reading \"{}\" failed: {}", path.display(), err))?;
+        let (width, height, pixels) = atlas::decode_png(&png)?;
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Write as a PNG image, creating the directory when it doesn't exist.
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        capture::save_png(path, self.width, self.height, &self.pixels)
+    }
+
+    /// The amount of pixels with a channel that differs more than the tolerance from the other
+    /// screenshot, with an image marking them in red.
+    ///
+    /// Fails when the sizes differ.
+    pub fn diff(&self, other: &Screenshot, tolerance: u8) -> Result<(usize, Screenshot)> {
+        if self.width != other.width || self.height != other.height {
+            return Err(anyhow!(
+                "size {}x{} differs from {}x{}",
+                self.width,
+                self.height,
+                other.width,
+                other.height
+            ));
+        }
+
+        let mut differing = 0;
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for (a, b) in self
+            .pixels
+            .chunks_exact(4)
+            .zip(other.pixels.chunks_exact(4))
+        {
+            let differs = a
+                .iter()
+                .zip(b.iter())
+                .any(|(a, b)| (*a as i16 - *b as i16).abs() > tolerance as i16);
+            if differs {
+                differing += 1;
+                pixels.extend_from_slice(&[255, 0, 0, 255]);
+            } else {
+                // Dim the matching pixels so the differences stand out
+                pixels.extend_from_slice(&[a[0] / 4, a[1] / 4, a[2] / 4, 255]);
+            }
+        }
+
+        Ok((
+            differing,
+            Screenshot {
+                width: self.width,
+                height: self.height,
+                pixels,
+            },
+        ))
+    }
+}
+
+/// Renders a game for a fixed amount of frames & compares the last one against a golden image.
+#[derive(Debug, Clone)]
+pub struct ScreenshotTest {
+    /// The frame that's captured, counted from the first frame after the assets are loaded.
+    frames: usize,
+    /// How much every channel of a pixel may differ.
+    tolerance: u8,
+    /// The fraction of pixels that may differ more than the tolerance.
+    max_differing: f64,
+}
+
+impl ScreenshotTest {
+    /// Capture the frame rendered after a number of updates.
+    pub fn new(frames: usize) -> Self {
+        Self {
+            frames: frames.max(1),
+            tolerance: 0,
+            max_differing: 0.0,
+        }
+    }
+
+    /// How much every channel of a pixel may differ, to allow for differences between GPU
+    /// drivers. Exact by default.
+    pub fn tolerance(mut self, tolerance: u8) -> Self {
+        self.tolerance = tolerance;
+
+        self
+    }
+
+    /// The fraction of pixels from `0.0` to `1.0` that may differ more than the tolerance, none
+    /// by default.
+    pub fn max_differing_pixels(mut self, fraction: f64) -> Self {
+        self.max_differing = fraction.max(0.0).min(1.0);
+
+        self
+    }
+
+    /// Run the game & capture the frame, rendered offscreen at the width & height of the game.
+    ///
+    /// Fails when a script function fails or the window is closed before the frame is rendered.
+    pub fn capture(&self, clog: Clog) -> Result<Screenshot> {
+        let result = Rc::new(RefCell::new(None));
+
+        // Nothing is drawn to the window, it only provides the graphics context
+        let conf = Conf {
+            window_title: clog.title.clone(),
+            window_width: 1,
+            window_height: 1,
+            loading: Loading::No,
+            sample_count: clog.sample_count,
+            ..Default::default()
+        };
+        let frames = self.frames;
+        let size = (clog.width.max(1) as u32, clog.height.max(1) as u32);
+        let test_result = result.clone();
+        miniquad::start(conf, move |mut ctx| {
+            let mut run = ClogRun::new(&mut ctx, clog);
+            // Every run must render the same frames
+            run.fixed_update_time = Some(HEADLESS_UPDATE_TIME);
+            run.runtime.run(|| {
+                Render::borrow_mut().set_offscreen_size(Some(size));
+
+                Ok(())
+            });
+
+            UserData::owning(
+                TestRun {
+                    run,
+                    frames,
+                    frame: 0,
+                    result: test_result,
+                },
+                ctx,
+            )
+        });
+
+        let result = result.borrow_mut().take();
+        result.unwrap_or_else(|| Err(anyhow!("window closed before frame {}", frames)))
+    }
+
+    /// Run the game & compare the frame against a golden image.
+    ///
+    /// The golden image is written when it doesn't exist or when the `CLOGS_UPDATE_GOLDEN`
+    /// environment variable is set. When the frame differs too much it's written next to the
+    /// golden image with an `.actual.png` extension, with the differing pixels marked in red in
+    /// an image with a `.diff.png` extension.
+    pub fn compare<P>(&self, clog: Clog, golden: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let golden = golden.as_ref();
+        let actual = self.capture(clog)?;
+
+        if std::env::var_os(UPDATE_GOLDEN_VAR).is_some() || !golden.exists() {
+            log::info!("writing golden image \"{}\"", golden.display());
+
+            return actual.save(golden);
+        }
+
+        let expected = Screenshot::load(golden)?;
+        let (differing, diff) = match actual.diff(&expected, self.tolerance) {
+            Ok(diff) => diff,
+            Err(err) => {
+                actual.save(with_suffix(golden, "actual"))?;
+
+                return Err(anyhow!(
+                    "frame doesn't match \"{}\": {}",
+                    golden.display(),
+                    err
+                ));
+            }
+        };
+
+        let total = (actual.width as usize * actual.height as usize).max(1);
+        if differing as f64 / total as f64 > self.max_differing {
+            actual.save(with_suffix(golden, "actual"))?;
+            diff.save(with_suffix(golden, "diff"))?;
+
+            return Err(anyhow!(
+                "frame doesn't match \"{}\": {} of {} pixels differ more than {}",
+                golden.display(),
+                differing,
+                total,
+                self.tolerance
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The path of an image next to another, e.g. `menu.png` becomes `menu.diff.png`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+
+    path.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+/// Runs the game offscreen until the frame is captured.
+struct TestRun {
+    run: ClogRun,
+    /// The frame that's captured.
+    frames: usize,
+    /// The amount of frames rendered since the assets were loaded.
+    frame: usize,
+    result: Rc<RefCell<Option<Result<Screenshot>>>>,
+}
+
+impl TestRun {
+    /// Stop the game with the result of the test.
+    fn finish(&mut self, ctx: &mut Context, result: Result<Screenshot>) {
+        *self.result.borrow_mut() = Some(result);
+        self.run.shutdown();
+        ctx.order_quit();
+    }
+}
+
+impl EventHandler for TestRun {
+    fn update(&mut self, ctx: &mut Context) {
+        if self.result.borrow().is_some() {
+            return;
+        }
+
+        if !self.run.update_game(Some(ctx)) {
+            self.finish(
+                ctx,
+                Err(anyhow!("engine:update failed during frame {}", self.frame)),
+            );
+        }
+    }
+
+    fn draw(&mut self, ctx: &mut Context) {
+        if self.result.borrow().is_some() || !self.run.started {
+            return;
+        }
+
+        self.frame += 1;
+        let last = self.frame >= self.frames;
+        if last {
+            self.run.runtime.run(|| {
+                Capture::borrow_mut().request_pixels();
+
+                Ok(())
+            });
+        }
+
+        self.run.render_game(Some(ctx));
+        if !last {
+            return;
+        }
+
+        let (failed, pixels) = self
+            .run
+            .runtime
+            .run(|| {
+                Ok((
+                    ErrorScreen::borrow().is_shown(),
+                    Capture::borrow_mut().take_pixels(),
+                ))
+            })
+            .unwrap_or((true, None));
+        let result = match pixels {
+            Some((width, height, pixels)) if !failed => Ok(Screenshot {
+                width,
+                height,
+                pixels,
+            }),
+            _ => Err(anyhow!("engine:render failed during frame {}", self.frame)),
+        };
+        self.finish(ctx, result);
+    }
+}
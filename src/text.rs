@@ -0,0 +1,632 @@
+use crate::{
+    assets::Assets,
+    color::Color,
+    locale::Locales,
+    render::{Mesh, Render, Vertex, VertexCtor},
+    ui::Ui,
+};
+use anyhow::{anyhow, Result};
+use glsp::{bail, rdata, rfn, GResult, Runtime, Sym};
+use lyon::{
+    math::{point, Point},
+    path::{Builder, Path},
+    tessellation::{BuffersBuilder, FillOptions, FillTessellator, VertexBuffers},
+};
+use ttf_parser::{Font, GlyphId, OutlineBuilder};
+
+/// The height of the text in pixels when none is supplied.
+const DEFAULT_SIZE: f32 = 16.0;
+/// The color of the text when none is supplied.
+const DEFAULT_COLOR: (u8, u8, u8) = (255, 255, 255);
+/// The maximum distance between the curves of a glyph and its triangles on the screen, in
+/// physical pixels.
+///
+/// Glyphs are meshes instead of rasterized textures or signed distance fields, so they don't get
+/// blurry but their curves are only smooth up to the zoom they are divided in triangles for.
+const GLYPH_TOLERANCE: f32 = 0.25;
+/// The zoom of the main camera up to which the most detailed glyphs are smooth, unless the
+/// camera is zoomed in further when the text is created.
+const DETAILED_TEXT_ZOOM: f32 = 32.0;
+/// How many coarser levels of detail are drawn when the camera zooms out.
+const TEXT_LOD_LEVELS: usize = 3;
+/// By how much the zoom is divided for every coarser level of detail.
+const TEXT_LOD_FACTOR: f32 = 4.0;
+
+/// Converts the outlines of glyphs into a lyon path.
+struct GlyphBuilder {
+    /// The path that's being built.
+    builder: Builder,
+    /// Converts font units to pixels.
+    scale: f32,
+    /// Where the origin of the current glyph is.
+    offset: Point,
+}
+
+impl GlyphBuilder {
+    /// Convert a point in font units to a point in the path.
+    fn point(&self, x: f32, y: f32) -> Point {
+        // Fonts have the Y axis pointing up, the engine has it pointing down
+        point(
+            self.offset.x + x * self.scale,
+            self.offset.y - y * self.scale,
+        )
+    }
+}
+
+impl OutlineBuilder for GlyphBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let to = self.point(x, y);
+        self.builder.move_to(to);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let to = self.point(x, y);
+        self.builder.line_to(to);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (ctrl, to) = (self.point(x1, y1), self.point(x, y));
+        self.builder.quadratic_bezier_to(ctrl, to);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (ctrl1, ctrl2, to) = (self.point(x1, y1), self.point(x2, y2), self.point(x, y));
+        self.builder.cubic_bezier_to(ctrl1, ctrl2, to);
+    }
+
+    fn close(&mut self) {
+        self.builder.close();
+    }
+}
+
+/// Convert a single line of text into a path that can be tessellated.
+///
+/// The `size` is the height of the font in pixels, `origin` is the top left of the text.
+pub fn text_path(font_data: &[u8], text: &str, size: f32, origin: Point) -> Result<Path> {
+    let font = Font::from_data(font_data, 0).ok_or_else(|| anyhow!("parsing font failed"))?;
+    let units_per_em = font
+        .units_per_em()
+        .ok_or_else(|| anyhow!("font is missing units per em"))?;
+    let scale = size / units_per_em as f32;
+
+    let mut glyph_builder = GlyphBuilder {
+        builder: Path::builder(),
+        scale,
+        // Move the baseline down so the origin is at the top
+        offset: point(origin.x, origin.y + font.ascender() as f32 * scale),
+    };
+
+    for character in text.chars() {
+        let glyph_id = match font.glyph_index(character) {
+            Some(glyph_id) => glyph_id,
+            None => continue,
+        };
+
+        font.outline_glyph(glyph_id, &mut glyph_builder);
+
+        // Move to the position of the next glyph
+        let advance = font.glyph_hor_advance(glyph_id).unwrap_or(0);
+        glyph_builder.offset.x += advance as f32 * scale;
+    }
+
+    Ok(glyph_builder.builder.build())
+}
+
+/// How the lines of a text block are positioned horizontally.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// A run of text with the same style.
+#[derive(Debug, Clone, PartialEq)]
+struct Span {
+    /// The characters of the run.
+    text: String,
+    /// The color of the glyphs.
+    color: (u8, u8, u8),
+    /// The size relative to the size of the text block.
+    scale: f32,
+}
+
+/// A glyph placed by the layout.
+#[derive(Debug, Copy, Clone)]
+struct PlacedGlyph {
+    /// The glyph in the font.
+    glyph: GlyphId,
+    /// The origin of the glyph on the baseline.
+    origin: Point,
+    /// Converts font units to pixels.
+    scale: f32,
+    /// The color of the glyph.
+    color: (u8, u8, u8),
+}
+
+/// The glyphs of a text block placed on lines.
+#[derive(Debug, Default)]
+struct Layout {
+    /// All visible glyphs.
+    glyphs: Vec<PlacedGlyph>,
+    /// The width of the longest line.
+    width: f32,
+    /// The height of all lines combined.
+    height: f32,
+}
+
+/// A single line of characters while laying out the text.
+#[derive(Debug, Default)]
+struct Line {
+    /// The characters with the span they belong to, their position & their advance.
+    characters: Vec<(char, usize, f32, f32)>,
+    /// Where the next character is placed.
+    width: f32,
+}
+
+impl Line {
+    /// The width without the whitespace at the end.
+    fn content_width(&self) -> f32 {
+        self.characters
+            .iter()
+            .rev()
+            .find(|(character, ..)| !character.is_whitespace())
+            .map_or(0.0, |(_, _, x, advance)| x + advance)
+    }
+}
+
+rdata! {
+/// Multi-line text that's wrapped & aligned, with the style changed for parts of it.
+///
+/// The style is changed with markup tags, `[color=255,0,0]red[/]` & `[scale=2]big[/]`. Tags can
+/// be nested and `[[` is a literal `[`.
+///
+/// ```gamelisp
+/// (let block (text-block "Press [color=255,255,0]space[/] to start" 24))
+/// (= [block 'max-width] 200)
+/// (.set-align block 'center)
+/// (add-instance (create-text block) 0 0)
+/// ```
+#[derive(Debug, Clone)]
+pub struct TextBlock {
+    /// The runs of text with their style.
+    spans: Vec<Span>,
+    /// The reference name of the font asset.
+    font: String,
+    /// The height of the font in pixels.
+    size: f32,
+    /// Lines longer than this are wrapped between words.
+    max_width: Option<f32>,
+    /// How the lines are positioned horizontally.
+    align: Align,
+    /// The distance between lines relative to the height of the font.
+    line_spacing: f32,
+}
+
+meths {
+    get "max-width": TextBlock::max_width,
+    set "max-width": TextBlock::set_max_width,
+    get "line-spacing": TextBlock::line_spacing,
+    set "line-spacing": TextBlock::set_line_spacing,
+    "set-align": TextBlock::set_align_script,
+}
+}
+
+impl TextBlock {
+    /// Create a left aligned text block from text with markup tags, without a maximum width.
+    pub fn new(markup: &str, size: f32, color: (u8, u8, u8), font: &str) -> Result<Self> {
+        Ok(Self {
+            spans: parse_markup(markup, color)?,
+            font: font.to_string(),
+            size,
+            max_width: None,
+            align: Align::Left,
+            line_spacing: 1.0,
+        })
+    }
+
+    /// Draw all text in a single color, replacing the colors of the markup tags.
+    pub fn set_color(&mut self, color: (u8, u8, u8)) {
+        for span in self.spans.iter_mut() {
+            span.color = color;
+        }
+    }
+
+    /// Lines longer than this are wrapped between words.
+    pub fn max_width(&self) -> Option<f32> {
+        self.max_width
+    }
+
+    /// Set the width after which lines are wrapped between words, `None` to never wrap.
+    pub fn set_max_width(&mut self, max_width: Option<f32>) {
+        self.max_width = max_width;
+    }
+
+    /// The distance between lines relative to the height of the font.
+    pub fn line_spacing(&self) -> f32 {
+        self.line_spacing
+    }
+
+    /// Set the distance between lines relative to the height of the font, 1 by default.
+    pub fn set_line_spacing(&mut self, line_spacing: f32) {
+        self.line_spacing = line_spacing;
+    }
+
+    /// Set how the lines are positioned horizontally.
+    pub fn set_align(&mut self, align: Align) {
+        self.align = align;
+    }
+
+    /// Set the alignment from a script, `'left`, `'center` or `'right`.
+    fn set_align_script(&mut self, align: Sym) -> GResult<()> {
+        self.align = match &*align.name() {
+            "left" => Align::Left,
+            "center" => Align::Center,
+            "right" => Align::Right,
+            name => bail!("unknown text alignment {}", name),
+        };
+
+        Ok(())
+    }
+
+    /// The width & height of the text in pixels.
+    pub fn measure(&self, font_data: &[u8]) -> Result<(f32, f32)> {
+        let layout = self.layout(&parse_font(font_data)?)?;
+
+        Ok((layout.width, layout.height))
+    }
+
+    /// Tessellate the glyphs with the top left of the text block at the origin.
+    ///
+    /// The tolerance is the maximum distance between the curves & the triangles in the units of
+    /// the text.
+    pub fn tessellate(
+        &self,
+        font_data: &[u8],
+        tolerance: f32,
+        geometry: &mut VertexBuffers<Vertex, u16>,
+    ) -> Result<()> {
+        profile_scope!("tessellate_text");
+
+        let font = parse_font(font_data)?;
+        let layout = self.layout(&font)?;
+
+        let mut tessellator = FillTessellator::new();
+        for glyph in layout.glyphs {
+            let mut glyph_builder = GlyphBuilder {
+                builder: Path::builder(),
+                scale: glyph.scale,
+                offset: glyph.origin,
+            };
+            font.outline_glyph(glyph.glyph, &mut glyph_builder);
+            let path = glyph_builder.builder.build();
+
+            let (red, green, blue) = glyph.color;
+            tessellator
+                .tessellate(
+                    path.iter(),
+                    &FillOptions::tolerance(tolerance),
+                    &mut BuffersBuilder::new(
+                        geometry,
+                        VertexCtor::new(Color::new(red, green, blue), 1.0),
+                    ),
+                )
+                .map_err(|err| anyhow!("tessellating text failed: {:?}", err))?;
+        }
+
+        Ok(())
+    }
+
+    /// Wrap the text into lines and place the glyphs on them.
+    fn layout(&self, font: &Font) -> Result<Layout> {
+        let units_per_em = font
+            .units_per_em()
+            .ok_or_else(|| anyhow!("font is missing units per em"))?;
+        let scale = self.size / units_per_em as f32;
+
+        let characters: Vec<(char, usize)> = self
+            .spans
+            .iter()
+            .enumerate()
+            .flat_map(|(index, span)| span.text.chars().map(move |character| (character, index)))
+            .collect();
+        let advance = |(character, span): (char, usize)| {
+            font.glyph_index(character)
+                .and_then(|glyph| font.glyph_hor_advance(glyph))
+                .unwrap_or(0) as f32
+                * scale
+                * self.spans[span].scale
+        };
+
+        // Place words on the current line until they don't fit anymore
+        let mut lines = vec![Line::default()];
+        let mut index = 0;
+        while index < characters.len() {
+            let (character, _) = characters[index];
+            if character == '\n' {
+                lines.push(Line::default());
+                index += 1;
+
+                continue;
+            }
+
+            // A word or a run of whitespace is placed as a whole
+            let whitespace = character.is_whitespace();
+            let end = characters[index..]
+                .iter()
+                .position(|(character, _)| {
+                    *character == '\n' || character.is_whitespace() != whitespace
+                })
+                .map_or(characters.len(), |offset| index + offset);
+            let word = &characters[index..end];
+            index = end;
+
+            let width: f32 = word.iter().map(|character| advance(*character)).sum();
+            let line = lines.last_mut().expect("there's always a line");
+            if let Some(max_width) = self.max_width {
+                if !line.characters.is_empty() && line.width + width > max_width {
+                    // Whitespace where a line is wrapped isn't shown
+                    if whitespace {
+                        continue;
+                    }
+
+                    lines.push(Line::default());
+                }
+            }
+
+            let line = lines.last_mut().expect("there's always a line");
+            for (character, span) in word {
+                let character_advance = advance((*character, *span));
+                line.characters
+                    .push((*character, *span, line.width, character_advance));
+                line.width += character_advance;
+            }
+        }
+
+        let width = lines.iter().map(Line::content_width).fold(0.0, f32::max);
+        let align_width = self.max_width.unwrap_or(width);
+        let line_height =
+            (font.ascender() as f32 - font.descender() as f32 + font.line_gap() as f32) * scale;
+
+        // Place the glyphs on the baseline of every line
+        let mut layout = Layout {
+            width,
+            ..Layout::default()
+        };
+        for line in lines {
+            // The biggest span on the line determines the height
+            let line_scale = line
+                .characters
+                .iter()
+                .map(|(_, span, ..)| self.spans[*span].scale)
+                .fold(None, |max: Option<f32>, scale| {
+                    Some(max.map_or(scale, |max| max.max(scale)))
+                })
+                .unwrap_or(1.0);
+            let baseline = layout.height + font.ascender() as f32 * scale * line_scale;
+
+            let offset = match self.align {
+                Align::Left => 0.0,
+                Align::Center => (align_width - line.content_width()) / 2.0,
+                Align::Right => align_width - line.content_width(),
+            };
+            for (character, span, x, _) in line.characters {
+                let glyph = match font.glyph_index(character) {
+                    Some(glyph) if !character.is_whitespace() => glyph,
+                    _ => continue,
+                };
+                let span = &self.spans[span];
+
+                layout.glyphs.push(PlacedGlyph {
+                    glyph,
+                    origin: point(offset + x, baseline),
+                    scale: scale * span.scale,
+                    color: span.color,
+                });
+            }
+
+            layout.height += line_height * line_scale * self.line_spacing;
+        }
+
+        Ok(layout)
+    }
+
+    /// The font data of the font asset.
+    fn with_font_data<F, R>(&self, func: F) -> GResult<R>
+    where
+        F: FnOnce(&[u8]) -> Result<R>,
+    {
+        let assets = Assets::borrow();
+        let font_data = match assets.data(&self.font) {
+            Some(font_data) => font_data,
+            None => bail!("font asset \"{}\" is not loaded", self.font),
+        };
+
+        match func(font_data) {
+            Ok(result) => Ok(result),
+            Err(err) => bail!("laying out text failed: {}", err),
+        }
+    }
+}
+
+/// Split text with markup tags into runs with the same style.
+fn parse_markup(markup: &str, color: (u8, u8, u8)) -> Result<Vec<Span>> {
+    // The style of the innermost tag is applied
+    let mut styles = vec![(color, 1.0)];
+    let mut spans = vec![];
+    let mut text = String::new();
+
+    let mut characters = markup.chars().peekable();
+    while let Some(character) = characters.next() {
+        if character != '[' {
+            text.push(character);
+
+            continue;
+        }
+        if characters.peek() == Some(&'[') {
+            characters.next();
+            text.push('[');
+
+            continue;
+        }
+
+        let mut tag = String::new();
+        loop {
+            match characters.next() {
+                Some(']') => break,
+                Some(character) => tag.push(character),
+                None => return Err(anyhow!("tag \"[{}\" is not closed", tag)),
+            }
+        }
+
+        // The text before the tag has the previous style
+        let (color, scale) = *styles.last().expect("there's always a style");
+        if !text.is_empty() {
+            spans.push(Span {
+                text: std::mem::take(&mut text),
+                color,
+                scale,
+            });
+        }
+
+        if tag == "/" {
+            if styles.len() == 1 {
+                return Err(anyhow!("\"[/]\" doesn't close a tag"));
+            }
+            styles.pop();
+
+            continue;
+        }
+
+        let mut parts = tag.splitn(2, '=');
+        let (name, value) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        styles.push(match name.trim() {
+            "color" => {
+                let channels = value
+                    .split(',')
+                    .map(|channel| channel.trim().parse::<u8>())
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(|err| anyhow!("invalid color \"{}\": {}", value, err))?;
+                match channels.as_slice() {
+                    [red, green, blue] => ((*red, *green, *blue), scale),
+                    _ => return Err(anyhow!("color \"{}\" must be red,green,blue", value)),
+                }
+            }
+            "scale" => match value.trim().parse::<f32>() {
+                Ok(scale) if scale > 0.0 => (color, scale),
+                _ => return Err(anyhow!("invalid scale \"{}\"", value)),
+            },
+            name => return Err(anyhow!("unknown tag \"{}\"", name)),
+        });
+    }
+
+    let (color, scale) = *styles.last().expect("there's always a style");
+    if !text.is_empty() {
+        spans.push(Span { text, color, scale });
+    }
+
+    Ok(spans)
+}
+
+/// Parse the font data.
+fn parse_font(font_data: &[u8]) -> Result<Font> {
+    Font::from_data(font_data, 0).ok_or_else(|| anyhow!("parsing font failed"))
+}
+
+/// Create a text block from a script, without a font the one of the current language is used.
+///
+/// The size is multiplied with the UI scale, in high contrast mode the colors of the markup tags
+/// are ignored.
+fn text_block(
+    markup: &str,
+    size: Option<f32>,
+    color: Option<(u8, u8, u8)>,
+    font: Option<&str>,
+) -> GResult<TextBlock> {
+    let (scale, high_contrast) = {
+        let ui = Ui::borrow();
+
+        (ui.scale(), ui.is_high_contrast())
+    };
+    let color = color.unwrap_or(DEFAULT_COLOR);
+
+    match TextBlock::new(
+        markup,
+        size.unwrap_or(DEFAULT_SIZE) * scale,
+        color,
+        font.unwrap_or(Locales::borrow().font()),
+    ) {
+        Ok(mut block) => {
+            if high_contrast {
+                block.set_color(color);
+            }
+
+            Ok(block)
+        }
+        Err(err) => bail!("parsing text markup failed: {}", err),
+    }
+}
+
+/// Get the width of the longest line of a text block in pixels from a script.
+fn text_width(block: &TextBlock) -> GResult<f32> {
+    block.with_font_data(|font_data| Ok(block.measure(font_data)?.0))
+}
+
+/// Get the height of all lines of a text block in pixels from a script.
+fn text_height(block: &TextBlock) -> GResult<f32> {
+    block.with_font_data(|font_data| Ok(block.measure(font_data)?.1))
+}
+
+/// Create a mesh from a text block with its top left at the origin from a script.
+///
+/// The glyphs are tessellated at multiple levels of detail so they stay smooth when the camera
+/// zooms in without drawing more triangles than needed when it zooms out. Levels that have too
+/// many vertices for a single mesh, like the most detailed ones of long paragraphs, are skipped.
+///
+/// Text isn't resolution independent: it's smooth up to a camera zoom of 32, or the zoom when
+/// it's created if that's higher. Zoomed in further the straight segments of the curves become
+/// visible, so text that's shown that large must be created again at the higher zoom.
+fn create_text(render: &mut Render, block: &TextBlock) -> GResult<Mesh> {
+    let detailed_zoom = DETAILED_TEXT_ZOOM.max(render.view_zoom());
+
+    let mut levels = Vec::with_capacity(TEXT_LOD_LEVELS + 1);
+    let mut last_error = None;
+    for level in 0..=TEXT_LOD_LEVELS {
+        let zoom = detailed_zoom / TEXT_LOD_FACTOR.powi(level as i32);
+        // A unit of the text covers half of the zoom in pixels
+        let tolerance = GLYPH_TOLERANCE * 2.0 / zoom;
+
+        let mut geometry: VertexBuffers<Vertex, u16> = VertexBuffers::new();
+        match block
+            .with_font_data(|font_data| Ok(block.tessellate(font_data, tolerance, &mut geometry)))?
+        {
+            Ok(()) => levels.push((zoom, geometry)),
+            Err(err) => last_error = Some(err),
+        }
+    }
+
+    if levels.is_empty() {
+        match last_error {
+            Some(err) => bail!("{}", err),
+            None => bail!("text doesn't have any levels of detail"),
+        }
+    }
+    let (_, detailed) = levels.remove(0);
+
+    match render.upload_buffers_lods(&detailed, &levels) {
+        Ok(mesh) => Ok(mesh),
+        Err(err) => bail!("uploading text failed: {}", err),
+    }
+}
+
+/// Bind the GameLisp functions for laying out text.
+pub fn bind_functions(runtime: &Runtime) {
+    runtime.run(|| {
+        glsp::bind_rfn("text-block", rfn!(text_block))?;
+        glsp::bind_rfn("text-width", rfn!(text_width))?;
+        glsp::bind_rfn("text-height", rfn!(text_height))?;
+        glsp::bind_rfn("create-text", rfn!(create_text))?;
+
+        Ok(())
+    });
+}
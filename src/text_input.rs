@@ -0,0 +1,302 @@
+use crate::{event::Events, window::Window};
+use glsp::{lib, rfn, GResult, Runtime};
+use miniquad::Context;
+
+lib! {
+/// An editable line of text receiving the typed characters, for name entry screens & chat boxes.
+///
+/// While the text input is active the pressed keys aren't emitted as `key-down` events. Every
+/// edit emits `text-input-changed` with the value, enter emits `text-input-submitted` with the
+/// value & escape emits `text-input-cancelled`, both end the text input. Text composed with an
+/// IME is inserted when it's committed, the composition itself isn't shown.
+///
+/// ```gamelisp
+/// (begin-text-input "Player" 16)
+/// (on-event 'text-input-submitted (fn (name)
+///   (= player-name name)))
+/// ```
+pub struct TextInput {
+    /// Whether typed characters are added to the value.
+    active: bool,
+    /// The text that's being edited.
+    value: String,
+    /// The position of the cursor in characters.
+    cursor: usize,
+    /// Where the selection started in characters, it ends at the cursor.
+    anchor: Option<usize>,
+    /// The maximum amount of characters of the value.
+    max_length: Option<usize>,
+    /// Whether a shift key is held, extending the selection when moving the cursor.
+    shift: bool,
+    /// Whether a control or command key is held, for the shortcuts.
+    control: bool,
+    /// Whether the on-screen keyboard must be shown or hidden.
+    show_keyboard: Option<bool>,
+}
+}
+
+impl TextInput {
+    /// Create an inactive text input.
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            value: String::new(),
+            cursor: 0,
+            anchor: None,
+            max_length: None,
+            shift: false,
+            control: false,
+            show_keyboard: None,
+        }
+    }
+
+    /// Start editing a value with the cursor at the end.
+    ///
+    /// ```gamelisp
+    /// (begin-text-input)
+    /// (begin-text-input "initial value" 32)
+    /// ```
+    pub fn begin(&mut self, value: Option<&str>, max_length: Option<usize>) {
+        self.active = true;
+        self.value = value.unwrap_or("").to_string();
+        self.max_length = max_length;
+        if let Some(max_length) = max_length {
+            self.value = self.value.chars().take(max_length).collect();
+        }
+        self.cursor = self.value.chars().count();
+        self.anchor = None;
+        self.show_keyboard = Some(true);
+    }
+
+    /// Stop editing, the value is kept until the next text input begins.
+    pub fn end(&mut self) {
+        self.active = false;
+        self.anchor = None;
+        self.show_keyboard = Some(false);
+    }
+
+    /// Whether typed characters are added to the value.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// The text that's being edited.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Replace the value, moving the cursor to the end.
+    pub fn set_value(&mut self, value: &str) {
+        self.value = value.to_string();
+        self.cursor = self.value.chars().count();
+        self.anchor = None;
+    }
+
+    /// The position of the cursor in characters.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// The first & the last character of the selection, if anything is selected.
+    pub fn selection(&self) -> Option<(usize, usize)> {
+        match self.anchor {
+            Some(anchor) if anchor != self.cursor => {
+                Some((anchor.min(self.cursor), anchor.max(self.cursor)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Insert a typed character at the cursor, replacing the selection.
+    ///
+    /// Must be called inside the GameLisp runtime.
+    pub fn char_typed(&mut self, character: char) -> GResult<()> {
+        if !self.active || character.is_control() || self.control {
+            return Ok(());
+        }
+
+        self.delete_selection();
+        if self
+            .max_length
+            .map_or(false, |max_length| self.value.chars().count() >= max_length)
+        {
+            return Ok(());
+        }
+
+        let index = self.byte_index(self.cursor);
+        self.value.insert(index, character);
+        self.cursor += 1;
+
+        self.changed()
+    }
+
+    /// Handle a pressed key by its name, returns whether it's used by the text input.
+    ///
+    /// Must be called inside the GameLisp runtime.
+    pub fn key_down(&mut self, key: &str) -> GResult<bool> {
+        match key {
+            "left-shift" | "right-shift" => self.shift = true,
+            "left-control" | "right-control" | "left-super" | "right-super" => self.control = true,
+            _ => (),
+        }
+        if !self.active {
+            return Ok(false);
+        }
+
+        let length = self.value.chars().count();
+        match key {
+            "backspace" => {
+                if !self.delete_selection() {
+                    if self.cursor == 0 {
+                        return Ok(true);
+                    }
+
+                    self.cursor -= 1;
+                    self.remove(self.cursor, self.cursor + 1);
+                }
+                self.changed()?;
+            }
+            "delete" => {
+                if !self.delete_selection() {
+                    if self.cursor == length {
+                        return Ok(true);
+                    }
+
+                    self.remove(self.cursor, self.cursor + 1);
+                }
+                self.changed()?;
+            }
+            "left" => self.move_cursor(self.cursor.saturating_sub(1), |(start, _)| start),
+            "right" => self.move_cursor((self.cursor + 1).min(length), |(_, end)| end),
+            "home" => self.move_cursor(0, |_| 0),
+            "end" => self.move_cursor(length, |_| length),
+            "a" if self.control => {
+                self.anchor = Some(0);
+                self.cursor = length;
+            }
+            "c" | "x" if self.control => {
+                if let Some((start, end)) = self.selection() {
+                    let selected: String =
+                        self.value.chars().skip(start).take(end - start).collect();
+                    Window::borrow_mut().set_clipboard(&selected);
+
+                    if key == "x" {
+                        self.delete_selection();
+                        self.changed()?;
+                    }
+                }
+            }
+            "enter" | "kp-enter" => {
+                self.end();
+                Events::emit_engine_event("text-input-submitted", self.value.clone())?;
+            }
+            "escape" => {
+                self.end();
+                Events::emit_engine_event("text-input-cancelled", self.value.clone())?;
+            }
+            // Other keys are only used for typing
+            _ => (),
+        }
+
+        Ok(true)
+    }
+
+    /// Handle a released key by its name.
+    pub fn key_up(&mut self, key: &str) {
+        match key {
+            "left-shift" | "right-shift" => self.shift = false,
+            "left-control" | "right-control" | "left-super" | "right-super" => self.control = false,
+            _ => (),
+        }
+    }
+
+    /// Whether the key combination pastes the clipboard into the value.
+    pub fn is_paste(&self, key: &str) -> bool {
+        self.active && self.control && key == "v"
+    }
+
+    /// Show or hide the on-screen keyboard when the text input began or ended.
+    pub fn apply(&mut self, ctx: &mut Context) {
+        if let Some(show) = self.show_keyboard.take() {
+            ctx.show_keyboard(show);
+        }
+    }
+
+    /// Move the cursor, extending the selection when shift is held.
+    ///
+    /// Without shift a selection is collapsed to the side picked from the selection instead.
+    fn move_cursor<F>(&mut self, to: usize, collapse: F)
+    where
+        F: FnOnce((usize, usize)) -> usize,
+    {
+        if self.shift {
+            self.anchor.get_or_insert(self.cursor);
+            self.cursor = to;
+        } else {
+            self.cursor = self.selection().map_or(to, collapse);
+            self.anchor = None;
+        }
+    }
+
+    /// Remove the selected characters, returns whether anything was selected.
+    fn delete_selection(&mut self) -> bool {
+        let selection = self.selection();
+        self.anchor = None;
+
+        match selection {
+            Some((start, end)) => {
+                self.remove(start, end);
+                self.cursor = start;
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove a range of characters.
+    fn remove(&mut self, start: usize, end: usize) {
+        let range = self.byte_index(start)..self.byte_index(end);
+        self.value.replace_range(range, "");
+    }
+
+    /// The position in bytes of a character.
+    fn byte_index(&self, character: usize) -> usize {
+        self.value
+            .char_indices()
+            .nth(character)
+            .map_or(self.value.len(), |(index, _)| index)
+    }
+
+    /// Notify the scripts that the value is edited.
+    fn changed(&self) -> GResult<()> {
+        Events::emit_engine_event("text-input-changed", self.value.clone())
+    }
+
+    /// Get the value from a script.
+    fn value_script(&self) -> String {
+        self.value.clone()
+    }
+
+    /// Get the first & the last selected character from a script, `#n` without a selection.
+    fn selection_script(&self) -> Option<(usize, usize)> {
+        self.selection()
+    }
+
+    /// Register the text input and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(TextInput::new());
+
+            glsp::bind_rfn("begin-text-input", rfn!(TextInput::begin))?;
+            glsp::bind_rfn("end-text-input", rfn!(TextInput::end))?;
+            glsp::bind_rfn("text-input-active?", rfn!(TextInput::is_active))?;
+            glsp::bind_rfn("text-input-value", rfn!(TextInput::value_script))?;
+            glsp::bind_rfn("set-text-input-value", rfn!(TextInput::set_value))?;
+            glsp::bind_rfn("text-input-cursor", rfn!(TextInput::cursor))?;
+            glsp::bind_rfn("text-input-selection", rfn!(TextInput::selection_script))?;
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,565 @@
+use crate::{
+    assets::Assets,
+    render::{Instance, InstanceRef, Mesh, Render},
+};
+use anyhow::{anyhow, Result};
+use glsp::{bail, lib, rdata, rfn, GResult, Runtime};
+use serde_json::Value;
+use std::{collections::HashMap, f32::consts::FRAC_PI_2, io::Read, ops::Range};
+
+/// The amount of tiles in both directions of a chunk that's shown or hidden at once.
+const CHUNK_SIZE: usize = 16;
+/// Bit of a tile identifier telling it's mirrored horizontally.
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+/// Bit of a tile identifier telling it's mirrored vertically.
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+/// Bit of a tile identifier telling its X & Y axis are swapped.
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+
+/// A single layer of tiles.
+#[derive(Debug)]
+struct TileLayer {
+    /// The name of the layer in Tiled.
+    name: String,
+    /// The tile identifiers including the flip bits, row by row.
+    tiles: Vec<u32>,
+    /// Whether the tiles of this layer block movement.
+    collision: bool,
+}
+
+/// A map made with the Tiled editor.
+///
+/// Every tile identifier is rendered with the mesh that's assigned to it, at the center of the
+/// tile. Only the chunks of tiles that are on screen have instances.
+#[derive(Debug)]
+pub struct Tilemap {
+    /// The amount of columns.
+    width: usize,
+    /// The amount of rows.
+    height: usize,
+    /// The size of a single tile in world units.
+    tile_size: (f32, f32),
+    /// The tile layers in the order they are drawn.
+    layers: Vec<TileLayer>,
+    /// The world position of the top left corner.
+    position: (f32, f32),
+    /// The mesh that's drawn for each tile identifier.
+    tile_meshes: HashMap<u32, Mesh>,
+    /// The instances of the chunks on the screen by layer, column & row of the chunk.
+    visible_chunks: HashMap<(usize, usize, usize), Vec<InstanceRef>>,
+    /// Whether the instances of all chunks must be created again.
+    changed: bool,
+}
+
+impl Tilemap {
+    /// Parse a Tiled map, either a TMX or a JSON file.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let text =
+            std::str::from_utf8(data).map_err(|err| anyhow!("tilemap isn't UTF-8: {}", err))?;
+
+        if text.trim_start().starts_with('{') {
+            Self::from_json(text)
+        } else {
+            Self::from_tmx(text)
+        }
+    }
+
+    /// Parse a Tiled map in the XML format.
+    fn from_tmx(text: &str) -> Result<Self> {
+        let document = roxmltree::Document::parse(text)
+            .map_err(|err| anyhow!("parsing TMX failed: {}", err))?;
+        let map = document.root_element();
+        if !map.has_tag_name("map") {
+            return Err(anyhow!("TMX doesn't contain a map"));
+        }
+        if map.attribute("infinite") == Some("1") {
+            return Err(anyhow!("infinite tilemaps aren't supported"));
+        }
+
+        let number = |name: &str| -> Result<usize> {
+            map.attribute(name)
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| anyhow!("TMX map is missing \"{}\"", name))
+        };
+        let (width, height) = (number("width")?, number("height")?);
+        let tile_size = (number("tilewidth")? as f32, number("tileheight")? as f32);
+
+        // Layers can also be nested in groups
+        let layers = map
+            .descendants()
+            .filter(|node| node.has_tag_name("layer"))
+            .map(|layer| {
+                let name = layer.attribute("name").unwrap_or_default().to_string();
+                let collision = layer
+                    .descendants()
+                    .filter(|node| node.has_tag_name("property"))
+                    .any(|property| {
+                        property.attribute("name") == Some("collision")
+                            && property.attribute("value") == Some("true")
+                    });
+
+                let data = layer
+                    .children()
+                    .find(|node| node.has_tag_name("data"))
+                    .ok_or_else(|| anyhow!("layer \"{}\" has no data", name))?;
+                let tiles = match data.attribute("encoding") {
+                    // Every tile is a XML element
+                    None => data
+                        .children()
+                        .filter(|node| node.has_tag_name("tile"))
+                        .map(|tile| tile.attribute("gid").unwrap_or("0").parse())
+                        .collect::<Result<Vec<u32>, _>>()
+                        .map_err(|err| anyhow!("invalid tile in layer \"{}\": {}", name, err))?,
+                    Some(encoding) => decode_tiles(
+                        encoding,
+                        data.attribute("compression"),
+                        data.text().unwrap_or_default(),
+                    )?,
+                };
+
+                TileLayer::new(name, tiles, collision, width * height)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self::new(width, height, tile_size, layers))
+    }
+
+    /// Parse a Tiled map in the JSON format.
+    fn from_json(text: &str) -> Result<Self> {
+        let map: Value =
+            serde_json::from_str(text).map_err(|err| anyhow!("parsing JSON failed: {}", err))?;
+        if map["infinite"] == Value::Bool(true) {
+            return Err(anyhow!("infinite tilemaps aren't supported"));
+        }
+
+        let number = |name: &str| -> Result<usize> {
+            map[name]
+                .as_u64()
+                .map(|value| value as usize)
+                .ok_or_else(|| anyhow!("JSON map is missing \"{}\"", name))
+        };
+        let (width, height) = (number("width")?, number("height")?);
+        let tile_size = (number("tilewidth")? as f32, number("tileheight")? as f32);
+
+        let mut layers = vec![];
+        json_layers(&map["layers"], width * height, &mut layers)?;
+
+        Ok(Self::new(width, height, tile_size, layers))
+    }
+
+    /// Create the map without any meshes assigned to the tiles.
+    fn new(width: usize, height: usize, tile_size: (f32, f32), layers: Vec<TileLayer>) -> Self {
+        Self {
+            width,
+            height,
+            tile_size,
+            layers,
+            position: (0.0, 0.0),
+            tile_meshes: HashMap::new(),
+            visible_chunks: HashMap::new(),
+            changed: false,
+        }
+    }
+
+    /// Draw a tile identifier with a mesh.
+    pub fn set_tile_mesh(&mut self, tile: u32, mesh: Mesh) {
+        self.tile_meshes.insert(tile, mesh);
+        self.changed = true;
+    }
+
+    /// Move the top left corner of the map to a world position.
+    pub fn set_position(&mut self, x: f32, y: f32) {
+        self.position = (x, y);
+        self.changed = true;
+    }
+
+    /// The names of all tile layers.
+    pub fn layer_names(&self) -> Vec<String> {
+        self.layers.iter().map(|layer| layer.name.clone()).collect()
+    }
+
+    /// The column & row of the tile at a world position.
+    pub fn tile_coordinates(&self, x: f32, y: f32) -> Option<(usize, usize)> {
+        let column = ((x - self.position.0) / self.tile_size.0).floor();
+        let row = ((y - self.position.1) / self.tile_size.1).floor();
+        if column < 0.0 || row < 0.0 || column >= self.width as f32 || row >= self.height as f32 {
+            return None;
+        }
+
+        Some((column as usize, row as usize))
+    }
+
+    /// The tile identifier at a world position, `None` if there's no tile.
+    ///
+    /// Without a layer name the tile of the top-most layer that has one is returned.
+    pub fn tile_at(&self, x: f32, y: f32, layer: Option<&str>) -> Option<u32> {
+        let (column, row) = self.tile_coordinates(x, y)?;
+
+        self.layers
+            .iter()
+            .rev()
+            .filter(|l| layer.map_or(true, |name| l.name == name))
+            .map(|l| l.tiles[row * self.width + column] & !flip_bits())
+            .find(|tile| *tile != 0)
+    }
+
+    /// Whether any of the collision layers has a tile at a world position.
+    pub fn is_solid(&self, x: f32, y: f32) -> bool {
+        let (column, row) = match self.tile_coordinates(x, y) {
+            Some(coordinates) => coordinates,
+            None => return false,
+        };
+
+        self.layers
+            .iter()
+            .filter(|layer| layer.collision)
+            .any(|layer| layer.tiles[row * self.width + column] & !flip_bits() != 0)
+    }
+
+    /// Create the instances of the chunks that came on screen and remove the ones that left.
+    pub fn update(&mut self, render: &mut Render) {
+        if self.changed {
+            for (_, instances) in self.visible_chunks.drain() {
+                instances.into_iter().for_each(|instance| {
+                    render.remove_instance(instance);
+                });
+            }
+
+            self.changed = false;
+        }
+
+        // The chunks overlapping with the screen
+        let (screen_width, screen_height) = render.screen_size();
+        let (left, top) = render.screen_to_world(0.0, 0.0);
+        let (right, bottom) = render.screen_to_world(screen_width, screen_height);
+        let columns = self.visible_range(left, right, self.tile_size.0, self.width);
+        let rows = self.visible_range(top, bottom, self.tile_size.1, self.height);
+
+        // Remove the chunks that left the screen
+        self.visible_chunks.retain(|(_, column, row), instances| {
+            let visible = columns.contains(column) && rows.contains(row);
+            if !visible {
+                instances.drain(..).for_each(|instance| {
+                    render.remove_instance(instance);
+                });
+            }
+
+            visible
+        });
+
+        // Add the chunks that came on screen
+        for layer in 0..self.layers.len() {
+            for row in rows.clone() {
+                for column in columns.clone() {
+                    if !self.visible_chunks.contains_key(&(layer, column, row)) {
+                        let instances = self.chunk_instances(layer, column, row, render);
+                        self.visible_chunks.insert((layer, column, row), instances);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remove the instances of all chunks.
+    pub fn clear(&mut self, render: &mut Render) {
+        for (_, instances) in self.visible_chunks.drain() {
+            instances.into_iter().for_each(|instance| {
+                render.remove_instance(instance);
+            });
+        }
+    }
+
+    /// The chunks between two world positions on a single axis.
+    fn visible_range(&self, start: f32, end: f32, tile_size: f32, tiles: usize) -> Range<usize> {
+        let offset = if tiles == self.width {
+            self.position.0
+        } else {
+            self.position.1
+        };
+        let chunk_size = tile_size * CHUNK_SIZE as f32;
+        let chunks = (tiles + CHUNK_SIZE - 1) / CHUNK_SIZE;
+
+        let first = ((start - offset) / chunk_size).floor().max(0.0) as usize;
+        let last = ((end - offset) / chunk_size).floor();
+        if last < 0.0 {
+            return 0..0;
+        }
+
+        first.min(chunks)..(last as usize + 1).min(chunks)
+    }
+
+    /// Create the instances for all tiles with a mesh in a chunk.
+    fn chunk_instances(
+        &self,
+        layer: usize,
+        chunk_column: usize,
+        chunk_row: usize,
+        render: &mut Render,
+    ) -> Vec<InstanceRef> {
+        let tiles = &self.layers[layer].tiles;
+
+        let mut instances = vec![];
+        let rows = chunk_row * CHUNK_SIZE..((chunk_row + 1) * CHUNK_SIZE).min(self.height);
+        for row in rows {
+            let columns =
+                chunk_column * CHUNK_SIZE..((chunk_column + 1) * CHUNK_SIZE).min(self.width);
+            for column in columns {
+                let tile = tiles[row * self.width + column];
+                let mesh = match self.tile_meshes.get(&(tile & !flip_bits())) {
+                    Some(mesh) => *mesh,
+                    None => continue,
+                };
+
+                let mut instance = Instance::new(
+                    self.position.0 + (column as f32 + 0.5) * self.tile_size.0,
+                    self.position.1 + (row as f32 + 0.5) * self.tile_size.1,
+                );
+
+                // Mirror the tile, a diagonal flip is a rotation with a vertical mirror
+                let horizontal = if tile & FLIPPED_HORIZONTALLY != 0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                let vertical = if tile & FLIPPED_VERTICALLY != 0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                if tile & FLIPPED_DIAGONALLY != 0 {
+                    instance.set_rotation(FRAC_PI_2);
+                    instance.set_scale_x(vertical);
+                    instance.set_scale_y(-horizontal);
+                } else {
+                    instance.set_scale_x(horizontal);
+                    instance.set_scale_y(vertical);
+                }
+
+                instances.push(render.add_instance(mesh, instance));
+            }
+        }
+
+        instances
+    }
+}
+
+impl TileLayer {
+    /// Create a layer, fails when the amount of tiles doesn't match the map.
+    fn new(name: String, tiles: Vec<u32>, collision: bool, tile_count: usize) -> Result<Self> {
+        if tiles.len() != tile_count {
+            return Err(anyhow!(
+                "layer \"{}\" has {} tiles instead of {}",
+                name,
+                tiles.len(),
+                tile_count
+            ));
+        }
+
+        // Layers named collision are also collision layers
+        let collision = collision || name.eq_ignore_ascii_case("collision");
+
+        Ok(Self {
+            name,
+            tiles,
+            collision,
+        })
+    }
+}
+
+rdata! {
+/// A reference to a tilemap.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TilemapRef(usize);
+}
+
+lib! {
+/// All tilemaps created by scripts.
+pub struct Tilemaps {
+    /// The tilemaps, a reference is the index.
+    maps: Vec<Tilemap>,
+}
+}
+
+impl Tilemaps {
+    /// Create without any tilemaps.
+    pub fn new() -> Self {
+        Self { maps: vec![] }
+    }
+
+    /// Add a parsed tilemap.
+    pub fn add(&mut self, tilemap: Tilemap) -> TilemapRef {
+        self.maps.push(tilemap);
+
+        TilemapRef(self.maps.len() - 1)
+    }
+
+    /// Get a tilemap.
+    pub fn get(&self, tilemap: TilemapRef) -> &Tilemap {
+        &self.maps[tilemap.0]
+    }
+
+    /// Get a tilemap for modifying it.
+    pub fn get_mut(&mut self, tilemap: TilemapRef) -> &mut Tilemap {
+        &mut self.maps[tilemap.0]
+    }
+
+    /// Update the visible chunks of all tilemaps, must be called before rendering.
+    pub fn update(&mut self, render: &mut Render) {
+        for tilemap in self.maps.iter_mut() {
+            tilemap.update(render);
+        }
+    }
+
+    /// Create a tilemap from a loaded asset.
+    ///
+    /// ```gamelisp
+    /// (let level (create-tilemap "level-1"))
+    /// ```
+    fn create_tilemap(&mut self, asset: &str) -> GResult<TilemapRef> {
+        let tilemap = match Assets::borrow().data(asset) {
+            Some(data) => Tilemap::parse(data),
+            None => bail!("tilemap asset \"{}\" isn't loaded", asset),
+        };
+
+        match tilemap {
+            Ok(tilemap) => Ok(self.add(tilemap)),
+            Err(err) => bail!("creating tilemap \"{}\" failed: {}", asset, err),
+        }
+    }
+
+    /// Draw a tile identifier with a mesh from a script.
+    ///
+    /// ```gamelisp
+    /// (set-tile-mesh level 1 (asset-mesh "grass"))
+    /// ```
+    fn set_tile_mesh(&mut self, tilemap: &TilemapRef, tile: u32, mesh: &Mesh) {
+        self.get_mut(*tilemap).set_tile_mesh(tile, *mesh);
+    }
+
+    /// Move a tilemap from a script.
+    fn set_tilemap_position(&mut self, tilemap: &TilemapRef, x: f32, y: f32) {
+        self.get_mut(*tilemap).set_position(x, y);
+    }
+
+    /// Get the tile identifier at a world position from a script.
+    ///
+    /// ```gamelisp
+    /// (tile-at level x y)
+    /// (tile-at level x y "ground")
+    /// ```
+    fn tile_at(&self, tilemap: &TilemapRef, x: f32, y: f32, layer: Option<&str>) -> Option<u32> {
+        self.get(*tilemap).tile_at(x, y, layer)
+    }
+
+    /// Check from a script whether a collision layer has a tile at a world position.
+    fn tile_solid(&self, tilemap: &TilemapRef, x: f32, y: f32) -> bool {
+        self.get(*tilemap).is_solid(x, y)
+    }
+
+    /// Get the names of the layers from a script.
+    fn tilemap_layers(&self, tilemap: &TilemapRef) -> Vec<String> {
+        self.get(*tilemap).layer_names()
+    }
+
+    /// Register the tilemaps and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Tilemaps::new());
+
+            glsp::bind_rfn("create-tilemap", rfn!(Self::create_tilemap))?;
+            glsp::bind_rfn("set-tile-mesh", rfn!(Self::set_tile_mesh))?;
+            glsp::bind_rfn("set-tilemap-position", rfn!(Self::set_tilemap_position))?;
+            glsp::bind_rfn("tile-at", rfn!(Self::tile_at))?;
+            glsp::bind_rfn("tile-solid?", rfn!(Self::tile_solid))?;
+            glsp::bind_rfn("tilemap-layers", rfn!(Self::tilemap_layers))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// All bits of a tile identifier that aren't part of the identifier itself.
+fn flip_bits() -> u32 {
+    FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY
+}
+
+/// Collect the tile layers of a JSON map, including the ones nested in groups.
+fn json_layers(layers: &Value, tile_count: usize, result: &mut Vec<TileLayer>) -> Result<()> {
+    for layer in layers.as_array().into_iter().flatten() {
+        match layer["type"].as_str() {
+            Some("tilelayer") => (),
+            Some("group") => {
+                json_layers(&layer["layers"], tile_count, result)?;
+                continue;
+            }
+            _ => continue,
+        }
+
+        let name = layer["name"].as_str().unwrap_or_default().to_string();
+        let collision = layer["properties"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|property| {
+                property["name"] == "collision" && property["value"] == Value::Bool(true)
+            });
+
+        let tiles = match layer["data"] {
+            Value::Array(ref tiles) => tiles
+                .iter()
+                .map(|tile| tile.as_u64().map(|tile| tile as u32))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| anyhow!("invalid tile in layer \"{}\"", name))?,
+            Value::String(ref data) => decode_tiles(
+                layer["encoding"].as_str().unwrap_or("base64"),
+                layer["compression"].as_str(),
+                data,
+            )?,
+            _ => return Err(anyhow!("layer \"{}\" has no data", name)),
+        };
+
+        result.push(TileLayer::new(name, tiles, collision, tile_count)?);
+    }
+
+    Ok(())
+}
+
+/// Decode the CSV or base64 encoded tiles of a layer.
+fn decode_tiles(encoding: &str, compression: Option<&str>, data: &str) -> Result<Vec<u32>> {
+    match encoding {
+        "csv" => data
+            .split(',')
+            .map(str::trim)
+            .filter(|tile| !tile.is_empty())
+            .map(|tile| {
+                tile.parse()
+                    .map_err(|err| anyhow!("invalid tile \"{}\": {}", tile, err))
+            })
+            .collect(),
+        "base64" => {
+            let bytes = base64::decode(data.trim())
+                .map_err(|err| anyhow!("decoding base64 tiles failed: {}", err))?;
+
+            let mut decompressed = vec![];
+            match compression {
+                None | Some("") => decompressed = bytes,
+                Some("zlib") => {
+                    flate2::read::ZlibDecoder::new(&bytes[..]).read_to_end(&mut decompressed)?;
+                }
+                Some("gzip") => {
+                    flate2::read::GzDecoder::new(&bytes[..]).read_to_end(&mut decompressed)?;
+                }
+                Some(compression) => {
+                    return Err(anyhow!("unsupported tile compression \"{}\"", compression))
+                }
+            }
+
+            // Every tile is a little endian 32 bit integer
+            Ok(decompressed
+                .chunks_exact(4)
+                .map(|tile| u32::from_le_bytes([tile[0], tile[1], tile[2], tile[3]]))
+                .collect())
+        }
+        _ => Err(anyhow!("unsupported tile encoding \"{}\"", encoding)),
+    }
+}
@@ -0,0 +1,204 @@
+use crate::sandbox;
+use glsp::{bail, lib, rdata, rfn, GFn, GResult, Root, Runtime, Val};
+use std::collections::HashMap;
+
+/// The shortest interval in seconds of a repeating timer.
+const MIN_INTERVAL: f64 = 0.001;
+/// How often a repeating timer is called during a single update to catch up, the calls it's
+/// still behind after that are dropped.
+const MAX_CATCH_UP_CALLS: u32 = 16;
+
+rdata! {
+/// A reference to a scheduled timer, used to cancel it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimerRef(u32);
+}
+
+/// A function that's called after a delay, optionally repeating.
+#[derive(Debug)]
+struct Timer {
+    /// The identifier referenced by the scripts.
+    id: u32,
+    /// The game time in seconds when the function must be called.
+    due: f64,
+    /// The time between calls in seconds if the timer repeats.
+    interval: Option<f64>,
+    /// The function to call.
+    function: Root<GFn>,
+    /// The update during which the timer was scheduled.
+    scheduled_update: u64,
+}
+
+lib! {
+/// Functions scheduled by scripts, driven by the engine clock.
+///
+/// The clock only advances while the game isn't paused, so timers are independent of the
+/// frame rate and don't fire while paused.
+pub struct Timers {
+    /// The scheduled timers.
+    timers: Vec<Timer>,
+    /// The identifier of the next timer.
+    next_id: u32,
+    /// The game time in seconds.
+    time: f64,
+    /// The wall-clock time of the previous update.
+    last_tick: Option<f64>,
+    /// The amount of updates so far.
+    update: u64,
+}
+}
+
+impl Timers {
+    /// Create without any timers.
+    pub fn new() -> Self {
+        Self {
+            timers: vec![],
+            next_id: 0,
+            time: 0.0,
+            last_tick: None,
+            update: 0,
+        }
+    }
+
+    /// The wall-clock time in seconds since the previous update.
+    pub fn tick(&mut self) -> f64 {
+        let now = miniquad::date::now();
+
+        match self.last_tick.replace(now) {
+            Some(last_tick) => now - last_tick,
+            None => 0.0,
+        }
+    }
+
+    /// Advance the game time with a duration in seconds.
+    pub fn advance_by(&mut self, seconds: f64) {
+        self.time += seconds;
+        self.update += 1;
+    }
+
+    /// The game time in seconds, the time the game has been running without being paused.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Schedule a function.
+    fn schedule(&mut self, delay: f64, interval: Option<f64>, function: Root<GFn>) -> TimerRef {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        self.timers.push(Timer {
+            id,
+            due: self.time + delay,
+            interval,
+            function,
+            scheduled_update: self.update,
+        });
+
+        TimerRef(id)
+    }
+
+    /// Call a function once after a delay in seconds from a script.
+    ///
+    /// ```gamelisp
+    /// (after 2.0 (fn () (push-scene 'game-over)))
+    /// ```
+    fn after(&mut self, seconds: f64, function: Root<GFn>) -> TimerRef {
+        self.schedule(seconds.max(0.0), None, function)
+    }
+
+    /// Call a function repeatedly with an interval in seconds from a script.
+    ///
+    /// The interval must be at least a millisecond.
+    ///
+    /// ```gamelisp
+    /// (let spawner (every 0.5 spawn-enemy))
+    /// ```
+    fn every(&mut self, interval: f64, function: Root<GFn>) -> GResult<TimerRef> {
+        if !interval.is_finite() || interval < MIN_INTERVAL {
+            bail!(
+                "timer interval must be at least {} seconds, got {}",
+                MIN_INTERVAL,
+                interval
+            );
+        }
+
+        Ok(self.schedule(interval, Some(interval), function))
+    }
+
+    /// Stop a timer from a script, returns whether it was still scheduled.
+    ///
+    /// ```gamelisp
+    /// (cancel-timer spawner)
+    /// ```
+    fn cancel_timer(&mut self, timer: &TimerRef) -> bool {
+        let count = self.timers.len();
+        self.timers.retain(|t| t.id != timer.0);
+
+        self.timers.len() != count
+    }
+
+    /// Get the game time in seconds from a script.
+    fn game_time(&self) -> f64 {
+        self.time
+    }
+
+    /// Call the functions of all timers that are due.
+    ///
+    /// Repeating timers are called once for every interval that passed, up to a maximum per
+    /// update so a long hitch doesn't stall the game. Must be called inside the GameLisp runtime.
+    pub fn fire_due() -> GResult<()> {
+        let mut calls: HashMap<u32, u32> = HashMap::new();
+        loop {
+            // Don't hold the borrow while calling the scripts, they might schedule new timers
+            let function = {
+                let mut timers = Timers::borrow_mut();
+                let (time, update) = (timers.time, timers.update);
+
+                let index = match timers
+                    .timers
+                    .iter()
+                    .enumerate()
+                    // Timers scheduled from a timer function wait for the next update
+                    .filter(|(_, timer)| timer.due <= time && timer.scheduled_update < update)
+                    .min_by(|(_, a), (_, b)| a.due.total_cmp(&b.due))
+                {
+                    Some((index, _)) => index,
+                    None => return Ok(()),
+                };
+
+                match timers.timers[index].interval {
+                    Some(interval) => {
+                        let timer = &mut timers.timers[index];
+                        let count = calls.entry(timer.id).or_insert(0);
+                        *count += 1;
+                        timer.due = if *count < MAX_CATCH_UP_CALLS {
+                            timer.due + interval
+                        } else {
+                            // Skip the missed calls instead of calling it for the rest of them
+                            time + interval
+                        };
+
+                        timer.function.clone()
+                    }
+                    None => timers.timers.remove(index).function,
+                }
+            };
+
+            let _: Val = sandbox::call(&function, &())?;
+        }
+    }
+
+    /// Register the timers and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Timers::new());
+
+            glsp::bind_rfn("after", rfn!(Timers::after))?;
+            glsp::bind_rfn("every", rfn!(Timers::every))?;
+            glsp::bind_rfn("cancel-timer", rfn!(Timers::cancel_timer))?;
+            glsp::bind_rfn("game-time", rfn!(Timers::game_time))?;
+
+            Ok(())
+        });
+    }
+}
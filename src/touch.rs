@@ -0,0 +1,251 @@
+use crate::render::Render;
+use glsp::{lib, rfn, GResult, Root, Runtime, Tab, Val};
+use miniquad::TouchPhase;
+use std::collections::HashMap;
+
+/// How long a touch can last in seconds to still count as a tap.
+const TAP_MAX_DURATION: f64 = 0.3;
+/// How far a touch can move in pixels to still count as a tap.
+const TAP_MAX_DISTANCE: f32 = 10.0;
+
+/// A finger touching the screen.
+#[derive(Debug, Copy, Clone)]
+struct TouchPoint {
+    /// Where the touch started in pixels.
+    start: (f32, f32),
+    /// The current position in pixels.
+    position: (f32, f32),
+    /// When the touch started.
+    start_time: f64,
+    /// Whether the touch moved too much or was part of a pinch, so it can't be a tap anymore.
+    moved: bool,
+}
+
+/// A raw touch or a gesture derived from the touches, passed to `engine:touch`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TouchEvent {
+    /// A finger touched the screen.
+    Start { id: u64, x: f32, y: f32 },
+    /// A finger moved.
+    Move { id: u64, x: f32, y: f32 },
+    /// A finger was lifted.
+    End { id: u64, x: f32, y: f32 },
+    /// The touch was interrupted by the system.
+    Cancel { id: u64, x: f32, y: f32 },
+    /// A short touch without moving.
+    Tap { x: f32, y: f32 },
+    /// A single finger moved by an offset.
+    Drag { x: f32, y: f32, dx: f32, dy: f32 },
+    /// Two fingers moved apart or together, the scale is the change in distance.
+    Pinch { x: f32, y: f32, scale: f32 },
+}
+
+lib! {
+/// The fingers touching the screen and the gestures they form.
+pub struct Touches {
+    /// The active touches by their identifier.
+    points: HashMap<u64, TouchPoint>,
+    /// The distance between the two pinching fingers during the previous event.
+    pinch_distance: Option<f32>,
+    /// Whether pinching changes the zoom of the camera.
+    pinch_zoom: bool,
+}
+}
+
+impl Touches {
+    /// Create without any touches, pinching zooms the camera.
+    pub fn new() -> Self {
+        Self {
+            points: HashMap::new(),
+            pinch_distance: None,
+            pinch_zoom: true,
+        }
+    }
+
+    /// Set whether pinching changes the zoom of the camera.
+    pub fn set_pinch_zoom(&mut self, pinch_zoom: bool) {
+        self.pinch_zoom = pinch_zoom;
+    }
+
+    /// Handle a touch from miniquad.
+    ///
+    /// Returns the raw touch followed by the gestures it completes.
+    pub fn touch(
+        &mut self,
+        phase: TouchPhase,
+        id: u64,
+        x: f32,
+        y: f32,
+        render: &mut Render,
+    ) -> Vec<TouchEvent> {
+        match phase {
+            TouchPhase::Started => {
+                self.points.insert(
+                    id,
+                    TouchPoint {
+                        start: (x, y),
+                        position: (x, y),
+                        start_time: miniquad::date::now(),
+                        moved: false,
+                    },
+                );
+
+                // A second finger can only be the start of a pinch
+                if self.points.len() > 1 {
+                    self.points
+                        .values_mut()
+                        .for_each(|point| point.moved = true);
+                }
+                self.pinch_distance = None;
+
+                vec![TouchEvent::Start { id, x, y }]
+            }
+            TouchPhase::Moved => {
+                let mut events = vec![TouchEvent::Move { id, x, y }];
+
+                let point = match self.points.get_mut(&id) {
+                    Some(point) => point,
+                    None => return events,
+                };
+                let (previous_x, previous_y) = point.position;
+                point.position = (x, y);
+                if distance(point.start, (x, y)) > TAP_MAX_DISTANCE {
+                    point.moved = true;
+                }
+                let moved = point.moved;
+
+                match self.points.len() {
+                    1 if moved => events.push(TouchEvent::Drag {
+                        x,
+                        y,
+                        dx: x - previous_x,
+                        dy: y - previous_y,
+                    }),
+                    2 => events.extend(self.pinch(render)),
+                    _ => (),
+                }
+
+                events
+            }
+            TouchPhase::Ended => {
+                let mut events = vec![TouchEvent::End { id, x, y }];
+
+                if let Some(point) = self.points.remove(&id) {
+                    let duration = miniquad::date::now() - point.start_time;
+                    if !point.moved && duration <= TAP_MAX_DURATION {
+                        events.push(TouchEvent::Tap { x, y });
+                    }
+                }
+                self.pinch_distance = None;
+
+                events
+            }
+            TouchPhase::Cancelled => {
+                self.points.remove(&id);
+                self.pinch_distance = None;
+
+                vec![TouchEvent::Cancel { id, x, y }]
+            }
+        }
+    }
+
+    /// Detect a pinch between the two fingers touching the screen.
+    fn pinch(&mut self, render: &mut Render) -> Option<TouchEvent> {
+        let mut points = self.points.values();
+        let (first, second) = (points.next()?.position, points.next()?.position);
+
+        let new_distance = distance(first, second);
+        let previous_distance = self.pinch_distance.replace(new_distance)?;
+        if previous_distance == 0.0 {
+            return None;
+        }
+
+        let scale = new_distance / previous_distance;
+        if self.pinch_zoom {
+            render.set_camera_zoom(render.camera_zoom() * scale);
+        }
+
+        Some(TouchEvent::Pinch {
+            x: (first.0 + second.0) / 2.0,
+            y: (first.1 + second.1) / 2.0,
+            scale,
+        })
+    }
+
+    /// The positions of all fingers touching the screen in pixels.
+    ///
+    /// ```gamelisp
+    /// (for touch in (touch-points)
+    ///   (prn [touch 'id] [touch 'x] [touch 'y]))
+    /// ```
+    fn touch_points(&self) -> GResult<Vec<Root<Tab>>> {
+        self.points
+            .iter()
+            .map(|(id, point)| {
+                let tab = glsp::tab();
+                tab.set(glsp::sym("id")?, *id as i32)?;
+                tab.set(glsp::sym("x")?, point.position.0)?;
+                tab.set(glsp::sym("y")?, point.position.1)?;
+
+                Ok(tab)
+            })
+            .collect()
+    }
+
+    /// Register the touches in the runtime and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Touches::new());
+
+            glsp::bind_rfn("touch-points", rfn!(Touches::touch_points))?;
+
+            Ok(())
+        });
+    }
+}
+
+impl TouchEvent {
+    /// Convert the event to a table for the scripts, the type is stored as a symbol in `kind`.
+    pub fn to_val(&self) -> GResult<Val> {
+        let tab = glsp::tab();
+
+        let (kind, x, y) = match *self {
+            TouchEvent::Start { id, x, y } => {
+                tab.set(glsp::sym("id")?, id as i32)?;
+                ("start", x, y)
+            }
+            TouchEvent::Move { id, x, y } => {
+                tab.set(glsp::sym("id")?, id as i32)?;
+                ("move", x, y)
+            }
+            TouchEvent::End { id, x, y } => {
+                tab.set(glsp::sym("id")?, id as i32)?;
+                ("end", x, y)
+            }
+            TouchEvent::Cancel { id, x, y } => {
+                tab.set(glsp::sym("id")?, id as i32)?;
+                ("cancel", x, y)
+            }
+            TouchEvent::Tap { x, y } => ("tap", x, y),
+            TouchEvent::Drag { x, y, dx, dy } => {
+                tab.set(glsp::sym("dx")?, dx)?;
+                tab.set(glsp::sym("dy")?, dy)?;
+                ("drag", x, y)
+            }
+            TouchEvent::Pinch { x, y, scale } => {
+                tab.set(glsp::sym("scale")?, scale)?;
+                ("pinch", x, y)
+            }
+        };
+        tab.set(glsp::sym("kind")?, glsp::sym(kind)?)?;
+        tab.set(glsp::sym("x")?, x)?;
+        tab.set(glsp::sym("y")?, y)?;
+
+        Ok(Val::Tab(tab))
+    }
+}
+
+/// The distance between two positions.
+fn distance((x1, y1): (f32, f32), (x2, y2): (f32, f32)) -> f32 {
+    ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt()
+}
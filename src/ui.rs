@@ -0,0 +1,1058 @@
+use crate::{
+    assets::Assets,
+    color::Color,
+    locale::Locales,
+    render::{Instance, InstanceRef, Mesh, Render},
+    sandbox, text,
+    touch::TouchEvent,
+};
+use anyhow::{anyhow, Result};
+use glsp::{bail, lib, rdata, rfn, GFn, GResult, Root, Runtime, Sym, Val};
+use lyon::{math::point, path::Path};
+use std::collections::HashMap;
+
+/// The height of the text of the widgets in pixels.
+const TEXT_SIZE: f32 = 16.0;
+/// The space between the text and the edges of a button in pixels.
+const PADDING: f32 = 8.0;
+/// The space between the children of a container in pixels.
+const SPACING: f32 = 4.0;
+/// The default width & height of a slider in pixels.
+const SLIDER_SIZE: (f32, f32) = (160.0, 24.0);
+/// The width of the knob of a slider in pixels.
+const KNOB_WIDTH: f32 = 12.0;
+/// The height of the track of a slider in pixels.
+const TRACK_HEIGHT: f32 = 4.0;
+/// The size of the box of a checkbox in pixels.
+const CHECKBOX_SIZE: f32 = 20.0;
+/// The space between the box of a checkbox and its check mark in pixels.
+const CHECK_INSET: f32 = 5.0;
+/// The draw order of the meshes of the widgets, so they're drawn on top of the game.
+const DRAW_ORDER: i32 = 1 << 20;
+/// The smallest & largest scale of the widgets.
+const SCALE_RANGE: (f32, f32) = (0.25, 4.0);
+/// The colors of the widgets.
+const THEME: Theme = Theme {
+    base: [0.25, 0.25, 0.3],
+    pressed: [0.15, 0.15, 0.2],
+    accent: [0.9, 0.9, 0.9],
+    text: [1.0, 1.0, 1.0],
+    backdrop: None,
+};
+/// The colors of the widgets in high contrast mode.
+const HIGH_CONTRAST_THEME: Theme = Theme {
+    base: [0.0, 0.0, 0.0],
+    pressed: [0.3, 0.3, 0.0],
+    accent: [1.0, 0.9, 0.0],
+    text: [1.0, 1.0, 1.0],
+    backdrop: Some([0.0, 0.0, 0.0]),
+};
+
+/// The colors the widgets are drawn with.
+#[derive(Debug, Copy, Clone)]
+struct Theme {
+    /// The color of the buttons, the boxes of the checkboxes & the tracks of the sliders.
+    base: [f32; 3],
+    /// The color of a button that's being pressed.
+    pressed: [f32; 3],
+    /// The color of the check marks & the knobs of the sliders.
+    accent: [f32; 3],
+    /// The color of the text.
+    text: [f32; 3],
+    /// The color of the rectangle behind labels, so they're readable on top of the game.
+    backdrop: Option<[f32; 3]>,
+}
+
+rdata! {
+/// A reference to a widget.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WidgetRef(u32);
+}
+
+/// In which direction a container places its children.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Direction {
+    Horizontal,
+    Vertical,
+}
+
+/// What a widget is and its state.
+#[derive(Debug)]
+enum WidgetKind {
+    /// A line of text.
+    Label,
+    /// A button with a line of text, calling a function when it's clicked.
+    Button { on_click: Option<Root<GFn>> },
+    /// A box with a line of text, calling a function with the new state when it's toggled.
+    Checkbox {
+        checked: bool,
+        on_change: Option<Root<GFn>>,
+    },
+    /// A horizontal bar, calling a function with the new value when it's dragged.
+    Slider {
+        value: f32,
+        min: f32,
+        max: f32,
+        on_change: Option<Root<GFn>>,
+    },
+    /// Places its children next to or below each other.
+    Container {
+        direction: Direction,
+        children: Vec<u32>,
+    },
+}
+
+/// The line of text shown by a widget.
+#[derive(Debug)]
+struct Label {
+    /// The mesh of the text.
+    mesh: Mesh,
+    /// The width of the text in pixels.
+    width: f32,
+    /// The instance of the text mesh.
+    instance: InstanceRef,
+}
+
+/// An element of the user interface.
+#[derive(Debug)]
+struct Widget {
+    /// What the widget is and its state.
+    kind: WidgetKind,
+    /// The text shown by the widget.
+    label: Option<Label>,
+    /// The instances of the rectangles the widget is made of.
+    shapes: Vec<InstanceRef>,
+    /// The container the widget is placed in.
+    parent: Option<u32>,
+    /// Where a widget that's not in a container is placed on the screen, between 0 & 1.
+    anchor: (f32, f32),
+    /// The offset from the anchor in pixels.
+    offset: (f32, f32),
+    /// The size in pixels, calculated from the contents when not set.
+    size: Option<(f32, f32)>,
+    /// Whether the widget and its children are shown.
+    visible: bool,
+    /// Where the widget was placed on the screen as `[x, y, width, height]` in pixels.
+    rect: Option<[f32; 4]>,
+}
+
+impl Widget {
+    /// Whether a pixel position is inside the widget when it was shown.
+    fn contains(&self, x: f32, y: f32) -> bool {
+        match self.rect {
+            Some([left, top, width, height]) => {
+                x >= left && x <= left + width && y >= top && y <= top + height
+            }
+            None => false,
+        }
+    }
+}
+
+lib! {
+/// Retained-mode widgets for menus & HUDs, drawn in screen space on top of the game.
+///
+/// Widgets are created once and laid out every frame, positions & sizes are in pixels. Widgets
+/// that aren't in a container are anchored to the screen:
+///
+/// ```gamelisp
+/// (let menu (ui-container 'vertical))
+/// (ui-anchor menu 'center)
+/// (ui-add menu (ui-label "Paused"))
+/// (ui-add menu (ui-slider 0 1 0.5 (fn (volume) (prn volume))))
+/// (ui-add menu (ui-checkbox "Fullscreen" #f (fn (checked) (set-fullscreen checked))))
+/// (ui-add menu (ui-button "Resume" (fn () (ui-set-visible menu #f))))
+/// ```
+///
+/// The text uses the font asset with the reference name `default`. Touches on interactive
+/// widgets aren't passed to the game.
+///
+/// For accessibility all widgets can be scaled, the positions & sizes stay in unscaled pixels so
+/// the layout doesn't change. The scale is also applied to the size of text blocks. High contrast
+/// mode draws the widgets in black & yellow with a black backdrop behind the labels, & text blocks
+/// without their inline colors:
+///
+/// ```gamelisp
+/// (set-ui-scale 1.5)
+/// (set-high-contrast #t)
+/// ```
+pub struct Ui {
+    /// All widgets by their identifier.
+    widgets: HashMap<u32, Widget>,
+    /// The widgets that aren't in a container, in the order they are drawn.
+    roots: Vec<u32>,
+    /// The identifier of the next widget.
+    next_id: u32,
+    /// A white square of a single pixel, created when the first widget needs it.
+    rect_mesh: Option<Mesh>,
+    /// The meshes of the text in a font shown by the widgets with the width of the text.
+    text_meshes: HashMap<(String, String), (Mesh, f32)>,
+    /// The touch pressing a widget and the widget.
+    pressed: Option<(u64, u32)>,
+    /// Whether the gestures of the last touch belong to the widgets.
+    captured: bool,
+    /// How much larger the widgets are drawn.
+    scale: f32,
+    /// Whether the widgets are drawn with the high contrast colors.
+    high_contrast: bool,
+}
+}
+
+impl Ui {
+    /// Create without any widgets.
+    pub fn new() -> Self {
+        Self {
+            widgets: HashMap::new(),
+            roots: vec![],
+            next_id: 0,
+            rect_mesh: None,
+            text_meshes: HashMap::new(),
+            pressed: None,
+            captured: false,
+            scale: 1.0,
+            high_contrast: false,
+        }
+    }
+
+    /// How much larger the widgets & text blocks are drawn.
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Whether the widgets & text blocks are drawn with high contrast.
+    pub fn is_high_contrast(&self) -> bool {
+        self.high_contrast
+    }
+
+    /// The colors the widgets are drawn with.
+    fn theme(&self) -> Theme {
+        if self.high_contrast {
+            HIGH_CONTRAST_THEME
+        } else {
+            THEME
+        }
+    }
+
+    /// Lay out all widgets and move their instances.
+    pub fn update(&mut self, render: &mut Render) {
+        // The layout is in unscaled pixels
+        let (screen_width, screen_height) = render.screen_size();
+        let (screen_width, screen_height) = (screen_width / self.scale, screen_height / self.scale);
+
+        for root in self.roots.clone() {
+            let (width, height) = self.measure(root);
+            let widget = &self.widgets[&root];
+            let x = (screen_width - width) * widget.anchor.0 + widget.offset.0;
+            let y = (screen_height - height) * widget.anchor.1 + widget.offset.1;
+
+            self.place(root, Some([x, y, width, height]), render);
+        }
+    }
+
+    /// Let the widgets handle a touch or gesture, returns whether it shouldn't be passed to the
+    /// game.
+    ///
+    /// Must be called inside the GameLisp runtime.
+    pub fn touch(event: &TouchEvent) -> GResult<bool> {
+        // Don't hold the borrow while calling the scripts, they might change the widgets
+        let (handled, callback) = Ui::borrow_mut().handle_touch(event);
+        if let Some((function, args)) = callback {
+            let _: Val = sandbox::call(&function, &args[..])?;
+        }
+
+        Ok(handled)
+    }
+
+    /// Press, drag & release the widgets, returns the function that must be called.
+    fn handle_touch(&mut self, event: &TouchEvent) -> (bool, Option<(Root<GFn>, Vec<Val>)>) {
+        // The widgets are placed in unscaled pixels
+        let scale = self.scale;
+        match *event {
+            TouchEvent::Start { id, x, y } => {
+                let (x, y) = (x / scale, y / scale);
+                let hit = self.interactive_widget_at(x, y);
+                self.captured = hit.is_some();
+
+                match hit {
+                    Some(widget) => {
+                        self.pressed = Some((id, widget));
+
+                        (true, self.drag(widget, x))
+                    }
+                    None => (false, None),
+                }
+            }
+            TouchEvent::Move { id, x, .. } => match self.pressed {
+                Some((touch, widget)) if touch == id => (true, self.drag(widget, x / scale)),
+                _ => (false, None),
+            },
+            TouchEvent::End { id, x, y } => match self.pressed {
+                Some((touch, widget)) if touch == id => {
+                    self.pressed = None;
+
+                    (true, self.release(widget, x / scale, y / scale))
+                }
+                _ => (false, None),
+            },
+            TouchEvent::Cancel { id, .. } => match self.pressed {
+                Some((touch, _)) if touch == id => {
+                    self.pressed = None;
+
+                    (true, None)
+                }
+                _ => (false, None),
+            },
+            TouchEvent::Tap { .. } | TouchEvent::Drag { .. } | TouchEvent::Pinch { .. } => {
+                (self.captured, None)
+            }
+        }
+    }
+
+    /// The topmost shown button, checkbox or slider at a pixel position.
+    fn interactive_widget_at(&self, x: f32, y: f32) -> Option<u32> {
+        self.widgets
+            .iter()
+            .filter(|(_, widget)| match widget.kind {
+                WidgetKind::Button { .. }
+                | WidgetKind::Checkbox { .. }
+                | WidgetKind::Slider { .. } => widget.contains(x, y),
+                _ => false,
+            })
+            .map(|(id, _)| *id)
+            .max()
+    }
+
+    /// Move the value of a slider to a pixel position.
+    fn drag(&mut self, widget: u32, x: f32) -> Option<(Root<GFn>, Vec<Val>)> {
+        let widget = self.widgets.get_mut(&widget)?;
+        let [left, _, width, _] = widget.rect?;
+
+        match widget.kind {
+            WidgetKind::Slider {
+                ref mut value,
+                min,
+                max,
+                ref on_change,
+            } => {
+                let fraction = ((x - left - KNOB_WIDTH / 2.0) / (width - KNOB_WIDTH).max(1.0))
+                    .max(0.0)
+                    .min(1.0);
+                let new_value = min + fraction * (max - min);
+                if new_value == *value {
+                    return None;
+                }
+                *value = new_value;
+
+                on_change
+                    .clone()
+                    .map(|function| (function, vec![Val::Flo(new_value)]))
+            }
+            _ => None,
+        }
+    }
+
+    /// Click a button or toggle a checkbox when the touch is released on top of it.
+    fn release(&mut self, widget: u32, x: f32, y: f32) -> Option<(Root<GFn>, Vec<Val>)> {
+        let widget = self.widgets.get_mut(&widget)?;
+        if !widget.contains(x, y) {
+            return None;
+        }
+
+        match widget.kind {
+            WidgetKind::Button { ref on_click } => {
+                on_click.clone().map(|function| (function, vec![]))
+            }
+            WidgetKind::Checkbox {
+                ref mut checked,
+                ref on_change,
+            } => {
+                *checked = !*checked;
+
+                on_change
+                    .clone()
+                    .map(|function| (function, vec![Val::Bool(*checked)]))
+            }
+            _ => None,
+        }
+    }
+
+    /// The size of a widget in pixels, zero when it's hidden.
+    fn measure(&self, id: u32) -> (f32, f32) {
+        let widget = &self.widgets[&id];
+        if !widget.visible {
+            return (0.0, 0.0);
+        }
+        if let Some(size) = widget.size {
+            return size;
+        }
+
+        let text_width = widget.label.as_ref().map_or(0.0, |label| label.width);
+        match widget.kind {
+            WidgetKind::Label => (text_width, TEXT_SIZE),
+            WidgetKind::Button { .. } => (text_width + PADDING * 2.0, TEXT_SIZE + PADDING * 2.0),
+            WidgetKind::Checkbox { .. } => (
+                CHECKBOX_SIZE + PADDING + text_width,
+                CHECKBOX_SIZE.max(TEXT_SIZE),
+            ),
+            WidgetKind::Slider { .. } => SLIDER_SIZE,
+            WidgetKind::Container {
+                direction,
+                ref children,
+            } => {
+                let sizes: Vec<(f32, f32)> = children
+                    .iter()
+                    .filter(|child| self.widgets[*child].visible)
+                    .map(|child| self.measure(*child))
+                    .collect();
+                let spacing = sizes.len().saturating_sub(1) as f32 * SPACING;
+
+                match direction {
+                    Direction::Horizontal => (
+                        sizes.iter().map(|(width, _)| width).sum::<f32>() + spacing,
+                        sizes.iter().map(|(_, height)| *height).fold(0.0, f32::max),
+                    ),
+                    Direction::Vertical => (
+                        sizes.iter().map(|(width, _)| *width).fold(0.0, f32::max),
+                        sizes.iter().map(|(_, height)| height).sum::<f32>() + spacing,
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Place a widget and its children on the screen, hide them when there's no rectangle.
+    fn place(&mut self, id: u32, rect: Option<[f32; 4]>, render: &mut Render) {
+        let rect = rect.filter(|_| self.widgets[&id].visible);
+        let pressed = self.pressed.map_or(false, |(_, widget)| widget == id);
+        let (theme, scale) = (self.theme(), self.scale);
+
+        let widget = self.widgets.get_mut(&id).expect("Widget doesn't exist");
+        widget.rect = rect;
+
+        // Where the text starts horizontally relative to the widget
+        let text_width = widget.label.as_ref().map_or(0.0, |label| label.width);
+        let text_x = match (&widget.kind, rect) {
+            (WidgetKind::Button { .. }, Some([_, _, width, _])) => (width - text_width) / 2.0,
+            (WidgetKind::Checkbox { .. }, _) => CHECKBOX_SIZE + PADDING,
+            _ => 0.0,
+        };
+        if let Some(ref label) = widget.label {
+            let position =
+                rect.map(|[x, y, _, height]| (x + text_x, y + (height - TEXT_SIZE) / 2.0));
+            place_text(render, label.instance, position, theme.text, scale);
+        }
+
+        let (direction, children) = match widget.kind {
+            WidgetKind::Label => {
+                let backdrop = rect.filter(|_| theme.backdrop.is_some());
+                let color = theme.backdrop.unwrap_or(theme.base);
+                place_rect(render, widget.shapes[0], backdrop, color, scale);
+
+                return;
+            }
+            WidgetKind::Button { .. } => {
+                let color = if pressed { theme.pressed } else { theme.base };
+                place_rect(render, widget.shapes[0], rect, color, scale);
+
+                return;
+            }
+            WidgetKind::Checkbox { checked, .. } => {
+                let square = rect.map(|[x, y, _, height]| {
+                    [
+                        x,
+                        y + (height - CHECKBOX_SIZE) / 2.0,
+                        CHECKBOX_SIZE,
+                        CHECKBOX_SIZE,
+                    ]
+                });
+                let check = square.filter(|_| checked).map(|[x, y, width, height]| {
+                    [
+                        x + CHECK_INSET,
+                        y + CHECK_INSET,
+                        width - CHECK_INSET * 2.0,
+                        height - CHECK_INSET * 2.0,
+                    ]
+                });
+                place_rect(render, widget.shapes[0], square, theme.base, scale);
+                place_rect(render, widget.shapes[1], check, theme.accent, scale);
+
+                return;
+            }
+            WidgetKind::Slider {
+                value, min, max, ..
+            } => {
+                let fraction = if max > min {
+                    ((value - min) / (max - min)).max(0.0).min(1.0)
+                } else {
+                    0.0
+                };
+                let track = rect.map(|[x, y, width, height]| {
+                    [x, y + (height - TRACK_HEIGHT) / 2.0, width, TRACK_HEIGHT]
+                });
+                let knob = rect.map(|[x, y, width, height]| {
+                    [x + fraction * (width - KNOB_WIDTH), y, KNOB_WIDTH, height]
+                });
+                place_rect(render, widget.shapes[0], track, theme.base, scale);
+                place_rect(render, widget.shapes[1], knob, theme.accent, scale);
+
+                return;
+            }
+            WidgetKind::Container {
+                direction,
+                ref children,
+            } => (direction, children.clone()),
+        };
+
+        // Place the children after each other
+        let mut cursor = rect.map(|[x, y, _, _]| (x, y));
+        for child in children {
+            let (width, height) = self.measure(child);
+            let child_rect = cursor.map(|(x, y)| [x, y, width, height]);
+            self.place(child, child_rect, render);
+
+            if !self.widgets[&child].visible {
+                continue;
+            }
+            cursor = cursor.map(|(x, y)| match direction {
+                Direction::Horizontal => (x + width + SPACING, y),
+                Direction::Vertical => (x, y + height + SPACING),
+            });
+        }
+    }
+
+    /// Create a widget that's not in a container, with the rectangles & text it's made of.
+    fn add_widget(&mut self, kind: WidgetKind, text: Option<&str>) -> GResult<WidgetRef> {
+        let mut render = Render::borrow_mut();
+
+        let label = match text {
+            Some(text) => Some(self.create_label(&mut render, text)?),
+            None => None,
+        };
+
+        // Labels have a backdrop in high contrast mode
+        let shape_count = match kind {
+            WidgetKind::Label | WidgetKind::Button { .. } => 1,
+            WidgetKind::Checkbox { .. } | WidgetKind::Slider { .. } => 2,
+            WidgetKind::Container { .. } => 0,
+        };
+        let rect_mesh = self.rect_mesh(&mut render);
+        let shapes: Vec<InstanceRef> = (0..shape_count)
+            .map(|_| render.add_instance(rect_mesh, hidden_instance()))
+            .collect();
+        // The check mark & the knob are drawn on top
+        if let Some(shape) = shapes.get(1) {
+            render.set_instance_draw_order(*shape, 1);
+        }
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.widgets.insert(
+            id,
+            Widget {
+                kind,
+                label,
+                shapes,
+                parent: None,
+                anchor: (0.0, 0.0),
+                offset: (0.0, 0.0),
+                size: None,
+                visible: true,
+                rect: None,
+            },
+        );
+        self.roots.push(id);
+
+        Ok(WidgetRef(id))
+    }
+
+    /// Create an instance of the mesh of a line of text in the font of the current language.
+    fn create_label(&mut self, render: &mut Render, text: &str) -> GResult<Label> {
+        let key = text_key(text);
+        let (mesh, width) = match self.text_meshes.get(&key) {
+            Some(text_mesh) => *text_mesh,
+            None => {
+                let path = match text_path(&key.0, text) {
+                    Ok(path) => path,
+                    Err(err) => bail!("creating text \"{}\" failed: {}", text, err),
+                };
+                let mesh = render.upload_path(path.iter(), Color::new(255, 255, 255), 1.0);
+                render.set_draw_order(mesh, DRAW_ORDER + 1);
+
+                // Text without any glyphs has no width
+                let [min_x, _, max_x, _] = render.mesh_bounds(mesh);
+                let width = if max_x >= min_x { max_x } else { 0.0 };
+
+                self.text_meshes.insert(key, (mesh, width));
+
+                (mesh, width)
+            }
+        };
+
+        Ok(Label {
+            mesh,
+            width,
+            instance: render.add_instance(mesh, hidden_instance()),
+        })
+    }
+
+    /// The mesh of a white square of a single pixel, created when it's first needed.
+    fn rect_mesh(&mut self, render: &mut Render) -> Mesh {
+        if let Some(mesh) = self.rect_mesh {
+            return mesh;
+        }
+
+        let mut builder = Path::builder();
+        builder.move_to(point(-0.5, -0.5));
+        builder.line_to(point(0.5, -0.5));
+        builder.line_to(point(0.5, 0.5));
+        builder.line_to(point(-0.5, 0.5));
+        builder.close();
+        let mesh = render.upload_path(builder.build().iter(), Color::new(255, 255, 255), 1.0);
+        render.set_draw_order(mesh, DRAW_ORDER);
+        self.rect_mesh = Some(mesh);
+
+        mesh
+    }
+
+    /// Get a widget that still exists.
+    fn widget_mut(&mut self, widget: &WidgetRef) -> GResult<&mut Widget> {
+        match self.widgets.get_mut(&widget.0) {
+            Some(widget) => Ok(widget),
+            None => bail!("widget is removed"),
+        }
+    }
+
+    /// Create a line of text from a script.
+    ///
+    /// ```gamelisp
+    /// (let score-label (ui-label "Score: 0"))
+    /// ```
+    fn label(&mut self, text: &str) -> GResult<WidgetRef> {
+        self.add_widget(WidgetKind::Label, Some(text))
+    }
+
+    /// Create a button from a script, the function is called without arguments when it's clicked.
+    ///
+    /// ```gamelisp
+    /// (ui-button "Start" (fn () (switch-scene 'level-1)))
+    /// ```
+    fn button(&mut self, text: &str, on_click: Option<Root<GFn>>) -> GResult<WidgetRef> {
+        self.add_widget(WidgetKind::Button { on_click }, Some(text))
+    }
+
+    /// Create a checkbox from a script, the function is called with the new state when toggled.
+    ///
+    /// ```gamelisp
+    /// (ui-checkbox "Music" #t (fn (checked) (prn checked)))
+    /// ```
+    fn checkbox(
+        &mut self,
+        text: &str,
+        checked: bool,
+        on_change: Option<Root<GFn>>,
+    ) -> GResult<WidgetRef> {
+        self.add_widget(WidgetKind::Checkbox { checked, on_change }, Some(text))
+    }
+
+    /// Create a slider from a script, the function is called with the new value when dragged.
+    ///
+    /// ```gamelisp
+    /// (ui-slider 0 100 50 (fn (value) (prn value)))
+    /// ```
+    fn slider(
+        &mut self,
+        min: f32,
+        max: f32,
+        value: f32,
+        on_change: Option<Root<GFn>>,
+    ) -> GResult<WidgetRef> {
+        if max < min {
+            bail!("slider maximum {} is lower than the minimum {}", max, min);
+        }
+
+        self.add_widget(
+            WidgetKind::Slider {
+                value: value.max(min).min(max),
+                min,
+                max,
+                on_change,
+            },
+            None,
+        )
+    }
+
+    /// Create a container from a script, the direction is `horizontal` or `vertical`.
+    ///
+    /// ```gamelisp
+    /// (let toolbar (ui-container 'horizontal))
+    /// ```
+    fn container(&mut self, direction: Sym) -> GResult<WidgetRef> {
+        let direction = match &*direction.name() {
+            "horizontal" => Direction::Horizontal,
+            "vertical" => Direction::Vertical,
+            name => bail!("unknown container direction {}", name),
+        };
+
+        self.add_widget(
+            WidgetKind::Container {
+                direction,
+                children: vec![],
+            },
+            None,
+        )
+    }
+
+    /// Move a widget to the end of a container from a script.
+    ///
+    /// ```gamelisp
+    /// (ui-add toolbar (ui-button "Save" save-game))
+    /// ```
+    fn add(&mut self, container: &WidgetRef, widget: &WidgetRef) -> GResult<()> {
+        // A container can't end up inside itself
+        let mut ancestor = Some(container.0);
+        while let Some(id) = ancestor {
+            if id == widget.0 {
+                bail!("a widget can't be added to itself or its children");
+            }
+            ancestor = self.widget_mut(&WidgetRef(id))?.parent;
+        }
+
+        match self.widget_mut(container)?.kind {
+            WidgetKind::Container { .. } => (),
+            _ => bail!("widgets can only be added to containers"),
+        }
+
+        self.detach(widget.0);
+        self.widget_mut(widget)?.parent = Some(container.0);
+        if let WidgetKind::Container {
+            ref mut children, ..
+        } = self.widget_mut(container)?.kind
+        {
+            children.push(widget.0);
+        }
+
+        Ok(())
+    }
+
+    /// Remove a widget from its container or the root widgets.
+    fn detach(&mut self, id: u32) {
+        let parent = match self.widgets.get_mut(&id) {
+            Some(widget) => widget.parent.take(),
+            None => return,
+        };
+
+        match parent.and_then(|parent| self.widgets.get_mut(&parent)) {
+            Some(Widget {
+                kind: WidgetKind::Container { children, .. },
+                ..
+            }) => children.retain(|child| *child != id),
+            _ => self.roots.retain(|root| *root != id),
+        }
+    }
+
+    /// Remove a widget with all its children from a script, returns whether it existed.
+    ///
+    /// ```gamelisp
+    /// (ui-remove menu)
+    /// ```
+    fn remove(&mut self, widget: &WidgetRef) -> bool {
+        if !self.widgets.contains_key(&widget.0) {
+            return false;
+        }
+
+        self.detach(widget.0);
+        self.remove_recursive(widget.0, &mut Render::borrow_mut());
+
+        true
+    }
+
+    /// Remove a widget that's already detached and its children, including their instances.
+    fn remove_recursive(&mut self, id: u32, render: &mut Render) {
+        let widget = match self.widgets.remove(&id) {
+            Some(widget) => widget,
+            None => return,
+        };
+
+        for shape in widget.shapes {
+            render.remove_instance(shape);
+        }
+        if let Some(label) = widget.label {
+            render.remove_instance(label.instance);
+        }
+        if let WidgetKind::Container { children, .. } = widget.kind {
+            for child in children {
+                self.remove_recursive(child, render);
+            }
+        }
+        if self.pressed.map_or(false, |(_, pressed)| pressed == id) {
+            self.pressed = None;
+        }
+    }
+
+    /// Anchor a widget that's not in a container to the screen from a script.
+    ///
+    /// The anchor is one of `top-left`, `top`, `top-right`, `left`, `center`, `right`,
+    /// `bottom-left`, `bottom` or `bottom-right`, the optional offset is in pixels.
+    ///
+    /// ```gamelisp
+    /// (ui-anchor score-label 'top-right -8 8)
+    /// ```
+    fn anchor(
+        &mut self,
+        widget: &WidgetRef,
+        anchor: Sym,
+        x: Option<f32>,
+        y: Option<f32>,
+    ) -> GResult<()> {
+        let anchor = match &*anchor.name() {
+            "top-left" => (0.0, 0.0),
+            "top" => (0.5, 0.0),
+            "top-right" => (1.0, 0.0),
+            "left" => (0.0, 0.5),
+            "center" => (0.5, 0.5),
+            "right" => (1.0, 0.5),
+            "bottom-left" => (0.0, 1.0),
+            "bottom" => (0.5, 1.0),
+            "bottom-right" => (1.0, 1.0),
+            name => bail!("unknown anchor {}", name),
+        };
+
+        let widget = self.widget_mut(widget)?;
+        widget.anchor = anchor;
+        widget.offset = (x.unwrap_or(0.0), y.unwrap_or(0.0));
+
+        Ok(())
+    }
+
+    /// Set the size of a widget in pixels from a script, instead of fitting the contents.
+    ///
+    /// ```gamelisp
+    /// (ui-set-size start-button 200 40)
+    /// ```
+    fn set_size(&mut self, widget: &WidgetRef, width: f32, height: f32) -> GResult<()> {
+        self.widget_mut(widget)?.size = Some((width, height));
+
+        Ok(())
+    }
+
+    /// Change the text of a label, button or checkbox from a script.
+    ///
+    /// ```gamelisp
+    /// (ui-set-text score-label (str "Score: " score))
+    /// ```
+    fn set_text(&mut self, widget: &WidgetRef, text: &str) -> GResult<()> {
+        let current = match self.widget_mut(widget)?.label {
+            Some(ref label) => label.mesh,
+            None => bail!("widget doesn't have any text"),
+        };
+        if self.text_meshes.get(&text_key(text)).map(|(mesh, _)| *mesh) == Some(current) {
+            return Ok(());
+        }
+
+        // The text is a different mesh so the instance is replaced
+        let mut render = Render::borrow_mut();
+        let label = self.create_label(&mut render, text)?;
+        if let Some(old) = self.widget_mut(widget)?.label.replace(label) {
+            render.remove_instance(old.instance);
+        }
+
+        Ok(())
+    }
+
+    /// Get the value of a slider or whether a checkbox is checked from a script.
+    ///
+    /// ```gamelisp
+    /// (prn (ui-value volume-slider))
+    /// ```
+    fn value(&mut self, widget: &WidgetRef) -> GResult<Val> {
+        Ok(match self.widget_mut(widget)?.kind {
+            WidgetKind::Slider { value, .. } => Val::Flo(value),
+            WidgetKind::Checkbox { checked, .. } => Val::Bool(checked),
+            _ => Val::Nil,
+        })
+    }
+
+    /// Set the value of a slider or whether a checkbox is checked from a script.
+    ///
+    /// The function of the widget isn't called.
+    ///
+    /// ```gamelisp
+    /// (ui-set-value volume-slider 0.8)
+    /// ```
+    fn set_value(&mut self, widget: &WidgetRef, new: Val) -> GResult<()> {
+        match self.widget_mut(widget)?.kind {
+            WidgetKind::Slider {
+                ref mut value,
+                min,
+                max,
+                ..
+            } => {
+                let new = match new {
+                    Val::Flo(new) => new,
+                    Val::Int(new) => new as f32,
+                    new => bail!("slider values must be numbers, found {}", new),
+                };
+
+                *value = new.max(min).min(max);
+            }
+            WidgetKind::Checkbox {
+                ref mut checked, ..
+            } => *checked = new.is_truthy(),
+            _ => bail!("only sliders & checkboxes have a value"),
+        }
+
+        Ok(())
+    }
+
+    /// Show or hide a widget with its children from a script.
+    ///
+    /// ```gamelisp
+    /// (ui-set-visible menu #f)
+    /// ```
+    fn set_visible(&mut self, widget: &WidgetRef, visible: bool) -> GResult<()> {
+        self.widget_mut(widget)?.visible = visible;
+
+        Ok(())
+    }
+
+    /// Scale the widgets & the text blocks created afterwards from a script, 1 by default.
+    ///
+    /// ```gamelisp
+    /// (set-ui-scale 2.0)
+    /// ```
+    fn set_ui_scale(&mut self, scale: f32) -> GResult<()> {
+        if !(scale >= SCALE_RANGE.0 && scale <= SCALE_RANGE.1) {
+            bail!(
+                "UI scale {} must be between {} & {}",
+                scale,
+                SCALE_RANGE.0,
+                SCALE_RANGE.1
+            );
+        }
+        self.scale = scale;
+
+        Ok(())
+    }
+
+    /// How much larger the widgets & text blocks are drawn from a script.
+    fn ui_scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Draw the widgets & the text blocks created afterwards with high contrast from a script.
+    fn set_high_contrast(&mut self, high_contrast: bool) {
+        self.high_contrast = high_contrast;
+    }
+
+    /// Whether the widgets & text blocks are drawn with high contrast from a script.
+    fn high_contrast(&self) -> bool {
+        self.high_contrast
+    }
+
+    /// Register the widgets and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Ui::new());
+
+            glsp::bind_rfn("ui-label", rfn!(Ui::label))?;
+            glsp::bind_rfn("ui-button", rfn!(Ui::button))?;
+            glsp::bind_rfn("ui-checkbox", rfn!(Ui::checkbox))?;
+            glsp::bind_rfn("ui-slider", rfn!(Ui::slider))?;
+            glsp::bind_rfn("ui-container", rfn!(Ui::container))?;
+            glsp::bind_rfn("ui-add", rfn!(Ui::add))?;
+            glsp::bind_rfn("ui-remove", rfn!(Ui::remove))?;
+            glsp::bind_rfn("ui-anchor", rfn!(Ui::anchor))?;
+            glsp::bind_rfn("ui-set-size", rfn!(Ui::set_size))?;
+            glsp::bind_rfn("ui-set-text", rfn!(Ui::set_text))?;
+            glsp::bind_rfn("ui-value", rfn!(Ui::value))?;
+            glsp::bind_rfn("ui-set-value", rfn!(Ui::set_value))?;
+            glsp::bind_rfn("ui-set-visible", rfn!(Ui::set_visible))?;
+            glsp::bind_rfn("set-ui-scale", rfn!(Ui::set_ui_scale))?;
+            glsp::bind_rfn("ui-scale", rfn!(Ui::ui_scale))?;
+            glsp::bind_rfn("set-high-contrast", rfn!(Ui::set_high_contrast))?;
+            glsp::bind_rfn("high-contrast?", rfn!(Ui::high_contrast))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// The key of the cached mesh of a line of text in the font of the current language.
+fn text_key(text: &str) -> (String, String) {
+    (Locales::borrow().font().to_string(), text.to_string())
+}
+
+/// Convert a line of text to a path with a font, the origin is the top left.
+fn text_path(font: &str, text: &str) -> Result<Path> {
+    let assets = Assets::borrow();
+    let font_data = assets
+        .data(font)
+        .ok_or_else(|| anyhow!("font asset \"{}\" is not loaded", font))?;
+
+    text::text_path(font_data, text, TEXT_SIZE, point(0.0, 0.0))
+}
+
+/// An instance that's not visible until the widget is placed.
+fn hidden_instance() -> Instance {
+    let mut instance = Instance::new(0.0, 0.0);
+    instance.set_scale(0.0);
+    // The camera doesn't move instances at the front
+    instance.set_z(u8::MAX);
+
+    instance
+}
+
+/// Replace an instance when it changed, so only changed instances are uploaded again.
+fn update_instance(render: &mut Render, instance: InstanceRef, new: Instance) {
+    if render.instance(instance) == Some(&new) {
+        return;
+    }
+
+    if let Some(stored) = render.instance_mut(instance) {
+        *stored = new;
+    }
+}
+
+/// Move & stretch the square mesh to cover a rectangle in unscaled pixels, or hide it.
+fn place_rect(
+    render: &mut Render,
+    instance: InstanceRef,
+    rect: Option<[f32; 4]>,
+    color: [f32; 3],
+    ui_scale: f32,
+) {
+    let mut new = hidden_instance();
+    if let Some([x, y, width, height]) = rect {
+        let (world_x, world_y) =
+            render.screen_to_world((x + width / 2.0) * ui_scale, (y + height / 2.0) * ui_scale);
+        let scale = render.world_units_per_pixel() * ui_scale;
+
+        new.set_x(world_x);
+        new.set_y(world_y);
+        new.set_scale_x(width * scale);
+        new.set_scale_y(height * scale);
+    }
+    new.set_color_multiplier(color[0], color[1], color[2]);
+
+    update_instance(render, instance, new);
+}
+
+/// Move a text mesh so its top left is at a position in unscaled pixels, or hide it.
+fn place_text(
+    render: &mut Render,
+    instance: InstanceRef,
+    position: Option<(f32, f32)>,
+    color: [f32; 3],
+    ui_scale: f32,
+) {
+    let mut new = hidden_instance();
+    if let Some((x, y)) = position {
+        let (world_x, world_y) = render.screen_to_world(x * ui_scale, y * ui_scale);
+
+        new.set_x(world_x);
+        new.set_y(world_y);
+        new.set_scale(render.world_units_per_pixel() * ui_scale);
+    }
+    new.set_color_multiplier(color[0], color[1], color[2]);
+
+    update_instance(render, instance, new);
+}
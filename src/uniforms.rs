@@ -0,0 +1,92 @@
+use anyhow::{anyhow, Result};
+use miniquad::{graphics::*, Context};
+
+/// The values of a dynamic set of float uniforms, packed in declaration order.
+#[derive(Debug)]
+pub struct UniformBlock {
+    /// The name, offset in the values & amount of floats for every uniform.
+    uniforms: Vec<(String, usize, usize)>,
+    /// The packed values of all uniforms.
+    values: Vec<f32>,
+}
+
+impl UniformBlock {
+    /// Create the block with builtin uniforms followed by user supplied ones.
+    ///
+    /// Also returns the layout that can be used for the shader meta.
+    pub fn new(
+        builtin: &[(&str, UniformType)],
+        custom: &[(String, UniformType)],
+    ) -> Result<(Self, UniformBlockLayout)> {
+        let mut uniforms = vec![];
+        let mut uniform_descs = vec![];
+        let mut size = 0;
+
+        let all_uniforms = builtin
+            .iter()
+            .map(|(name, uniform_type)| (name.to_string(), *uniform_type))
+            .chain(custom.iter().cloned());
+        for (name, uniform_type) in all_uniforms {
+            let floats = match uniform_type {
+                UniformType::Float1 => 1,
+                UniformType::Float2 => 2,
+                UniformType::Float3 => 3,
+                UniformType::Float4 => 4,
+                UniformType::Mat4 => 16,
+                _ => return Err(anyhow!("uniform \"{}\" must be a float type", name)),
+            };
+
+            // The shader meta must be static, shaders live as long as the game so leak it
+            let static_name: &'static str = Box::leak(name.clone().into_boxed_str());
+            uniform_descs.push(UniformDesc::new(static_name, uniform_type));
+            uniforms.push((name, size, floats));
+            size += floats;
+        }
+
+        let layout = UniformBlockLayout {
+            uniforms: Box::leak(uniform_descs.into_boxed_slice()),
+        };
+
+        Ok((
+            Self {
+                uniforms,
+                values: vec![0.0; size],
+            },
+            layout,
+        ))
+    }
+
+    /// Set the value of a uniform by name.
+    pub fn set(&mut self, uniform: &str, values: &[f32]) -> Result<()> {
+        let (_, offset, floats) = self
+            .uniforms
+            .iter()
+            .find(|(name, _, _)| name == uniform)
+            .ok_or_else(|| anyhow!("uniform \"{}\" doesn't exist", uniform))?;
+
+        if values.len() != *floats {
+            return Err(anyhow!(
+                "uniform \"{}\" expects {} values, {} supplied",
+                uniform,
+                floats,
+                values.len()
+            ));
+        }
+        self.values[*offset..*offset + *floats].copy_from_slice(values);
+
+        Ok(())
+    }
+
+    /// The packed values, the builtin uniforms come first.
+    pub fn values_mut(&mut self) -> &mut [f32] {
+        &mut self.values
+    }
+
+    /// Upload the uniforms for the currently applied pipeline.
+    pub fn apply(&self, ctx: &mut Context) {
+        ctx.apply_uniforms_from_bytes(
+            self.values.as_ptr() as *const u8,
+            self.values.len() * std::mem::size_of::<f32>(),
+        );
+    }
+}
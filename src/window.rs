@@ -0,0 +1,194 @@
+use crate::{atlas, event::Events};
+use anyhow::{anyhow, Result};
+use glsp::{bail, lib, rfn, GResult, Runtime};
+use miniquad::{conf::Icon, Context};
+
+lib! {
+/// Changes to the window requested by scripts, applied during the next update.
+pub struct Window {
+    /// Whether the window must switch to or from fullscreen.
+    fullscreen: Option<bool>,
+    /// Whether the game must be closed.
+    quit: bool,
+    /// Whether the window is minimized or hidden.
+    minimized: bool,
+    /// Whether the updates are stopped while the window is minimized.
+    pause_when_minimized: bool,
+    /// Whether the updates are stopped by a script.
+    paused: bool,
+    /// The text that must be copied to the clipboard.
+    clipboard_set: Option<String>,
+    /// Whether a script requested the text on the clipboard.
+    clipboard_requested: bool,
+    /// The maximum amount of frames per second, unlimited when `None`.
+    target_fps: Option<u32>,
+}
+}
+
+impl Window {
+    /// Create without any pending changes.
+    pub fn new() -> Self {
+        Self {
+            fullscreen: None,
+            quit: false,
+            minimized: false,
+            pause_when_minimized: true,
+            paused: false,
+            clipboard_set: None,
+            clipboard_requested: false,
+            target_fps: None,
+        }
+    }
+
+    /// Switch the window to or from fullscreen.
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        self.fullscreen = Some(fullscreen);
+    }
+
+    /// Close the game during the next update, without calling `engine:quit-requested`.
+    pub fn quit(&mut self) {
+        self.quit = true;
+    }
+
+    /// Whether closing the game is requested.
+    pub fn is_quitting(&self) -> bool {
+        self.quit
+    }
+
+    /// Set whether the window is minimized or hidden.
+    pub fn set_minimized(&mut self, minimized: bool) {
+        self.minimized = minimized;
+    }
+
+    /// Whether the window is minimized or hidden.
+    pub fn is_minimized(&self) -> bool {
+        self.minimized
+    }
+
+    /// Set whether the updates are stopped while the window is minimized.
+    pub fn set_pause_when_minimized(&mut self, pause_when_minimized: bool) {
+        self.pause_when_minimized = pause_when_minimized;
+    }
+
+    /// Stop or continue calling the update functions, rendering continues.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Whether the update functions must not be called.
+    pub fn is_paused(&self) -> bool {
+        self.paused || (self.minimized && self.pause_when_minimized)
+    }
+
+    /// Limit the amount of frames per second, `None` or zero removes the limit.
+    ///
+    /// ```gamelisp
+    /// (set-target-fps 30)
+    /// ```
+    pub fn set_target_fps(&mut self, target_fps: Option<u32>) {
+        self.target_fps = target_fps.filter(|fps| *fps > 0);
+    }
+
+    /// The maximum amount of frames per second, unlimited when `None`.
+    pub fn target_fps(&self) -> Option<u32> {
+        self.target_fps
+    }
+
+    /// Copy text to the clipboard of the user during the next update.
+    pub fn set_clipboard(&mut self, text: &str) {
+        self.clipboard_set = Some(text.to_string());
+    }
+
+    /// Request the text on the clipboard of the user from a script.
+    ///
+    /// The text is read during the next update and emitted as a `clipboard` event, with `#n`
+    /// when the clipboard doesn't contain text. On the web only text pasted into the page can be
+    /// read.
+    ///
+    /// ```gamelisp
+    /// (on-event 'clipboard (fn (text)
+    ///   (when text
+    ///     (load-level-code text))))
+    /// (clipboard-get)
+    /// ```
+    fn request_clipboard(&mut self) {
+        self.clipboard_requested = true;
+    }
+
+    /// Changing the title of an open window isn't supported by miniquad.
+    fn set_window_title(&mut self, _title: &str) -> GResult<()> {
+        bail!("changing the window title after starting isn't supported on this platform")
+    }
+
+    /// Apply the requested changes to the window.
+    ///
+    /// Must be called inside the GameLisp runtime.
+    pub fn apply(&mut self, ctx: &mut Context) -> GResult<()> {
+        if let Some(fullscreen) = self.fullscreen.take() {
+            ctx.set_fullscreen(fullscreen);
+        }
+
+        // Copy before reading so a script gets back what it copied
+        if let Some(text) = self.clipboard_set.take() {
+            ctx.clipboard_set(&text);
+        }
+        if std::mem::replace(&mut self.clipboard_requested, false) {
+            Events::emit_engine_event("clipboard", ctx.clipboard_get())?;
+        }
+
+        Ok(())
+    }
+
+    /// Register the window in the runtime and bind the GameLisp functions.
+    pub fn bind_functions(runtime: &Runtime) {
+        runtime.run(|| {
+            glsp::add_lib(Window::new());
+
+            glsp::bind_rfn("quit", rfn!(Window::quit))?;
+            glsp::bind_rfn("set-fullscreen", rfn!(Window::set_fullscreen))?;
+            glsp::bind_rfn("set-window-title", rfn!(Window::set_window_title))?;
+            glsp::bind_rfn("set-paused", rfn!(Window::set_paused))?;
+            glsp::bind_rfn("paused?", rfn!(Window::is_paused))?;
+            glsp::bind_rfn("window-minimized?", rfn!(Window::is_minimized))?;
+            glsp::bind_rfn("clipboard-get", rfn!(Window::request_clipboard))?;
+            glsp::bind_rfn("clipboard-set", rfn!(Window::set_clipboard))?;
+            glsp::bind_rfn("set-target-fps", rfn!(Window::set_target_fps))?;
+            glsp::bind_rfn("target-fps", rfn!(Window::target_fps))?;
+
+            Ok(())
+        });
+    }
+}
+
+/// Decode a PNG image and scale it to the sizes of the window icon.
+pub fn icon_from_png(png: &[u8]) -> Result<Icon> {
+    let (width, height, rgba) =
+        atlas::decode_png(png).map_err(|err| anyhow!("reading icon failed: {}", err))?;
+
+    let (width, height) = (width as usize, height as usize);
+    if width == 0 || height == 0 {
+        return Err(anyhow!("icon PNG is empty"));
+    }
+    let mut icon = Icon {
+        small: [0; 16 * 16 * 4],
+        medium: [0; 32 * 32 * 4],
+        big: [0; 64 * 64 * 4],
+    };
+    scale_nearest(&rgba, width, height, &mut icon.small, 16);
+    scale_nearest(&rgba, width, height, &mut icon.medium, 32);
+    scale_nearest(&rgba, width, height, &mut icon.big, 64);
+
+    Ok(icon)
+}
+
+/// Scale a RGBA image to a square with the nearest neighbor.
+fn scale_nearest(rgba: &[u8], width: usize, height: usize, target: &mut [u8], size: usize) {
+    for y in 0..size {
+        for x in 0..size {
+            let source = ((y * height / size) * width + x * width / size) * 4;
+            let destination = (y * size + x) * 4;
+
+            target[destination..destination + 4].copy_from_slice(&rgba[source..source + 4]);
+        }
+    }
+}
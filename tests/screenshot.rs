@@ -0,0 +1,16 @@
+use anyhow::Result;
+use clogs::{testing::ScreenshotTest, Clog};
+
+#[test]
+#[ignore = "opens a window, run with `xvfb-run cargo test -- --ignored` without a display"]
+fn empty_scene_is_cleared_to_the_sky() -> Result<()> {
+    let game = Clog::new("Empty scene")
+        .width(64)
+        .height(48)
+        .main_script("(defn engine:update ()) (defn engine:render ())")?;
+
+    // The clear color can be rounded differently by GPU drivers
+    ScreenshotTest::new(3)
+        .tolerance(2)
+        .compare(game, "tests/golden/empty.png")
+}